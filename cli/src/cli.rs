@@ -1,4 +1,6 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use simperby_node::simperby_common::Timestamp;
+use simperby_settlement::execution::GasPriceStrategy;
 
 /**
 Welcome to the Simperby CLI!
@@ -8,10 +10,21 @@ Welcome to the Simperby CLI!
 #[clap(about = "A Simperby client CLI", long_about = None)]
 pub struct Cli {
     pub path: std::path::PathBuf,
+    /// The format of the output of the query commands.
+    ///
+    /// `json` prints a single JSON value per command, whose schema is stable across versions.
+    #[clap(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
     #[clap(subcommand)]
     pub command: Commands,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
 #[derive(Debug, Subcommand)]
 pub enum CreateCommands {
     /// An extra-agenda transaction that delegates the consensus voting power.
@@ -19,6 +32,7 @@ pub enum CreateCommands {
         delegator: String,
         delegatee: String,
         /// Whether to delegate the governance voting power too.
+        #[clap(long, action)]
         governance: bool,
         proof: String,
     },
@@ -27,17 +41,136 @@ pub enum CreateCommands {
     TxUndelegate { delegator: String, proof: String },
     /// An extra-agenda transaction that reports a misbehaving validator.
     TxReport, // TODO
+    /// A transaction that delivers an execution to a settlement chain.
+    ///
+    /// The contract sequence is assigned automatically from the existing executions.
+    TxExecution {
+        #[command(subcommand)]
+        execution: Box<ExecutionCommands>,
+        #[command(flatten)]
+        dry_run: DryRunOptions,
+        /// If enabled, every address must be well-formed for the target chain
+        /// (e.g., EIP-55 for the EVM chains, Bech32 for the Cosmos chains).
+        #[clap(long, action)]
+        strict_addresses: bool,
+        /// If enabled, the body is in the compact binary encoding (e.g., for long call data).
+        #[clap(long, action)]
+        binary: bool,
+        #[command(flatten)]
+        options: ExecutionOptions,
+    },
     /// A block waiting for finalization.
     Block,
     /// An agenda waiting for governance approval.
     Agenda,
 }
 
+/// How to check an execution without committing it.
+#[derive(Debug, Args)]
+pub struct DryRunOptions {
+    /// If enabled, it validates and prints the transaction without committing it.
+    #[clap(long, action)]
+    pub dry_run: bool,
+    /// The driver config (a JSON file) of the target chain, to simulate the execution against
+    /// in the dry run, estimating its fee.
+    ///
+    /// It's tagged by the driver, e.g., `{"driver": "ethereum", "rpc_url": ...}`.
+    #[clap(long, requires = "dry_run")]
+    pub settlement_config: Option<std::path::PathBuf>,
+}
+
+/// The optional fields of an execution.
+#[derive(Debug, Args)]
+pub struct ExecutionOptions {
+    /// The address of the treasury contract to execute, if not the default one of the target chain.
+    #[clap(long)]
+    pub treasury: Option<String>,
+    /// The time after which the execution must not take effect, in milliseconds since the epoch.
+    #[clap(long)]
+    pub valid_until: Option<Timestamp>,
+    /// The maximum gas that the relayer may spend delivering the execution.
+    #[clap(long)]
+    pub max_gas: Option<u64>,
+    /// The gas price of the delivery: `market`, `fixed:<price>` or `capped:<price>`.
+    #[clap(long)]
+    pub gas_price: Option<GasPriceStrategy>,
+    /// The relayer account that must deliver the execution, paying its fee.
+    #[clap(long)]
+    pub fee_payer: Option<String>,
+}
+
+/// The target chain is either a known one (e.g., `ethereum` or `cosmoshub`) or a custom name.
+#[derive(Debug, Subcommand)]
+pub enum ExecutionCommands {
+    /// Transfer the native coin of the settlement chain from the treasury.
+    TransferNative {
+        target_chain: String,
+        amount: u128,
+        receiver_address: String,
+    },
+    /// Transfer a fungible token from the treasury.
+    TransferFt {
+        target_chain: String,
+        token_address: String,
+        amount: u128,
+        receiver_address: String,
+    },
+    /// Transfer a non-fungible token from the treasury.
+    TransferNft {
+        target_chain: String,
+        collection_address: String,
+        token_index: String,
+        receiver_address: String,
+    },
+    /// Transfer a semi-fungible token (e.g., of ERC-1155) from the treasury.
+    TransferSft {
+        target_chain: String,
+        collection_address: String,
+        token_index: String,
+        amount: u128,
+        receiver_address: String,
+    },
+    /// Call a contract from the treasury.
+    ContractCall {
+        target_chain: String,
+        contract_address: String,
+        /// The hex-encoded call data, with or without the `0x` prefix.
+        calldata: String,
+        /// The amount of the native token to send along with the call.
+        #[clap(long, default_value_t = 0)]
+        value: u128,
+    },
+    /// Cancel an earlier execution to the same treasury, which has not been delivered yet.
+    Cancel {
+        target_chain: String,
+        /// The contract sequence of the execution to cancel.
+        contract_sequence: u128,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AgendaCommands {
+    /// List the agendas waiting for the governance approval, with their transactions.
+    List,
+    /// Show the diffs of the transactions included in the agenda.
+    Diff { commit: String },
+    /// Approve the agenda, signing with the node key and broadcasting the vote.
+    ///
+    /// This is equivalent to `vote`.
+    Approve { commit: String },
+    /// Veto the agenda, so that this node won't vote for it.
+    ///
+    /// It leaves a `veto` tag on the commit (with some postfix), and is not broadcasted
+    /// since the governance has no negative votes.
+    Veto { commit: String },
+}
+
 #[derive(Debug, Subcommand)]
 pub enum SignCommands {
     TxDelegate {
         delegatee: String,
         /// Whether to delegate the governance voting power too.
+        #[clap(long, action)]
         governance: bool,
         target_height: u64,
     },
@@ -110,6 +243,9 @@ pub enum Commands {
         /// If the given commit is already set to `veto`, it will be removed.
         commit: Option<String>,
     },
+    /// Inspect and vote on the agendas waiting for the governance approval.
+    #[command(subcommand)]
+    Agenda(AgendaCommands),
     /// Make a progress on the consensus.
     ///
     /// The node may broadcast the proposal or consensus messages depending on the
@@ -130,6 +266,17 @@ pub enum Commands {
     Show { commit: String },
     /// Show the current status of the p2p network.
     Network,
+    /// Export the known peers as a JSON snapshot, to bootstrap another node from.
+    ExportPeers {
+        /// The file to write the snapshot to. If not specified, it prints the snapshot.
+        file: Option<String>,
+    },
+    /// Import the known peers from a JSON snapshot exported by another node of the network.
+    ImportPeers { file: String },
+    /// List the execution transactions from the genesis to the `work` branch.
+    Executions,
+    /// Show the versions of this node, the protocol and the configured chain.
+    Version,
 
     // ----- Network Commands ----- //
     /// Become a server node indefinitely, serving all message propagations and Git requests.
@@ -155,6 +302,8 @@ pub enum Commands {
         #[clap(short, long, action)]
         interactive: bool,
     },
+    /// Print the shell completion script for the given shell.
+    Completions { shell: clap_complete::Shell },
     /// Sign a message with the configured private key.
     #[command(subcommand)]
     Sign(SignCommands),
@@ -73,13 +73,20 @@ pub async fn run_genesis_proposer(private_key: &str) {
         Config {
             chain_name: "PDAO-mainnet".to_owned(),
             public_key: private_key.public_key(),
-            private_key,
+            private_key: Some(private_key),
             broadcast_interval_ms: None,
             fetch_interval_ms: None,
             public_repo_url: vec![],
-            governance_port: 1155,
-            consensus_port: 1166,
+            network_port: 1155,
             repository_port: 1177,
+            pre_shared_key: None,
+            dns_seeds: Vec::new(),
+            enable_mdns: false,
+            proxy: None,
+            denied_peers: Vec::new(),
+            allowed_peers: Vec::new(),
+            keystore: None,
+            api: Default::default(),
         },
         &dir,
     )
@@ -162,8 +169,7 @@ pub async fn run_genesis_non_proposer(private_key: &str) {
         "cd {dir}/repository/repo && git remote remove origin"
     ))
     .await;
-    let ports =
-        r#"{"dms-consensus-0-3f22f0a0":1166,"repository":1177,"dms-governance-0-3f22f0a0":1155}"#;
+    let ports = r#"{"mux":1155,"repository":1177}"#;
     let ports = serde_json::from_str(ports).unwrap();
     setup_peer(
         &dir,
@@ -178,8 +184,9 @@ pub async fn run_genesis_non_proposer(private_key: &str) {
             .unwrap(),
             name: "proposer".to_owned(),
             address: "43.201.28.183:1".parse().unwrap(),
+            addresses: Vec::new(),
             ports,
-            message: "123".to_owned(),
+            metadata: Default::default(),
             recently_seen_timestamp: 0,
         }],
     )
@@ -189,13 +196,20 @@ pub async fn run_genesis_non_proposer(private_key: &str) {
         Config {
             chain_name: "PDAO-mainnet".to_owned(),
             public_key: private_key.public_key(),
-            private_key,
+            private_key: Some(private_key),
             broadcast_interval_ms: None,
             fetch_interval_ms: None,
             public_repo_url: vec![],
-            governance_port: 1155,
-            consensus_port: 1166,
+            network_port: 1155,
             repository_port: 1177,
+            pre_shared_key: None,
+            dns_seeds: Vec::new(),
+            enable_mdns: false,
+            proxy: None,
+            denied_peers: Vec::new(),
+            allowed_peers: Vec::new(),
+            keystore: None,
+            api: Default::default(),
         },
         &dir,
     )
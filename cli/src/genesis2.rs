@@ -27,13 +27,20 @@ async fn fuck() {
     genesis(Config {
         chain_name: "pdao-mainnet".to_owned(),
         public_key: private_key.public_key(),
-        private_key,
+        private_key: Some(private_key),
         broadcast_interval_ms: None,
         fetch_interval_ms: None,
         public_repo_url: vec![],
-        governance_port: 1155,
-        consensus_port: 1166,
+        network_port: 1155,
         repository_port: 1177,
+        pre_shared_key: None,
+        dns_seeds: Vec::new(),
+        enable_mdns: false,
+        proxy: None,
+        denied_peers: Vec::new(),
+        allowed_peers: Vec::new(),
+        keystore: None,
+        api: Default::default(),
     }, "/Users/junhayang/pdao/genesis").await.unwrap();
 }
 
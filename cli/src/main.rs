@@ -1,10 +1,20 @@
 mod cli;
 mod genesis;
 
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use cli::*;
 use eyre::{eyre, Result};
-use simperby_node::{simperby_common::*, simperby_repository::CommitHash, CommitInfo, Config};
+use serde::{Deserialize, Serialize};
+use simperby_node::{
+    simperby_common::*, simperby_network::Peer, simperby_repository::CommitHash, CommitInfo, Config,
+};
+use simperby_settlement::address::{check_execution_addresses, AddressValidation};
+use simperby_settlement::cosmwasm::{CosmWasmChain, CosmWasmConfig};
+use simperby_settlement::ethereum::{EthereumChain, EthereumConfig};
+use simperby_settlement::execution::*;
+use simperby_settlement::sequence::SequenceTracker;
+use simperby_settlement::simulation::{simulate_execution, CheckOutcome};
+use simperby_settlement::SettlementChain;
 
 fn to_commit_hash(s: &str) -> Result<CommitHash> {
     let hash = hex::decode(s).map_err(|_| eyre!("invalid hash"))?;
@@ -15,7 +25,16 @@ fn to_commit_hash(s: &str) -> Result<CommitHash> {
     Ok(CommitHash { hash })
 }
 
+/// Prints the value as JSON, or calls `print_text` in the text mode.
+fn print_output<T: Serialize>(output: OutputFormat, value: &T, print_text: impl FnOnce(&T)) {
+    match output {
+        OutputFormat::Json => println!("{}", serde_json::to_string(value).unwrap()),
+        OutputFormat::Text => print_text(value),
+    }
+}
+
 async fn run(args: cli::Cli, path: String, config: Config) -> eyre::Result<()> {
+    let output = args.output;
     match args.command {
         Commands::Clone { .. } => todo!(),
         Commands::Sync {
@@ -23,13 +42,106 @@ async fn run(args: cli::Cli, path: String, config: Config) -> eyre::Result<()> {
         } => todo!(),
         Commands::Git => todo!(),
         Commands::Clean { .. } => todo!(),
+        Commands::Create(CreateCommands::TxExecution {
+            execution,
+            dry_run,
+            strict_addresses,
+            binary,
+            options,
+        }) => {
+            let encoding = if binary {
+                BodyEncoding::Binary
+            } else {
+                BodyEncoding::Text
+            };
+            create_execution(
+                config,
+                &path,
+                execution,
+                dry_run,
+                strict_addresses,
+                encoding,
+                options,
+            )
+            .await?
+        }
         Commands::Create(CreateCommands::Agenda) => todo!(),
         Commands::Create(CreateCommands::Block) => todo!(),
-        Commands::Vote { .. } => todo!(),
+        Commands::Vote { commit } => vote(config, &path, commit).await?,
         Commands::Veto { .. } => todo!(),
-        Commands::Show { commit } => show(config, &path, commit).await?,
+        Commands::Agenda(AgendaCommands::List) => list_agendas(config, &path, output).await?,
+        Commands::Agenda(AgendaCommands::Diff { commit }) => {
+            let node = simperby_node::initialize(config, &path).await?;
+            println!("{}", node.get_agenda_diff(to_commit_hash(&commit)?).await?);
+        }
+        Commands::Agenda(AgendaCommands::Approve { commit }) => vote(config, &path, commit).await?,
+        Commands::Agenda(AgendaCommands::Veto { commit }) => {
+            let mut node = simperby_node::initialize(config, &path).await?;
+            node.veto_agenda(to_commit_hash(&commit)?).await?;
+        }
+        Commands::Show { commit } => show(config, &path, commit, output).await?,
         Commands::Consensus { show: _ } => todo!(),
-        Commands::Network => todo!(),
+        Commands::Network => {
+            let peers: Vec<Peer> = serde_spb::from_str(
+                &tokio::fs::read_to_string(&format!("{path}/peers.json")).await?,
+            )?;
+            print_output(output, &peers, |peers| {
+                for peer in peers {
+                    println!("{} {} {}", peer.name, peer.address, peer.public_key);
+                }
+            });
+        }
+        Commands::ExportPeers { file } => {
+            let node = simperby_node::initialize(config, &path).await?;
+            let snapshot = node.export_peers().await?;
+            match file {
+                Some(file) => tokio::fs::write(file, snapshot).await?,
+                None => println!("{snapshot}"),
+            }
+        }
+        Commands::ImportPeers { file } => {
+            let node = simperby_node::initialize(config, &path).await?;
+            let imported = node
+                .import_peers(&tokio::fs::read_to_string(file).await?)
+                .await?;
+            println!("imported {imported} peers");
+        }
+        Commands::Version => {
+            let node = simperby_node::initialize(config, &path).await?;
+            print_output(output, &node.version(), |version| {
+                println!("node: {}", version.node_version);
+                println!(
+                    "protocol: {} (chain: {})",
+                    version.protocol_version, version.chain_protocol_version
+                );
+                println!(
+                    "network protocol: {} ({})",
+                    version.network.protocol_version,
+                    version.network.features.join(", ")
+                );
+                println!(
+                    "chain: {} (genesis {})",
+                    version.chain_name, version.genesis_hash
+                );
+            });
+        }
+        Commands::Executions => {
+            let node = simperby_node::initialize(config, &path).await?;
+            let executions = node
+                .get_transactions()
+                .await?
+                .iter()
+                .filter_map(|t| convert_transaction_to_execution(t).ok())
+                .collect::<Vec<_>>();
+            print_output(output, &executions, |executions| {
+                for execution in executions {
+                    println!(
+                        "{} #{} {:?}",
+                        execution.target_chain, execution.contract_sequence, execution.message
+                    );
+                }
+            });
+        }
         Commands::Serve => todo!(),
         Commands::Update => todo!(),
         Commands::Broadcast => todo!(),
@@ -43,7 +155,14 @@ async fn run(args: cli::Cli, path: String, config: Config) -> eyre::Result<()> {
             );
             println!(
                 "{}",
-                Signature::sign(hash, &config.private_key).map_err(|_| eyre!("failed to sign"))?
+                Signature::sign(
+                    hash,
+                    config
+                        .private_key
+                        .as_ref()
+                        .ok_or_else(|| eyre!("no private key in the config"))?
+                )
+                .map_err(|_| eyre!("failed to sign"))?
             );
         }
         _ => unimplemented!(),
@@ -52,25 +171,28 @@ async fn run(args: cli::Cli, path: String, config: Config) -> eyre::Result<()> {
 }
 
 #[tokio::main(flavor = "multi_thread")]
-#[allow(unreachable_code)]
 async fn main() -> eyre::Result<()> {
     color_eyre::install().unwrap();
     env_logger::init();
 
-    let private_key = std::env::args().nth(1).unwrap();
-    let server_or_client = std::env::args().nth(2).unwrap();
-    if server_or_client == "s" {
-        genesis::run_genesis_proposer(&private_key).await;
-    } else {
-        genesis::run_genesis_non_proposer(&private_key).await;
-    }
-
-    return Ok(());
-
     let args = cli::Cli::parse();
+    if let Commands::Completions { shell } = args.command {
+        clap_complete::generate(
+            shell,
+            &mut cli::Cli::command(),
+            "simperby",
+            &mut std::io::stdout(),
+        );
+        return Ok(());
+    }
     let path = args.path.display().to_string();
-    let config: Config =
+    let mut config: Config =
         serde_spb::from_str(&tokio::fs::read_to_string(&format!("{path}/config.json")).await?)?;
+    if config.private_key.is_none() && config.keystore.is_some() {
+        let passphrase = read_passphrase()?;
+        config.private_key =
+            Some(simperby_node::unlock_keystore(&config, &path, &passphrase).await?);
+    }
 
     if let Err(e) = run(args, path, config).await {
         if let Ok(_err) = e.downcast::<simperby_node::simperby_repository::IntegrityError>() {
@@ -81,6 +203,17 @@ async fn main() -> eyre::Result<()> {
     Ok(())
 }
 
+/// Reads the passphrase of the keystore from `SIMPERBY_PASSPHRASE`, or else from the standard input.
+fn read_passphrase() -> Result<String> {
+    if let Ok(passphrase) = std::env::var("SIMPERBY_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    eprint!("passphrase: ");
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
 /// For every type of commit,
 /// 1. Show the content.
 /// 2. Show the hash of it.
@@ -88,9 +221,13 @@ async fn main() -> eyre::Result<()> {
 /// For an agenda, show the governance status.
 /// For a block, show the consensus status projected on this block.
 /// For an extra-agenda transaction and a chat log, TODO.
-async fn show(config: Config, path: &str, commit_hash: String) -> Result<()> {
+async fn show(config: Config, path: &str, commit_hash: String, output: OutputFormat) -> Result<()> {
     let node = simperby_node::initialize(config, path).await?;
     let result = node.show(to_commit_hash(&commit_hash)?).await?;
+    if output == OutputFormat::Json {
+        print_output(output, &result, |_| ());
+        return Ok(());
+    }
     match result {
         CommitInfo::Block { block_header, .. } => {
             println!("hash: {}", block_header.to_hash256());
@@ -100,3 +237,214 @@ async fn show(config: Config, path: &str, commit_hash: String) -> Result<()> {
     }
     Ok(())
 }
+
+/// Lists the pending agendas with their governance status.
+async fn list_agendas(config: Config, path: &str, output: OutputFormat) -> Result<()> {
+    let mut node = simperby_node::initialize(config, path).await?;
+    node.fetch().await?;
+    print_output(output, &node.get_pending_agendas().await?, |agendas| {
+        for agenda in agendas {
+            println!(
+                "agenda {} (height {}, {}/{} voted{})",
+                agenda.commit_hash,
+                agenda.agenda.height,
+                agenda.voted_power,
+                agenda.total_voting_power,
+                if agenda.voted { ", including you" } else { "" }
+            );
+            for (commit_hash, title) in &agenda.transactions {
+                println!("  {commit_hash} {title}");
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Votes on the agenda with the node key and broadcasts it through the governance network.
+async fn vote(config: Config, path: &str, commit_hash: String) -> Result<()> {
+    let mut node = simperby_node::initialize(config, path).await?;
+    node.fetch().await?;
+    node.vote(to_commit_hash(&commit_hash)?).await?;
+    node.broadcast().await?;
+    Ok(())
+}
+
+/// The driver config of a settlement chain, tagged by the driver.
+#[derive(Deserialize)]
+#[serde(tag = "driver", rename_all = "snake_case")]
+enum SettlementConfig {
+    Ethereum(EthereumConfig),
+    Cosmwasm(CosmWasmConfig),
+}
+
+/// Connects to the settlement chain with the driver config in the file.
+async fn connect_settlement_chain(path: &std::path::Path) -> Result<Box<dyn SettlementChain>> {
+    let config: SettlementConfig = serde_json::from_str(&tokio::fs::read_to_string(path).await?)?;
+    let chain: Box<dyn SettlementChain> = match config {
+        SettlementConfig::Ethereum(x) => Box::new(EthereumChain::new(x)?),
+        SettlementConfig::Cosmwasm(x) => Box::new(CosmWasmChain::new(x)?),
+    };
+    chain.check_connection().await?;
+    Ok(chain)
+}
+
+/// Creates an execution transaction on the `work` branch, assigning the next contract sequence.
+async fn create_execution(
+    config: Config,
+    path: &str,
+    execution: Box<ExecutionCommands>,
+    dry_run: DryRunOptions,
+    strict_addresses: bool,
+    encoding: BodyEncoding,
+    options: ExecutionOptions,
+) -> Result<()> {
+    let (target_chain, message) = match *execution {
+        ExecutionCommands::TransferNative {
+            target_chain,
+            amount,
+            receiver_address,
+        } => (
+            target_chain,
+            ExecutionMessage::TransferNativeCoin(TransferNativeCoin {
+                amount,
+                receiver_address,
+            }),
+        ),
+        ExecutionCommands::TransferFt {
+            target_chain,
+            token_address,
+            amount,
+            receiver_address,
+        } => (
+            target_chain,
+            ExecutionMessage::TransferFungibleToken(TransferFungibleToken {
+                token_address,
+                amount,
+                receiver_address,
+            }),
+        ),
+        ExecutionCommands::TransferNft {
+            target_chain,
+            collection_address,
+            token_index,
+            receiver_address,
+        } => (
+            target_chain,
+            ExecutionMessage::TransferNonFungibleToken(TransferNonFungibleToken {
+                collection_address,
+                token_index,
+                receiver_address,
+            }),
+        ),
+        ExecutionCommands::TransferSft {
+            target_chain,
+            collection_address,
+            token_index,
+            amount,
+            receiver_address,
+        } => (
+            target_chain,
+            ExecutionMessage::TransferSemiFungibleToken(TransferSemiFungibleToken {
+                collection_address,
+                token_index,
+                amount,
+                receiver_address,
+            }),
+        ),
+        ExecutionCommands::ContractCall {
+            target_chain,
+            contract_address,
+            calldata,
+            value,
+        } => (
+            target_chain,
+            ExecutionMessage::ContractCall(ContractCall {
+                contract_address,
+                calldata: hex::decode(calldata.trim_start_matches("0x"))
+                    .map_err(|_| eyre!("invalid call data"))?,
+                value,
+            }),
+        ),
+        ExecutionCommands::Cancel {
+            target_chain,
+            contract_sequence,
+        } => (
+            target_chain,
+            ExecutionMessage::CancelExecution(CancelExecution { contract_sequence }),
+        ),
+    };
+    let target_chain: ChainId = target_chain.parse().map_err(|e: String| eyre!(e))?;
+    let treasury_id = TreasuryId::new(target_chain, options.treasury);
+    let fee =
+        (options.max_gas.is_some() || options.gas_price.is_some() || options.fee_payer.is_some())
+            .then(|| FeeBudget {
+                max_gas: options.max_gas,
+                gas_price: options.gas_price.unwrap_or_default(),
+                fee_payer: options.fee_payer,
+            });
+    let author = config.public_key.clone();
+    let mut node = simperby_node::initialize(config, path).await?;
+    // The executions already in the repository take their sequences, so it continues from them.
+    let mut sequences = SequenceTracker::new(None);
+    sequences.scan(&node.get_transactions().await?);
+    let execution = Execution {
+        version: EXECUTION_VERSION,
+        contract_sequence: sequences.allocate_sequence(&treasury_id),
+        target_chain: treasury_id.target_chain,
+        treasury: treasury_id.treasury,
+        valid_until: options.valid_until,
+        fee,
+        message,
+    };
+    validate_execution(&execution).map_err(|e| eyre!(e))?;
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_millis() as Timestamp;
+    let address_validation = if strict_addresses {
+        AddressValidation::Strict
+    } else {
+        AddressValidation::Lenient
+    };
+    let transaction = create_execution_transaction_encoded(
+        &execution,
+        author,
+        timestamp,
+        address_validation,
+        encoding,
+    )
+    .map_err(|e| eyre!(e))?;
+    if dry_run.dry_run {
+        for check in check_execution_addresses(&execution, address_validation) {
+            let expected = check
+                .expected
+                .map_or_else(|| "unknown".to_owned(), |x| x.to_string());
+            let detected = check
+                .detected
+                .map_or_else(|| "unknown".to_owned(), |x| x.to_string());
+            println!(
+                "address {} (expected {expected}, detected {detected})",
+                check.address
+            );
+        }
+        if let Some(settlement_config) = dry_run.settlement_config {
+            let chain = connect_settlement_chain(&settlement_config).await?;
+            let report = simulate_execution(&execution, chain.as_ref()).await?;
+            for check in &report.checks {
+                match &check.outcome {
+                    CheckOutcome::Passed => println!("{}: passed", check.subject),
+                    CheckOutcome::Failed(e) => println!("{}: failed ({e})", check.subject),
+                    CheckOutcome::Skipped(e) => println!("{}: skipped ({e})", check.subject),
+                }
+            }
+            match &report.estimated_fee {
+                Ok(fee) => println!("estimated fee {fee}"),
+                Err(e) => println!("estimated fee unknown ({e})"),
+            }
+        }
+        println!("{}", serde_spb::to_string(&transaction)?);
+        return Ok(());
+    }
+    let commit_hash = node.create_transaction(transaction).await?;
+    println!("created execution transaction {commit_hash}");
+    Ok(())
+}
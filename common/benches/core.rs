@@ -0,0 +1,112 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use simperby_common::test_util::*;
+use simperby_common::verify::{verify_finalization_proof, CommitSequenceVerifier};
+use simperby_common::*;
+
+fn transaction(i: usize) -> Transaction {
+    Transaction {
+        author: PublicKey::zero(),
+        timestamp: i as Timestamp,
+        head: format!("transaction {i}"),
+        body: "x".repeat(256),
+        diff: Diff::None,
+    }
+}
+
+fn signature(c: &mut Criterion) {
+    let (_, private_key) = generate_keypair("bench");
+    let data = "data".to_owned();
+    let signature = TypedSignature::sign(&data, &private_key).unwrap();
+    c.bench_function("signature/sign", |b| {
+        b.iter(|| TypedSignature::sign(&data, &private_key).unwrap())
+    });
+    c.bench_function("signature/verify", |b| {
+        b.iter(|| signature.verify(&data).unwrap())
+    });
+}
+
+fn finalization_proof(c: &mut Criterion) {
+    let mut group = c.benchmark_group("finalization_proof");
+    for validators in [4, 16, 64] {
+        let keys = (0..validators)
+            .map(|i| generate_keypair(format!("{i}")))
+            .collect::<Vec<_>>();
+        let mut header = test_genesis().0.genesis_info.header;
+        header.validator_set = keys.iter().map(|(k, _)| (k.clone(), 1)).collect();
+        let proof = keys
+            .iter()
+            .map(|(_, private_key)| TypedSignature::sign(&header, private_key).unwrap())
+            .collect::<Vec<_>>();
+        group.throughput(Throughput::Elements(validators as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(validators),
+            &proof,
+            |b, proof| b.iter(|| verify_finalization_proof(&header, proof).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn serialization(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serde_spb");
+    for transactions in [100, 1000] {
+        let commits = (0..transactions)
+            .map(|i| Commit::Transaction(transaction(i)))
+            .collect::<Vec<_>>();
+        let json = serde_spb::to_string(&commits).unwrap();
+        let bytes = serde_spb::to_vec(&commits).unwrap();
+        group.throughput(Throughput::Bytes(json.len() as u64));
+        group.bench_function(BenchmarkId::new("to_string", transactions), |b| {
+            b.iter(|| serde_spb::to_string(&commits).unwrap())
+        });
+        group.bench_function(BenchmarkId::new("from_str", transactions), |b| {
+            b.iter(|| serde_spb::from_str::<Vec<Commit>>(&json).unwrap())
+        });
+        group.bench_function(BenchmarkId::new("to_vec", transactions), |b| {
+            b.iter(|| serde_spb::to_vec(&commits).unwrap())
+        });
+        group.bench_function(BenchmarkId::new("from_slice", transactions), |b| {
+            b.iter(|| serde_spb::from_slice::<Vec<Commit>>(&bytes).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn history_verification(c: &mut Criterion) {
+    let mut group = c.benchmark_group("history_verification");
+    group.sample_size(10);
+    for blocks in [10, 100] {
+        let sequence = build_commit_sequence(
+            (0..blocks)
+                .map(|i| (0..10).map(|j| transaction(i * 10 + j)).collect())
+                .collect(),
+        );
+        group.throughput(Throughput::Elements(sequence.commits.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(blocks),
+            &sequence,
+            |b, sequence| {
+                b.iter(|| {
+                    let mut verifier = CommitSequenceVerifier::new(
+                        sequence.reserved_state.genesis_info.header.clone(),
+                        sequence.reserved_state.clone(),
+                    )
+                    .unwrap();
+                    for commit in &sequence.commits {
+                        verifier.apply_commit(commit).unwrap();
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    signature,
+    finalization_proof,
+    serialization,
+    history_verification
+);
+criterion_main!(benches);
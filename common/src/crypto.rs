@@ -1,4 +1,8 @@
 //! A set of types and functions related to cryptography, that are widely used in the entire Simperby project.
+//!
+//! The keys and the signatures are of secp256k1 unless noted otherwise (see [`SignatureScheme`]).
+//! The BLS ones are used along with them, so that many validators' signatures on the same data
+//! can be aggregated and verified at once (e.g., by the light client in a treasury contract).
 use secp256k1::{
     ecdsa::{RecoverableSignature, RecoveryId},
     Message, Secp256k1, SecretKey,
@@ -9,6 +13,10 @@ use std::fmt;
 use thiserror::Error;
 
 const EVM_EC_RECOVERY_OFFSET: u8 = 27;
+/// The domain separation tag of the BLS signatures, in the proof-of-possession ciphersuite.
+const BLS_SIGNATURE_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+/// The domain separation tag of the BLS proofs of possession.
+const BLS_POSSESSION_DST: &[u8] = b"BLS_POP_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
 
 #[derive(Error, Debug, Clone)]
 pub enum CryptoError {
@@ -70,6 +78,9 @@ impl<'de, const N: usize> Deserialize<'de> for HexSerializedBytes<N> {
     where
         D: serde::de::Deserializer<'de>,
     {
+        if !deserializer.is_human_readable() {
+            return deserializer.deserialize_tuple(N, BytesVisitor::<N>);
+        }
         let s: String = Deserialize::deserialize(deserializer)?;
         let bytes = hex::decode(s).map_err(|e| serde::de::Error::custom(e.to_string()))?;
         if bytes.len() != N {
@@ -81,6 +92,30 @@ impl<'de, const N: usize> Deserialize<'de> for HexSerializedBytes<N> {
     }
 }
 
+/// Reads the tuple of bytes that a non-human-readable format serializes.
+struct BytesVisitor<const N: usize>;
+
+impl<'de, const N: usize> serde::de::Visitor<'de> for BytesVisitor<N> {
+    type Value = HexSerializedBytes<N>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{N} bytes")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut data = [0; N];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = seq
+                .next_element()?
+                .ok_or_else(|| serde::de::Error::invalid_length(i, &self))?;
+        }
+        Ok(HexSerializedBytes { data })
+    }
+}
+
 /// A cryptographic hash.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Copy, Serialize, Deserialize)]
 #[serde(transparent)]
@@ -306,6 +341,21 @@ impl PublicKey {
             key: HexSerializedBytes { data: key },
         })
     }
+
+    /// Returns the Ethereum address of the key, i.e., the last 20 bytes of the Keccak-256 hash
+    /// of its uncompressed form.
+    ///
+    /// This is what `ecrecover` returns for a [`Signature`] of the key, so that an EVM contract
+    /// can verify it by comparing the addresses.
+    pub fn to_evm_address(&self) -> Result<[u8; 20], Error> {
+        let key = secp256k1::PublicKey::from_slice(&self.key.data)
+            .map_err(|_| Error::InvalidFormat(format!("public_key: {self}")))?
+            .serialize_uncompressed();
+        let hash = Hash256::hash(&key[1..]);
+        let mut address = [0; 20];
+        address.copy_from_slice(&hash.as_ref()[12..]);
+        Ok(address)
+    }
 }
 
 /// A private key.
@@ -380,6 +430,268 @@ pub fn generate_keypair_random() -> (PublicKey, PrivateKey) {
     )
 }
 
+/// The signature schemes of the keys and the signatures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum SignatureScheme {
+    /// The recoverable ECDSA over secp256k1, which [`Signature`] is of.
+    Secp256k1,
+    /// The BLS over BLS12-381 with the public keys in G1, which [`BlsSignature`] is of.
+    ///
+    /// The signatures on the same data can be aggregated into one.
+    Bls12381,
+}
+
+impl SignatureScheme {
+    /// Returns the sizes of a public key and a signature of the scheme, in bytes.
+    pub const fn sizes(&self) -> (usize, usize) {
+        match self {
+            Self::Secp256k1 => (33, 65),
+            Self::Bls12381 => (48, 96),
+        }
+    }
+}
+
+/// A BLS public key, compressed.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BlsPublicKey {
+    key: HexSerializedBytes<48>,
+}
+
+impl BlsPublicKey {
+    pub fn from_array(array: [u8; 48]) -> Result<Self, Error> {
+        blst::min_pk::PublicKey::key_validate(&array)
+            .map_err(|_| Error::InvalidFormat(format!("given bytes: {}", hex::encode(array))))?;
+        Ok(BlsPublicKey {
+            key: HexSerializedBytes { data: array },
+        })
+    }
+
+    fn to_blst(&self) -> Result<blst::min_pk::PublicKey, Error> {
+        blst::min_pk::PublicKey::key_validate(&self.key.data)
+            .map_err(|_| Error::InvalidFormat(format!("BLS public key: {self}")))
+    }
+
+    /// Proves the possession of the private key, which must be checked
+    /// before the key is aggregated with the others against the rogue key attacks.
+    pub fn prove_possession(private_key: &BlsPrivateKey) -> Result<BlsSignature, Error> {
+        let private_key = private_key.to_blst()?;
+        let public_key = private_key.sk_to_pk().to_bytes();
+        let signature = private_key.sign(&public_key, BLS_POSSESSION_DST, &[]);
+        Ok(BlsSignature {
+            signature: HexSerializedBytes {
+                data: signature.to_bytes(),
+            },
+        })
+    }
+
+    /// Verifies the proof of possession of the private key.
+    pub fn verify_possession(&self, proof: &BlsSignature) -> Result<(), Error> {
+        let result = proof.to_blst()?.verify(
+            true,
+            &self.key.data,
+            BLS_POSSESSION_DST,
+            &[],
+            &self.to_blst()?,
+            true,
+        );
+        if result != blst::BLST_ERROR::BLST_SUCCESS {
+            return Err(Error::VerificationFailed);
+        }
+        Ok(())
+    }
+}
+
+impl std::convert::AsRef<[u8]> for BlsPublicKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.key.data
+    }
+}
+
+impl fmt::Display for BlsPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.key)
+    }
+}
+
+/// A BLS private key.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BlsPrivateKey {
+    key: HexSerializedBytes<32>,
+}
+
+impl std::convert::AsRef<[u8]> for BlsPrivateKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.key.data
+    }
+}
+
+impl BlsPrivateKey {
+    pub fn from_array(array: [u8; 32]) -> Result<Self, Error> {
+        let key = blst::min_pk::SecretKey::from_bytes(&array)
+            .map_err(|_| Error::InvalidFormat("BLS private key: [omitted]".to_owned()))?
+            .to_bytes();
+        Ok(BlsPrivateKey {
+            key: HexSerializedBytes { data: key },
+        })
+    }
+
+    fn to_blst(&self) -> Result<blst::min_pk::SecretKey, Error> {
+        blst::min_pk::SecretKey::from_bytes(&self.key.data)
+            .map_err(|_| Error::InvalidFormat("BLS private key: [omitted]".to_owned()))
+    }
+
+    pub fn public_key(&self) -> BlsPublicKey {
+        let public_key = self.to_blst().expect("invalid private key").sk_to_pk();
+        BlsPublicKey::from_array(public_key.to_bytes()).expect("invalid public key")
+    }
+}
+
+/// A BLS signature, compressed, which might be an aggregate of many signatures on the same data.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BlsSignature {
+    signature: HexSerializedBytes<96>,
+}
+
+impl BlsSignature {
+    /// Constructs a signature from the given bytes, checking only that it's a point of the curve.
+    pub fn from_array(array: [u8; 96]) -> Result<Self, Error> {
+        blst::min_pk::Signature::from_bytes(&array)
+            .map_err(|_| Error::InvalidFormat(format!("given bytes: {}", hex::encode(array))))?;
+        Ok(BlsSignature {
+            signature: HexSerializedBytes { data: array },
+        })
+    }
+
+    fn to_blst(&self) -> Result<blst::min_pk::Signature, Error> {
+        blst::min_pk::Signature::from_bytes(&self.signature.data)
+            .map_err(|_| Error::InvalidFormat(format!("BLS signature: {self}")))
+    }
+
+    /// Creates a new signature from the given data and key.
+    pub fn sign(data: Hash256, private_key: &BlsPrivateKey) -> Result<Self, Error> {
+        let signature = private_key
+            .to_blst()?
+            .sign(data.as_ref(), BLS_SIGNATURE_DST, &[]);
+        Ok(BlsSignature {
+            signature: HexSerializedBytes {
+                data: signature.to_bytes(),
+            },
+        })
+    }
+
+    /// Verifies the signature against the given data and public key.
+    pub fn verify(&self, data: Hash256, public_key: &BlsPublicKey) -> Result<(), Error> {
+        self.verify_aggregate(data, std::slice::from_ref(public_key))
+    }
+
+    /// Aggregates the signatures on the same data into one.
+    pub fn aggregate(signatures: &[BlsSignature]) -> Result<Self, Error> {
+        let signatures = signatures
+            .iter()
+            .map(|x| x.to_blst())
+            .collect::<Result<Vec<_>, _>>()?;
+        let signature = blst::min_pk::AggregateSignature::aggregate(
+            &signatures.iter().collect::<Vec<_>>(),
+            true,
+        )
+        .map_err(|e| Error::InvalidFormat(format!("BLS signatures: {e:?}")))?
+        .to_signature();
+        Ok(BlsSignature {
+            signature: HexSerializedBytes {
+                data: signature.to_bytes(),
+            },
+        })
+    }
+
+    /// Verifies the aggregate signature against the given data and the public keys of the signers.
+    ///
+    /// Every public key must have its possession proven (see [`BlsPublicKey::verify_possession`]).
+    pub fn verify_aggregate(
+        &self,
+        data: Hash256,
+        public_keys: &[BlsPublicKey],
+    ) -> Result<(), Error> {
+        if public_keys.is_empty() {
+            return Err(Error::VerificationFailed);
+        }
+        let public_keys = public_keys
+            .iter()
+            .map(|x| x.to_blst())
+            .collect::<Result<Vec<_>, _>>()?;
+        let result = self.to_blst()?.fast_aggregate_verify(
+            true,
+            data.as_ref(),
+            BLS_SIGNATURE_DST,
+            &public_keys.iter().collect::<Vec<_>>(),
+        );
+        if result != blst::BLST_ERROR::BLST_SUCCESS {
+            return Err(Error::VerificationFailed);
+        }
+        Ok(())
+    }
+}
+
+impl std::convert::AsRef<[u8]> for BlsSignature {
+    fn as_ref(&self) -> &[u8] {
+        &self.signature.data
+    }
+}
+
+impl fmt::Display for BlsSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.signature)
+    }
+}
+
+/// A BLS public key bound to a (secp256k1) public key, which signs it.
+///
+/// It lets a validator, known by its public key in the validator set, sign in the BLS scheme.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct BlsKeyBinding {
+    pub bls_public_key: BlsPublicKey,
+    /// The proof of possession of the BLS private key.
+    pub possession_proof: BlsSignature,
+    /// The signature on the BLS public key by the bound key.
+    pub signature: TypedSignature<BlsPublicKey>,
+}
+
+impl BlsKeyBinding {
+    pub fn create(
+        private_key: &PrivateKey,
+        bls_private_key: &BlsPrivateKey,
+    ) -> Result<Self, Error> {
+        let bls_public_key = bls_private_key.public_key();
+        Ok(BlsKeyBinding {
+            possession_proof: BlsPublicKey::prove_possession(bls_private_key)?,
+            signature: TypedSignature::sign(&bls_public_key, private_key)?,
+            bls_public_key,
+        })
+    }
+
+    /// Returns the bound public key.
+    pub fn public_key(&self) -> &PublicKey {
+        self.signature.signer()
+    }
+
+    pub fn verify(&self) -> Result<(), Error> {
+        self.bls_public_key
+            .verify_possession(&self.possession_proof)?;
+        self.signature.verify(&self.bls_public_key)
+    }
+}
+
+/// Generates a new BLS keypair using the seed.
+pub fn generate_bls_keypair(seed: impl AsRef<[u8]>) -> (BlsPublicKey, BlsPrivateKey) {
+    let private_key = blst::min_pk::SecretKey::key_gen(Hash256::hash(seed).as_ref(), &[])
+        .expect("the seed is long enough");
+    let private_key =
+        BlsPrivateKey::from_array(private_key.to_bytes()).expect("invalid private key");
+    (private_key.public_key(), private_key)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -428,6 +740,15 @@ mod tests {
         assert_eq!(private_key, decoded);
     }
 
+    #[test]
+    fn binary_encode_decode() {
+        let (public_key, private_key) = generate_keypair("hello world");
+        let signature = Signature::sign(Hash256::hash("hello world"), &private_key).unwrap();
+        let encoded = serde_spb::to_vec(&(public_key.clone(), signature.clone())).unwrap();
+        let decoded: (PublicKey, Signature) = serde_spb::from_slice(&encoded).unwrap();
+        assert_eq!(decoded, (public_key, signature));
+    }
+
     #[test]
     fn signature_encode_decode() {
         let (public_key, private_key) = generate_keypair("hello world");
@@ -471,6 +792,18 @@ mod tests {
         check_keypair_match(&public_key, &private_key).unwrap();
     }
 
+    #[test]
+    fn evm_address() {
+        let mut array = [0; 32];
+        array[31] = 1;
+        let public_key = PrivateKey::from_array(array).unwrap().public_key();
+        assert_eq!(
+            hex::encode(public_key.to_evm_address().unwrap()),
+            "7e5f4552091a69125d5dfcb7b8c2659029395bdf"
+        );
+        PublicKey::zero().to_evm_address().unwrap_err();
+    }
+
     #[test]
     fn recover_public_key() {
         let (public_key, private_key) = generate_keypair("hello world");
@@ -481,4 +814,85 @@ mod tests {
             hex::encode(recovered.as_ref())
         );
     }
+
+    #[test]
+    fn bls_signature() {
+        let (public_key, private_key) = generate_bls_keypair("hello world");
+        assert_eq!(private_key.public_key(), public_key);
+        assert_eq!(generate_bls_keypair("hello world").0, public_key);
+        let signature = BlsSignature::sign(Hash256::hash("hello world"), &private_key).unwrap();
+        signature
+            .verify(Hash256::hash("hello world"), &public_key)
+            .unwrap();
+        signature
+            .verify(Hash256::hash("hello world2"), &public_key)
+            .unwrap_err();
+
+        let encoded = serde_spb::to_string(&(&public_key, &signature)).unwrap();
+        let decoded: (BlsPublicKey, BlsSignature) = serde_spb::from_str(&encoded).unwrap();
+        assert_eq!(decoded, (public_key.clone(), signature.clone()));
+        let encoded = serde_spb::to_vec(&(&public_key, &signature)).unwrap();
+        let (public_key_size, signature_size) = SignatureScheme::Bls12381.sizes();
+        assert_eq!(encoded.len(), public_key_size + signature_size);
+        let decoded: (BlsPublicKey, BlsSignature) = serde_spb::from_slice(&encoded).unwrap();
+        assert_eq!(decoded, (public_key, signature));
+        BlsPublicKey::from_array([0; 48]).unwrap_err();
+    }
+
+    #[test]
+    fn bls_aggregate() {
+        let data = Hash256::hash("hello world");
+        let keys = (0..3)
+            .map(|i| generate_bls_keypair(format!("validator{i}")))
+            .collect::<Vec<_>>();
+        let public_keys = keys.iter().map(|(x, _)| x.clone()).collect::<Vec<_>>();
+        let signatures = keys
+            .iter()
+            .map(|(_, x)| BlsSignature::sign(data, x).unwrap())
+            .collect::<Vec<_>>();
+        let aggregate = BlsSignature::aggregate(&signatures).unwrap();
+        aggregate.verify_aggregate(data, &public_keys).unwrap();
+        aggregate
+            .verify_aggregate(data, &public_keys[0..2])
+            .unwrap_err();
+        aggregate
+            .verify_aggregate(Hash256::hash("hello world2"), &public_keys)
+            .unwrap_err();
+        BlsSignature::aggregate(&signatures[0..2])
+            .unwrap()
+            .verify_aggregate(data, &public_keys)
+            .unwrap_err();
+        aggregate.verify_aggregate(data, &[]).unwrap_err();
+        BlsSignature::aggregate(&[]).unwrap_err();
+    }
+
+    #[test]
+    fn bls_key_binding() {
+        let (public_key, private_key) = generate_keypair("hello world");
+        let (_, bls_private_key) = generate_bls_keypair("hello world");
+        let binding = BlsKeyBinding::create(&private_key, &bls_private_key).unwrap();
+        binding.verify().unwrap();
+        assert_eq!(binding.public_key(), &public_key);
+
+        // A BLS key whose private key is not possessed.
+        let (other, _) = generate_bls_keypair("other");
+        let forged = BlsKeyBinding {
+            bls_public_key: other.clone(),
+            signature: TypedSignature::sign(&other, &private_key).unwrap(),
+            ..binding.clone()
+        };
+        forged.verify().unwrap_err();
+        // Signed by another key than the signer.
+        let (_, other_private_key) = generate_keypair("other");
+        let forged = BlsKeyBinding {
+            signature: TypedSignature::new(
+                TypedSignature::sign(&binding.bls_public_key, &other_private_key)
+                    .unwrap()
+                    .get_raw_signature(),
+                public_key,
+            ),
+            ..binding
+        };
+        forged.verify().unwrap_err();
+    }
 }
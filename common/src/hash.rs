@@ -6,6 +6,12 @@ impl ToHash256 for String {
     }
 }
 
+impl ToHash256 for BlsPublicKey {
+    fn to_hash256(&self) -> Hash256 {
+        Hash256::hash(self)
+    }
+}
+
 impl ToHash256 for Member {
     fn to_hash256(&self) -> Hash256 {
         Hash256::hash(serde_spb::to_vec(self).unwrap())
@@ -60,6 +66,12 @@ impl ToHash256 for UndelegationTransactionData {
     }
 }
 
+impl ToHash256 for KeyRotationData {
+    fn to_hash256(&self) -> Hash256 {
+        Hash256::hash(serde_spb::to_vec(self).unwrap())
+    }
+}
+
 impl ToHash256 for ChatLog {
     fn to_hash256(&self) -> Hash256 {
         Hash256::hash(serde_spb::to_vec(self).unwrap())
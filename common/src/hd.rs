@@ -0,0 +1,258 @@
+//! The hierarchical deterministic derivation of the key pairs (BIP32),
+//! so that a large set of keys (e.g., the validators of a test network) can be generated
+//! from one mnemonic and addressed by their paths.
+//!
+//! A mnemonic is turned into the seed as BIP39 does ([`mnemonic_to_seed`]), which means
+//! the keys match those of the usual wallets for the same mnemonic and path.
+use crate::crypto::*;
+use hmac::{Hmac, Mac};
+use secp256k1::{Scalar, SecretKey};
+use sha2::Sha512;
+use std::fmt;
+use std::str::FromStr;
+
+type Error = CryptoError;
+
+/// The offset of the hardened child indices, which are derived from the parent private key.
+pub const HARDENED: u32 = 1 << 31;
+
+const MASTER_KEY_SALT: &[u8] = b"Bitcoin seed";
+const MNEMONIC_ITERATIONS: u32 = 2048;
+
+/// A path of the child indices from the master key (e.g., `m/44'/60'/0'/0/1`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct DerivationPath {
+    pub indices: Vec<u32>,
+}
+
+impl DerivationPath {
+    /// Returns the path to the `index`-th child of this path.
+    pub fn child(&self, index: u32) -> Self {
+        let mut indices = self.indices.clone();
+        indices.push(index);
+        Self { indices }
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut components = s.split('/');
+        if components.next() != Some("m") {
+            return Err(Error::InvalidFormat(format!("derivation path: {s}")));
+        }
+        let indices = components
+            .map(|component| {
+                let (index, offset) = match component
+                    .strip_suffix('\'')
+                    .or_else(|| component.strip_suffix('h'))
+                {
+                    Some(index) => (index, HARDENED),
+                    None => (component, 0),
+                };
+                index
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|index| *index < HARDENED)
+                    .map(|index| index + offset)
+                    .ok_or_else(|| Error::InvalidFormat(format!("derivation path: {s}")))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { indices })
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "m")?;
+        for index in &self.indices {
+            if *index >= HARDENED {
+                write!(f, "/{}'", index - HARDENED)?;
+            } else {
+                write!(f, "/{index}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A private key along with its chain code, from which the child keys are derived.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedPrivateKey {
+    pub private_key: PrivateKey,
+    pub chain_code: [u8; 32],
+}
+
+impl ExtendedPrivateKey {
+    /// Creates the master key from the seed, which should be of 16 to 64 bytes.
+    pub fn from_seed(seed: impl AsRef<[u8]>) -> Result<Self, Error> {
+        Self::from_hmac(MASTER_KEY_SALT, &[seed.as_ref()])
+    }
+
+    /// Derives the child key of the index, which is hardened if it's at least [`HARDENED`].
+    pub fn derive_child(&self, index: u32) -> Result<Self, Error> {
+        let index_bytes = index.to_be_bytes();
+        let child = if index >= HARDENED {
+            Self::from_hmac(
+                &self.chain_code,
+                &[&[0], self.private_key.as_ref(), &index_bytes],
+            )?
+        } else {
+            Self::from_hmac(
+                &self.chain_code,
+                &[self.private_key.public_key().as_ref(), &index_bytes],
+            )?
+        };
+        let tweak = child
+            .private_key
+            .as_ref()
+            .try_into()
+            .ok()
+            .and_then(|x| Scalar::from_be_bytes(x).ok())
+            .ok_or_else(|| Error::InvalidFormat(format!("child index: {index}")))?;
+        let private_key = SecretKey::from_slice(self.private_key.as_ref())
+            .and_then(|key| key.add_tweak(&tweak))
+            .map_err(|_| Error::InvalidFormat(format!("child index: {index}")))?;
+        Ok(Self {
+            private_key: PrivateKey::from_array(private_key.secret_bytes())?,
+            chain_code: child.chain_code,
+        })
+    }
+
+    /// Derives the descendant key of the path.
+    pub fn derive(&self, path: &DerivationPath) -> Result<Self, Error> {
+        path.indices
+            .iter()
+            .try_fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+
+    /// Splits HMAC-SHA512 into the key (the left half) and the chain code (the right half).
+    ///
+    /// The key is used as a tweak in [`ExtendedPrivateKey::derive_child`], where it must also
+    /// be a valid private key.
+    fn from_hmac(key: &[u8], data: &[&[u8]]) -> Result<Self, Error> {
+        let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC takes any size of key");
+        for data in data {
+            mac.update(data);
+        }
+        let output = mac.finalize().into_bytes();
+        let mut private_key = [0; 32];
+        private_key.copy_from_slice(&output[..32]);
+        let mut chain_code = [0; 32];
+        chain_code.copy_from_slice(&output[32..]);
+        Ok(Self {
+            private_key: PrivateKey::from_array(private_key)?,
+            chain_code,
+        })
+    }
+}
+
+/// Derives the key pair of the path from the seed.
+pub fn derive_keypair(
+    seed: impl AsRef<[u8]>,
+    path: &DerivationPath,
+) -> Result<(PublicKey, PrivateKey), Error> {
+    let private_key = ExtendedPrivateKey::from_seed(seed)?
+        .derive(path)?
+        .private_key;
+    Ok((private_key.public_key(), private_key))
+}
+
+/// Turns the mnemonic into the seed, with the optional passphrase, as BIP39 does.
+///
+/// The words are not checked against the BIP39 word list, and the mnemonic and the passphrase
+/// are expected to be already normalized (NFKD), which is a no-op for ASCII.
+pub fn mnemonic_to_seed(mnemonic: &str, passphrase: &str) -> [u8; 64] {
+    let mut seed = [0; 64];
+    pbkdf2::pbkdf2::<Hmac<Sha512>>(
+        mnemonic.as_bytes(),
+        format!("mnemonic{passphrase}").as_bytes(),
+        MNEMONIC_ITERATIONS,
+        &mut seed,
+    );
+    seed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derivation_path() {
+        let path: DerivationPath = "m/44'/60'/0'/0/1".parse().unwrap();
+        assert_eq!(
+            path.indices,
+            vec![44 + HARDENED, 60 + HARDENED, HARDENED, 0, 1]
+        );
+        assert_eq!(path.to_string(), "m/44'/60'/0'/0/1");
+        assert_eq!(
+            "m/44h/1".parse::<DerivationPath>().unwrap().to_string(),
+            "m/44'/1"
+        );
+        assert_eq!(
+            "m".parse::<DerivationPath>().unwrap(),
+            DerivationPath::default()
+        );
+        assert_eq!(
+            DerivationPath::default()
+                .child(1)
+                .child(2 + HARDENED)
+                .to_string(),
+            "m/1/2'"
+        );
+        for invalid in ["", "44'/0", "m/", "m/a", "m/-1", "m/2147483648"] {
+            invalid.parse::<DerivationPath>().unwrap_err();
+        }
+    }
+
+    /// The test vector 1 of BIP32.
+    #[test]
+    fn bip32_test_vector() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedPrivateKey::from_seed(&seed).unwrap();
+        assert_eq!(
+            hex::encode(&master.private_key),
+            "e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35"
+        );
+        assert_eq!(
+            hex::encode(master.chain_code),
+            "873dff81c02f525623fd1fe5167eac3a55a049de3d314bb42ee227ffed37d508"
+        );
+        let cases = [
+            (
+                "m/0'",
+                "edb2e14f9ee77d26dd93b4ecede8d16ed408ce149b6cd80b0715a2d911a0afea",
+            ),
+            (
+                "m/0'/1",
+                "3c6cb8d0f6a264c91ea8b5030fadaa8e538b020f0a387421a12de9319dc93368",
+            ),
+            (
+                "m/0'/1/2'/2/1000000000",
+                "471b76e389e528d6de6d816857e012c5455051cad6660850e58372a6c3e6e7c8",
+            ),
+        ];
+        for (path, expected) in cases {
+            let (public_key, private_key) = derive_keypair(&seed, &path.parse().unwrap()).unwrap();
+            assert_eq!(hex::encode(&private_key), expected);
+            assert_eq!(public_key, private_key.public_key());
+        }
+    }
+
+    /// The first test vector of BIP39 (with the passphrase `TREZOR`).
+    #[test]
+    fn mnemonic() {
+        let mnemonic = "abandon abandon abandon abandon abandon abandon \
+            abandon abandon abandon abandon abandon about";
+        assert_eq!(
+            hex::encode(mnemonic_to_seed(mnemonic, "TREZOR")),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e5349553\
+            1f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+        assert_ne!(
+            mnemonic_to_seed(mnemonic, "TREZOR"),
+            mnemonic_to_seed(mnemonic, "")
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! The keystore, a JSON file that holds a private key encrypted with a passphrase,
+//! so that the key is never stored in plaintext.
+//!
+//! The passphrase is stretched by Argon2id into the key of ChaCha20-Poly1305,
+//! which encrypts the private key. Everything else in the file (e.g., the public key)
+//! is authenticated along with it, so any tampering fails the decryption
+//! just like a wrong passphrase does.
+use crate::crypto::*;
+use crate::serde_spb;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+use thiserror::Error;
+
+/// The version of the keystore format.
+pub const KEYSTORE_VERSION: u32 = 1;
+
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+
+#[derive(Error, Debug)]
+pub enum KeystoreError {
+    #[error("unsupported keystore version: {0}")]
+    UnsupportedVersion(u32),
+    /// When the passphrase is wrong or the keystore has been tampered with.
+    #[error("failed to decrypt the keystore")]
+    DecryptionFailed,
+    #[error("invalid keystore: {0}")]
+    InvalidFormat(String),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+type Error = KeystoreError;
+
+/// The parameters of Argon2id that derive the encryption key from the passphrase.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct KeyDerivation {
+    /// The hex-encoded salt.
+    pub salt: String,
+    /// The memory cost in KiB.
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KeyDerivation {
+    /// Creates the parameters with a random salt, at the recommended costs of OWASP
+    /// (19 MiB of memory and 2 iterations).
+    pub fn new() -> Self {
+        let mut salt = [0; SALT_SIZE];
+        rand::thread_rng().fill_bytes(&mut salt);
+        Self {
+            salt: hex::encode(salt),
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+
+    fn derive(&self, passphrase: &str) -> Result<Key, Error> {
+        let salt = hex::decode(&self.salt).map_err(|e| Error::InvalidFormat(e.to_string()))?;
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+            .map_err(|e| Error::InvalidFormat(format!("key derivation: {e}")))?;
+        let mut key = Key::default();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| Error::InvalidFormat(format!("key derivation: {e}")))?;
+        Ok(key)
+    }
+}
+
+impl Default for KeyDerivation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A private key encrypted with a passphrase.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Keystore {
+    pub version: u32,
+    /// The public key of the encrypted private key, which can be read without the passphrase.
+    pub public_key: PublicKey,
+    pub key_derivation: KeyDerivation,
+    /// The hex-encoded nonce of ChaCha20-Poly1305.
+    pub nonce: String,
+    /// The hex-encoded private key encrypted by ChaCha20-Poly1305, followed by the tag.
+    pub ciphertext: String,
+}
+
+impl Keystore {
+    /// Encrypts the private key with the passphrase, with a random salt and nonce.
+    pub fn encrypt(private_key: &PrivateKey, passphrase: &str) -> Result<Self, Error> {
+        let mut nonce = [0; NONCE_SIZE];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        Self::encrypt_with(private_key, passphrase, KeyDerivation::new(), nonce)
+    }
+
+    /// Encrypts the private key with the passphrase, with the given parameters and nonce.
+    ///
+    /// The nonce must never be reused with the same salt and passphrase.
+    pub fn encrypt_with(
+        private_key: &PrivateKey,
+        passphrase: &str,
+        key_derivation: KeyDerivation,
+        nonce: [u8; NONCE_SIZE],
+    ) -> Result<Self, Error> {
+        let mut keystore = Self {
+            version: KEYSTORE_VERSION,
+            public_key: private_key.public_key(),
+            key_derivation,
+            nonce: hex::encode(nonce),
+            ciphertext: String::new(),
+        };
+        let ciphertext = ChaCha20Poly1305::new(&keystore.key_derivation.derive(passphrase)?)
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: private_key.as_ref(),
+                    aad: &keystore.associated_data(),
+                },
+            )
+            .map_err(|_| Error::InvalidFormat("encryption failed".to_owned()))?;
+        keystore.ciphertext = hex::encode(ciphertext);
+        Ok(keystore)
+    }
+
+    /// Decrypts the private key with the passphrase.
+    pub fn decrypt(&self, passphrase: &str) -> Result<PrivateKey, Error> {
+        if self.version != KEYSTORE_VERSION {
+            return Err(Error::UnsupportedVersion(self.version));
+        }
+        let nonce = hex::decode(&self.nonce).map_err(|e| Error::InvalidFormat(e.to_string()))?;
+        if nonce.len() != NONCE_SIZE {
+            return Err(Error::InvalidFormat(format!(
+                "nonce of {} bytes",
+                nonce.len()
+            )));
+        }
+        let ciphertext =
+            hex::decode(&self.ciphertext).map_err(|e| Error::InvalidFormat(e.to_string()))?;
+        let plaintext = ChaCha20Poly1305::new(&self.key_derivation.derive(passphrase)?)
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: &self.associated_data(),
+                },
+            )
+            .map_err(|_| Error::DecryptionFailed)?;
+        let private_key = PrivateKey::from_array(
+            plaintext
+                .as_slice()
+                .try_into()
+                .map_err(|_| Error::InvalidFormat(format!("key of {} bytes", plaintext.len())))?,
+        )
+        .map_err(|e| Error::InvalidFormat(e.to_string()))?;
+        check_keypair_match(&self.public_key, &private_key)
+            .map_err(|_| Error::InvalidFormat("the public key doesn't match".to_owned()))?;
+        Ok(private_key)
+    }
+
+    /// Reads the keystore from the file, without decrypting it.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let keystore: Self = serde_spb::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| Error::InvalidFormat(e.to_string()))?;
+        if keystore.version != KEYSTORE_VERSION {
+            return Err(Error::UnsupportedVersion(keystore.version));
+        }
+        Ok(keystore)
+    }
+
+    /// Writes the keystore to the file in the JSON of [`serde_spb`],
+    /// readable and writable only by the owner on Unix.
+    ///
+    /// It's written to a temporary file next to it first, which is then renamed into place,
+    /// so an existing keystore is never left half-written.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let json = serde_spb::to_string(self).map_err(|e| Error::InvalidFormat(e.to_string()))?;
+        let mut temp_name = path
+            .file_name()
+            .ok_or_else(|| Error::InvalidFormat(format!("not a file: {}", path.display())))?
+            .to_owned();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+        // A leftover may have been created with any permissions.
+        if temp_path.exists() {
+            std::fs::remove_file(&temp_path)?;
+        }
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&temp_path)?;
+        file.write_all(json.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Everything but the ciphertext, which the encryption authenticates.
+    fn associated_data(&self) -> Vec<u8> {
+        serde_spb::to_vec(&(
+            self.version,
+            &self.public_key,
+            &self.key_derivation,
+            &self.nonce,
+        ))
+        .expect("serialization of the keystore never fails")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The cheapest parameters, to keep the tests fast.
+    fn key_derivation() -> KeyDerivation {
+        KeyDerivation {
+            memory_kib: 8,
+            iterations: 1,
+            ..KeyDerivation::new()
+        }
+    }
+
+    #[test]
+    fn encrypt_decrypt() {
+        let (public_key, private_key) = generate_keypair("hello world");
+        let keystore =
+            Keystore::encrypt_with(&private_key, "passphrase", key_derivation(), [1; 12]).unwrap();
+        assert_eq!(keystore.public_key, public_key);
+        assert!(!keystore.ciphertext.contains(&hex::encode(&private_key)));
+        assert_eq!(keystore.decrypt("passphrase").unwrap(), private_key);
+        assert!(matches!(
+            keystore.decrypt("wrong"),
+            Err(KeystoreError::DecryptionFailed)
+        ));
+
+        // Tampering with any part fails the decryption.
+        let mut tampered = keystore.clone();
+        tampered.public_key = generate_keypair("other").0;
+        assert!(matches!(
+            tampered.decrypt("passphrase"),
+            Err(KeystoreError::DecryptionFailed)
+        ));
+        let mut tampered = keystore.clone();
+        tampered.key_derivation.iterations = 2;
+        assert!(matches!(
+            tampered.decrypt("passphrase"),
+            Err(KeystoreError::DecryptionFailed)
+        ));
+        let mut tampered = keystore.clone();
+        tampered.version = 2;
+        assert!(matches!(
+            tampered.decrypt("passphrase"),
+            Err(KeystoreError::UnsupportedVersion(2))
+        ));
+
+        // Random salts and nonces.
+        let other = Keystore::encrypt(&private_key, "passphrase").unwrap();
+        assert_ne!(other.key_derivation.salt, keystore.key_derivation.salt);
+        assert_ne!(other.ciphertext, keystore.ciphertext);
+    }
+
+    #[test]
+    fn save_load() {
+        let (_, private_key) = generate_keypair("hello world");
+        let keystore =
+            Keystore::encrypt_with(&private_key, "passphrase", key_derivation(), [1; 12]).unwrap();
+        let path =
+            std::env::temp_dir().join(format!("simperby-keystore-{}.json", std::process::id()));
+        keystore.save(&path).unwrap();
+        let json = std::fs::read_to_string(&path).unwrap();
+        assert!(json.contains("\"version\": 1"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        // Overwriting an existing one.
+        keystore.save(&path).unwrap();
+        let loaded = Keystore::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, keystore);
+        assert_eq!(loaded.decrypt("passphrase").unwrap(), private_key);
+        assert!(matches!(Keystore::load(path), Err(KeystoreError::Io(_))));
+    }
+}
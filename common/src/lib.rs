@@ -1,9 +1,15 @@
 pub mod crypto;
 pub mod hash;
+pub mod hd;
+pub mod keystore;
 pub mod light_client;
 pub mod merkle_tree;
 pub mod reserved;
 pub mod serde_spb;
+pub mod signer;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod threshold;
 pub mod types;
 pub mod verify;
 
@@ -1,6 +1,12 @@
+//! The light client, which follows the chain by the block headers only
+//! and verifies the data committed in the blocks with their Merkle proofs.
+//!
+//! It's meant to run where the full node can't, e.g., in a treasury contract on another chain.
+//! So it rejects an invalid input with an error (or `false`) instead of panicking.
 use crate::*;
 use merkle_tree::*;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// A light client state machine.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -9,6 +15,9 @@ pub struct LightClient {
     pub commit_roots: Vec<Hash256>,
     pub height_offset: u64,
     pub last_header: BlockHeader,
+    /// The BLS keys registered by the validators, which the aggregate finalization proofs are verified with.
+    #[serde(default)]
+    pub bls_keys: BTreeMap<PublicKey, BlsPublicKey>,
 }
 
 impl LightClient {
@@ -19,47 +28,299 @@ impl LightClient {
             commit_roots: vec![initial_header.commit_merkle_root],
             height_offset: initial_header.height,
             last_header: initial_header,
+            bls_keys: BTreeMap::new(),
         }
     }
 
     /// Updates the header by providing the next block and the proof of it.
     pub fn update(&mut self, header: BlockHeader, proof: FinalizationProof) -> Result<(), String> {
-        verify::verify_header_to_header(&self.last_header, &header).map_err(|e| e.to_string())?;
-        verify::verify_finalization_proof(&header, &proof).map_err(|e| e.to_string())?;
-        self.repository_roots.push(header.repository_merkle_root);
-        self.commit_roots.push(header.commit_merkle_root);
-        self.last_header = header;
+        self.update_any(header, AnyFinalizationProof::Individual(proof))
+    }
+
+    /// Updates the header by providing the next block and the proof of it in either scheme.
+    ///
+    /// An aggregate proof is verified with the BLS keys registered by [`LightClient::register_bls_key`].
+    pub fn update_any(
+        &mut self,
+        header: BlockHeader,
+        proof: AnyFinalizationProof,
+    ) -> Result<(), String> {
+        self.update_chain(vec![header], proof)
+    }
+
+    /// Updates the header by providing the next blocks in order and the proof of the last one.
+    ///
+    /// The proofs of the others are in their next headers (`prev_block_finalization_proof`),
+    /// each verified with the validator set of the header it finalizes, so that the changes of
+    /// the validator set are followed. Nothing is applied unless all of them are valid.
+    pub fn update_chain(
+        &mut self,
+        headers: Vec<BlockHeader>,
+        proof: AnyFinalizationProof,
+    ) -> Result<(), String> {
+        let last = headers.last().ok_or("no header to update with")?;
+        let mut previous = &self.last_header;
+        for header in &headers {
+            verify::verify_header_to_header(previous, header).map_err(|e| e.to_string())?;
+            previous = header;
+        }
+        verify::verify_any_finalization_proof(last, &proof, &self.bls_keys)
+            .map_err(|e| e.to_string())?;
+        for header in headers {
+            self.repository_roots.push(header.repository_merkle_root);
+            self.commit_roots.push(header.commit_merkle_root);
+            // The BLS keys of the validators that have left are no longer needed.
+            self.bls_keys.retain(|public_key, _| {
+                header
+                    .validator_set
+                    .iter()
+                    .any(|(validator, _)| validator == public_key)
+            });
+            self.last_header = header;
+        }
         Ok(())
     }
 
-    /// Verifies the given transaction with its proof.
+    /// Registers the BLS key of a validator in the last header, replacing its previous one.
+    pub fn register_bls_key(&mut self, binding: BlsKeyBinding) -> Result<(), String> {
+        binding.verify().map_err(|e| e.to_string())?;
+        let public_key = binding.public_key();
+        if !self
+            .last_header
+            .validator_set
+            .iter()
+            .any(|(x, _)| x == public_key)
+        {
+            return Err(format!("{public_key} is not in the validator set"));
+        }
+        self.bls_keys
+            .insert(public_key.clone(), binding.bls_public_key);
+        Ok(())
+    }
+
+    /// Verifies the given transaction with its proof,
+    /// against the commit Merkle root of the block at the height.
     pub fn verify_transaction_commitment(
         &self,
         transaction: &Transaction,
         block_height: u64,
         proof: MerkleProof,
     ) -> bool {
-        let message = serde_spb::to_vec(transaction).unwrap();
-        if block_height < self.height_offset
-            || block_height >= self.height_offset + self.commit_roots.len() as u64
-        {
-            return false;
+        let message = match serde_spb::to_vec(transaction) {
+            Ok(message) => message,
+            Err(_) => return false,
+        };
+        match self.root_at(&self.commit_roots, block_height) {
+            Some(root) => proof.verify(root, &message).is_ok(),
+            None => false,
         }
-        proof
-            .verify(
-                self.commit_roots[(block_height - self.height_offset) as usize],
-                &message,
-            )
-            .is_ok()
     }
 
-    /// Verifies the state entry with its proof.
+    /// Verifies the state entry with its proof,
+    /// against the repository Merkle root of the block at the height.
     pub fn verify_state_commitment(
         &self,
-        _message: Vec<u8>,
-        _block_height: u64,
-        _proof: MerkleProof,
+        message: Vec<u8>,
+        block_height: u64,
+        proof: MerkleProof,
     ) -> bool {
-        todo!()
+        match self.root_at(&self.repository_roots, block_height) {
+            Some(root) => proof.verify(root, &message).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Returns the root of the block at the height, if the light client has followed it.
+    fn root_at(&self, roots: &[Hash256], block_height: u64) -> Option<Hash256> {
+        let index = block_height.checked_sub(self.height_offset)?;
+        roots.get(usize::try_from(index).ok()?).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregate_finalization_proof() {
+        let keys = (0..4)
+            .map(|i| {
+                let (public_key, private_key) = generate_keypair(format!("validator{i}"));
+                let (_, bls_private_key) = generate_bls_keypair(format!("validator{i}"));
+                (public_key, private_key, bls_private_key)
+            })
+            .collect::<Vec<_>>();
+        let genesis = BlockHeader {
+            author: keys[0].0.clone(),
+            prev_block_finalization_proof: Vec::new(),
+            previous_hash: Hash256::zero(),
+            height: 0,
+            timestamp: 0,
+            commit_merkle_root: Hash256::zero(),
+            repository_merkle_root: Hash256::zero(),
+            validator_set: keys.iter().map(|(x, _, _)| (x.clone(), 1)).collect(),
+            version: "0.0.0".to_owned(),
+        };
+        let header = BlockHeader {
+            prev_block_finalization_proof: keys
+                .iter()
+                .map(|(_, x, _)| TypedSignature::sign(&genesis, x).unwrap())
+                .collect(),
+            previous_hash: genesis.to_hash256(),
+            height: 1,
+            ..genesis.clone()
+        };
+        let proof = |n: usize| {
+            AggregateFinalizationProof::aggregate(
+                keys[0..n]
+                    .iter()
+                    .map(|(public_key, _, bls_private_key)| {
+                        (
+                            public_key.clone(),
+                            BlsSignature::sign(header.to_hash256(), bls_private_key).unwrap(),
+                        )
+                    })
+                    .collect(),
+            )
+            .unwrap()
+        };
+
+        let mut light_client = LightClient::new(genesis);
+        // Without the BLS keys registered.
+        let update = |light_client: &LightClient, proof| {
+            light_client
+                .clone()
+                .update_any(header.clone(), AnyFinalizationProof::Aggregate(proof))
+        };
+        update(&light_client, proof(3)).unwrap_err();
+        for (_, private_key, bls_private_key) in &keys {
+            light_client
+                .register_bls_key(BlsKeyBinding::create(private_key, bls_private_key).unwrap())
+                .unwrap();
+        }
+        let (_, outsider) = generate_keypair("outsider");
+        light_client
+            .register_bls_key(BlsKeyBinding::create(&outsider, &keys[0].2).unwrap())
+            .unwrap_err();
+
+        // Not more than 2/3 of the voting power.
+        update(&light_client, proof(2)).unwrap_err();
+        let mut duplicate = proof(2);
+        duplicate.signers.push(keys[0].0.clone());
+        update(&light_client, duplicate).unwrap_err();
+        let mut mislabeled = proof(3);
+        mislabeled.signers[2] = keys[3].0.clone();
+        update(&light_client, mislabeled).unwrap_err();
+
+        light_client
+            .update_any(header.clone(), AnyFinalizationProof::Aggregate(proof(3)))
+            .unwrap();
+        assert_eq!(light_client.last_header, header);
+    }
+
+    #[test]
+    fn header_chain() {
+        let keys = (0..4)
+            .map(|i| generate_keypair(format!("validator{i}")))
+            .collect::<Vec<_>>();
+        let sign = |header: &BlockHeader, signers: &[usize]| {
+            signers
+                .iter()
+                .map(|i| TypedSignature::sign(header, &keys[*i].1).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let transaction = Transaction {
+            author: keys[0].0.clone(),
+            timestamp: 0,
+            head: "hello".to_owned(),
+            body: "world".to_owned(),
+            diff: Diff::None,
+        };
+        let commit_tree =
+            OneshotMerkleTree::create(vec![Hash256::hash("other"), transaction.to_hash256()]);
+        let repository_tree = OneshotMerkleTree::create(vec![
+            Hash256::hash("entry0"),
+            Hash256::hash("entry1"),
+            Hash256::hash("entry2"),
+        ]);
+        let genesis = BlockHeader {
+            author: keys[0].0.clone(),
+            prev_block_finalization_proof: Vec::new(),
+            previous_hash: Hash256::zero(),
+            height: 0,
+            timestamp: 0,
+            commit_merkle_root: Hash256::zero(),
+            repository_merkle_root: Hash256::zero(),
+            validator_set: keys.iter().map(|(x, _)| (x.clone(), 1)).collect(),
+            version: "0.0.0".to_owned(),
+        };
+        // The last validator leaves.
+        let header1 = BlockHeader {
+            prev_block_finalization_proof: sign(&genesis, &[0, 1, 2, 3]),
+            previous_hash: genesis.to_hash256(),
+            height: 1,
+            commit_merkle_root: commit_tree.root(),
+            validator_set: genesis.validator_set[0..3].to_vec(),
+            ..genesis.clone()
+        };
+        let header2 = BlockHeader {
+            prev_block_finalization_proof: sign(&header1, &[0, 1, 2]),
+            previous_hash: header1.to_hash256(),
+            height: 2,
+            commit_merkle_root: Hash256::zero(),
+            repository_merkle_root: repository_tree.root(),
+            ..header1.clone()
+        };
+
+        let mut light_client = LightClient::new(genesis.clone());
+        for i in [0, 3] {
+            let (_, bls_private_key) = generate_bls_keypair(format!("validator{i}"));
+            light_client
+                .register_bls_key(BlsKeyBinding::create(&keys[i].1, &bls_private_key).unwrap())
+                .unwrap();
+        }
+        let update = |light_client: &LightClient, headers: &[&BlockHeader], signers: &[usize]| {
+            let mut light_client = light_client.clone();
+            light_client
+                .update_chain(
+                    headers.iter().map(|x| (*x).clone()).collect(),
+                    AnyFinalizationProof::Individual(sign(headers.last().unwrap(), signers)),
+                )
+                .map(|_| light_client)
+        };
+        light_client
+            .update_chain(Vec::new(), AnyFinalizationProof::Individual(Vec::new()))
+            .unwrap_err();
+        // The one who left doesn't count.
+        update(&light_client, &[&header1, &header2], &[1, 2, 3]).unwrap_err();
+        // Not consecutive.
+        update(&light_client, &[&header2], &[0, 1, 2]).unwrap_err();
+        // An invalid proof in the middle.
+        let mut invalid = header2.clone();
+        invalid.prev_block_finalization_proof = sign(&header1, &[0, 3]);
+        update(&light_client, &[&header1, &invalid], &[0, 1, 2]).unwrap_err();
+
+        let light_client = update(&light_client, &[&header1, &header2], &[0, 1, 2]).unwrap();
+        assert_eq!(light_client.last_header, header2);
+        assert_eq!(
+            light_client.bls_keys.keys().collect::<Vec<_>>(),
+            vec![&keys[0].0]
+        );
+
+        let proof = commit_tree
+            .create_merkle_proof(transaction.to_hash256())
+            .unwrap();
+        assert!(light_client.verify_transaction_commitment(&transaction, 1, proof.clone()));
+        for height in [0, 2, 3, u64::MAX] {
+            assert!(!light_client.verify_transaction_commitment(
+                &transaction,
+                height,
+                proof.clone()
+            ));
+        }
+        let proof = repository_tree.create_merkle_proof_at(2).unwrap();
+        assert!(light_client.verify_state_commitment(b"entry2".to_vec(), 2, proof.clone()));
+        assert!(!light_client.verify_state_commitment(b"entry1".to_vec(), 2, proof.clone()));
+        assert!(!light_client.verify_state_commitment(b"entry2".to_vec(), 1, proof));
     }
 }
@@ -1,61 +1,114 @@
+//! The Merkle tree of the hashes, which commits to a list of data (e.g., the commits of a block)
+//! so that the inclusion of each can be proven with [`MerkleProof`].
+//!
+//! How the nodes are hashed is configurable with [`MerkleHasher`],
+//! where [`Keccak256Hasher`] is the one used by Simperby itself.
 use crate::*;
 use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
 use thiserror::Error;
 
+/// The hash functions of the nodes of a Merkle tree.
+pub trait MerkleHasher {
+    /// Hashes the data of a leaf.
+    fn hash_leaf(data: &[u8]) -> Hash256;
+
+    /// Hashes the pair of the sibling nodes into their parent.
+    fn hash_pair(left: &Hash256, right: &Hash256) -> Hash256;
+
+    /// Hashes the node without a sibling into its parent.
+    fn hash_only_child(node: &Hash256) -> Hash256;
+}
+
+/// The hasher of the Merkle trees in the blocks, which uses Keccak-256
+/// (i.e., [`Hash256::hash`] and [`Hash256::aggregate`]) for all the nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Keccak256Hasher;
+
+impl MerkleHasher for Keccak256Hasher {
+    fn hash_leaf(data: &[u8]) -> Hash256 {
+        Hash256::hash(data)
+    }
+
+    fn hash_pair(left: &Hash256, right: &Hash256) -> Hash256 {
+        Hash256::aggregate(left, right)
+    }
+
+    fn hash_only_child(node: &Hash256) -> Hash256 {
+        Hash256::hash(node)
+    }
+}
+
 /// A Merkle tree that is created once but never modified.
 ///
 /// This is useful for per-block data such as transaction lists.
-pub struct OneshotMerkleTree {
+pub struct OneshotMerkleTree<H: MerkleHasher = Keccak256Hasher> {
     hash_list: Vec<Hash256>,
+    _hasher: PhantomData<H>,
 }
 
 impl OneshotMerkleTree {
+    /// The root of the empty tree, regardless of the hasher.
     pub const EMPTY_HASH: Hash256 = Hash256::zero();
 
-    /// Creates a new OneshotMerkleTree from the given data.
+    /// Creates a new OneshotMerkleTree from the given data, with [`Keccak256Hasher`].
     pub fn create(data: Vec<Hash256>) -> Self {
-        OneshotMerkleTree { hash_list: data }
+        Self::create_with_hasher(data)
+    }
+}
+
+impl<H: MerkleHasher> OneshotMerkleTree<H> {
+    /// Creates a new OneshotMerkleTree from the given data, with the hasher `H`.
+    ///
+    /// The data are the hashes of the leaves (i.e., `H::hash_leaf` of the original data).
+    pub fn create_with_hasher(data: Vec<Hash256>) -> Self {
+        OneshotMerkleTree {
+            hash_list: data,
+            _hasher: PhantomData,
+        }
     }
 
     /// Creates a Merkle proof for a given data in the tree.
     ///
     /// Returns `None` if the data is not in the tree.
+    /// If the data appears more than once, the proof is of the first one.
+    pub fn create_merkle_proof(&self, key: Hash256) -> Option<MerkleProof> {
+        let index = self.hash_list.iter().position(|x| *x == key)?;
+        self.create_merkle_proof_at(index)
+    }
+
+    /// Creates a Merkle proof for the data at the given index.
+    ///
+    /// Returns `None` if the index is out of range.
     ///
     /// Given a tree [[1, 2, 3], [4, 5], [6]],
     /// Merkle proof for 2 is [1, 5] and Merkle proof for 3 is [OnlyChild, 4].
     ///
     /// For `LeftChild` and `RightChild`, pair hash of the sibling node is given.
     /// For `OnlyChild`, only the instruction is given.
-    pub fn create_merkle_proof(&self, key: Hash256) -> Option<MerkleProof> {
-        if !self.hash_list.contains(&key) {
+    pub fn create_merkle_proof_at(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.hash_list.len() {
             return None;
         }
         let mut merkle_proof: MerkleProof = MerkleProof { proof: Vec::new() };
         let mut merkle_tree: Vec<Vec<Hash256>> = Self::merkle_tree(&self.hash_list);
-        let mut target_hash: Hash256 = key;
+        let mut index = index;
         // Pop because the root is never included in the Merkle proof
         merkle_tree.pop();
         for level in merkle_tree {
-            for pair in level.chunks(2) {
-                if pair.contains(&target_hash) {
-                    if pair.len() == 2 {
-                        let is_right_node: bool = pair[0] == target_hash;
-                        if is_right_node {
-                            merkle_proof
-                                .proof
-                                .push(MerkleProofEntry::RightChild(pair[is_right_node as usize]))
-                        } else {
-                            merkle_proof
-                                .proof
-                                .push(MerkleProofEntry::LeftChild(pair[is_right_node as usize]))
-                        }
-                        target_hash = Hash256::aggregate(&pair[0], &pair[1]);
-                    } else {
-                        merkle_proof.proof.push(MerkleProofEntry::OnlyChild);
-                        target_hash = Hash256::hash(pair[0]);
-                    };
-                }
+            let sibling = index ^ 1;
+            if sibling >= level.len() {
+                merkle_proof.proof.push(MerkleProofEntry::OnlyChild);
+            } else if index % 2 == 0 {
+                merkle_proof
+                    .proof
+                    .push(MerkleProofEntry::RightChild(level[sibling]));
+            } else {
+                merkle_proof
+                    .proof
+                    .push(MerkleProofEntry::LeftChild(level[sibling]));
             }
+            index /= 2;
         }
         Some(merkle_proof)
     }
@@ -82,9 +135,9 @@ impl OneshotMerkleTree {
             let mut upper_level_hash_list: Vec<Hash256> = Vec::new();
             for pair in merkle_tree.last().unwrap().chunks(2) {
                 if pair.len() == 2 {
-                    upper_level_hash_list.push(Hash256::aggregate(&pair[0], &pair[1]));
+                    upper_level_hash_list.push(H::hash_pair(&pair[0], &pair[1]));
                 } else {
-                    upper_level_hash_list.push(Hash256::hash(pair[0]));
+                    upper_level_hash_list.push(H::hash_only_child(&pair[0]));
                 }
             }
             merkle_tree.push(upper_level_hash_list);
@@ -104,12 +157,12 @@ impl OneshotMerkleTree {
 
     /// Returns the root of the tree.
     ///
-    /// If the tree is empty, this returns a `Self::EMPTY_HASH`.
+    /// If the tree is empty, this returns a `OneshotMerkleTree::EMPTY_HASH`.
     ///
     /// Never panics on unwrap because merkle_tree is initialized with `vec![hash_list.to_vec()]` where `hash_list` is not empty.
     pub fn root(&self) -> Hash256 {
         if self.hash_list.is_empty() {
-            Self::EMPTY_HASH
+            OneshotMerkleTree::EMPTY_HASH
         } else {
             Self::merkle_tree(&self.hash_list)
                 .last()
@@ -143,19 +196,30 @@ pub enum MerkleProofError {
     UnmatchedRoot(String, String),
 }
 
+const LEFT_CHILD_TAG: u8 = 0;
+const RIGHT_CHILD_TAG: u8 = 1;
+const ONLY_CHILD_TAG: u8 = 2;
+
 impl MerkleProof {
     /// Verifies whether the given data is in the block.
     pub fn verify(&self, root: Hash256, data: &[u8]) -> Result<(), MerkleProofError> {
-        let mut calculated_root: Hash256 = Hash256::hash(data);
+        self.verify_with_hasher::<Keccak256Hasher>(root, data)
+    }
+
+    /// Verifies whether the given data is in the tree of the hasher `H`.
+    pub fn verify_with_hasher<H: MerkleHasher>(
+        &self,
+        root: Hash256,
+        data: &[u8],
+    ) -> Result<(), MerkleProofError> {
+        let mut calculated_root: Hash256 = H::hash_leaf(data);
         for node in &self.proof {
             calculated_root = match node {
-                MerkleProofEntry::LeftChild(pair_hash) => {
-                    Hash256::aggregate(pair_hash, &calculated_root)
-                }
+                MerkleProofEntry::LeftChild(pair_hash) => H::hash_pair(pair_hash, &calculated_root),
                 MerkleProofEntry::RightChild(pair_hash) => {
-                    Hash256::aggregate(&calculated_root, pair_hash)
+                    H::hash_pair(&calculated_root, pair_hash)
                 }
-                MerkleProofEntry::OnlyChild => Hash256::hash(calculated_root),
+                MerkleProofEntry::OnlyChild => H::hash_only_child(&calculated_root),
             };
         }
         if root == calculated_root {
@@ -167,6 +231,67 @@ impl MerkleProof {
             ))
         }
     }
+
+    /// Encodes the proof in its canonical binary form, which is independent of `serde`.
+    ///
+    /// It's the number of the entries as a big-endian `u32`, followed by each entry as a tag byte
+    /// (`0` for `LeftChild`, `1` for `RightChild` and `2` for `OnlyChild`)
+    /// and, except for `OnlyChild`, the 32 bytes of the sibling hash.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = (self.proof.len() as u32).to_be_bytes().to_vec();
+        for entry in &self.proof {
+            match entry {
+                MerkleProofEntry::LeftChild(hash) => {
+                    bytes.push(LEFT_CHILD_TAG);
+                    bytes.extend_from_slice(hash.as_ref());
+                }
+                MerkleProofEntry::RightChild(hash) => {
+                    bytes.push(RIGHT_CHILD_TAG);
+                    bytes.extend_from_slice(hash.as_ref());
+                }
+                MerkleProofEntry::OnlyChild => bytes.push(ONLY_CHILD_TAG),
+            }
+        }
+        bytes
+    }
+
+    /// Decodes the proof from its canonical binary form (see [`MerkleProof::to_bytes`]).
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MerkleProofError> {
+        let malformed = |message: &str| MerkleProofError::MalformedProof(message.to_owned());
+        let mut rest = bytes;
+        let length = take::<4>(&mut rest).ok_or_else(|| malformed("missing the length"))?;
+        let length = u32::from_be_bytes(length) as usize;
+        // Every entry takes at least a byte, which bounds the allocation.
+        if length > rest.len() {
+            return Err(malformed("too many entries"));
+        }
+        let mut proof = Vec::with_capacity(length);
+        for _ in 0..length {
+            let [tag] = take::<1>(&mut rest).ok_or_else(|| malformed("missing an entry"))?;
+            let mut sibling = || {
+                take::<32>(&mut rest)
+                    .map(Hash256::from_array)
+                    .ok_or_else(|| malformed("missing a sibling hash"))
+            };
+            proof.push(match tag {
+                LEFT_CHILD_TAG => MerkleProofEntry::LeftChild(sibling()?),
+                RIGHT_CHILD_TAG => MerkleProofEntry::RightChild(sibling()?),
+                ONLY_CHILD_TAG => MerkleProofEntry::OnlyChild,
+                tag => return Err(malformed(&format!("unknown tag {tag}"))),
+            });
+        }
+        if !rest.is_empty() {
+            return Err(malformed("trailing bytes"));
+        }
+        Ok(Self { proof })
+    }
+}
+
+/// Takes the first `N` bytes, advancing the slice.
+fn take<const N: usize>(bytes: &mut &[u8]) -> Option<[u8; N]> {
+    let head = bytes.get(..N)?.try_into().ok()?;
+    *bytes = &bytes[N..];
+    Some(head)
 }
 
 #[cfg(test)]
@@ -265,4 +390,131 @@ mod test {
         assert!(root_hash != OneshotMerkleTree::EMPTY_HASH);
         assert!(MerkleProof::verify(&merkle_proof.unwrap(), root_hash, &[10]).is_ok());
     }
+
+    /// A hasher with the domain separation of the leaves and the inner nodes,
+    /// to test a tree with a hasher other than the default.
+    struct PrefixedHasher;
+
+    impl MerkleHasher for PrefixedHasher {
+        fn hash_leaf(data: &[u8]) -> Hash256 {
+            Hash256::hash([&[0], data].concat())
+        }
+
+        fn hash_pair(left: &Hash256, right: &Hash256) -> Hash256 {
+            Hash256::hash([&[1], left.as_ref(), right.as_ref()].concat())
+        }
+
+        fn hash_only_child(node: &Hash256) -> Hash256 {
+            Hash256::hash([&[2], node.as_ref()].concat())
+        }
+    }
+
+    #[test]
+    /// Test the trees with no leaf and a single leaf.
+    fn empty_and_single_leaf() {
+        let merkle_tree = OneshotMerkleTree::create(Vec::new());
+        assert!(merkle_tree.create_merkle_proof_at(0).is_none());
+
+        let merkle_tree = OneshotMerkleTree::create(create_hash_list(1));
+        let merkle_proof = merkle_tree.create_merkle_proof_at(0).unwrap();
+        assert_eq!(merkle_tree.root(), Hash256::hash([0]));
+        assert!(merkle_proof.proof.is_empty());
+        merkle_proof.verify(merkle_tree.root(), &[0]).unwrap();
+        merkle_proof.verify(merkle_tree.root(), &[1]).unwrap_err();
+        assert!(merkle_tree.create_merkle_proof_at(1).is_none());
+    }
+
+    #[test]
+    /// Test the root of a small tree against the one computed by hand,
+    /// so that the roots in the existing blocks never change.
+    fn root_of_three_leaves() {
+        let hash_list = create_hash_list(3);
+        let expected = Hash256::aggregate(
+            &Hash256::aggregate(&hash_list[0], &hash_list[1]),
+            &Hash256::hash(hash_list[2]),
+        );
+        assert_eq!(OneshotMerkleTree::create(hash_list).root(), expected);
+    }
+
+    #[test]
+    /// Test the proofs of every leaf of the trees of every size up to 33 leaves,
+    /// covering the odd number of nodes at each level.
+    fn every_leaf_of_every_size() {
+        for number in 1..=33 {
+            let merkle_tree = OneshotMerkleTree::create(create_hash_list(number));
+            let root_hash = merkle_tree.root();
+            for n in 0..number {
+                let merkle_proof = merkle_tree.create_merkle_proof_at(n as usize).unwrap();
+                assert_eq!(
+                    merkle_tree.create_merkle_proof(Hash256::hash([n])),
+                    Some(merkle_proof.clone())
+                );
+                merkle_proof.verify(root_hash, &[n]).unwrap();
+                merkle_proof.verify(root_hash, &[number]).unwrap_err();
+                assert_eq!(
+                    MerkleProof::from_bytes(&merkle_proof.to_bytes()).unwrap(),
+                    merkle_proof
+                );
+            }
+            assert!(merkle_tree
+                .create_merkle_proof_at(number as usize)
+                .is_none());
+        }
+    }
+
+    #[test]
+    /// Test a tree with a hasher other than the default.
+    fn custom_hasher() {
+        let hash_list: Vec<Hash256> = (0..5).map(|n| PrefixedHasher::hash_leaf(&[n])).collect();
+        let merkle_tree =
+            OneshotMerkleTree::<PrefixedHasher>::create_with_hasher(hash_list.clone());
+        assert_ne!(
+            merkle_tree.root(),
+            OneshotMerkleTree::create(hash_list).root()
+        );
+        let merkle_proof = merkle_tree.create_merkle_proof_at(4).unwrap();
+        merkle_proof
+            .verify_with_hasher::<PrefixedHasher>(merkle_tree.root(), &[4])
+            .unwrap();
+        merkle_proof.verify(merkle_tree.root(), &[4]).unwrap_err();
+    }
+
+    #[test]
+    /// Test the canonical binary form of a proof against the one written by hand.
+    fn proof_serialization() {
+        let merkle_proof = MerkleProof {
+            proof: vec![
+                MerkleProofEntry::LeftChild(Hash256::hash([0])),
+                MerkleProofEntry::OnlyChild,
+                MerkleProofEntry::RightChild(Hash256::hash([1])),
+            ],
+        };
+        let bytes = [
+            &[0, 0, 0, 3, 0][..],
+            Hash256::hash([0]).as_ref(),
+            &[2, 1],
+            Hash256::hash([1]).as_ref(),
+        ]
+        .concat();
+        assert_eq!(merkle_proof.to_bytes(), bytes);
+        assert_eq!(MerkleProof::from_bytes(&bytes).unwrap(), merkle_proof);
+        assert_eq!(
+            MerkleProof::from_bytes(&[0, 0, 0, 0]).unwrap(),
+            MerkleProof { proof: Vec::new() }
+        );
+
+        // Truncated, with trailing bytes, with an unknown tag and with a huge length.
+        for malformed in [
+            &bytes[..bytes.len() - 1],
+            &[bytes.as_slice(), &[0]].concat(),
+            &[0, 0, 0, 1, 3],
+            &[255, 255, 255, 255, 2],
+            &[0, 0, 0],
+        ] {
+            assert!(matches!(
+                MerkleProof::from_bytes(malformed),
+                Err(MerkleProofError::MalformedProof(_))
+            ));
+        }
+    }
 }
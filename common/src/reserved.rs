@@ -73,6 +73,41 @@ impl ReservedState {
         unimplemented!()
     }
 
+    /// Replaces the key of the member, checking the signatures of both keys on the rotation
+    /// at `block_height`.
+    pub fn apply_rotate_key(
+        &mut self,
+        tx: &TxRotateKey,
+        block_height: BlockHeight,
+    ) -> Result<Self, String> {
+        let data = KeyRotationData {
+            old_key: tx.old_key.clone(),
+            new_key: tx.new_key.clone(),
+            block_height,
+        };
+        for (proof, key) in [
+            (&tx.old_key_proof, &tx.old_key),
+            (&tx.new_key_proof, &tx.new_key),
+        ] {
+            if proof.signer() != key {
+                return Err(format!("the proof is not signed by {key}"));
+            }
+            proof
+                .verify(&data)
+                .map_err(|e| format!("invalid proof of {key}: {e}"))?;
+        }
+        if self.query_name(&tx.new_key).is_some() {
+            return Err(format!("{} is already a key of a member", tx.new_key));
+        }
+        let member = self
+            .members
+            .iter_mut()
+            .find(|member| member.public_key == tx.old_key)
+            .ok_or_else(|| format!("{} is not a key of a member", tx.old_key))?;
+        member.public_key = tx.new_key.clone();
+        Ok(self.clone())
+    }
+
     pub fn query_name(&self, public_key: &PublicKey) -> Option<MemberName> {
         for member in &self.members {
             if &member.public_key == public_key {
@@ -338,4 +373,84 @@ mod tests {
                 .collect::<HashSet<_>>()
         );
     }
+
+    #[test]
+    fn rotate_key() {
+        setup_test();
+        let keys = (0..5)
+            .into_iter()
+            .map(|i| generate_keypair(format!("{i}")))
+            .collect::<Vec<_>>();
+        let members = (0..4)
+            .map(|i| create_member(keys.clone(), i))
+            .collect::<Vec<_>>();
+        let genesis_header = BlockHeader {
+            author: PublicKey::zero(),
+            prev_block_finalization_proof: Vec::new(),
+            previous_hash: Hash256::zero(),
+            height: 0,
+            timestamp: 0,
+            commit_merkle_root: Hash256::zero(),
+            repository_merkle_root: Hash256::zero(),
+            validator_set: members
+                .iter()
+                .map(|member| (member.public_key.clone(), member.consensus_voting_power))
+                .collect::<Vec<_>>(),
+            version: "0.1.0".to_string(),
+        };
+        let mut reserved_state = ReservedState {
+            genesis_info: GenesisInfo {
+                header: genesis_header,
+                genesis_proof: Vec::new(),
+                chain_name: "test-chain".to_string(),
+            },
+            members,
+            consensus_leader_order: (0..4)
+                .into_iter()
+                .map(|i| format!("member-{i:04}"))
+                .collect::<Vec<_>>(),
+            version: "0.1.0".to_string(),
+        };
+        let rotation = |old: usize, new: usize, block_height| {
+            let data = KeyRotationData {
+                old_key: keys[old].0.clone(),
+                new_key: keys[new].0.clone(),
+                block_height,
+            };
+            TxRotateKey {
+                old_key: keys[old].0.clone(),
+                new_key: keys[new].0.clone(),
+                old_key_proof: TypedSignature::sign(&data, &keys[old].1).unwrap(),
+                new_key_proof: TypedSignature::sign(&data, &keys[new].1).unwrap(),
+                timestamp: 0,
+            }
+        };
+
+        // Signed for another height.
+        reserved_state
+            .apply_rotate_key(&rotation(0, 4, 2), 1)
+            .unwrap_err();
+        // To the key of another member.
+        reserved_state
+            .apply_rotate_key(&rotation(0, 1, 1), 1)
+            .unwrap_err();
+        // Without the possession of the new key.
+        let mut tx = rotation(0, 4, 1);
+        tx.new_key_proof = tx.old_key_proof.clone();
+        reserved_state.apply_rotate_key(&tx, 1).unwrap_err();
+
+        reserved_state
+            .apply_rotate_key(&rotation(0, 4, 1), 1)
+            .unwrap();
+        assert_eq!(
+            reserved_state.query_public_key(&"member-0000".to_owned()),
+            Some(keys[4].0.clone())
+        );
+        assert_eq!(reserved_state.query_name(&keys[0].0), None);
+        assert_eq!(reserved_state.get_validator_set().unwrap()[0].0, keys[4].0);
+        // The old key is no longer of the member.
+        reserved_state
+            .apply_rotate_key(&rotation(0, 4, 2), 2)
+            .unwrap_err();
+    }
 }
@@ -1,5 +1,27 @@
+//! The encodings of the Simperby data structures, all of which work with the same `serde` derives.
+//!
+//! - [`to_string`]: the pretty JSON, for the humans (e.g., the files in the repository).
+//! - [`to_vec`]: bincode, for the network and the hashes.
+//! - [`to_canonical_json`]: the compact JSON with the sorted keys, for the external tools.
+//! - [`to_cbor`]: the deterministic CBOR (RFC 8949, Section 4.2), for the verifiers on other chains.
+//!
+//! The two canonical encodings give the same bytes for the same data
+//! (e.g., regardless of the iteration order of a `HashMap`), and their decoders accept nothing else.
+//! Neither of them supports floats, which have no canonical form that every reader agrees on.
+//! Note that CBOR is not human-readable, so the keys and the hashes are arrays of bytes in it
+//! as in bincode, while they are hex strings in JSON.
+use ciborium::value::Value as CborValue;
 use serde::{de::DeserializeOwned, ser::Serialize};
-use serde_json::Error;
+use serde_json::{Error, Value as JsonValue};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CborError {
+    #[error("failed to encode: {0}")]
+    Encode(String),
+    #[error("failed to decode: {0}")]
+    Decode(String),
+}
 
 pub fn to_string<T: Serialize>(t: &T) -> Result<String, Error> {
     serde_json::to_string_pretty(t)
@@ -16,3 +38,250 @@ pub fn to_vec<T: Serialize>(t: &T) -> Result<Vec<u8>, bincode::Error> {
 pub fn from_slice<T: DeserializeOwned>(s: &[u8]) -> Result<T, bincode::Error> {
     bincode::deserialize_from(s)
 }
+
+/// Encodes in the canonical JSON: no whitespace, the keys of every object sorted by their bytes,
+/// and the strings escaped only where JSON requires.
+pub fn to_canonical_json<T: Serialize>(t: &T) -> Result<String, Error> {
+    serde_json::to_string(&canonicalize_json(serde_json::to_value(t)?)?)
+}
+
+/// Decodes the canonical JSON, rejecting any other form of the same data.
+pub fn from_canonical_json<T: DeserializeOwned>(s: &str) -> Result<T, Error> {
+    let value: JsonValue = serde_json::from_str(s)?;
+    if serde_json::to_string(&canonicalize_json(value.clone())?)? != s {
+        return Err(<Error as serde::de::Error>::custom(
+            "not in the canonical form",
+        ));
+    }
+    serde_json::from_value(value)
+}
+
+/// Encodes in the deterministic CBOR: the shortest forms of the integers and the lengths,
+/// no indefinite lengths, and the keys of every map sorted by their encoded bytes.
+pub fn to_cbor<T: Serialize>(t: &T) -> Result<Vec<u8>, CborError> {
+    let value = CborValue::serialized(t).map_err(|e| CborError::Encode(e.to_string()))?;
+    encode_cbor(&canonicalize_cbor(value).map_err(CborError::Encode)?)
+}
+
+/// Decodes the deterministic CBOR, rejecting any other form of the same data
+/// and any trailing bytes.
+pub fn from_cbor<T: DeserializeOwned>(s: &[u8]) -> Result<T, CborError> {
+    let value: CborValue =
+        ciborium::de::from_reader(s).map_err(|e| CborError::Decode(e.to_string()))?;
+    let value = canonicalize_cbor(value).map_err(CborError::Decode)?;
+    if encode_cbor(&value)? != s {
+        return Err(CborError::Decode("not in the canonical form".to_owned()));
+    }
+    value
+        .deserialized()
+        .map_err(|e| CborError::Decode(e.to_string()))
+}
+
+fn canonicalize_json(value: JsonValue) -> Result<JsonValue, Error> {
+    Ok(match value {
+        JsonValue::Number(number) if number.is_f64() => {
+            return Err(<Error as serde::ser::Error>::custom(format!(
+                "float: {number}"
+            )))
+        }
+        JsonValue::Array(array) => JsonValue::Array(
+            array
+                .into_iter()
+                .map(canonicalize_json)
+                .collect::<Result<_, _>>()?,
+        ),
+        JsonValue::Object(object) => {
+            let mut entries = object.into_iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            JsonValue::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, canonicalize_json(value)?)))
+                    .collect::<Result<_, Error>>()?,
+            )
+        }
+        value => value,
+    })
+}
+
+fn canonicalize_cbor(value: CborValue) -> Result<CborValue, String> {
+    Ok(match value {
+        CborValue::Float(float) => return Err(format!("float: {float}")),
+        CborValue::Array(array) => CborValue::Array(
+            array
+                .into_iter()
+                .map(canonicalize_cbor)
+                .collect::<Result<_, _>>()?,
+        ),
+        CborValue::Map(map) => {
+            let mut entries = map
+                .into_iter()
+                .map(|(key, value)| {
+                    let key = canonicalize_cbor(key)?;
+                    let encoded_key = encode_cbor(&key).map_err(|e| e.to_string())?;
+                    Ok((encoded_key, key, canonicalize_cbor(value)?))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            entries.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+            CborValue::Map(
+                entries
+                    .into_iter()
+                    .map(|(_, key, value)| (key, value))
+                    .collect(),
+            )
+        }
+        CborValue::Tag(tag, value) => CborValue::Tag(tag, Box::new(canonicalize_cbor(*value)?)),
+        value => value,
+    })
+}
+
+fn encode_cbor(value: &CborValue) -> Result<Vec<u8>, CborError> {
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(value, &mut bytes).map_err(|e| CborError::Encode(e.to_string()))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle_tree::*;
+    use crate::*;
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+    struct Example {
+        header: BlockHeader,
+        transaction: Transaction,
+        merkle_proof: MerkleProof,
+        balances: HashMap<String, u64>,
+        memo: Option<String>,
+    }
+
+    fn example() -> Example {
+        let (public_key, private_key) = generate_keypair("hello world");
+        let header = BlockHeader {
+            author: public_key.clone(),
+            prev_block_finalization_proof: Vec::new(),
+            previous_hash: Hash256::hash("previous"),
+            height: 1,
+            timestamp: -1,
+            commit_merkle_root: Hash256::zero(),
+            repository_merkle_root: Hash256::zero(),
+            validator_set: vec![(public_key.clone(), 1)],
+            version: "0.0.0".to_owned(),
+        };
+        let header = BlockHeader {
+            prev_block_finalization_proof: vec![
+                TypedSignature::sign(&header, &private_key).unwrap()
+            ],
+            ..header
+        };
+        Example {
+            header,
+            transaction: Transaction {
+                author: public_key,
+                timestamp: 0,
+                head: "hello \"world\"\n".to_owned(),
+                body: "\u{c548}\u{b155}".to_owned(),
+                diff: Diff::NonReserved(Hash256::hash("diff")),
+            },
+            merkle_proof: MerkleProof {
+                proof: vec![
+                    MerkleProofEntry::LeftChild(Hash256::hash("left")),
+                    MerkleProofEntry::OnlyChild,
+                ],
+            },
+            balances: (0..20).map(|i| (format!("member-{i}"), i * 1000)).collect(),
+            memo: None,
+        }
+    }
+
+    #[test]
+    fn cross_encoding_round_trip() {
+        let example = example();
+        let json = to_canonical_json(&example).unwrap();
+        let cbor = to_cbor(&example).unwrap();
+        assert_eq!(from_canonical_json::<Example>(&json).unwrap(), example);
+        assert_eq!(from_cbor::<Example>(&cbor).unwrap(), example);
+        assert_eq!(
+            from_str::<Example>(&to_string(&example).unwrap()).unwrap(),
+            example
+        );
+        assert_eq!(
+            from_slice::<Example>(&to_vec(&example).unwrap()).unwrap(),
+            example
+        );
+        // From one encoding to another.
+        assert_eq!(
+            to_cbor(&from_canonical_json::<Example>(&json).unwrap()).unwrap(),
+            cbor
+        );
+        assert_eq!(
+            to_canonical_json(&from_cbor::<Example>(&cbor).unwrap()).unwrap(),
+            json
+        );
+        // Through the generic values, as a tool without the types would do.
+        let value = from_canonical_json::<JsonValue>(&json).unwrap();
+        assert_eq!(to_canonical_json(&value).unwrap(), json);
+        let value = from_cbor::<JsonValue>(&cbor).unwrap();
+        assert_eq!(to_cbor(&value).unwrap(), cbor);
+    }
+
+    #[test]
+    fn deterministic() {
+        let example = example();
+        // The same map, built in the reverse order with another random state of the hasher.
+        let mut balances = example.balances.clone().into_iter().collect::<Vec<_>>();
+        balances.sort();
+        let mut reordered = example.clone();
+        reordered.balances = balances.into_iter().rev().collect();
+        assert_eq!(
+            to_canonical_json(&example).unwrap(),
+            to_canonical_json(&reordered).unwrap()
+        );
+        assert_eq!(to_cbor(&example).unwrap(), to_cbor(&reordered).unwrap());
+    }
+
+    #[test]
+    fn canonical_json() {
+        let value: JsonValue =
+            serde_json::from_str(r#"{"b": [1, {"d": null, "c": "x"}], "a": -1}"#).unwrap();
+        let json = to_canonical_json(&value).unwrap();
+        assert_eq!(json, r#"{"a":-1,"b":[1,{"c":"x","d":null}]}"#);
+        from_canonical_json::<JsonValue>(&json).unwrap();
+        for non_canonical in [
+            r#"{"b":[1,{"c":"x","d":null}],"a":-1}"#,
+            r#"{"a": -1,"b":[1,{"c":"x","d":null}]}"#,
+            "{\"a\":-1,\"b\":[1,{\"c\":\"x\",\"d\":null}]}\n",
+            r#"{"a":-1,"b":[1,{"c":"\u0078","d":null}]}"#,
+            r#"{"a":-1,"a":-1,"b":[1,{"c":"x","d":null}]}"#,
+            r#"{"a":-1,"b":[1.0,{"c":"x","d":null}]}"#,
+        ] {
+            from_canonical_json::<JsonValue>(non_canonical).unwrap_err();
+        }
+        to_canonical_json(&1.5f64).unwrap_err();
+    }
+
+    #[test]
+    fn canonical_cbor() {
+        // `{"b": 1, "a": [-1, 24]}`, with the keys sorted.
+        let value: JsonValue = serde_json::from_str(r#"{"b": 1, "a": [-1, 24]}"#).unwrap();
+        let cbor = to_cbor(&value).unwrap();
+        assert_eq!(hex::encode(&cbor), "a2616182201818616201");
+        assert_eq!(from_cbor::<JsonValue>(&cbor).unwrap(), value);
+        for non_canonical in [
+            // The keys not sorted.
+            "a2616201616182201818",
+            // 24 in the longer form.
+            "a261618220190018616201",
+            // An indefinite-length array.
+            "a261619f201818ff616201",
+            // A trailing byte.
+            "a261618220181861620100",
+        ] {
+            from_cbor::<JsonValue>(&hex::decode(non_canonical).unwrap()).unwrap_err();
+        }
+        to_cbor(&1.5f64).unwrap_err();
+    }
+}
@@ -0,0 +1,62 @@
+//! The abstraction of the signing keys, so that a node can keep its key outside of the process
+//! (e.g., in an HSM or a remote signer).
+//!
+//! [`PrivateKey`] is the in-memory [`Signer`], which is used by default.
+use crate::crypto::*;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// A holder of a private key, which signs the hashes without exposing the key.
+#[async_trait]
+pub trait Signer: Send + Sync {
+    /// Returns the public key of the signing key.
+    fn public_key(&self) -> PublicKey;
+
+    /// Signs the given hash, which must be verifiable with [`Signer::public_key`].
+    async fn sign(&self, data: Hash256) -> Result<Signature, CryptoError>;
+}
+
+#[async_trait]
+impl Signer for PrivateKey {
+    fn public_key(&self) -> PublicKey {
+        PrivateKey::public_key(self)
+    }
+
+    async fn sign(&self, data: Hash256) -> Result<Signature, CryptoError> {
+        Signature::sign(data, self)
+    }
+}
+
+impl From<PrivateKey> for Arc<dyn Signer> {
+    fn from(private_key: PrivateKey) -> Self {
+        Arc::new(private_key)
+    }
+}
+
+impl<T: ToHash256> TypedSignature<T> {
+    /// Creates a new signature with the given signer.
+    ///
+    /// It fails if the signer returns a signature that doesn't match its public key.
+    pub async fn sign_by(data: &T, signer: &dyn Signer) -> Result<Self, CryptoError> {
+        let signature = Self::new(signer.sign(data.to_hash256()).await?, signer.public_key());
+        signature.verify(data)?;
+        Ok(signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn in_memory_signer() {
+        let (public_key, private_key) = generate_keypair("hello world");
+        let signer: Arc<dyn Signer> = private_key.into();
+        assert_eq!(signer.public_key(), public_key);
+        let signature =
+            futures::executor::block_on(TypedSignature::sign_by(&"hello".to_owned(), &*signer))
+                .unwrap();
+        signature.verify(&"hello".to_owned()).unwrap();
+        assert_eq!(signature.signer(), &public_key);
+    }
+}
@@ -0,0 +1,227 @@
+//! [`proptest`] strategies for the core types, enabled by the `test-util` feature.
+//!
+//! Besides the arbitrary values of each type, it generates commit sequences that are valid by construction,
+//! so that the properties of the verification can be checked against them.
+use crate::*;
+use proptest::prelude::*;
+
+/// The number of members in the genesis of the generated chains.
+pub const TEST_MEMBER_NUMBER: usize = 4;
+
+pub fn test_keys() -> Vec<(PublicKey, PrivateKey)> {
+    (0..TEST_MEMBER_NUMBER)
+        .map(|i| generate_keypair(format!("test-util-{i}")))
+        .collect()
+}
+
+/// Creates the genesis reserved state of `TEST_MEMBER_NUMBER` members, with their keys.
+pub fn test_genesis() -> (ReservedState, Vec<(PublicKey, PrivateKey)>) {
+    let keys = test_keys();
+    let members = keys
+        .iter()
+        .enumerate()
+        .map(|(i, (public_key, _))| Member {
+            public_key: public_key.clone(),
+            name: format!("member-{i:04}"),
+            governance_voting_power: 1,
+            consensus_voting_power: 1,
+            governance_delegatee: None,
+            consensus_delegatee: None,
+        })
+        .collect::<Vec<_>>();
+    let header = BlockHeader {
+        author: PublicKey::zero(),
+        prev_block_finalization_proof: Vec::new(),
+        previous_hash: Hash256::zero(),
+        height: 0,
+        timestamp: 0,
+        commit_merkle_root: Hash256::zero(),
+        repository_merkle_root: Hash256::zero(),
+        validator_set: members
+            .iter()
+            .map(|member| (member.public_key.clone(), member.consensus_voting_power))
+            .collect(),
+        version: SIMPERBY_CORE_PROTOCOL_VERSION.to_string(),
+    };
+    let genesis_info = GenesisInfo {
+        genesis_proof: sign_all(&header, &keys),
+        header,
+        chain_name: "test-chain".to_string(),
+    };
+    let reserved_state = ReservedState {
+        genesis_info,
+        consensus_leader_order: members.iter().map(|x| x.name.clone()).collect(),
+        members,
+        version: SIMPERBY_CORE_PROTOCOL_VERSION.to_string(),
+    };
+    (reserved_state, keys)
+}
+
+fn sign_all<T: ToHash256>(data: &T, keys: &[(PublicKey, PrivateKey)]) -> Vec<TypedSignature<T>> {
+    keys.iter()
+        .map(|(_, private_key)| TypedSignature::sign(data, private_key).unwrap())
+        .collect()
+}
+
+pub fn arb_hash256() -> impl Strategy<Value = Hash256> {
+    any::<[u8; 32]>().prop_map(Hash256::from_array)
+}
+
+/// Generates the public key of one of the test members.
+pub fn arb_member_key() -> impl Strategy<Value = PublicKey> {
+    let keys = test_keys();
+    (0..TEST_MEMBER_NUMBER).prop_map(move |i| keys[i].0.clone())
+}
+
+pub fn arb_timestamp() -> impl Strategy<Value = Timestamp> {
+    0..1_000_000_000i64
+}
+
+pub fn arb_diff() -> impl Strategy<Value = Diff> {
+    prop_oneof![Just(Diff::None), arb_hash256().prop_map(Diff::NonReserved)]
+}
+
+pub fn arb_transaction() -> impl Strategy<Value = Transaction> {
+    (
+        arb_member_key(),
+        arb_timestamp(),
+        "[a-z0-9: -]{1,32}",
+        ".{0,128}",
+        arb_diff(),
+    )
+        .prop_map(|(author, timestamp, head, body, diff)| Transaction {
+            author,
+            timestamp,
+            head,
+            body,
+            diff,
+        })
+}
+
+pub fn arb_agenda() -> impl Strategy<Value = Agenda> {
+    (0..1000u64, arb_member_key(), arb_timestamp(), arb_hash256()).prop_map(
+        |(height, author, timestamp, transactions_hash)| Agenda {
+            height,
+            author,
+            timestamp,
+            transactions_hash,
+        },
+    )
+}
+
+/// Generates a block header, which is not necessarily valid.
+pub fn arb_block_header() -> impl Strategy<Value = BlockHeader> {
+    (
+        arb_member_key(),
+        arb_hash256(),
+        0..1000u64,
+        arb_timestamp(),
+        arb_hash256(),
+        arb_hash256(),
+        "[0-9]\\.[0-9]\\.[0-9]",
+    )
+        .prop_map(
+            |(
+                author,
+                previous_hash,
+                height,
+                timestamp,
+                commit_merkle_root,
+                repository_merkle_root,
+                version,
+            )| BlockHeader {
+                author,
+                prev_block_finalization_proof: Vec::new(),
+                previous_hash,
+                height,
+                timestamp,
+                commit_merkle_root,
+                repository_merkle_root,
+                validator_set: test_keys().into_iter().map(|(k, _)| (k, 1)).collect(),
+                version,
+            },
+        )
+}
+
+pub fn arb_commit() -> impl Strategy<Value = Commit> {
+    prop_oneof![
+        arb_block_header().prop_map(Commit::Block),
+        arb_transaction().prop_map(Commit::Transaction),
+        arb_agenda().prop_map(Commit::Agenda),
+    ]
+}
+
+/// A commit sequence from the genesis that passes `CommitSequenceVerifier`.
+#[derive(Debug, Clone)]
+pub struct ValidCommitSequence {
+    pub reserved_state: ReservedState,
+    /// The commits after the genesis block.
+    pub commits: Vec<Commit>,
+    /// The finalization proof of the last block.
+    pub last_finalization_proof: FinalizationProof,
+}
+
+/// Builds a valid commit sequence with a block for each given set of transactions.
+///
+/// The transactions are sorted by their timestamps, and every agenda is approved by all the members.
+pub fn build_commit_sequence(blocks: Vec<Vec<Transaction>>) -> ValidCommitSequence {
+    let (reserved_state, keys) = test_genesis();
+    let mut header = reserved_state.genesis_info.header.clone();
+    let mut proof = reserved_state.genesis_info.genesis_proof.clone();
+    let mut commits = Vec::new();
+    for mut transactions in blocks {
+        transactions.sort_by_key(|x| x.timestamp);
+        let timestamp = transactions
+            .last()
+            .map_or(header.timestamp, |x| x.timestamp.max(header.timestamp));
+        let agenda = Agenda {
+            height: header.height + 1,
+            author: keys[0].0.clone(),
+            timestamp,
+            transactions_hash: Agenda::calculate_transactions_hash(&transactions),
+        };
+        let agenda_proof = AgendaProof {
+            height: agenda.height,
+            agenda_hash: agenda.to_hash256(),
+            proof: sign_all(&agenda, &keys),
+        };
+        let mut block_commits = transactions
+            .into_iter()
+            .map(Commit::Transaction)
+            .collect::<Vec<_>>();
+        block_commits.push(Commit::Agenda(agenda));
+        block_commits.push(Commit::AgendaProof(agenda_proof));
+        header = BlockHeader {
+            author: keys[0].0.clone(),
+            prev_block_finalization_proof: proof,
+            previous_hash: header.to_hash256(),
+            height: header.height + 1,
+            timestamp,
+            commit_merkle_root: BlockHeader::calculate_commit_merkle_root(&block_commits),
+            repository_merkle_root: Hash256::zero(),
+            validator_set: header.validator_set.clone(),
+            version: header.version.clone(),
+        };
+        proof = sign_all(&header, &keys);
+        commits.extend(block_commits);
+        commits.push(Commit::Block(header.clone()));
+    }
+    ValidCommitSequence {
+        reserved_state,
+        commits,
+        last_finalization_proof: proof,
+    }
+}
+
+/// Generates a valid commit sequence of `1..=max_blocks` blocks,
+/// each of which has `0..=max_transactions` transactions.
+pub fn arb_valid_commit_sequence(
+    max_blocks: usize,
+    max_transactions: usize,
+) -> impl Strategy<Value = ValidCommitSequence> {
+    prop::collection::vec(
+        prop::collection::vec(arb_transaction(), 0..=max_transactions),
+        1..=max_blocks,
+    )
+    .prop_map(build_commit_sequence)
+}
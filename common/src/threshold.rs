@@ -0,0 +1,348 @@
+//! The t-of-n threshold BLS signatures, which let a member that is actually an organization
+//! sign by any `t` of its `n` operators.
+//!
+//! The BLS private key of the member is split into `n` shares by the Shamir secret sharing
+//! ([`deal_key_shares`]). Each operator signs with its share, and any `t` of the partial signatures
+//! combine into an ordinary [`BlsSignature`] of the member's key ([`ThresholdPublicKey::combine`]),
+//! which is indistinguishable from the one signed by the whole key.
+//! So the key is bound to the member as usual (see [`BlsKeyBinding`]),
+//! and the others need nothing to verify its signatures.
+//!
+//! # Scope
+//!
+//! Only the BLS key of the member is shared, so a threshold signature stands for the member
+//! only where its BLS signature is accepted, which is its part of an
+//! [`AggregateFinalizationProof`](crate::AggregateFinalizationProof).
+//! Everything signed with the member's own key, including the consensus votes,
+//! the agenda votes and the finalization proofs of individual signatures,
+//! is still signed by a single holder of that key.
+use crate::crypto::*;
+use blst::{blst_p2, blst_p2_affine, blst_scalar, BLST_ERROR};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+type Error = CryptoError;
+
+/// The public part of a threshold key, known to every operator.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ThresholdPublicKey {
+    /// The public key of the whole key, which the combined signatures are of.
+    pub group_key: BlsPublicKey,
+    /// The number of the partial signatures needed.
+    pub threshold: u32,
+    /// The public keys of the shares, where that of the share `i` is at `i - 1`.
+    pub share_keys: Vec<BlsPublicKey>,
+}
+
+/// A share of a threshold key, held by an operator.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct KeyShare {
+    /// The index of the share, from 1 to `n`.
+    pub index: u32,
+    pub private_key: BlsPrivateKey,
+}
+
+/// A signature by a share.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct PartialSignature {
+    pub index: u32,
+    pub signature: BlsSignature,
+}
+
+impl KeyShare {
+    pub fn sign(&self, data: Hash256) -> Result<PartialSignature, Error> {
+        Ok(PartialSignature {
+            index: self.index,
+            signature: BlsSignature::sign(data, &self.private_key)?,
+        })
+    }
+}
+
+impl ThresholdPublicKey {
+    /// Verifies the signature of a share.
+    pub fn verify_partial(&self, data: Hash256, partial: &PartialSignature) -> Result<(), Error> {
+        let share_key = (partial.index as usize)
+            .checked_sub(1)
+            .and_then(|i| self.share_keys.get(i))
+            .ok_or_else(|| Error::InvalidFormat(format!("share index: {}", partial.index)))?;
+        partial.signature.verify(data, share_key)
+    }
+
+    /// Combines the signatures of at least `threshold` distinct shares into the signature of the
+    /// group key, ignoring the rest.
+    ///
+    /// Every partial signature is verified, so that an invalid one is reported
+    /// instead of producing an invalid signature.
+    pub fn combine(
+        &self,
+        data: Hash256,
+        partials: &[PartialSignature],
+    ) -> Result<BlsSignature, Error> {
+        let mut signatures = BTreeMap::new();
+        for partial in partials {
+            self.verify_partial(data, partial)?;
+            signatures.insert(partial.index, &partial.signature);
+        }
+        if signatures.len() < self.threshold as usize {
+            return Err(Error::InvalidFormat(format!(
+                "{} partial signatures for the threshold {}",
+                signatures.len(),
+                self.threshold
+            )));
+        }
+        let signatures = signatures
+            .into_iter()
+            .take(self.threshold as usize)
+            .collect::<Vec<_>>();
+        let indices = signatures.iter().map(|(i, _)| *i).collect::<Vec<_>>();
+        let mut sum: Option<blst_p2> = None;
+        for (index, signature) in &signatures {
+            let coefficient = lagrange_coefficient(*index, &indices)?;
+            let mut affine = blst_p2_affine::default();
+            let mut point = blst_p2::default();
+            let mut term = blst_p2::default();
+            // SAFETY: the pointers are of the local values, and the buffers are of the sizes
+            // that blst expects (96 bytes of a compressed G2 point and 256 bits of a scalar).
+            unsafe {
+                if blst::blst_p2_uncompress(&mut affine, signature.as_ref().as_ptr())
+                    != BLST_ERROR::BLST_SUCCESS
+                {
+                    return Err(Error::InvalidFormat(format!("BLS signature: {signature}")));
+                }
+                blst::blst_p2_from_affine(&mut point, &affine);
+                blst::blst_p2_mult(&mut term, &point, coefficient.b.as_ptr(), 255);
+                if let Some(previous) = sum {
+                    let term: *mut blst_p2 = &mut term;
+                    blst::blst_p2_add_or_double(term, &previous, term);
+                }
+            }
+            sum = Some(term);
+        }
+        let sum = sum.expect("the threshold is at least 1");
+        let mut bytes = [0; 96];
+        // SAFETY: the output buffer is of a compressed G2 point.
+        unsafe { blst::blst_p2_compress(bytes.as_mut_ptr(), &sum) };
+        let signature = BlsSignature::from_array(bytes)?;
+        signature.verify(data, &self.group_key)?;
+        Ok(signature)
+    }
+}
+
+/// Splits the private key into `n` shares, any `threshold` of which can sign for it.
+///
+/// The coefficients of the sharing polynomial are derived from the seed, which must be
+/// as secret as the key. The dealer should bind the key to the member
+/// (see [`BlsKeyBinding::create`]) and then discard it.
+pub fn deal_key_shares(
+    private_key: &BlsPrivateKey,
+    threshold: u32,
+    n: u32,
+    seed: impl AsRef<[u8]>,
+) -> Result<(ThresholdPublicKey, Vec<KeyShare>), Error> {
+    if threshold == 0 || threshold > n {
+        return Err(Error::InvalidFormat(format!("{threshold}-of-{n}")));
+    }
+    // `f(x) = k + c_1 x + ... + c_{t-1} x^{t-1}`, from the highest degree.
+    let mut coefficients = (1..threshold)
+        .map(|i| {
+            let (_, coefficient) = generate_bls_keypair([seed.as_ref(), &i.to_be_bytes()].concat());
+            scalar(coefficient.as_ref())
+        })
+        .rev()
+        .collect::<Vec<_>>();
+    coefficients.push(scalar(private_key.as_ref()));
+    let shares = (1..=n)
+        .map(|index| {
+            let x = scalar_of(index);
+            let mut y = coefficients[0].clone();
+            for coefficient in &coefficients[1..] {
+                y = add(&multiply(&y, &x)?, coefficient)?;
+            }
+            let mut bytes = [0; 32];
+            // SAFETY: the output buffer is of 256 bits.
+            unsafe { blst::blst_bendian_from_scalar(bytes.as_mut_ptr(), &y) };
+            Ok(KeyShare {
+                index,
+                private_key: BlsPrivateKey::from_array(bytes)?,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let public_key = ThresholdPublicKey {
+        group_key: private_key.public_key(),
+        threshold,
+        share_keys: shares.iter().map(|x| x.private_key.public_key()).collect(),
+    };
+    Ok((public_key, shares))
+}
+
+/// The coefficient of the share `index` to interpolate the polynomial at 0 from the `indices`,
+/// i.e., the product of `j / (j - index)` for the other `j`.
+fn lagrange_coefficient(index: u32, indices: &[u32]) -> Result<blst_scalar, Error> {
+    let mut numerator = scalar_of(1);
+    let mut denominator = scalar_of(1);
+    for j in indices.iter().filter(|j| **j != index) {
+        numerator = multiply(&numerator, &scalar_of(*j))?;
+        denominator = multiply(&denominator, &subtract(&scalar_of(*j), &scalar_of(index))?)?;
+    }
+    let mut inverse = blst_scalar::default();
+    // SAFETY: the pointers are of the local values.
+    unsafe { blst::blst_sk_inverse(&mut inverse, &denominator) };
+    multiply(&numerator, &inverse)
+}
+
+fn scalar(bytes: &[u8]) -> blst_scalar {
+    let mut scalar = blst_scalar::default();
+    // SAFETY: the keys are of 32 bytes.
+    unsafe { blst::blst_scalar_from_bendian(&mut scalar, bytes.as_ptr()) };
+    scalar
+}
+
+fn scalar_of(x: u32) -> blst_scalar {
+    let mut bytes = [0; 32];
+    bytes[28..].copy_from_slice(&x.to_be_bytes());
+    scalar(&bytes)
+}
+
+/// Applies one of the `blst_sk_*_n_check`s, which fail if the result is zero.
+fn apply(
+    operation: unsafe extern "C" fn(
+        *mut blst_scalar,
+        *const blst_scalar,
+        *const blst_scalar,
+    ) -> bool,
+    a: &blst_scalar,
+    b: &blst_scalar,
+) -> Result<blst_scalar, Error> {
+    let mut result = blst_scalar::default();
+    // SAFETY: the pointers are of the local values.
+    if unsafe { operation(&mut result, a, b) } {
+        Ok(result)
+    } else {
+        Err(Error::InvalidFormat("zero scalar".to_owned()))
+    }
+}
+
+fn add(a: &blst_scalar, b: &blst_scalar) -> Result<blst_scalar, Error> {
+    apply(blst::blst_sk_add_n_check, a, b)
+}
+
+fn subtract(a: &blst_scalar, b: &blst_scalar) -> Result<blst_scalar, Error> {
+    apply(blst::blst_sk_sub_n_check, a, b)
+}
+
+fn multiply(a: &blst_scalar, b: &blst_scalar) -> Result<blst_scalar, Error> {
+    apply(blst::blst_sk_mul_n_check, a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threshold_signature() {
+        let (group_key, private_key) = generate_bls_keypair("organization");
+        let (public_key, shares) = deal_key_shares(&private_key, 3, 5, "dealer").unwrap();
+        assert_eq!(public_key.group_key, group_key);
+        assert_eq!(shares.len(), 5);
+        let data = Hash256::hash("hello world");
+        let partials = shares
+            .iter()
+            .map(|share| share.sign(data).unwrap())
+            .collect::<Vec<_>>();
+        for partial in &partials {
+            public_key.verify_partial(data, partial).unwrap();
+        }
+
+        // Any 3 of them give the same signature as the whole key.
+        let expected = BlsSignature::sign(data, &private_key).unwrap();
+        for subset in [[0, 1, 2], [0, 2, 4], [4, 3, 1]] {
+            let subset = subset.map(|i| partials[i].clone());
+            assert_eq!(public_key.combine(data, &subset).unwrap(), expected);
+        }
+        assert_eq!(public_key.combine(data, &partials).unwrap(), expected);
+
+        // Not enough, even with a duplicate.
+        public_key
+            .combine(
+                data,
+                &[
+                    partials[0].clone(),
+                    partials[1].clone(),
+                    partials[1].clone(),
+                ],
+            )
+            .unwrap_err();
+        // A partial signature of another data or share.
+        let mut forged = partials.clone();
+        forged[0] = shares[0].sign(Hash256::hash("hello")).unwrap();
+        public_key.combine(data, &forged).unwrap_err();
+        forged[0] = PartialSignature {
+            index: 1,
+            ..partials[1].clone()
+        };
+        public_key.combine(data, &forged).unwrap_err();
+        forged[0].index = 6;
+        public_key.verify_partial(data, &forged[0]).unwrap_err();
+
+        deal_key_shares(&private_key, 0, 5, "dealer").unwrap_err();
+        deal_key_shares(&private_key, 6, 5, "dealer").unwrap_err();
+        let (public_key, shares) = deal_key_shares(&private_key, 1, 1, "dealer").unwrap();
+        assert_eq!(shares[0].private_key, private_key);
+        assert_eq!(
+            public_key
+                .combine(data, &[shares[0].sign(data).unwrap()])
+                .unwrap(),
+            expected
+        );
+    }
+
+    /// The combined signature counts as the member's in an aggregate finalization proof.
+    #[test]
+    fn threshold_finalization_proof() {
+        use crate::verify::verify_aggregate_finalization_proof;
+        use crate::*;
+        use std::collections::BTreeMap;
+
+        let validators = (0..4)
+            .map(|i| {
+                let (public_key, private_key) = generate_keypair(format!("validator{i}"));
+                let (_, bls_private_key) = generate_bls_keypair(format!("validator{i}"));
+                let binding = BlsKeyBinding::create(&private_key, &bls_private_key).unwrap();
+                (public_key, bls_private_key, binding)
+            })
+            .collect::<Vec<_>>();
+        let header = BlockHeader {
+            author: validators[0].0.clone(),
+            prev_block_finalization_proof: vec![],
+            previous_hash: Hash256::zero(),
+            height: 1,
+            timestamp: 0,
+            commit_merkle_root: Hash256::zero(),
+            repository_merkle_root: Hash256::zero(),
+            validator_set: validators.iter().map(|(x, _, _)| (x.clone(), 1)).collect(),
+            version: SIMPERBY_CORE_PROTOCOL_VERSION.to_string(),
+        };
+        let mut bls_keys = BTreeMap::new();
+        for (public_key, _, binding) in &validators {
+            binding.verify().unwrap();
+            bls_keys.insert(public_key.clone(), binding.bls_public_key.clone());
+        }
+
+        // The first validator is an organization of 3 operators, 2 of which sign.
+        let (threshold_key, shares) = deal_key_shares(&validators[0].1, 2, 3, "dealer").unwrap();
+        let data = header.to_hash256();
+        let partials = [shares[0].sign(data).unwrap(), shares[2].sign(data).unwrap()];
+        let mut signatures = vec![(
+            validators[0].0.clone(),
+            threshold_key.combine(data, &partials).unwrap(),
+        )];
+        for (public_key, bls_private_key, _) in &validators[1..3] {
+            signatures.push((
+                public_key.clone(),
+                BlsSignature::sign(data, bls_private_key).unwrap(),
+            ));
+        }
+        let proof = AggregateFinalizationProof::aggregate(signatures).unwrap();
+        verify_aggregate_finalization_proof(&header, &proof, &bls_keys).unwrap();
+    }
+}
@@ -10,6 +10,44 @@ pub type ConsensusRound = u64;
 pub type FinalizationProof = Vec<TypedSignature<BlockHeader>>;
 pub type MemberName = String;
 
+/// A finalization proof whose BLS signatures (see [`BlsKeyBinding`]) are aggregated into one.
+///
+/// Unlike [`FinalizationProof`], its verification costs a single signature regardless of the number of the signers.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct AggregateFinalizationProof {
+    /// The validators that have signed, without duplicates.
+    pub signers: Vec<PublicKey>,
+    /// The aggregate of the BLS signatures on the hash of the header.
+    pub signature: BlsSignature,
+}
+
+impl AggregateFinalizationProof {
+    /// Aggregates the BLS signatures on the header, each with its signer.
+    pub fn aggregate(signatures: Vec<(PublicKey, BlsSignature)>) -> Result<Self, CryptoError> {
+        let (signers, signatures): (Vec<_>, Vec<_>) = signatures.into_iter().unzip();
+        Ok(Self {
+            signers,
+            signature: BlsSignature::aggregate(&signatures)?,
+        })
+    }
+}
+
+/// A finalization proof in either signature scheme.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum AnyFinalizationProof {
+    Individual(FinalizationProof),
+    Aggregate(AggregateFinalizationProof),
+}
+
+impl AnyFinalizationProof {
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            Self::Individual(_) => SignatureScheme::Secp256k1,
+            Self::Aggregate(_) => SignatureScheme::Bls12381,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct Member {
     pub public_key: PublicKey,
@@ -111,6 +149,7 @@ pub enum ExtraAgendaTransaction {
     Delegate(TxDelegate),
     Undelegate(TxUndelegate),
     Report(TxReport),
+    RotateKey(TxRotateKey),
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -130,6 +169,21 @@ pub struct TxUndelegate {
     pub timestamp: Timestamp,
 }
 
+/// Replaces the key of a member with a new one.
+///
+/// The new key takes effect from the block that includes this transaction,
+/// after which the old key is no longer accepted.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct TxRotateKey {
+    pub old_key: PublicKey,
+    pub new_key: PublicKey,
+    /// The signature of the old key, which authorizes the rotation.
+    pub old_key_proof: TypedSignature<KeyRotationData>,
+    /// The signature of the new key, which proves the possession of it.
+    pub new_key_proof: TypedSignature<KeyRotationData>,
+    pub timestamp: Timestamp,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct TxReport {
     // TODO
@@ -149,6 +203,14 @@ pub struct UndelegationTransactionData {
     pub block_height: BlockHeight,
 }
 
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct KeyRotationData {
+    pub old_key: PublicKey,
+    pub new_key: PublicKey,
+    /// The height of the block that includes the rotation (i.e., the transition height).
+    pub block_height: BlockHeight,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct GenesisInfo {
     pub header: BlockHeader,
@@ -1,5 +1,6 @@
 use crate::reserved::ReservedState;
 use crate::*;
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::collections::HashMap;
 use thiserror::Error;
@@ -62,7 +63,6 @@ pub fn verify_finalization_proof(
     header: &BlockHeader,
     block_finalization_proof: &FinalizationProof,
 ) -> Result<(), Error> {
-    let total_voting_power: VotingPower = header.validator_set.iter().map(|(_, v)| v).sum();
     // TODO: change to `HashSet` after `PublicKey` supports `Hash`.
     let mut voted_validators = BTreeSet::new();
     for signature in block_finalization_proof {
@@ -71,6 +71,62 @@ pub fn verify_finalization_proof(
             .map_err(|e| Error::CryptoError("invalid finalization proof".to_string(), e))?;
         voted_validators.insert(signature.signer());
     }
+    verify_voting_power(header, &voted_validators)
+}
+
+/// Verifies the aggregate finalization proof of the given block header,
+/// with the BLS public keys of the validators.
+///
+/// The BLS keys must have been verified by their bindings (see [`BlsKeyBinding::verify`]).
+pub fn verify_aggregate_finalization_proof(
+    header: &BlockHeader,
+    proof: &AggregateFinalizationProof,
+    bls_keys: &BTreeMap<PublicKey, BlsPublicKey>,
+) -> Result<(), Error> {
+    let voted_validators = proof.signers.iter().collect::<BTreeSet<_>>();
+    if voted_validators.len() != proof.signers.len() {
+        return Err(Error::InvalidProof(
+            "invalid finalization proof - duplicate signers".to_string(),
+        ));
+    }
+    let signer_keys = proof
+        .signers
+        .iter()
+        .map(|signer| {
+            bls_keys.get(signer).cloned().ok_or_else(|| {
+                Error::InvalidProof(format!(
+                    "invalid finalization proof - no BLS key of {signer}"
+                ))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    proof
+        .signature
+        .verify_aggregate(header.to_hash256(), &signer_keys)
+        .map_err(|e| Error::CryptoError("invalid finalization proof".to_string(), e))?;
+    verify_voting_power(header, &voted_validators)
+}
+
+/// Verifies the finalization proof of either scheme.
+pub fn verify_any_finalization_proof(
+    header: &BlockHeader,
+    proof: &AnyFinalizationProof,
+    bls_keys: &BTreeMap<PublicKey, BlsPublicKey>,
+) -> Result<(), Error> {
+    match proof {
+        AnyFinalizationProof::Individual(proof) => verify_finalization_proof(header, proof),
+        AnyFinalizationProof::Aggregate(proof) => {
+            verify_aggregate_finalization_proof(header, proof, bls_keys)
+        }
+    }
+}
+
+/// Checks whether the validators voted have more than 2/3 of the voting power of the header.
+fn verify_voting_power(
+    header: &BlockHeader,
+    voted_validators: &BTreeSet<&PublicKey>,
+) -> Result<(), Error> {
+    let total_voting_power: VotingPower = header.validator_set.iter().map(|(_, v)| v).sum();
     let voted_voting_power: VotingPower = header
         .validator_set
         .iter()
@@ -143,6 +199,11 @@ impl CommitSequenceVerifier {
         &self.total_commits
     }
 
+    /// Returns the reserved state after the commits received so far.
+    pub fn get_reserved_state(&self) -> &ReservedState {
+        &self.reserved_state
+    }
+
     /// Returns the block headers received so far, with the index of the commit.
     ///
     /// It returns `[start_header]` if no block header has been received.
@@ -372,7 +433,7 @@ impl CommitSequenceVerifier {
             (Commit::ExtraAgendaTransaction(tx), Phase::AgendaProof { agenda_proof: _ }) => {
                 match tx {
                     ExtraAgendaTransaction::Delegate(tx) => {
-                        // Update reserved reserved_state by applying delegation
+                        // Update the reserved state by applying delegation
                         self.reserved_state.apply_delegate(tx).map_err(|e| {
                             Error::InvalidArgument(format!("invalid delegation: {e}"))
                         })?;
@@ -381,7 +442,7 @@ impl CommitSequenceVerifier {
                         };
                     }
                     ExtraAgendaTransaction::Undelegate(tx) => {
-                        // Update reserved reserved_state by applying undelegation
+                        // Update the reserved state by applying undelegation
                         self.reserved_state.apply_undelegate(tx).map_err(|e| {
                             Error::InvalidArgument(format!("invalid undelegation: {e}"))
                         })?;
@@ -389,6 +450,17 @@ impl CommitSequenceVerifier {
                             last_extra_agenda_timestamp: tx.timestamp,
                         };
                     }
+                    ExtraAgendaTransaction::RotateKey(tx) => {
+                        // Update the reserved state by applying key rotation
+                        self.reserved_state
+                            .apply_rotate_key(tx, self.header.height + 1)
+                            .map_err(|e| {
+                                Error::InvalidArgument(format!("invalid key rotation: {e}"))
+                            })?;
+                        self.phase = Phase::ExtraAgendaTransaction {
+                            last_extra_agenda_timestamp: tx.timestamp,
+                        };
+                    }
                     ExtraAgendaTransaction::Report(_tx) => unimplemented!(),
                 }
             }
@@ -400,7 +472,7 @@ impl CommitSequenceVerifier {
             ) => {
                 match tx {
                     ExtraAgendaTransaction::Delegate(tx) => {
-                        // Update reserved reserved_state by applying delegation
+                        // Update the reserved state by applying delegation
                         self.reserved_state.apply_delegate(tx).map_err(|e| {
                             Error::InvalidArgument(format!("invalid delegation: {e}"))
                         })?;
@@ -413,7 +485,7 @@ impl CommitSequenceVerifier {
                         *last_extra_agenda_timestamp = tx.timestamp;
                     }
                     ExtraAgendaTransaction::Undelegate(tx) => {
-                        // Update reserved reserved_state by applying undelegation
+                        // Update the reserved state by applying undelegation
                         self.reserved_state.apply_undelegate(tx).map_err(|e| {
                             Error::InvalidArgument(format!("invalid undelegation: {e}"))
                         })?;
@@ -425,6 +497,21 @@ impl CommitSequenceVerifier {
                         }
                         *last_extra_agenda_timestamp = tx.timestamp;
                     }
+                    ExtraAgendaTransaction::RotateKey(tx) => {
+                        // Update the reserved state by applying key rotation
+                        self.reserved_state
+                            .apply_rotate_key(tx, self.header.height + 1)
+                            .map_err(|e| {
+                                Error::InvalidArgument(format!("invalid key rotation: {e}"))
+                            })?;
+                        // Check if extra-agenda transactions are in chronological order
+                        if tx.timestamp < *last_extra_agenda_timestamp {
+                            return Err(Error::InvalidArgument(
+                                format!("invalid extra-agenda transaction timestamp: expected larger than or equal to the last transaction timestamp {}, got {}", last_extra_agenda_timestamp, tx.timestamp)
+                            ));
+                        }
+                        *last_extra_agenda_timestamp = tx.timestamp;
+                    }
                     ExtraAgendaTransaction::Report(_tx) => unimplemented!(),
                 }
             }
@@ -436,7 +523,10 @@ impl CommitSequenceVerifier {
                 ));
             }
         }
-        self.next_block_commits.push(commit.clone());
+        // A block commit closes the current block, so it doesn't belong to the next one.
+        if !matches!(commit, Commit::Block(_)) {
+            self.next_block_commits.push(commit.clone());
+        }
         self.total_commits.push(commit.clone());
         Ok(())
     }
@@ -574,7 +664,7 @@ mod test {
         reserved_state: &mut ReservedState,
         time: Timestamp,
     ) -> Commit {
-        // Update reserved reserved_state
+        // Update the reserved state
         validator_keypair.push(generate_keypair([3]));
         reserved_state.members.push(Member {
             public_key: validator_keypair.last().unwrap().0.clone(),
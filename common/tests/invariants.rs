@@ -0,0 +1,96 @@
+//! Property-based tests of the invariants of the core types.
+//!
+//! Run with `cargo test --features test-util`.
+#![cfg(feature = "test-util")]
+
+use proptest::prelude::*;
+use simperby_common::test_util::*;
+use simperby_common::{verify::CommitSequenceVerifier, *};
+
+fn verify(
+    sequence: &ValidCommitSequence,
+    commits: &[Commit],
+) -> Result<CommitSequenceVerifier, String> {
+    let mut verifier = CommitSequenceVerifier::new(
+        sequence.reserved_state.genesis_info.header.clone(),
+        sequence.reserved_state.clone(),
+    )
+    .map_err(|e| e.to_string())?;
+    for commit in commits {
+        verifier.apply_commit(commit).map_err(|e| e.to_string())?;
+    }
+    Ok(verifier)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn commit_serialization_round_trip(commit in arb_commit()) {
+        let json = serde_spb::to_string(&commit).unwrap();
+        prop_assert_eq!(&serde_spb::from_str::<Commit>(&json).unwrap(), &commit);
+        let bytes = serde_spb::to_vec(&commit).unwrap();
+        prop_assert_eq!(&serde_spb::from_slice::<Commit>(&bytes).unwrap(), &commit);
+    }
+
+    #[test]
+    fn hash_stability(commit in arb_commit()) {
+        let json = serde_spb::to_string(&commit).unwrap();
+        let decoded: Commit = serde_spb::from_str(&json).unwrap();
+        prop_assert_eq!(commit.to_hash256(), decoded.to_hash256());
+        prop_assert_eq!(commit.to_hash256(), commit.clone().to_hash256());
+    }
+
+    #[test]
+    fn hash_sensitivity(transaction in arb_transaction()) {
+        let mut other = transaction.clone();
+        other.timestamp += 1;
+        prop_assert_ne!(transaction.to_hash256(), other.to_hash256());
+    }
+
+    #[test]
+    fn valid_sequence_verifies(sequence in arb_valid_commit_sequence(3, 4)) {
+        let verifier = verify(&sequence, &sequence.commits).unwrap();
+        verifier
+            .verify_last_header_finalization(&sequence.last_finalization_proof)
+            .unwrap();
+    }
+
+    /// Every prefix of a valid sequence is valid, and the verified headers only grow.
+    #[test]
+    fn verification_monotonicity(sequence in arb_valid_commit_sequence(3, 4)) {
+        let mut headers = 1;
+        for i in 0..=sequence.commits.len() {
+            let verifier = verify(&sequence, &sequence.commits[..i]).unwrap();
+            let n = verifier.get_block_headers().len();
+            prop_assert!(n >= headers);
+            headers = n;
+        }
+    }
+
+    /// Tampering a block breaks the verification of the whole sequence.
+    #[test]
+    fn tampered_block_is_rejected(
+        sequence in arb_valid_commit_sequence(3, 4),
+        index in any::<prop::sample::Index>(),
+    ) {
+        let blocks: Vec<_> = sequence
+            .commits
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| matches!(c, Commit::Block(_)))
+            .map(|(i, _)| i)
+            .collect();
+        let target = blocks[index.index(blocks.len())];
+        let mut commits = sequence.commits.clone();
+        if let Commit::Block(header) = &mut commits[target] {
+            header.repository_merkle_root = Hash256::hash("tampered");
+        }
+        let result = verify(&sequence, &commits).and_then(|verifier| {
+            verifier
+                .verify_last_header_finalization(&sequence.last_finalization_proof)
+                .map_err(|e| e.to_string())
+        });
+        prop_assert!(result.is_err());
+    }
+}
@@ -5,8 +5,10 @@ use eyre::eyre;
 use serde::{Deserialize, Serialize};
 use simperby_common::{
     crypto::{Hash256, PublicKey},
-    serde_spb, BlockHeader, BlockHeight, ConsensusRound, FinalizationProof, PrivateKey, Signature,
-    Timestamp, ToHash256, TypedSignature, VotingPower,
+    serde_spb,
+    signer::Signer,
+    BlockHeader, BlockHeight, ConsensusRound, FinalizationProof, Signature, Timestamp, ToHash256,
+    TypedSignature, VotingPower,
 };
 use simperby_network::{
     dms::{DistributedMessageSet as DMS, Message, MessageFilter},
@@ -60,12 +62,12 @@ fn generate_height_info(
     header: &BlockHeader,
     consensus_params: ConsensusParams,
     round_zero_timestamp: Timestamp,
-    this_node_key: PrivateKey,
+    this_node_key: PublicKey,
 ) -> Result<HeightInfo, Error> {
     let this_node_index = header
         .validator_set
         .iter()
-        .position(|(pubkey, _)| *pubkey == this_node_key.public_key());
+        .position(|(pubkey, _)| *pubkey == this_node_key);
     let info = HeightInfo {
         validators: header
             .validator_set
@@ -200,8 +202,8 @@ pub struct Consensus<N: GossipNetwork, S: Storage> {
     ///
     /// Note that there is the exactly same copy in the `state`.
     verified_block_hashes: Arc<parking_lot::RwLock<BTreeSet<Hash256>>>,
-    /// (If participated) the private key of this node
-    this_node_key: Option<PrivateKey>,
+    /// (If participated) the signer of this node
+    this_node_key: Option<Arc<dyn Signer>>,
 }
 
 impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
@@ -215,14 +217,14 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
         block_header: BlockHeader,
         consensus_parameters: ConsensusParams,
         round_zero_timestamp: Timestamp,
-        this_node_key: Option<PrivateKey>,
+        this_node_key: Option<Arc<dyn Signer>>,
     ) -> Result<Self, Error> {
         // Prepare new state in case of storage reset.
         let new_state = Self::construct_new_state(
             &block_header,
             consensus_parameters,
             round_zero_timestamp,
-            this_node_key.clone().unwrap(),
+            this_node_key.as_ref().unwrap().public_key(),
         )?;
         let state = if let Ok(raw_state) = state_storage.read_file(STATE_FILE_NAME).await {
             let state: State = serde_spb::from_str(&raw_state)?;
@@ -427,7 +429,7 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
         block_header: &BlockHeader,
         consensus_parameters: ConsensusParams,
         round_zero_timestamp: Timestamp,
-        this_node_key: PrivateKey,
+        this_node_key: PublicKey,
     ) -> Result<State, Error> {
         let height_info = generate_height_info(
             block_header,
@@ -478,8 +480,8 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
         consensus_message: &ConsensusMessage,
     ) -> Result<(), Error> {
         let serialized = serde_spb::to_string(consensus_message).unwrap();
-        let signature = TypedSignature::sign(&serialized, self.this_node_key.as_ref().unwrap())
-            .expect("invalid(malformed) private key");
+        let signature =
+            TypedSignature::sign_by(&serialized, self.this_node_key.as_deref().unwrap()).await?;
         let message = Message::new(serialized, signature).expect("signature just created");
         self.dms.add_message(message).await
     }
@@ -599,7 +601,7 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
                 ))
             }
             ConsensusResponse::BroadcastPrevote { proposal, round } => {
-                let private_key = self
+                let signer = self
                     .this_node_key
                     .as_ref()
                     .ok_or_else(|| eyre!("this node is not a validator"))?;
@@ -612,10 +614,11 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
                     let message = ConsensusMessage::NonNilPreVoted(
                         round as u64,
                         block_hash,
-                        TypedSignature::sign(
+                        TypedSignature::sign_by(
                             &format!("{}-{}", block_hash, "prevote"),
-                            private_key,
-                        )?,
+                            signer.as_ref(),
+                        )
+                        .await?,
                     );
                     let result =
                         ProgressResult::NonNilPreVoted(round as u64, block_hash, timestamp);
@@ -629,7 +632,7 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
                 Ok(progress_result)
             }
             ConsensusResponse::BroadcastPrecommit { proposal, round } => {
-                let private_key = self
+                let signer = self
                     .this_node_key
                     .as_ref()
                     .ok_or_else(|| eyre!("this node is not a validator"))?;
@@ -642,10 +645,7 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
                     let message = ConsensusMessage::NonNilPreCommitted(
                         round as u64,
                         block_hash,
-                        TypedSignature::new(
-                            Signature::sign(block_hash, private_key)?,
-                            private_key.public_key(),
-                        ),
+                        TypedSignature::new(signer.sign(block_hash).await?, signer.public_key()),
                     );
                     let result =
                         ProgressResult::NonNilPreCommitted(round as u64, block_hash, timestamp);
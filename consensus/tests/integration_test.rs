@@ -112,7 +112,7 @@ async fn single_server_propose_1() {
         block_header.clone(),
         params.clone(),
         round_zero_timestamp,
-        Some(server_config.private_key.clone()),
+        server_config.signer(),
     )
     .await
     .unwrap();
@@ -124,7 +124,7 @@ async fn single_server_propose_1() {
             block_header.clone(),
             params.clone(),
             round_zero_timestamp,
-            Some(config.private_key.clone()),
+            config.signer(),
         )
         .await
         .unwrap();
@@ -204,7 +204,7 @@ async fn single_server_propose_1() {
             ConsensusMessage::NonNilPreVoted(
                 0,
                 dummy_block_hash,
-                prevote(dummy_block_hash, &server_config.private_key),
+                prevote(dummy_block_hash, server_config.private_key().unwrap()),
             ),
             server_config.public_key.clone(),
         ),
@@ -214,7 +214,7 @@ async fn single_server_propose_1() {
             ConsensusMessage::NonNilPreVoted(
                 0,
                 dummy_block_hash,
-                prevote(dummy_block_hash, &config.private_key),
+                prevote(dummy_block_hash, config.private_key().unwrap()),
             ),
             config.public_key.clone(),
         ));
@@ -223,7 +223,7 @@ async fn single_server_propose_1() {
                 ConsensusMessage::NonNilPreCommitted(
                     0,
                     dummy_block_hash,
-                    precommit(dummy_block_hash, &config.private_key),
+                    precommit(dummy_block_hash, config.private_key().unwrap()),
                 ),
                 config.public_key.clone(),
             ));
@@ -288,7 +288,7 @@ async fn single_server_propose_1() {
         ConsensusMessage::NonNilPreCommitted(
             0,
             dummy_block_hash,
-            precommit(dummy_block_hash, &server_config.private_key),
+            precommit(dummy_block_hash, server_config.private_key().unwrap()),
         ),
         server_config.public_key.clone(),
     ));
@@ -297,7 +297,7 @@ async fn single_server_propose_1() {
             ConsensusMessage::NonNilPreCommitted(
                 0,
                 dummy_block_hash,
-                precommit(dummy_block_hash, &config.private_key),
+                precommit(dummy_block_hash, config.private_key().unwrap()),
             ),
             config.public_key.clone(),
         ));
@@ -1,10 +1,11 @@
 use serde::{Deserialize, Serialize};
-use simperby_common::*;
+use simperby_common::{signer::Signer, *};
 use simperby_network::{
     dms::{DistributedMessageSet as DMS, Message},
     primitives::{GossipNetwork, Storage},
 };
 use std::collections::HashMap;
+use std::sync::Arc;
 
 pub type Error = eyre::Error;
 
@@ -30,12 +31,15 @@ struct Vote {
 
 pub struct Governance<N: GossipNetwork, S: Storage> {
     pub dms: DMS<N, S>,
-    pub this_node_key: Option<PrivateKey>,
+    pub this_node_key: Option<Arc<dyn Signer>>,
 }
 
 impl<N: GossipNetwork, S: Storage> Governance<N, S> {
     /// TODO: this must take the eligible governance set for this height.
-    pub async fn new(dms: DMS<N, S>, this_node_key: Option<PrivateKey>) -> Result<Self, Error> {
+    pub async fn new(
+        dms: DMS<N, S>,
+        this_node_key: Option<Arc<dyn Signer>>,
+    ) -> Result<Self, Error> {
         Ok(Self { dms, this_node_key })
     }
 
@@ -59,16 +63,14 @@ impl<N: GossipNetwork, S: Storage> Governance<N, S> {
     }
 
     pub async fn vote(&mut self, agenda_hash: Hash256) -> Result<(), Error> {
+        let signer = self.this_node_key.as_deref().unwrap();
         let data = serde_spb::to_string(&Vote {
             agenda_hash,
-            voter: self.this_node_key.as_ref().unwrap().public_key(),
-            signature: Signature::sign(agenda_hash, self.this_node_key.as_ref().unwrap())?,
+            voter: signer.public_key(),
+            signature: signer.sign(agenda_hash).await?,
         })
         .unwrap();
-        let message = Message::new(
-            data.clone(),
-            TypedSignature::sign(&data, self.this_node_key.as_ref().unwrap())?,
-        )?;
+        let message = Message::new(data.clone(), TypedSignature::sign_by(&data, signer).await?)?;
 
         self.dms.add_message(message).await?;
         Ok(())
@@ -18,7 +18,7 @@ async fn basic_1() {
             SharedKnownPeers::new_static(Default::default()),
         )
         .await,
-        Some(server_network_config.private_key),
+        server_network_config.signer(),
     )
     .await
     .unwrap();
@@ -28,7 +28,7 @@ async fn basic_1() {
         client_nodes.push((
             Governance::new(
                 create_test_dms(network_config.clone(), network_id.clone(), peer.clone()).await,
-                Some(network_config.private_key.clone()),
+                network_config.signer(),
             )
             .await
             .unwrap(),
@@ -60,3 +60,34 @@ async fn basic_1() {
     }
     serve_task.await.unwrap();
 }
+
+#[tokio::test]
+async fn basic_with_builder() {
+    setup_test();
+
+    let cluster = DmsClusterBuilder::new("governance-basic-with-builder")
+        .clients(3)
+        .build()
+        .await;
+    let mut server_node = Governance::new(cluster.server, cluster.server_config.signer())
+        .await
+        .unwrap();
+    let mut client_nodes = Vec::new();
+    for (dms, network_config) in cluster.clients.into_iter().zip(cluster.client_configs) {
+        client_nodes.push(Governance::new(dms, network_config.signer()).await.unwrap());
+    }
+
+    let agenda_hash = Hash256::hash("agenda");
+    server_node.vote(agenda_hash).await.unwrap();
+    let serve_task = tokio::spawn(async move { server_node.serve(3000).await.unwrap() });
+    sleep_ms(1000).await;
+    for node in client_nodes.iter_mut() {
+        node.vote(agenda_hash).await.unwrap();
+        node.broadcast().await.unwrap();
+    }
+    let server_node = serve_task.await.unwrap();
+    assert_eq!(
+        server_node.read().await.unwrap().votes[&agenda_hash].len(),
+        4
+    );
+}
@@ -0,0 +1,99 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::Rng;
+use simperby_common::*;
+use simperby_network::dms::Message;
+use simperby_network::simulation::*;
+use simperby_test_suite::*;
+
+/// A node that periodically sends its known peers to a random known peer.
+struct DiscoveryNode {
+    known_peers: Vec<bool>,
+}
+
+impl SimulatedNode for DiscoveryNode {
+    type Message = Vec<bool>;
+
+    fn on_start(&mut self, context: &mut Context<Vec<bool>>) {
+        let delay = context.rng().gen_range(0..1000);
+        context.set_timer(delay, 0);
+    }
+
+    fn on_message(&mut self, _context: &mut Context<Vec<bool>>, from: NodeIndex, peers: Vec<bool>) {
+        self.known_peers[from] = true;
+        for (known, new) in self.known_peers.iter_mut().zip(peers) {
+            *known |= new;
+        }
+    }
+
+    fn on_timer(&mut self, context: &mut Context<Vec<bool>>, _timer: u64) {
+        let known = (0..self.known_peers.len())
+            .filter(|x| self.known_peers[*x] && *x != context.this_node())
+            .collect::<Vec<_>>();
+        let peer = known[context.rng().gen_range(0..known.len())];
+        context.send(peer, self.known_peers.clone());
+        context.set_timer(1000, 0);
+    }
+}
+
+fn discovery_convergence(c: &mut Criterion) {
+    let mut group = c.benchmark_group("discovery_convergence");
+    group.sample_size(10);
+    for n in [50, 100, 200, 400] {
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
+            b.iter(|| {
+                let nodes = (0..n)
+                    .map(|i| {
+                        let mut known_peers = vec![false; n];
+                        known_peers[i] = true;
+                        known_peers[(i + 1) % n] = true;
+                        DiscoveryNode { known_peers }
+                    })
+                    .collect();
+                let mut simulator = Simulator::new(nodes, NetworkConditions::default(), 0);
+                assert!(simulator.run_until_condition(
+                    |nodes| nodes.iter().all(|x| x.known_peers.iter().all(|k| *k)),
+                    u64::MAX
+                ));
+                simulator.now()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn broadcast_throughput(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("dms_broadcast");
+    group.sample_size(10);
+    for messages in [10, 100] {
+        let (client, serve_task) = runtime.block_on(async {
+            let mut cluster = DmsClusterBuilder::new(&format!("bench-broadcast-{messages}"))
+                .clients(1)
+                .interval(None)
+                .build()
+                .await;
+            let serve_task = tokio::spawn(cluster.server.serve(600_000));
+            let mut client = cluster.clients.pop().unwrap();
+            let private_key = cluster.client_configs[0].private_key.clone();
+            for i in 0..messages {
+                let data = format!("message {i}");
+                let signature = TypedSignature::sign(&data, &private_key).unwrap();
+                client
+                    .add_message(Message::new(data, signature).unwrap())
+                    .await
+                    .unwrap();
+            }
+            sleep_ms(500).await;
+            (client, serve_task)
+        });
+        group.throughput(Throughput::Elements(messages));
+        group.bench_function(BenchmarkId::from_parameter(messages), |b| {
+            b.iter(|| runtime.block_on(client.broadcast_all()).unwrap())
+        });
+        serve_task.abort();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, discovery_convergence, broadcast_throughput);
+criterion_main!(benches);
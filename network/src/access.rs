@@ -0,0 +1,229 @@
+//! Denying or allowing the peers by their keys or addresses.
+//!
+//! A peer matching any of `NetworkConfig::denied_peers` is refused.
+//! If `NetworkConfig::allowed_peers` is not empty, a peer must also match one of them.
+//! The lists are enforced when a peer is dialed, when a connection is accepted
+//! and when a peer is discovered (before being merged into the known peers).
+//!
+//! An accepted connection tells only the address of the other side, so it's checked against
+//! the CIDR patterns alone; the key patterns are enforced once the key is known.
+use super::*;
+use eyre::eyre;
+use std::str::FromStr;
+
+/// A range of IP addresses (e.g., `10.0.0.0/8` or `2001:db8::/32`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpCidr {
+    pub address: IpAddr,
+    pub prefix_len: u8,
+}
+
+impl IpCidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = Error;
+
+    /// Parses `address/prefix_len`, or a single address.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s, None),
+        };
+        let address =
+            IpAddr::from_str(address).map_err(|e| eyre!("invalid address in {s}: {e}"))?;
+        let max_prefix_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(x) => x
+                .parse::<u8>()
+                .map_err(|e| eyre!("invalid prefix length in {s}: {e}"))?,
+            None => max_prefix_len,
+        };
+        if prefix_len > max_prefix_len {
+            return Err(eyre!("prefix length of {s} exceeds {max_prefix_len}"));
+        }
+        Ok(Self {
+            address,
+            prefix_len,
+        })
+    }
+}
+
+impl TryFrom<String> for IpCidr {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Error> {
+        s.parse()
+    }
+}
+
+impl From<IpCidr> for String {
+    fn from(cidr: IpCidr) -> Self {
+        cidr.to_string()
+    }
+}
+
+impl std::fmt::Display for IpCidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PeerPattern {
+    Key(PublicKey),
+    Cidr(IpCidr),
+}
+
+/// The denied and the allowed peers (usually of `NetworkConfig`), checked together.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerFilter {
+    pub denied: Vec<PeerPattern>,
+    /// If empty, all the peers but the denied ones are allowed.
+    pub allowed: Vec<PeerPattern>,
+}
+
+impl PeerFilter {
+    /// Checks the peer by its key and all of its IP addresses.
+    pub fn check_peer(&self, peer: &Peer) -> Result<(), Error> {
+        let ips = peer
+            .hosts()
+            .into_iter()
+            .filter_map(|host| match host {
+                PeerAddress::Ip(ip) => Some(ip),
+                PeerAddress::Dns(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let matches = |pattern: &PeerPattern| match pattern {
+            PeerPattern::Key(key) => *key == peer.public_key,
+            PeerPattern::Cidr(cidr) => ips.iter().any(|ip| cidr.contains(ip)),
+        };
+        if let Some(pattern) = self.denied.iter().find(|x| matches(x)) {
+            return Err(eyre!("peer {} is denied by {pattern:?}", peer.public_key));
+        }
+        if !self.allowed.is_empty() && !self.allowed.iter().any(matches) {
+            return Err(eyre!("peer {} is not allowed", peer.public_key));
+        }
+        Ok(())
+    }
+
+    /// Checks the address of a peer whose key is unknown yet.
+    ///
+    /// If any key is allowed, the address is allowed as well, to be checked again with the key.
+    pub fn check_address(&self, ip: &IpAddr) -> Result<(), Error> {
+        let matches = |pattern: &PeerPattern| match pattern {
+            PeerPattern::Key(_) => false,
+            PeerPattern::Cidr(cidr) => cidr.contains(ip),
+        };
+        if let Some(pattern) = self.denied.iter().find(|x| matches(x)) {
+            return Err(eyre!("address {ip} is denied by {pattern:?}"));
+        }
+        let any_key_allowed = self
+            .allowed
+            .iter()
+            .any(|x| matches!(x, PeerPattern::Key(_)));
+        if !self.allowed.is_empty() && !any_key_allowed && !self.allowed.iter().any(matches) {
+            return Err(eyre!("address {ip} is not allowed"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    fn peer(seed: &str, address: &str) -> Peer {
+        Peer {
+            public_key: generate_keypair(seed).0,
+            name: seed.to_owned(),
+            address: address.parse().unwrap(),
+            addresses: vec![PeerAddress::Ip("2001:db8::1".parse().unwrap())],
+            ports: Default::default(),
+            metadata: Default::default(),
+            recently_seen_timestamp: 0,
+        }
+    }
+
+    fn cidr(s: &str) -> PeerPattern {
+        PeerPattern::Cidr(s.parse().unwrap())
+    }
+
+    #[test]
+    fn cidr_parsing() {
+        setup_test();
+        let x: IpCidr = "10.1.0.0/16".parse().unwrap();
+        assert!(x.contains(&"10.1.200.3".parse().unwrap()));
+        assert!(!x.contains(&"10.2.0.1".parse().unwrap()));
+        assert!(!x.contains(&"::1".parse().unwrap()));
+        let all: IpCidr = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(&"8.8.8.8".parse().unwrap()));
+        let single: IpCidr = "2001:db8::1".parse().unwrap();
+        assert_eq!(single.prefix_len, 128);
+        assert!(single.contains(&"2001:db8::1".parse().unwrap()));
+        assert!(!single.contains(&"2001:db8::2".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpCidr>().is_err());
+        assert!("10.0.0/8".parse::<IpCidr>().is_err());
+        assert_eq!(
+            serde_spb::from_str::<IpCidr>(&serde_spb::to_string(&x).unwrap()).unwrap(),
+            x
+        );
+    }
+
+    #[test]
+    fn filter() {
+        setup_test();
+        let (a, b) = (peer("a", "10.0.0.1:1"), peer("b", "192.168.0.1:1"));
+        let none = PeerFilter::default();
+        assert!(none.check_peer(&a).is_ok());
+
+        let deny = PeerFilter {
+            denied: vec![PeerPattern::Key(b.public_key.clone()), cidr("10.0.0.0/8")],
+            allowed: Vec::new(),
+        };
+        assert!(deny.check_peer(&a).is_err());
+        assert!(deny.check_peer(&b).is_err());
+        assert!(deny.check_address(&"10.3.0.1".parse().unwrap()).is_err());
+        assert!(deny.check_address(&"192.168.0.1".parse().unwrap()).is_ok());
+
+        let allow = PeerFilter {
+            denied: Vec::new(),
+            allowed: vec![cidr("2001:db8::/32")],
+        };
+        assert!(allow.check_peer(&a).is_ok());
+        assert!(allow.check_address(&"10.0.0.1".parse().unwrap()).is_err());
+        assert!(allow.check_address(&"2001:db8::5".parse().unwrap()).is_ok());
+
+        let allow_key = PeerFilter {
+            denied: vec![cidr("192.168.0.0/16")],
+            allowed: vec![PeerPattern::Key(a.public_key.clone())],
+        };
+        assert!(allow_key.check_peer(&a).is_ok());
+        assert!(allow_key.check_peer(&peer("c", "172.16.0.1:1")).is_err());
+        assert!(allow_key
+            .check_address(&"172.16.0.1".parse().unwrap())
+            .is_ok());
+        assert!(allow_key
+            .check_address(&"192.168.0.1".parse().unwrap())
+            .is_err());
+    }
+}
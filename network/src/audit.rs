@@ -0,0 +1,180 @@
+//! An append-only, hash-chained log of the signatures produced by this node.
+//!
+//! Each entry commits to the hash of the previous one, so that entries cannot be removed
+//! or altered afterward without breaking the chain. The log is stored as JSON lines.
+use super::*;
+use eyre::eyre;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub index: u64,
+    pub timestamp: Timestamp,
+    /// Where the signature was used (e.g., the DMS key).
+    pub context: String,
+    /// The exact data that has been signed.
+    pub data: String,
+    pub signature: TypedSignature<String>,
+    /// The hash of the previous entry (zero for the first one).
+    pub prev_hash: Hash256,
+}
+
+impl ToHash256 for AuditEntry {
+    fn to_hash256(&self) -> Hash256 {
+        Hash256::hash(serde_spb::to_vec(self).unwrap())
+    }
+}
+
+/// Conditions to query the entries. Every condition is optional.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditQuery {
+    pub context: Option<String>,
+    pub since: Option<Timestamp>,
+    pub until: Option<Timestamp>,
+}
+
+impl AuditQuery {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.context.as_ref().map_or(true, |x| *x == entry.context)
+            && self.since.map_or(true, |x| entry.timestamp >= x)
+            && self.until.map_or(true, |x| entry.timestamp <= x)
+    }
+}
+
+/// Verifies the hash chain and the signatures of the entries.
+pub fn verify_chain(entries: &[AuditEntry]) -> Result<(), Error> {
+    let mut prev_hash = Hash256::zero();
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.index != i as u64 {
+            return Err(eyre!("entry {} has an invalid index {}", i, entry.index));
+        }
+        if entry.prev_hash != prev_hash {
+            return Err(eyre!("entry {} breaks the hash chain", i));
+        }
+        entry
+            .signature
+            .verify(&entry.data)
+            .map_err(|e| eyre!("entry {} has an invalid signature: {}", i, e))?;
+        prev_hash = entry.to_hash256();
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct SigningAuditLog {
+    path: String,
+    /// The index and the hash of the last entry.
+    last: Mutex<(u64, Hash256)>,
+}
+
+impl SigningAuditLog {
+    /// Opens the log at the given file path, creating it if it doesn't exist.
+    ///
+    /// Fails if the existing log is corrupted.
+    pub async fn open(path: &str) -> Result<Self, Error> {
+        let entries = Self::read_entries(path).await?;
+        verify_chain(&entries)?;
+        let last = entries
+            .last()
+            .map(|x| (x.index + 1, x.to_hash256()))
+            .unwrap_or((0, Hash256::zero()));
+        Ok(Self {
+            path: path.to_owned(),
+            last: Mutex::new(last),
+        })
+    }
+
+    async fn read_entries(path: &str) -> Result<Vec<AuditEntry>, Error> {
+        if tokio::fs::metadata(path).await.is_err() {
+            return Ok(Vec::new());
+        }
+        tokio::fs::read_to_string(path)
+            .await?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_str(line).map_err(|e| e.into()))
+            .collect()
+    }
+
+    /// Appends a new entry for the given signature.
+    pub async fn record(
+        &self,
+        context: &str,
+        data: &str,
+        signature: &TypedSignature<String>,
+        timestamp: Timestamp,
+    ) -> Result<(), Error> {
+        let mut last = self.last.lock().await;
+        let entry = AuditEntry {
+            index: last.0,
+            timestamp,
+            context: context.to_owned(),
+            data: data.to_owned(),
+            signature: signature.clone(),
+            prev_hash: last.1,
+        };
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(format!("{}\n", serde_json::to_string(&entry)?).as_bytes())
+            .await?;
+        file.flush().await?;
+        *last = (entry.index + 1, entry.to_hash256());
+        Ok(())
+    }
+
+    /// Reads the entries matching the query, verifying the whole chain.
+    pub async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, Error> {
+        let _last = self.last.lock().await;
+        let entries = Self::read_entries(&self.path).await?;
+        verify_chain(&entries)?;
+        Ok(entries.into_iter().filter(|x| query.matches(x)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    #[tokio::test]
+    async fn record_and_verify() {
+        setup_test();
+        let path = std::env::temp_dir().join(format!("simperby-audit-{}", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let _ = tokio::fs::remove_file(&path).await;
+        let (_, private_key) = generate_keypair("audit");
+
+        let log = SigningAuditLog::open(&path).await.unwrap();
+        for (i, context) in ["governance", "consensus", "governance"].iter().enumerate() {
+            let data = format!("message {i}");
+            let signature = TypedSignature::sign(&data, &private_key).unwrap();
+            log.record(context, &data, &signature, i as Timestamp)
+                .await
+                .unwrap();
+        }
+        let governance = AuditQuery {
+            context: Some("governance".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(log.query(&governance).await.unwrap().len(), 2);
+
+        // Reopening continues the chain.
+        let log = SigningAuditLog::open(&path).await.unwrap();
+        let signature = TypedSignature::sign(&"more".to_owned(), &private_key).unwrap();
+        log.record("consensus", "more", &signature, 3)
+            .await
+            .unwrap();
+        let entries = log.query(&AuditQuery::default()).await.unwrap();
+        assert_eq!(entries.len(), 4);
+
+        // Tampering breaks the chain.
+        let mut tampered = entries;
+        tampered.remove(1);
+        assert!(verify_chain(&tampered).is_err());
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+//! Reporting the peers refused by this node.
+//!
+//! Operators need to see who is probing their node, which is otherwise only in the debug logs.
+//! The components that refuse a peer (e.g., [`crate::rpc::TcpRpc`] and [`crate::mux::MuxRpc`]
+//! given the same [`AuthEvents`]) report an [`AuthFailure`] to it, with where the peer came from
+//! (if known) and why it was refused, which the subscribers receive as a stream.
+use super::*;
+use std::net::SocketAddr;
+
+/// The number of the failures kept for a subscriber that falls behind.
+const AUTH_EVENT_CAPACITY: usize = 1024;
+
+/// Why a peer was refused.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum AuthFailureReason {
+    /// The connection failed the handshake (e.g., with a wrong or missing pre-shared key).
+    Handshake(String),
+    /// The address is denied by the peer filter (e.g., of a banned peer).
+    Denied(String),
+    /// The peer used a key that isn't of a member.
+    UnknownKey(PublicKey),
+}
+
+impl std::fmt::Display for AuthFailureReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Handshake(e) => write!(f, "failed the handshake: {e}"),
+            Self::Denied(e) => write!(f, "denied: {e}"),
+            Self::UnknownKey(key) => write!(f, "unknown key {key}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AuthFailure {
+    /// Where the peer connected from, if known (e.g., not for a relayed message).
+    pub address: Option<SocketAddr>,
+    pub reason: AuthFailureReason,
+    pub timestamp: Timestamp,
+}
+
+/// The stream of the authentication failures, which can be shared by multiple components of a node.
+#[derive(Debug, Clone)]
+pub struct AuthEvents {
+    events: tokio::sync::broadcast::Sender<AuthFailure>,
+}
+
+impl Default for AuthEvents {
+    fn default() -> Self {
+        Self {
+            events: tokio::sync::broadcast::channel(AUTH_EVENT_CAPACITY).0,
+        }
+    }
+}
+
+impl AuthEvents {
+    /// Subscribes to the failures reported after this call.
+    ///
+    /// A subscriber that falls behind by more than [`AUTH_EVENT_CAPACITY`] failures
+    /// gets `RecvError::Lagged`, missing the older ones.
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<AuthFailure> {
+        self.events.subscribe()
+    }
+
+    pub fn report(&self, address: Option<SocketAddr>, reason: AuthFailureReason) {
+        tracing::debug!(?address, %reason, "refused a peer");
+        let failure = AuthFailure {
+            address,
+            reason,
+            timestamp: chrono::Utc::now().timestamp_millis() as Timestamp,
+        };
+        // Fails only if there is no subscriber.
+        let _ = self.events.send(failure);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::{PeerFilter, PeerPattern};
+    use crate::mux::{MuxRpc, MUX_PROTOCOL};
+    use crate::psk::PreSharedKey;
+    use crate::rpc::{RpcConfig, TcpRpc};
+    use simperby_test_suite::*;
+
+    fn peer(port: u16) -> Peer {
+        Peer {
+            public_key: PublicKey::zero(),
+            name: "server".to_owned(),
+            address: "127.0.0.1:1".parse().unwrap(),
+            addresses: Vec::new(),
+            ports: vec![("sum".to_owned(), port), (MUX_PROTOCOL.to_owned(), port)]
+                .into_iter()
+                .collect(),
+            metadata: Default::default(),
+            recently_seen_timestamp: 0,
+        }
+    }
+
+    fn config() -> RpcConfig {
+        RpcConfig {
+            timeout_ms: 1000,
+            ..Default::default()
+        }
+    }
+
+    async fn next_reason(
+        failures: &mut tokio::sync::broadcast::Receiver<AuthFailure>,
+    ) -> AuthFailureReason {
+        let failure = tokio::time::timeout(std::time::Duration::from_secs(1), failures.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            failure.address.unwrap().ip(),
+            std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST)
+        );
+        failure.reason
+    }
+
+    #[tokio::test]
+    async fn transports() {
+        setup_test();
+        let events = AuthEvents::default();
+        let mut failures = events.subscribe();
+        let key = Some(PreSharedKey::from_passphrase("private"));
+        let denied = PeerFilter {
+            denied: vec![PeerPattern::Cidr("127.0.0.0/8".parse().unwrap())],
+            allowed: Vec::new(),
+        };
+
+        let port = dispense_port();
+        let _tcp = TcpRpc::new(config())
+            .with_pre_shared_key(key)
+            .with_auth_events(events.clone())
+            .serve(port, |x: u64| async move { Ok(x) })
+            .await
+            .unwrap();
+        let client = TcpRpc::new(config());
+        assert!(client
+            .request::<_, u64>(&peer(port), "sum", 1u64)
+            .await
+            .is_err());
+        assert!(matches!(
+            next_reason(&mut failures).await,
+            AuthFailureReason::Handshake(_)
+        ));
+
+        let port = dispense_port();
+        let mux = MuxRpc::new(config())
+            .with_peer_filter(denied)
+            .with_auth_events(events.clone());
+        let _mux = mux
+            .serve_protocol("sum", port, |x: u64| async move { Ok(x) })
+            .await
+            .unwrap();
+        let client = MuxRpc::new(config());
+        assert!(client
+            .request::<_, u64>(&peer(port), "sum", 1u64)
+            .await
+            .is_err());
+        assert!(matches!(
+            next_reason(&mut failures).await,
+            AuthFailureReason::Denied(_)
+        ));
+    }
+}
@@ -0,0 +1,191 @@
+//! Accounting of the bandwidth used with each peer, with per-peer rate limits.
+//!
+//! A peer that exceeds a rate limit is throttled (neither fetched from nor broadcasted to)
+//! for the rest of the one-second window.
+use super::*;
+use parking_lot::Mutex;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BandwidthLimits {
+    /// The maximum bytes per second received from a peer. `None` means unlimited.
+    pub max_ingress_bytes_per_sec: Option<u64>,
+    /// The maximum bytes per second sent to a peer. `None` means unlimited.
+    pub max_egress_bytes_per_sec: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TrafficCounter {
+    pub ingress_bytes: u64,
+    pub egress_bytes: u64,
+}
+
+/// The traffic with a peer.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PeerTraffic {
+    pub total: TrafficCounter,
+    /// By the protocol (e.g., the DMS key).
+    pub protocols: HashMap<String, TrafficCounter>,
+    /// How many times the peer has been throttled.
+    pub throttled: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NetworkStats {
+    pub total: TrafficCounter,
+    pub peers: HashMap<PublicKey, PeerTraffic>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Ingress,
+    Egress,
+}
+
+#[derive(Debug, Default)]
+struct Window {
+    start: Timestamp,
+    counter: TrafficCounter,
+    throttled: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct BandwidthMeter {
+    limits: BandwidthLimits,
+    stats: Mutex<NetworkStats>,
+    windows: Mutex<HashMap<PublicKey, Window>>,
+}
+
+impl BandwidthMeter {
+    pub fn new(limits: BandwidthLimits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    /// Records the traffic with the peer.
+    ///
+    /// Returns an error if it exceeds the rate limit, throttling the peer for the rest of the window.
+    pub fn record(
+        &self,
+        peer: &PublicKey,
+        protocol: &str,
+        direction: Direction,
+        bytes: u64,
+        now: Timestamp,
+    ) -> Result<(), Error> {
+        let add = |counter: &mut TrafficCounter| match direction {
+            Direction::Ingress => counter.ingress_bytes += bytes,
+            Direction::Egress => counter.egress_bytes += bytes,
+        };
+        let mut stats = self.stats.lock();
+        add(&mut stats.total);
+        let peer_traffic = stats.peers.entry(peer.clone()).or_default();
+        add(&mut peer_traffic.total);
+        add(peer_traffic
+            .protocols
+            .entry(protocol.to_owned())
+            .or_default());
+
+        let mut windows = self.windows.lock();
+        let window = windows.entry(peer.clone()).or_default();
+        if now - window.start >= 1000 {
+            *window = Window {
+                start: now,
+                ..Default::default()
+            };
+        }
+        add(&mut window.counter);
+        let (used, limit) = match direction {
+            Direction::Ingress => (
+                window.counter.ingress_bytes,
+                self.limits.max_ingress_bytes_per_sec,
+            ),
+            Direction::Egress => (
+                window.counter.egress_bytes,
+                self.limits.max_egress_bytes_per_sec,
+            ),
+        };
+        match limit {
+            Some(limit) if used > limit => {
+                if !window.throttled {
+                    window.throttled = true;
+                    peer_traffic.throttled += 1;
+                }
+                Err(eyre::eyre!(
+                    "{direction:?} rate limit exceeded for peer {peer}: {used} > {limit} bytes/s"
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_throttled(&self, peer: &PublicKey, now: Timestamp) -> bool {
+        self.windows
+            .lock()
+            .get(peer)
+            .map_or(false, |x| x.throttled && now - x.start < 1000)
+    }
+
+    /// Returns the peers that are not throttled.
+    pub fn filter_throttled(&self, peers: Vec<Peer>, now: Timestamp) -> Vec<Peer> {
+        peers
+            .into_iter()
+            .filter(|peer| !self.is_throttled(&peer.public_key, now))
+            .collect()
+    }
+
+    /// Returns the bandwidth used so far.
+    pub fn stats(&self) -> NetworkStats {
+        self.stats.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    #[test]
+    fn accounting_and_throttling() {
+        setup_test();
+        let meter = BandwidthMeter::new(BandwidthLimits {
+            max_ingress_bytes_per_sec: Some(100),
+            max_egress_bytes_per_sec: None,
+        });
+        let a = generate_keypair("a").0;
+        let b = generate_keypair("b").0;
+        meter
+            .record(&a, "dms-1", Direction::Ingress, 60, 0)
+            .unwrap();
+        meter
+            .record(&a, "dms-2", Direction::Egress, 1000, 0)
+            .unwrap();
+        meter
+            .record(&b, "dms-1", Direction::Ingress, 30, 0)
+            .unwrap();
+        assert!(meter
+            .record(&a, "dms-1", Direction::Ingress, 60, 500)
+            .is_err());
+        assert!(meter.is_throttled(&a, 999));
+        assert!(!meter.is_throttled(&b, 999));
+        // A new window.
+        assert!(!meter.is_throttled(&a, 1000));
+        meter
+            .record(&a, "dms-1", Direction::Ingress, 60, 1000)
+            .unwrap();
+
+        let stats = meter.stats();
+        assert_eq!(
+            stats.total,
+            TrafficCounter {
+                ingress_bytes: 210,
+                egress_bytes: 1000
+            }
+        );
+        assert_eq!(stats.peers[&a].protocols["dms-1"].ingress_bytes, 180);
+        assert_eq!(stats.peers[&a].protocols["dms-2"].egress_bytes, 1000);
+        assert_eq!(stats.peers[&a].throttled, 1);
+        assert_eq!(stats.peers[&b].total.ingress_bytes, 30);
+    }
+}
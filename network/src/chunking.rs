@@ -0,0 +1,320 @@
+//! Chunking of the large gossip messages.
+//!
+//! A gossip message larger than `ChunkConfig::max_chunk_size` is split into chunks,
+//! each of which goes out as a separate gossip message and is reassembled by the recipients.
+//! The chunks of a message share the hash of the whole payload as their token,
+//! which the reassembled payload is verified against.
+use super::*;
+use eyre::eyre;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChunkConfig {
+    /// The maximum size of the payload of a chunk (i.e., of a single gossip message) in bytes.
+    pub max_chunk_size: usize,
+    /// The maximum size of a reassembled message in bytes.
+    pub max_message_size: usize,
+    /// How long the chunks of an incomplete message are kept, in milliseconds.
+    pub reassembly_timeout_ms: u64,
+    /// The maximum number of the incomplete messages kept at once.
+    pub max_pending_messages: usize,
+    /// The maximum size of the chunks of all the incomplete messages in bytes.
+    pub max_pending_bytes: usize,
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        Self {
+            max_chunk_size: 64 * 1024,
+            max_message_size: 16 * 1024 * 1024,
+            reassembly_timeout_ms: 60 * 1000,
+            max_pending_messages: 64,
+            max_pending_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// The hash of the whole payload of a broadcast, identifying the message.
+pub type BroadcastToken = Hash256;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Chunk {
+    pub token: BroadcastToken,
+    pub index: u32,
+    pub total: u32,
+    pub data: Vec<u8>,
+}
+
+/// Splits the payload into the chunks, which is a single one if the payload is small enough.
+pub fn split(payload: &[u8], config: &ChunkConfig) -> Result<Vec<Chunk>, Error> {
+    if payload.len() > config.max_message_size {
+        return Err(eyre!(
+            "message of {} bytes exceeds the limit of {} bytes",
+            payload.len(),
+            config.max_message_size
+        ));
+    }
+    let token = Hash256::hash(payload);
+    let mut chunks = payload
+        .chunks(config.max_chunk_size.max(1))
+        .collect::<Vec<_>>();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+    let total = chunks.len() as u32;
+    Ok(chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, data)| Chunk {
+            token,
+            index: index as u32,
+            total,
+            data: data.to_vec(),
+        })
+        .collect())
+}
+
+#[derive(Debug)]
+struct PendingMessage {
+    chunks: Vec<Option<Vec<u8>>>,
+    received: u32,
+    size: usize,
+    first_received: Timestamp,
+}
+
+/// Reassembles the messages from the chunks received.
+#[derive(Debug)]
+pub struct Reassembler {
+    config: ChunkConfig,
+    pending: HashMap<BroadcastToken, PendingMessage>,
+}
+
+impl Reassembler {
+    pub fn new(config: ChunkConfig) -> Self {
+        Self {
+            config,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Adds a chunk, returning the whole payload once all the chunks of the message have been received.
+    ///
+    /// The oldest incomplete messages are dropped to keep the number and the size of them
+    /// within the limits.
+    pub fn add(&mut self, chunk: Chunk, now: Timestamp) -> Result<Option<Vec<u8>>, Error> {
+        let timeout = self.config.reassembly_timeout_ms as Timestamp;
+        self.pending
+            .retain(|_, x| now.saturating_sub(x.first_received) <= timeout);
+
+        let chunk_size = self.config.max_chunk_size.max(1);
+        let max_chunks = ((self.config.max_message_size + chunk_size - 1) / chunk_size).max(1);
+        if chunk.total == 0 || chunk.total as usize > max_chunks {
+            return Err(eyre!("invalid number of chunks: {}", chunk.total));
+        }
+        if chunk.index >= chunk.total || chunk.data.len() > chunk_size {
+            return Err(eyre!("invalid chunk {} of {}", chunk.index, chunk.total));
+        }
+        if !self.pending.contains_key(&chunk.token) {
+            while self.pending.len() >= self.config.max_pending_messages.max(1) {
+                self.drop_oldest(&chunk.token);
+            }
+        }
+        let pending = self
+            .pending
+            .entry(chunk.token)
+            .or_insert_with(|| PendingMessage {
+                chunks: vec![None; chunk.total as usize],
+                received: 0,
+                size: 0,
+                first_received: now,
+            });
+        if pending.chunks.len() != chunk.total as usize {
+            return Err(eyre!("chunk of {} has a different total", chunk.token));
+        }
+        let slot = &mut pending.chunks[chunk.index as usize];
+        if slot.is_some() {
+            return Ok(None);
+        }
+        pending.size += chunk.data.len();
+        if pending.size > self.config.max_message_size {
+            self.pending.remove(&chunk.token);
+            return Err(eyre!("message {} exceeds the size limit", chunk.token));
+        }
+        *slot = Some(chunk.data);
+        pending.received += 1;
+        if pending.received < chunk.total {
+            while self.pending_bytes() > self.config.max_pending_bytes {
+                if !self.drop_oldest(&chunk.token) {
+                    self.pending.remove(&chunk.token);
+                    return Err(eyre!("message {} exceeds the pending limit", chunk.token));
+                }
+            }
+            return Ok(None);
+        }
+
+        let pending = self
+            .pending
+            .remove(&chunk.token)
+            .expect("the message is pending");
+        let payload = pending
+            .chunks
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect::<Vec<_>>();
+        if Hash256::hash(&payload) != chunk.token {
+            return Err(eyre!("reassembled message doesn't match {}", chunk.token));
+        }
+        Ok(Some(payload))
+    }
+
+    /// Returns the number of the incomplete messages.
+    pub fn pending_messages(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns the size of the chunks of the incomplete messages in bytes.
+    pub fn pending_bytes(&self) -> usize {
+        self.pending.values().map(|x| x.size).sum()
+    }
+
+    /// Drops the incomplete message received first other than `keep`, returning whether there was one.
+    fn drop_oldest(&mut self, keep: &BroadcastToken) -> bool {
+        let oldest = self
+            .pending
+            .iter()
+            .filter(|(token, _)| *token != keep)
+            .min_by_key(|(_, x)| x.first_received)
+            .map(|(token, _)| *token);
+        if let Some(token) = oldest {
+            self.pending.remove(&token);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    fn config() -> ChunkConfig {
+        ChunkConfig {
+            max_chunk_size: 10,
+            max_message_size: 100,
+            reassembly_timeout_ms: 1000,
+            max_pending_messages: 3,
+            max_pending_bytes: 100,
+        }
+    }
+
+    #[test]
+    fn split_and_reassemble() {
+        setup_test();
+        let payload = (0..95u8).collect::<Vec<_>>();
+        let mut chunks = split(&payload, &config()).unwrap();
+        assert_eq!(chunks.len(), 10);
+        assert!(chunks.iter().all(|x| x.total == 10 && x.data.len() <= 10));
+        assert_eq!(split(&[1, 2, 3], &config()).unwrap().len(), 1);
+        assert_eq!(split(&[], &config()).unwrap().len(), 1);
+        assert!(split(&[0; 101], &config()).is_err());
+
+        // In any order, with duplicates.
+        chunks.reverse();
+        let duplicate = chunks[0].clone();
+        let mut reassembler = Reassembler::new(config());
+        for chunk in chunks.iter().take(9) {
+            assert_eq!(reassembler.add(chunk.clone(), 0).unwrap(), None);
+        }
+        assert_eq!(reassembler.add(duplicate, 0).unwrap(), None);
+        assert_eq!(reassembler.pending_messages(), 1);
+        assert_eq!(
+            reassembler.add(chunks[9].clone(), 0).unwrap(),
+            Some(payload)
+        );
+        assert_eq!(reassembler.pending_messages(), 0);
+
+        let empty = split(&[], &config()).unwrap().pop().unwrap();
+        assert_eq!(reassembler.add(empty, 0).unwrap(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn invalid_chunks() {
+        setup_test();
+        let mut reassembler = Reassembler::new(config());
+        let chunks = split(&[7; 30], &config()).unwrap();
+        assert!(reassembler
+            .add(
+                Chunk {
+                    total: 11,
+                    ..chunks[0].clone()
+                },
+                0
+            )
+            .is_err());
+        assert!(reassembler
+            .add(
+                Chunk {
+                    index: 3,
+                    ..chunks[0].clone()
+                },
+                0
+            )
+            .is_err());
+        // Tampered.
+        reassembler.add(chunks[0].clone(), 0).unwrap();
+        reassembler.add(chunks[1].clone(), 0).unwrap();
+        assert!(reassembler
+            .add(
+                Chunk {
+                    data: vec![8; 10],
+                    ..chunks[2].clone()
+                },
+                0
+            )
+            .is_err());
+
+        // Expired.
+        reassembler.add(chunks[0].clone(), 0).unwrap();
+        reassembler.add(chunks[1].clone(), 0).unwrap();
+        assert_eq!(reassembler.add(chunks[2].clone(), 1001).unwrap(), None);
+        assert_eq!(reassembler.pending_messages(), 1);
+    }
+
+    #[test]
+    fn pending_limits() {
+        setup_test();
+        let mut reassembler = Reassembler::new(config());
+        let messages = (0..4u8)
+            .map(|i| split(&[i; 50], &config()).unwrap())
+            .collect::<Vec<_>>();
+
+        // The oldest message is dropped for the fourth.
+        for (i, chunks) in messages.iter().enumerate() {
+            reassembler.add(chunks[0].clone(), i as Timestamp).unwrap();
+        }
+        assert_eq!(reassembler.pending_messages(), 3);
+        assert_eq!(reassembler.add(messages[0][1].clone(), 4).unwrap(), None);
+        assert_eq!(reassembler.pending_messages(), 3);
+
+        // The oldest ones are dropped to keep the bytes within the limit.
+        let mut reassembler = Reassembler::new(config());
+        for chunk in &messages[0][0..4] {
+            reassembler.add(chunk.clone(), 0).unwrap();
+        }
+        for chunk in &messages[1][0..4] {
+            reassembler.add(chunk.clone(), 1).unwrap();
+        }
+        assert_eq!(reassembler.pending_bytes(), 80);
+        for chunk in &messages[2][0..4] {
+            reassembler.add(chunk.clone(), 2).unwrap();
+        }
+        assert_eq!(reassembler.pending_messages(), 2);
+        assert_eq!(reassembler.pending_bytes(), 80);
+        assert_eq!(
+            reassembler.add(messages[2][4].clone(), 3).unwrap(),
+            Some(vec![2; 50])
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Compression of the frames of a connection.
+//!
+//! On opening a connection (see [`crate::rpc`]), each side tells the algorithms that it supports,
+//! and both pick the preferred one among the common algorithms.
+//! Each frame then starts with a byte telling how it's compressed,
+//! so the small frames (below `CompressionConfig::threshold_bytes`) can go out uncompressed.
+use super::*;
+use eyre::eyre;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Compression {
+    /// Fast, with a modest ratio.
+    Lz4,
+    /// Slower, with a better ratio.
+    Zstd,
+}
+
+impl Compression {
+    /// The preference order when both sides support multiple algorithms.
+    const PREFERENCE: [Compression; 2] = [Compression::Zstd, Compression::Lz4];
+
+    /// The byte identifying the algorithm, in the negotiation (as a bit) and in the frames.
+    fn id(self) -> u8 {
+        match self {
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CompressionConfig {
+    /// The algorithms supported by this node. If empty, the frames are never compressed.
+    pub algorithms: Vec<Compression>,
+    /// The frames smaller than this are sent uncompressed.
+    pub threshold_bytes: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            algorithms: Vec::new(),
+            threshold_bytes: 1024,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Returns the algorithms supported by this node as bits, to be sent to the other side.
+    pub(crate) fn supported(&self) -> u8 {
+        self.algorithms.iter().fold(0, |bits, x| bits | x.id())
+    }
+
+    /// Picks the algorithm to use with the other side that supports the algorithms of `other_bits`.
+    pub(crate) fn negotiate(&self, other_bits: u8) -> Option<Compression> {
+        let common = self.supported() & other_bits;
+        Compression::PREFERENCE
+            .into_iter()
+            .find(|x| common & x.id() != 0)
+    }
+}
+
+/// Encodes the payload of a frame, compressing it with the algorithm if it's large enough.
+pub(crate) fn encode(
+    algorithm: Option<Compression>,
+    threshold_bytes: usize,
+    payload: &[u8],
+) -> Vec<u8> {
+    let algorithm = match algorithm {
+        Some(x) if payload.len() >= threshold_bytes => x,
+        _ => return [&[0], payload].concat(),
+    };
+    let compressed = match algorithm {
+        Compression::Lz4 => lz4_flex::compress_prepend_size(payload),
+        Compression::Zstd => {
+            zstd::bulk::compress(payload, 0).expect("compression in memory never fails")
+        }
+    };
+    [&[algorithm.id()], compressed.as_slice()].concat()
+}
+
+/// Decodes the payload of a frame, refusing the ones that decompress beyond `max_size`.
+pub(crate) fn decode(frame: &[u8], max_size: usize) -> Result<Vec<u8>, Error> {
+    let (id, data) = frame.split_first().ok_or_else(|| eyre!("empty frame"))?;
+    let payload = match *id {
+        0 => data.to_vec(),
+        1 => {
+            let size = data
+                .get(..4)
+                .map(|x| u32::from_le_bytes(x.try_into().expect("4 bytes")) as usize)
+                .ok_or_else(|| eyre!("malformed LZ4 frame"))?;
+            if size > max_size {
+                return Err(eyre!("frame decompresses to {size} bytes, over the limit"));
+            }
+            lz4_flex::decompress_size_prepended(data)
+                .map_err(|e| eyre!("malformed LZ4 frame: {e}"))?
+        }
+        2 => zstd::bulk::decompress(data, max_size)
+            .map_err(|e| eyre!("malformed zstd frame: {e}"))?,
+        x => return Err(eyre!("unknown compression {x}")),
+    };
+    if payload.len() > max_size {
+        return Err(eyre!("frame of {} bytes exceeds the limit", payload.len()));
+    }
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    #[test]
+    fn negotiation() {
+        setup_test();
+        let config = |algorithms: &[Compression]| CompressionConfig {
+            algorithms: algorithms.to_vec(),
+            threshold_bytes: 0,
+        };
+        let both = config(&[Compression::Lz4, Compression::Zstd]);
+        let lz4 = config(&[Compression::Lz4]);
+        let none = config(&[]);
+        assert_eq!(both.negotiate(both.supported()), Some(Compression::Zstd));
+        assert_eq!(both.negotiate(lz4.supported()), Some(Compression::Lz4));
+        assert_eq!(lz4.negotiate(both.supported()), Some(Compression::Lz4));
+        assert_eq!(both.negotiate(none.supported()), None);
+        assert_eq!(none.negotiate(both.supported()), None);
+    }
+
+    #[test]
+    fn encode_decode() {
+        setup_test();
+        let payload = vec![7u8; 10_000];
+        for algorithm in [None, Some(Compression::Lz4), Some(Compression::Zstd)] {
+            let frame = encode(algorithm, 1024, &payload);
+            if algorithm.is_some() {
+                assert!(frame.len() < 1000);
+            }
+            assert_eq!(decode(&frame, 10_000).unwrap(), payload);
+            // Too large once decompressed.
+            assert!(decode(&frame, 9_999).is_err());
+            // Below the threshold.
+            assert_eq!(encode(algorithm, 1024, &[1, 2, 3]), vec![0, 1, 2, 3]);
+        }
+        assert!(decode(&[], 10).is_err());
+        assert!(decode(&[3, 0, 0], 10).is_err());
+        assert!(decode(&[1, 0], 10).is_err());
+    }
+}
@@ -0,0 +1,379 @@
+//! Keeping the connections to the members alive.
+//!
+//! The requests to the peers are not tied to long-lived connections, so a peer that went away
+//! is noticed only when a request to it fails, and comes back only through a discovery round.
+//! A [`ConnectionManager`] instead keeps track of every current member: it pings the connected ones
+//! periodically, and retries the disconnected ones with an exponential backoff,
+//! jittered so that the members don't retry a peer in lockstep after it comes back.
+use super::*;
+use parking_lot::Mutex;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// The protocol identifier of the keep-alive pings, in `Peer::ports`.
+pub const KEEP_ALIVE_PROTOCOL: &str = "keep-alive";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct KeepAliveConfig {
+    /// How often a connected peer is pinged, in milliseconds.
+    pub ping_interval_ms: u64,
+    /// The backoff after the first failure, which doubles on each subsequent failure.
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// The fraction of the backoff (from `0.0` to `1.0`) by which it's randomly shortened or lengthened.
+    pub jitter: f64,
+}
+
+impl Default for KeepAliveConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval_ms: 15 * 1000,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 60 * 1000,
+            jitter: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Not tried yet.
+    Connecting,
+    Connected {
+        since: Timestamp,
+        last_ping: Timestamp,
+    },
+    Disconnected {
+        /// The consecutive failures to reach the peer.
+        failures: u32,
+        retry_at: Timestamp,
+        /// Why the last attempt failed.
+        error: String,
+    },
+}
+
+/// The connection states of the members, which can be shared by multiple components of a node.
+#[derive(Debug)]
+pub struct ConnectionManager {
+    config: KeepAliveConfig,
+    states: Mutex<HashMap<PublicKey, ConnectionState>>,
+    rng: Mutex<StdRng>,
+}
+
+impl ConnectionManager {
+    pub fn new(config: KeepAliveConfig) -> Self {
+        Self::with_rng(config, StdRng::from_entropy())
+    }
+
+    /// Creates a manager whose jitter is reproduced with the same seed.
+    pub fn with_seed(config: KeepAliveConfig, seed: u64) -> Self {
+        Self::with_rng(config, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(config: KeepAliveConfig, rng: StdRng) -> Self {
+        Self {
+            config,
+            states: Default::default(),
+            rng: Mutex::new(rng),
+        }
+    }
+
+    /// Tracks exactly the given members, returning the ones due for a ping or a reconnection at `now`.
+    pub fn due_members(&self, members: &[PublicKey], now: Timestamp) -> Vec<PublicKey> {
+        let mut states = self.states.lock();
+        states.retain(|key, _| members.contains(key));
+        members
+            .iter()
+            .filter(|member| {
+                match states
+                    .entry((*member).clone())
+                    .or_insert(ConnectionState::Connecting)
+                {
+                    ConnectionState::Connecting => true,
+                    ConnectionState::Connected { last_ping, .. } => {
+                        now.saturating_sub(*last_ping) >= self.config.ping_interval_ms as Timestamp
+                    }
+                    ConnectionState::Disconnected { retry_at, .. } => *retry_at <= now,
+                }
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Records the result of a ping (or a reconnection) to the peer.
+    pub fn record(&self, peer: &PublicKey, result: Result<(), String>, now: Timestamp) {
+        let mut states = self.states.lock();
+        let state = states
+            .entry(peer.clone())
+            .or_insert(ConnectionState::Connecting);
+        *state = match (result, &*state) {
+            (Ok(()), ConnectionState::Connected { since, .. }) => ConnectionState::Connected {
+                since: *since,
+                last_ping: now,
+            },
+            (Ok(()), _) => {
+                log::info!("connected to {peer}");
+                ConnectionState::Connected {
+                    since: now,
+                    last_ping: now,
+                }
+            }
+            (Err(error), state) => {
+                let failures = match state {
+                    ConnectionState::Disconnected { failures, .. } => failures + 1,
+                    _ => {
+                        log::info!("disconnected from {peer}: {error}");
+                        1
+                    }
+                };
+                ConnectionState::Disconnected {
+                    failures,
+                    retry_at: now + self.backoff(failures) as Timestamp,
+                    error,
+                }
+            }
+        };
+    }
+
+    /// The jittered backoff after the given number of consecutive failures.
+    fn backoff(&self, failures: u32) -> u64 {
+        let backoff = self
+            .config
+            .initial_backoff_ms
+            .saturating_mul(1 << (failures - 1).min(32))
+            .min(self.config.max_backoff_ms);
+        let jitter = self.config.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return backoff;
+        }
+        let factor = self.rng.lock().gen_range(1.0 - jitter..=1.0 + jitter);
+        (backoff as f64 * factor) as u64
+    }
+
+    pub fn states(&self) -> HashMap<PublicKey, ConnectionState> {
+        self.states.lock().clone()
+    }
+
+    pub fn connected_members(&self) -> Vec<PublicKey> {
+        self.states
+            .lock()
+            .iter()
+            .filter(|(_, state)| matches!(state, ConnectionState::Connected { .. }))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+}
+
+/// Answers the keep-alive pings from the other members.
+pub async fn serve_keep_alive<P: RpcPrimitive>(
+    rpc: &P,
+    port: u16,
+) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error> {
+    rpc.serve_protocol(KEEP_ALIVE_PROTOCOL, port, |_: ()| async move { Ok(()) })
+        .await
+}
+
+/// Keeps the connections to the current members (except this node) indefinitely,
+/// checking which are due every `tick_ms`.
+///
+/// A member that is not among the known peers counts as a failure, to be retried after the backoff.
+pub async fn run_connection_manager<P: RpcPrimitive>(
+    rpc: Arc<P>,
+    manager: Arc<ConnectionManager>,
+    network_config: NetworkConfig,
+    members: SharedMembers,
+    known_peers: SharedKnownPeers,
+    tick_ms: u64,
+) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_millis(tick_ms));
+    loop {
+        interval.tick().await;
+        let members = members
+            .read()
+            .into_iter()
+            .filter(|x| *x != network_config.public_key)
+            .collect::<Vec<_>>();
+        let now = chrono::Utc::now().timestamp_millis() as Timestamp;
+        let due = manager.due_members(&members, now);
+        if due.is_empty() {
+            continue;
+        }
+        let peers = known_peers.read().await;
+        let pings = due.into_iter().map(|member| {
+            let peer = peers.iter().find(|x| x.public_key == member).cloned();
+            let rpc = Arc::clone(&rpc);
+            async move {
+                let result = match peer {
+                    Some(peer) => rpc
+                        .request::<(), ()>(&peer, KEEP_ALIVE_PROTOCOL, ())
+                        .await
+                        .map_err(|e| e.to_string()),
+                    None => Err("unknown address".to_owned()),
+                };
+                (member, result)
+            }
+        });
+        for (member, result) in futures::future::join_all(pings).await {
+            let now = chrono::Utc::now().timestamp_millis() as Timestamp;
+            manager.record(&member, result, now);
+        }
+    }
+}
+
+/// Spawns [`run_connection_manager`] as a task.
+pub fn spawn_connection_manager<P: RpcPrimitive>(
+    rpc: Arc<P>,
+    manager: Arc<ConnectionManager>,
+    network_config: NetworkConfig,
+    members: SharedMembers,
+    known_peers: SharedKnownPeers,
+    tick_ms: u64,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(run_connection_manager(
+        rpc,
+        manager,
+        network_config,
+        members,
+        known_peers,
+        tick_ms,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::{RpcConfig, TcpRpc};
+    use simperby_test_suite::*;
+
+    fn config(jitter: f64) -> KeepAliveConfig {
+        KeepAliveConfig {
+            ping_interval_ms: 100,
+            initial_backoff_ms: 10,
+            max_backoff_ms: 50,
+            jitter,
+        }
+    }
+
+    #[test]
+    fn states() {
+        setup_test();
+        let manager = ConnectionManager::new(config(0.0));
+        let (a, b) = (generate_keypair("a").0, generate_keypair("b").0);
+        let members = vec![a.clone(), b.clone()];
+        assert_eq!(manager.due_members(&members, 0), members);
+
+        manager.record(&a, Ok(()), 0);
+        manager.record(&b, Err("refused".to_owned()), 0);
+        assert_eq!(manager.connected_members(), vec![a.clone()]);
+        assert!(manager.due_members(&members, 9).is_empty());
+        assert_eq!(manager.due_members(&members, 10), vec![b.clone()]);
+        assert_eq!(manager.due_members(&members, 100), members);
+
+        // The backoff doubles up to the limit.
+        for (failures, retry_at) in [(2, 20), (3, 40), (4, 50), (5, 50)] {
+            manager.record(&b, Err("refused".to_owned()), 0);
+            assert!(matches!(
+                manager.states()[&b],
+                ConnectionState::Disconnected { failures: f, retry_at: r, .. }
+                    if f == failures && r == retry_at
+            ));
+        }
+        manager.record(&b, Ok(()), 100);
+        manager.record(&b, Ok(()), 200);
+        assert_eq!(
+            manager.states()[&b],
+            ConnectionState::Connected {
+                since: 100,
+                last_ping: 200
+            }
+        );
+
+        // No longer a member.
+        manager.due_members(&[a], 300);
+        assert_eq!(manager.states().len(), 1);
+    }
+
+    #[test]
+    fn jitter() {
+        setup_test();
+        let retry_ats = |seed| {
+            let manager = ConnectionManager::with_seed(config(0.5), seed);
+            (0..20)
+                .map(|i| {
+                    let peer = generate_keypair(format!("jitter-{i}")).0;
+                    manager.record(&peer, Err("refused".to_owned()), 0);
+                    match &manager.states()[&peer] {
+                        ConnectionState::Disconnected { retry_at, .. } => *retry_at,
+                        _ => unreachable!(),
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        let x = retry_ats(1);
+        assert!(x.iter().all(|x| (5..=15).contains(x)));
+        assert!(x.iter().any(|y| *y != x[0]));
+        assert_eq!(retry_ats(1), x);
+    }
+
+    #[tokio::test]
+    async fn keep_alive() {
+        setup_test();
+        let port = dispense_port();
+        let rpc = Arc::new(TcpRpc::new(RpcConfig {
+            timeout_ms: 500,
+            ..Default::default()
+        }));
+        let server = serve_keep_alive(rpc.as_ref(), port).await.unwrap();
+        let (me, private_key) = generate_keypair("me");
+        let (alive, unknown) = (generate_keypair("alive").0, generate_keypair("unknown").0);
+        let network_config = NetworkConfig {
+            network_id: "test".to_owned(),
+            ports: HashMap::new(),
+            members: Vec::new(),
+            public_key: me.clone(),
+            private_key: Some(private_key),
+            dns_seeds: Vec::new(),
+            enable_mdns: false,
+            pre_shared_key: None,
+            proxy: None,
+            denied_peers: Vec::new(),
+            allowed_peers: Vec::new(),
+        };
+        let known_peers = SharedKnownPeers::new_static(vec![Peer {
+            public_key: alive.clone(),
+            name: "alive".to_owned(),
+            address: "127.0.0.1:1".parse().unwrap(),
+            addresses: Vec::new(),
+            ports: vec![(KEEP_ALIVE_PROTOCOL.to_owned(), port)]
+                .into_iter()
+                .collect(),
+            metadata: Default::default(),
+            recently_seen_timestamp: 0,
+        }]);
+        let manager = Arc::new(ConnectionManager::new(config(0.2)));
+        let task = spawn_connection_manager(
+            rpc,
+            Arc::clone(&manager),
+            network_config,
+            SharedMembers::new(vec![me, alive.clone(), unknown.clone()]),
+            known_peers,
+            10,
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        let states = manager.states();
+        assert_eq!(states.len(), 2);
+        assert!(matches!(states[&alive], ConnectionState::Connected { .. }));
+        assert!(matches!(
+            &states[&unknown],
+            ConnectionState::Disconnected { failures, .. } if *failures > 1
+        ));
+
+        server.abort();
+        let _ = server.await;
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        assert!(matches!(
+            manager.states()[&alive],
+            ConnectionState::Disconnected { .. }
+        ));
+        task.abort();
+    }
+}
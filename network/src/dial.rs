@@ -0,0 +1,204 @@
+//! Limits on dialing the peers.
+//!
+//! With a large member set, dialing every peer at once in each round (e.g., a fetch) would exhaust
+//! the connections. A [`Dialer`] bounds the number of peers per round and the concurrent dials,
+//! keeps the overflow peers pending for the next rounds, and backs off exponentially from the peers
+//! that keep failing.
+use super::*;
+use parking_lot::Mutex;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DialConfig {
+    /// The maximum number of the peers to dial in a round. `None` means unlimited.
+    ///
+    /// The peers beyond it are pending, coming first in the next rounds.
+    pub max_peers_per_round: Option<usize>,
+    /// The maximum number of the peers in the same /16 subnet to dial in a round,
+    /// against the eclipse attacks (see [`crate::eclipse`]). `None` means unlimited.
+    ///
+    /// The peers beyond it are pending like the ones beyond `max_peers_per_round`.
+    #[serde(default)]
+    pub max_peers_per_subnet: Option<usize>,
+    /// The maximum number of the dials in progress at once.
+    pub max_concurrent_dials: usize,
+    /// The backoff after the first failure, which doubles on each subsequent failure.
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for DialConfig {
+    fn default() -> Self {
+        Self {
+            max_peers_per_round: None,
+            max_peers_per_subnet: None,
+            max_concurrent_dials: 16,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 10 * 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct DialState {
+    consecutive_failures: u32,
+    retry_at: Timestamp,
+    last_dialed: Timestamp,
+}
+
+#[derive(Debug)]
+pub struct Dialer {
+    config: DialConfig,
+    semaphore: Arc<Semaphore>,
+    states: Mutex<HashMap<PublicKey, DialState>>,
+}
+
+impl Dialer {
+    pub fn new(config: DialConfig) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(config.max_concurrent_dials.max(1))),
+            config,
+            states: Default::default(),
+        }
+    }
+
+    /// Picks the peers to dial in this round, marking them as dialed.
+    ///
+    /// The peers in backoff are skipped, and the least recently dialed ones come first.
+    pub fn schedule(&self, peers: Vec<Peer>, now: Timestamp) -> Vec<Peer> {
+        let mut states = self.states.lock();
+        let mut peers = peers
+            .into_iter()
+            .filter(|peer| {
+                states
+                    .get(&peer.public_key)
+                    .map_or(true, |x| x.retry_at <= now)
+            })
+            .collect::<Vec<_>>();
+        peers.sort_by_key(|peer| states.get(&peer.public_key).map_or(0, |x| x.last_dialed));
+        if let Some(max) = self.config.max_peers_per_subnet {
+            peers = eclipse::diversify(peers, max);
+        }
+        if let Some(max) = self.config.max_peers_per_round {
+            peers.truncate(max);
+        }
+        for peer in &peers {
+            states
+                .entry(peer.public_key.clone())
+                .or_default()
+                .last_dialed = now;
+        }
+        peers
+    }
+
+    /// Waits until a dial can be started, holding the slot until the permit is dropped.
+    pub async fn permit(&self) -> OwnedSemaphorePermit {
+        Arc::clone(&self.semaphore)
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed")
+    }
+
+    /// Records the result of a dial, putting the peer into backoff if it failed.
+    pub fn record(&self, peer: &PublicKey, success: bool, now: Timestamp) {
+        let mut states = self.states.lock();
+        let state = states.entry(peer.clone()).or_default();
+        if success {
+            state.consecutive_failures = 0;
+            state.retry_at = 0;
+        } else {
+            let backoff = self
+                .config
+                .initial_backoff_ms
+                .saturating_mul(1 << state.consecutive_failures.min(32))
+                .min(self.config.max_backoff_ms);
+            state.consecutive_failures += 1;
+            state.retry_at = now + backoff as Timestamp;
+        }
+    }
+
+    /// Returns whether the peer is in backoff at `now`.
+    pub fn is_backing_off(&self, peer: &PublicKey, now: Timestamp) -> bool {
+        self.states
+            .lock()
+            .get(peer)
+            .map_or(false, |x| x.retry_at > now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    fn peers(n: usize) -> Vec<Peer> {
+        (0..n)
+            .map(|i| Peer {
+                public_key: generate_keypair(format!("dial-{i}")).0,
+                name: format!("{i}"),
+                address: "127.0.0.1:1".parse().unwrap(),
+                addresses: Vec::new(),
+                ports: HashMap::new(),
+                metadata: Default::default(),
+                recently_seen_timestamp: 0,
+            })
+            .collect()
+    }
+
+    fn names(peers: &[Peer]) -> Vec<&str> {
+        peers.iter().map(|x| x.name.as_str()).collect()
+    }
+
+    #[test]
+    fn pending_peers_come_first() {
+        setup_test();
+        let dialer = Dialer::new(DialConfig {
+            max_peers_per_round: Some(2),
+            ..Default::default()
+        });
+        let peers = peers(5);
+        assert_eq!(names(&dialer.schedule(peers.clone(), 1)), ["0", "1"]);
+        assert_eq!(names(&dialer.schedule(peers.clone(), 2)), ["2", "3"]);
+        assert_eq!(names(&dialer.schedule(peers.clone(), 3)), ["4", "0"]);
+        assert_eq!(names(&dialer.schedule(peers, 4)), ["1", "2"]);
+    }
+
+    #[test]
+    fn subnet_diversity() {
+        setup_test();
+        let dialer = Dialer::new(DialConfig {
+            max_peers_per_subnet: Some(1),
+            ..Default::default()
+        });
+        let mut peers = peers(3);
+        peers[2].address = "10.0.0.1:1".parse().unwrap();
+        assert_eq!(names(&dialer.schedule(peers.clone(), 1)), ["0", "2"]);
+        assert_eq!(names(&dialer.schedule(peers, 2)), ["1", "2"]);
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        setup_test();
+        let dialer = Dialer::new(DialConfig {
+            initial_backoff_ms: 100,
+            max_backoff_ms: 300,
+            ..Default::default()
+        });
+        let peers = peers(2);
+        let key = &peers[0].public_key;
+        dialer.record(key, false, 0);
+        assert_eq!(names(&dialer.schedule(peers.clone(), 99)), ["1"]);
+        assert_eq!(dialer.schedule(peers.clone(), 100).len(), 2);
+        dialer.record(key, false, 100);
+        assert!(dialer.is_backing_off(key, 299));
+        assert!(!dialer.is_backing_off(key, 300));
+        // Capped by `max_backoff_ms`.
+        dialer.record(key, false, 300);
+        dialer.record(key, false, 300);
+        assert!(!dialer.is_backing_off(key, 600));
+        dialer.record(key, true, 600);
+        assert!(!dialer.is_backing_off(key, 600));
+        dialer.record(key, false, 600);
+        assert!(!dialer.is_backing_off(key, 700));
+    }
+}
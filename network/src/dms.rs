@@ -1,3 +1,16 @@
+use super::auth_events::*;
+use super::bandwidth::*;
+use super::chunking::*;
+use super::dial::*;
+use super::handshake::*;
+use super::latency::*;
+use super::limits::*;
+use super::metrics::*;
+use super::mux::{MuxRpc, MUX_PROTOCOL};
+use super::priority::*;
+use super::scoring::*;
+use super::seen_cache::*;
+use super::telemetry::*;
 use super::Storage;
 use super::*;
 use async_trait::async_trait;
@@ -6,12 +19,15 @@ use futures::prelude::*;
 use serde_tc::http::*;
 use serde_tc::{serde_tc_full, StubCall};
 use simperby_common::*;
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::RwLock;
+use tracing::Instrument;
 
 const STATE_FILE_PATH: &str = "_state.json";
+/// The maximum number of the cancellations to remember.
+const MAX_CANCELLATIONS: usize = 4096;
 type DmsKey = String;
 
 #[derive(Debug, Clone, Serialize)]
@@ -41,6 +57,22 @@ impl ToHash256 for Message {
     }
 }
 
+/// Returns the size of the message in the storage.
+fn message_size(message: &Message) -> u64 {
+    serde_spb::to_string(message).unwrap().len() as u64
+}
+
+fn message_file_name(message: &Message) -> String {
+    format!("{}.json", message.to_hash256())
+}
+
+/// Checks whether the message is already stored, so that it is not admitted twice.
+///
+/// The files are named after the hashes of the messages, so the stored one is the same.
+async fn is_stored(storage: &impl Storage, message: &Message) -> bool {
+    storage.read_file(&message_file_name(message)).await.is_ok()
+}
+
 /// Decides whether a message should be accepted or not.
 pub trait MessageFilter: Send + Sync + 'static {
     fn filter(&self, message: &Message) -> Result<(), String>;
@@ -66,6 +98,41 @@ impl RawMessage {
     }
 }
 
+/// How far a message has been delivered to the other members by `broadcast_all()`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BroadcastStatus {
+    /// The members that have acknowledged the message.
+    pub acked: HashSet<PublicKey>,
+    /// The number of the members other than this node.
+    pub total: usize,
+}
+
+impl BroadcastStatus {
+    pub fn completion_percentage(&self) -> f64 {
+        if self.total == 0 {
+            return 100.0;
+        }
+        self.acked.len() as f64 / self.total as f64 * 100.0
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.acked.len() >= self.total
+    }
+}
+
+/// A request to drop a message, which must be signed by the signer of the message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cancellation {
+    pub dms_key: DmsKey,
+    pub message_hash: Hash256,
+}
+
+impl ToHash256 for Cancellation {
+    fn to_hash256(&self) -> Hash256 {
+        Hash256::hash(serde_spb::to_vec(self).unwrap())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct State {
     pub dms_key: DmsKey,
@@ -83,11 +150,61 @@ trait DistributedMessageSetRpcInterface: Send + Sync + 'static {
 
     /// Requests this node to accept a new message.
     async fn add_messages(&self, dms_key: DmsKey, messages: Vec<RawMessage>) -> Result<(), String>;
+
+    /// Requests this node to drop the messages, and not to accept them again.
+    async fn cancel_messages(
+        &self,
+        dms_key: DmsKey,
+        cancellations: Vec<(Cancellation, TypedSignature<Cancellation>)>,
+    ) -> Result<(), String>;
+
+    /// Returns the network version of this node.
+    async fn get_version(&self) -> Result<NetworkVersion, String>;
 }
 
 struct DmsWrapper<N: GossipNetwork, S: Storage> {
     #[allow(clippy::type_complexity)]
     dms: Arc<parking_lot::RwLock<Option<Arc<RwLock<DistributedMessageSet<N, S>>>>>>,
+    /// Notified whenever an RPC releases the DMS.
+    released: Arc<tokio::sync::Notify>,
+}
+
+impl<N: GossipNetwork, S: Storage> DmsWrapper<N, S> {
+    /// Holds the DMS while handling an RPC, unless the server has been terminated.
+    fn lease(&self) -> Result<Lease<N, S>, String> {
+        let dms = Arc::clone(
+            self.dms
+                .read()
+                .as_ref()
+                .ok_or_else(|| "server terminated".to_owned())?,
+        );
+        Ok(Lease {
+            dms: Some(dms),
+            released: Arc::clone(&self.released),
+        })
+    }
+}
+
+/// The DMS held by an RPC being handled, which notifies `serve()` when released.
+struct Lease<N, S> {
+    dms: Option<Arc<RwLock<DistributedMessageSet<N, S>>>>,
+    released: Arc<tokio::sync::Notify>,
+}
+
+impl<N, S> std::ops::Deref for Lease<N, S> {
+    type Target = RwLock<DistributedMessageSet<N, S>>;
+
+    fn deref(&self) -> &Self::Target {
+        self.dms.as_ref().expect("held until dropped")
+    }
+}
+
+impl<N, S> Drop for Lease<N, S> {
+    fn drop(&mut self) {
+        // The DMS must be released before `serve()` wakes up to take it back.
+        self.dms.take();
+        self.released.notify_one();
+    }
 }
 
 #[async_trait]
@@ -97,12 +214,7 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSetRpcInterface for DmsWrap
         dms_key: DmsKey,
         knowns: Vec<Hash256>,
     ) -> Result<Vec<RawMessage>, String> {
-        let dms = Arc::clone(
-            self.dms
-                .read()
-                .as_ref()
-                .ok_or_else(|| "server terminated".to_owned())?,
-        );
+        let dms = self.lease()?;
         let mut messages = dms
             .read()
             .await
@@ -123,27 +235,311 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSetRpcInterface for DmsWrap
     }
 
     async fn add_messages(&self, dms_key: DmsKey, messages: Vec<RawMessage>) -> Result<(), String> {
-        let dms = Arc::clone(
-            self.dms
-                .read()
-                .as_ref()
-                .ok_or_else(|| "server terminated".to_owned())?,
-        );
+        let dms = self.lease()?;
         let dms_key_ = dms.read().await.key.clone();
         if dms_key != dms.read().await.key {
             return Err(format!("key mismatch: requested {dms_key}, but {dms_key_}"));
         }
+        let guard = Arc::clone(&dms.read().await.guard);
+        let cancelled = Arc::clone(&dms.read().await.cancelled);
         for message in messages {
             let message = message.into_message().map_err(|e| e.to_string())?;
-            DistributedMessageSet::<N, S>::add_message_but_not_broadcast(
-                &mut (*dms.write().await.storage.write().await),
-                message,
-            )
-            .await
-            .map_err(|e| e.to_string())?;
+            if is_cancelled(&cancelled, &message) {
+                continue;
+            }
+            let storage = Arc::clone(&dms.read().await.storage);
+            let mut storage = storage.write().await;
+            if is_stored(&*storage, &message).await {
+                continue;
+            }
+            guard
+                .admit(message_size(&message), false)
+                .map_err(|e| e.to_string())?;
+            DistributedMessageSet::<N, S>::add_message_but_not_broadcast(&mut *storage, message)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    async fn cancel_messages(
+        &self,
+        dms_key: DmsKey,
+        cancellations: Vec<(Cancellation, TypedSignature<Cancellation>)>,
+    ) -> Result<(), String> {
+        let dms = self.lease()?;
+        let dms_key_ = dms.read().await.key.clone();
+        if dms_key != dms_key_ {
+            return Err(format!("key mismatch: requested {dms_key}, but {dms_key_}"));
+        }
+        for (cancellation, signature) in cancellations {
+            if cancellation.dms_key != dms_key_ {
+                return Err(format!(
+                    "key mismatch: cancellation for {}, but {dms_key_}",
+                    cancellation.dms_key
+                ));
+            }
+            signature
+                .verify(&cancellation)
+                .map_err(|e| format!("invalid cancellation: {e}"))?;
+            if !dms.read().await.members.read().contains(signature.signer()) {
+                return Err(format!(
+                    "cancellation signed by a non-member {}",
+                    signature.signer()
+                ));
+            }
+            dms.read()
+                .await
+                .drop_message(&cancellation.message_hash, signature.signer())
+                .await
+                .map_err(|e| e.to_string())?;
         }
         Ok(())
     }
+
+    async fn get_version(&self) -> Result<NetworkVersion, String> {
+        let dms = self.lease()?;
+        let network_id = dms.read().await.config.network_config.network_id.clone();
+        Ok(NetworkVersion::current(&network_id))
+    }
+}
+
+/// A request of [`DistributedMessageSetRpcInterface`] over a multiplexed connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum DmsRequest {
+    GetMessage {
+        dms_key: DmsKey,
+        knowns: Vec<Hash256>,
+    },
+    AddMessages {
+        dms_key: DmsKey,
+        messages: Vec<RawMessage>,
+    },
+    CancelMessages {
+        dms_key: DmsKey,
+        cancellations: Vec<(Cancellation, TypedSignature<Cancellation>)>,
+    },
+    GetVersion,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum DmsResponse {
+    Messages(Vec<RawMessage>),
+    Done,
+    Version(NetworkVersion),
+}
+
+/// A reply of a peer that doesn't decode as the response to the request.
+#[derive(Debug)]
+struct UnexpectedResponse(DmsResponse);
+
+impl std::fmt::Display for UnexpectedResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unexpected response: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnexpectedResponse {}
+
+impl<N: GossipNetwork, S: Storage> DmsWrapper<N, S> {
+    async fn handle(&self, request: DmsRequest) -> Result<DmsResponse, String> {
+        match request {
+            DmsRequest::GetMessage { dms_key, knowns } => self
+                .get_message(dms_key, knowns)
+                .await
+                .map(DmsResponse::Messages),
+            DmsRequest::AddMessages { dms_key, messages } => self
+                .add_messages(dms_key, messages)
+                .await
+                .map(|_| DmsResponse::Done),
+            DmsRequest::CancelMessages {
+                dms_key,
+                cancellations,
+            } => self
+                .cancel_messages(dms_key, cancellations)
+                .await
+                .map(|_| DmsResponse::Done),
+            DmsRequest::GetVersion => self.get_version().await.map(DmsResponse::Version),
+        }
+    }
+}
+
+/// The client of the RPCs to the DMS of a peer.
+enum DmsClient {
+    /// Over the HTTP server on the port of the DMS (`dms-<key>`).
+    Http(DistributedMessageSetRpcInterfaceStub),
+    /// Over the multiplexed connection to the peer, with the DMS as the protocol.
+    Mux {
+        rpc: Arc<MuxRpc>,
+        peer: Box<Peer>,
+        protocol: String,
+    },
+}
+
+impl DmsClient {
+    async fn get_message(
+        &self,
+        dms_key: DmsKey,
+        knowns: Vec<Hash256>,
+    ) -> Result<Vec<RawMessage>, Error> {
+        match self {
+            DmsClient::Http(stub) => stub
+                .get_message(dms_key, knowns)
+                .await
+                .map_err(|e| eyre!("{}", e))?
+                .map_err(|e| eyre!(e)),
+            DmsClient::Mux {
+                rpc,
+                peer,
+                protocol,
+            } => match rpc
+                .request(peer, protocol, DmsRequest::GetMessage { dms_key, knowns })
+                .await?
+            {
+                DmsResponse::Messages(messages) => Ok(messages),
+                response => Err(UnexpectedResponse(response).into()),
+            },
+        }
+    }
+
+    async fn add_messages(&self, dms_key: DmsKey, messages: Vec<RawMessage>) -> Result<(), Error> {
+        match self {
+            DmsClient::Http(stub) => stub
+                .add_messages(dms_key, messages)
+                .await
+                .map_err(|e| eyre!("{}", e))?
+                .map_err(|e| eyre!(e)),
+            DmsClient::Mux {
+                rpc,
+                peer,
+                protocol,
+            } => match rpc
+                .request(
+                    peer,
+                    protocol,
+                    DmsRequest::AddMessages { dms_key, messages },
+                )
+                .await?
+            {
+                DmsResponse::Done => Ok(()),
+                response => Err(UnexpectedResponse(response).into()),
+            },
+        }
+    }
+
+    async fn cancel_messages(
+        &self,
+        dms_key: DmsKey,
+        cancellations: Vec<(Cancellation, TypedSignature<Cancellation>)>,
+    ) -> Result<(), Error> {
+        match self {
+            DmsClient::Http(stub) => stub
+                .cancel_messages(dms_key, cancellations)
+                .await
+                .map_err(|e| eyre!("{}", e))?
+                .map_err(|e| eyre!(e)),
+            DmsClient::Mux {
+                rpc,
+                peer,
+                protocol,
+            } => {
+                let request = DmsRequest::CancelMessages {
+                    dms_key,
+                    cancellations,
+                };
+                match rpc.request(peer, protocol, request).await? {
+                    DmsResponse::Done => Ok(()),
+                    response => Err(UnexpectedResponse(response).into()),
+                }
+            }
+        }
+    }
+
+    async fn get_version(&self) -> Result<NetworkVersion, Error> {
+        match self {
+            DmsClient::Http(stub) => stub
+                .get_version()
+                .await
+                .map_err(|e| eyre!("{}", e))?
+                .map_err(|e| eyre!(e)),
+            DmsClient::Mux {
+                rpc,
+                peer,
+                protocol,
+            } => match rpc.request(peer, protocol, DmsRequest::GetVersion).await? {
+                DmsResponse::Version(version) => Ok(version),
+                response => Err(UnexpectedResponse(response).into()),
+            },
+        }
+    }
+}
+
+fn now() -> Timestamp {
+    chrono::Utc::now().timestamp_millis() as Timestamp
+}
+
+/// Exchanges with the peer under the dial limits, recording whether it succeeded.
+async fn dial<T>(
+    dialer: &Dialer,
+    metrics: &NetworkMetrics,
+    peer: PublicKey,
+    exchange: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    let _permit = dialer.permit().await;
+    let result = exchange.await;
+    dialer.record(&peer, result.is_ok(), now());
+    metrics.record_dial(&peer, result.is_ok());
+    result
+}
+
+/// The cancelled messages with their signers, forgetting the oldest ones
+/// beyond [`MAX_CANCELLATIONS`].
+#[derive(Debug, Default)]
+struct Cancellations {
+    entries: HashSet<(Hash256, PublicKey)>,
+    order: VecDeque<(Hash256, PublicKey)>,
+}
+
+impl Cancellations {
+    fn insert(&mut self, entry: (Hash256, PublicKey)) {
+        if !self.entries.insert(entry.clone()) {
+            return;
+        }
+        self.order.push_back(entry);
+        if self.order.len() > MAX_CANCELLATIONS {
+            let oldest = self.order.pop_front().expect("not empty");
+            self.entries.remove(&oldest);
+        }
+    }
+
+    fn contains(&self, entry: &(Hash256, PublicKey)) -> bool {
+        self.entries.contains(entry)
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+/// Checks whether the message has been cancelled by its signer.
+fn is_cancelled(cancelled: &parking_lot::RwLock<Cancellations>, message: &Message) -> bool {
+    cancelled
+        .read()
+        .contains(&(message.to_hash256(), message.signature.signer().clone()))
+}
+
+/// Waits until a peer is added, or until some changes are missed (which may include one).
+async fn peer_added(events: &mut tokio::sync::broadcast::Receiver<PeerEvent>) {
+    use tokio::sync::broadcast::error::RecvError;
+    loop {
+        match events.recv().await {
+            Ok(PeerEvent::Added(_)) | Err(RecvError::Lagged(_)) => return,
+            Ok(_) => {}
+            // Never, as the known peers outlive the subscription.
+            Err(RecvError::Closed) => futures::future::pending::<()>().await,
+        }
+    }
 }
 
 struct DummyFilter;
@@ -172,7 +568,31 @@ pub struct DistributedMessageSet<N, S> {
     filter: Arc<dyn MessageFilter>,
     peers: SharedKnownPeers,
     key: DmsKey,
-    _marker: std::marker::PhantomData<N>,
+    guard: Arc<ResourceGuard>,
+    audit_log: Option<Arc<audit::SigningAuditLog>>,
+    /// The members that have acknowledged each message, by the message hash.
+    acks: Arc<parking_lot::RwLock<HashMap<Hash256, HashSet<PublicKey>>>>,
+    /// The hashes of the cancelled messages, with their signers.
+    ///
+    /// A cancelled message is not accepted again.
+    cancelled: Arc<parking_lot::RwLock<Cancellations>>,
+    scores: Arc<PeerScoreBoard>,
+    dialer: Dialer,
+    bandwidth: Arc<BandwidthMeter>,
+    handshakes: Arc<HandshakeTable>,
+    latencies: Arc<LatencyTable>,
+    /// Initially `network_config.members`, but can be updated while serving.
+    members: SharedMembers,
+    /// The client of the RPCs to the peers, which goes through `network_config.proxy` if any.
+    http: reqwest::Client,
+    metrics: Arc<NetworkMetrics>,
+    /// Schedules the broadcasts over the gossip network with those of the other DMSs, if set.
+    broadcaster: Option<Arc<PriorityBroadcaster<N>>>,
+    auth_events: AuthEvents,
+    /// Makes the RPCs over the multiplexed connections, if set.
+    mux: Option<Arc<MuxRpc>>,
+    /// Signs the cancellations; initially of `network_config.private_key`, if any.
+    signer: Option<Arc<dyn Signer>>,
 }
 
 impl<N, S> std::fmt::Debug for DistributedMessageSet<N, S> {
@@ -189,6 +609,27 @@ pub struct Config {
     pub broadcast_interval: Option<Duration>,
     /// The interval of the direct-peer fetch. If none, it will fetch only in `fetch()`, not in `serve()`.
     pub fetch_interval: Option<Duration>,
+    /// The limits on the storage used by the messages.
+    ///
+    /// Messages from the peers are rejected and the fetch is paused once they are reached,
+    /// while the messages added by this node are always accepted.
+    #[serde(default)]
+    pub limits: ResourceLimits,
+    /// The limits on dialing the peers in the fetches and the broadcasts.
+    #[serde(default)]
+    pub dial: DialConfig,
+    /// The chunking of the messages broadcasted over the gossip network.
+    #[serde(default)]
+    pub chunking: ChunkConfig,
+    /// The tokens of the broadcasts received over the gossip network, to drop the duplicates.
+    #[serde(default)]
+    pub seen_cache: SeenCacheConfig,
+    /// The lane of the broadcasts over the gossip network, if there's a broadcaster.
+    #[serde(default)]
+    pub priority: Priority,
+    /// The subscriber installed in `serve()`, unless the application has installed one already.
+    #[serde(default)]
+    pub tracing: Option<TracingConfig>,
 }
 
 impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
@@ -217,22 +658,56 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
                 Self::write_state(&mut storage, State { dms_key }).await?;
             }
         };
+        let mut stored_bytes = 0;
+        for file in storage.list_files().await? {
+            if file != STATE_FILE_PATH {
+                stored_bytes += storage.read_file(&file).await?.len() as u64;
+            }
+        }
+        let guard = Arc::new(ResourceGuard::new(config.limits.clone(), stored_bytes));
+        let dialer = Dialer::new(config.dial.clone());
+        let members = SharedMembers::new(config.network_config.members.clone());
+        let http = proxy::http_client(config.network_config.proxy.as_ref())?;
+        let signer = config.network_config.signer();
         Ok(Self {
             storage: Arc::new(RwLock::new(storage)),
             config,
             filter: Arc::new(DummyFilter),
             peers,
             key: dms_key_,
-            _marker: std::marker::PhantomData,
+            guard,
+            audit_log: None,
+            acks: Default::default(),
+            cancelled: Default::default(),
+            scores: Default::default(),
+            dialer,
+            bandwidth: Default::default(),
+            handshakes: Default::default(),
+            latencies: Default::default(),
+            members,
+            http,
+            metrics: Default::default(),
+            broadcaster: None,
+            auth_events: Default::default(),
+            mux: None,
+            signer,
         })
     }
 
     pub async fn clear(&mut self, dms_key: DmsKey) -> Result<(), Error> {
         self.storage.write().await.remove_all_files().await?;
         Self::write_state(&mut (*self.storage.write().await), State { dms_key }).await?;
+        self.guard.reset(0);
+        self.acks.write().clear();
+        self.cancelled.write().clear();
         Ok(())
     }
 
+    /// Returns how many times the resource limits have been hit.
+    pub fn limit_metrics(&self) -> LimitMetrics {
+        self.guard.metrics()
+    }
+
     pub fn get_key(&self) -> String {
         self.key.clone()
     }
@@ -241,9 +716,227 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
         self.filter = filter;
     }
 
+    /// Signs with the given signer (e.g., an HSM) instead of `network_config.private_key`.
+    pub fn set_signer(&mut self, signer: Arc<dyn Signer>) {
+        self.signer = Some(signer);
+    }
+
+    /// Records every message signed by this node (i.e., added by `add_message()`) in the log.
+    pub fn set_audit_log(&mut self, audit_log: Arc<audit::SigningAuditLog>) {
+        self.audit_log = Some(audit_log);
+    }
+
+    /// Shares the scores of the peers with the other components (e.g., the other DMSs of the node).
+    pub fn set_peer_scores(&mut self, scores: Arc<PeerScoreBoard>) {
+        self.scores = scores;
+    }
+
+    /// Returns the scores of the peers, where the peers can be banned or unbanned.
+    pub fn peer_scores(&self) -> &Arc<PeerScoreBoard> {
+        &self.scores
+    }
+
+    /// Shares the bandwidth accounting with the other components (e.g., the other DMSs of the node).
+    pub fn set_bandwidth_meter(&mut self, bandwidth: Arc<BandwidthMeter>) {
+        self.bandwidth = bandwidth;
+    }
+
+    /// Returns the bandwidth used with each peer so far.
+    pub fn network_stats(&self) -> NetworkStats {
+        self.bandwidth.stats()
+    }
+
+    /// Shares the handshakes with the other components (e.g., the other DMSs of the node).
+    pub fn set_handshake_table(&mut self, handshakes: Arc<HandshakeTable>) {
+        self.handshakes = handshakes;
+    }
+
+    /// Shares the latencies with the other components (e.g., the other DMSs of the node).
+    pub fn set_latency_table(&mut self, latencies: Arc<LatencyTable>) {
+        self.latencies = latencies;
+    }
+
+    /// Returns the latencies of the peers measured so far.
+    pub fn peer_latencies(&self) -> HashMap<PublicKey, PeerLatency> {
+        self.latencies.latencies()
+    }
+
+    /// Shares the members with the other components (e.g., the other DMSs of the node),
+    /// so that an update is picked up by all of them.
+    pub fn set_shared_members(&mut self, members: SharedMembers) {
+        self.members = members;
+    }
+
+    /// Replaces the members, taking effect even while serving.
+    pub fn update_members(&self, members: Vec<PublicKey>) {
+        self.members.update_members(members);
+    }
+
+    /// Shares the metrics with the other components (e.g., the other DMSs of the node).
+    pub fn set_network_metrics(&mut self, metrics: Arc<NetworkMetrics>) {
+        self.metrics = metrics;
+    }
+
+    /// Broadcasts over the gossip network through the broadcaster, in the lane of `Config::priority`.
+    pub fn set_broadcaster(&mut self, broadcaster: Arc<PriorityBroadcaster<N>>) {
+        self.broadcaster = Some(broadcaster);
+    }
+
+    /// Reports the gossiped messages signed by the non-members to the events,
+    /// shared with the other components (e.g., the RPCs of the node).
+    ///
+    /// Whether such a message is accepted is still up to the filter.
+    pub fn set_auth_events(&mut self, auth_events: AuthEvents) {
+        self.auth_events = auth_events;
+    }
+
+    /// Makes the RPCs to the peers over the multiplexed connections of `mux`
+    /// (with its pre-shared key and member identity), as the protocol `dms-<key>`.
+    ///
+    /// `serve()` then registers the DMS on the port of [`MUX_PROTOCOL`] in `network_config.ports`,
+    /// and the peers that don't multiplex are still reached on the port of the DMS, if any.
+    ///
+    /// Fails if `mux` isn't encrypted with `network_config.pre_shared_key`.
+    pub fn set_mux(&mut self, mux: Arc<MuxRpc>) -> Result<(), Error> {
+        if mux.pre_shared_key() != self.config.network_config.pre_shared_key.as_ref() {
+            return Err(eyre!(
+                "the multiplexed transport doesn't use the pre-shared key of the network"
+            ));
+        }
+        self.mux = Some(mux);
+        Ok(())
+    }
+
+    fn port_key(&self) -> String {
+        format!("dms-{}", self.key)
+    }
+
+    /// Makes the client of the RPCs to the peer, over the multiplexed connection if both sides have it.
+    ///
+    /// With a pre-shared key, the peer must multiplex, as the HTTP server isn't encrypted.
+    fn client(&self, peer: &Peer) -> Result<DmsClient, Error> {
+        if let Some(rpc) = &self.mux {
+            if peer.ports.contains_key(MUX_PROTOCOL) {
+                return Ok(DmsClient::Mux {
+                    rpc: Arc::clone(rpc),
+                    peer: Box::new(peer.clone()),
+                    protocol: self.port_key(),
+                });
+            }
+        }
+        if self.config.network_config.pre_shared_key.is_some() {
+            return Err(eyre!(
+                "the pre-shared key requires a multiplexed connection to {}",
+                peer.public_key
+            ));
+        }
+        let port_key = self.port_key();
+        let port = peer
+            .ports
+            .get(&port_key)
+            .ok_or_else(|| eyre!("can't find port key: {}", port_key))?;
+        Ok(DmsClient::Http(DistributedMessageSetRpcInterfaceStub::new(
+            Box::new(HttpClient::new(
+                format!("{}:{}/dms", peer.address.ip(), port),
+                self.http.clone(),
+            )),
+        )))
+    }
+
+    /// Returns a snapshot of the metrics of the network layer.
+    pub async fn metrics(&self) -> MetricsSnapshot {
+        self.metrics
+            .snapshot(self.peers.len().await, self.bandwidth.stats().total)
+    }
+
+    /// Returns the results of the version handshakes with the peers.
+    pub fn peer_versions(&self) -> HashMap<PublicKey, PeerVersion> {
+        self.handshakes.versions()
+    }
+
+    /// Reads the known peers except the banned, the throttled, the refused
+    /// and the ones denied (or not allowed) by the network config.
+    async fn read_available_peers(&self) -> Vec<Peer> {
+        let now = now();
+        let filter = self.config.network_config.peer_filter();
+        let peers = self
+            .peers
+            .read_filtered(|x| filter.check_peer(x).is_ok())
+            .await;
+        let peers = self.scores.filter_banned(peers, now);
+        self.handshakes
+            .filter_refused(self.bandwidth.filter_throttled(peers, now))
+    }
+
+    /// Performs the version handshakes with the peers that haven't been done recently,
+    /// returning the peers that are not refused.
+    ///
+    /// The version query also serves as the ping, so the peers due for a ping are queried as well,
+    /// measuring their latencies.
+    ///
+    /// The peers that fail to respond are kept, to be handshaked again in the next round.
+    async fn handshake(&self, peers: Vec<Peer>) -> Vec<Peer> {
+        let my_version = NetworkVersion::current(&self.config.network_config.network_id);
+        let tasks = peers
+            .iter()
+            .filter(|peer| {
+                self.handshakes.needs_handshake(&peer.public_key, now())
+                    || self.latencies.needs_ping(&peer.public_key, now())
+            })
+            .map(|peer| async {
+                let start = std::time::Instant::now();
+                let version = async { self.client(peer)?.get_version().await };
+                match version.await {
+                    Ok(version) => {
+                        self.latencies.record(
+                            &peer.public_key,
+                            start.elapsed().as_millis() as u64,
+                            now(),
+                        );
+                        let _ =
+                            self.handshakes
+                                .record(&peer.public_key, &my_version, version, now());
+                    }
+                    Err(e) => log::debug!("handshake with {} failed: {e}", peer.public_key),
+                }
+            });
+        future::join_all(tasks).await;
+        self.handshakes.filter_refused(peers)
+    }
+
+    /// Returns which members have acknowledged the message of the given hash.
+    ///
+    /// A member acknowledges a message when it accepts the message pushed by `broadcast_all()`.
+    pub fn broadcast_status(&self, message_hash: &Hash256) -> BroadcastStatus {
+        let my_key = &self.config.network_config.public_key;
+        let members = &self.members.read();
+        BroadcastStatus {
+            acked: self
+                .acks
+                .read()
+                .get(message_hash)
+                .map(|acked| {
+                    acked
+                        .iter()
+                        .filter(|x| members.contains(x))
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default(),
+            total: members.iter().filter(|member| *member != my_key).count(),
+        }
+    }
+
     /// Fetches unknown messages from the peers using an RPC protocol,
     /// and adds them to the local storage.
     pub async fn fetch(&mut self) -> Result<(), Error> {
+        if self.guard.is_saturated() {
+            log::warn!(
+                "resource limits reached; pausing the fetch for {}",
+                self.key
+            );
+            return Ok(());
+        }
         let mut tasks = Vec::new();
         let messages = self.read_messages().await?;
         let known_messages = messages
@@ -251,40 +944,75 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
             .map(|m| m.to_hash256())
             .collect::<Vec<_>>();
 
-        for peer in self.peers.read().await {
+        let peers = self
+            .handshake(
+                self.dialer
+                    .schedule(self.read_available_peers().await, now()),
+            )
+            .await;
+        for peer in peers.clone() {
+            let public_key = peer.public_key.clone();
             let storage = Arc::clone(&self.storage);
             let filter = Arc::clone(&self.filter);
-            let port_key = format!("dms-{}", self.key);
+            let port_key = self.port_key();
+            let client = self.client(&peer);
             let known_messages_ = known_messages.clone();
             let key = self.key.clone();
+            let guard = Arc::clone(&self.guard);
+            let cancelled = Arc::clone(&self.cancelled);
+            let scores = Arc::clone(&self.scores);
+            let bandwidth = Arc::clone(&self.bandwidth);
+            let span = tracing::debug_span!("fetch", dms = %self.key, peer = %public_key);
+            let (dialer, metrics) = (&self.dialer, &self.metrics);
             let task = async move {
-                let stub = DistributedMessageSetRpcInterfaceStub::new(Box::new(HttpClient::new(
-                    format!(
-                        "{}:{}/dms",
-                        peer.address.ip(),
-                        peer.ports
-                            .get(&port_key)
-                            .ok_or_else(|| eyre!("can't find port key: {}", port_key))?
-                    ),
-                    reqwest::Client::new(),
-                )));
-                let raw_messages = stub
-                    .get_message(key, known_messages_)
-                    .await
-                    .map_err(|e| eyre!("{}", e))?
-                    .map_err(|e| eyre!(e))?;
+                let record = |direction, bytes: usize| {
+                    bandwidth.record(&peer.public_key, &port_key, direction, bytes as u64, now())
+                };
+                // Only the exchange counts as the dial; a peer relaying a message that this node
+                // can't accept yet is still reachable, and mustn't be backed off from.
+                let report = |misbehavior| scores.report(&peer.public_key, misbehavior, now());
+                let raw_messages = dial(dialer, metrics, public_key, async {
+                    let client = client?;
+                    record(
+                        Direction::Egress,
+                        serde_spb::to_vec(&known_messages_)?.len(),
+                    )?;
+                    client.get_message(key, known_messages_).await
+                })
+                .await
+                .inspect_err(|e| {
+                    if e.is::<UnexpectedResponse>() {
+                        report(Misbehavior::InvalidMessage);
+                    }
+                })?;
+                record(Direction::Ingress, serde_spb::to_vec(&raw_messages)?.len())?;
                 let mut storage = storage.write().await;
                 for raw_message in raw_messages {
-                    let message = raw_message.into_message()?;
+                    let message = raw_message.into_message().inspect_err(|_| {
+                        report(Misbehavior::InvalidSignature);
+                    })?;
+                    if is_cancelled(&cancelled, &message) || is_stored(&*storage, &message).await {
+                        continue;
+                    }
+                    // Not scored; an honest peer relays the messages that this node can't accept yet
+                    // (e.g., a vote for a block that isn't verified here yet).
                     filter.filter(&message).map_err(|e| eyre!("{}", e))?;
+                    guard.admit(message_size(&message), false).map_err(|e| {
+                        // Only the rate is the peer's fault; the storage may be full for any reason.
+                        if e == LimitExceeded::Growth {
+                            report(Misbehavior::Spam);
+                        }
+                        eyre!("{}", e)
+                    })?;
+                    tracing::debug!(message = %message.to_hash256(), "fetched a message");
                     Self::add_message_but_not_broadcast(&mut *storage, message).await?;
                 }
                 Result::<(), Error>::Ok(())
             };
-            tasks.push(task);
+            tasks.push(task.instrument(span));
         }
         let results = future::join_all(tasks).await;
-        for (result, peer) in results.into_iter().zip(self.peers.read().await.iter()) {
+        for (result, peer) in results.into_iter().zip(peers.iter()) {
             if let Err(e) = result {
                 log::warn!("failed to fetch from client {:?}: {}", peer, e);
             }
@@ -292,61 +1020,198 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
         Ok(())
     }
 
+    /// Queries the network versions of the peers, checking whether they are compatible.
+    ///
+    /// The results are recorded as the handshakes, refusing the incompatible peers.
+    pub async fn query_peer_versions(&self) -> Vec<(Peer, Result<NetworkVersion, Error>)> {
+        let peers = self.peers.read().await;
+        let my_version = NetworkVersion::current(&self.config.network_config.network_id);
+        let tasks = peers.iter().map(|peer| async {
+            let version = self.client(peer)?.get_version().await?;
+            let _ = self
+                .handshakes
+                .record(&peer.public_key, &my_version, version.clone(), now());
+            Result::<_, Error>::Ok(version)
+        });
+        let results = future::join_all(tasks).await;
+        peers.into_iter().zip(results).collect()
+    }
+
     /// Adds the given message to the storage, immediately broadcasting it to the network.
     ///
     /// Note that it is guaranteed that the message will not be broadcasted unless it
     /// is successfully added to the storage. (but it is not guaranteed for the other way around)
     pub async fn add_message(&mut self, message: Message) -> Result<(), Error> {
-        Self::add_message_but_not_broadcast(&mut *(self.storage.write().await), message.clone())
-            .await?;
+        if let Some(audit_log) = &self.audit_log {
+            if *message.signature.signer() == self.config.network_config.public_key {
+                let timestamp = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as Timestamp;
+                audit_log
+                    .record(&self.key, &message.data, &message.signature, timestamp)
+                    .await?;
+            }
+        }
+        let mut storage = self.storage.write().await;
+        if is_stored(&*storage, &message).await {
+            return Ok(());
+        }
+        let _ = self.guard.admit(message_size(&message), true);
+        Self::add_message_but_not_broadcast(&mut *storage, message.clone()).await?;
         Ok(())
     }
 
+    /// Stops broadcasting a message added by this node, and requests the peers to drop it too.
+    ///
+    /// It is useful when the message is superseded (e.g., by a newer agenda).
+    /// The peers which can't be reached now may keep the message.
+    pub async fn cancel_broadcast(&self, message_hash: &Hash256) -> Result<(), Error> {
+        let cancellation = Cancellation {
+            dms_key: self.key.clone(),
+            message_hash: *message_hash,
+        };
+        let signer = self
+            .signer
+            .as_ref()
+            .ok_or_else(|| eyre!("no signer to cancel the message"))?;
+        let signature = TypedSignature::sign_by(&cancellation, signer.as_ref()).await?;
+        if !self.drop_message(message_hash, signature.signer()).await? {
+            return Err(eyre!("no message {message_hash} of this node to cancel"));
+        }
+        self.acks.write().remove(message_hash);
+
+        let tasks = self.peers.read().await.into_iter().map(|peer| {
+            let cancellations = vec![(cancellation.clone(), signature.clone())];
+            async move {
+                self.client(&peer)?
+                    .cancel_messages(self.key.clone(), cancellations)
+                    .await
+            }
+        });
+        for result in future::join_all(tasks).await {
+            if let Err(e) = result {
+                log::warn!("failed to cancel message {message_hash}: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the message from the storage and marks it as cancelled,
+    /// if it is stored and was signed by `signer`. Returns whether it has been removed.
+    async fn drop_message(
+        &self,
+        message_hash: &Hash256,
+        signer: &PublicKey,
+    ) -> Result<bool, Error> {
+        let mut storage = self.storage.write().await;
+        let file = format!("{message_hash}.json");
+        if !storage.list_files().await?.contains(&file) {
+            return Ok(false);
+        }
+        let content = storage.read_file(&file).await?;
+        let message = serde_spb::from_str::<RawMessage>(&content)?;
+        if message.signature.signer() != signer {
+            return Ok(false);
+        }
+        storage.remove_file(&file).await?;
+        self.guard.release(content.len() as u64);
+        self.cancelled
+            .write()
+            .insert((*message_hash, signer.clone()));
+        Ok(true)
+    }
+
     /// Tries to broadcast all the message that this DMS instance has.
     pub async fn broadcast_all(&self) -> Result<(), Error> {
         let mut tasks1 = Vec::new();
-        let messages = self
-            .read_messages()
-            .await?
+        let messages = self.read_messages().await?;
+        let message_hashes = messages.iter().map(|m| m.to_hash256()).collect::<Vec<_>>();
+        let messages = messages
             .into_iter()
             .map(RawMessage::from_message)
             .collect::<Vec<_>>();
-        for peer in self.peers.read().await {
-            let port_key = format!("dms-{}", self.key);
+        // The scheduling keeps the order among the peers dialed at the same time,
+        // so the ones with lower latencies are preferred.
+        let peers = self
+            .latencies
+            .sort_by_latency(self.read_available_peers().await);
+        for peer in self.handshake(self.dialer.schedule(peers, now())).await {
+            let port_key = self.port_key();
+            let client = self.client(&peer);
             let messages_ = messages.clone();
-            let task = async move {
-                let stub = DistributedMessageSetRpcInterfaceStub::new(Box::new(HttpClient::new(
-                    format!(
-                        "{}:{}/dms",
-                        peer.address.ip(),
-                        peer.ports
-                            .get(&port_key)
-                            .ok_or_else(|| eyre!("can't find port key: {}", port_key))?
-                    ),
-                    reqwest::Client::new(),
-                )));
-                stub.add_messages(self.key.clone(), messages_.clone())
-                    .await
-                    .map_err(|e| eyre!(e))?
-                    .map_err(|e| eyre!(e))?;
+            let message_hashes_ = message_hashes.clone();
+            let acks = Arc::clone(&self.acks);
+            let public_key = peer.public_key.clone();
+            let bandwidth = Arc::clone(&self.bandwidth);
+            let metrics = Arc::clone(&self.metrics);
+            let label = format!("RPC message add to {}", peer.public_key);
+            let span = tracing::debug_span!("push", dms = %self.key, peer = %public_key);
+            let task = dial(&self.dialer, &self.metrics, public_key, async move {
+                let _in_flight = metrics.start_broadcast();
+                let client = client?;
+                bandwidth.record(
+                    &peer.public_key,
+                    &port_key,
+                    Direction::Egress,
+                    serde_spb::to_vec(&messages_)?.len() as u64,
+                    now(),
+                )?;
+                client
+                    .add_messages(self.key.clone(), messages_.clone())
+                    .await?;
+                metrics.record_acks(message_hashes_.len() as u64);
+                tracing::debug!(messages = message_hashes_.len(), "acknowledged");
+                let mut acks = acks.write();
+                for message_hash in message_hashes_ {
+                    acks.entry(message_hash)
+                        .or_default()
+                        .insert(peer.public_key.clone());
+                }
                 Result::<(), Error>::Ok(())
-            };
-            tasks1.push((task, format!("RPC message add to {}", peer.public_key)));
+            });
+            tasks1.push((task.instrument(span), label));
         }
-        let peers_ = self.peers.read().await;
+        let peers_ = self
+            .latencies
+            .sort_by_latency(self.read_available_peers().await);
+        let network_config = NetworkConfig {
+            members: self.members.read(),
+            ..self.config.network_config.clone()
+        };
         let tasks2 = messages.into_iter().map(|message| {
-            let network_config = self.config.network_config.clone();
+            let network_config = network_config.clone();
             let peers = peers_.clone();
             let message_hash = message.data.to_hash256();
+            let chunking = self.config.chunking.clone();
+            let broadcaster = self.broadcaster.clone();
+            let priority = self.config.priority;
             (
                 async move {
-                    N::broadcast(
-                        &network_config,
-                        &peers,
-                        serde_spb::to_vec(&message).unwrap(),
-                    )
-                    .await?;
-                    Result::<(), Error>::Ok(())
+                    let chunks = split(&serde_spb::to_vec(&message)?, &chunking)?;
+                    let span = tracing::debug_span!(
+                        "gossip",
+                        message = %message_hash,
+                        token = %chunks[0].token,
+                        chunks = chunks.len(),
+                    );
+                    async {
+                        for chunk in chunks {
+                            let chunk = serde_spb::to_vec(&chunk)?;
+                            match &broadcaster {
+                                Some(broadcaster) => {
+                                    broadcaster
+                                        .broadcast(&network_config, &peers, chunk, priority)
+                                        .await?
+                                }
+                                None => N::broadcast(&network_config, &peers, chunk).await?,
+                            }
+                        }
+                        tracing::debug!("broadcasted");
+                        Result::<(), Error>::Ok(())
+                    }
+                    .instrument(span)
+                    .await
                 },
                 format!("broadcast message {message_hash} to all peers"),
             )
@@ -398,7 +1263,7 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
     ) -> Result<(), Error> {
         storage
             .add_or_overwrite_file(
-                &format!("{}.json", message.to_hash256()),
+                &message_file_name(&message),
                 serde_spb::to_string(&message).unwrap(),
             )
             .await?;
@@ -412,31 +1277,65 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
         Ok(())
     }
 
-    async fn serve_rpc(this: Arc<RwLock<Self>>, rpc_port: u16) -> Result<(), Error> {
+    /// Serves the RPCs on the multiplexed port of `mux`, and over HTTP on `http_port`, if any.
+    async fn serve_rpc(
+        this: Arc<RwLock<Self>>,
+        http_port: Option<u16>,
+        mux: Option<(Arc<MuxRpc>, u16)>,
+        released: Arc<tokio::sync::Notify>,
+    ) -> Result<(), Error> {
+        let port_key = this.read().await.port_key();
         let wrapped_this = Arc::new(parking_lot::RwLock::new(Some(this)));
-        let wrapped_this_ = Arc::clone(&wrapped_this);
+        let wrapper = Arc::new(DmsWrapper {
+            dms: Arc::clone(&wrapped_this),
+            released,
+        });
 
         struct DropHelper<T> {
             wrapped_this: Arc<parking_lot::RwLock<Option<Arc<RwLock<T>>>>>,
+            registration: Option<tokio::task::JoinHandle<Result<(), Error>>>,
         }
         impl<T> Drop for DropHelper<T> {
             fn drop(&mut self) {
                 self.wrapped_this.write().take().unwrap();
+                if let Some(registration) = &self.registration {
+                    registration.abort();
+                }
             }
         }
-        let _drop_helper = DropHelper { wrapped_this };
-        run_server(
-            rpc_port,
-            [(
-                "dms".to_owned(),
-                create_http_object(Arc::new(DmsWrapper { dms: wrapped_this_ })
-                    as Arc<dyn DistributedMessageSetRpcInterface>),
-            )]
-            .iter()
-            .cloned()
-            .collect(),
-        )
-        .await;
+        let registration = match mux {
+            Some((mux, port)) => {
+                let wrapper = Arc::clone(&wrapper);
+                Some(
+                    mux.serve_protocol(&port_key, port, move |request: DmsRequest| {
+                        let wrapper = Arc::clone(&wrapper);
+                        async move { wrapper.handle(request).await }
+                    })
+                    .await?,
+                )
+            }
+            None => None,
+        };
+        let _drop_helper = DropHelper {
+            wrapped_this,
+            registration,
+        };
+        match http_port {
+            Some(port) => {
+                run_server(
+                    port,
+                    [(
+                        "dms".to_owned(),
+                        create_http_object(wrapper as Arc<dyn DistributedMessageSetRpcInterface>),
+                    )]
+                    .iter()
+                    .cloned()
+                    .collect(),
+                )
+                .await
+            }
+            None => future::pending().await,
+        }
         Ok(())
     }
 
@@ -446,11 +1345,16 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
         } else {
             return Result::<(), Error>::Ok(());
         };
+        let mut events = this.read().await.peers.subscribe();
         loop {
             if let Err(e) = Self::fetch(&mut *this.write().await).await {
                 log::warn!("failed to parse message from the RPC-fetch: {}", e);
             }
-            tokio::time::sleep(interval).await;
+            // A new peer is fetched from right away, rather than after the interval.
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = peer_added(&mut events) => {}
+            }
         }
     }
 
@@ -460,8 +1364,10 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
         } else {
             return Result::<(), Error>::Ok(());
         };
+        let key = this.read().await.key.clone();
         loop {
-            if let Err(e) = this.read().await.broadcast_all().await {
+            let span = tracing::info_span!("broadcast", dms = %key);
+            if let Err(e) = this.read().await.broadcast_all().instrument(span).await {
                 log::warn!("failed to broadcast to the network: {}", e);
             }
             tokio::time::sleep(interval).await;
@@ -474,10 +1380,54 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
             this.read().await.peers.clone(),
         )
         .await?;
+        let mut reassembler = Reassembler::new(this.read().await.config.chunking.clone());
+        let seen_config = this.read().await.config.seen_cache.clone();
+        let mut seen = match SeenCache::load(seen_config.clone(), now()).await {
+            Ok(x) => x,
+            Err(e) => {
+                log::warn!("failed to load the seen broadcasts: {}", e);
+                SeenCache::new(seen_config)
+            }
+        };
         while let Some(m) = recv.0.recv().await {
+            // Handled by `GossipPeerDiscovery`, if the node discovers its peers over the same network.
+            if super::gossip_discovery::is_discovery_gossip(&m) {
+                continue;
+            }
+            let chunk: Chunk = match serde_spb::from_slice(&m) {
+                Ok(x) => x,
+                Err(e) => {
+                    log::warn!("failed to receive a message from the gossip network: {}", e);
+                    continue;
+                }
+            };
+            if seen.contains(&chunk.token, now()) {
+                tracing::trace!(token = %chunk.token, "dropped a duplicate broadcast");
+                continue;
+            }
+            let span = tracing::debug_span!("gossip_receive", token = %chunk.token);
             let result = async {
+                tracing::trace!(index = chunk.index, total = chunk.total, "received a chunk");
+                let token = chunk.token;
+                let m = match reassembler.add(chunk, now())? {
+                    Some(m) => m,
+                    None => return Ok(()),
+                };
+                // Whether or not it's accepted, it's not processed again.
+                seen.insert(token, now());
+                tracing::debug!(size = m.len(), "reassembled a message");
                 let message: RawMessage = serde_spb::from_slice(&m)?;
                 let message = message.into_message()?;
+                if is_cancelled(&this.read().await.cancelled, &message) {
+                    return Ok(());
+                }
+                let signer = message.signature.signer();
+                let dms = this.read().await;
+                if !dms.members.read().contains(signer) {
+                    let reason = AuthFailureReason::UnknownKey(signer.clone());
+                    dms.auth_events.report(None, reason);
+                }
+                drop(dms);
                 this.read()
                     .await
                     .filter
@@ -490,10 +1440,14 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
                 .await?;
                 Result::<(), eyre::Error>::Ok(())
             }
+            .instrument(span)
             .await;
             if let Err(e) = result {
                 log::warn!("failed to receive a message from the gossip network: {}", e);
             }
+            if let Err(e) = seen.save_if_due(now()).await {
+                log::warn!("failed to save the seen broadcasts: {}", e);
+            }
         }
         Ok(())
     }
@@ -502,17 +1456,45 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
     ///
     /// TODO: currently it just returns itself after the given time.
     pub async fn serve(self, time_in_ms: u64) -> Result<Self, Error> {
-        let port_key = format!("dms-{}", self.key);
-        let port = *self
-            .config
-            .network_config
-            .ports
-            .get(&port_key)
-            .ok_or_else(|| eyre!(format!("`ports` has no field of {port_key}")))?;
+        if let Some(config) = &self.config.tracing {
+            init_tracing(config)?;
+        }
+        let port_key = self.port_key();
+        let ports = &self.config.network_config.ports;
+        let mux = match &self.mux {
+            Some(mux) => {
+                let port = *ports
+                    .get(MUX_PROTOCOL)
+                    .ok_or_else(|| eyre!("`ports` has no field of {MUX_PROTOCOL}"))?;
+                Some((Arc::clone(mux), port))
+            }
+            None => None,
+        };
+        // The HTTP server isn't encrypted, so a private network is served only on the multiplexed port.
+        let http_port = if self.config.network_config.pre_shared_key.is_some() {
+            if mux.is_none() {
+                return Err(eyre!(
+                    "the pre-shared key requires a multiplexed transport; see `set_mux()`"
+                ));
+            }
+            None
+        } else {
+            match ports.get(&port_key) {
+                Some(port) => Some(*port),
+                None if mux.is_some() => None,
+                None => return Err(eyre!("`ports` has no field of {port_key}")),
+            }
+        };
 
         let this = Arc::new(RwLock::new(self));
+        let released = Arc::new(tokio::sync::Notify::new());
         let this_ = Arc::clone(&this);
-        let rpc_task = async move { Self::serve_rpc(this_, port).await.map(|_| false) };
+        let released_ = Arc::clone(&released);
+        let rpc_task = async move {
+            Self::serve_rpc(this_, http_port, mux, released_)
+                .await
+                .map(|_| false)
+        };
         let this_ = Arc::clone(&this);
         let fetch_task = async move { Self::serve_fetch(this_).await.map(|_| false) };
         let this_ = Arc::clone(&this);
@@ -537,13 +1519,24 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
             }
             tasks = remaining_futures;
         }
-        Ok(Arc::try_unwrap(this).unwrap().into_inner())
+        // The RPCs still being handled may hold the DMS for a moment after the server stops,
+        // but no new one can get it.
+        let mut this = this;
+        loop {
+            match Arc::try_unwrap(this) {
+                Ok(this) => return Ok(this.into_inner()),
+                Err(this_) => this = this_,
+            }
+            released.notified().await;
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::identity::MemberIdentity;
+    use crate::psk::PreSharedKey;
     use crate::storage::StorageImpl;
     use rand::prelude::*;
     use simperby_test_suite::*;
@@ -583,7 +1576,13 @@ mod tests {
                 ports: Default::default(),
                 members: keys.iter().map(|(x, _)| x).cloned().collect(),
                 public_key: keys[i + 1].0.clone(),
-                private_key: keys[i + 1].1.clone(),
+                private_key: Some(keys[i + 1].1.clone()),
+                dns_seeds: Vec::new(),
+                enable_mdns: false,
+                pre_shared_key: None,
+                proxy: None,
+                denied_peers: Vec::new(),
+                allowed_peers: Vec::new(),
             });
         }
         (
@@ -595,18 +1594,25 @@ mod tests {
                     .collect(),
                 members: keys.iter().map(|(x, _)| x).cloned().collect(),
                 public_key: keys[0].0.clone(),
-                private_key: keys[0].1.clone(),
+                private_key: Some(keys[0].1.clone()),
+                dns_seeds: Vec::new(),
+                enable_mdns: false,
+                pre_shared_key: None,
+                proxy: None,
+                denied_peers: Vec::new(),
+                allowed_peers: Vec::new(),
             },
             configs,
             Peer {
                 public_key: keys[0].0.clone(),
                 name: format!("{}", keys[0].0),
                 address: SocketAddrV4::new("127.0.0.1".parse().unwrap(), serving_node_port),
+                addresses: Vec::new(),
                 ports: [(format!("dms-{network_id}"), serving_node_port)]
                     .iter()
                     .cloned()
                     .collect(),
-                message: "".to_owned(),
+                metadata: Default::default(),
                 recently_seen_timestamp: 0,
             },
         )
@@ -623,6 +1629,12 @@ mod tests {
                 fetch_interval: Some(std::time::Duration::from_millis(500)),
                 broadcast_interval: Some(std::time::Duration::from_millis(500)),
                 network_config,
+                limits: Default::default(),
+                dial: Default::default(),
+                chunking: Default::default(),
+                seen_cache: Default::default(),
+                priority: Default::default(),
+                tracing: None,
             },
             peers,
         )
@@ -638,7 +1650,13 @@ mod tests {
                 ports: Default::default(),
                 members: Default::default(),
                 public_key: PublicKey::zero(),
-                private_key: PrivateKey::zero(),
+                private_key: Some(PrivateKey::zero()),
+                dns_seeds: Vec::new(),
+                enable_mdns: false,
+                pre_shared_key: None,
+                proxy: None,
+                denied_peers: Vec::new(),
+                allowed_peers: Vec::new(),
             },
             SharedKnownPeers::new(Default::default()),
         )
@@ -649,7 +1667,8 @@ mod tests {
             let msg = format!("{i}");
             dms.add_message(Message {
                 data: msg.clone(),
-                signature: TypedSignature::sign(&msg, &network_config.private_key).unwrap(),
+                signature: TypedSignature::sign(&msg, network_config.private_key().unwrap())
+                    .unwrap(),
             })
             .await
             .unwrap();
@@ -680,7 +1699,8 @@ mod tests {
             let msg = format!("{i}");
             dms.add_message(Message {
                 data: msg.clone(),
-                signature: TypedSignature::sign(&msg, &network_config.private_key).unwrap(),
+                signature: TypedSignature::sign(&msg, network_config.private_key().unwrap())
+                    .unwrap(),
             })
             .await
             .unwrap();
@@ -720,6 +1740,423 @@ mod tests {
         assert_eq!(expected, messages);
     }
 
+    #[tokio::test]
+    async fn broadcast_status() {
+        setup_test();
+        let rpc_port = dispense_port();
+        let (server_network_config, network_configs, server_peer) =
+            generate_node_configs(rpc_port, 3);
+        let server_dms = setup(
+            server_network_config.clone(),
+            SharedKnownPeers::new(Default::default()),
+        )
+        .await;
+        let network_config = network_configs[0].clone();
+        let mut dms = setup(
+            network_config.clone(),
+            SharedKnownPeers::new_static(vec![server_peer.clone()]),
+        )
+        .await;
+        let msg = "hello".to_owned();
+        let message = Message {
+            data: msg.clone(),
+            signature: TypedSignature::sign(&msg, network_config.private_key().unwrap()).unwrap(),
+        };
+        dms.add_message(message.clone()).await.unwrap();
+        let status = dms.broadcast_status(&message.to_hash256());
+        assert!(status.acked.is_empty());
+        assert_eq!(status.total, 2);
+
+        let handle = tokio::spawn(async move { server_dms.serve(3000).await.unwrap() });
+        sleep(1000).await;
+        dms.broadcast_all().await.unwrap();
+        let status = dms.broadcast_status(&message.to_hash256());
+        assert_eq!(
+            status.acked,
+            vec![server_peer.public_key.clone()].into_iter().collect()
+        );
+        assert_eq!(status.completion_percentage(), 50.0);
+        assert!(!status.is_complete());
+
+        // The other client is no longer a member.
+        dms.update_members(vec![
+            network_config.public_key.clone(),
+            server_peer.public_key.clone(),
+        ]);
+        assert!(dms.broadcast_status(&message.to_hash256()).is_complete());
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancel_broadcast() {
+        setup_test();
+        let rpc_port = dispense_port();
+        let (server_network_config, network_configs, server_peer) =
+            generate_node_configs(rpc_port, 2);
+        let server_dms = setup(
+            server_network_config.clone(),
+            SharedKnownPeers::new(Default::default()),
+        )
+        .await;
+        let network_config = network_configs[0].clone();
+        let mut dms = setup(
+            network_config.clone(),
+            SharedKnownPeers::new_static(vec![server_peer]),
+        )
+        .await;
+        let messages = ["superseded", "kept"]
+            .iter()
+            .map(|msg| Message {
+                data: msg.to_string(),
+                signature: TypedSignature::sign(
+                    &msg.to_string(),
+                    network_config.private_key().unwrap(),
+                )
+                .unwrap(),
+            })
+            .collect::<Vec<_>>();
+        for message in &messages {
+            dms.add_message(message.clone()).await.unwrap();
+        }
+
+        let handle = tokio::spawn(async move { server_dms.serve(4000).await.unwrap() });
+        sleep(1000).await;
+        dms.broadcast_all().await.unwrap();
+        dms.cancel_broadcast(&messages[0].to_hash256())
+            .await
+            .unwrap();
+        // The peer must not take the cancelled message again.
+        dms.broadcast_all().await.unwrap();
+        assert!(dms
+            .cancel_broadcast(&messages[0].to_hash256())
+            .await
+            .is_err());
+
+        let server_dms = handle.await.unwrap();
+        for dms in [&dms, &server_dms] {
+            let data = dms
+                .read_messages()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|x| x.data)
+                .collect::<Vec<_>>();
+            assert_eq!(data, vec!["kept".to_owned()]);
+        }
+    }
+
+    #[tokio::test]
+    async fn cancellation_checks() {
+        setup_test();
+        let (network_config, _, _) = generate_node_configs(dispense_port(), 2);
+        let mut dms = setup(
+            network_config.clone(),
+            SharedKnownPeers::new(Default::default()),
+        )
+        .await;
+        let sign = |data: &str, private_key: &PrivateKey| Message {
+            data: data.to_owned(),
+            signature: TypedSignature::sign(&data.to_owned(), private_key).unwrap(),
+        };
+        let message = sign("hello", network_config.private_key().unwrap());
+        dms.add_message(message.clone()).await.unwrap();
+        assert!(dms.guard.stored_bytes() > 0);
+        let dms_key = dms.key.clone();
+        let dms = Arc::new(RwLock::new(dms));
+        let wrapper = DmsWrapper::<_, _> {
+            dms: Arc::new(parking_lot::RwLock::new(Some(Arc::clone(&dms)))),
+            released: Default::default(),
+        };
+        let cancel = |message_hash: Hash256, private_key: &PrivateKey| {
+            let cancellation = Cancellation {
+                dms_key: dms_key.clone(),
+                message_hash,
+            };
+            let signature = TypedSignature::sign(&cancellation, private_key).unwrap();
+            vec![(cancellation, signature)]
+        };
+
+        // A non-member can't cancel anything.
+        let (_, stranger) = generate_keypair_random();
+        assert!(wrapper
+            .cancel_messages(dms_key.clone(), cancel(message.to_hash256(), &stranger))
+            .await
+            .is_err());
+        // Nothing is recorded for a message that isn't stored.
+        let unknown = sign("unknown", network_config.private_key().unwrap());
+        wrapper
+            .cancel_messages(
+                dms_key.clone(),
+                cancel(unknown.to_hash256(), network_config.private_key().unwrap()),
+            )
+            .await
+            .unwrap();
+        assert!(!is_cancelled(&dms.read().await.cancelled, &unknown));
+
+        wrapper
+            .cancel_messages(
+                dms_key.clone(),
+                cancel(message.to_hash256(), network_config.private_key().unwrap()),
+            )
+            .await
+            .unwrap();
+        let dms = dms.read().await;
+        assert!(is_cancelled(&dms.cancelled, &message));
+        assert!(dms.read_messages().await.unwrap().is_empty());
+        assert_eq!(dms.guard.stored_bytes(), 0);
+    }
+
+    #[tokio::test]
+    async fn duplicates_admitted_once() {
+        setup_test();
+        let (network_config, _, _) = generate_node_configs(dispense_port(), 2);
+        let mut dms = setup(
+            network_config.clone(),
+            SharedKnownPeers::new(Default::default()),
+        )
+        .await;
+        let sign = |data: &str| Message {
+            data: data.to_owned(),
+            signature: TypedSignature::sign(
+                &data.to_owned(),
+                network_config.private_key().unwrap(),
+            )
+            .unwrap(),
+        };
+        let message = sign("hello");
+        dms.add_message(message.clone()).await.unwrap();
+        let stored_bytes = dms.guard.stored_bytes();
+        dms.add_message(message.clone()).await.unwrap();
+        assert_eq!(dms.guard.stored_bytes(), stored_bytes);
+
+        let dms_key = dms.key.clone();
+        let dms = Arc::new(RwLock::new(dms));
+        let wrapper = DmsWrapper::<_, _> {
+            dms: Arc::new(parking_lot::RwLock::new(Some(Arc::clone(&dms)))),
+            released: Default::default(),
+        };
+        wrapper
+            .add_messages(
+                dms_key.clone(),
+                vec![RawMessage::from_message(message.clone()); 2],
+            )
+            .await
+            .unwrap();
+        assert_eq!(dms.read().await.guard.stored_bytes(), stored_bytes);
+        wrapper
+            .add_messages(dms_key, vec![RawMessage::from_message(sign("world"))])
+            .await
+            .unwrap();
+        assert!(dms.read().await.guard.stored_bytes() > stored_bytes);
+    }
+
+    #[test]
+    fn cancellations_bounded() {
+        setup_test();
+        let (public_key, _) = generate_keypair_random();
+        let mut cancellations = Cancellations::default();
+        for i in 0..=MAX_CANCELLATIONS {
+            cancellations.insert((Hash256::hash(i.to_le_bytes()), public_key.clone()));
+        }
+        assert_eq!(cancellations.order.len(), MAX_CANCELLATIONS);
+        assert!(!cancellations.contains(&(Hash256::hash(0usize.to_le_bytes()), public_key.clone())));
+        assert!(
+            cancellations.contains(&(Hash256::hash(MAX_CANCELLATIONS.to_le_bytes()), public_key))
+        );
+    }
+
+    struct RejectAll;
+
+    impl MessageFilter for RejectAll {
+        fn filter(&self, _message: &Message) -> Result<(), String> {
+            Err("not acceptable yet".to_owned())
+        }
+    }
+
+    #[tokio::test]
+    async fn filter_rejection_not_scored() {
+        setup_test();
+        let rpc_port = dispense_port();
+        let (server_network_config, network_configs, server_peer) =
+            generate_node_configs(rpc_port, 2);
+        let mut server_dms = setup(
+            server_network_config.clone(),
+            SharedKnownPeers::new(Default::default()),
+        )
+        .await;
+        let msg = "early".to_owned();
+        server_dms
+            .add_message(Message {
+                data: msg.clone(),
+                signature: TypedSignature::sign(&msg, server_network_config.private_key().unwrap())
+                    .unwrap(),
+            })
+            .await
+            .unwrap();
+        let mut dms = setup(
+            network_configs[0].clone(),
+            SharedKnownPeers::new_static(vec![server_peer.clone()]),
+        )
+        .await;
+        dms.set_filter(Arc::new(RejectAll));
+
+        let handle = tokio::spawn(async move { server_dms.serve(3000).await.unwrap() });
+        sleep(1000).await;
+        for _ in 0..3 {
+            dms.fetch().await.unwrap();
+        }
+        assert!(dms.read_messages().await.unwrap().is_empty());
+        assert!(dms.peer_scores().scores().is_empty());
+        handle.await.unwrap();
+    }
+
+    fn mux(network_config: &NetworkConfig) -> Arc<MuxRpc> {
+        Arc::new(
+            MuxRpc::new(Default::default())
+                .with_pre_shared_key(network_config.pre_shared_key.clone())
+                .with_member_identity(Some(Arc::new(MemberIdentity::new(
+                    network_config,
+                    network_config.signer().unwrap(),
+                )))),
+        )
+    }
+
+    #[tokio::test]
+    async fn multiplexed() {
+        setup_test();
+        let port = dispense_port();
+        let (server_network_config, network_configs, mut server_peer) =
+            generate_node_configs(port, 3);
+        let server_network_config = NetworkConfig {
+            ports: [(MUX_PROTOCOL.to_owned(), port)].into_iter().collect(),
+            ..server_network_config
+        };
+        server_peer.ports = server_network_config.ports.clone();
+        let peers = SharedKnownPeers::new_static(vec![server_peer.clone()]);
+        let mut server_dms = setup(
+            server_network_config.clone(),
+            SharedKnownPeers::new(Default::default()),
+        )
+        .await;
+        server_dms.set_mux(mux(&server_network_config)).unwrap();
+
+        let network_config = network_configs[0].clone();
+        let mut dms = setup(network_config.clone(), peers.clone()).await;
+        dms.set_mux(mux(&network_config)).unwrap();
+        // The server has no port of the DMS to reach without multiplexing.
+        let mut unmuxed = setup(network_configs[1].clone(), peers).await;
+
+        let msg = "hello".to_owned();
+        let message = Message {
+            data: msg.clone(),
+            signature: TypedSignature::sign(&msg, network_config.private_key().unwrap()).unwrap(),
+        };
+        dms.add_message(message.clone()).await.unwrap();
+
+        let handle = tokio::spawn(async move { server_dms.serve(3000).await.unwrap() });
+        sleep(1000).await;
+        dms.broadcast_all().await.unwrap();
+        assert_eq!(
+            dms.broadcast_status(&message.to_hash256()).acked,
+            vec![server_peer.public_key.clone()].into_iter().collect()
+        );
+        unmuxed.fetch().await.unwrap();
+        assert!(unmuxed.read_messages().await.unwrap().is_empty());
+        let server_dms = handle.await.unwrap();
+        let data = server_dms
+            .read_messages()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|x| x.data)
+            .collect::<Vec<_>>();
+        assert_eq!(data, vec![msg]);
+    }
+
+    #[tokio::test]
+    async fn private_network() {
+        setup_test();
+        let port = dispense_port();
+        let (server_network_config, network_configs, mut server_peer) =
+            generate_node_configs(port, 4);
+        let key = Some(PreSharedKey::from_passphrase("private_network"));
+        let server_network_config = NetworkConfig {
+            ports: [
+                (MUX_PROTOCOL.to_owned(), port),
+                (
+                    format!("dms-{}", server_network_config.network_id),
+                    port + 1,
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            pre_shared_key: key.clone(),
+            ..server_network_config
+        };
+        server_peer.ports = server_network_config.ports.clone();
+        let peers = SharedKnownPeers::new_static(vec![server_peer.clone()]);
+
+        // The HTTP server isn't encrypted, so it needs a mux.
+        let unmuxed_server = setup(
+            server_network_config.clone(),
+            SharedKnownPeers::new(Default::default()),
+        )
+        .await;
+        assert!(unmuxed_server.serve(100).await.is_err());
+        let mut server_dms = setup(
+            server_network_config.clone(),
+            SharedKnownPeers::new(Default::default()),
+        )
+        .await;
+        // A mux without the key would leave the DMS in plaintext.
+        assert!(server_dms
+            .set_mux(Arc::new(MuxRpc::new(Default::default())))
+            .is_err());
+        server_dms.set_mux(mux(&server_network_config)).unwrap();
+
+        let network_config = NetworkConfig {
+            pre_shared_key: key.clone(),
+            ..network_configs[0].clone()
+        };
+        let mut dms = setup(network_config.clone(), peers.clone()).await;
+        dms.set_mux(mux(&network_config)).unwrap();
+        // Even though the server advertises the port of the DMS.
+        let mut unmuxed = setup(
+            NetworkConfig {
+                pre_shared_key: key,
+                ..network_configs[1].clone()
+            },
+            peers.clone(),
+        )
+        .await;
+        let outsider_config = NetworkConfig {
+            pre_shared_key: Some(PreSharedKey::from_passphrase("other")),
+            ..network_configs[2].clone()
+        };
+        let mut outsider = setup(outsider_config.clone(), peers).await;
+        outsider.set_mux(mux(&outsider_config)).unwrap();
+
+        let msg = "hello".to_owned();
+        let message = Message {
+            data: msg.clone(),
+            signature: TypedSignature::sign(&msg, network_config.private_key().unwrap()).unwrap(),
+        };
+        dms.add_message(message.clone()).await.unwrap();
+
+        let handle = tokio::spawn(async move { server_dms.serve(3000).await.unwrap() });
+        sleep(1000).await;
+        dms.broadcast_all().await.unwrap();
+        assert_eq!(
+            dms.broadcast_status(&message.to_hash256()).acked,
+            vec![server_peer.public_key.clone()].into_iter().collect()
+        );
+        for dms in [&mut unmuxed, &mut outsider] {
+            dms.fetch().await.unwrap();
+            assert!(dms.read_messages().await.unwrap().is_empty());
+        }
+        handle.await.unwrap();
+    }
+
     /// Multi-node test assuming dummy gossip network and a single server node.
     #[tokio::test]
     async fn multi_dummy_gn_single_sn_1() {
@@ -735,14 +2172,14 @@ mod tests {
         .await;
         let mut tasks = Vec::new();
         let k = 5;
-        let all_numbers = (0..k * n).into_iter().collect::<Vec<_>>();
+        let all_numbers = (0..k * n).collect::<Vec<_>>();
         for (i, network_config) in network_configs.iter().enumerate() {
             let dms = setup(
                 server_network_config.clone(),
                 SharedKnownPeers::new_static(vec![server_peer.clone()]),
             )
             .await;
-            let numbers = ((i * k)..(i * k + k)).into_iter().collect::<Vec<_>>();
+            let numbers = ((i * k)..(i * k + k)).collect::<Vec<_>>();
             tasks.push(run_non_server_node_1(
                 i,
                 dms,
@@ -773,14 +2210,14 @@ mod tests {
         .await;
         let mut tasks = Vec::new();
         let k = 5;
-        let all_numbers = (0..k * n).into_iter().collect::<Vec<_>>();
+        let all_numbers = (0..k * n).collect::<Vec<_>>();
         for (i, network_config) in network_configs.iter().enumerate() {
             let dms = setup(
                 server_network_config.clone(),
                 SharedKnownPeers::new(Arc::new(RwLock::new(vec![server_peer.clone()]))),
             )
             .await;
-            let numbers = ((i * k)..(i * k + k)).into_iter().collect::<Vec<_>>();
+            let numbers = ((i * k)..(i * k + k)).collect::<Vec<_>>();
             tasks.push(run_non_server_node_1(
                 i,
                 dms,
@@ -0,0 +1,466 @@
+//! Protections against the eclipse attacks in the peer discovery.
+//!
+//! A node that learns its peers from a few sources (e.g., a single malicious bootstrap peer)
+//! can be fed a view consisting only of the attacker's nodes. The view is diversified by:
+//!
+//! - limiting the peers dialed in a round per /16 subnet (`DialConfig::max_peers_per_subnet`),
+//!   so a single operator's addresses can't take all the slots;
+//! - probing random members of the full member list, to refresh them from their own signed records
+//!   rather than from the peers relaying them;
+//! - accepting the address of a member advertised by the other peers only if it's reported by
+//!   sources in multiple subnets, and then only after the member confirms it with its signed record.
+use super::*;
+use crate::seeds::{merge_peer_record, PEER_RECORD_PROTOCOL};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use std::collections::HashSet;
+use std::net::Ipv6Addr;
+
+/// The protocol identifier of asking the known peers of a node, in `Peer::ports`.
+pub const PEER_EXCHANGE_PROTOCOL: &str = "peer-exchange";
+
+/// A /16 subnet of IPv4, or a /32 subnet of IPv6.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Subnet {
+    V4([u8; 2]),
+    V6([u16; 2]),
+}
+
+impl Subnet {
+    pub fn of(ip: &IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => {
+                let [a, b, _, _] = ip.octets();
+                Subnet::V4([a, b])
+            }
+            IpAddr::V6(ip) => {
+                let [a, b, ..] = Ipv6Addr::segments(ip);
+                Subnet::V6([a, b])
+            }
+        }
+    }
+
+    /// The subnet of the discovery address of the peer.
+    pub fn of_peer(peer: &Peer) -> Self {
+        Self::of(&IpAddr::V4(*peer.address.ip()))
+    }
+}
+
+/// Keeps at most `max_per_subnet` peers of each subnet, preserving the order.
+pub fn diversify(peers: Vec<Peer>, max_per_subnet: usize) -> Vec<Peer> {
+    let mut counts = HashMap::<Subnet, usize>::new();
+    peers
+        .into_iter()
+        .filter(|peer| {
+            let count = counts.entry(Subnet::of_peer(peer)).or_default();
+            *count += 1;
+            *count <= max_per_subnet
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProbeConfig {
+    /// How often the members are probed, in milliseconds.
+    pub interval_ms: u64,
+    /// The number of the random members probed in a round.
+    pub members_per_round: usize,
+    /// The number of the known peers (in distinct subnets) asked for the members in a round.
+    pub sources_per_round: usize,
+    /// The number of the sources in distinct subnets that must report the same address of a member.
+    pub min_sources: usize,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: 60 * 1000,
+            members_per_round: 4,
+            sources_per_round: 4,
+            min_sources: 2,
+        }
+    }
+}
+
+/// Answers the requests for the known peers among the given keys,
+/// with at most `DiscoveryConfig::max_response_peers` peers.
+pub async fn serve_peer_exchange<P: RpcPrimitive>(
+    rpc: &P,
+    port: u16,
+    known_peers: SharedKnownPeers,
+    config: &DiscoveryConfig,
+) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error> {
+    let max_response_peers = config.max_response_peers;
+    rpc.serve_protocol(PEER_EXCHANGE_PROTOCOL, port, move |keys: Vec<PublicKey>| {
+        let known_peers = known_peers.clone();
+        async move {
+            let mut peers = known_peers
+                .read_filtered(|peer| keys.contains(&peer.public_key))
+                .await;
+            peers.truncate(max_response_peers);
+            Ok(peers)
+        }
+    })
+    .await
+}
+
+/// The address where the peer serves its signed record.
+fn record_address(peer: &Peer) -> Option<SocketAddrV4> {
+    peer.port(PEER_RECORD_PROTOCOL)
+        .map(|port| SocketAddrV4::new(*peer.address.ip(), port))
+}
+
+/// Returns the addresses reported by at least `min_sources` sources in distinct subnets,
+/// from the reports of `(source, reported peer)`.
+pub fn cross_check(reports: &[(Peer, Peer)], min_sources: usize) -> Vec<SocketAddrV4> {
+    let mut sources = HashMap::<(PublicKey, SocketAddrV4), HashSet<Subnet>>::new();
+    for (source, reported) in reports {
+        if let Some(address) = record_address(reported) {
+            sources
+                .entry((reported.public_key.clone(), address))
+                .or_default()
+                .insert(Subnet::of_peer(source));
+        }
+    }
+    let mut confirmed = sources
+        .into_iter()
+        .filter(|(_, subnets)| subnets.len() >= min_sources.max(1))
+        .map(|((_, address), _)| address)
+        .collect::<Vec<_>>();
+    confirmed.sort();
+    confirmed.dedup();
+    confirmed
+}
+
+/// Probes random members once, returning the number of the peer records merged.
+///
+/// The known members are probed at their known addresses, and the addresses of all the probed members
+/// are asked to the known peers, to be probed if they pass the cross-check.
+pub async fn probe_members<P: RpcPrimitive>(
+    rpc: &P,
+    network_config: &NetworkConfig,
+    known_peers: &SharedKnownPeers,
+    config: &ProbeConfig,
+    rng: &mut StdRng,
+) -> usize {
+    let filter = network_config.peer_filter();
+    let mut members = network_config
+        .members
+        .iter()
+        .filter(|x| **x != network_config.public_key)
+        .cloned()
+        .collect::<Vec<_>>();
+    members.shuffle(rng);
+    members.truncate(config.members_per_round);
+    if members.is_empty() {
+        return 0;
+    }
+
+    let mut sources = known_peers.read().await;
+    sources.shuffle(rng);
+    let sources = diversify(sources, 1)
+        .into_iter()
+        .filter(|peer| peer.serves(PEER_EXCHANGE_PROTOCOL))
+        .take(config.sources_per_round)
+        .collect::<Vec<_>>();
+    let requests = sources.iter().map(|source| {
+        let members = members.clone();
+        async move {
+            match rpc
+                .request::<_, Vec<Peer>>(source, PEER_EXCHANGE_PROTOCOL, members)
+                .await
+            {
+                Ok(reported) => reported
+                    .into_iter()
+                    .map(|peer| (source.clone(), peer))
+                    .collect(),
+                Err(e) => {
+                    log::debug!("failed to ask {} for the members: {e}", source.public_key);
+                    Vec::new()
+                }
+            }
+        }
+    });
+    let reports = futures::future::join_all(requests)
+        .await
+        .into_iter()
+        .flatten()
+        // A source can't vouch for itself.
+        .filter(|(source, reported)| source.public_key != reported.public_key)
+        .filter(|(_, reported)| members.contains(&reported.public_key))
+        .collect::<Vec<_>>();
+
+    let mut addresses = known_peers
+        .read_filtered(|peer| members.contains(&peer.public_key))
+        .await
+        .iter()
+        .filter_map(record_address)
+        .collect::<Vec<_>>();
+    for address in cross_check(&reports, config.min_sources) {
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+    let mut merged = 0;
+    for address in addresses {
+        let network_id = &network_config.network_id;
+        let members = &network_config.members;
+        match merge_peer_record(rpc, address, network_id, members, &filter, known_peers).await {
+            Ok(()) => merged += 1,
+            Err(e) => log::debug!("failed to probe {address}: {e}"),
+        }
+    }
+    merged
+}
+
+/// Spawns a task that periodically probes random members.
+///
+/// The probed members are of the current `members` in each round.
+pub fn spawn_probe_task<P: RpcPrimitive>(
+    rpc: Arc<P>,
+    mut network_config: NetworkConfig,
+    members: SharedMembers,
+    known_peers: SharedKnownPeers,
+    config: ProbeConfig,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut rng = StdRng::from_entropy();
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_millis(config.interval_ms));
+        loop {
+            interval.tick().await;
+            network_config.members = members.read();
+            let merged = probe_members(
+                rpc.as_ref(),
+                &network_config,
+                &known_peers,
+                &config,
+                &mut rng,
+            )
+            .await;
+            log::debug!("merged {merged} peer records by probing the members");
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::{RpcConfig, TcpRpc};
+    use crate::seeds::serve_peer_record;
+    use peer_record::SignedPeerRecord;
+    use simperby_test_suite::*;
+
+    fn peer(public_key: PublicKey, address: SocketAddrV4, ports: &[(&str, u16)]) -> Peer {
+        Peer {
+            public_key,
+            name: address.to_string(),
+            address,
+            addresses: Vec::new(),
+            ports: ports
+                .iter()
+                .map(|(protocol, port)| (protocol.to_string(), *port))
+                .collect(),
+            metadata: Default::default(),
+            recently_seen_timestamp: 0,
+        }
+    }
+
+    fn network_config(seed: &str, members: Vec<PublicKey>) -> NetworkConfig {
+        let (public_key, private_key) = generate_keypair(seed);
+        NetworkConfig {
+            network_id: "test".to_owned(),
+            ports: HashMap::new(),
+            members,
+            public_key,
+            private_key: Some(private_key),
+            dns_seeds: Vec::new(),
+            enable_mdns: false,
+            pre_shared_key: None,
+            proxy: None,
+            denied_peers: Vec::new(),
+            allowed_peers: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn subnets() {
+        setup_test();
+        let key = PublicKey::zero();
+        let peers = ["10.0.0.1:1", "10.0.200.1:1", "10.1.0.1:1", "10.0.0.2:1"]
+            .iter()
+            .map(|x| peer(key.clone(), x.parse().unwrap(), &[]))
+            .collect::<Vec<_>>();
+        let names = |peers: Vec<Peer>| peers.into_iter().map(|x| x.name).collect::<Vec<_>>();
+        assert_eq!(
+            names(diversify(peers.clone(), 1)),
+            ["10.0.0.1:1", "10.1.0.1:1"]
+        );
+        assert_eq!(
+            names(diversify(peers, 2)),
+            ["10.0.0.1:1", "10.0.200.1:1", "10.1.0.1:1"]
+        );
+        assert_eq!(
+            Subnet::of(&"2001:db8:1::1".parse().unwrap()),
+            Subnet::of(&"2001:db8:2::1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn cross_checking() {
+        setup_test();
+        let member = generate_keypair("member").0;
+        let source = |i: u8, subnet: u8| {
+            peer(
+                generate_keypair([i]).0,
+                SocketAddrV4::new([10, subnet, 0, i].into(), 1),
+                &[],
+            )
+        };
+        let reported = |port: u16| {
+            peer(
+                member.clone(),
+                "10.9.0.1:1".parse().unwrap(),
+                &[(PEER_RECORD_PROTOCOL, port)],
+            )
+        };
+        let reports = vec![
+            (source(1, 1), reported(100)),
+            // The same subnet.
+            (source(2, 1), reported(100)),
+            (source(3, 2), reported(200)),
+            (source(4, 3), reported(200)),
+        ];
+        assert_eq!(
+            cross_check(&reports, 2),
+            vec!["10.9.0.1:200".parse().unwrap()]
+        );
+        assert_eq!(cross_check(&reports, 1).len(), 2);
+        assert!(cross_check(&reports, 3).is_empty());
+    }
+
+    #[tokio::test]
+    async fn peer_exchange_cap() {
+        setup_test();
+        let port = dispense_port();
+        let address = SocketAddrV4::new([127, 0, 0, 1].into(), 1);
+        let keys = (0..5)
+            .map(|i| generate_keypair(format!("peer-{i}")).0)
+            .collect::<Vec<_>>();
+        let known_peers = SharedKnownPeers::new_static(
+            keys.iter()
+                .map(|key| peer(key.clone(), address, &[]))
+                .collect(),
+        );
+        let rpc = TcpRpc::new(RpcConfig::default());
+        let config = DiscoveryConfig {
+            max_response_peers: 3,
+            ..Default::default()
+        };
+        let task = serve_peer_exchange(&rpc, port, known_peers, &config)
+            .await
+            .unwrap();
+        let source = peer(
+            PublicKey::zero(),
+            address,
+            &[(PEER_EXCHANGE_PROTOCOL, port)],
+        );
+        let peers: Vec<Peer> = rpc
+            .request(&source, PEER_EXCHANGE_PROTOCOL, keys)
+            .await
+            .unwrap();
+        assert_eq!(peers.len(), 3);
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn probing() {
+        setup_test();
+        let target = network_config("target", Vec::new());
+        let members = vec![
+            generate_keypair("me").0,
+            target.public_key.clone(),
+            generate_keypair("source-1").0,
+            generate_keypair("source-2").0,
+        ];
+        let rpc = |address: &str| {
+            TcpRpc::new(RpcConfig {
+                timeout_ms: 500,
+                listen_addresses: vec![address.parse().unwrap()],
+                ..Default::default()
+            })
+        };
+
+        // The target serves its record, and the sources (in distinct subnets) know it.
+        let record_port = dispense_port();
+        let record_address = SocketAddrV4::new([127, 0, 0, 1].into(), record_port);
+        let record = SignedPeerRecord::new(
+            &NetworkConfig {
+                ports: vec![(PEER_RECORD_PROTOCOL.to_owned(), record_port)]
+                    .into_iter()
+                    .collect(),
+                ..target.clone()
+            },
+            "target".to_owned(),
+            record_address,
+            Vec::new(),
+            Default::default(),
+            1,
+        )
+        .unwrap();
+        let mut tasks = vec![serve_peer_record(&rpc("127.0.0.1"), record_port, record)
+            .await
+            .unwrap()];
+        let mut sources = Vec::new();
+        for (i, address) in ["127.0.0.1", "127.1.0.1"].into_iter().enumerate() {
+            let port = dispense_port();
+            let known_peers = SharedKnownPeers::new_static(vec![peer(
+                target.public_key.clone(),
+                record_address,
+                &[(PEER_RECORD_PROTOCOL, record_port)],
+            )]);
+            tasks.push(
+                serve_peer_exchange(
+                    &rpc(address),
+                    port,
+                    known_peers,
+                    &DiscoveryConfig::default(),
+                )
+                .await
+                .unwrap(),
+            );
+            sources.push(peer(
+                members[2 + i].clone(),
+                SocketAddrV4::new(address.parse().unwrap(), 1),
+                &[(PEER_EXCHANGE_PROTOCOL, port)],
+            ));
+        }
+
+        let network_config = network_config("me", members);
+        let config = |min_sources| ProbeConfig {
+            members_per_round: 3,
+            sources_per_round: 2,
+            min_sources,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let rpc = rpc("0.0.0.0");
+        let known_peers = SharedKnownPeers::new_static(sources.clone());
+        assert_eq!(
+            probe_members(&rpc, &network_config, &known_peers, &config(3), &mut rng).await,
+            0
+        );
+        assert_eq!(known_peers.read().await.len(), 2);
+        assert_eq!(
+            probe_members(&rpc, &network_config, &known_peers, &config(2), &mut rng).await,
+            1
+        );
+        assert!(known_peers
+            .read()
+            .await
+            .iter()
+            .any(|x| x.public_key == target.public_key && x.address == record_address));
+        for task in tasks {
+            task.abort();
+        }
+    }
+}
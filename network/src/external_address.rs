@@ -0,0 +1,411 @@
+//! Discovering the external address of this node.
+//!
+//! A node behind a NAT or in a container doesn't know the address at which the others reach it,
+//! so the configured one may be wrong. Instead, like AutoNAT, the node asks its peers
+//! from which address they observe its requests ([`OBSERVED_ADDRESS_PROTOCOL`]),
+//! and advertises the one agreed on by the observers in multiple subnets in its signed peer record.
+//!
+//! Only the IP is taken from the observations, since the source port of an outgoing connection
+//! says nothing about the port that the node listens on.
+use super::*;
+use crate::eclipse::Subnet;
+use crate::peer_record::PeerRecord;
+use crate::seeds::PEER_RECORD_PROTOCOL;
+use parking_lot::Mutex;
+use rand::seq::SliceRandom;
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+
+/// The protocol identifier of telling the requester its observed address, in `Peer::ports`.
+pub const OBSERVED_ADDRESS_PROTOCOL: &str = "observed-address";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ExternalAddressConfig {
+    /// How often the observers are asked, in milliseconds.
+    pub interval_ms: u64,
+    /// The number of the random known peers asked in a round.
+    pub observers_per_round: usize,
+    /// The number of the observers in distinct subnets that must report the same address.
+    pub min_observers: usize,
+    /// How long an observation counts, in milliseconds.
+    pub observation_ttl_ms: u64,
+}
+
+impl Default for ExternalAddressConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: 60 * 1000,
+            observers_per_round: 4,
+            min_observers: 2,
+            observation_ttl_ms: 10 * 60 * 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Observation {
+    ip: IpAddr,
+    /// The subnet of the observer.
+    subnet: Subnet,
+    timestamp: Timestamp,
+}
+
+/// The addresses of this node observed by the peers, which can be shared by multiple components of a node.
+#[derive(Debug)]
+pub struct ExternalAddress {
+    config: ExternalAddressConfig,
+    /// The last observation of each observer, so that a single peer can't outvote the others.
+    observations: Mutex<HashMap<PublicKey, Observation>>,
+}
+
+impl ExternalAddress {
+    pub fn new(config: ExternalAddressConfig) -> Self {
+        Self {
+            config,
+            observations: Default::default(),
+        }
+    }
+
+    /// Records the address of this node reported by the observer.
+    pub fn record(&self, observer: &Peer, ip: IpAddr, now: Timestamp) {
+        self.observations.lock().insert(
+            observer.public_key.clone(),
+            Observation {
+                ip,
+                subnet: Subnet::of_peer(observer),
+                timestamp: now,
+            },
+        );
+    }
+
+    /// Returns the address observed from the most subnets at `now`, if they're at least
+    /// `min_observers` and more than those of any other address.
+    pub fn consensus(&self, now: Timestamp) -> Option<IpAddr> {
+        let mut observations = self.observations.lock();
+        let ttl = self.config.observation_ttl_ms as Timestamp;
+        observations.retain(|_, x| now.saturating_sub(x.timestamp) <= ttl);
+        let mut subnets = HashMap::<IpAddr, HashSet<Subnet>>::new();
+        for observation in observations.values() {
+            subnets
+                .entry(observation.ip)
+                .or_default()
+                .insert(observation.subnet);
+        }
+        let mut counts = subnets
+            .into_iter()
+            .map(|(ip, subnets)| (subnets.len(), ip))
+            .collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.cmp(a));
+        match counts.as_slice() {
+            [(first, ip), rest @ ..]
+                if *first >= self.config.min_observers.max(1)
+                    && rest.first().map_or(true, |(second, _)| second < first) =>
+            {
+                Some(*ip)
+            }
+            _ => None,
+        }
+    }
+
+    /// Replaces the configured address in the record with the consensus at `now`, if any.
+    ///
+    /// An IPv4 address replaces the IP of `PeerRecord::address` (keeping the port),
+    /// while an IPv6 address is added to `PeerRecord::addresses`.
+    pub fn advertise(&self, record: &mut PeerRecord, now: Timestamp) {
+        match self.consensus(now) {
+            Some(IpAddr::V4(ip)) => record.address.set_ip(ip),
+            Some(ip @ IpAddr::V6(_)) if !record.addresses.contains(&PeerAddress::Ip(ip)) => {
+                record.addresses.push(PeerAddress::Ip(ip));
+            }
+            _ => (),
+        }
+    }
+
+    /// Creates the record of this node (as described by `own`, with the ports in the config),
+    /// advertising the external address.
+    ///
+    /// If the address of `own` is unspecified (`0.0.0.0`), it fails until the observers agree on one.
+    pub async fn sign_record(
+        &self,
+        network_config: &NetworkConfig,
+        own: &Peer,
+        signer: &dyn Signer,
+        now: Timestamp,
+    ) -> Result<SignedPeerRecord, Error> {
+        let mut record = PeerRecord {
+            network_id: network_config.network_id.clone(),
+            public_key: network_config.public_key.clone(),
+            name: own.name.clone(),
+            address: own.address,
+            addresses: own.addresses.clone(),
+            ports: network_config.ports.clone(),
+            metadata: own.metadata.clone(),
+            timestamp: now,
+        };
+        self.advertise(&mut record, now);
+        if record.address.ip().is_unspecified() {
+            return Err(eyre::eyre!("the address of this node is not known yet"));
+        }
+        let signature = TypedSignature::sign_by(&record, signer).await?;
+        Ok(SignedPeerRecord { record, signature })
+    }
+}
+
+/// Tells the requesters the addresses that their requests come from.
+pub async fn serve_observed_address<P: RpcPrimitive>(
+    rpc: &P,
+    port: u16,
+) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error> {
+    rpc.serve_protocol(OBSERVED_ADDRESS_PROTOCOL, port, |_: ()| async move {
+        rpc::remote_address()
+            .map(|x| x.ip())
+            .ok_or_else(|| "the remote address is unknown".to_owned())
+    })
+    .await
+}
+
+/// Serves the peer record of this node like [`crate::seeds::serve_peer_record()`],
+/// but signed on each request to advertise the current external address.
+pub async fn serve_advertised_peer_record<P: RpcPrimitive>(
+    rpc: &P,
+    port: u16,
+    network_config: NetworkConfig,
+    own: Peer,
+    signer: Arc<dyn Signer>,
+    external_address: Arc<ExternalAddress>,
+) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error> {
+    let (network_config, own) = (Arc::new(network_config), Arc::new(own));
+    rpc.serve_protocol(PEER_RECORD_PROTOCOL, port, move |_: ()| {
+        let (network_config, own) = (Arc::clone(&network_config), Arc::clone(&own));
+        let (signer, external_address) = (Arc::clone(&signer), Arc::clone(&external_address));
+        async move {
+            let now = chrono::Utc::now().timestamp_millis() as Timestamp;
+            external_address
+                .sign_record(&network_config, &own, signer.as_ref(), now)
+                .await
+                .map_err(|e| e.to_string())
+        }
+    })
+    .await
+}
+
+/// Asks random known peers (except this node) for the address of this node once,
+/// returning the number of the observations recorded.
+pub async fn observe_once<P: RpcPrimitive>(
+    rpc: &P,
+    network_config: &NetworkConfig,
+    known_peers: &SharedKnownPeers,
+    external_address: &ExternalAddress,
+) -> usize {
+    let mut observers = known_peers
+        .read_filtered(|x| {
+            x.public_key != network_config.public_key && x.serves(OBSERVED_ADDRESS_PROTOCOL)
+        })
+        .await;
+    observers.shuffle(&mut rand::thread_rng());
+    observers.truncate(external_address.config.observers_per_round);
+    let requests = observers.iter().map(|observer| async move {
+        let result = rpc
+            .request::<(), IpAddr>(observer, OBSERVED_ADDRESS_PROTOCOL, ())
+            .await;
+        (observer, result)
+    });
+    let mut recorded = 0;
+    for (observer, result) in futures::future::join_all(requests).await {
+        match result {
+            Ok(ip) if !ip.is_unspecified() && ip != IpAddr::V4(Ipv4Addr::BROADCAST) => {
+                let now = chrono::Utc::now().timestamp_millis() as Timestamp;
+                external_address.record(observer, ip, now);
+                recorded += 1;
+            }
+            Ok(ip) => log::debug!("{} observed an invalid address {ip}", observer.public_key),
+            Err(e) => log::debug!("failed to ask {} for the address: {e}", observer.public_key),
+        }
+    }
+    recorded
+}
+
+/// Asks the known peers for the address of this node periodically, indefinitely.
+pub async fn run_address_observer<P: RpcPrimitive>(
+    rpc: Arc<P>,
+    network_config: NetworkConfig,
+    known_peers: SharedKnownPeers,
+    external_address: Arc<ExternalAddress>,
+) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_millis(
+        external_address.config.interval_ms,
+    ));
+    loop {
+        interval.tick().await;
+        observe_once(
+            rpc.as_ref(),
+            &network_config,
+            &known_peers,
+            &external_address,
+        )
+        .await;
+        let now = chrono::Utc::now().timestamp_millis() as Timestamp;
+        if let Some(ip) = external_address.consensus(now) {
+            tracing::debug!(%ip, "observed the external address");
+        }
+    }
+}
+
+/// Spawns [`run_address_observer`] as a task.
+pub fn spawn_address_observer<P: RpcPrimitive>(
+    rpc: Arc<P>,
+    network_config: NetworkConfig,
+    known_peers: SharedKnownPeers,
+    external_address: Arc<ExternalAddress>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(run_address_observer(
+        rpc,
+        network_config,
+        known_peers,
+        external_address,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::in_memory::InMemoryRpc;
+    use simperby_test_suite::*;
+
+    fn peer(seed: &str, address: &str, port: u16) -> Peer {
+        Peer {
+            public_key: generate_keypair(seed).0,
+            name: seed.to_owned(),
+            address: format!("{address}:1").parse().unwrap(),
+            addresses: Vec::new(),
+            ports: vec![
+                (OBSERVED_ADDRESS_PROTOCOL.to_owned(), port),
+                (PEER_RECORD_PROTOCOL.to_owned(), port),
+            ]
+            .into_iter()
+            .collect(),
+            metadata: Default::default(),
+            recently_seen_timestamp: 0,
+        }
+    }
+
+    fn config(min_observers: usize) -> ExternalAddressConfig {
+        ExternalAddressConfig {
+            interval_ms: 50,
+            observers_per_round: 10,
+            min_observers,
+            observation_ttl_ms: 100,
+        }
+    }
+
+    #[test]
+    fn consensus() {
+        setup_test();
+        let external_address = ExternalAddress::new(config(2));
+        let (x, y): (IpAddr, IpAddr) = ("1.2.3.4".parse().unwrap(), "5.6.7.8".parse().unwrap());
+        external_address.record(&peer("a", "10.0.0.1", 1), x, 0);
+        assert_eq!(external_address.consensus(0), None);
+
+        // The observers in the same subnet count once.
+        external_address.record(&peer("b", "10.0.0.2", 1), x, 0);
+        assert_eq!(external_address.consensus(0), None);
+        external_address.record(&peer("c", "10.1.0.1", 1), x, 0);
+        assert_eq!(external_address.consensus(0), Some(x));
+
+        // A tie, where an observer counts only its last observation.
+        external_address.record(&peer("d", "10.2.0.1", 1), y, 0);
+        external_address.record(&peer("e", "10.3.0.1", 1), y, 0);
+        assert_eq!(external_address.consensus(0), None);
+        external_address.record(&peer("a", "10.0.0.1", 1), y, 0);
+        assert_eq!(external_address.consensus(0), Some(y));
+
+        let mut record = PeerRecord {
+            network_id: "test".to_owned(),
+            public_key: generate_keypair("me").0,
+            name: "me".to_owned(),
+            address: "192.168.0.1:2000".parse().unwrap(),
+            addresses: Vec::new(),
+            ports: HashMap::new(),
+            metadata: Default::default(),
+            timestamp: 0,
+        };
+        external_address.advertise(&mut record, 0);
+        assert_eq!(record.address, "5.6.7.8:2000".parse().unwrap());
+
+        // Expired.
+        assert_eq!(external_address.consensus(101), None);
+    }
+
+    #[tokio::test]
+    async fn observe() {
+        setup_test();
+        let network_id = "external-address";
+        let port = 1000;
+        let (me, private_key) = generate_keypair("me");
+        let network_config = NetworkConfig {
+            network_id: network_id.to_owned(),
+            ports: vec![(PEER_RECORD_PROTOCOL.to_owned(), port)]
+                .into_iter()
+                .collect(),
+            members: Vec::new(),
+            public_key: me.clone(),
+            private_key: None,
+            dns_seeds: Vec::new(),
+            enable_mdns: false,
+            pre_shared_key: None,
+            proxy: None,
+            denied_peers: Vec::new(),
+            allowed_peers: Vec::new(),
+        };
+        // Only the observers in distinct subnets agree; the node itself is never asked.
+        let observers = [("a", "10.0.0.1"), ("b", "10.1.0.1"), ("me", "10.2.0.1")];
+        let mut tasks = Vec::new();
+        let mut known_peers = Vec::new();
+        for (seed, address) in observers {
+            let rpc = InMemoryRpc::new(network_id, address.parse().unwrap());
+            tasks.push(serve_observed_address(&rpc, port).await.unwrap());
+            known_peers.push(peer(seed, address, port));
+        }
+        let known_peers = SharedKnownPeers::new_static(known_peers);
+
+        // This node is at `7.7.7.7` as seen by the others, while configured as `192.168.0.1`.
+        let rpc = Arc::new(InMemoryRpc::new(network_id, "7.7.7.7".parse().unwrap()));
+        let external_address = Arc::new(ExternalAddress::new(ExternalAddressConfig {
+            observation_ttl_ms: 60 * 1000,
+            ..config(2)
+        }));
+        let recorded = observe_once(
+            rpc.as_ref(),
+            &network_config,
+            &known_peers,
+            &external_address,
+        )
+        .await;
+        assert_eq!(recorded, 2);
+        let own = peer("me", "192.168.0.1", port);
+        let record_rpc = InMemoryRpc::new(network_id, "10.3.0.1".parse().unwrap());
+        tasks.push(
+            serve_advertised_peer_record(
+                &record_rpc,
+                port,
+                network_config,
+                own,
+                Arc::new(private_key),
+                Arc::clone(&external_address),
+            )
+            .await
+            .unwrap(),
+        );
+        let server = peer("me", "10.3.0.1", port);
+        let record: SignedPeerRecord = rpc
+            .request(&server, PEER_RECORD_PROTOCOL, ())
+            .await
+            .unwrap();
+        let advertised = record.into_peer().unwrap();
+        assert_eq!(advertised.public_key, me);
+        assert_eq!(advertised.address, "7.7.7.7:1".parse().unwrap());
+        for task in tasks {
+            task.abort();
+        }
+    }
+}
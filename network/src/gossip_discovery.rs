@@ -0,0 +1,692 @@
+//! Peer discovery over the gossip network.
+//!
+//! Instead of a dedicated discovery port, [`GossipPeerDiscovery`] broadcasts the signed peer record
+//! of this node over the [`GossipNetwork`] that the node already serves (e.g., for the DMS),
+//! once every discovery round, and merges the records broadcast by the other members.
+//! So a validator exposes no more ports than the gossip needs, and the records travel over
+//! the transport of the gossip, with whatever authentication it has.
+//!
+//! On shutdown, the node broadcasts a signed [`PeerDeparture`], so that the others forget it
+//! right away rather than after the peer expiry, and don't take it back from the stale records
+//! still being relayed (see `PeerExpiry::tombstone_ttl_ms`).
+//!
+//! The messages of the discovery are distinguished from the other gossip by [`DISCOVERY_GOSSIP_PREFIX`].
+//!
+//! The address in the record of this node is taken from its own entry in the initially known peers.
+//! Without one, the IP is left unspecified, and the receivers only update the peer if they know
+//! its address already.
+use super::*;
+use crate::peer_store::{merge_peers, PeerStore};
+use std::marker::PhantomData;
+use std::net::Ipv4Addr;
+
+/// The prefix of a message of the discovery on the gossip network.
+pub const DISCOVERY_GOSSIP_PREFIX: &[u8] = b"simperby-peer-discovery:";
+
+/// The announcement of a node leaving the network.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PeerDeparture {
+    pub public_key: PublicKey,
+    /// When the node left, so that a departure doesn't remove a later record of the node.
+    pub timestamp: Timestamp,
+}
+
+impl ToHash256 for PeerDeparture {
+    fn to_hash256(&self) -> Hash256 {
+        Hash256::hash(serde_spb::to_vec(self).unwrap())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SignedPeerDeparture {
+    pub departure: PeerDeparture,
+    pub signature: TypedSignature<PeerDeparture>,
+}
+
+impl SignedPeerDeparture {
+    pub fn new(network_config: &NetworkConfig, timestamp: Timestamp) -> Result<Self, Error> {
+        let departure = PeerDeparture {
+            public_key: network_config.public_key.clone(),
+            timestamp,
+        };
+        let signature = TypedSignature::sign(&departure, network_config.private_key()?)?;
+        Ok(Self {
+            departure,
+            signature,
+        })
+    }
+
+    /// Checks that the departure is signed by the node that leaves.
+    pub fn verify(&self) -> Result<(), Error> {
+        if *self.signature.signer() != self.departure.public_key {
+            return Err(eyre::eyre!(
+                "departure of {} is signed by {}",
+                self.departure.public_key,
+                self.signature.signer()
+            ));
+        }
+        self.signature
+            .verify(&self.departure)
+            .map_err(|e| eyre::eyre!("invalid signature on the departure: {e}"))
+    }
+}
+
+/// A message of the discovery on the gossip network.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DiscoveryGossip {
+    Record(SignedPeerRecord),
+    Departure(SignedPeerDeparture),
+}
+
+impl DiscoveryGossip {
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut message = DISCOVERY_GOSSIP_PREFIX.to_vec();
+        message.extend(serde_spb::to_vec(self)?);
+        Ok(message)
+    }
+
+    /// Decodes the gossip message, or returns `None` if it's not of the discovery.
+    pub fn decode(message: &[u8]) -> Option<Result<Self, Error>> {
+        let message = message.strip_prefix(DISCOVERY_GOSSIP_PREFIX)?;
+        Some(serde_spb::from_slice(message).map_err(Error::from))
+    }
+}
+
+/// Whether the gossip message is of the discovery, rather than of the other protocols.
+pub fn is_discovery_gossip(message: &[u8]) -> bool {
+    message.starts_with(DISCOVERY_GOSSIP_PREFIX)
+}
+
+/// A [`PeerDiscoveryPrimitive`] that piggybacks the peer records on the gossip network `N`.
+pub struct GossipPeerDiscovery<N: GossipNetwork> {
+    _network: PhantomData<N>,
+}
+
+/// Merges the peer record received from the gossip network into the known peers.
+pub async fn merge_gossiped_record(
+    network_config: &NetworkConfig,
+    known_peers: &SharedKnownPeers,
+    record: SignedPeerRecord,
+) -> Result<(), Error> {
+    if record.record.public_key == network_config.public_key {
+        return Ok(());
+    }
+    record.check_network(&network_config.network_id)?;
+    if !network_config.members.contains(&record.record.public_key) {
+        return Err(eyre::eyre!(
+            "peer record of a non-member {}",
+            record.record.public_key
+        ));
+    }
+    let mut peer = record.into_peer()?;
+    let known = known_peers.get_peer(&peer.public_key).await;
+    if let Some(known) = &known {
+        if known.recently_seen_timestamp > peer.recently_seen_timestamp {
+            return Err(eyre::eyre!(
+                "peer record of {} is older than the known one",
+                peer.public_key
+            ));
+        }
+    }
+    if peer.address.ip().is_unspecified() {
+        match known {
+            Some(known) => peer.address = known.address,
+            None => {
+                return Err(eyre::eyre!(
+                    "peer record of {} has no address",
+                    peer.public_key
+                ))
+            }
+        }
+    }
+    network_config.peer_filter().check_peer(&peer)?;
+    known_peers.add_or_replace(peer).await;
+    Ok(())
+}
+
+/// Marks the peer that announced its departure as departed (see [`SharedKnownPeers::mark_departed()`]).
+pub async fn merge_gossiped_departure(
+    network_config: &NetworkConfig,
+    known_peers: &SharedKnownPeers,
+    departure: SignedPeerDeparture,
+) -> Result<(), Error> {
+    if departure.departure.public_key == network_config.public_key {
+        return Ok(());
+    }
+    departure.verify()?;
+    let departure = departure.departure;
+    known_peers
+        .mark_departed(&departure.public_key, departure.timestamp)
+        .await;
+    Ok(())
+}
+
+#[async_trait]
+impl<N: GossipNetwork> PeerDiscoveryPrimitive for GossipPeerDiscovery<N> {
+    /// Broadcasts the record of this node to `DiscoveryConfig::fanout` random known peers
+    /// each round.
+    ///
+    /// The propagation beyond them is up to the gossip network,
+    /// so `DiscoveryConfig::max_response_peers` doesn't apply.
+    ///
+    /// On shutdown, the departure is broadcast to all the known peers and the store is saved.
+    async fn serve<S: PeerStore>(
+        network_config: NetworkConfig,
+        metadata: PeerMetadata,
+        port_map: HashMap<String, u16>,
+        initially_known_peers: Vec<Peer>,
+        peer_store: S,
+        peer_expiry: PeerExpiry,
+        discovery: DiscoveryConfig,
+    ) -> Result<(SharedKnownPeers, ServiceHandle), Error> {
+        let initially_known_peers = merge_peers(initially_known_peers, peer_store.load().await?);
+        let (name, address, addresses) = initially_known_peers
+            .iter()
+            .find(|x| x.public_key == network_config.public_key)
+            .map(|x| (x.name.clone(), x.address, x.addresses.clone()))
+            .unwrap_or_else(|| {
+                let address = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0);
+                (String::new(), address, Vec::new())
+            });
+        let known_peers = SharedKnownPeers::new_static(initially_known_peers);
+        let (mut messages, gossip) = N::serve(network_config.clone(), known_peers.clone()).await?;
+        let record_config = NetworkConfig {
+            ports: port_map,
+            ..network_config.clone()
+        };
+        let known_peers_ = known_peers.clone();
+        let handle = ServiceHandle::spawn(move |mut shutdown| async move {
+            let known_peers = known_peers_;
+            let mut round = tokio::time::interval(std::time::Duration::from_millis(
+                discovery.round_interval_ms,
+            ));
+            let mut eviction = tokio::time::interval(std::time::Duration::from_millis(
+                peer_expiry.eviction_interval_ms,
+            ));
+            loop {
+                tokio::select! {
+                    message = messages.recv() => {
+                        let message = match message {
+                            Some(x) => x,
+                            None => return Ok(()),
+                        };
+                        let result = match DiscoveryGossip::decode(&message) {
+                            Some(Ok(DiscoveryGossip::Record(record))) => {
+                                merge_gossiped_record(&network_config, &known_peers, record).await
+                            }
+                            Some(Ok(DiscoveryGossip::Departure(departure))) => {
+                                merge_gossiped_departure(&network_config, &known_peers, departure)
+                                    .await
+                            }
+                            Some(Err(e)) => Err(e),
+                            None => Ok(()),
+                        };
+                        if let Err(e) = result {
+                            log::debug!("discarded a gossiped discovery message: {e}");
+                        }
+                    }
+                    _ = round.tick() => {
+                        let now = chrono::Utc::now().timestamp_millis() as Timestamp;
+                        let record = SignedPeerRecord::new(
+                            &record_config,
+                            name.clone(),
+                            address,
+                            addresses.clone(),
+                            metadata.clone(),
+                            now,
+                        )?;
+                        let message = DiscoveryGossip::Record(record).encode()?;
+                        let peers = known_peers.sample_peers(discovery.fanout, rand::random()).await;
+                        if let Err(e) = N::broadcast(&network_config, &peers, message).await {
+                            log::warn!("failed to broadcast the peer record: {e}");
+                        }
+                        if let Err(e) = peer_store.save(&known_peers.read().await).await {
+                            log::warn!("failed to save the known peers: {e}");
+                        }
+                    }
+                    _ = eviction.tick() => {
+                        let now = chrono::Utc::now().timestamp_millis() as Timestamp;
+                        known_peers.evict_expired(&peer_expiry, now).await;
+                    }
+                    _ = shutdown.requested() => {
+                        let now = chrono::Utc::now().timestamp_millis() as Timestamp;
+                        let departure = SignedPeerDeparture::new(&network_config, now)?;
+                        let message = DiscoveryGossip::Departure(departure).encode()?;
+                        let peers = known_peers.read().await;
+                        if let Err(e) = N::broadcast(&network_config, &peers, message).await {
+                            log::warn!("failed to broadcast the departure: {e}");
+                        }
+                        if let Err(e) = peer_store.save(&peers).await {
+                            log::warn!("failed to save the known peers: {e}");
+                        }
+                        return gossip.shutdown().await;
+                    }
+                }
+            }
+        });
+        Ok((known_peers, handle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::in_memory::{reset_network, InMemoryGossipNetwork};
+    use crate::peer_store::NoPeerStore;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use simperby_test_suite::*;
+
+    fn network_config(seed: &str, members: Vec<PublicKey>) -> NetworkConfig {
+        let (public_key, private_key) = generate_keypair(seed);
+        NetworkConfig {
+            network_id: "gossip-discovery".to_owned(),
+            ports: HashMap::new(),
+            members,
+            public_key,
+            private_key: Some(private_key),
+            dns_seeds: Vec::new(),
+            enable_mdns: false,
+            pre_shared_key: None,
+            proxy: None,
+            denied_peers: Vec::new(),
+            allowed_peers: Vec::new(),
+        }
+    }
+
+    fn own_peer(config: &NetworkConfig, address: &str) -> Peer {
+        Peer {
+            public_key: config.public_key.clone(),
+            name: address.to_owned(),
+            address: address.parse().unwrap(),
+            addresses: Vec::new(),
+            ports: HashMap::new(),
+            metadata: Default::default(),
+            recently_seen_timestamp: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn discovery() {
+        setup_test();
+        let seeds = ["a", "b", "c"];
+        let members = seeds
+            .iter()
+            .map(|x| generate_keypair(x).0)
+            .collect::<Vec<_>>();
+        let discovery = DiscoveryConfig {
+            round_interval_ms: 50,
+            ..Default::default()
+        };
+        let mut nodes = Vec::new();
+        for (i, seed) in seeds.iter().enumerate() {
+            let config = network_config(seed, members.clone());
+            // Only `a` advertises its address, which the others don't know.
+            let initially_known_peers = if i == 0 {
+                vec![own_peer(&config, "127.0.0.1:3000")]
+            } else {
+                Vec::new()
+            };
+            let port_map = vec![("dms".to_owned(), 3000 + i as u16)]
+                .into_iter()
+                .collect();
+            nodes.push(
+                GossipPeerDiscovery::<InMemoryGossipNetwork>::serve(
+                    config,
+                    Default::default(),
+                    port_map,
+                    initially_known_peers,
+                    NoPeerStore,
+                    PeerExpiry::default(),
+                    discovery.clone(),
+                )
+                .await
+                .unwrap(),
+            );
+        }
+        // A non-member broadcasts its record too.
+        let outsider = network_config("outsider", members.clone());
+        let _outsider = GossipPeerDiscovery::<InMemoryGossipNetwork>::serve(
+            outsider.clone(),
+            Default::default(),
+            HashMap::new(),
+            vec![own_peer(&outsider, "127.0.0.9:3000")],
+            NoPeerStore,
+            PeerExpiry::default(),
+            discovery,
+        )
+        .await
+        .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+
+        // Everyone learns `a`, with its ports; `b` and `c` can't be reached without an address.
+        for (known_peers, _) in &nodes[1..] {
+            let peers = known_peers.read().await;
+            assert_eq!(peers.len(), 1);
+            assert_eq!(peers[0].public_key, members[0]);
+            assert_eq!(peers[0].ports["dms"], 3000);
+        }
+        assert!(nodes[0]
+            .0
+            .read_filtered(|x| x.public_key != members[0])
+            .await
+            .is_empty());
+
+        // Once `c` knows the address of `b`, the records of `b` keep it updated.
+        let b = own_peer(&network_config("b", Vec::new()), "127.0.0.2:3000");
+        nodes[2].0.add_or_replace(b).await;
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let peer = nodes[2].0.get_peer(&members[1]).await.unwrap();
+        assert_eq!(peer.address, "127.0.0.2:3000".parse().unwrap());
+        assert_eq!(peer.ports["dms"], 3001);
+
+        // `a` leaves, and is forgotten without waiting for the expiry.
+        let mut nodes = nodes.into_iter();
+        let (_, a) = nodes.next().unwrap();
+        a.shutdown().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        for (known_peers, handle) in nodes {
+            assert!(known_peers.get_peer(&members[0]).await.is_none());
+            handle.shutdown().await.unwrap();
+        }
+    }
+
+    #[test]
+    fn encoding() {
+        setup_test();
+        let config = network_config("a", Vec::new());
+        let record = SignedPeerRecord::new(
+            &config,
+            "a".to_owned(),
+            "127.0.0.1:1".parse().unwrap(),
+            Vec::new(),
+            Default::default(),
+            0,
+        )
+        .unwrap();
+        let message = DiscoveryGossip::Record(record.clone()).encode().unwrap();
+        assert!(is_discovery_gossip(&message));
+        assert_eq!(
+            DiscoveryGossip::decode(&message).unwrap().unwrap(),
+            DiscoveryGossip::Record(record)
+        );
+        assert!(DiscoveryGossip::decode(b"something else").is_none());
+
+        // Only the leaving node can announce its departure.
+        let mut departure = SignedPeerDeparture::new(&config, 0).unwrap();
+        assert!(departure.verify().is_ok());
+        departure.departure.public_key = generate_keypair("b").0;
+        assert!(departure.verify().is_err());
+    }
+
+    /// A step of a churn scenario, on the member at the index.
+    #[derive(Debug, Clone)]
+    enum Step {
+        Join(usize),
+        /// Shuts the node down, so that it announces its departure.
+        Leave(usize),
+        /// Aborts the node, which is then forgotten only once its record expires.
+        Crash(usize),
+        Wait(u64),
+    }
+
+    /// The number of the online members that a random scenario never goes below.
+    const MIN_ONLINE_MEMBERS: usize = 2;
+
+    /// Generates a random scenario of joins, leaves, crashes and waits from the seed,
+    /// starting with all the `members` offline.
+    fn random_scenario(seed: u64, members: usize, length: usize) -> Vec<Step> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut online = vec![false; members];
+        let mut scenario = Vec::new();
+        for _ in 0..length {
+            let (up, down): (Vec<_>, Vec<_>) = (0..members).partition(|i| online[*i]);
+            let step = match rng.gen_range(0..4) {
+                0 if !down.is_empty() => Step::Join(*down.choose(&mut rng).unwrap()),
+                1 if up.len() > MIN_ONLINE_MEMBERS => Step::Leave(*up.choose(&mut rng).unwrap()),
+                2 if up.len() > MIN_ONLINE_MEMBERS => Step::Crash(*up.choose(&mut rng).unwrap()),
+                _ => Step::Wait(rng.gen_range(50..300)),
+            };
+            match step {
+                Step::Join(i) => online[i] = true,
+                Step::Leave(i) | Step::Crash(i) => online[i] = false,
+                Step::Wait(_) => (),
+            }
+            scenario.push(step);
+        }
+        scenario
+    }
+
+    /// Members of a network that join and depart, each advertising its own address.
+    struct ChurnNet {
+        network_id: String,
+        members: Vec<PublicKey>,
+        /// `None` for the members offline.
+        nodes: Vec<Option<(SharedKnownPeers, ServiceHandle)>>,
+        /// A non-member that keeps broadcasting its record.
+        _outsider: (SharedKnownPeers, ServiceHandle),
+    }
+
+    fn churn_seed(network_id: &str, index: usize) -> String {
+        format!("{network_id}-{index}")
+    }
+
+    fn churn_discovery() -> DiscoveryConfig {
+        DiscoveryConfig {
+            round_interval_ms: 50,
+            ..Default::default()
+        }
+    }
+
+    fn churn_expiry() -> PeerExpiry {
+        PeerExpiry {
+            ttl_ms: 1_000,
+            eviction_interval_ms: 100,
+            ..Default::default()
+        }
+    }
+
+    async fn serve_churn_node(
+        network_id: &str,
+        seed: &str,
+        members: Vec<PublicKey>,
+        address: &str,
+    ) -> (SharedKnownPeers, ServiceHandle) {
+        let config = NetworkConfig {
+            network_id: network_id.to_owned(),
+            ..network_config(seed, members)
+        };
+        let own_peer = own_peer(&config, address);
+        GossipPeerDiscovery::<InMemoryGossipNetwork>::serve(
+            config,
+            Default::default(),
+            HashMap::new(),
+            vec![own_peer],
+            NoPeerStore,
+            churn_expiry(),
+            churn_discovery(),
+        )
+        .await
+        .unwrap()
+    }
+
+    impl ChurnNet {
+        async fn new(network_id: &str, seed: u64, members: usize) -> Self {
+            reset_network(network_id, seed, 0.0);
+            let public_keys = (0..members)
+                .map(|i| generate_keypair(churn_seed(network_id, i)).0)
+                .collect::<Vec<_>>();
+            let outsider = serve_churn_node(
+                network_id,
+                &format!("{network_id}-outsider"),
+                public_keys.clone(),
+                "127.0.1.1:3000",
+            )
+            .await;
+            Self {
+                network_id: network_id.to_owned(),
+                members: public_keys,
+                nodes: (0..members).map(|_| None).collect(),
+                _outsider: outsider,
+            }
+        }
+
+        async fn add_member(&mut self, index: usize) {
+            let node = serve_churn_node(
+                &self.network_id,
+                &churn_seed(&self.network_id, index),
+                self.members.clone(),
+                &format!("127.0.0.{}:3000", index + 1),
+            )
+            .await;
+            self.nodes[index] = Some(node);
+        }
+
+        async fn remove_member(&mut self, index: usize) {
+            let (_, handle) = self.nodes[index].take().unwrap();
+            handle.shutdown().await.unwrap();
+        }
+
+        fn crash_member(&mut self, index: usize) {
+            let (_, handle) = self.nodes[index].take().unwrap();
+            handle.abort();
+        }
+
+        async fn run(&mut self, scenario: &[Step]) {
+            for step in scenario {
+                match step {
+                    Step::Join(i) => self.add_member(*i).await,
+                    Step::Leave(i) => self.remove_member(*i).await,
+                    Step::Crash(i) => self.crash_member(*i),
+                    Step::Wait(millis) => {
+                        tokio::time::sleep(std::time::Duration::from_millis(*millis)).await
+                    }
+                }
+            }
+        }
+
+        /// Returns what keeps the discovery from converging: an online member must know
+        /// all the other online members, and nothing else.
+        async fn violations(&self) -> Vec<String> {
+            let online = self
+                .nodes
+                .iter()
+                .enumerate()
+                .filter(|(_, node)| node.is_some())
+                .map(|(i, _)| (self.members[i].clone(), i))
+                .collect::<HashMap<_, _>>();
+            let mut violations = Vec::new();
+            for (i, node) in self.nodes.iter().enumerate() {
+                let Some((known_peers, _)) = node else {
+                    continue;
+                };
+                let known_peers = known_peers.read().await;
+                for (public_key, j) in &online {
+                    if *j != i && !known_peers.iter().any(|x| x.public_key == *public_key) {
+                        violations.push(format!("{i} doesn't know {j}"));
+                    }
+                }
+                for peer in known_peers {
+                    match online.get(&peer.public_key) {
+                        Some(_) => (),
+                        None => match self.members.iter().position(|x| *x == peer.public_key) {
+                            Some(j) => violations.push(format!("{i} still knows {j}")),
+                            None => violations.push(format!("{i} knows a non-member")),
+                        },
+                    }
+                }
+            }
+            violations
+        }
+
+        /// Waits until the discovery converges, returning how long it took,
+        /// or the violations left if it doesn't within the bound.
+        async fn wait_for_convergence(
+            &self,
+            bound: std::time::Duration,
+        ) -> Result<std::time::Duration, Vec<String>> {
+            let started = tokio::time::Instant::now();
+            loop {
+                let violations = self.violations().await;
+                if violations.is_empty() {
+                    return Ok(started.elapsed());
+                }
+                if started.elapsed() >= bound {
+                    return Err(violations);
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+        }
+
+        async fn shutdown(self) {
+            for (_, handle) in self.nodes.into_iter().flatten() {
+                handle.shutdown().await.unwrap();
+            }
+        }
+    }
+
+    /// The time within which the discovery must converge after a scenario.
+    const CONVERGENCE_BOUND: std::time::Duration = std::time::Duration::from_secs(10);
+
+    #[tokio::test]
+    async fn leave_and_crash() {
+        setup_test();
+        let mut net = ChurnNet::new("gossip-discovery-churn", 0, 4).await;
+        net.run(&[Step::Join(0), Step::Join(1), Step::Join(2), Step::Join(3)])
+            .await;
+        net.wait_for_convergence(CONVERGENCE_BOUND).await.unwrap();
+
+        // A leaving member is forgotten by its departure, well before its record expires.
+        net.remove_member(0).await;
+        let elapsed = net.wait_for_convergence(CONVERGENCE_BOUND).await.unwrap();
+        assert!(elapsed.as_millis() < churn_expiry().ttl_ms as u128 / 2);
+
+        // A crashed one is forgotten once its record expires.
+        net.crash_member(1);
+        assert!(!net.violations().await.is_empty());
+        net.wait_for_convergence(CONVERGENCE_BOUND).await.unwrap();
+
+        // Both come back.
+        net.run(&[Step::Join(0), Step::Join(1)]).await;
+        net.wait_for_convergence(CONVERGENCE_BOUND).await.unwrap();
+        net.shutdown().await;
+    }
+
+    #[test]
+    fn scenario_is_seeded() {
+        setup_test();
+        let scenario = |seed| format!("{:?}", random_scenario(seed, 6, 30));
+        assert_eq!(scenario(1), scenario(1));
+        assert_ne!(scenario(1), scenario(2));
+    }
+
+    async fn random_churn(seed: u64) {
+        let scenario = random_scenario(seed, 6, 30);
+        let mut net = ChurnNet::new(&format!("gossip-discovery-churn-{seed}"), seed, 6).await;
+        net.run(&scenario).await;
+        if let Err(violations) = net.wait_for_convergence(CONVERGENCE_BOUND).await {
+            panic!(
+                "discovery didn't converge with seed {seed} ({scenario:?}): {}",
+                violations.join(", ")
+            );
+        }
+        net.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn random_churn_1() {
+        setup_test();
+        random_churn(1).await;
+    }
+
+    #[tokio::test]
+    async fn random_churn_2() {
+        setup_test();
+        random_churn(2).await;
+    }
+
+    #[tokio::test]
+    async fn random_churn_3() {
+        setup_test();
+        random_churn(3).await;
+    }
+}
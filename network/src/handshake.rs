@@ -0,0 +1,156 @@
+//! The version handshake with the peers.
+//!
+//! Before exchanging messages with a peer, a node queries the [`NetworkVersion`] of the peer
+//! and negotiates the one to use, refusing the peer early if it's incompatible or from another network.
+//! The results are kept for a while, so that operators can see which peers need to upgrade.
+use super::*;
+use parking_lot::Mutex;
+
+/// The result of the handshake with a peer.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PeerVersion {
+    /// The version that the peer reported.
+    pub version: NetworkVersion,
+    /// The negotiated version, or why the peer is refused.
+    pub negotiated: Result<NetworkVersion, String>,
+    pub timestamp: Timestamp,
+}
+
+impl PeerVersion {
+    pub fn is_refused(&self) -> bool {
+        self.negotiated.is_err()
+    }
+}
+
+/// The handshakes with the peers, which can be shared by multiple components of a node.
+#[derive(Debug)]
+pub struct HandshakeTable {
+    /// How long the result of a handshake is valid, in milliseconds.
+    ttl_ms: u64,
+    versions: Mutex<HashMap<PublicKey, PeerVersion>>,
+}
+
+impl Default for HandshakeTable {
+    fn default() -> Self {
+        Self::new(10 * 60 * 1000)
+    }
+}
+
+impl HandshakeTable {
+    pub fn new(ttl_ms: u64) -> Self {
+        Self {
+            ttl_ms,
+            versions: Default::default(),
+        }
+    }
+
+    /// Records the version reported by the peer, negotiating it with `current`.
+    pub fn record(
+        &self,
+        peer: &PublicKey,
+        current: &NetworkVersion,
+        version: NetworkVersion,
+        now: Timestamp,
+    ) -> Result<NetworkVersion, String> {
+        let negotiated = current.negotiate(&version);
+        if let Err(e) = &negotiated {
+            log::warn!("refusing peer {peer}: {e}");
+        }
+        self.versions.lock().insert(
+            peer.clone(),
+            PeerVersion {
+                version,
+                negotiated: negotiated.clone(),
+                timestamp: now,
+            },
+        );
+        negotiated
+    }
+
+    /// Returns whether the handshake with the peer is missing or expired at `now`.
+    pub fn needs_handshake(&self, peer: &PublicKey, now: Timestamp) -> bool {
+        self.versions.lock().get(peer).map_or(true, |x| {
+            now.saturating_sub(x.timestamp) > self.ttl_ms as Timestamp
+        })
+    }
+
+    /// Returns whether the peer has been refused by the last handshake.
+    pub fn is_refused(&self, peer: &PublicKey) -> bool {
+        self.versions
+            .lock()
+            .get(peer)
+            .map_or(false, PeerVersion::is_refused)
+    }
+
+    /// Returns the peers that are not refused.
+    pub fn filter_refused(&self, peers: Vec<Peer>) -> Vec<Peer> {
+        peers
+            .into_iter()
+            .filter(|peer| !self.is_refused(&peer.public_key))
+            .collect()
+    }
+
+    /// Returns the results of the last handshakes with the peers.
+    pub fn versions(&self) -> HashMap<PublicKey, PeerVersion> {
+        self.versions.lock().clone()
+    }
+
+    /// Returns the peers that run an older protocol version than `current`, which need to upgrade.
+    pub fn outdated_peers(&self, current: &NetworkVersion) -> Vec<PublicKey> {
+        self.versions
+            .lock()
+            .iter()
+            .filter(|(_, x)| x.version.is_older_than(current))
+            .map(|(peer, _)| peer.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    fn version(protocol_version: &str, features: &[&str], network_id: &str) -> NetworkVersion {
+        NetworkVersion {
+            protocol_version: protocol_version.to_owned(),
+            features: features.iter().map(|x| x.to_string()).collect(),
+            network_id: network_id.to_owned(),
+        }
+    }
+
+    #[test]
+    fn negotiation() {
+        setup_test();
+        let current = version("1.2.0", &["a", "b"], "net");
+        let table = HandshakeTable::new(100);
+        let (old, new, foreign, incompatible) = (
+            generate_keypair("old").0,
+            generate_keypair("new").0,
+            generate_keypair("foreign").0,
+            generate_keypair("incompatible").0,
+        );
+        assert!(table.needs_handshake(&old, 0));
+
+        assert_eq!(
+            table.record(&old, &current, version("1.1.9", &["b", "c"], "net"), 0),
+            Ok(version("1.1.9", &["b"], "net"))
+        );
+        assert_eq!(
+            table.record(&new, &current, version("1.10.0", &["a"], "net"), 0),
+            Ok(version("1.2.0", &["a"], "net"))
+        );
+        assert!(table
+            .record(&foreign, &current, version("1.2.0", &["a"], "other"), 0)
+            .is_err());
+        assert!(table
+            .record(&incompatible, &current, version("2.0.0", &["a"], "net"), 0)
+            .is_err());
+
+        assert!(!table.needs_handshake(&old, 100));
+        assert!(table.needs_handshake(&old, 101));
+        assert!(table.is_refused(&foreign));
+        assert!(!table.is_refused(&new));
+        assert_eq!(table.outdated_peers(&current), vec![old]);
+    }
+}
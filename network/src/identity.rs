@@ -0,0 +1,445 @@
+//! Pinning the connections to the member keys.
+//!
+//! With a [`MemberIdentity`], the handshake of a connection (see [`crate::rpc`]) also proves
+//! the key of each side. Once the pre-shared key and the compression are settled, each side sends
+//! a fresh challenge and an ephemeral ECDH key, and then its public key with a signature over
+//! both challenges, both ephemeral keys and its side, so a proof can't be replayed on another
+//! connection or reflected back. Every later frame is encrypted with a session key derived from
+//! the ECDH secret, so a relay of the handshake can't read or take over the connection.
+//!
+//! A client whose key isn't of a current member is refused by the server,
+//! and the client refuses a server whose key isn't of a current member,
+//! or isn't the key of the peer being dialed (when that's a member's;
+//! it may be a placeholder when bootstrapping from an address).
+//!
+//! Both sides must pin or neither, like the pre-shared key.
+use super::*;
+use crate::psk::{SessionCipher, Side};
+use crate::rpc::Connection;
+use eyre::eyre;
+use rand::Rng;
+use secp256k1::ecdh::SharedSecret;
+
+/// The size of the first frame: a challenge and a compressed ephemeral public key.
+const HELLO_SIZE: usize = 32 + 33;
+
+/// The maximum size of an encoded [`IdentityProof`].
+const MAX_PROOF_SIZE: u32 = 1024;
+
+/// The key of this node and the member keys that the other side of a connection must prove.
+pub struct MemberIdentity {
+    signer: Arc<dyn Signer>,
+    members: parking_lot::RwLock<Vec<PublicKey>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IdentityProof {
+    public_key: PublicKey,
+    signature: Signature,
+}
+
+/// The challenges and the ephemeral keys of both sides, which every proof and the session key
+/// are bound to.
+struct Transcript {
+    client_challenge: [u8; 32],
+    server_challenge: [u8; 32],
+    client_ephemeral_key: [u8; 33],
+    server_ephemeral_key: [u8; 33],
+}
+
+impl Transcript {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = self.client_challenge.to_vec();
+        data.extend(self.server_challenge);
+        data.extend(self.client_ephemeral_key);
+        data.extend(self.server_ephemeral_key);
+        data
+    }
+
+    fn proof_hash(&self, side: Side) -> Hash256 {
+        let mut data = b"simperby-member-identity".to_vec();
+        data.push(side as u8);
+        data.extend(self.to_bytes());
+        Hash256::hash(data)
+    }
+
+    fn session_key(&self, shared_secret: &SharedSecret) -> Hash256 {
+        let mut data = b"simperby-member-session".to_vec();
+        data.extend(shared_secret.secret_bytes());
+        data.extend(self.to_bytes());
+        Hash256::hash(data)
+    }
+}
+
+impl MemberIdentity {
+    /// Proves the key of `signer`, which is usually the one of `network_config.private_key`.
+    pub fn new(network_config: &NetworkConfig, signer: Arc<dyn Signer>) -> Self {
+        Self {
+            signer,
+            members: parking_lot::RwLock::new(network_config.members.clone()),
+        }
+    }
+
+    /// Replaces the member keys, so that the connections from the removed members are refused.
+    ///
+    /// The connections open already are kept.
+    pub fn update_members(&self, members: Vec<PublicKey>) {
+        *self.members.write() = members;
+    }
+
+    pub fn is_member(&self, public_key: &PublicKey) -> bool {
+        self.members.read().contains(public_key)
+    }
+
+    /// Checks the key proven by the server of an outbound connection to the peer.
+    pub(crate) fn check_server(&self, peer: &Peer, public_key: &PublicKey) -> Result<(), Error> {
+        if !self.is_member(public_key) {
+            return Err(eyre!("the server proved a non-member key {public_key}"));
+        }
+        if self.is_member(&peer.public_key) && peer.public_key != *public_key {
+            return Err(eyre!(
+                "the server proved {public_key} instead of {}",
+                peer.public_key
+            ));
+        }
+        Ok(())
+    }
+
+    /// Proves the key of this side to the other side, returning the key that the other side proves
+    /// and the cipher for the rest of the connection.
+    ///
+    /// Whether that key is of a member is up to the caller.
+    pub(crate) async fn authenticate(
+        &self,
+        connection: &mut Connection,
+        side: Side,
+    ) -> Result<(PublicKey, SessionCipher), Error> {
+        let challenge = rand::thread_rng().gen::<[u8; 32]>();
+        let ephemeral_secret =
+            secp256k1::SecretKey::from_slice(&rand::thread_rng().gen::<[u8; 32]>())
+                .map_err(|_| eyre!("failed to generate an ephemeral key"))?;
+        let ephemeral_key = secp256k1::PublicKey::from_secret_key(
+            &secp256k1::Secp256k1::signing_only(),
+            &ephemeral_secret,
+        )
+        .serialize();
+        connection
+            .write_frame(&[challenge.as_slice(), ephemeral_key.as_slice()].concat())
+            .await?;
+        let hello = connection.read_frame(HELLO_SIZE as u32).await?;
+        if hello.len() != HELLO_SIZE {
+            return Err(eyre!("malformed identity challenge"));
+        }
+        let (other_challenge, other_ephemeral_key) = hello.split_at(32);
+        let other_challenge: [u8; 32] = other_challenge.try_into().expect("checked the size");
+        let other_ephemeral_key: [u8; 33] =
+            other_ephemeral_key.try_into().expect("checked the size");
+        let shared_secret = SharedSecret::new(
+            &secp256k1::PublicKey::from_slice(&other_ephemeral_key)
+                .map_err(|_| eyre!("malformed ephemeral key"))?,
+            &ephemeral_secret,
+        );
+        let (transcript, other_side) = match side {
+            Side::Client => (
+                Transcript {
+                    client_challenge: challenge,
+                    server_challenge: other_challenge,
+                    client_ephemeral_key: ephemeral_key,
+                    server_ephemeral_key: other_ephemeral_key,
+                },
+                Side::Server,
+            ),
+            Side::Server => (
+                Transcript {
+                    client_challenge: other_challenge,
+                    server_challenge: challenge,
+                    client_ephemeral_key: other_ephemeral_key,
+                    server_ephemeral_key: ephemeral_key,
+                },
+                Side::Client,
+            ),
+        };
+        let proof = IdentityProof {
+            public_key: self.signer.public_key(),
+            signature: self.signer.sign(transcript.proof_hash(side)).await?,
+        };
+        connection.write_frame(&serde_spb::to_vec(&proof)?).await?;
+        let other_proof: IdentityProof =
+            serde_spb::from_slice(&connection.read_frame(MAX_PROOF_SIZE).await?)?;
+        other_proof
+            .signature
+            .verify(transcript.proof_hash(other_side), &other_proof.public_key)
+            .map_err(|_| eyre!("invalid identity proof of {}", other_proof.public_key))?;
+        Ok((
+            other_proof.public_key,
+            SessionCipher::new(transcript.session_key(&shared_secret), "member session key"),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth_events::{AuthEvents, AuthFailureReason};
+    use crate::compression::CompressionConfig;
+    use crate::mux::{MuxRpc, MUX_PROTOCOL};
+    use crate::rpc::{RpcConfig, TcpRpc};
+    use simperby_test_suite::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn network_config(seed: &str, members: &[&str]) -> NetworkConfig {
+        let (public_key, private_key) = generate_keypair(seed);
+        NetworkConfig {
+            network_id: "identity".to_owned(),
+            ports: HashMap::new(),
+            members: members.iter().map(|x| generate_keypair(x).0).collect(),
+            public_key,
+            private_key: Some(private_key),
+            dns_seeds: Vec::new(),
+            enable_mdns: false,
+            pre_shared_key: None,
+            proxy: None,
+            denied_peers: Vec::new(),
+            allowed_peers: Vec::new(),
+        }
+    }
+
+    fn identity(seed: &str) -> Option<Arc<MemberIdentity>> {
+        let config = network_config(seed, &["a", "b", "c"]);
+        Some(Arc::new(MemberIdentity::new(
+            &config,
+            config.signer().unwrap(),
+        )))
+    }
+
+    fn peer(seed: &str, port: u16) -> Peer {
+        Peer {
+            public_key: generate_keypair(seed).0,
+            name: seed.to_owned(),
+            address: "127.0.0.1:1".parse().unwrap(),
+            addresses: Vec::new(),
+            ports: vec![("echo".to_owned(), port), (MUX_PROTOCOL.to_owned(), port)]
+                .into_iter()
+                .collect(),
+            metadata: Default::default(),
+            recently_seen_timestamp: 0,
+        }
+    }
+
+    fn config() -> RpcConfig {
+        RpcConfig {
+            timeout_ms: 1000,
+            ..Default::default()
+        }
+    }
+
+    async fn serve_tcp(
+        identity: Option<Arc<MemberIdentity>>,
+        auth_events: AuthEvents,
+    ) -> (u16, tokio::task::JoinHandle<Result<(), Error>>) {
+        let port = dispense_port();
+        let server = TcpRpc::new(config())
+            .with_member_identity(identity)
+            .with_auth_events(auth_events)
+            .serve(port, |x: u64| async move { Ok(x) })
+            .await
+            .unwrap();
+        (port, server)
+    }
+
+    async fn echo(client: &TcpRpc, peer: &Peer) -> Result<u64, Error> {
+        client.request::<_, u64>(peer, "echo", 1u64).await
+    }
+
+    #[tokio::test]
+    async fn member_pinning() {
+        setup_test();
+        let events = AuthEvents::default();
+        let mut failures = events.subscribe();
+        let (port, _server) = serve_tcp(identity("a"), events).await;
+        let a = peer("a", port);
+
+        let member = TcpRpc::new(config()).with_member_identity(identity("b"));
+        assert_eq!(echo(&member, &a).await.unwrap(), 1);
+
+        // An outsider, even one that takes the members as its own.
+        let outsider = TcpRpc::new(config()).with_member_identity(identity("outsider"));
+        assert!(echo(&outsider, &a).await.is_err());
+        let failure = failures.recv().await.unwrap();
+        assert_eq!(
+            failure.reason,
+            AuthFailureReason::UnknownKey(generate_keypair("outsider").0)
+        );
+
+        // Both sides must pin.
+        let unpinned = TcpRpc::new(config());
+        assert!(echo(&unpinned, &a).await.is_err());
+        assert!(matches!(
+            failures.recv().await.unwrap().reason,
+            AuthFailureReason::Handshake(_)
+        ));
+        let (port, _unpinned_server) = serve_tcp(None, AuthEvents::default()).await;
+        assert!(echo(&member, &peer("a", port)).await.is_err());
+
+        // The server must be a member too.
+        let (port, _outsider_server) = serve_tcp(identity("outsider"), AuthEvents::default()).await;
+        assert!(echo(&member, &peer("outsider", port)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn impersonation() {
+        setup_test();
+        let (port, _server) = serve_tcp(identity("a"), AuthEvents::default()).await;
+        let client = TcpRpc::new(config()).with_member_identity(identity("b"));
+
+        // The server can't pass for another member.
+        assert!(echo(&client, &peer("c", port)).await.is_err());
+        // A placeholder key when bootstrapping is fine, as long as the server is a member.
+        assert_eq!(echo(&client, &peer("unknown", port)).await.unwrap(), 1);
+
+        // An outsider claiming the key of a member can't sign for it.
+        struct Impostor(PrivateKey);
+        #[async_trait]
+        impl Signer for Impostor {
+            fn public_key(&self) -> PublicKey {
+                generate_keypair("c").0
+            }
+
+            async fn sign(&self, data: Hash256) -> Result<Signature, CryptoError> {
+                Signature::sign(data, &self.0)
+            }
+        }
+        let impostor =
+            TcpRpc::new(config()).with_member_identity(Some(Arc::new(MemberIdentity::new(
+                &network_config("outsider", &["a", "b", "c"]),
+                Arc::new(Impostor(generate_keypair("outsider").1)),
+            ))));
+        assert!(echo(&impostor, &peer("a", port)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn removed_member() {
+        setup_test();
+        let port = dispense_port();
+        let server = identity("a").unwrap();
+        let _server = TcpRpc::new(config())
+            .with_member_identity(Some(Arc::clone(&server)))
+            .serve(port, |x: u64| async move { Ok(x) })
+            .await
+            .unwrap();
+        let client = TcpRpc::new(config()).with_member_identity(identity("b"));
+        assert_eq!(echo(&client, &peer("a", port)).await.unwrap(), 1);
+        server.update_members(vec![generate_keypair("a").0, generate_keypair("c").0]);
+        assert!(echo(&client, &peer("a", port)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn relayed_handshake() {
+        setup_test();
+        let server_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_address = server_listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = server_listener.accept().await.unwrap();
+            let identity = identity("a").unwrap();
+            let mut connection = Connection::open(
+                stream,
+                Side::Server,
+                None,
+                &CompressionConfig::default(),
+                Some(&identity),
+            )
+            .await
+            .unwrap();
+            let first = connection.read_frame(1024).await.unwrap();
+            (first, connection.read_frame(1024).await)
+        });
+
+        // A relay on the path, which records what the client sends
+        // and then tries to take over the connection once the handshake is done.
+        let relay_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay_address = relay_listener.local_addr().unwrap();
+        let relay = tokio::spawn(async move {
+            let (client, _) = relay_listener.accept().await.unwrap();
+            let server = TcpStream::connect(server_address).await.unwrap();
+            let (mut client_read, mut client_write) = client.into_split();
+            let (mut server_read, mut server_write) = server.into_split();
+            tokio::spawn(async move { tokio::io::copy(&mut server_read, &mut client_write).await });
+            let mut recorded = Vec::new();
+            let mut buffer = [0; 4096];
+            loop {
+                let n = client_read.read(&mut buffer).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                recorded.extend_from_slice(&buffer[..n]);
+                server_write.write_all(&buffer[..n]).await.unwrap();
+            }
+            let injected = [&[0u8], b"hijacked".as_slice()].concat();
+            server_write.write_u32(injected.len() as u32).await.unwrap();
+            server_write.write_all(&injected).await.unwrap();
+            recorded
+        });
+
+        let stream = TcpStream::connect(relay_address).await.unwrap();
+        let client_identity = identity("b").unwrap();
+        let mut connection = Connection::open(
+            stream,
+            Side::Client,
+            None,
+            &CompressionConfig::default(),
+            Some(&client_identity),
+        )
+        .await
+        .unwrap();
+        assert_eq!(connection.remote_key(), Some(&generate_keypair("a").0));
+        connection.write_frame(b"confidential").await.unwrap();
+        // Closes the client's half, after which the relay injects its frame.
+        let (_reader, writer) = connection.into_split();
+        drop(writer);
+
+        let recorded = relay.await.unwrap();
+        assert!(!recorded
+            .windows(b"confidential".len())
+            .any(|x| x == b"confidential"));
+        let (first, injected) = server.await.unwrap();
+        assert_eq!(first, b"confidential");
+        assert!(injected.is_err());
+    }
+
+    #[tokio::test]
+    async fn multiplexed() {
+        setup_test();
+        let events = AuthEvents::default();
+        let mut failures = events.subscribe();
+        let port = dispense_port();
+        let server = MuxRpc::new(config())
+            .with_member_identity(identity("a"))
+            .with_auth_events(events);
+        let _server = server
+            .serve_protocol("echo", port, |x: u64| async move { Ok(x) })
+            .await
+            .unwrap();
+        let member = MuxRpc::new(config()).with_member_identity(identity("b"));
+        assert_eq!(
+            member
+                .request::<_, u64>(&peer("a", port), "echo", 1u64)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(member
+            .request::<_, u64>(&peer("c", port), "echo", 1u64)
+            .await
+            .is_err());
+        let outsider = MuxRpc::new(config()).with_member_identity(identity("outsider"));
+        assert!(outsider
+            .request::<_, u64>(&peer("a", port), "echo", 1u64)
+            .await
+            .is_err());
+        assert_eq!(
+            failures.recv().await.unwrap().reason,
+            AuthFailureReason::UnknownKey(generate_keypair("outsider").0)
+        );
+    }
+}
@@ -0,0 +1,351 @@
+//! An in-memory transport of the network primitives for the tests, which binds no port.
+//!
+//! The nodes of a network (`NetworkConfig::network_id`) are connected through a process-wide hub,
+//! so the tests using distinct network ids don't interfere with each other.
+//! The hub delivers the gossip to the recipients in an order shuffled by its seeded RNG,
+//! which also decides the lost messages (see [`reset_network()`]),
+//! so a run is reproduced exactly with the same seed.
+//!
+//! Available in the tests of this crate, or elsewhere with the `testing` feature.
+use super::*;
+use eyre::eyre;
+use futures::future::BoxFuture;
+use futures::Future;
+use parking_lot::Mutex;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tokio::sync::mpsc;
+
+type Handler = Arc<dyn Fn(Vec<u8>) -> BoxFuture<'static, Result<Vec<u8>, String>> + Send + Sync>;
+
+struct Hub {
+    rng: StdRng,
+    drop_probability: f64,
+    gossip: Vec<(PublicKey, mpsc::Sender<Vec<u8>>)>,
+    rpc: HashMap<(Ipv4Addr, u16), Handler>,
+}
+
+impl Hub {
+    fn new(seed: u64, drop_probability: f64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            drop_probability,
+            gossip: Vec::new(),
+            rpc: HashMap::new(),
+        }
+    }
+}
+
+static HUBS: Mutex<BTreeMap<String, Arc<Mutex<Hub>>>> = parking_lot::const_mutex(BTreeMap::new());
+
+fn hub(network_id: &str) -> Arc<Mutex<Hub>> {
+    Arc::clone(
+        HUBS.lock()
+            .entry(network_id.to_owned())
+            .or_insert_with(|| Arc::new(Mutex::new(Hub::new(0, 0.0)))),
+    )
+}
+
+/// Resets the network, disconnecting all of its nodes and seeding its RNG.
+///
+/// Each gossip message is lost with `drop_probability`. Without a reset, a network has the seed `0`
+/// and loses nothing.
+pub fn reset_network(network_id: &str, seed: u64, drop_probability: f64) {
+    HUBS.lock().insert(
+        network_id.to_owned(),
+        Arc::new(Mutex::new(Hub::new(seed, drop_probability))),
+    );
+}
+
+/// A [`GossipNetwork`] that delivers each message to all the other nodes serving the same network.
+pub struct InMemoryGossipNetwork;
+
+#[async_trait]
+impl GossipNetwork for InMemoryGossipNetwork {
+    async fn broadcast(
+        config: &NetworkConfig,
+        _known_peers: &[Peer],
+        message: Vec<u8>,
+    ) -> Result<(), Error> {
+        let recipients = {
+            let hub = hub(&config.network_id);
+            let mut hub = hub.lock();
+            hub.gossip.retain(|(_, sender)| !sender.is_closed());
+            let mut recipients = hub
+                .gossip
+                .iter()
+                .filter(|(public_key, _)| *public_key != config.public_key)
+                .map(|(_, sender)| sender.clone())
+                .collect::<Vec<_>>();
+            recipients.shuffle(&mut hub.rng);
+            let drop_probability = hub.drop_probability;
+            recipients.retain(|_| !hub.rng.gen_bool(drop_probability));
+            recipients
+        };
+        for recipient in recipients {
+            // The recipient may have stopped meanwhile.
+            let _ = recipient.send(message.clone()).await;
+        }
+        Ok(())
+    }
+
+    async fn serve(
+        config: NetworkConfig,
+        _peers: SharedKnownPeers,
+    ) -> Result<(mpsc::Receiver<Vec<u8>>, ServiceHandle), Error> {
+        let (send, recv) = mpsc::channel(1024);
+        let hub = hub(&config.network_id);
+        hub.lock().gossip.push((config.public_key, send.clone()));
+        // Stays registered until the receiver is dropped or the service is shut down.
+        let handle = ServiceHandle::spawn(|mut shutdown| async move {
+            tokio::select! {
+                _ = send.closed() => {}
+                _ = shutdown.requested() => {
+                    hub.lock().gossip.retain(|(_, x)| !x.same_channel(&send));
+                }
+            }
+            Ok(())
+        });
+        Ok((recv, handle))
+    }
+}
+
+/// An [`RpcPrimitive`] of a node at `address` in the network, which serves the requests in the same process.
+pub struct InMemoryRpc {
+    network_id: String,
+    address: Ipv4Addr,
+}
+
+impl InMemoryRpc {
+    pub fn new(network_id: &str, address: Ipv4Addr) -> Self {
+        Self {
+            network_id: network_id.to_owned(),
+            address,
+        }
+    }
+}
+
+/// Unregisters the handler when the serving task is dropped (e.g., aborted).
+struct Registration {
+    hub: Arc<Mutex<Hub>>,
+    key: (Ipv4Addr, u16),
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.hub.lock().rpc.remove(&self.key);
+    }
+}
+
+#[async_trait]
+impl RpcPrimitive for InMemoryRpc {
+    async fn request<Q, R>(&self, peer: &Peer, protocol: &str, request: Q) -> Result<R, Error>
+    where
+        Q: Serialize + Send + 'static,
+        R: DeserializeOwned + Send + 'static,
+    {
+        let port = *peer
+            .ports
+            .get(protocol)
+            .ok_or_else(|| eyre!("peer {} doesn't serve {protocol}", peer.public_key))?;
+        let handler = {
+            let hub = hub(&self.network_id);
+            let hub = hub.lock();
+            peer.hosts().into_iter().find_map(|host| match host {
+                PeerAddress::Ip(IpAddr::V4(ip)) => hub.rpc.get(&(ip, port)).cloned(),
+                _ => None,
+            })
+        }
+        .ok_or_else(|| eyre!("failed to connect to {}:{port}", peer.address.ip()))?;
+        // The requests come from port `0`, as no connection is made.
+        let address = SocketAddr::new(IpAddr::V4(self.address), 0);
+        let response = rpc::with_remote_address(address, handler(serde_spb::to_vec(&request)?))
+            .await
+            .map_err(|e| eyre!("{protocol} request to {} failed: {e}", peer.public_key))?;
+        Ok(serde_spb::from_slice(&response)?)
+    }
+
+    async fn serve<Q, R, F, Fut>(
+        &self,
+        port: u16,
+        handler: F,
+    ) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error>
+    where
+        Q: DeserializeOwned + Send + 'static,
+        R: Serialize + Send + 'static,
+        F: Fn(Q) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, String>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let handler: Handler = Arc::new(move |request: Vec<u8>| {
+            let handler = Arc::clone(&handler);
+            Box::pin(async move {
+                let request = serde_spb::from_slice::<Q>(&request)
+                    .map_err(|e| format!("malformed request: {e}"))?;
+                let response = handler(request).await?;
+                serde_spb::to_vec(&response).map_err(|e| e.to_string())
+            })
+        });
+        let hub = hub(&self.network_id);
+        let key = (self.address, port);
+        {
+            let mut hub = hub.lock();
+            if hub.rpc.contains_key(&key) {
+                return Err(eyre!("{}:{port} is already in use", self.address));
+            }
+            hub.rpc.insert(key, handler);
+        }
+        let registration = Registration { hub, key };
+        Ok(tokio::spawn(async move {
+            let _registration = registration;
+            futures::future::pending::<()>().await;
+            Ok(())
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    fn network_config(network_id: &str, seed: &str) -> NetworkConfig {
+        let (public_key, private_key) = generate_keypair(seed);
+        NetworkConfig {
+            network_id: network_id.to_owned(),
+            ports: HashMap::new(),
+            members: Vec::new(),
+            public_key,
+            private_key: Some(private_key),
+            dns_seeds: Vec::new(),
+            enable_mdns: false,
+            pre_shared_key: None,
+            proxy: None,
+            denied_peers: Vec::new(),
+            allowed_peers: Vec::new(),
+        }
+    }
+
+    /// Broadcasts the messages from node `a`, returning the ones that `b` and `c` received.
+    async fn run_gossip(
+        network_id: &str,
+        seed: u64,
+        drop_probability: f64,
+    ) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+        reset_network(network_id, seed, drop_probability);
+        let (a, b, c) = (
+            network_config(network_id, "a"),
+            network_config(network_id, "b"),
+            network_config(network_id, "c"),
+        );
+        let peers = SharedKnownPeers::new_static(Vec::new());
+        let (_recv_a, _) = InMemoryGossipNetwork::serve(a.clone(), peers.clone())
+            .await
+            .unwrap();
+        let (mut recv_b, _) = InMemoryGossipNetwork::serve(b, peers.clone())
+            .await
+            .unwrap();
+        let (mut recv_c, _) = InMemoryGossipNetwork::serve(c, peers).await.unwrap();
+        for i in 0..100u8 {
+            InMemoryGossipNetwork::broadcast(&a, &[], vec![i])
+                .await
+                .unwrap();
+        }
+        let drain = |recv: &mut mpsc::Receiver<Vec<u8>>| {
+            let mut messages = Vec::new();
+            while let Ok(message) = recv.try_recv() {
+                messages.push(message);
+            }
+            messages
+        };
+        (drain(&mut recv_b), drain(&mut recv_c))
+    }
+
+    #[tokio::test]
+    async fn gossip() {
+        setup_test();
+        let (b, c) = run_gossip("in_memory_gossip", 0, 0.0).await;
+        let all = (0..100u8).map(|i| vec![i]).collect::<Vec<_>>();
+        assert_eq!(b, all);
+        assert_eq!(c, all);
+
+        let lossy = run_gossip("in_memory_gossip", 1, 0.5).await;
+        assert!(lossy.0.len() < 100 && lossy.1.len() < 100);
+        assert_eq!(run_gossip("in_memory_gossip", 1, 0.5).await, lossy);
+        assert_ne!(run_gossip("in_memory_gossip", 2, 0.5).await, lossy);
+    }
+
+    #[tokio::test]
+    async fn gossip_shutdown() {
+        setup_test();
+        let network_id = "in_memory_gossip_shutdown";
+        let (a, b) = (
+            network_config(network_id, "a"),
+            network_config(network_id, "b"),
+        );
+        let peers = SharedKnownPeers::new_static(Vec::new());
+        let (mut recv_b, handle) = InMemoryGossipNetwork::serve(b, peers).await.unwrap();
+        InMemoryGossipNetwork::broadcast(&a, &[], vec![0])
+            .await
+            .unwrap();
+        handle.shutdown().await.unwrap();
+        InMemoryGossipNetwork::broadcast(&a, &[], vec![1])
+            .await
+            .unwrap();
+        assert_eq!(recv_b.recv().await, Some(vec![0]));
+        assert_eq!(recv_b.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn rpc() {
+        setup_test();
+        let network_id = "in_memory_rpc";
+        let server = InMemoryRpc::new(network_id, "10.0.0.1".parse().unwrap());
+        let client = InMemoryRpc::new(network_id, "10.0.0.2".parse().unwrap());
+        let task = server
+            .serve(1000, |x: u64| async move {
+                if x == 0 {
+                    return Err("zero".to_owned());
+                }
+                Ok(x + 1)
+            })
+            .await
+            .unwrap();
+        assert!(server
+            .serve(1000, |x: u64| async move { Ok(x) })
+            .await
+            .is_err());
+
+        let peer = Peer {
+            public_key: PublicKey::zero(),
+            name: "server".to_owned(),
+            address: "10.0.0.1:1".parse().unwrap(),
+            addresses: Vec::new(),
+            ports: vec![("inc".to_owned(), 1000)].into_iter().collect(),
+            metadata: Default::default(),
+            recently_seen_timestamp: 0,
+        };
+        assert_eq!(
+            client.request::<_, u64>(&peer, "inc", 1u64).await.unwrap(),
+            2
+        );
+        let error = client
+            .request::<_, u64>(&peer, "inc", 0u64)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("zero"));
+        // Another network.
+        assert!(
+            InMemoryRpc::new("in_memory_other", "10.0.0.2".parse().unwrap())
+                .request::<_, u64>(&peer, "inc", 1u64)
+                .await
+                .is_err()
+        );
+
+        task.abort();
+        let _ = task.await;
+        assert!(client.request::<_, u64>(&peer, "inc", 1u64).await.is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//! Latency measurement of the peers.
+//!
+//! The round-trip time (RTT) of a peer is measured by a periodic ping (the cheapest request of a protocol,
+//! e.g., the version query of the DMS), smoothed over the measurements like the TCP SRTT.
+//! The broadcasts prefer the peers with lower latencies as their relays.
+use super::*;
+use parking_lot::Mutex;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PeerLatency {
+    /// The smoothed round-trip time, in milliseconds.
+    pub rtt_ms: u64,
+    /// The round-trip time of the last ping, in milliseconds.
+    pub last_rtt_ms: u64,
+    pub timestamp: Timestamp,
+}
+
+/// The latencies of the peers, which can be shared by multiple components of a node.
+#[derive(Debug)]
+pub struct LatencyTable {
+    /// How often a peer is pinged, in milliseconds.
+    ping_interval_ms: u64,
+    latencies: Mutex<HashMap<PublicKey, PeerLatency>>,
+}
+
+impl Default for LatencyTable {
+    fn default() -> Self {
+        Self::new(30 * 1000)
+    }
+}
+
+impl LatencyTable {
+    pub fn new(ping_interval_ms: u64) -> Self {
+        Self {
+            ping_interval_ms,
+            latencies: Default::default(),
+        }
+    }
+
+    /// Records the round-trip time of a ping to the peer.
+    pub fn record(&self, peer: &PublicKey, rtt_ms: u64, now: Timestamp) {
+        let mut latencies = self.latencies.lock();
+        let smoothed = latencies
+            .get(peer)
+            .map_or(rtt_ms, |x| (x.rtt_ms * 7 + rtt_ms) / 8);
+        latencies.insert(
+            peer.clone(),
+            PeerLatency {
+                rtt_ms: smoothed,
+                last_rtt_ms: rtt_ms,
+                timestamp: now,
+            },
+        );
+    }
+
+    /// Returns whether the peer has not been pinged in the interval at `now`.
+    pub fn needs_ping(&self, peer: &PublicKey, now: Timestamp) -> bool {
+        self.latencies.lock().get(peer).map_or(true, |x| {
+            now.saturating_sub(x.timestamp) >= self.ping_interval_ms as Timestamp
+        })
+    }
+
+    /// Returns the latencies of the peers that have been pinged.
+    pub fn latencies(&self) -> HashMap<PublicKey, PeerLatency> {
+        self.latencies.lock().clone()
+    }
+
+    /// Sorts the peers from the lowest latency, leaving the unmeasured ones at the end in their order.
+    pub fn sort_by_latency(&self, mut peers: Vec<Peer>) -> Vec<Peer> {
+        let latencies = self.latencies.lock();
+        peers.sort_by_key(|peer| {
+            latencies
+                .get(&peer.public_key)
+                .map_or(u64::MAX, |x| x.rtt_ms)
+        });
+        peers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    fn peer(seed: &str) -> Peer {
+        Peer {
+            public_key: generate_keypair(seed).0,
+            name: seed.to_owned(),
+            address: "127.0.0.1:1".parse().unwrap(),
+            addresses: Vec::new(),
+            ports: HashMap::new(),
+            metadata: Default::default(),
+            recently_seen_timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn latency() {
+        setup_test();
+        let table = LatencyTable::new(100);
+        let (a, b, c, d) = (peer("a"), peer("b"), peer("c"), peer("d"));
+        assert!(table.needs_ping(&a.public_key, 0));
+        table.record(&a.public_key, 80, 0);
+        table.record(&b.public_key, 10, 0);
+        table.record(&c.public_key, 50, 0);
+        assert!(!table.needs_ping(&a.public_key, 99));
+        assert!(table.needs_ping(&a.public_key, 100));
+
+        // Smoothed over the pings.
+        table.record(&b.public_key, 170, 100);
+        let latency = &table.latencies()[&b.public_key];
+        assert_eq!((latency.rtt_ms, latency.last_rtt_ms), (30, 170));
+
+        assert_eq!(
+            table.sort_by_latency(vec![d.clone(), a.clone(), b.clone(), c.clone()]),
+            vec![b, c, a, d]
+        );
+    }
+}
@@ -1,20 +1,132 @@
+pub mod access;
+pub mod audit;
+pub mod auth_events;
+pub mod bandwidth;
+pub mod chunking;
+pub mod compression;
+pub mod connections;
+pub mod dial;
 pub mod dms;
+pub mod eclipse;
+pub mod external_address;
+pub mod gossip_discovery;
+pub mod handshake;
+pub mod identity;
+#[cfg(any(test, feature = "testing"))]
+pub mod in_memory;
+pub mod latency;
+pub mod limits;
+pub mod mdns;
+pub mod metrics;
+pub mod mux;
 #[cfg(never)]
 mod peer_discovery;
+pub mod peer_record;
+pub mod peer_store;
 pub mod primitives;
+pub mod priority;
+pub mod proxy;
+pub mod psk;
+pub mod rpc;
+pub mod scoring;
+pub mod seeds;
+pub mod seen_cache;
+#[cfg(any(test, feature = "testing"))]
+pub mod simulation;
 pub mod storage;
+pub mod telemetry;
+pub mod threshold;
 
 use async_trait::async_trait;
+use peer_record::SignedPeerRecord;
 use primitives::*;
+use rand::{seq::SliceRandom, SeedableRng};
 use serde::{Deserialize, Serialize};
-use simperby_common::{crypto::*, MemberName, Timestamp};
-use std::collections::HashMap;
-use std::{net::SocketAddrV4, sync::Arc};
+use simperby_common::{crypto::*, serde_spb, signer::Signer, MemberName, Timestamp};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddrV4};
+use std::sync::Arc;
 use tokio::sync::RwLock;
 
 pub type Error = eyre::Error;
 pub type Dms = dms::DistributedMessageSet<primitives::DummyGossipNetwork, storage::StorageImpl>;
 
+/// The version of the Simperby network protocol.
+pub const NETWORK_PROTOCOL_VERSION: &str = "0.1.0";
+
+/// The optional network protocol features that this implementation supports.
+pub const NETWORK_PROTOCOL_FEATURES: &[&str] = &["dms-http-rpc", "git-daemon", "chunked-gossip"];
+
+/// The version information exchanged between peers, to diagnose incompatibilities.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct NetworkVersion {
+    pub protocol_version: String,
+    pub features: Vec<String>,
+    pub network_id: String,
+}
+
+impl NetworkVersion {
+    pub fn current(network_id: &str) -> Self {
+        Self {
+            protocol_version: NETWORK_PROTOCOL_VERSION.to_owned(),
+            features: NETWORK_PROTOCOL_FEATURES
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+            network_id: network_id.to_owned(),
+        }
+    }
+
+    /// Checks whether the peer with the given version can communicate with this node.
+    pub fn check_compatibility(&self, peer: &NetworkVersion) -> Result<(), String> {
+        if self.network_id != peer.network_id {
+            return Err(format!(
+                "network id mismatch: {} vs {}",
+                self.network_id, peer.network_id
+            ));
+        }
+        let major = |v: &str| v.split('.').next().unwrap_or_default().to_owned();
+        if major(&self.protocol_version) != major(&peer.protocol_version) {
+            return Err(format!(
+                "incompatible protocol version: {} vs {}",
+                self.protocol_version, peer.protocol_version
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks whether the protocol version is older than the other's, comparing each number.
+    pub fn is_older_than(&self, other: &NetworkVersion) -> bool {
+        let parse = |v: &str| {
+            v.split('.')
+                .map(|x| x.parse::<u64>().unwrap_or_default())
+                .collect::<Vec<_>>()
+        };
+        parse(&self.protocol_version) < parse(&other.protocol_version)
+    }
+
+    /// Negotiates the version to communicate with the peer:
+    /// the lower protocol version of the two, with the features that both support.
+    pub fn negotiate(&self, peer: &NetworkVersion) -> Result<NetworkVersion, String> {
+        self.check_compatibility(peer)?;
+        let protocol_version = if peer.is_older_than(self) {
+            peer.protocol_version.clone()
+        } else {
+            self.protocol_version.clone()
+        };
+        Ok(NetworkVersion {
+            protocol_version,
+            features: self
+                .features
+                .iter()
+                .filter(|x| peer.features.contains(x))
+                .cloned()
+                .collect(),
+            network_id: self.network_id.clone(),
+        })
+    }
+}
+
 /// The information of a network peer that is discovered by the discovery protocol.
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct Peer {
@@ -22,13 +134,110 @@ pub struct Peer {
     pub name: MemberName,
     /// The address used for the discovery protocol
     pub address: SocketAddrV4,
+    /// The other addresses (e.g., IPv6 or DNS) where the peer serves the same ports.
+    #[serde(default)]
+    pub addresses: Vec<PeerAddress>,
     /// For the other network services like gossip or RPC,
     /// it provides a map of `identifier->port`.
+    ///
+    /// The per-service ports are deprecated: a peer that multiplexes its protocols
+    /// advertises only the port of [`mux::MUX_PROTOCOL`].
     pub ports: HashMap<String, u16>,
-    pub message: String,
+    /// What the peer advertises about itself.
+    #[serde(default)]
+    pub metadata: PeerMetadata,
     pub recently_seen_timestamp: Timestamp,
 }
 
+/// A role that a node takes in the network, besides being a member.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+pub enum PeerRole {
+    /// Keeps the whole history, serving it to the others.
+    Archive,
+    /// Relays the messages of the others.
+    Relay,
+}
+
+/// The information that a node advertises about itself.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct PeerMetadata {
+    /// The version of the node software.
+    pub node_version: String,
+    /// The optional protocol features that the node supports (see [`NETWORK_PROTOCOL_FEATURES`]).
+    pub protocols: Vec<String>,
+    pub roles: Vec<PeerRole>,
+}
+
+impl PeerMetadata {
+    /// The metadata of this node, supporting all the features of this implementation.
+    pub fn current(node_version: &str, roles: Vec<PeerRole>) -> Self {
+        Self {
+            node_version: node_version.to_owned(),
+            protocols: NETWORK_PROTOCOL_FEATURES
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+            roles,
+        }
+    }
+}
+
+impl Peer {
+    pub fn node_version(&self) -> &str {
+        &self.metadata.node_version
+    }
+
+    pub fn supports(&self, protocol: &str) -> bool {
+        self.metadata.protocols.iter().any(|x| x == protocol)
+    }
+
+    pub fn has_role(&self, role: PeerRole) -> bool {
+        self.metadata.roles.contains(&role)
+    }
+
+    /// The port where the peer serves the protocol, either its own or the multiplexed one.
+    pub fn port(&self, protocol: &str) -> Option<u16> {
+        self.ports
+            .get(protocol)
+            .or_else(|| self.ports.get(mux::MUX_PROTOCOL))
+            .copied()
+    }
+
+    pub fn serves(&self, protocol: &str) -> bool {
+        self.port(protocol).is_some()
+    }
+
+    /// Returns all the addresses of the peer, starting from the IP of `address`.
+    pub fn hosts(&self) -> Vec<PeerAddress> {
+        let mut hosts = vec![PeerAddress::Ip(IpAddr::V4(*self.address.ip()))];
+        for address in &self.addresses {
+            if !hosts.contains(address) {
+                hosts.push(address.clone());
+            }
+        }
+        hosts
+    }
+}
+
+/// An address of a peer, without the port.
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
+pub enum PeerAddress {
+    Ip(IpAddr),
+    /// A DNS name, which is resolved on each connection.
+    Dns(String),
+}
+
+impl std::fmt::Display for PeerAddress {
+    /// Formats the address to be followed by `:port` (e.g., in a URL).
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeerAddress::Ip(IpAddr::V4(ip)) => write!(f, "{ip}"),
+            PeerAddress::Ip(IpAddr::V6(ip)) => write!(f, "[{ip}]"),
+            PeerAddress::Dns(name) => write!(f, "{name}"),
+        }
+    }
+}
+
 /// Configuration to access the Simperby P2P network.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkConfig {
@@ -36,48 +245,482 @@ pub struct NetworkConfig {
     pub network_id: String,
     /// The map of `identifier->port` where an `identifier` represent each network services
     /// (.e.g, gossip-consensus, RPC-governance, discovery, ..)
+    ///
+    /// Deprecated in favor of a single port for [`mux::MUX_PROTOCOL`],
+    /// to which all the services of [`RpcPrimitive`] are multiplexed by [`mux::MuxRpc`].
     pub ports: HashMap<String, u16>,
     /// The set of the members of the network.
     pub members: Vec<PublicKey>,
     /// The public key of this node.
     pub public_key: PublicKey,
-    /// The private key of this node.
-    pub private_key: PrivateKey,
+    /// The private key of this node, unless it is kept outside of the process.
+    ///
+    /// Without it, the node signs through a [`Signer`] given to the components instead
+    /// (see [`identity::MemberIdentity::new`] and [`dms::DistributedMessageSet::set_signer`]).
+    #[serde(default)]
+    pub private_key: Option<PrivateKey>,
+    /// The DNS seeds (`host:port`) to bootstrap the known peers from. See [`seeds`].
+    #[serde(default)]
+    pub dns_seeds: Vec<String>,
+    /// Whether to discover the peers on the local subnet over mDNS. See [`mdns`].
+    #[serde(default)]
+    pub enable_mdns: bool,
+    /// The key that isolates a private network. See [`psk`].
+    #[serde(default)]
+    pub pre_shared_key: Option<psk::PreSharedKey>,
+    /// The SOCKS5 proxy to make the outbound connections through. See [`proxy`].
+    #[serde(default)]
+    pub proxy: Option<proxy::ProxyConfig>,
+    /// The peers refused by this node. See [`access`].
+    #[serde(default)]
+    pub denied_peers: Vec<access::PeerPattern>,
+    /// If not empty, the only peers accepted by this node. See [`access`].
+    #[serde(default)]
+    pub allowed_peers: Vec<access::PeerPattern>,
 }
 
+impl NetworkConfig {
+    /// Returns the private key, which fails if it is kept outside of the process.
+    pub fn private_key(&self) -> Result<&PrivateKey, Error> {
+        self.private_key
+            .as_ref()
+            .ok_or_else(|| eyre::eyre!("no private key in the network config"))
+    }
+
+    /// Returns the signer of `private_key`, if any.
+    pub fn signer(&self) -> Option<Arc<dyn Signer>> {
+        self.private_key.clone().map(Into::into)
+    }
+
+    pub fn peer_filter(&self) -> access::PeerFilter {
+        access::PeerFilter {
+            denied: self.denied_peers.clone(),
+            allowed: self.allowed_peers.clone(),
+        }
+    }
+}
+
+/// How long a peer is considered live after it was last seen.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PeerExpiry {
+    /// The time-to-live of a peer since its `recently_seen_timestamp`, in milliseconds.
+    pub ttl_ms: u64,
+    /// How often the expired peers are evicted, in milliseconds.
+    pub eviction_interval_ms: u64,
+    /// How long a departed peer is kept from being added again with the information
+    /// from before its departure, in milliseconds.
+    pub tombstone_ttl_ms: u64,
+}
+
+impl Default for PeerExpiry {
+    fn default() -> Self {
+        Self {
+            ttl_ms: 30 * 60 * 1000,
+            eviction_interval_ms: 60 * 1000,
+            tombstone_ttl_ms: 10 * 60 * 1000,
+        }
+    }
+}
+
+/// How the peers are discovered through the other peers, trading the convergence speed
+/// for the bandwidth in large networks.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DiscoveryConfig {
+    /// How often a discovery round is run, in milliseconds.
+    pub round_interval_ms: u64,
+    /// The number of the peers contacted at once in a round.
+    pub fanout: usize,
+    /// The maximum number of the peers in a response to a discovery request.
+    pub max_response_peers: usize,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            round_interval_ms: 10 * 1000,
+            fanout: 3,
+            max_response_peers: 20,
+        }
+    }
+}
+
+impl PeerExpiry {
+    pub fn is_live(&self, peer: &Peer, now: Timestamp) -> bool {
+        now.saturating_sub(peer.recently_seen_timestamp) <= self.ttl_ms as Timestamp
+    }
+}
+
+/// A change of the known peers, notified to the subscribers of [`SharedKnownPeers`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerEvent {
+    Added(Peer),
+    /// The peer is replaced with the new one (e.g., with a new address or a later timestamp).
+    Updated(Peer),
+    Removed(Peer),
+}
+
+/// The number of the events kept for a subscriber that falls behind.
+const PEER_EVENT_CAPACITY: usize = 1024;
+
 /// The currently known peers that are for other modules,
 /// which will be updated by `PeerDiscovery`.
 #[derive(Clone, Debug)]
 pub struct SharedKnownPeers {
     lock: Arc<RwLock<Vec<Peer>>>,
+    events: tokio::sync::broadcast::Sender<PeerEvent>,
+    /// When each departed peer left, until its tombstone expires.
+    tombstones: Arc<parking_lot::Mutex<HashMap<PublicKey, Timestamp>>>,
+    /// The old keys of the members who rotated them, which are never accepted again.
+    retired_keys: Arc<parking_lot::Mutex<HashSet<PublicKey>>>,
+    /// The keys of the configured peers, which are never evicted.
+    configured: Arc<HashSet<PublicKey>>,
 }
 
 impl SharedKnownPeers {
     /// It is not constantly updated once created
+    ///
+    /// The given peers are the configured ones (e.g., in `peers.json`), which are never evicted
+    /// however long they haven't been seen.
     pub fn new_static(peers: Vec<Peer>) -> Self {
+        let configured = peers.iter().map(|peer| peer.public_key.clone()).collect();
         Self {
-            lock: Arc::new(RwLock::new(peers)),
+            configured: Arc::new(configured),
+            ..Self::new(Arc::new(RwLock::new(peers)))
         }
     }
 
+    /// Note that the changes made directly to `lock` are not notified to the subscribers.
     pub fn new(lock: Arc<RwLock<Vec<Peer>>>) -> Self {
-        Self { lock }
+        Self {
+            lock,
+            events: tokio::sync::broadcast::channel(PEER_EVENT_CAPACITY).0,
+            tombstones: Default::default(),
+            retired_keys: Default::default(),
+            configured: Default::default(),
+        }
+    }
+
+    /// Subscribes to the changes of the known peers made after this call,
+    /// so that a component can react to a new peer without polling.
+    ///
+    /// A subscriber that falls behind by more than [`PEER_EVENT_CAPACITY`] events
+    /// gets `RecvError::Lagged`, after which it should read the peers again.
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<PeerEvent> {
+        self.events.subscribe()
+    }
+
+    fn notify(&self, event: PeerEvent) {
+        // Fails only if there is no subscriber.
+        let _ = self.events.send(event);
     }
 
     pub async fn read(&self) -> Vec<Peer> {
         self.lock.read().await.clone()
     }
 
+    /// Reads only the peers that are live at `now`.
+    pub async fn read_live(&self, expiry: &PeerExpiry, now: Timestamp) -> Vec<Peer> {
+        self.read_filtered(|peer| expiry.is_live(peer, now)).await
+    }
+
+    /// Reads only the peers that satisfy the predicate.
+    pub async fn read_filtered(&self, predicate: impl Fn(&Peer) -> bool) -> Vec<Peer> {
+        self.lock
+            .read()
+            .await
+            .iter()
+            .filter(|peer| predicate(peer))
+            .cloned()
+            .collect()
+    }
+
+    /// Reads only the peers that support the protocol feature.
+    pub async fn read_supporting(&self, protocol: &str) -> Vec<Peer> {
+        self.read_filtered(|peer| peer.supports(protocol)).await
+    }
+
+    /// Reads only the peers that take the role.
+    pub async fn read_with_role(&self, role: PeerRole) -> Vec<Peer> {
+        self.read_filtered(|peer| peer.has_role(role)).await
+    }
+
+    /// Reads only the peers seen at or after `min_timestamp`.
+    pub async fn get_live_peers(&self, min_timestamp: Timestamp) -> Vec<Peer> {
+        self.read_filtered(|peer| peer.recently_seen_timestamp >= min_timestamp)
+            .await
+    }
+
+    /// Reads the peer of the key, if known.
+    pub async fn get_peer(&self, public_key: &PublicKey) -> Option<Peer> {
+        self.lock
+            .read()
+            .await
+            .iter()
+            .find(|peer| peer.public_key == *public_key)
+            .cloned()
+    }
+
+    /// Reads `n` random peers (or all of them, if fewer), in a random order.
+    ///
+    /// The same seed picks the same peers from the same known peers, so that the selection
+    /// can be reproduced (e.g., in tests).
+    pub async fn sample_peers(&self, n: usize, rng_seed: u64) -> Vec<Peer> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        self.lock
+            .read()
+            .await
+            .choose_multiple(&mut rng, n)
+            .cloned()
+            .collect()
+    }
+
+    pub async fn len(&self) -> usize {
+        self.lock.read().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.lock.read().await.is_empty()
+    }
+
+    /// Removes the peers that are no longer live at `now`, returning them.
+    ///
+    /// The configured peers are kept, and the expired tombstones are dropped.
+    pub async fn evict_expired(&self, expiry: &PeerExpiry, now: Timestamp) -> Vec<Peer> {
+        self.tombstones.lock().retain(|_, departed_at| {
+            now.saturating_sub(*departed_at) <= expiry.tombstone_ttl_ms as Timestamp
+        });
+        let mut known_peers = self.lock.write().await;
+        let (live, expired): (Vec<_>, Vec<_>) = known_peers.drain(..).partition(|peer| {
+            self.configured.contains(&peer.public_key) || expiry.is_live(peer, now)
+        });
+        *known_peers = live;
+        for peer in &expired {
+            self.notify(PeerEvent::Removed(peer.clone()));
+        }
+        expired
+    }
+
+    /// Spawns a task that periodically evicts the expired peers.
+    pub fn spawn_eviction_task(&self, expiry: PeerExpiry) -> tokio::task::JoinHandle<()> {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_millis(
+                expiry.eviction_interval_ms,
+            ));
+            loop {
+                interval.tick().await;
+                let now = chrono::Utc::now().timestamp_millis() as Timestamp;
+                for peer in this.evict_expired(&expiry, now).await {
+                    log::debug!("evicted an expired peer {}", peer.public_key);
+                }
+            }
+        })
+    }
+
+    /// Marks the peer as departed at `departed_at` (as announced by the peer),
+    /// removing it unless it has been seen after that.
+    ///
+    /// Until the tombstone expires, the peer isn't added again unless it's seen after the departure,
+    /// so that the stale information relayed by the others doesn't bring it back.
+    pub async fn mark_departed(
+        &self,
+        public_key: &PublicKey,
+        departed_at: Timestamp,
+    ) -> Option<Peer> {
+        let mut known_peers = self.lock.write().await;
+        {
+            let mut tombstones = self.tombstones.lock();
+            let tombstone = tombstones.entry(public_key.clone()).or_insert(departed_at);
+            *tombstone = (*tombstone).max(departed_at);
+        }
+        let index = known_peers.iter().position(|peer| {
+            peer.public_key == *public_key && peer.recently_seen_timestamp <= departed_at
+        })?;
+        let peer = known_peers.remove(index);
+        self.notify(PeerEvent::Removed(peer.clone()));
+        Some(peer)
+    }
+
+    /// Returns whether the peer is kept from being added by the tombstone of its departure.
+    pub fn is_tombstoned(&self, peer: &Peer) -> bool {
+        self.tombstones
+            .lock()
+            .get(&peer.public_key)
+            .map_or(false, |departed_at| {
+                peer.recently_seen_timestamp <= *departed_at
+            })
+    }
+
+    /// Returns whether the key has been rotated out, so that it's never accepted again.
+    pub fn is_retired(&self, public_key: &PublicKey) -> bool {
+        self.retired_keys.lock().contains(public_key)
+    }
+
+    /// Applies the key rotation of a member (see `simperby_common::TxRotateKey`),
+    /// returning the peer under the new key if it was known under the old one.
+    ///
+    /// The old key is retired, so the peer is known only by the new key from then on.
+    pub async fn rotate_key(&self, old_key: &PublicKey, new_key: PublicKey) -> Option<Peer> {
+        self.retired_keys.lock().insert(old_key.clone());
+        let mut known_peers = self.lock.write().await;
+        let index = known_peers
+            .iter()
+            .position(|peer| peer.public_key == *old_key)?;
+        let peer = known_peers.remove(index);
+        self.notify(PeerEvent::Removed(peer.clone()));
+        if known_peers.iter().any(|peer| peer.public_key == new_key) {
+            return None;
+        }
+        let peer = Peer {
+            public_key: new_key,
+            ..peer
+        };
+        known_peers.push(peer.clone());
+        self.notify(PeerEvent::Added(peer.clone()));
+        Some(peer)
+    }
+
+    /// Replaces the whole set of the known peers, except the tombstoned or retired ones.
+    pub async fn replace_all(&self, peers: Vec<Peer>) {
+        let peers = peers
+            .into_iter()
+            .filter(|peer| !self.is_tombstoned(peer) && !self.is_retired(&peer.public_key))
+            .collect::<Vec<_>>();
+        let mut known_peers = self.lock.write().await;
+        for peer in known_peers.iter() {
+            if !peers.iter().any(|x| x.public_key == peer.public_key) {
+                self.notify(PeerEvent::Removed(peer.clone()));
+            }
+        }
+        for peer in &peers {
+            match known_peers.iter().find(|x| x.public_key == peer.public_key) {
+                Some(known_peer) if known_peer == peer => {}
+                Some(_) => self.notify(PeerEvent::Updated(peer.clone())),
+                None => self.notify(PeerEvent::Added(peer.clone())),
+            }
+        }
+        *known_peers = peers;
+    }
+
+    /// Removes the peer, returning it if it was known.
+    pub async fn remove(&self, public_key: &PublicKey) -> Option<Peer> {
+        let mut known_peers = self.lock.write().await;
+        let index = known_peers
+            .iter()
+            .position(|peer| peer.public_key == *public_key)?;
+        let peer = known_peers.remove(index);
+        self.notify(PeerEvent::Removed(peer.clone()));
+        Some(peer)
+    }
+
+    /// Adds the peer, or replaces the known one of the same key, unless it's tombstoned or retired.
     pub async fn add_or_replace(&self, peer: Peer) {
+        if self.is_tombstoned(&peer) {
+            log::debug!("ignored the departed peer {}", peer.public_key);
+            return;
+        }
+        if self.is_retired(&peer.public_key) {
+            log::debug!("ignored the retired key {}", peer.public_key);
+            return;
+        }
         let mut known_peers = self.lock.write().await;
         let index = known_peers
             .iter()
             .position(|known_peer| known_peer.public_key == peer.public_key);
         match index {
-            Some(index) => known_peers[index] = peer,
-            None => known_peers.push(peer),
+            Some(index) if known_peers[index] == peer => {}
+            Some(index) => {
+                known_peers[index] = peer.clone();
+                self.notify(PeerEvent::Updated(peer));
+            }
+            None => {
+                known_peers.push(peer.clone());
+                self.notify(PeerEvent::Added(peer));
+            }
         }
     }
+
+    /// Verifies the signed record and adds the peer that it describes.
+    ///
+    /// Fails if the record is forged, of a non-member, not newer than the known one
+    /// of the same peer (a replay), from before the departure of the peer, or of a retired key.
+    pub async fn add_signed(
+        &self,
+        record: SignedPeerRecord,
+        members: &[PublicKey],
+    ) -> Result<(), Error> {
+        let peer = record.into_peer()?;
+        if !members.contains(&peer.public_key) {
+            return Err(eyre::eyre!(
+                "peer record of a non-member {}",
+                peer.public_key
+            ));
+        }
+        if self.is_retired(&peer.public_key) {
+            return Err(eyre::eyre!(
+                "peer record of {} is of a retired key",
+                peer.public_key
+            ));
+        }
+        if self.is_tombstoned(&peer) {
+            return Err(eyre::eyre!(
+                "peer record of {} is from before its departure",
+                peer.public_key
+            ));
+        }
+        let mut known_peers = self.lock.write().await;
+        match known_peers
+            .iter_mut()
+            .find(|known_peer| known_peer.public_key == peer.public_key)
+        {
+            Some(known_peer)
+                if known_peer.recently_seen_timestamp >= peer.recently_seen_timestamp =>
+            {
+                Err(eyre::eyre!(
+                    "peer record of {} is not newer than the known one",
+                    peer.public_key
+                ))
+            }
+            Some(known_peer) => {
+                *known_peer = peer.clone();
+                self.notify(PeerEvent::Updated(peer));
+                Ok(())
+            }
+            None => {
+                known_peers.push(peer.clone());
+                self.notify(PeerEvent::Added(peer));
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The current members of the network, which can be updated while the network layer is running
+/// (e.g., after a block that changes the validator set).
+#[derive(Clone, Debug, Default)]
+pub struct SharedMembers {
+    lock: Arc<parking_lot::RwLock<Vec<PublicKey>>>,
+}
+
+impl SharedMembers {
+    pub fn new(members: Vec<PublicKey>) -> Self {
+        Self {
+            lock: Arc::new(parking_lot::RwLock::new(members)),
+        }
+    }
+
+    pub fn read(&self) -> Vec<PublicKey> {
+        self.lock.read().clone()
+    }
+
+    pub fn contains(&self, member: &PublicKey) -> bool {
+        self.lock.read().contains(member)
+    }
+
+    /// Replaces the members, which every component sharing this set picks up.
+    pub fn update_members(&self, members: Vec<PublicKey>) {
+        *self.lock.write() = members;
+    }
 }
 
 /// The peer discovery protocol backed by the local file system.
@@ -99,7 +742,7 @@ pub trait PeerDiscovery {
     async fn serve(
         storage_directory: &str,
         network_config: &NetworkConfig,
-    ) -> Result<(SharedKnownPeers, tokio::task::JoinHandle<Result<(), Error>>), Error>;
+    ) -> Result<(SharedKnownPeers, ServiceHandle), Error>;
 
     /// Reads the known peers from the storage.
     async fn read_known_peers(storage_directory: &str) -> Result<Vec<Peer>, Error>;
@@ -133,7 +776,286 @@ impl<T, E> Serve<T, E> {
         self.task.await
     }
 
-    pub async fn read(&self) -> tokio::sync::RwLockReadGuard<T> {
+    pub async fn read(&self) -> tokio::sync::RwLockReadGuard<'_, T> {
         self.read_only_lock.read().await
     }
 }
+
+/// The handle of a network service (e.g., a primitive) running in the background,
+/// which can be shut down gracefully.
+///
+/// Dropping the handle detaches the service, like dropping a `JoinHandle`.
+pub struct ServiceHandle {
+    task: tokio::task::JoinHandle<Result<(), Error>>,
+    shutdown: tokio::sync::oneshot::Sender<()>,
+}
+
+/// Tells a service that its [`ServiceHandle::shutdown()`] is called.
+pub struct ShutdownSignal {
+    receiver: Option<tokio::sync::oneshot::Receiver<()>>,
+}
+
+impl ShutdownSignal {
+    /// Resolves once the shutdown is requested, or never if the handle is dropped instead.
+    ///
+    /// It's cancel-safe, so it can be awaited in a `select!` loop.
+    pub async fn requested(&mut self) {
+        if let Some(receiver) = &mut self.receiver {
+            if receiver.await.is_ok() {
+                return;
+            }
+            self.receiver = None;
+        }
+        futures::future::pending().await
+    }
+}
+
+impl ServiceHandle {
+    /// Spawns the service, which is expected to clean up and finish once the signal is received.
+    pub fn spawn<F, Fut>(service: F) -> Self
+    where
+        F: FnOnce(ShutdownSignal) -> Fut,
+        Fut: futures::Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        let (shutdown, receiver) = tokio::sync::oneshot::channel();
+        let signal = ShutdownSignal {
+            receiver: Some(receiver),
+        };
+        Self {
+            task: tokio::spawn(service(signal)),
+            shutdown,
+        }
+    }
+
+    /// Asks the service to shut down, waiting until it finishes.
+    pub async fn shutdown(self) -> Result<(), Error> {
+        // Fails only if the service has finished already.
+        let _ = self.shutdown.send(());
+        self.task.await?
+    }
+
+    /// Waits until the service finishes by itself.
+    pub async fn join(self) -> Result<(), Error> {
+        self.task.await?
+    }
+
+    /// Stops the service immediately, without cleaning up.
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    fn peer(seed: &str, recently_seen_timestamp: Timestamp) -> Peer {
+        Peer {
+            public_key: generate_keypair(seed).0,
+            name: seed.to_owned(),
+            address: "127.0.0.1:1".parse().unwrap(),
+            addresses: Vec::new(),
+            ports: HashMap::new(),
+            metadata: Default::default(),
+            recently_seen_timestamp,
+        }
+    }
+
+    #[tokio::test]
+    async fn peer_expiry() {
+        setup_test();
+        let expiry = PeerExpiry {
+            ttl_ms: 100,
+            eviction_interval_ms: 10,
+            tombstone_ttl_ms: 100,
+        };
+        // The configured peer is never evicted.
+        let peers = SharedKnownPeers::new_static(vec![peer("s", 0)]);
+        for peer in [peer("a", 0), peer("b", 50), peer("c", 150)] {
+            peers.add_or_replace(peer).await;
+        }
+        assert_eq!(
+            peers.read_live(&expiry, 140).await,
+            vec![peer("b", 50), peer("c", 150)]
+        );
+        assert_eq!(peers.read().await.len(), 4);
+        assert_eq!(peers.evict_expired(&expiry, 140).await, vec![peer("a", 0)]);
+        assert_eq!(
+            peers.read().await,
+            vec![peer("s", 0), peer("b", 50), peer("c", 150)]
+        );
+
+        // Every peer is long expired in the wall clock.
+        let task = peers.spawn_eviction_task(expiry);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        task.abort();
+        assert_eq!(peers.read().await, vec![peer("s", 0)]);
+    }
+
+    #[tokio::test]
+    async fn queries() {
+        setup_test();
+        let peers =
+            SharedKnownPeers::new_static((0..10).map(|i| peer(&i.to_string(), i)).collect());
+        assert_eq!(peers.len().await, 10);
+        assert_eq!(
+            peers.get_peer(&peer("3", 0).public_key).await,
+            Some(peer("3", 3))
+        );
+        assert_eq!(peers.get_peer(&peer("x", 0).public_key).await, None);
+        assert_eq!(
+            peers.get_live_peers(8).await,
+            vec![peer("8", 8), peer("9", 9)]
+        );
+
+        let sample = peers.sample_peers(4, 42).await;
+        assert_eq!(sample.len(), 4);
+        assert_eq!(peers.sample_peers(4, 42).await, sample);
+        let mut keys = sample
+            .iter()
+            .map(|x| x.public_key.clone())
+            .collect::<Vec<_>>();
+        keys.dedup();
+        assert_eq!(keys.len(), 4);
+        assert_ne!(
+            peers.sample_peers(10, 1).await,
+            peers.sample_peers(10, 2).await
+        );
+        assert_eq!(peers.sample_peers(20, 42).await.len(), 10);
+        assert!(SharedKnownPeers::new_static(Vec::new()).is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn tombstones() {
+        setup_test();
+        let peers = SharedKnownPeers::new_static(vec![peer("a", 10), peer("b", 30)]);
+        let (a, b) = (peer("a", 0).public_key, peer("b", 0).public_key);
+        assert_eq!(peers.mark_departed(&a, 20).await, Some(peer("a", 10)));
+        // `b` has been seen after its departure.
+        assert_eq!(peers.mark_departed(&b, 20).await, None);
+        assert_eq!(peers.read().await, vec![peer("b", 30)]);
+
+        // The stale information doesn't bring `a` back, while it can rejoin.
+        peers.add_or_replace(peer("a", 20)).await;
+        peers.replace_all(vec![peer("a", 15), peer("b", 30)]).await;
+        assert_eq!(peers.read().await, vec![peer("b", 30)]);
+        peers.add_or_replace(peer("a", 25)).await;
+        assert_eq!(peers.read().await, vec![peer("b", 30), peer("a", 25)]);
+
+        // Once the tombstone expires, anything goes.
+        peers.mark_departed(&a, 40).await;
+        let expiry = PeerExpiry {
+            ttl_ms: 1000,
+            eviction_interval_ms: 10,
+            tombstone_ttl_ms: 100,
+        };
+        peers.evict_expired(&expiry, 100).await;
+        assert!(peers.is_tombstoned(&peer("a", 0)));
+        peers.evict_expired(&expiry, 141).await;
+        assert!(!peers.is_tombstoned(&peer("a", 0)));
+        peers.add_or_replace(peer("a", 0)).await;
+        assert_eq!(peers.read().await, vec![peer("b", 30), peer("a", 0)]);
+    }
+
+    #[tokio::test]
+    async fn key_rotation() {
+        setup_test();
+        let peers = SharedKnownPeers::new_static(vec![peer("a", 10), peer("b", 10)]);
+        let (a, a2) = (peer("a", 0).public_key, peer("a2", 0).public_key);
+        let rotated = Peer {
+            public_key: a2.clone(),
+            ..peer("a", 10)
+        };
+        assert_eq!(peers.rotate_key(&a, a2).await, Some(rotated.clone()));
+        assert_eq!(peers.read().await, vec![peer("b", 10), rotated.clone()]);
+
+        // The old key is never accepted again, however recent.
+        assert!(peers.is_retired(&a));
+        peers.add_or_replace(peer("a", 20)).await;
+        peers
+            .replace_all(vec![peer("a", 20), peer("b", 10), rotated.clone()])
+            .await;
+        assert_eq!(peers.read().await, vec![peer("b", 10), rotated]);
+    }
+
+    #[tokio::test]
+    async fn peer_events() {
+        setup_test();
+        let peers = SharedKnownPeers::new_static(vec![peer("a", 0)]);
+        let mut events = peers.subscribe();
+        peers.add_or_replace(peer("b", 0)).await;
+        peers.add_or_replace(peer("b", 0)).await;
+        peers.add_or_replace(peer("b", 10)).await;
+        peers.replace_all(vec![peer("b", 10), peer("c", 0)]).await;
+        let expiry = PeerExpiry {
+            ttl_ms: 5,
+            eviction_interval_ms: 10,
+            tombstone_ttl_ms: 5,
+        };
+        peers.evict_expired(&expiry, 10).await;
+
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        assert_eq!(
+            received,
+            vec![
+                PeerEvent::Added(peer("b", 0)),
+                PeerEvent::Updated(peer("b", 10)),
+                PeerEvent::Removed(peer("a", 0)),
+                PeerEvent::Added(peer("c", 0)),
+                PeerEvent::Removed(peer("c", 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn peer_hosts() {
+        setup_test();
+        let mut peer = peer("a", 0);
+        peer.addresses = vec![
+            PeerAddress::Ip("::1".parse().unwrap()),
+            PeerAddress::Ip("127.0.0.1".parse().unwrap()),
+            PeerAddress::Dns("node.example".to_owned()),
+        ];
+        let hosts = peer
+            .hosts()
+            .iter()
+            .map(|x| format!("{x}:80"))
+            .collect::<Vec<_>>();
+        assert_eq!(hosts, ["127.0.0.1:80", "[::1]:80", "node.example:80"]);
+    }
+
+    #[tokio::test]
+    async fn metadata_filters() {
+        setup_test();
+        let mut archive = peer("a", 0);
+        archive.metadata = PeerMetadata::current("0.1.0", vec![PeerRole::Archive]);
+        let mut relay = peer("b", 0);
+        relay.metadata = PeerMetadata {
+            node_version: "0.2.0".to_owned(),
+            protocols: vec!["dms-http-rpc".to_owned()],
+            roles: vec![PeerRole::Relay],
+        };
+        let peers =
+            SharedKnownPeers::new_static(vec![archive.clone(), relay.clone(), peer("c", 0)]);
+
+        assert_eq!(relay.node_version(), "0.2.0");
+        assert!(archive.supports("git-daemon"));
+        assert!(!relay.supports("git-daemon"));
+        assert_eq!(
+            peers.read_with_role(PeerRole::Archive).await,
+            vec![archive.clone()]
+        );
+        assert_eq!(
+            peers.read_supporting("dms-http-rpc").await,
+            vec![archive, relay]
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Resource limits with backpressure.
+//!
+//! Instead of growing without bound under a load spike (and getting killed by the OS),
+//! a component admits incoming data through a [`ResourceGuard`].
+//! Critical data (e.g., messages created by this node) is always admitted,
+//! while non-critical data (e.g., messages gossiped from the peers) is shed
+//! once the limits are reached.
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// The maximum total size of the stored data in bytes. `None` means unlimited.
+    pub max_storage_bytes: Option<u64>,
+    /// The maximum number of bytes that may be stored per second. `None` means unlimited.
+    pub max_growth_bytes_per_sec: Option<u64>,
+}
+
+/// The number of times that each limit has been hit.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LimitMetrics {
+    pub storage_limit_hits: u64,
+    pub growth_limit_hits: u64,
+    pub shed_bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Storage,
+    Growth,
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitExceeded::Storage => write!(f, "storage limit exceeded"),
+            LimitExceeded::Growth => write!(f, "storage growth rate limit exceeded"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ResourceGuard {
+    limits: ResourceLimits,
+    stored_bytes: AtomicU64,
+    /// The start of the current one-second window and the bytes stored in it.
+    window: parking_lot::Mutex<(Instant, u64)>,
+    metrics: parking_lot::Mutex<LimitMetrics>,
+}
+
+impl ResourceGuard {
+    pub fn new(limits: ResourceLimits, stored_bytes: u64) -> Self {
+        Self {
+            limits,
+            stored_bytes: AtomicU64::new(stored_bytes),
+            window: parking_lot::Mutex::new((Instant::now(), 0)),
+            metrics: Default::default(),
+        }
+    }
+
+    pub fn limits(&self) -> &ResourceLimits {
+        &self.limits
+    }
+
+    pub fn stored_bytes(&self) -> u64 {
+        self.stored_bytes.load(Ordering::SeqCst)
+    }
+
+    pub fn metrics(&self) -> LimitMetrics {
+        self.metrics.lock().clone()
+    }
+
+    /// Returns whether non-critical work should be paused, i.e., not even a byte can be admitted.
+    pub fn is_saturated(&self) -> bool {
+        self.check(1).is_err()
+    }
+
+    fn check(&self, size: u64) -> Result<(), LimitExceeded> {
+        if let Some(max) = self.limits.max_storage_bytes {
+            if self.stored_bytes() + size > max {
+                return Err(LimitExceeded::Storage);
+            }
+        }
+        if let Some(max) = self.limits.max_growth_bytes_per_sec {
+            let mut window = self.window.lock();
+            if window.0.elapsed() >= Duration::from_secs(1) {
+                *window = (Instant::now(), 0);
+            }
+            if window.1 + size > max {
+                return Err(LimitExceeded::Growth);
+            }
+        }
+        Ok(())
+    }
+
+    /// Tries to admit data of the given size.
+    ///
+    /// Critical data is always admitted (but still counted).
+    pub fn admit(&self, size: u64, critical: bool) -> Result<(), LimitExceeded> {
+        if let Err(e) = self.check(size) {
+            let mut metrics = self.metrics.lock();
+            match e {
+                LimitExceeded::Storage => metrics.storage_limit_hits += 1,
+                LimitExceeded::Growth => metrics.growth_limit_hits += 1,
+            }
+            if !critical {
+                metrics.shed_bytes += size;
+                return Err(e);
+            }
+        }
+        self.stored_bytes.fetch_add(size, Ordering::SeqCst);
+        self.window.lock().1 += size;
+        Ok(())
+    }
+
+    /// Releases data of the given size (e.g., after a file is removed).
+    pub fn release(&self, size: u64) {
+        let _ = self
+            .stored_bytes
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| {
+                Some(x.saturating_sub(size))
+            });
+    }
+
+    /// Resets the stored size (e.g., after the storage is cleared).
+    pub fn reset(&self, stored_bytes: u64) {
+        self.stored_bytes.store(stored_bytes, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    #[test]
+    fn storage_limit() {
+        setup_test();
+        let guard = ResourceGuard::new(
+            ResourceLimits {
+                max_storage_bytes: Some(100),
+                max_growth_bytes_per_sec: None,
+            },
+            0,
+        );
+        guard.admit(60, false).unwrap();
+        assert_eq!(guard.admit(60, false), Err(LimitExceeded::Storage));
+        guard.admit(40, false).unwrap();
+        assert!(guard.is_saturated());
+        guard.admit(10, true).unwrap();
+        assert_eq!(guard.stored_bytes(), 110);
+        assert_eq!(
+            guard.metrics(),
+            LimitMetrics {
+                storage_limit_hits: 2,
+                growth_limit_hits: 0,
+                shed_bytes: 60,
+            }
+        );
+    }
+
+    #[test]
+    fn growth_limit() {
+        setup_test();
+        let guard = ResourceGuard::new(
+            ResourceLimits {
+                max_storage_bytes: None,
+                max_growth_bytes_per_sec: Some(100),
+            },
+            0,
+        );
+        guard.admit(100, false).unwrap();
+        assert_eq!(guard.admit(1, false), Err(LimitExceeded::Growth));
+        std::thread::sleep(Duration::from_millis(1100));
+        guard.admit(100, false).unwrap();
+    }
+}
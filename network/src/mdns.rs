@@ -0,0 +1,165 @@
+//! Discovery of the peers on the local subnet over mDNS, for local development and LAN testnets.
+//!
+//! Enabled by `NetworkConfig::enable_mdns`, a node advertises the port serving its peer record
+//! (the [`PEER_RECORD_PROTOCOL`] port) as an mDNS service, tagged with the network id.
+//! The records of the discovered nodes are fetched and merged just like the ones from the DNS seeds,
+//! so they are still verified against the member list.
+use super::*;
+use crate::mux::MUX_PROTOCOL;
+use crate::seeds::{merge_peer_record, PEER_RECORD_PROTOCOL};
+use eyre::eyre;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use tracing::Instrument;
+
+/// The mDNS service type of the Simperby nodes.
+pub const MDNS_SERVICE_TYPE: &str = "_simperby._tcp.local.";
+
+fn instance_name(public_key: &PublicKey) -> String {
+    // A DNS label can't exceed 63 bytes.
+    Hash256::hash(public_key.to_string()).to_string()[..32].to_owned()
+}
+
+fn service_info(network_config: &NetworkConfig) -> Result<ServiceInfo, Error> {
+    let port = *network_config
+        .ports
+        .get(PEER_RECORD_PROTOCOL)
+        .or_else(|| network_config.ports.get(MUX_PROTOCOL))
+        .ok_or_else(|| eyre!("mDNS requires the `{PEER_RECORD_PROTOCOL}` port"))?;
+    let name = instance_name(&network_config.public_key);
+    Ok(ServiceInfo::new(
+        MDNS_SERVICE_TYPE,
+        &name,
+        &format!("{name}.local."),
+        "",
+        port,
+        &[("network_id", network_config.network_id.as_str())][..],
+    )?
+    .enable_addr_auto())
+}
+
+/// Returns the addresses serving the peer records of the discovered service,
+/// or nothing if the service belongs to another network.
+fn peer_record_addresses(info: &ServiceInfo, network_id: &str) -> Vec<SocketAddrV4> {
+    if info.get_property_val_str("network_id") != Some(network_id) {
+        return Vec::new();
+    }
+    info.get_addresses_v4()
+        .into_iter()
+        .map(|ip| SocketAddrV4::new(*ip, info.get_port()))
+        .collect()
+}
+
+/// Advertises this node and spawns a task that merges the peers discovered over mDNS
+/// into the known peers.
+///
+/// The discovered peers are checked against the current `members`.
+///
+/// Returns `None` if mDNS is not enabled in the config.
+pub fn spawn_mdns_discovery<P: RpcPrimitive>(
+    rpc: Arc<P>,
+    network_config: NetworkConfig,
+    members: SharedMembers,
+    known_peers: SharedKnownPeers,
+) -> Result<Option<tokio::task::JoinHandle<()>>, Error> {
+    if !network_config.enable_mdns {
+        return Ok(None);
+    }
+    let daemon = ServiceDaemon::new()?;
+    let info = service_info(&network_config)?;
+    let own_fullname = info.get_fullname().to_owned();
+    daemon.register(info)?;
+    let receiver = daemon.browse(MDNS_SERVICE_TYPE)?;
+    let filter = network_config.peer_filter();
+    Ok(Some(tokio::spawn(async move {
+        // Keeps the daemon (and the advertisement) alive along with the task.
+        let _daemon = daemon;
+        while let Ok(event) = receiver.recv_async().await {
+            let info = match event {
+                ServiceEvent::ServiceResolved(info) if info.get_fullname() != own_fullname => info,
+                _ => continue,
+            };
+            let span = tracing::info_span!("mdns_discovery", service = info.get_fullname());
+            for address in peer_record_addresses(&info, &network_config.network_id) {
+                let members = members.read();
+                if let Err(e) = merge_peer_record(
+                    rpc.as_ref(),
+                    address,
+                    &network_config.network_id,
+                    &members,
+                    &filter,
+                    &known_peers,
+                )
+                .instrument(span.clone())
+                .await
+                {
+                    log::warn!("failed to merge the peer at {address} discovered over mDNS: {e}");
+                }
+            }
+        }
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::{RpcConfig, TcpRpc};
+    use simperby_test_suite::*;
+
+    fn network_config(network_id: &str) -> NetworkConfig {
+        let (public_key, private_key) = generate_keypair_random();
+        NetworkConfig {
+            network_id: network_id.to_owned(),
+            ports: vec![(PEER_RECORD_PROTOCOL.to_owned(), 1234)]
+                .into_iter()
+                .collect(),
+            members: vec![public_key.clone()],
+            public_key,
+            private_key: Some(private_key),
+            dns_seeds: Vec::new(),
+            enable_mdns: true,
+            pre_shared_key: None,
+            proxy: None,
+            denied_peers: Vec::new(),
+            allowed_peers: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn filter_other_networks() {
+        setup_test();
+        let info = ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            "node",
+            "node.local.",
+            "192.168.0.2,fe80::1",
+            1234,
+            &[("network_id", "mynet")][..],
+        )
+        .unwrap();
+        assert_eq!(
+            peer_record_addresses(&info, "mynet"),
+            vec!["192.168.0.2:1234".parse::<SocketAddrV4>().unwrap()]
+        );
+        assert!(peer_record_addresses(&info, "othernet").is_empty());
+
+        let info = service_info(&network_config("mynet")).unwrap();
+        assert_eq!(info.get_port(), 1234);
+        assert_eq!(info.get_property_val_str("network_id"), Some("mynet"));
+    }
+
+    #[tokio::test]
+    async fn disabled() {
+        setup_test();
+        let config = NetworkConfig {
+            enable_mdns: false,
+            pre_shared_key: None,
+            ..network_config("mynet")
+        };
+        let rpc = Arc::new(TcpRpc::new(RpcConfig::default()));
+        let known_peers = SharedKnownPeers::new_static(Vec::new());
+        let members = SharedMembers::new(config.members.clone());
+        assert!(spawn_mdns_discovery(rpc, config, members, known_peers)
+            .unwrap()
+            .is_none());
+    }
+}
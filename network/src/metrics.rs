@@ -0,0 +1,235 @@
+//! Metrics of the network layer, for monitoring the connectivity of a node.
+//!
+//! The counters are collected in a [`NetworkMetrics`] (which can be shared by multiple components of a node)
+//! and read as a [`MetricsSnapshot`], which can be exported in the Prometheus text format
+//! by [`serve_metrics()`].
+use super::*;
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// The peers whose last dial succeeded.
+    pub connected_peers: u64,
+    pub known_peers: u64,
+    /// The broadcasts to the peers in progress.
+    pub broadcasts_in_flight: u64,
+    /// The acknowledgements of the broadcasted messages from the peers, in total.
+    pub acks_received: u64,
+    pub ingress_bytes: u64,
+    pub egress_bytes: u64,
+    pub dial_failures: u64,
+}
+
+impl MetricsSnapshot {
+    /// Formats the metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let metrics: [(&str, &str, &str, u64); 7] = [
+            (
+                "connected_peers",
+                "gauge",
+                "The peers whose last dial succeeded.",
+                self.connected_peers,
+            ),
+            ("known_peers", "gauge", "The known peers.", self.known_peers),
+            (
+                "broadcasts_in_flight",
+                "gauge",
+                "The broadcasts to the peers in progress.",
+                self.broadcasts_in_flight,
+            ),
+            (
+                "acks_received_total",
+                "counter",
+                "The acknowledgements of the broadcasted messages.",
+                self.acks_received,
+            ),
+            (
+                "ingress_bytes_total",
+                "counter",
+                "The bytes received from the peers.",
+                self.ingress_bytes,
+            ),
+            (
+                "egress_bytes_total",
+                "counter",
+                "The bytes sent to the peers.",
+                self.egress_bytes,
+            ),
+            (
+                "dial_failures_total",
+                "counter",
+                "The failed dials to the peers.",
+                self.dial_failures,
+            ),
+        ];
+        metrics
+            .iter()
+            .map(|(name, kind, help, value)| {
+                format!(
+                    "# HELP simperby_network_{name} {help}\n\
+                     # TYPE simperby_network_{name} {kind}\n\
+                     simperby_network_{name} {value}\n"
+                )
+            })
+            .collect()
+    }
+}
+
+/// The counters of the network layer.
+#[derive(Debug, Default)]
+pub struct NetworkMetrics {
+    broadcasts_in_flight: AtomicU64,
+    acks_received: AtomicU64,
+    dial_failures: AtomicU64,
+    /// Whether the last dial to each peer succeeded.
+    connected: Mutex<HashMap<PublicKey, bool>>,
+}
+
+/// Counts a broadcast as in flight until dropped.
+pub struct InFlightBroadcast<'a> {
+    metrics: &'a NetworkMetrics,
+}
+
+impl Drop for InFlightBroadcast<'_> {
+    fn drop(&mut self) {
+        self.metrics
+            .broadcasts_in_flight
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl NetworkMetrics {
+    pub fn record_dial(&self, peer: &PublicKey, success: bool) {
+        if !success {
+            self.dial_failures.fetch_add(1, Ordering::Relaxed);
+        }
+        self.connected.lock().insert(peer.clone(), success);
+    }
+
+    pub fn record_acks(&self, count: u64) {
+        self.acks_received.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn start_broadcast(&self) -> InFlightBroadcast<'_> {
+        self.broadcasts_in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightBroadcast { metrics: self }
+    }
+
+    /// Takes a snapshot along with the metrics kept elsewhere.
+    pub fn snapshot(
+        &self,
+        known_peers: usize,
+        traffic: bandwidth::TrafficCounter,
+    ) -> MetricsSnapshot {
+        MetricsSnapshot {
+            connected_peers: self.connected.lock().values().filter(|x| **x).count() as u64,
+            known_peers: known_peers as u64,
+            broadcasts_in_flight: self.broadcasts_in_flight.load(Ordering::Relaxed),
+            acks_received: self.acks_received.load(Ordering::Relaxed),
+            ingress_bytes: traffic.ingress_bytes,
+            egress_bytes: traffic.egress_bytes,
+            dial_failures: self.dial_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Serves the metrics taken by `snapshot` at `/metrics` on the given port indefinitely,
+/// for Prometheus to scrape.
+pub async fn serve_metrics<F, Fut>(port: u16, snapshot: F) -> Result<(), Error>
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: futures::Future<Output = MetricsSnapshot> + Send + 'static,
+{
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    let snapshot = Arc::new(snapshot);
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let snapshot = Arc::clone(&snapshot);
+        tokio::spawn(async move {
+            let mut buffer = [0; 1024];
+            let n = match stream.read(&mut buffer).await {
+                Ok(n) => n,
+                Err(_) => return,
+            };
+            let request = String::from_utf8_lossy(&buffer[..n]);
+            let response = match request.split_whitespace().nth(1).unwrap_or_default() {
+                "/metrics" => {
+                    let body = snapshot().await.to_prometheus();
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                }
+                _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_owned(),
+            };
+            if let Err(e) = stream.write_all(response.as_bytes()).await {
+                log::warn!("failed to respond to a metrics scrape: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    #[tokio::test]
+    async fn metrics() {
+        setup_test();
+        let metrics = Arc::new(NetworkMetrics::default());
+        let (a, b) = (generate_keypair("a").0, generate_keypair("b").0);
+        metrics.record_dial(&a, true);
+        metrics.record_dial(&b, false);
+        metrics.record_acks(3);
+        let broadcast = metrics.start_broadcast();
+        let traffic = bandwidth::TrafficCounter {
+            ingress_bytes: 10,
+            egress_bytes: 20,
+        };
+        let snapshot = metrics.snapshot(5, traffic);
+        assert_eq!(
+            snapshot,
+            MetricsSnapshot {
+                connected_peers: 1,
+                known_peers: 5,
+                broadcasts_in_flight: 1,
+                acks_received: 3,
+                ingress_bytes: 10,
+                egress_bytes: 20,
+                dial_failures: 1,
+            }
+        );
+        drop(broadcast);
+        metrics.record_dial(&b, true);
+        let snapshot = metrics.snapshot(5, traffic);
+        assert_eq!(snapshot.broadcasts_in_flight, 0);
+        assert_eq!(snapshot.connected_peers, 2);
+
+        let port = dispense_port();
+        let metrics_ = Arc::clone(&metrics);
+        let server = tokio::spawn(serve_metrics(port, move || {
+            let snapshot = metrics_.snapshot(5, traffic);
+            async move { snapshot }
+        }));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("# TYPE simperby_network_dial_failures_total counter\n"));
+        assert!(response.contains("\nsimperby_network_connected_peers 2\n"));
+        server.abort();
+    }
+}
@@ -0,0 +1,849 @@
+//! Multiplexing the sub-protocols over a single connection per peer.
+//!
+//! With [`crate::rpc::TcpRpc`], each protocol is served on its own port (see `Peer::ports`)
+//! and each request opens a new connection. A [`MuxRpc`] instead serves all the protocols
+//! on a single port, advertised as [`MUX_PROTOCOL`] in `Peer::ports`, and keeps a single
+//! connection to each peer, over which the requests of all the protocols are made concurrently.
+//!
+//! The connection is set up as in [`crate::rpc`] (with the pre-shared key, the compression
+//! and the member identity),
+//! after which each frame is a [`MuxFrame`]: a request names its sub-protocol,
+//! and a response carries the id of its request, so the responses can arrive in any order.
+//!
+//! The per-protocol ports are deprecated in favor of the multiplexed one,
+//! but still served by `TcpRpc` for the peers that don't multiplex yet.
+//! A DMS multiplexes its sync and broadcast RPCs as `dms-<key>` once given a `MuxRpc`
+//! (see [`crate::dms::DistributedMessageSet::set_mux()`]).
+//!
+//! A node participating in multiple networks (e.g., a relayer) can serve all of them on one port
+//! through a [`ScopedMuxRpc`] for each network, which names the protocols by [`scoped_protocol()`],
+//! so that a request never reaches the handler of another network.
+use super::*;
+use crate::access::PeerFilter;
+use crate::auth_events::{AuthEvents, AuthFailureReason};
+use crate::identity::MemberIdentity;
+use crate::proxy::ProxyConfig;
+use crate::psk::{PreSharedKey, Side};
+use crate::rpc::{
+    connect, with_remote_address, Acceptor, Connection, FrameReader, FrameWriter, RpcConfig,
+};
+use eyre::eyre;
+use futures::{future::BoxFuture, Future, FutureExt};
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{oneshot, Semaphore};
+use tracing::Instrument;
+
+/// The identifier of the multiplexed port in `Peer::ports`.
+pub const MUX_PROTOCOL: &str = "mux";
+
+/// The name that the protocol of the network is multiplexed under by a [`ScopedMuxRpc`].
+pub fn scoped_protocol(network_id: &str, protocol: &str) -> String {
+    format!("{network_id}/{protocol}")
+}
+
+/// A frame on a multiplexed connection.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum MuxFrame {
+    Request {
+        id: u64,
+        protocol: String,
+        /// The encoded request.
+        payload: Vec<u8>,
+    },
+    Response {
+        id: u64,
+        /// The encoded `Result<R, String>`.
+        payload: Vec<u8>,
+    },
+}
+
+/// Answers an encoded request with an encoded response.
+type Handler = Arc<dyn Fn(Vec<u8>) -> BoxFuture<'static, Vec<u8>> + Send + Sync>;
+
+type Handlers = Arc<parking_lot::RwLock<HashMap<String, Handler>>>;
+
+/// The requests waiting for their responses, or `None` once the connection is closed.
+type Pending = Arc<Mutex<Option<HashMap<u64, oneshot::Sender<Vec<u8>>>>>>;
+
+/// The connections by the key, the address and the multiplexed port of the peers
+/// (as the key is unknown when bootstrapping from an address).
+type Connections = HashMap<(PublicKey, SocketAddrV4, u16), Arc<MuxConnection>>;
+
+/// An implementation of [`RpcPrimitive`] that multiplexes the protocols over a single port and
+/// a single connection per peer.
+///
+/// The protocols must be served with [`RpcPrimitive::serve_protocol()`], all on the same port.
+pub struct MuxRpc {
+    config: RpcConfig,
+    semaphore: Arc<Semaphore>,
+    pre_shared_key: Option<PreSharedKey>,
+    proxy: Option<ProxyConfig>,
+    filter: PeerFilter,
+    auth_events: AuthEvents,
+    identity: Option<Arc<MemberIdentity>>,
+    handlers: Handlers,
+    /// The port being listened on, and the task accepting the connections.
+    #[allow(clippy::type_complexity)]
+    listener: tokio::sync::Mutex<Option<(u16, tokio::task::JoinHandle<Result<(), Error>>)>>,
+    connections: tokio::sync::Mutex<Connections>,
+}
+
+impl MuxRpc {
+    pub fn new(config: RpcConfig) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(config.max_concurrent_requests.max(1))),
+            config,
+            pre_shared_key: None,
+            proxy: None,
+            filter: PeerFilter::default(),
+            auth_events: AuthEvents::default(),
+            identity: None,
+            handlers: Default::default(),
+            listener: Default::default(),
+            connections: Default::default(),
+        }
+    }
+
+    /// Encrypts the connections with the key (usually `NetworkConfig::pre_shared_key`),
+    /// refusing the other side unless it has the same key.
+    pub fn with_pre_shared_key(mut self, pre_shared_key: Option<PreSharedKey>) -> Self {
+        self.pre_shared_key = pre_shared_key;
+        self
+    }
+
+    /// The key that the connections are encrypted with, if any.
+    pub fn pre_shared_key(&self) -> Option<&PreSharedKey> {
+        self.pre_shared_key.as_ref()
+    }
+
+    /// Makes the outbound connections through the proxy (usually `NetworkConfig::proxy`).
+    pub fn with_proxy(mut self, proxy: Option<ProxyConfig>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Refuses to connect to the peers, or to accept the connections from the addresses,
+    /// that the filter (usually `NetworkConfig::peer_filter()`) refuses.
+    pub fn with_peer_filter(mut self, filter: PeerFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Reports the refused inbound connections (e.g., with a wrong pre-shared key) to the events,
+    /// including the requests refused by the filters of the [`ScopedMuxRpc`]s.
+    pub fn with_auth_events(mut self, auth_events: AuthEvents) -> Self {
+        self.auth_events = auth_events;
+        self
+    }
+
+    /// Pins the connections to the member keys of the identity
+    /// (usually made from the `NetworkConfig`), refusing the other side unless it pins too.
+    pub fn with_member_identity(mut self, identity: Option<Arc<MemberIdentity>>) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Returns a view of this that serves and requests only the protocols of the network,
+    /// sharing the port and the connections with the other networks.
+    pub fn scoped(self: &Arc<Self>, network_id: &str) -> ScopedMuxRpc {
+        ScopedMuxRpc {
+            rpc: Arc::clone(self),
+            network_id: network_id.to_owned(),
+            filter: PeerFilter::default(),
+        }
+    }
+
+    /// The peers with an open connection.
+    pub async fn connected_peers(&self) -> Vec<PublicKey> {
+        self.connections
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, connection)| !connection.is_closed())
+            .map(|((key, _, _), _)| key.clone())
+            .collect()
+    }
+
+    /// Returns the open connection to the peer, or opens a new one.
+    async fn connection(&self, peer: &Peer) -> Result<Arc<MuxConnection>, Error> {
+        let port = *peer.ports.get(MUX_PROTOCOL).ok_or_else(|| {
+            eyre!(
+                "peer {} doesn't serve multiplexed protocols",
+                peer.public_key
+            )
+        })?;
+        let key = (peer.public_key.clone(), peer.address, port);
+        if let Some(connection) = self.connections.lock().await.get(&key) {
+            if !connection.is_closed() {
+                return Ok(Arc::clone(connection));
+            }
+        }
+        let stream = connect(peer, port, self.proxy.as_ref()).await?;
+        let connection = Connection::open(
+            stream,
+            Side::Client,
+            self.pre_shared_key.as_ref(),
+            &self.config.compression,
+            self.identity.as_deref(),
+        )
+        .await?;
+        if let (Some(identity), Some(key)) = (&self.identity, connection.remote_key()) {
+            identity.check_server(peer, key)?;
+        }
+        let connection = Arc::new(MuxConnection::new(connection, self.config.max_frame_size));
+        let mut connections = self.connections.lock().await;
+        // Another request may have connected in the meantime.
+        match connections.get(&key) {
+            Some(existing) if !existing.is_closed() => Ok(Arc::clone(existing)),
+            _ => {
+                connections.insert(key, Arc::clone(&connection));
+                Ok(connection)
+            }
+        }
+    }
+
+    /// Listens on the port, serving all the registered protocols.
+    async fn listen(&self, port: u16) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error> {
+        let mut listeners = Vec::new();
+        for address in &self.config.listen_addresses {
+            listeners.push(TcpListener::bind((*address, port)).await?);
+        }
+        let semaphore = Arc::new(Semaphore::new(self.config.max_concurrent_requests.max(1)));
+        let accept_loops = listeners.into_iter().map(|listener| {
+            accept_loop(
+                listener,
+                Arc::clone(&self.handlers),
+                self.config.clone(),
+                Arc::clone(&semaphore),
+                Acceptor {
+                    pre_shared_key: self.pre_shared_key.clone(),
+                    identity: self.identity.clone(),
+                    auth_events: self.auth_events.clone(),
+                },
+                self.filter.clone(),
+            )
+        });
+        let accept_loops = futures::future::try_join_all(accept_loops);
+        Ok(tokio::spawn(async move { accept_loops.await.map(|_| ()) }))
+    }
+}
+
+impl Drop for MuxRpc {
+    fn drop(&mut self) {
+        if let Some((_, listener)) = self.listener.get_mut().take() {
+            listener.abort();
+        }
+    }
+}
+
+/// A [`MuxRpc`] scoped to a network, made with [`MuxRpc::scoped()`].
+///
+/// The networks sharing a `MuxRpc` share its pre-shared key and its filter,
+/// while each can refuse more peers with its own filter.
+#[derive(Clone)]
+pub struct ScopedMuxRpc {
+    rpc: Arc<MuxRpc>,
+    network_id: String,
+    filter: PeerFilter,
+}
+
+impl ScopedMuxRpc {
+    /// Refuses to request to the peers, or to answer the requests from the addresses,
+    /// that the filter (usually `NetworkConfig::peer_filter()`) refuses.
+    pub fn with_peer_filter(mut self, filter: PeerFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn network_id(&self) -> &str {
+        &self.network_id
+    }
+}
+
+/// The client side of a multiplexed connection.
+struct MuxConnection {
+    writer: tokio::sync::Mutex<FrameWriter>,
+    pending: Pending,
+    next_id: AtomicU64,
+    reader: tokio::task::JoinHandle<()>,
+}
+
+impl MuxConnection {
+    fn new(connection: Connection, max_frame_size: u32) -> Self {
+        let (reader, writer) = connection.into_split();
+        let pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        Self {
+            writer: tokio::sync::Mutex::new(writer),
+            pending: Arc::clone(&pending),
+            next_id: AtomicU64::new(0),
+            reader: tokio::spawn(read_responses(reader, pending, max_frame_size).in_current_span()),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.pending.lock().is_none()
+    }
+
+    async fn request(&self, protocol: &str, payload: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (send, recv) = oneshot::channel();
+        self.pending
+            .lock()
+            .as_mut()
+            .ok_or_else(|| eyre!("the connection is closed"))?
+            .insert(id, send);
+        let frame = serde_spb::to_vec(&MuxFrame::Request {
+            id,
+            protocol: protocol.to_owned(),
+            payload,
+        })?;
+        if let Err(e) = self.writer.lock().await.write_frame(&frame).await {
+            self.pending.lock().take();
+            return Err(e);
+        }
+        recv.await
+            .map_err(|_| eyre!("the connection closed before the response"))
+    }
+}
+
+impl Drop for MuxConnection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Dispatches the responses to the waiting requests until the connection is closed.
+async fn read_responses(mut reader: FrameReader, pending: Pending, max_frame_size: u32) {
+    loop {
+        let frame = match reader.read_frame(max_frame_size).await {
+            Ok(frame) => frame,
+            Err(e) => {
+                tracing::debug!("multiplexed connection closed: {e}");
+                break;
+            }
+        };
+        match serde_spb::from_slice::<MuxFrame>(&frame) {
+            Ok(MuxFrame::Response { id, payload }) => {
+                let sender = pending.lock().as_mut().and_then(|x| x.remove(&id));
+                // The request may have timed out already.
+                if let Some(sender) = sender {
+                    let _ = sender.send(payload);
+                }
+            }
+            Ok(MuxFrame::Request { .. }) => {
+                log::warn!("unexpected request on a client connection");
+                break;
+            }
+            Err(e) => {
+                log::warn!("malformed multiplexed frame: {e}");
+                break;
+            }
+        }
+    }
+    pending.lock().take();
+}
+
+/// Unregisters the protocol when dropped.
+struct Registration {
+    handlers: Handlers,
+    protocol: String,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.handlers.write().remove(&self.protocol);
+    }
+}
+
+/// Erases the types of the handler, answering with its encoded response within the timeout.
+fn erase<Q, R, F, Fut>(handler: F, timeout: Duration) -> Handler
+where
+    Q: DeserializeOwned + Send + 'static,
+    R: Serialize + Send + 'static,
+    F: Fn(Q) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<R, String>> + Send + 'static,
+{
+    let handler = Arc::new(handler);
+    Arc::new(move |request: Vec<u8>| {
+        let handler = Arc::clone(&handler);
+        async move {
+            let response = match serde_spb::from_slice::<Q>(&request) {
+                Ok(request) => match tokio::time::timeout(timeout, handler(request)).await {
+                    Ok(response) => response,
+                    Err(_) => Err("the request timed out".to_owned()),
+                },
+                Err(e) => Err(format!("malformed request: {e}")),
+            };
+            serde_spb::to_vec(&response).unwrap_or_else(|e| {
+                serde_spb::to_vec(&Result::<R, String>::Err(format!(
+                    "failed to encode the response: {e}"
+                )))
+                .expect("an error always encodes")
+            })
+        }
+        .boxed()
+    })
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    handlers: Handlers,
+    config: RpcConfig,
+    semaphore: Arc<Semaphore>,
+    acceptor: Acceptor,
+    filter: PeerFilter,
+) -> Result<(), Error> {
+    // Aborted along with the accept loop.
+    let mut connections = ConnectionTasks(Vec::new());
+    loop {
+        let (stream, address) = listener.accept().await?;
+        connections.0.retain(|x| !x.is_finished());
+        if let Err(e) = filter.check_address(&address.ip()) {
+            acceptor
+                .auth_events
+                .report(Some(address), AuthFailureReason::Denied(e.to_string()));
+            continue;
+        }
+        let handlers = Arc::clone(&handlers);
+        let config = config.clone();
+        let semaphore = Arc::clone(&semaphore);
+        let acceptor = acceptor.clone();
+        let span = tracing::debug_span!("mux_connection", %address);
+        connections.0.push(tokio::spawn(
+            async move {
+                tracing::debug!("accepted");
+                let result = match acceptor.open(stream, address, &config.compression).await {
+                    Ok(connection) => {
+                        serve_connection(connection, address, handlers, config, semaphore).await
+                    }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    log::warn!("failed to serve the multiplexed connection from {address}: {e}");
+                }
+            }
+            .instrument(span),
+        ));
+    }
+}
+
+/// The tasks serving the accepted connections, which are aborted when dropped.
+struct ConnectionTasks(Vec<tokio::task::JoinHandle<()>>);
+
+impl Drop for ConnectionTasks {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
+
+/// Answers the requests on the connection concurrently, until it's closed.
+async fn serve_connection(
+    connection: Connection,
+    address: SocketAddr,
+    handlers: Handlers,
+    config: RpcConfig,
+    semaphore: Arc<Semaphore>,
+) -> Result<(), Error> {
+    let (mut reader, writer) = connection.into_split();
+    let writer = Arc::new(tokio::sync::Mutex::new(writer));
+    loop {
+        let frame = match reader.read_frame(config.max_frame_size).await {
+            Ok(frame) => frame,
+            Err(e) => {
+                tracing::debug!("closed: {e}");
+                return Ok(());
+            }
+        };
+        let (id, protocol, payload) = match serde_spb::from_slice::<MuxFrame>(&frame)? {
+            MuxFrame::Request {
+                id,
+                protocol,
+                payload,
+            } => (id, protocol, payload),
+            MuxFrame::Response { .. } => return Err(eyre!("unexpected response")),
+        };
+        let handler = handlers.read().get(&protocol).cloned();
+        let permit = Arc::clone(&semaphore).acquire_owned().await?;
+        let writer = Arc::clone(&writer);
+        tokio::spawn(
+            async move {
+                let payload = match handler {
+                    Some(handler) => with_remote_address(address, handler(payload)).await,
+                    // The error of any `Result<R, String>` is encoded the same.
+                    None => serde_spb::to_vec(&Result::<(), String>::Err(format!(
+                        "{protocol} is not served"
+                    )))
+                    .expect("an error always encodes"),
+                };
+                let frame = serde_spb::to_vec(&MuxFrame::Response { id, payload })
+                    .expect("a frame always encodes");
+                if let Err(e) = writer.lock().await.write_frame(&frame).await {
+                    tracing::debug!("failed to respond: {e}");
+                }
+                drop(permit);
+            }
+            .in_current_span(),
+        );
+    }
+}
+
+#[async_trait]
+impl RpcPrimitive for MuxRpc {
+    async fn request<Q, R>(&self, peer: &Peer, protocol: &str, request: Q) -> Result<R, Error>
+    where
+        Q: Serialize + Send + 'static,
+        R: DeserializeOwned + Send + 'static,
+    {
+        self.filter.check_peer(peer)?;
+        let request = serde_spb::to_vec(&request)?;
+        let _permit = self.semaphore.acquire().await?;
+        let span = tracing::debug_span!("mux_request", peer = %peer.public_key, protocol);
+        let exchange = async move {
+            let connection = self.connection(peer).await?;
+            let response = connection.request(protocol, request).await?;
+            Result::<_, Error>::Ok(serde_spb::from_slice::<Result<R, String>>(&response)?)
+        };
+        tokio::time::timeout(
+            Duration::from_millis(self.config.timeout_ms),
+            exchange.instrument(span),
+        )
+        .await
+        .map_err(|_| eyre!("request to {} timed out", peer.public_key))??
+        .map_err(|e| eyre!("{protocol} request to {} failed: {e}", peer.public_key))
+    }
+
+    async fn serve<Q, R, F, Fut>(
+        &self,
+        port: u16,
+        _handler: F,
+    ) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error>
+    where
+        Q: DeserializeOwned + Send + 'static,
+        R: Serialize + Send + 'static,
+        F: Fn(Q) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, String>> + Send + 'static,
+    {
+        Err(eyre!(
+            "an unnamed protocol can't be multiplexed on port {port}; use `serve_protocol()`"
+        ))
+    }
+
+    /// Registers the protocol on the multiplexed port, listening on it if not yet.
+    ///
+    /// Aborting the returned task unregisters the protocol.
+    async fn serve_protocol<Q, R, F, Fut>(
+        &self,
+        protocol: &str,
+        port: u16,
+        handler: F,
+    ) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error>
+    where
+        Q: DeserializeOwned + Send + 'static,
+        R: Serialize + Send + 'static,
+        F: Fn(Q) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, String>> + Send + 'static,
+    {
+        {
+            let mut listener = self.listener.lock().await;
+            match &*listener {
+                Some((listening, _)) if *listening != port => {
+                    return Err(eyre!(
+                        "the protocols are multiplexed on port {listening}, not {port}"
+                    ))
+                }
+                Some(_) => {}
+                None => *listener = Some((port, self.listen(port).await?)),
+            }
+        }
+        let handler = erase(handler, Duration::from_millis(self.config.timeout_ms));
+        {
+            let mut handlers = self.handlers.write();
+            if handlers.contains_key(protocol) {
+                return Err(eyre!("{protocol} is served already"));
+            }
+            handlers.insert(protocol.to_owned(), handler);
+        }
+        let registration = Registration {
+            handlers: Arc::clone(&self.handlers),
+            protocol: protocol.to_owned(),
+        };
+        Ok(tokio::spawn(async move {
+            let _registration = registration;
+            futures::future::pending::<()>().await;
+            Ok(())
+        }))
+    }
+}
+
+#[async_trait]
+impl RpcPrimitive for ScopedMuxRpc {
+    async fn request<Q, R>(&self, peer: &Peer, protocol: &str, request: Q) -> Result<R, Error>
+    where
+        Q: Serialize + Send + 'static,
+        R: DeserializeOwned + Send + 'static,
+    {
+        self.filter.check_peer(peer)?;
+        let protocol = scoped_protocol(&self.network_id, protocol);
+        self.rpc.request(peer, &protocol, request).await
+    }
+
+    async fn serve<Q, R, F, Fut>(
+        &self,
+        port: u16,
+        handler: F,
+    ) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error>
+    where
+        Q: DeserializeOwned + Send + 'static,
+        R: Serialize + Send + 'static,
+        F: Fn(Q) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, String>> + Send + 'static,
+    {
+        self.rpc.serve(port, handler).await
+    }
+
+    /// Registers the protocol of the network on the shared multiplexed port.
+    ///
+    /// Aborting the returned task unregisters the protocol.
+    async fn serve_protocol<Q, R, F, Fut>(
+        &self,
+        protocol: &str,
+        port: u16,
+        handler: F,
+    ) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error>
+    where
+        Q: DeserializeOwned + Send + 'static,
+        R: Serialize + Send + 'static,
+        F: Fn(Q) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, String>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let filter = self.filter.clone();
+        let auth_events = self.rpc.auth_events.clone();
+        let protocol = scoped_protocol(&self.network_id, protocol);
+        self.rpc
+            .serve_protocol(&protocol, port, move |request: Q| {
+                let handler = Arc::clone(&handler);
+                let filter = filter.clone();
+                let auth_events = auth_events.clone();
+                async move {
+                    if let Some(address) = crate::rpc::remote_address() {
+                        if let Err(e) = filter.check_address(&address.ip()) {
+                            let reason = AuthFailureReason::Denied(e.to_string());
+                            auth_events.report(Some(address), reason);
+                            return Err(e.to_string());
+                        }
+                    }
+                    handler(request).await
+                }
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    fn peer(port: u16) -> Peer {
+        Peer {
+            public_key: PublicKey::zero(),
+            name: "server".to_owned(),
+            address: "127.0.0.1:1".parse().unwrap(),
+            addresses: Vec::new(),
+            ports: vec![(MUX_PROTOCOL.to_owned(), port)].into_iter().collect(),
+            metadata: Default::default(),
+            recently_seen_timestamp: 0,
+        }
+    }
+
+    fn rpc(key: Option<&str>) -> MuxRpc {
+        MuxRpc::new(RpcConfig {
+            timeout_ms: 1000,
+            ..Default::default()
+        })
+        .with_pre_shared_key(key.map(PreSharedKey::from_passphrase))
+    }
+
+    #[tokio::test]
+    async fn sub_protocols() {
+        setup_test();
+        let port = dispense_port();
+        let server = rpc(None);
+        let _sum = server
+            .serve_protocol("sum", port, |numbers: Vec<u64>| async move {
+                Ok(numbers.iter().sum::<u64>())
+            })
+            .await
+            .unwrap();
+        let echo = server
+            .serve_protocol("echo", port, |x: String| async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(x)
+            })
+            .await
+            .unwrap();
+        assert!(server
+            .serve_protocol("echo", port, |x: String| async move { Ok(x) })
+            .await
+            .is_err());
+        assert!(server
+            .serve_protocol("other", dispense_port(), |x: u64| async move { Ok(x) })
+            .await
+            .is_err());
+        assert!(server
+            .serve(port, |x: u64| async move { Ok(x) })
+            .await
+            .is_err());
+
+        // The requests of both protocols are made over a single connection, concurrently.
+        let client = rpc(None);
+        let peer = peer(port);
+        let echoes = (0..10).map(|i| client.request::<_, String>(&peer, "echo", i.to_string()));
+        let sums = (0..10u64).map(|i| client.request::<_, u64>(&peer, "sum", vec![i, 1]));
+        let (echoes, sums) = futures::future::join(
+            futures::future::join_all(echoes),
+            futures::future::join_all(sums),
+        )
+        .await;
+        for (i, (echo, sum)) in echoes.into_iter().zip(sums).enumerate() {
+            assert_eq!(echo.unwrap(), i.to_string());
+            assert_eq!(sum.unwrap(), i as u64 + 1);
+        }
+        assert_eq!(client.connected_peers().await, vec![PublicKey::zero()]);
+
+        let error = client
+            .request::<_, u64>(&peer, "unknown", 1u64)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("not served"));
+        echo.abort();
+        let _ = echo.await;
+        assert!(client
+            .request::<_, String>(&peer, "echo", "x".to_owned())
+            .await
+            .is_err());
+        let sum: u64 = client.request(&peer, "sum", vec![2u64, 3]).await.unwrap();
+        assert_eq!(sum, 5);
+    }
+
+    #[tokio::test]
+    async fn pre_shared_key() {
+        setup_test();
+        let port = dispense_port();
+        let server = rpc(Some("private"));
+        let _task = server
+            .serve_protocol("sum", port, |x: u64| async move { Ok(x + 1) })
+            .await
+            .unwrap();
+        let peer = peer(port);
+
+        let result: u64 = rpc(Some("private"))
+            .request(&peer, "sum", 1u64)
+            .await
+            .unwrap();
+        assert_eq!(result, 2);
+        assert!(rpc(Some("other"))
+            .request::<_, u64>(&peer, "sum", 1u64)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn reconnect() {
+        setup_test();
+        let port = dispense_port();
+        let serve = || async move {
+            let server = rpc(None);
+            let task = server
+                .serve_protocol("sum", port, |x: u64| async move { Ok(x + 1) })
+                .await
+                .unwrap();
+            (server, task)
+        };
+        let server = serve().await;
+        let client = rpc(None);
+        let peer = peer(port);
+        assert_eq!(
+            client.request::<_, u64>(&peer, "sum", 1u64).await.unwrap(),
+            2
+        );
+
+        drop(server);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(client.connected_peers().await.is_empty());
+        assert!(client.request::<_, u64>(&peer, "sum", 1u64).await.is_err());
+
+        let _server = serve().await;
+        assert_eq!(
+            client.request::<_, u64>(&peer, "sum", 2u64).await.unwrap(),
+            3
+        );
+        assert_eq!(client.connected_peers().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn networks() {
+        setup_test();
+        let port = dispense_port();
+        let server = Arc::new(rpc(None));
+        let denied = PeerFilter {
+            denied: vec![crate::access::PeerPattern::Cidr(
+                "127.0.0.0/8".parse().unwrap(),
+            )],
+            allowed: Vec::new(),
+        };
+        let mut tasks = Vec::new();
+        for (network_id, filter) in [("main", PeerFilter::default()), ("test", denied)] {
+            let scoped = server.scoped(network_id).with_peer_filter(filter);
+            let task = scoped
+                .serve_protocol("echo", port, move |x: String| async move {
+                    Ok(format!("{network_id}: {x}"))
+                })
+                .await
+                .unwrap();
+            tasks.push(task);
+        }
+        assert!(server
+            .scoped("main")
+            .serve_protocol("echo", port, |x: String| async move { Ok(x) })
+            .await
+            .is_err());
+
+        // Both are requested over a single connection, each reaching only its own handler.
+        let client = Arc::new(rpc(None));
+        let peer = peer(port);
+        let response: String = client
+            .scoped("main")
+            .request(&peer, "echo", "x".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(response, "main: x");
+        let error = client
+            .scoped("test")
+            .request::<_, String>(&peer, "echo", "x".to_owned())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("denied"));
+        for network_id in ["other", ""] {
+            let error = client
+                .scoped(network_id)
+                .request::<_, String>(&peer, "echo", "x".to_owned())
+                .await
+                .unwrap_err();
+            assert!(error.to_string().contains("not served"));
+        }
+        assert!(client
+            .request::<_, String>(&peer, "echo", "x".to_owned())
+            .await
+            .is_err());
+        assert_eq!(client.connected_peers().await.len(), 1);
+    }
+}
@@ -1,10 +1,11 @@
+use crate::DiscoveryConfig;
 use libp2p::{
     identify,
     identity::PublicKey,
     kad::{store::MemoryStore, Kademlia, KademliaConfig, KademliaEvent},
     swarm::NetworkBehaviour,
 };
-use std::{borrow::Cow, time::Duration};
+use std::{borrow::Cow, num::NonZeroUsize, time::Duration};
 
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "DiscoveryEvent")]
@@ -20,7 +21,7 @@ pub struct DiscoveryBehaviour {
 }
 
 impl DiscoveryBehaviour {
-    pub(crate) fn new(pubkey: PublicKey, message: String) -> Self {
+    pub(crate) fn new(pubkey: PublicKey, message: String, discovery: &DiscoveryConfig) -> Self {
         let peer_id = pubkey.to_peer_id();
 
         let identify_config = identify::Config::new("/simperby/discovery".to_string(), pubkey)
@@ -29,7 +30,13 @@ impl DiscoveryBehaviour {
 
         let mut kademlia_config = KademliaConfig::default();
         kademlia_config
-            .set_protocol_names(vec![Cow::from("/simperby/discovery/kademlia".as_bytes())]);
+            .set_protocol_names(vec![Cow::from("/simperby/discovery/kademlia".as_bytes())])
+            // The peers queried at once in a lookup.
+            .set_parallelism(NonZeroUsize::new(discovery.fanout.max(1)).expect("nonzero"))
+            // The closest peers returned by a peer for a lookup.
+            .set_replication_factor(
+                NonZeroUsize::new(discovery.max_response_peers.max(1)).expect("nonzero"),
+            );
 
         let store = MemoryStore::new(peer_id);
 
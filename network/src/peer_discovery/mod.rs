@@ -22,7 +22,7 @@ impl PeerDiscovery for PeerDiscoveryImpl {
     async fn serve(
         _storage_directory: &str,
         _network_config: &NetworkConfig,
-    ) -> Result<(SharedKnownPeers, tokio::task::JoinHandle<Result<(), Error>>), Error> {
+    ) -> Result<(SharedKnownPeers, ServiceHandle), Error> {
         unimplemented!();
     }
 
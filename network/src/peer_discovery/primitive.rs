@@ -2,7 +2,7 @@ use super::behaviour::{DiscoveryBehaviour, DiscoveryEvent};
 use super::utils::{
     convert_keypair, convert_multiaddr_into_sockv4, convert_public_key, get_peer_id,
 };
-use crate::{primitives::PeerDiscoveryPrimitive, *};
+use crate::{peer_record::*, peer_store::*, primitives::PeerDiscoveryPrimitive, *};
 use async_trait::async_trait;
 use chrono::Utc;
 use eyre::eyre;
@@ -17,27 +17,37 @@ use libp2p::{
     yamux::YamuxConfig,
     Multiaddr, PeerId, Swarm, Transport,
 };
-use tokio::task::JoinHandle;
 
 pub(crate) struct PeerDiscoveryPrimitiveImpl;
 
 #[async_trait]
 impl PeerDiscoveryPrimitive for PeerDiscoveryPrimitiveImpl {
-    async fn serve(
+    async fn serve<S: PeerStore>(
         network_config: NetworkConfig,
-        message: String,
+        metadata: PeerMetadata,
         port_map: HashMap<String, u16>,
         initially_known_peers: Vec<Peer>,
-    ) -> Result<(SharedKnownPeers, JoinHandle<Result<(), Error>>), Error> {
-        let mut swarm = Self::create_swarm(&network_config, message, port_map).await?;
+        peer_store: S,
+        peer_expiry: PeerExpiry,
+        discovery: DiscoveryConfig,
+    ) -> Result<(SharedKnownPeers, ServiceHandle), Error> {
+        let initially_known_peers = merge_peers(initially_known_peers, peer_store.load().await?);
+        let mut swarm = Self::create_swarm(&network_config, metadata, port_map, &discovery).await?;
         swarm
             .listen_on(format!("/ip4/0.0.0.0/tcp/{}", network_config.port.unwrap_or(0)).parse()?)?;
-        let shared_known_peers = SharedKnownPeers {
-            lock: Arc::new(RwLock::new(initially_known_peers)),
-        };
+        let shared_known_peers = SharedKnownPeers::new_static(initially_known_peers);
         Ok((
             shared_known_peers.to_owned(),
-            tokio::spawn(Self::discovery_task(swarm, shared_known_peers)),
+            ServiceHandle::spawn(|shutdown| {
+                Self::discovery_task(
+                    swarm,
+                    shared_known_peers,
+                    peer_store,
+                    peer_expiry,
+                    discovery,
+                    shutdown,
+                )
+            }),
         ))
     }
 }
@@ -45,13 +55,21 @@ impl PeerDiscoveryPrimitive for PeerDiscoveryPrimitiveImpl {
 impl PeerDiscoveryPrimitiveImpl {
     async fn create_swarm(
         network_config: &NetworkConfig,
-        message: String,
+        metadata: PeerMetadata,
         port_map: HashMap<String, u16>,
+        discovery: &DiscoveryConfig,
     ) -> Result<Swarm<DiscoveryBehaviour>, Error> {
         let libp2p_keypair =
-            convert_keypair(&network_config.public_key, &network_config.private_key)?;
+            convert_keypair(&network_config.public_key, network_config.private_key()?)?;
         let transport = Self::create_transport(&libp2p_keypair).await?;
-        let behaviour = Self::create_behaviour(&libp2p_keypair, message, port_map).await?;
+        let behaviour = Self::create_behaviour(
+            network_config,
+            &libp2p_keypair,
+            metadata,
+            port_map,
+            discovery,
+        )
+        .await?;
         let swarm = SwarmBuilder::with_executor(
             transport,
             behaviour,
@@ -64,6 +82,9 @@ impl PeerDiscoveryPrimitiveImpl {
         Ok(swarm)
     }
 
+    // Todo: Add a QUIC transport alongside TCP (`libp2p-quic`, with `OrTransport`),
+    //       selectable by a `NetworkConfig::transport` option,
+    //       once the `libp2p` dependency is enabled again.
     async fn create_transport(
         libp2p_keypair: &identity::Keypair,
     ) -> Result<transport::Boxed<(PeerId, StreamMuxerBox)>, Error> {
@@ -75,24 +96,58 @@ impl PeerDiscoveryPrimitiveImpl {
         Ok(transport)
     }
 
+    /// Creates the behaviour that advertises the signed record of this node.
+    ///
+    /// The IP address in the record is left unspecified,
+    /// since the node doesn't know its own public address; the receiver takes it from the connection.
     async fn create_behaviour(
+        network_config: &NetworkConfig,
         libp2p_keypair: &identity::Keypair,
-        message: String,
+        metadata: PeerMetadata,
         port_map: HashMap<String, u16>,
+        discovery: &DiscoveryConfig,
     ) -> Result<DiscoveryBehaviour, Error> {
-        let message = serde_spb::to_string(&(message, port_map))?;
-        Ok(DiscoveryBehaviour::new(libp2p_keypair.public(), message))
+        let record = SignedPeerRecord::new(
+            &NetworkConfig {
+                ports: port_map,
+                ..network_config.clone()
+            },
+            String::new(),
+            SocketAddrV4::new(
+                std::net::Ipv4Addr::UNSPECIFIED,
+                network_config.port.unwrap_or(0),
+            ),
+            Vec::new(),
+            metadata,
+            Utc::now().timestamp_millis() as Timestamp,
+        )?;
+        let message = serde_spb::to_string(&record)?;
+        Ok(DiscoveryBehaviour::new(
+            libp2p_keypair.public(),
+            message,
+            discovery,
+        ))
     }
 
     #[allow(clippy::single_match)]
     /// The background task that serves peer discovery protocol.
-    async fn discovery_task(
+    ///
+    /// On shutdown, it saves the known peers and closes the connections to the peers.
+    async fn discovery_task<S: PeerStore>(
         mut swarm: Swarm<DiscoveryBehaviour>,
         shared_known_peers: SharedKnownPeers,
+        peer_store: S,
+        peer_expiry: PeerExpiry,
+        discovery: DiscoveryConfig,
+        mut shutdown: ShutdownSignal,
     ) -> Result<(), Error> {
         Self::add_known_peers_to_routing_table(&mut swarm, &shared_known_peers).await?;
-        // Todo: Make the interval configurable.
-        let mut discovery_timer = tokio::time::interval(tokio::time::Duration::from_secs(10));
+        let mut discovery_timer = tokio::time::interval(tokio::time::Duration::from_millis(
+            discovery.round_interval_ms,
+        ));
+        let mut eviction_timer = tokio::time::interval(tokio::time::Duration::from_millis(
+            peer_expiry.eviction_interval_ms,
+        ));
         loop {
             tokio::select! {
                 event = swarm.select_next_some() => match event {
@@ -100,8 +155,26 @@ impl PeerDiscoveryPrimitiveImpl {
                     // All incoming events should be comsumed even though we don't handle them.
                     _any_other_event => (),
                 },
-                _ = discovery_timer.tick() =>
-                    Self::regular_discovery(&mut swarm).await,
+                _ = discovery_timer.tick() => {
+                    Self::regular_discovery(&mut swarm).await;
+                    if let Err(e) = peer_store.save(&shared_known_peers.read().await).await {
+                        log::warn!("failed to save the known peers: {e}");
+                    }
+                }
+                _ = eviction_timer.tick() => {
+                    let now = Utc::now().timestamp_millis() as Timestamp;
+                    shared_known_peers.evict_expired(&peer_expiry, now).await;
+                }
+                _ = shutdown.requested() => {
+                    if let Err(e) = peer_store.save(&shared_known_peers.read().await).await {
+                        log::warn!("failed to save the known peers: {e}");
+                    }
+                    let connected = swarm.connected_peers().cloned().collect::<Vec<_>>();
+                    for peer_id in connected {
+                        let _ = swarm.disconnect_peer_id(peer_id);
+                    }
+                    return Ok(());
+                }
             }
         }
     }
@@ -131,8 +204,7 @@ impl PeerDiscoveryPrimitiveImpl {
         swarm: &mut Swarm<DiscoveryBehaviour>,
         shared_known_peers: &SharedKnownPeers,
     ) -> Result<(), Error> {
-        let known_peers = shared_known_peers.lock.read().await;
-        for peer in known_peers.iter() {
+        for peer in shared_known_peers.read().await.iter() {
             let address: Multiaddr =
                 format!("/ip4/{}/tcp/{}", peer.address.ip(), peer.address.port()).parse()?;
             swarm
@@ -156,14 +228,14 @@ impl PeerDiscoveryPrimitiveImpl {
             .filter_map(|multiaddr| convert_multiaddr_into_sockv4(multiaddr.to_owned()).ok())
             .find(|address| global_v4(address.ip()) || address.ip().is_loopback())
             .ok_or_else(|| eyre!("no public ip address found"))?;
-        let (message, ports) = serde_spb::from_str(&info.agent_version)?;
-        let peer = Peer {
-            public_key,
-            address: public_ip_addr,
-            ports,
-            message,
-            recently_seen_timestamp: Utc::now().timestamp_millis() as Timestamp,
-        };
+        let record: SignedPeerRecord = serde_spb::from_str(&info.agent_version)?;
+        if record.record.public_key != public_key {
+            return Err(eyre!("peer record of another node"));
+        }
+        // Reject forged advertisements before they reach the known peers.
+        let mut peer = record.into_peer()?;
+        peer.address.set_ip(*public_ip_addr.ip());
+        peer.recently_seen_timestamp = Utc::now().timestamp_millis() as Timestamp;
         shared_known_peers.add_or_replace(peer).await;
         Ok(())
     }
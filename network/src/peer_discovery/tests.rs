@@ -1,19 +1,20 @@
 use super::primitive::PeerDiscoveryPrimitiveImpl;
-use crate::{primitives::PeerDiscoveryPrimitive, *};
-use simperby_common::crypto::*;
+use crate::{peer_store::NoPeerStore, primitives::PeerDiscoveryPrimitive, *};
+use simperby_common::{crypto::*, hd};
 
 use chrono::Utc;
 use rand::{thread_rng, Rng};
 use std::{collections::HashMap, ops::Range};
 use tokio::{
     sync::{Mutex, OnceCell},
-    task::JoinHandle,
     time::{self, Duration},
 };
 
 const MAX_NODES: u64 = 300;
 const AVAILABLE_PORT_RANGE: Range<u16> = 55000..56000;
 const MAX_INITIALLY_KNOWN_PEERS: u64 = 2;
+/// The mnemonic from which the keys of the nodes are derived.
+const TEST_MNEMONIC: &str = "simperby peer discovery test";
 /// A prime number used in RNG.
 const LCG_MULTIPLIER: u64 = 16536801242360453141;
 /// An allowed amount of difference between real timestamp and discovered timestamp, in milliseconds.
@@ -34,13 +35,6 @@ impl DeterministicRng {
     fn get_u64(&self) -> u64 {
         LCG_MULTIPLIER.wrapping_mul(self.seed)
     }
-
-    fn get_bytes(&self, length: u64) -> Vec<u8> {
-        let num_blocks = length.rem_euclid(8) + 1;
-        (0..num_blocks)
-            .flat_map(|_| self.get_u64().to_be_bytes())
-            .collect()
-    }
 }
 
 struct KeyStore {
@@ -48,12 +42,17 @@ struct KeyStore {
 }
 
 impl KeyStore {
+    /// Derives the keys of the nodes, `m/0` to `m/{MAX_NODES - 1}`.
     fn new() -> Self {
-        let store = Vec::from_iter(
-            (0..MAX_NODES)
-                .map(|index| DeterministicRng::new(index).get_bytes(8))
-                .map(generate_keypair),
-        );
+        let master_key = hd::ExtendedPrivateKey::from_seed(hd::mnemonic_to_seed(TEST_MNEMONIC, ""))
+            .expect("invalid master key");
+        let store = Vec::from_iter((0..MAX_NODES as u32).map(|index| {
+            let private_key = master_key
+                .derive_child(index)
+                .expect("invalid child key")
+                .private_key;
+            (private_key.public_key(), private_key)
+        }));
         Self { store }
     }
 
@@ -84,13 +83,24 @@ async fn wait_ms(millis: u64) {
 /// A peer discovery node.
 struct TestNetNode {
     shared_known_peers: SharedKnownPeers,
-    handle: JoinHandle<Result<(), Error>>,
+    /// `None` once it's shut down.
+    handle: Option<ServiceHandle>,
     network_config: NetworkConfig,
 }
 
+impl TestNetNode {
+    async fn shutdown(mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.shutdown().await.unwrap();
+        }
+    }
+}
+
 impl Drop for TestNetNode {
     fn drop(&mut self) {
-        self.handle.abort();
+        if let Some(handle) = &self.handle {
+            handle.abort();
+        }
     }
 }
 
@@ -106,7 +116,11 @@ struct TestNet {
 impl TestNet {
     fn new() -> Self {
         let keystore = KeyStore::new();
-        let (dummy_pubkey, dummy_privkey) = generate_keypair(DeterministicRng::new(0).get_bytes(1));
+        let (dummy_pubkey, dummy_privkey) = hd::derive_keypair(
+            hd::mnemonic_to_seed(TEST_MNEMONIC, ""),
+            &hd::DerivationPath::default().child(hd::HARDENED),
+        )
+        .expect("invalid dummy key");
         let dummy_port = Some(1);
         let default_network_config = NetworkConfig {
             network_id: format!("test-{}", thread_rng().gen::<u32>()),
@@ -118,7 +132,7 @@ impl TestNet {
                 .map(|(pubkey, _)| pubkey)
                 .collect(),
             public_key: dummy_pubkey,
-            private_key: dummy_privkey,
+            private_key: Some(dummy_privkey),
         };
         Self {
             keystore,
@@ -133,11 +147,11 @@ impl TestNet {
         }
     }
 
-    fn remove_members(&mut self, mut indices: Vec<usize>) {
+    async fn remove_members(&mut self, mut indices: Vec<usize>) {
         indices.sort();
         indices.reverse();
         for index in indices {
-            self.nodes.remove(index);
+            self.nodes.remove(index).shutdown().await;
         }
     }
 
@@ -162,21 +176,24 @@ impl TestNet {
         let network_config = NetworkConfig {
             port: Some(port),
             public_key,
-            private_key,
+            private_key: Some(private_key),
             ..self.default_network_config.to_owned()
         };
         let initially_known_peers = self.get_initially_known_peers();
         let (shared_known_peers, handle) = PeerDiscoveryPrimitiveImpl::serve(
             network_config.clone(),
-            "".to_owned(),
+            Default::default(),
             Default::default(),
             initially_known_peers,
+            NoPeerStore,
+            PeerExpiry::default(),
+            DiscoveryConfig::default(),
         )
         .await
         .unwrap();
         self.nodes.push(TestNetNode {
             shared_known_peers,
-            handle,
+            handle: Some(handle),
             network_config,
         });
     }
@@ -201,7 +218,7 @@ impl TestNet {
             .map(|(pubkey, port)| Peer {
                 public_key: pubkey,
                 address: format!("127.0.0.1:{}", port).parse().unwrap(),
-                message: String::new(),
+                metadata: Default::default(),
                 ports: HashMap::new(),
                 recently_seen_timestamp: 0,
             })
@@ -70,7 +70,7 @@ mod tests {
             public_key,
             address: "0.0.0.0:0".parse().unwrap(),
             ports: HashMap::new(),
-            message: String::new(),
+            metadata: Default::default(),
             recently_seen_timestamp: 0,
         };
         assert_eq!(
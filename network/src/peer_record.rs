@@ -0,0 +1,176 @@
+//! Self-advertised peer information, signed by the advertising node.
+//!
+//! A peer record can be relayed by anyone, but only the owner of the key can create one,
+//! so a node cannot advertise a forged address or ports on behalf of another node.
+use super::*;
+use eyre::eyre;
+
+/// The information that a node advertises about itself.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct PeerRecord {
+    /// The network that the record is for, so that it can't be replayed in another network
+    /// that the node participates in.
+    pub network_id: String,
+    pub public_key: PublicKey,
+    pub name: MemberName,
+    pub address: SocketAddrV4,
+    pub addresses: Vec<PeerAddress>,
+    pub ports: HashMap<String, u16>,
+    pub metadata: PeerMetadata,
+    /// When the record was created, to prefer the newer records of the same node.
+    pub timestamp: Timestamp,
+}
+
+impl ToHash256 for PeerRecord {
+    fn to_hash256(&self) -> Hash256 {
+        Hash256::hash(serde_spb::to_vec(self).unwrap())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct SignedPeerRecord {
+    pub record: PeerRecord,
+    pub signature: TypedSignature<PeerRecord>,
+}
+
+impl SignedPeerRecord {
+    /// Creates a record of this node, with the ports in the config.
+    pub fn new(
+        network_config: &NetworkConfig,
+        name: MemberName,
+        address: SocketAddrV4,
+        addresses: Vec<PeerAddress>,
+        metadata: PeerMetadata,
+        timestamp: Timestamp,
+    ) -> Result<Self, Error> {
+        let record = PeerRecord {
+            network_id: network_config.network_id.clone(),
+            public_key: network_config.public_key.clone(),
+            name,
+            address,
+            addresses,
+            ports: network_config.ports.clone(),
+            metadata,
+            timestamp,
+        };
+        let signature = TypedSignature::sign(&record, network_config.private_key()?)?;
+        Ok(Self { record, signature })
+    }
+
+    /// Checks that the record is signed by the node that it describes.
+    pub fn verify(&self) -> Result<(), Error> {
+        if *self.signature.signer() != self.record.public_key {
+            return Err(eyre!(
+                "peer record of {} is signed by {}",
+                self.record.public_key,
+                self.signature.signer()
+            ));
+        }
+        self.signature
+            .verify(&self.record)
+            .map_err(|e| eyre!("invalid signature on the peer record: {e}"))
+    }
+
+    /// Checks that the record is for the network.
+    pub fn check_network(&self, network_id: &str) -> Result<(), Error> {
+        if self.record.network_id != network_id {
+            return Err(eyre!(
+                "peer record of {} is for network {}, not {network_id}",
+                self.record.public_key,
+                self.record.network_id
+            ));
+        }
+        Ok(())
+    }
+
+    /// Verifies the record and converts it into a `Peer`.
+    pub fn into_peer(self) -> Result<Peer, Error> {
+        self.verify()?;
+        Ok(Peer {
+            public_key: self.record.public_key,
+            name: self.record.name,
+            address: self.record.address,
+            addresses: self.record.addresses,
+            ports: self.record.ports,
+            metadata: self.record.metadata,
+            recently_seen_timestamp: self.record.timestamp,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    fn network_config(seed: &str) -> NetworkConfig {
+        let (public_key, private_key) = generate_keypair(seed);
+        NetworkConfig {
+            network_id: "test".to_owned(),
+            ports: vec![("dms".to_owned(), 1234)].into_iter().collect(),
+            members: vec![public_key.clone()],
+            public_key,
+            private_key: Some(private_key),
+            dns_seeds: Vec::new(),
+            enable_mdns: false,
+            pre_shared_key: None,
+            proxy: None,
+            denied_peers: Vec::new(),
+            allowed_peers: Vec::new(),
+        }
+    }
+
+    fn record(network_config: &NetworkConfig, timestamp: Timestamp) -> SignedPeerRecord {
+        SignedPeerRecord::new(
+            network_config,
+            "member".to_owned(),
+            "127.0.0.1:1".parse().unwrap(),
+            Vec::new(),
+            Default::default(),
+            timestamp,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reject_forged_records() {
+        setup_test();
+        let config = network_config("a");
+        let peers = SharedKnownPeers::new_static(Vec::new());
+        let members = [config.public_key.clone()];
+        peers
+            .add_signed(record(&config, 10), &members)
+            .await
+            .unwrap();
+        assert_eq!(peers.read().await[0].recently_seen_timestamp, 10);
+
+        // A modified address.
+        let mut forged = record(&config, 20);
+        forged.record.address = "10.0.0.1:1".parse().unwrap();
+        assert!(peers.add_signed(forged, &members).await.is_err());
+
+        // Signed by another key.
+        let mut forged = record(&network_config("b"), 20);
+        forged.record.public_key = config.public_key.clone();
+        assert!(peers.add_signed(forged, &members).await.is_err());
+
+        // A replay of an older record.
+        peers
+            .add_signed(record(&config, 30), &members)
+            .await
+            .unwrap();
+        assert!(peers
+            .add_signed(record(&config, 10), &members)
+            .await
+            .is_err());
+        assert!(peers
+            .add_signed(record(&config, 30), &members)
+            .await
+            .is_err());
+
+        // A record of a non-member.
+        assert!(peers.add_signed(record(&config, 40), &[]).await.is_err());
+        assert_eq!(peers.read().await.len(), 1);
+        assert_eq!(peers.read().await[0].recently_seen_timestamp, 30);
+    }
+}
@@ -0,0 +1,310 @@
+//! Persistence of the known peers, so that the peer discovery can resume
+//! from the previously discovered peers after a restart instead of re-bootstrapping.
+//!
+//! The known peers can also be exported as a [`PeerSnapshot`], for the operators to bootstrap
+//! another node from.
+use super::*;
+use async_trait::async_trait;
+
+/// A storage of the known peers.
+#[async_trait]
+pub trait PeerStore: Send + Sync + 'static {
+    /// Loads the stored peers. Returns an empty list if nothing has been stored yet.
+    async fn load(&self) -> Result<Vec<Peer>, Error>;
+
+    /// Overwrites the stored peers with the given ones.
+    async fn save(&self, peers: &[Peer]) -> Result<(), Error>;
+}
+
+/// The default `PeerStore` that keeps the peers in a JSON file.
+#[derive(Debug, Clone)]
+pub struct FilePeerStore {
+    path: String,
+}
+
+impl FilePeerStore {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl PeerStore for FilePeerStore {
+    async fn load(&self) -> Result<Vec<Peer>, Error> {
+        if tokio::fs::metadata(&self.path).await.is_err() {
+            return Ok(Vec::new());
+        }
+        Ok(serde_json::from_str(
+            &tokio::fs::read_to_string(&self.path).await?,
+        )?)
+    }
+
+    async fn save(&self, peers: &[Peer]) -> Result<(), Error> {
+        // Write to a temporary file first so that a crash never leaves a truncated store.
+        let temp_path = format!("{}.tmp", self.path);
+        tokio::fs::write(&temp_path, serde_json::to_string_pretty(peers)?).await?;
+        tokio::fs::rename(&temp_path, &self.path).await?;
+        Ok(())
+    }
+}
+
+/// A `PeerStore` that stores nothing, for the nodes that don't need persistence.
+#[derive(Debug, Clone, Default)]
+pub struct NoPeerStore;
+
+#[async_trait]
+impl PeerStore for NoPeerStore {
+    async fn load(&self) -> Result<Vec<Peer>, Error> {
+        Ok(Vec::new())
+    }
+
+    async fn save(&self, _peers: &[Peer]) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// The known peers of a node at a moment, shared between the nodes (e.g., for bootstrapping)
+/// or across the restarts.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PeerSnapshot {
+    pub network_id: String,
+    pub exported_at: Timestamp,
+    pub peers: Vec<Peer>,
+}
+
+/// Serializes the known peers into a snapshot document (in JSON).
+pub async fn export_peers(
+    network_config: &NetworkConfig,
+    known_peers: &SharedKnownPeers,
+) -> Result<String, Error> {
+    let snapshot = PeerSnapshot {
+        network_id: network_config.network_id.clone(),
+        exported_at: chrono::Utc::now().timestamp_millis() as Timestamp,
+        peers: known_peers.read().await,
+    };
+    Ok(serde_spb::to_string(&snapshot)?)
+}
+
+/// Merges the peers in the snapshot document exported by [`export_peers()`] into the known peers,
+/// returning the number of the peers added or updated.
+///
+/// The peers in a snapshot are not signed, so it must come from a trusted source.
+/// Still, only the members refused by no filter are taken (except this node),
+/// and a known peer is updated only if the snapshot has seen it more recently.
+pub async fn import_peers(
+    network_config: &NetworkConfig,
+    known_peers: &SharedKnownPeers,
+    document: &str,
+) -> Result<usize, Error> {
+    let snapshot: PeerSnapshot = serde_spb::from_str(document)?;
+    if snapshot.network_id != network_config.network_id {
+        return Err(eyre::eyre!(
+            "the snapshot is of network {}, not {}",
+            snapshot.network_id,
+            network_config.network_id
+        ));
+    }
+    let filter = network_config.peer_filter();
+    let mut imported = 0;
+    for peer in snapshot.peers {
+        if peer.public_key == network_config.public_key
+            || !network_config.members.contains(&peer.public_key)
+        {
+            continue;
+        }
+        if let Err(e) = filter.check_peer(&peer) {
+            log::debug!("skipped a peer in the snapshot: {e}");
+            continue;
+        }
+        let newer = known_peers
+            .get_peer(&peer.public_key)
+            .await
+            .map_or(true, |x| {
+                x.recently_seen_timestamp < peer.recently_seen_timestamp
+            });
+        if newer {
+            known_peers.add_or_replace(peer).await;
+            imported += 1;
+        }
+    }
+    Ok(imported)
+}
+
+/// Merges the stored peers into the initially known ones.
+///
+/// For a peer present in both, the entry that was seen more recently wins.
+pub fn merge_peers(initially_known_peers: Vec<Peer>, stored_peers: Vec<Peer>) -> Vec<Peer> {
+    let mut peers = initially_known_peers;
+    for stored in stored_peers {
+        match peers.iter_mut().find(|x| x.public_key == stored.public_key) {
+            Some(peer) => {
+                if stored.recently_seen_timestamp > peer.recently_seen_timestamp {
+                    *peer = stored;
+                }
+            }
+            None => peers.push(stored),
+        }
+    }
+    peers
+}
+
+/// Loads the stored peers into the known peers, replacing a known peer only with
+/// a more recently seen one. Returns the number of the peers added or updated.
+pub async fn load_stored_peers<S: PeerStore>(
+    store: &S,
+    known_peers: &SharedKnownPeers,
+) -> Result<usize, Error> {
+    let known = known_peers.read().await;
+    let mut loaded = 0;
+    for peer in merge_peers(known.clone(), store.load().await?) {
+        if !known.contains(&peer) {
+            known_peers.add_or_replace(peer).await;
+            loaded += 1;
+        }
+    }
+    Ok(loaded)
+}
+
+/// Saves the known peers to the store on the start and whenever they change, indefinitely.
+///
+/// Note that the changes made directly to the lock of the known peers are not noticed
+/// (see [`SharedKnownPeers::new`]).
+pub async fn run_peer_store<S: PeerStore>(
+    store: S,
+    known_peers: SharedKnownPeers,
+) -> Result<(), Error> {
+    let mut events = known_peers.subscribe();
+    store.save(&known_peers.read().await).await?;
+    loop {
+        match events.recv().await {
+            Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
+                store.save(&known_peers.read().await).await?
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    fn peer(seed: &str, recently_seen_timestamp: Timestamp) -> Peer {
+        Peer {
+            public_key: generate_keypair(seed).0,
+            name: seed.to_owned(),
+            address: "127.0.0.1:1".parse().unwrap(),
+            addresses: Vec::new(),
+            ports: HashMap::new(),
+            metadata: Default::default(),
+            recently_seen_timestamp,
+        }
+    }
+
+    #[tokio::test]
+    async fn save_and_load() {
+        setup_test();
+        let path = std::env::temp_dir().join(format!("simperby-peers-{}", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let _ = tokio::fs::remove_file(&path).await;
+
+        let store = FilePeerStore::new(&path);
+        assert!(store.load().await.unwrap().is_empty());
+        let peers = vec![peer("a", 10), peer("b", 20)];
+        store.save(&peers).await.unwrap();
+        assert_eq!(store.load().await.unwrap(), peers);
+        store.save(&peers[1..]).await.unwrap();
+        assert_eq!(store.load().await.unwrap(), peers[1..]);
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn persistence() {
+        setup_test();
+        let path =
+            std::env::temp_dir().join(format!("simperby-stored-peers-{}", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let _ = tokio::fs::remove_file(&path).await;
+
+        let store = FilePeerStore::new(&path);
+        let known_peers = SharedKnownPeers::new_static(vec![peer("a", 10)]);
+        let task = tokio::spawn(run_peer_store(store.clone(), known_peers.clone()));
+        known_peers.add_or_replace(peer("b", 20)).await;
+        known_peers.add_or_replace(peer("a", 30)).await;
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        task.abort();
+
+        // After a restart, only the more recently seen peers replace the known ones.
+        let known_peers = SharedKnownPeers::new_static(vec![peer("a", 40)]);
+        assert_eq!(load_stored_peers(&store, &known_peers).await.unwrap(), 1);
+        assert_eq!(known_peers.read().await, vec![peer("a", 40), peer("b", 20)]);
+        let known_peers = SharedKnownPeers::new_static(vec![peer("a", 10)]);
+        assert_eq!(load_stored_peers(&store, &known_peers).await.unwrap(), 2);
+        assert_eq!(known_peers.read().await, vec![peer("a", 30), peer("b", 20)]);
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[test]
+    fn merge() {
+        setup_test();
+        let merged = merge_peers(
+            vec![peer("a", 10), peer("b", 20)],
+            vec![peer("a", 5), peer("b", 30), peer("c", 1)],
+        );
+        assert_eq!(merged, vec![peer("a", 10), peer("b", 30), peer("c", 1)]);
+    }
+
+    #[tokio::test]
+    async fn snapshot() {
+        setup_test();
+        let network_config = |seed: &str, members: Vec<PublicKey>| {
+            let (public_key, private_key) = generate_keypair(seed);
+            NetworkConfig {
+                network_id: "snapshot".to_owned(),
+                ports: HashMap::new(),
+                members,
+                public_key,
+                private_key: Some(private_key),
+                dns_seeds: Vec::new(),
+                enable_mdns: false,
+                pre_shared_key: None,
+                proxy: None,
+                denied_peers: Vec::new(),
+                allowed_peers: Vec::new(),
+            }
+        };
+        let members = ["a", "b", "c", "me"]
+            .iter()
+            .map(|x| generate_keypair(x).0)
+            .collect::<Vec<_>>();
+        let exporter = network_config("a", members.clone());
+        let exported = SharedKnownPeers::new_static(vec![
+            peer("b", 20),
+            peer("c", 5),
+            peer("me", 1),
+            peer("outsider", 1),
+        ]);
+        let document = export_peers(&exporter, &exported).await.unwrap();
+
+        let importer = network_config("me", members);
+        let known_peers = SharedKnownPeers::new_static(vec![peer("b", 10), peer("c", 30)]);
+        assert_eq!(
+            import_peers(&importer, &known_peers, &document)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(known_peers.read().await, vec![peer("b", 20), peer("c", 30)]);
+
+        let other_network = NetworkConfig {
+            network_id: "other".to_owned(),
+            ..importer
+        };
+        assert!(import_peers(&other_network, &known_peers, &document)
+            .await
+            .is_err());
+    }
+}
@@ -1,5 +1,8 @@
 use super::*;
+use crate::peer_store::PeerStore;
 use async_trait::async_trait;
+use futures::Future;
+use serde::de::DeserializeOwned;
 use tokio::sync::mpsc;
 
 pub type StorageError = std::io::Error;
@@ -41,12 +44,42 @@ pub trait PeerDiscoveryPrimitive: Send + Sync + 'static {
     /// Remains online on the network indefinitely,
     /// responding to discovery requests from other nodes,
     /// updating `known_peers`.
-    async fn serve(
+    ///
+    /// The peers in `peer_store` are loaded on startup along with `initially_known_peers`,
+    /// and the known peers are saved back to it as they get updated.
+    /// The peers that haven't been seen for `peer_expiry.ttl_ms` are evicted.
+    /// The discovery rounds are run as configured by `discovery`.
+    ///
+    /// Shutting down the service announces the departure of this node to the peers
+    /// (if the implementation can) and saves the known peers.
+    async fn serve<S: PeerStore>(
         network_config: NetworkConfig,
-        message: String,
+        metadata: PeerMetadata,
         port_map: HashMap<String, u16>,
         initially_known_peers: Vec<Peer>,
-    ) -> Result<(SharedKnownPeers, tokio::task::JoinHandle<Result<(), Error>>), Error>;
+        peer_store: S,
+        peer_expiry: PeerExpiry,
+        discovery: DiscoveryConfig,
+    ) -> Result<(SharedKnownPeers, ServiceHandle), Error>;
+
+    /// Exports the known peers as a snapshot document, to be shared with the other nodes
+    /// or imported after a restart.
+    async fn export_peers(
+        network_config: &NetworkConfig,
+        known_peers: &SharedKnownPeers,
+    ) -> Result<String, Error> {
+        crate::peer_store::export_peers(network_config, known_peers).await
+    }
+
+    /// Merges the peers in a snapshot document exported by `export_peers()` into the known peers,
+    /// returning the number of the peers added or updated.
+    async fn import_peers(
+        network_config: &NetworkConfig,
+        known_peers: &SharedKnownPeers,
+        document: &str,
+    ) -> Result<usize, Error> {
+        crate::peer_store::import_peers(network_config, known_peers, document).await
+    }
 }
 
 /// The p2p gossip network.
@@ -61,16 +94,59 @@ pub trait GossipNetwork: Send + Sync + 'static {
 
     /// Remains online on the network indefinitely,
     /// serving (propagating) messages broadcasted over the network.
+    ///
+    /// Shutting down the service stops the delivery of new messages,
+    /// while the ones delivered already remain in the receiver.
     async fn serve(
         config: NetworkConfig,
         peers: SharedKnownPeers,
-    ) -> Result<
-        (
-            mpsc::Receiver<Vec<u8>>,
-            tokio::task::JoinHandle<Result<(), Error>>,
-        ),
-        Error,
-    >;
+    ) -> Result<(mpsc::Receiver<Vec<u8>>, ServiceHandle), Error>;
+}
+
+/// Point-to-point request/response between the peers,
+/// for the protocols that need to ask a specific peer (e.g., state sync).
+///
+/// A protocol is served on the port of the same name in `Peer::ports`,
+/// or along with the others on the port of [`crate::mux::MUX_PROTOCOL`] by a multiplexing transport.
+#[async_trait]
+pub trait RpcPrimitive: Send + Sync + 'static {
+    /// Sends a request to the peer, waiting for the response.
+    async fn request<Q, R>(&self, peer: &Peer, protocol: &str, request: Q) -> Result<R, Error>
+    where
+        Q: Serialize + Send + 'static,
+        R: DeserializeOwned + Send + 'static;
+
+    /// Serves the requests on the given port indefinitely, answering them with `handler`.
+    async fn serve<Q, R, F, Fut>(
+        &self,
+        port: u16,
+        handler: F,
+    ) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error>
+    where
+        Q: DeserializeOwned + Send + 'static,
+        R: Serialize + Send + 'static,
+        F: Fn(Q) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, String>> + Send + 'static;
+
+    /// Serves the requests of the protocol on the given port indefinitely, answering them with `handler`.
+    ///
+    /// A multiplexing transport dispatches the requests on the port by `protocol`,
+    /// while the others serve only this protocol on the port.
+    async fn serve_protocol<Q, R, F, Fut>(
+        &self,
+        protocol: &str,
+        port: u16,
+        handler: F,
+    ) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error>
+    where
+        Q: DeserializeOwned + Send + 'static,
+        R: Serialize + Send + 'static,
+        F: Fn(Q) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, String>> + Send + 'static,
+    {
+        let _ = protocol;
+        self.serve(port, handler).await
+    }
 }
 
 pub struct DummyGossipNetwork;
@@ -88,19 +164,13 @@ impl GossipNetwork for DummyGossipNetwork {
     async fn serve(
         _config: NetworkConfig,
         _peers: SharedKnownPeers,
-    ) -> Result<
-        (
-            mpsc::Receiver<Vec<u8>>,
-            tokio::task::JoinHandle<Result<(), Error>>,
-        ),
-        Error,
-    > {
+    ) -> Result<(mpsc::Receiver<Vec<u8>>, ServiceHandle), Error> {
         let (send, recv) = mpsc::channel(1);
-        let task = tokio::spawn(async move {
+        let handle = ServiceHandle::spawn(|mut shutdown| async move {
             let _send = send;
-            tokio::time::sleep(std::time::Duration::MAX).await;
+            shutdown.requested().await;
             Ok(())
         });
-        Ok((recv, task))
+        Ok((recv, handle))
     }
 }
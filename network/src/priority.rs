@@ -0,0 +1,227 @@
+//! Priority lanes of the broadcasts.
+//!
+//! The broadcasts of a node share its uplink, so the consensus votes could wait behind
+//! the chunks of a large bulk message (e.g., of the governance). A [`PriorityBroadcaster`]
+//! queues the broadcasts of each [`Priority`] separately and sends them one at a time,
+//! always preferring the consensus lane; only after `PriorityConfig::max_consensus_burst`
+//! consensus broadcasts in a row does a waiting bulk one go out, so that the bulk lane isn't starved.
+use super::*;
+use tokio::sync::{mpsc, oneshot};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, Default)]
+pub enum Priority {
+    /// The messages that the consensus progresses with (e.g., the votes).
+    Consensus,
+    #[default]
+    Bulk,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PriorityConfig {
+    /// The maximum number of the broadcasts queued in each lane,
+    /// beyond which the broadcasts of the lane wait.
+    pub lane_capacity: usize,
+    /// The maximum number of the consensus broadcasts sent in a row while a bulk one is waiting.
+    pub max_consensus_burst: usize,
+}
+
+impl Default for PriorityConfig {
+    fn default() -> Self {
+        Self {
+            lane_capacity: 1024,
+            max_consensus_burst: 16,
+        }
+    }
+}
+
+struct QueuedBroadcast {
+    config: NetworkConfig,
+    peers: Vec<Peer>,
+    message: Vec<u8>,
+    result: oneshot::Sender<Result<(), Error>>,
+}
+
+/// Schedules the broadcasts over `N` by their priorities, which can be shared by multiple components of a node.
+pub struct PriorityBroadcaster<N> {
+    consensus: mpsc::Sender<QueuedBroadcast>,
+    bulk: mpsc::Sender<QueuedBroadcast>,
+    _marker: std::marker::PhantomData<N>,
+}
+
+impl<N> std::fmt::Debug for PriorityBroadcaster<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PriorityBroadcaster")
+    }
+}
+
+impl<N: GossipNetwork> PriorityBroadcaster<N> {
+    /// Spawns the scheduler, which sends the queued broadcasts until it's shut down.
+    pub fn spawn(config: PriorityConfig) -> (Self, ServiceHandle) {
+        let (consensus, consensus_lane) = mpsc::channel(config.lane_capacity.max(1));
+        let (bulk, bulk_lane) = mpsc::channel(config.lane_capacity.max(1));
+        let handle = ServiceHandle::spawn(move |shutdown| {
+            schedule::<N>(config, consensus_lane, bulk_lane, shutdown)
+        });
+        let broadcaster = Self {
+            consensus,
+            bulk,
+            _marker: std::marker::PhantomData,
+        };
+        (broadcaster, handle)
+    }
+
+    /// Broadcasts the message in the lane of the priority, waiting until it's sent.
+    pub async fn broadcast(
+        &self,
+        config: &NetworkConfig,
+        known_peers: &[Peer],
+        message: Vec<u8>,
+        priority: Priority,
+    ) -> Result<(), Error> {
+        let (result, receiver) = oneshot::channel();
+        let queued = QueuedBroadcast {
+            config: config.clone(),
+            peers: known_peers.to_vec(),
+            message,
+            result,
+        };
+        let lane = match priority {
+            Priority::Consensus => &self.consensus,
+            Priority::Bulk => &self.bulk,
+        };
+        lane.send(queued)
+            .await
+            .map_err(|_| eyre::eyre!("the broadcaster has been shut down"))?;
+        receiver
+            .await
+            .map_err(|_| eyre::eyre!("the broadcaster has been shut down"))?
+    }
+}
+
+async fn schedule<N: GossipNetwork>(
+    config: PriorityConfig,
+    mut consensus: mpsc::Receiver<QueuedBroadcast>,
+    mut bulk: mpsc::Receiver<QueuedBroadcast>,
+    mut shutdown: ShutdownSignal,
+) -> Result<(), Error> {
+    // The consensus broadcasts sent in a row.
+    let mut burst = 0;
+    loop {
+        let overdue = if burst >= config.max_consensus_burst {
+            bulk.try_recv().ok()
+        } else {
+            None
+        };
+        let queued = match overdue {
+            Some(x) => {
+                burst = 0;
+                x
+            }
+            None => tokio::select! {
+                biased;
+                _ = shutdown.requested() => return Ok(()),
+                Some(x) = consensus.recv() => {
+                    burst += 1;
+                    x
+                }
+                Some(x) = bulk.recv() => {
+                    burst = 0;
+                    x
+                }
+                else => return Ok(()),
+            },
+        };
+        let result = N::broadcast(&queued.config, &queued.peers, queued.message).await;
+        // The broadcaster may have stopped waiting.
+        let _ = queued.result.send(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use simperby_test_suite::*;
+
+    static SENT: Mutex<Vec<Vec<u8>>> = parking_lot::const_mutex(Vec::new());
+
+    /// Records the messages, taking a while to send each.
+    struct SlowNetwork;
+
+    #[async_trait]
+    impl GossipNetwork for SlowNetwork {
+        async fn broadcast(
+            _config: &NetworkConfig,
+            _known_peers: &[Peer],
+            message: Vec<u8>,
+        ) -> Result<(), Error> {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            SENT.lock().push(message);
+            Ok(())
+        }
+
+        async fn serve(
+            _config: NetworkConfig,
+            _peers: SharedKnownPeers,
+        ) -> Result<(mpsc::Receiver<Vec<u8>>, ServiceHandle), Error> {
+            unimplemented!()
+        }
+    }
+
+    #[tokio::test]
+    async fn lanes() {
+        setup_test();
+        let (public_key, private_key) = generate_keypair("priority");
+        let network_config = NetworkConfig {
+            network_id: "priority".to_owned(),
+            ports: HashMap::new(),
+            members: Vec::new(),
+            public_key,
+            private_key: Some(private_key),
+            dns_seeds: Vec::new(),
+            enable_mdns: false,
+            pre_shared_key: None,
+            proxy: None,
+            denied_peers: Vec::new(),
+            allowed_peers: Vec::new(),
+        };
+        let (broadcaster, handle) = PriorityBroadcaster::<SlowNetwork>::spawn(PriorityConfig {
+            lane_capacity: 10,
+            max_consensus_burst: 2,
+        });
+        let broadcaster = Arc::new(broadcaster);
+        let broadcast = |message: &'static str, priority| {
+            let broadcaster = Arc::clone(&broadcaster);
+            let network_config = network_config.clone();
+            tokio::spawn(async move {
+                broadcaster
+                    .broadcast(&network_config, &[], message.as_bytes().to_vec(), priority)
+                    .await
+            })
+        };
+        // The others are queued while the first is being sent.
+        let mut tasks = vec![broadcast("first", Priority::Bulk)];
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        for i in ["b1", "b2", "b3"] {
+            tasks.push(broadcast(i, Priority::Bulk));
+        }
+        for i in ["c1", "c2", "c3"] {
+            tasks.push(broadcast(i, Priority::Consensus));
+        }
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        let sent = SENT
+            .lock()
+            .iter()
+            .map(|x| String::from_utf8(x.clone()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(sent, ["first", "c1", "c2", "b1", "c3", "b2", "b3"]);
+
+        handle.shutdown().await.unwrap();
+        assert!(broadcaster
+            .broadcast(&network_config, &[], Vec::new(), Priority::Consensus)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,263 @@
+//! Dialing the peers through a SOCKS5 proxy (e.g., Tor), for the nodes in restrictive environments.
+//!
+//! With `NetworkConfig::proxy`, the outbound connections of the DMS and of the peer discovery
+//! (e.g., fetching the peer records of the seeds) are made through the proxy.
+//! The DNS names of the peers are resolved by the proxy, so the lookups don't leak either.
+//!
+//! With the `tor` feature, the peers can also be dialed at their onion addresses
+//! (a [`PeerAddress::Dns`] ending with `.onion`), which requires the proxy to be Tor.
+use super::*;
+use eyre::eyre;
+use std::net::IpAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// The address (`host:port`) of the SOCKS5 proxy (e.g., `127.0.0.1:9050` for Tor).
+    pub address: String,
+    /// The username and the password, if the proxy requires them.
+    #[serde(default)]
+    pub credentials: Option<(String, String)>,
+}
+
+impl ProxyConfig {
+    /// Creates an HTTP client whose connections go through the proxy.
+    pub fn http_client(&self) -> Result<reqwest::Client, Error> {
+        // `socks5h` makes the proxy resolve the host names.
+        let mut proxy = reqwest::Proxy::all(format!("socks5h://{}", self.address))?;
+        if let Some((username, password)) = &self.credentials {
+            proxy = proxy.basic_auth(username, password);
+        }
+        Ok(client_builder().proxy(proxy).build()?)
+    }
+}
+
+/// Creates an HTTP client that goes through the proxy if there is one.
+pub fn http_client(proxy: Option<&ProxyConfig>) -> Result<reqwest::Client, Error> {
+    match proxy {
+        Some(proxy) => proxy.http_client(),
+        None => Ok(client_builder().build()?),
+    }
+}
+
+fn client_builder() -> reqwest::ClientBuilder {
+    // The client is shared across the serving sessions of the peers. A kept-alive connection
+    // outlives the server session that accepted it, and would only answer "server terminated"
+    // once the peer serves again, so the connections are not kept for reuse.
+    reqwest::Client::builder().pool_max_idle_per_host(0)
+}
+
+pub fn is_onion(host: &PeerAddress) -> bool {
+    matches!(host, PeerAddress::Dns(name) if name.trim_end_matches('.').ends_with(".onion"))
+}
+
+/// Checks whether the host can be dialed, which fails only for the onion addresses
+/// unless they are dialed through a proxy with the `tor` feature.
+pub fn check_dialable(host: &PeerAddress, proxy: Option<&ProxyConfig>) -> Result<(), Error> {
+    if !is_onion(host) {
+        return Ok(());
+    }
+    if !cfg!(feature = "tor") {
+        return Err(eyre!("dialing {host} requires the `tor` feature"));
+    }
+    if proxy.is_none() {
+        return Err(eyre!("dialing {host} requires a Tor proxy"));
+    }
+    Ok(())
+}
+
+/// Connects to `host:port` through the proxy.
+pub async fn connect(
+    proxy: &ProxyConfig,
+    host: &PeerAddress,
+    port: u16,
+) -> Result<TcpStream, Error> {
+    let mut stream = TcpStream::connect(&proxy.address)
+        .await
+        .map_err(|e| eyre!("failed to connect to the proxy {}: {e}", proxy.address))?;
+
+    // Negotiates the authentication method.
+    match &proxy.credentials {
+        Some(_) => stream.write_all(&[5, 2, 0, 2]).await?,
+        None => stream.write_all(&[5, 1, 0]).await?,
+    }
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    match (reply, &proxy.credentials) {
+        ([5, 0], _) => (),
+        ([5, 2], Some((username, password))) => {
+            let mut request = vec![1, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request).await?;
+            stream.read_exact(&mut reply).await?;
+            if reply[1] != 0 {
+                return Err(eyre!("the proxy rejected the credentials"));
+            }
+        }
+        _ => return Err(eyre!("the proxy accepts no authentication method offered")),
+    }
+
+    // Requests the connection.
+    let mut request = vec![5, 1, 0];
+    match host {
+        PeerAddress::Ip(IpAddr::V4(ip)) => {
+            request.push(1);
+            request.extend_from_slice(&ip.octets());
+        }
+        PeerAddress::Ip(IpAddr::V6(ip)) => {
+            request.push(4);
+            request.extend_from_slice(&ip.octets());
+        }
+        PeerAddress::Dns(name) => {
+            if name.len() > 255 {
+                return Err(eyre!("host name {name} is too long"));
+            }
+            request.push(3);
+            request.push(name.len() as u8);
+            request.extend_from_slice(name.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        return Err(eyre!(
+            "the proxy failed to connect to {host}:{port} (reply {})",
+            reply[1]
+        ));
+    }
+    // Skips the address that the proxy bound.
+    let bound_address_size = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => stream.read_u8().await? as usize,
+        x => return Err(eyre!("unknown address type {x} from the proxy")),
+    };
+    let mut bound_address = vec![0; bound_address_size + 2];
+    stream.read_exact(&mut bound_address).await?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+    use tokio::net::TcpListener;
+
+    /// A SOCKS5 proxy that only supports the username/password authentication and the DNS names.
+    async fn run_proxy(listener: TcpListener) {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut header = [0; 2];
+                stream.read_exact(&mut header).await.unwrap();
+                let mut methods = vec![0; header[1] as usize];
+                stream.read_exact(&mut methods).await.unwrap();
+                if !methods.contains(&2) {
+                    stream.write_all(&[5, 0xff]).await.unwrap();
+                    return;
+                }
+                stream.write_all(&[5, 2]).await.unwrap();
+                let mut fields = Vec::new();
+                stream.read_u8().await.unwrap();
+                for _ in 0..2 {
+                    let mut field = vec![0; stream.read_u8().await.unwrap() as usize];
+                    stream.read_exact(&mut field).await.unwrap();
+                    fields.push(String::from_utf8(field).unwrap());
+                }
+                let authenticated = fields == ["user", "pass"];
+                stream.write_all(&[1, !authenticated as u8]).await.unwrap();
+                if !authenticated {
+                    return;
+                }
+
+                let mut request = [0; 4];
+                stream.read_exact(&mut request).await.unwrap();
+                assert_eq!(request, [5, 1, 0, 3]);
+                let mut name = vec![0; stream.read_u8().await.unwrap() as usize];
+                stream.read_exact(&mut name).await.unwrap();
+                let port = stream.read_u16().await.unwrap();
+                let name = String::from_utf8(name).unwrap();
+                let target = name.trim_end_matches(".onion").replace('-', ".");
+                let mut target = match TcpStream::connect(format!("{target}:{port}")).await {
+                    Ok(x) => x,
+                    Err(_) => {
+                        stream
+                            .write_all(&[5, 4, 0, 1, 0, 0, 0, 0, 0, 0])
+                            .await
+                            .unwrap();
+                        return;
+                    }
+                };
+                stream
+                    .write_all(&[5, 0, 0, 3, 4, b'n', b'a', b'm', b'e', 0, 0])
+                    .await
+                    .unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut stream, &mut target).await;
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn socks5() {
+        setup_test();
+        let proxy_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_address = proxy_listener.local_addr().unwrap().to_string();
+        let proxy = tokio::spawn(run_proxy(proxy_listener));
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = target.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = target.accept().await.unwrap();
+            let x = stream.read_u64().await.unwrap();
+            stream.write_u64(x + 1).await.unwrap();
+        });
+
+        // The mock proxy maps `127-0-0-1.onion` to `127.0.0.1`.
+        let host = PeerAddress::Dns("127-0-0-1.onion".to_owned());
+        let config = |credentials: Option<(&str, &str)>| ProxyConfig {
+            address: proxy_address.clone(),
+            credentials: credentials.map(|(x, y)| (x.to_owned(), y.to_owned())),
+        };
+        let mut stream = connect(&config(Some(("user", "pass"))), &host, port)
+            .await
+            .unwrap();
+        stream.write_u64(1).await.unwrap();
+        assert_eq!(stream.read_u64().await.unwrap(), 2);
+        server.await.unwrap();
+
+        let error = connect(&config(Some(("user", "wrong"))), &host, port)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("rejected the credentials"));
+        let error = connect(&config(None), &host, port).await.unwrap_err();
+        assert!(error.to_string().contains("no authentication method"));
+        let host = PeerAddress::Dns("nonexistent-invalid".to_owned());
+        let error = connect(&config(Some(("user", "pass"))), &host, port)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("failed to connect"));
+        proxy.abort();
+    }
+
+    #[test]
+    fn onion_addresses() {
+        setup_test();
+        let onion = PeerAddress::Dns("example.onion".to_owned());
+        let proxy = ProxyConfig {
+            address: "127.0.0.1:9050".to_owned(),
+            credentials: None,
+        };
+        assert!(is_onion(&onion));
+        assert!(!is_onion(&PeerAddress::Dns("onion.example".to_owned())));
+        assert!(check_dialable(&PeerAddress::Dns("example.com".to_owned()), None).is_ok());
+        assert!(check_dialable(&onion, None).is_err());
+        assert_eq!(
+            check_dialable(&onion, Some(&proxy)).is_ok(),
+            cfg!(feature = "tor")
+        );
+    }
+}
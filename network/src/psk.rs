@@ -0,0 +1,126 @@
+//! Private networks isolated by a pre-shared key.
+//!
+//! When `NetworkConfig::pre_shared_key` is set, the connections of the transports that support it
+//! ([`crate::rpc::TcpRpc`] and [`crate::mux::MuxRpc`]) are encrypted with a session key derived from
+//! the pre-shared key and the nonces that both sides pick, so only the nodes with the same key can
+//! talk to each other.
+//!
+//! A DMS with the key makes its RPCs only over the multiplexed connections
+//! (see [`crate::dms::DistributedMessageSet::set_mux()`]), not serving or dialing the HTTP ports.
+//! The gossip network and the Git server of the repository are not covered by the key.
+use super::*;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use eyre::eyre;
+
+/// A 32-byte secret shared by all the nodes of a private network.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PreSharedKey {
+    key: Hash256,
+}
+
+impl std::fmt::Debug for PreSharedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PreSharedKey(..)")
+    }
+}
+
+impl PreSharedKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            key: Hash256::from_array(key),
+        }
+    }
+
+    /// Derives the key from a passphrase, for the deployments that don't manage a random key.
+    pub fn from_passphrase(passphrase: &str) -> Self {
+        Self {
+            key: Hash256::hash(passphrase),
+        }
+    }
+
+    /// Derives the cipher of a connection from the nonces of the client and the server.
+    pub(crate) fn session(&self, client_nonce: &[u8], server_nonce: &[u8]) -> SessionCipher {
+        let mut material = self.key.as_ref().to_vec();
+        material.extend_from_slice(client_nonce);
+        material.extend_from_slice(server_nonce);
+        SessionCipher::new(Hash256::hash(material), "pre-shared key")
+    }
+}
+
+/// Which side sends a frame, so that the two directions never share a nonce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Side {
+    Client = 0,
+    Server = 1,
+}
+
+pub(crate) struct SessionCipher {
+    cipher: ChaCha20Poly1305,
+    key_name: &'static str,
+}
+
+impl SessionCipher {
+    /// `key_name` tells which key mismatched when a frame fails to decrypt.
+    pub(crate) fn new(key: Hash256, key_name: &'static str) -> Self {
+        SessionCipher {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key.as_ref())),
+            key_name,
+        }
+    }
+
+    fn nonce(side: Side, counter: u64) -> Nonce {
+        let mut nonce = [0; 12];
+        nonce[0] = side as u8;
+        nonce[4..].copy_from_slice(&counter.to_be_bytes());
+        *Nonce::from_slice(&nonce)
+    }
+
+    pub(crate) fn encrypt(&self, side: Side, counter: u64, payload: &[u8]) -> Vec<u8> {
+        self.cipher
+            .encrypt(&Self::nonce(side, counter), payload)
+            .expect("encryption never fails")
+    }
+
+    pub(crate) fn decrypt(
+        &self,
+        side: Side,
+        counter: u64,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        self.cipher
+            .decrypt(&Self::nonce(side, counter), payload)
+            .map_err(|_| eyre!("{} mismatch (failed to decrypt a frame)", self.key_name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    #[test]
+    fn session() {
+        setup_test();
+        let psk = PreSharedKey::from_passphrase("consortium");
+        let (client, server) = (psk.session(b"c", b"s"), psk.session(b"c", b"s"));
+        let frame = client.encrypt(Side::Client, 0, b"hello");
+        assert_eq!(server.decrypt(Side::Client, 0, &frame).unwrap(), b"hello");
+        // Another direction, counter, session or key.
+        assert!(server.decrypt(Side::Server, 0, &frame).is_err());
+        assert!(server.decrypt(Side::Client, 1, &frame).is_err());
+        assert!(psk
+            .session(b"c", b"t")
+            .decrypt(Side::Client, 0, &frame)
+            .is_err());
+        assert!(PreSharedKey::from_passphrase("other")
+            .session(b"c", b"s")
+            .decrypt(Side::Client, 0, &frame)
+            .is_err());
+
+        let encoded = serde_json::to_string(&psk).unwrap();
+        assert_eq!(serde_json::from_str::<PreSharedKey>(&encoded).unwrap(), psk);
+        assert_eq!(format!("{psk:?}"), "PreSharedKey(..)");
+    }
+}
@@ -0,0 +1,741 @@
+//! An implementation of [`RpcPrimitive`] over plain TCP.
+//!
+//! Each request is made on a new connection, carrying a single frame in each direction.
+//! A frame is the length (4 bytes, big-endian) followed by the `serde_spb` encoding of the payload;
+//! the response payload is a `Result<R, String>`.
+//!
+//! Before the frames, each side sends whether it uses a pre-shared key (a byte).
+//! If they do, each side also sends a nonce and then proves that it has the same key,
+//! after which the frames are encrypted (see [`crate::psk`]).
+//! Each side also sends the compression algorithms that it supports,
+//! and the frames are compressed (before the encryption) with the negotiated one (see [`crate::compression`]).
+//!
+//! With a [`MemberIdentity`], each side then proves its member key,
+//! and the frames are encrypted with the session key agreed on in the proofs (see [`crate::identity`]).
+//!
+//! The connections can be made through a SOCKS5 proxy (see [`crate::proxy`]).
+//!
+//! A handler can tell where the request came from with [`remote_address()`].
+use super::*;
+use crate::access::PeerFilter;
+use crate::auth_events::{AuthEvents, AuthFailureReason};
+use crate::compression::{self, Compression, CompressionConfig};
+use crate::identity::MemberIdentity;
+use crate::proxy::{self, ProxyConfig};
+use crate::psk::{PreSharedKey, SessionCipher, Side};
+use eyre::eyre;
+use futures::Future;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tracing::Instrument;
+
+tokio::task_local! {
+    static REMOTE_ADDRESS: SocketAddr;
+}
+
+/// The address that the request being handled came from, as observed by this node
+/// (e.g., the external address of a peer behind a NAT).
+///
+/// It's known only within the future returned by a handler (not in the tasks that it spawns),
+/// and only if the transport tells it.
+pub fn remote_address() -> Option<SocketAddr> {
+    REMOTE_ADDRESS.try_with(|x| *x).ok()
+}
+
+/// Runs the future of a handler with its [`remote_address()`].
+pub(crate) async fn with_remote_address<F: Future>(address: SocketAddr, future: F) -> F::Output {
+    REMOTE_ADDRESS.scope(address, future).await
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RpcConfig {
+    /// The timeout of a whole request, including the connection.
+    /// For the server, it's the timeout of handling a request.
+    pub timeout_ms: u64,
+    /// The maximum number of the requests in progress at once,
+    /// for the sent ones and the served ones respectively.
+    pub max_concurrent_requests: usize,
+    /// The maximum size of a frame in bytes.
+    pub max_frame_size: u32,
+    /// The local addresses to listen on, all at once.
+    ///
+    /// Note that `::` usually accepts the IPv4 connections as well,
+    /// so it can't be listened on along with `0.0.0.0`.
+    pub listen_addresses: Vec<IpAddr>,
+    /// The compression of the frames, for both the sent and the served requests.
+    #[serde(default)]
+    pub compression: CompressionConfig,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 10 * 1000,
+            max_concurrent_requests: 64,
+            max_frame_size: 16 * 1024 * 1024,
+            listen_addresses: vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)],
+            compression: Default::default(),
+        }
+    }
+}
+
+pub struct TcpRpc {
+    config: RpcConfig,
+    semaphore: Arc<Semaphore>,
+    pre_shared_key: Option<PreSharedKey>,
+    proxy: Option<ProxyConfig>,
+    filter: PeerFilter,
+    auth_events: AuthEvents,
+    identity: Option<Arc<MemberIdentity>>,
+}
+
+impl TcpRpc {
+    pub fn new(config: RpcConfig) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(config.max_concurrent_requests.max(1))),
+            config,
+            pre_shared_key: None,
+            proxy: None,
+            filter: PeerFilter::default(),
+            auth_events: AuthEvents::default(),
+            identity: None,
+        }
+    }
+
+    /// Encrypts the connections with the key (usually `NetworkConfig::pre_shared_key`),
+    /// refusing the other side unless it has the same key.
+    pub fn with_pre_shared_key(mut self, pre_shared_key: Option<PreSharedKey>) -> Self {
+        self.pre_shared_key = pre_shared_key;
+        self
+    }
+
+    /// Makes the outbound connections through the proxy (usually `NetworkConfig::proxy`).
+    pub fn with_proxy(mut self, proxy: Option<ProxyConfig>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Refuses to connect to the peers, or to accept the connections from the addresses,
+    /// that the filter (usually `NetworkConfig::peer_filter()`) refuses.
+    pub fn with_peer_filter(mut self, filter: PeerFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Reports the refused inbound connections (e.g., with a wrong pre-shared key) to the events.
+    pub fn with_auth_events(mut self, auth_events: AuthEvents) -> Self {
+        self.auth_events = auth_events;
+        self
+    }
+
+    /// Pins the connections to the member keys of the identity
+    /// (usually made from the `NetworkConfig`), refusing the other side unless it pins too.
+    pub fn with_member_identity(mut self, identity: Option<Arc<MemberIdentity>>) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.config.timeout_ms)
+    }
+}
+
+/// A connection, which is encrypted if a pre-shared key or a member identity is used.
+pub(crate) struct Connection {
+    reader: FrameReader,
+    writer: FrameWriter,
+    /// The key proven by the other side, if the identities are pinned.
+    remote_key: Option<PublicKey>,
+}
+
+/// The receiving half of a [`Connection`].
+pub(crate) struct FrameReader {
+    stream: OwnedReadHalf,
+    side: Side,
+    cipher: Option<Arc<SessionCipher>>,
+    received: u64,
+}
+
+/// The sending half of a [`Connection`].
+pub(crate) struct FrameWriter {
+    stream: OwnedWriteHalf,
+    side: Side,
+    cipher: Option<Arc<SessionCipher>>,
+    compression: Option<Compression>,
+    compression_threshold: usize,
+    sent: u64,
+}
+
+impl Connection {
+    fn new(
+        stream: TcpStream,
+        side: Side,
+        cipher: Option<SessionCipher>,
+        compression: Option<Compression>,
+        compression_threshold: usize,
+    ) -> Self {
+        let cipher = cipher.map(Arc::new);
+        let (reader, writer) = stream.into_split();
+        Self {
+            reader: FrameReader {
+                stream: reader,
+                side,
+                cipher: cipher.clone(),
+                received: 0,
+            },
+            writer: FrameWriter {
+                stream: writer,
+                side,
+                cipher,
+                compression,
+                compression_threshold,
+                sent: 0,
+            },
+            remote_key: None,
+        }
+    }
+
+    pub(crate) async fn open(
+        mut stream: TcpStream,
+        side: Side,
+        pre_shared_key: Option<&PreSharedKey>,
+        compression: &CompressionConfig,
+        identity: Option<&MemberIdentity>,
+    ) -> Result<Self, Error> {
+        let nonce = rand::thread_rng().gen::<[u8; 32]>();
+        stream.write_u8(pre_shared_key.is_some() as u8).await?;
+        stream.write_u8(compression.supported()).await?;
+        stream.write_u8(identity.is_some() as u8).await?;
+        if pre_shared_key.is_some() {
+            stream.write_all(&nonce).await?;
+        }
+        stream.flush().await?;
+        let other_uses_key = stream.read_u8().await? == 1;
+        let compression_threshold = compression.threshold_bytes;
+        let compression = compression.negotiate(stream.read_u8().await?);
+        let other_pins = stream.read_u8().await? == 1;
+        match (identity.is_some(), other_pins) {
+            (true, false) => return Err(eyre!("the other side doesn't prove its member key")),
+            (false, true) => return Err(eyre!("the other side requires a member key")),
+            _ => (),
+        }
+        let mut this = match (pre_shared_key, other_uses_key) {
+            (Some(pre_shared_key), true) => {
+                let mut other_nonce = [0; 32];
+                stream.read_exact(&mut other_nonce).await?;
+                let cipher = match side {
+                    Side::Client => pre_shared_key.session(&nonce, &other_nonce),
+                    Side::Server => pre_shared_key.session(&other_nonce, &nonce),
+                };
+                let mut this = Self::new(
+                    stream,
+                    side,
+                    Some(cipher),
+                    compression,
+                    compression_threshold,
+                );
+                // The first frames confirm the key, so that a mismatch is detected on both sides.
+                this.write_frame(&[]).await?;
+                this.read_frame(0).await?;
+                this
+            }
+            (None, false) => Self::new(stream, side, None, compression, compression_threshold),
+            (Some(_), false) => return Err(eyre!("the other side has no pre-shared key")),
+            (None, true) => return Err(eyre!("the other side requires a pre-shared key")),
+        };
+        if let Some(identity) = identity {
+            let (remote_key, cipher) = identity.authenticate(&mut this, side).await?;
+            this.set_cipher(cipher);
+            this.remote_key = Some(remote_key);
+        }
+        Ok(this)
+    }
+
+    /// Encrypts the frames from now on with the cipher, in place of the current one.
+    fn set_cipher(&mut self, cipher: SessionCipher) {
+        let cipher = Arc::new(cipher);
+        self.reader.cipher = Some(Arc::clone(&cipher));
+        self.writer.cipher = Some(cipher);
+    }
+
+    /// The key proven by the other side, if the identities are pinned.
+    pub(crate) fn remote_key(&self) -> Option<&PublicKey> {
+        self.remote_key.as_ref()
+    }
+
+    pub(crate) async fn write_frame(&mut self, payload: &[u8]) -> Result<(), Error> {
+        self.writer.write_frame(payload).await
+    }
+
+    pub(crate) async fn read_frame(&mut self, max_frame_size: u32) -> Result<Vec<u8>, Error> {
+        self.reader.read_frame(max_frame_size).await
+    }
+
+    /// Splits the connection, to read and write the frames concurrently.
+    pub(crate) fn into_split(self) -> (FrameReader, FrameWriter) {
+        (self.reader, self.writer)
+    }
+}
+
+impl FrameWriter {
+    pub(crate) async fn write_frame(&mut self, payload: &[u8]) -> Result<(), Error> {
+        let payload = compression::encode(self.compression, self.compression_threshold, payload);
+        let payload = match &self.cipher {
+            Some(cipher) => cipher.encrypt(self.side, self.sent, &payload),
+            None => payload,
+        };
+        self.sent += 1;
+        self.stream.write_u32(payload.len() as u32).await?;
+        self.stream.write_all(&payload).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+impl FrameReader {
+    pub(crate) async fn read_frame(&mut self, max_frame_size: u32) -> Result<Vec<u8>, Error> {
+        // The compression byte, and the authentication tag of an encrypted frame.
+        let overhead = if self.cipher.is_some() { 17 } else { 1 };
+        let size = self.stream.read_u32().await?;
+        if size > max_frame_size + overhead {
+            return Err(eyre!("frame of {size} bytes exceeds the limit"));
+        }
+        let mut payload = vec![0; size as usize];
+        self.stream.read_exact(&mut payload).await?;
+        let other_side = match self.side {
+            Side::Client => Side::Server,
+            Side::Server => Side::Client,
+        };
+        let payload = match &self.cipher {
+            Some(cipher) => cipher.decrypt(other_side, self.received, &payload)?,
+            None => payload,
+        };
+        self.received += 1;
+        compression::decode(&payload, max_frame_size as usize)
+    }
+}
+
+/// How the accepted connections are opened, and where the refused ones are reported.
+#[derive(Clone)]
+pub(crate) struct Acceptor {
+    pub(crate) pre_shared_key: Option<PreSharedKey>,
+    pub(crate) identity: Option<Arc<MemberIdentity>>,
+    pub(crate) auth_events: AuthEvents,
+}
+
+impl Acceptor {
+    /// Opens the accepted connection, refusing the client unless it proves a member key
+    /// (if the identities are pinned).
+    pub(crate) async fn open(
+        &self,
+        stream: TcpStream,
+        address: SocketAddr,
+        compression: &CompressionConfig,
+    ) -> Result<Connection, Error> {
+        let connection = match Connection::open(
+            stream,
+            Side::Server,
+            self.pre_shared_key.as_ref(),
+            compression,
+            self.identity.as_deref(),
+        )
+        .await
+        {
+            Ok(x) => x,
+            Err(e) => {
+                self.auth_events
+                    .report(Some(address), AuthFailureReason::Handshake(e.to_string()));
+                return Err(e);
+            }
+        };
+        match (&self.identity, connection.remote_key()) {
+            (Some(identity), Some(key)) if !identity.is_member(key) => {
+                self.auth_events
+                    .report(Some(address), AuthFailureReason::UnknownKey(key.clone()));
+                Err(eyre!("the client proved a non-member key {key}"))
+            }
+            _ => Ok(connection),
+        }
+    }
+}
+
+/// Connects to the first reachable address of the peer.
+pub(crate) async fn connect(
+    peer: &Peer,
+    port: u16,
+    proxy: Option<&ProxyConfig>,
+) -> Result<TcpStream, Error> {
+    let mut error = None;
+    for host in peer.hosts() {
+        if let Err(e) = proxy::check_dialable(&host, proxy) {
+            error = Some(e);
+            continue;
+        }
+        let result = match proxy {
+            Some(proxy) => proxy::connect(proxy, &host, port).await,
+            None => TcpStream::connect(format!("{host}:{port}"))
+                .await
+                .map_err(Error::from),
+        };
+        match result {
+            Ok(stream) => {
+                tracing::debug!(%host, port, proxied = proxy.is_some(), "connected");
+                return Ok(stream);
+            }
+            Err(e) => {
+                tracing::debug!(%host, port, "failed to connect: {e}");
+                error = Some(eyre!("failed to connect to {host}:{port}: {e}"));
+            }
+        }
+    }
+    Err(error.expect("there is at least one address"))
+}
+
+async fn handle_connection<Q, R, F, Fut>(
+    stream: TcpStream,
+    address: SocketAddr,
+    handler: Arc<F>,
+    config: RpcConfig,
+    acceptor: Acceptor,
+) -> Result<(), Error>
+where
+    Q: DeserializeOwned + Send + 'static,
+    R: Serialize + Send + 'static,
+    F: Fn(Q) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<R, String>> + Send + 'static,
+{
+    let mut connection = acceptor.open(stream, address, &config.compression).await?;
+    let request = connection.read_frame(config.max_frame_size).await?;
+    let response = match serde_spb::from_slice::<Q>(&request) {
+        Ok(request) => {
+            let response = with_remote_address(address, handler(request));
+            match tokio::time::timeout(Duration::from_millis(config.timeout_ms), response).await {
+                Ok(response) => response,
+                Err(_) => Err("the request timed out".to_owned()),
+            }
+        }
+        Err(e) => Err(format!("malformed request: {e}")),
+    };
+    connection.write_frame(&serde_spb::to_vec(&response)?).await
+}
+
+#[async_trait]
+impl RpcPrimitive for TcpRpc {
+    async fn request<Q, R>(&self, peer: &Peer, protocol: &str, request: Q) -> Result<R, Error>
+    where
+        Q: Serialize + Send + 'static,
+        R: DeserializeOwned + Send + 'static,
+    {
+        let port = *peer
+            .ports
+            .get(protocol)
+            .ok_or_else(|| eyre!("peer {} doesn't serve {protocol}", peer.public_key))?;
+        self.filter.check_peer(peer)?;
+        let request = serde_spb::to_vec(&request)?;
+        let _permit = self.semaphore.acquire().await?;
+        let max_frame_size = self.config.max_frame_size;
+        let pre_shared_key = self.pre_shared_key.as_ref();
+        let proxy = self.proxy.as_ref();
+        let compression = &self.config.compression;
+        let identity = self.identity.as_deref();
+        let span = tracing::debug_span!("rpc_request", peer = %peer.public_key, protocol);
+        let exchange = async move {
+            let stream = connect(peer, port, proxy).await?;
+            let mut connection =
+                Connection::open(stream, Side::Client, pre_shared_key, compression, identity)
+                    .await?;
+            if let (Some(identity), Some(key)) = (identity, connection.remote_key()) {
+                identity.check_server(peer, key)?;
+            }
+            connection.write_frame(&request).await?;
+            let response = connection.read_frame(max_frame_size).await?;
+            Result::<_, Error>::Ok(serde_spb::from_slice::<Result<R, String>>(&response)?)
+        };
+        tokio::time::timeout(self.timeout(), exchange.instrument(span))
+            .await
+            .map_err(|_| eyre!("request to {} timed out", peer.public_key))??
+            .map_err(|e| eyre!("{protocol} request to {} failed: {e}", peer.public_key))
+    }
+
+    async fn serve<Q, R, F, Fut>(
+        &self,
+        port: u16,
+        handler: F,
+    ) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error>
+    where
+        Q: DeserializeOwned + Send + 'static,
+        R: Serialize + Send + 'static,
+        F: Fn(Q) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, String>> + Send + 'static,
+    {
+        let mut listeners = Vec::new();
+        for address in &self.config.listen_addresses {
+            listeners.push(TcpListener::bind((*address, port)).await?);
+        }
+        let handler = Arc::new(handler);
+        let config = self.config.clone();
+        let semaphore = Arc::new(Semaphore::new(config.max_concurrent_requests.max(1)));
+        let accept_loops = listeners.into_iter().map(|listener| {
+            accept_loop(
+                listener,
+                Arc::clone(&handler),
+                config.clone(),
+                Arc::clone(&semaphore),
+                Acceptor {
+                    pre_shared_key: self.pre_shared_key.clone(),
+                    identity: self.identity.clone(),
+                    auth_events: self.auth_events.clone(),
+                },
+                self.filter.clone(),
+            )
+        });
+        let accept_loops = futures::future::try_join_all(accept_loops);
+        Ok(tokio::spawn(async move { accept_loops.await.map(|_| ()) }))
+    }
+}
+
+async fn accept_loop<Q, R, F, Fut>(
+    listener: TcpListener,
+    handler: Arc<F>,
+    config: RpcConfig,
+    semaphore: Arc<Semaphore>,
+    acceptor: Acceptor,
+    filter: PeerFilter,
+) -> Result<(), Error>
+where
+    Q: DeserializeOwned + Send + 'static,
+    R: Serialize + Send + 'static,
+    F: Fn(Q) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<R, String>> + Send + 'static,
+{
+    loop {
+        let permit = Arc::clone(&semaphore).acquire_owned().await?;
+        let (stream, address) = listener.accept().await?;
+        if let Err(e) = filter.check_address(&address.ip()) {
+            acceptor
+                .auth_events
+                .report(Some(address), AuthFailureReason::Denied(e.to_string()));
+            continue;
+        }
+        let handler = Arc::clone(&handler);
+        let config = config.clone();
+        let acceptor = acceptor.clone();
+        let span = tracing::debug_span!("rpc_connection", %address);
+        tokio::spawn(
+            async move {
+                tracing::debug!("accepted");
+                let result = handle_connection(stream, address, handler, config, acceptor).await;
+                if let Err(e) = result {
+                    log::warn!("failed to serve a request from {address}: {e}");
+                }
+                drop(permit);
+            }
+            .instrument(span),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::PeerPattern;
+    use simperby_test_suite::*;
+
+    fn peer(port: u16) -> Peer {
+        Peer {
+            public_key: PublicKey::zero(),
+            name: "server".to_owned(),
+            address: "127.0.0.1:1".parse().unwrap(),
+            addresses: Vec::new(),
+            ports: vec![("sum".to_owned(), port)].into_iter().collect(),
+            metadata: Default::default(),
+            recently_seen_timestamp: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn request_response() {
+        setup_test();
+        let port = dispense_port();
+        let rpc = TcpRpc::new(RpcConfig {
+            timeout_ms: 500,
+            ..Default::default()
+        });
+        let server = rpc
+            .serve(port, |numbers: Vec<u64>| async move {
+                if numbers.is_empty() {
+                    return Err("nothing to sum".to_owned());
+                }
+                if numbers.contains(&0) {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+                Ok(numbers.iter().sum::<u64>())
+            })
+            .await
+            .unwrap();
+        let peer = peer(port);
+
+        let sum: u64 = rpc.request(&peer, "sum", vec![1u64, 2, 3]).await.unwrap();
+        assert_eq!(sum, 6);
+        let error = rpc
+            .request::<_, u64>(&peer, "sum", Vec::<u64>::new())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("nothing to sum"));
+        let error = rpc
+            .request::<_, u64>(&peer, "sum", vec![0u64])
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("timed out"));
+        assert!(rpc
+            .request::<_, u64>(&peer, "unknown", vec![1u64])
+            .await
+            .is_err());
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn remote_address() {
+        setup_test();
+        let port = dispense_port();
+        let rpc = TcpRpc::new(Default::default());
+        let server = rpc
+            .serve(port, |_: ()| async move {
+                super::remote_address().ok_or_else(|| "unknown".to_owned())
+            })
+            .await
+            .unwrap();
+        let address: SocketAddr = rpc.request(&peer(port), "sum", ()).await.unwrap();
+        assert_eq!(address.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(super::remote_address(), None);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn multiple_addresses() {
+        setup_test();
+        let port = dispense_port();
+        let rpc = TcpRpc::new(RpcConfig {
+            listen_addresses: vec!["127.0.0.2".parse().unwrap(), "127.0.0.3".parse().unwrap()],
+            ..Default::default()
+        });
+        let server = rpc
+            .serve(port, |x: u64| async move { Ok(x + 1) })
+            .await
+            .unwrap();
+
+        // Unreachable at `address`, but reachable at the other ones.
+        let mut peer = peer(port);
+        assert!(rpc.request::<_, u64>(&peer, "sum", 1u64).await.is_err());
+        peer.addresses = vec![
+            PeerAddress::Dns("nonexistent.invalid".to_owned()),
+            PeerAddress::Ip("127.0.0.3".parse().unwrap()),
+        ];
+        let result: u64 = rpc.request(&peer, "sum", 1u64).await.unwrap();
+        assert_eq!(result, 2);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn pre_shared_key() {
+        setup_test();
+        let port = dispense_port();
+        let rpc = |key: Option<&str>| {
+            TcpRpc::new(RpcConfig {
+                timeout_ms: 1000,
+                ..Default::default()
+            })
+            .with_pre_shared_key(key.map(PreSharedKey::from_passphrase))
+        };
+        let server = rpc(Some("private"))
+            .serve(port, |x: u64| async move { Ok(x + 1) })
+            .await
+            .unwrap();
+        let peer = peer(port);
+
+        let result: u64 = rpc(Some("private"))
+            .request(&peer, "sum", 1u64)
+            .await
+            .unwrap();
+        assert_eq!(result, 2);
+        let error = rpc(Some("other"))
+            .request::<_, u64>(&peer, "sum", 1u64)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("pre-shared key mismatch"));
+        let error = rpc(None)
+            .request::<_, u64>(&peer, "sum", 1u64)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("requires a pre-shared key"));
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn compression() {
+        setup_test();
+        let port = dispense_port();
+        let rpc = |algorithms: &[Compression]| {
+            TcpRpc::new(RpcConfig {
+                timeout_ms: 1000,
+                compression: CompressionConfig {
+                    algorithms: algorithms.to_vec(),
+                    threshold_bytes: 100,
+                },
+                ..Default::default()
+            })
+            .with_pre_shared_key(Some(PreSharedKey::from_passphrase("private")))
+        };
+        let server = rpc(&[Compression::Lz4, Compression::Zstd])
+            .serve(port, |n: u64| async move { Ok(vec![7u8; n as usize]) })
+            .await
+            .unwrap();
+        let peer = peer(port);
+
+        for algorithms in [&[Compression::Zstd][..], &[Compression::Lz4], &[]] {
+            for n in [10u64, 100_000] {
+                let result: Vec<u8> = rpc(algorithms).request(&peer, "sum", n).await.unwrap();
+                assert_eq!(result, vec![7u8; n as usize]);
+            }
+        }
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn peer_filter() {
+        setup_test();
+        let port = dispense_port();
+        let rpc = |denied: Vec<PeerPattern>| {
+            TcpRpc::new(RpcConfig {
+                timeout_ms: 1000,
+                ..Default::default()
+            })
+            .with_peer_filter(PeerFilter {
+                denied,
+                allowed: Vec::new(),
+            })
+        };
+        let peer = peer(port);
+        let error = rpc(vec![PeerPattern::Key(PublicKey::zero())])
+            .request::<_, u64>(&peer, "sum", 1u64)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("denied"));
+
+        let server = rpc(vec![PeerPattern::Cidr("127.0.0.0/8".parse().unwrap())])
+            .serve(port, |x: u64| async move { Ok(x + 1) })
+            .await
+            .unwrap();
+        assert!(rpc(Vec::new())
+            .request::<_, u64>(&peer, "sum", 1u64)
+            .await
+            .is_err());
+        server.abort();
+    }
+}
@@ -0,0 +1,174 @@
+//! Scoring of the peers by their behavior.
+//!
+//! Every misbehavior (e.g., sending a message with an invalid signature) lowers the score of the peer,
+//! and a peer whose score falls below the threshold is banned for a while.
+//! The banned peers are neither fetched from nor broadcasted to.
+use super::*;
+use parking_lot::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Misbehavior {
+    /// A reply that doesn't decode as the response to the request.
+    InvalidMessage,
+    /// A message whose signature is invalid.
+    InvalidSignature,
+    /// Messages beyond the rate limit.
+    Spam,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ScoringConfig {
+    pub invalid_message_penalty: i64,
+    pub invalid_signature_penalty: i64,
+    pub spam_penalty: i64,
+    /// A peer is banned once its score falls below this.
+    pub ban_threshold: i64,
+    pub ban_duration_ms: u64,
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        Self {
+            invalid_message_penalty: 10,
+            invalid_signature_penalty: 50,
+            spam_penalty: 5,
+            ban_threshold: -100,
+            ban_duration_ms: 60 * 60 * 1000,
+        }
+    }
+}
+
+impl ScoringConfig {
+    fn penalty(&self, misbehavior: Misbehavior) -> i64 {
+        match misbehavior {
+            Misbehavior::InvalidMessage => self.invalid_message_penalty,
+            Misbehavior::InvalidSignature => self.invalid_signature_penalty,
+            Misbehavior::Spam => self.spam_penalty,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PeerScore {
+    /// Starts from zero and only decreases, until the peer is unbanned.
+    pub score: i64,
+    pub invalid_messages: u64,
+    pub invalid_signatures: u64,
+    pub spam: u64,
+    pub banned_until: Option<Timestamp>,
+}
+
+/// The scores of the peers, which can be shared by multiple components of a node.
+#[derive(Debug, Default)]
+pub struct PeerScoreBoard {
+    config: ScoringConfig,
+    scores: Mutex<HashMap<PublicKey, PeerScore>>,
+}
+
+impl PeerScoreBoard {
+    pub fn new(config: ScoringConfig) -> Self {
+        Self {
+            config,
+            scores: Default::default(),
+        }
+    }
+
+    /// Lowers the score of the peer, banning it if the score falls below the threshold.
+    ///
+    /// Returns whether the peer is banned.
+    pub fn report(&self, peer: &PublicKey, misbehavior: Misbehavior, now: Timestamp) -> bool {
+        let mut scores = self.scores.lock();
+        let score = scores.entry(peer.clone()).or_default();
+        score.score -= self.config.penalty(misbehavior);
+        match misbehavior {
+            Misbehavior::InvalidMessage => score.invalid_messages += 1,
+            Misbehavior::InvalidSignature => score.invalid_signatures += 1,
+            Misbehavior::Spam => score.spam += 1,
+        }
+        if score.score < self.config.ban_threshold && score.banned_until.is_none() {
+            log::warn!(
+                "banning peer {peer} for misbehavior (score {})",
+                score.score
+            );
+            score.banned_until = Some(now + self.config.ban_duration_ms as Timestamp);
+        }
+        score.banned_until.is_some()
+    }
+
+    /// Bans the peer for the given duration, regardless of its score.
+    pub fn ban_peer(&self, peer: &PublicKey, duration_ms: u64, now: Timestamp) {
+        self.scores
+            .lock()
+            .entry(peer.clone())
+            .or_default()
+            .banned_until = Some(now + duration_ms as Timestamp);
+    }
+
+    /// Lifts the ban of the peer, resetting its score.
+    pub fn unban_peer(&self, peer: &PublicKey) {
+        self.scores.lock().remove(peer);
+    }
+
+    /// Checks whether the peer is banned, lifting the ban if it has expired.
+    pub fn is_banned(&self, peer: &PublicKey, now: Timestamp) -> bool {
+        let mut scores = self.scores.lock();
+        match scores.get(peer).and_then(|x| x.banned_until) {
+            Some(until) if until <= now => {
+                scores.remove(peer);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Returns the peers that are not banned.
+    pub fn filter_banned(&self, peers: Vec<Peer>, now: Timestamp) -> Vec<Peer> {
+        peers
+            .into_iter()
+            .filter(|peer| !self.is_banned(&peer.public_key, now))
+            .collect()
+    }
+
+    /// Returns the current scores of the peers that have misbehaved or been banned.
+    pub fn scores(&self) -> HashMap<PublicKey, PeerScore> {
+        self.scores.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    #[test]
+    fn ban_and_unban() {
+        setup_test();
+        let board = PeerScoreBoard::new(ScoringConfig {
+            ban_duration_ms: 1000,
+            ..Default::default()
+        });
+        let peer = generate_keypair("peer").0;
+        let other = generate_keypair("other").0;
+
+        assert!(!board.report(&peer, Misbehavior::InvalidSignature, 0));
+        assert!(!board.report(&peer, Misbehavior::InvalidSignature, 0));
+        assert!(!board.report(&other, Misbehavior::Spam, 0));
+        assert!(board.report(&peer, Misbehavior::InvalidMessage, 10));
+        assert!(board.is_banned(&peer, 500));
+        assert!(!board.is_banned(&other, 500));
+        assert_eq!(board.scores()[&peer].score, -110);
+        assert_eq!(board.scores()[&peer].invalid_signatures, 2);
+        assert_eq!(board.scores()[&peer].banned_until, Some(1010));
+
+        // The ban expires, with the score reset.
+        assert!(!board.is_banned(&peer, 1010));
+        assert!(!board.scores().contains_key(&peer));
+
+        board.ban_peer(&other, 100, 0);
+        assert!(board.is_banned(&other, 50));
+        board.unban_peer(&other);
+        assert!(!board.is_banned(&other, 50));
+        assert!(board.scores().is_empty());
+    }
+}
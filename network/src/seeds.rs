@@ -0,0 +1,241 @@
+//! Bootstrapping from DNS seeds.
+//!
+//! A DNS seed (`NetworkConfig::dns_seeds`) is a long-lived `host:port` (e.g., `seed.mynet.example:2000`)
+//! whose A records point to the nodes serving their peer records on the port.
+//! An address alone doesn't tell the key or the ports of a node, so each resolved address is asked
+//! for its [`SignedPeerRecord`], which is verified before being merged into the known peers.
+//!
+//! Only the A records are resolved, since the system resolver doesn't provide the TXT records.
+use super::*;
+use crate::access::PeerFilter;
+use crate::mux::MUX_PROTOCOL;
+use eyre::eyre;
+use std::net::SocketAddr;
+use tracing::Instrument;
+
+/// The protocol identifier of serving the peer record, in `Peer::ports`.
+pub const PEER_RECORD_PROTOCOL: &str = "peer-record";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SeedConfig {
+    /// How often the seeds are re-resolved, in milliseconds.
+    pub resolve_interval_ms: u64,
+}
+
+impl Default for SeedConfig {
+    fn default() -> Self {
+        Self {
+            resolve_interval_ms: 5 * 60 * 1000,
+        }
+    }
+}
+
+/// Resolves the seed into the (IPv4) addresses of the seed nodes.
+pub async fn resolve_seed(seed: &str) -> Result<Vec<SocketAddrV4>, Error> {
+    let mut addresses = Vec::new();
+    for address in tokio::net::lookup_host(seed).await? {
+        if let SocketAddr::V4(address) = address {
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+    }
+    if addresses.is_empty() {
+        return Err(eyre!("seed {seed} has no IPv4 address"));
+    }
+    Ok(addresses)
+}
+
+/// Serves the peer record of this node to those bootstrapping from it.
+pub async fn serve_peer_record<P: RpcPrimitive>(
+    rpc: &P,
+    port: u16,
+    record: SignedPeerRecord,
+) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error> {
+    rpc.serve_protocol(PEER_RECORD_PROTOCOL, port, move |_: ()| {
+        let record = record.clone();
+        async move { Ok(record) }
+    })
+    .await
+}
+
+/// Fetches the peer record served at the address, merging it into the known peers
+/// if it's valid, for the network, of a member and not refused by the filter.
+pub(crate) async fn merge_peer_record<P: RpcPrimitive>(
+    rpc: &P,
+    address: SocketAddrV4,
+    network_id: &str,
+    members: &[PublicKey],
+    filter: &PeerFilter,
+    known_peers: &SharedKnownPeers,
+) -> Result<(), Error> {
+    filter.check_address(&IpAddr::V4(*address.ip()))?;
+    let record = fetch_peer_record(rpc, address).await?;
+    record.check_network(network_id)?;
+    filter.check_peer(&record.clone().into_peer()?)?;
+    tracing::debug!(%address, peer = %record.record.public_key, "merging a peer record");
+    known_peers.add_signed(record, members).await
+}
+
+async fn fetch_peer_record<P: RpcPrimitive>(
+    rpc: &P,
+    address: SocketAddrV4,
+) -> Result<SignedPeerRecord, Error> {
+    // The key is unknown until the record arrives,
+    // and so is whether the port is multiplexed.
+    let seed_node = Peer {
+        public_key: PublicKey::zero(),
+        name: address.to_string(),
+        address,
+        addresses: Vec::new(),
+        ports: [PEER_RECORD_PROTOCOL, MUX_PROTOCOL]
+            .into_iter()
+            .map(|x| (x.to_owned(), address.port()))
+            .collect(),
+        metadata: Default::default(),
+        recently_seen_timestamp: 0,
+    };
+    rpc.request(&seed_node, PEER_RECORD_PROTOCOL, ()).await
+}
+
+/// Resolves all the seeds in the config and merges the peer records of the seed nodes
+/// into the known peers, returning the number of the records merged.
+///
+/// The records of non-members and the ones that fail to verify are discarded.
+pub async fn bootstrap_from_seeds<P: RpcPrimitive>(
+    rpc: &P,
+    network_config: &NetworkConfig,
+    known_peers: &SharedKnownPeers,
+) -> usize {
+    let mut merged = 0;
+    let filter = network_config.peer_filter();
+    for seed in &network_config.dns_seeds {
+        let addresses = match resolve_seed(seed).await {
+            Ok(x) => x,
+            Err(e) => {
+                log::warn!("failed to resolve seed {seed}: {e}");
+                continue;
+            }
+        };
+        tracing::debug!(%seed, addresses = addresses.len(), "resolved a seed");
+        for address in addresses {
+            let network_id = &network_config.network_id;
+            let members = &network_config.members;
+            match merge_peer_record(rpc, address, network_id, members, &filter, known_peers).await {
+                Ok(()) => merged += 1,
+                Err(e) => log::warn!("failed to bootstrap from {address} of seed {seed}: {e}"),
+            }
+        }
+    }
+    merged
+}
+
+/// Spawns a task that periodically re-resolves the seeds, merging the results into the known peers.
+///
+/// The records are checked against the current `members` in each round.
+pub fn spawn_seed_task<P: RpcPrimitive>(
+    rpc: Arc<P>,
+    mut network_config: NetworkConfig,
+    members: SharedMembers,
+    known_peers: SharedKnownPeers,
+    config: SeedConfig,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_millis(config.resolve_interval_ms));
+        for round in 0u64.. {
+            interval.tick().await;
+            network_config.members = members.read();
+            let span = tracing::info_span!("seed_round", round);
+            let merged = bootstrap_from_seeds(rpc.as_ref(), &network_config, &known_peers)
+                .instrument(span.clone())
+                .await;
+            span.in_scope(|| tracing::debug!(merged, "merged the peer records from the DNS seeds"));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::PeerPattern;
+    use crate::rpc::{RpcConfig, TcpRpc};
+    use simperby_test_suite::*;
+
+    #[tokio::test]
+    async fn bootstrap() {
+        setup_test();
+        let (public_key, private_key) = generate_keypair_random();
+        let (stranger, _) = generate_keypair_random();
+        let port = dispense_port();
+        let network_config = NetworkConfig {
+            network_id: "test".to_owned(),
+            ports: vec![("dms-test".to_owned(), 1234)].into_iter().collect(),
+            members: vec![public_key.clone()],
+            public_key,
+            private_key: Some(private_key),
+            dns_seeds: vec![
+                format!("localhost:{port}"),
+                "nonexistent.invalid:1".to_owned(),
+            ],
+            enable_mdns: false,
+            pre_shared_key: None,
+            proxy: None,
+            denied_peers: Vec::new(),
+            allowed_peers: Vec::new(),
+        };
+        let record = SignedPeerRecord::new(
+            &network_config,
+            "seed".to_owned(),
+            SocketAddrV4::new("127.0.0.1".parse().unwrap(), port),
+            Vec::new(),
+            Default::default(),
+            10,
+        )
+        .unwrap();
+        let rpc = TcpRpc::new(RpcConfig {
+            timeout_ms: 1000,
+            ..Default::default()
+        });
+        let server = serve_peer_record(&rpc, port, record).await.unwrap();
+
+        let known_peers = SharedKnownPeers::new_static(Vec::new());
+        assert_eq!(
+            bootstrap_from_seeds(&rpc, &network_config, &known_peers).await,
+            1
+        );
+        let peers = known_peers.read().await;
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].public_key, network_config.public_key);
+        assert_eq!(peers[0].ports["dms-test"], 1234);
+
+        // Denied.
+        for pattern in [
+            PeerPattern::Key(network_config.public_key.clone()),
+            PeerPattern::Cidr("127.0.0.0/8".parse().unwrap()),
+        ] {
+            let known_peers = SharedKnownPeers::new_static(Vec::new());
+            let network_config = NetworkConfig {
+                denied_peers: vec![pattern],
+                ..network_config.clone()
+            };
+            assert_eq!(
+                bootstrap_from_seeds(&rpc, &network_config, &known_peers).await,
+                0
+            );
+        }
+
+        // Not a member.
+        let known_peers = SharedKnownPeers::new_static(Vec::new());
+        let network_config = NetworkConfig {
+            members: vec![stranger],
+            ..network_config
+        };
+        assert_eq!(
+            bootstrap_from_seeds(&rpc, &network_config, &known_peers).await,
+            0
+        );
+        assert!(known_peers.read().await.is_empty());
+        server.abort();
+    }
+}
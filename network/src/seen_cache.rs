@@ -0,0 +1,233 @@
+//! Suppressing the duplicate broadcasts.
+//!
+//! A broadcast may arrive many times, through different peers or replayed by a rebroadcast storm.
+//! A [`SeenCache`] remembers the tokens of the broadcasts received recently, so that the duplicates
+//! are dropped before being processed again. It keeps at most `SeenCacheConfig::capacity` tokens,
+//! evicting the least recently seen one, and forgets a token after `SeenCacheConfig::ttl_ms`.
+//!
+//! With `SeenCacheConfig::path`, the tokens are saved to the file and loaded back on the start,
+//! so that a restarted node doesn't take the broadcasts that it has just seen as new.
+use super::*;
+use crate::chunking::BroadcastToken;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SeenCacheConfig {
+    /// The maximum number of the tokens kept.
+    pub capacity: usize,
+    /// How long a token is kept after it was last seen, in milliseconds.
+    pub ttl_ms: u64,
+    /// The file that the tokens are saved to, if they're kept across the restarts.
+    pub path: Option<String>,
+    /// How often the tokens are saved to the file at most, in milliseconds.
+    pub save_interval_ms: u64,
+}
+
+impl Default for SeenCacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 64 * 1024,
+            ttl_ms: 10 * 60 * 1000,
+            path: None,
+            save_interval_ms: 10 * 1000,
+        }
+    }
+}
+
+/// The tokens of the broadcasts seen recently.
+#[derive(Debug)]
+pub struct SeenCache {
+    config: SeenCacheConfig,
+    /// When each token was last seen, with its position in `order`.
+    seen: HashMap<BroadcastToken, (Timestamp, u64)>,
+    /// The tokens from the least recently seen.
+    order: BTreeMap<u64, BroadcastToken>,
+    next_position: u64,
+    /// Whether there are changes not saved to the file yet.
+    dirty: bool,
+    last_saved: Timestamp,
+}
+
+impl SeenCache {
+    pub fn new(config: SeenCacheConfig) -> Self {
+        Self {
+            config,
+            seen: HashMap::new(),
+            order: BTreeMap::new(),
+            next_position: 0,
+            dirty: false,
+            last_saved: 0,
+        }
+    }
+
+    /// Creates a cache with the tokens saved in `SeenCacheConfig::path`, if any,
+    /// dropping the ones expired at `now`.
+    pub async fn load(config: SeenCacheConfig, now: Timestamp) -> Result<Self, Error> {
+        let path = config.path.clone();
+        let mut cache = Self::new(config);
+        let path = match path {
+            Some(x) => x,
+            None => return Ok(cache),
+        };
+        if tokio::fs::metadata(&path).await.is_err() {
+            return Ok(cache);
+        }
+        let tokens: Vec<(BroadcastToken, Timestamp)> =
+            serde_spb::from_str(&tokio::fs::read_to_string(&path).await?)?;
+        for (token, timestamp) in tokens {
+            cache.touch(token, timestamp);
+        }
+        cache.evict(now);
+        cache.dirty = false;
+        cache.last_saved = now;
+        Ok(cache)
+    }
+
+    /// Saves the tokens to `SeenCacheConfig::path`, if any.
+    pub async fn save(&mut self, now: Timestamp) -> Result<(), Error> {
+        let path = match &self.config.path {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let tokens = self
+            .order
+            .values()
+            .map(|token| (*token, self.seen[token].0))
+            .collect::<Vec<_>>();
+        // Write to a temporary file first so that a crash never leaves a truncated file.
+        let temp_path = format!("{path}.tmp");
+        tokio::fs::write(&temp_path, serde_spb::to_string(&tokens)?).await?;
+        tokio::fs::rename(&temp_path, path).await?;
+        self.dirty = false;
+        self.last_saved = now;
+        Ok(())
+    }
+
+    /// Saves the tokens if they've changed and `SeenCacheConfig::save_interval_ms` has passed.
+    pub async fn save_if_due(&mut self, now: Timestamp) -> Result<(), Error> {
+        let interval = self.config.save_interval_ms as Timestamp;
+        if self.dirty && now.saturating_sub(self.last_saved) >= interval {
+            self.save(now).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether the token has been seen and not expired at `now`.
+    pub fn contains(&self, token: &BroadcastToken, now: Timestamp) -> bool {
+        self.seen
+            .get(token)
+            .map_or(false, |(timestamp, _)| !self.is_expired(*timestamp, now))
+    }
+
+    /// Marks the token as seen at `now`, returning whether it's new (i.e., not a duplicate).
+    pub fn insert(&mut self, token: BroadcastToken, now: Timestamp) -> bool {
+        let new = !self.contains(&token, now);
+        self.touch(token, now);
+        self.evict(now);
+        self.dirty = true;
+        new
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    fn is_expired(&self, timestamp: Timestamp, now: Timestamp) -> bool {
+        now.saturating_sub(timestamp) > self.config.ttl_ms as Timestamp
+    }
+
+    fn touch(&mut self, token: BroadcastToken, timestamp: Timestamp) {
+        if let Some((_, position)) = self.seen.get(&token) {
+            self.order.remove(position);
+        }
+        let position = self.next_position;
+        self.next_position += 1;
+        self.order.insert(position, token);
+        self.seen.insert(token, (timestamp, position));
+    }
+
+    /// Removes the expired tokens and the least recently seen ones beyond the capacity.
+    fn evict(&mut self, now: Timestamp) {
+        while let Some((&position, token)) = self.order.iter().next() {
+            let timestamp = self.seen[token].0;
+            if self.seen.len() <= self.config.capacity && !self.is_expired(timestamp, now) {
+                break;
+            }
+            let token = *token;
+            self.order.remove(&position);
+            self.seen.remove(&token);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    fn token(i: u8) -> BroadcastToken {
+        Hash256::hash([i])
+    }
+
+    #[test]
+    fn capacity_and_expiry() {
+        setup_test();
+        let mut cache = SeenCache::new(SeenCacheConfig {
+            capacity: 3,
+            ttl_ms: 100,
+            ..Default::default()
+        });
+        for i in 0..3 {
+            assert!(cache.insert(token(i), 0));
+        }
+        assert!(!cache.insert(token(0), 10));
+        // `1` is the least recently seen.
+        assert!(cache.insert(token(3), 10));
+        assert_eq!(cache.len(), 3);
+        assert!(!cache.contains(&token(1), 10));
+        assert!(cache.contains(&token(0), 10));
+
+        // `2` expires first, while `0` has been seen again.
+        assert!(!cache.contains(&token(2), 101));
+        assert!(cache.contains(&token(0), 101));
+        assert!(cache.insert(token(2), 101));
+        assert_eq!(cache.len(), 3);
+        assert!(cache.insert(token(4), 200));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn persistence() {
+        setup_test();
+        let path = std::env::temp_dir().join(format!("simperby-seen-{}", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let _ = tokio::fs::remove_file(&path).await;
+        let config = SeenCacheConfig {
+            ttl_ms: 100,
+            path: Some(path.clone()),
+            save_interval_ms: 50,
+            ..Default::default()
+        };
+
+        let mut cache = SeenCache::load(config.clone(), 0).await.unwrap();
+        assert!(cache.is_empty());
+        cache.insert(token(0), 0);
+        cache.insert(token(1), 20);
+        cache.save_if_due(20).await.unwrap();
+        assert!(SeenCache::load(config.clone(), 20)
+            .await
+            .unwrap()
+            .is_empty());
+        cache.save_if_due(50).await.unwrap();
+
+        // A restarted node still knows them, until they expire.
+        let mut restarted = SeenCache::load(config.clone(), 110).await.unwrap();
+        assert!(!restarted.insert(token(1), 110));
+        assert!(restarted.insert(token(0), 110));
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
@@ -0,0 +1,754 @@
+//! A deterministic discrete-event network simulator.
+//!
+//! Unlike the tests running on real sockets, the simulator runs the nodes on a simulated clock
+//! over an in-memory network, so that a scenario of hundreds of nodes finishes in seconds.
+//!
+//! Every source of randomness (the latencies, the message drops, and the randomness given to the nodes)
+//! is derived from a single seed, so a failing scenario can be reproduced exactly by rerunning it with the same seed.
+//!
+//! Besides the lossy conditions, a scenario can inject failures: crashing and pausing nodes,
+//! dropping or delaying specific links, and partitioning the network.
+//!
+//! Available in the tests of this crate, or elsewhere with the `testing` feature.
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+/// The index of a node in a simulation.
+pub type NodeIndex = usize;
+/// A simulated time in milliseconds.
+pub type SimulatedTime = u64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkConditions {
+    pub min_latency_ms: u64,
+    pub max_latency_ms: u64,
+    /// The probability that a message is lost.
+    pub drop_probability: f64,
+}
+
+impl Default for NetworkConditions {
+    fn default() -> Self {
+        Self {
+            min_latency_ms: 10,
+            max_latency_ms: 100,
+            drop_probability: 0.0,
+        }
+    }
+}
+
+/// A node logic driven by the simulator.
+///
+/// The logic must not read the real clock or use its own randomness;
+/// it should use `Context::now()` and `Context::rng()` instead to stay deterministic.
+pub trait SimulatedNode {
+    type Message: Clone;
+
+    /// Called when the node starts (or restarts).
+    fn on_start(&mut self, _context: &mut Context<Self::Message>) {}
+
+    fn on_message(
+        &mut self,
+        context: &mut Context<Self::Message>,
+        from: NodeIndex,
+        message: Self::Message,
+    );
+
+    /// Called when a timer set by `Context::set_timer()` expires.
+    fn on_timer(&mut self, _context: &mut Context<Self::Message>, _timer: u64) {}
+}
+
+/// A failure injected into the (directed) link from a node to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkFault {
+    /// Every message over the link is lost, including the ones in flight.
+    Drop,
+    /// Every message sent over the link takes the additional milliseconds.
+    Delay(u64),
+}
+
+enum Action<M> {
+    Send { to: NodeIndex, message: M },
+    SetTimer { delay: u64, timer: u64 },
+}
+
+/// The interface given to a node while it handles an event.
+pub struct Context<'a, M> {
+    now: SimulatedTime,
+    this_node: NodeIndex,
+    node_count: usize,
+    rng: &'a mut StdRng,
+    actions: Vec<Action<M>>,
+}
+
+impl<'a, M: Clone> Context<'a, M> {
+    pub fn now(&self) -> SimulatedTime {
+        self.now
+    }
+
+    pub fn this_node(&self) -> NodeIndex {
+        self.this_node
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.node_count
+    }
+
+    /// Returns the seeded random number generator of the simulation.
+    pub fn rng(&mut self) -> &mut StdRng {
+        self.rng
+    }
+
+    pub fn send(&mut self, to: NodeIndex, message: M) {
+        self.actions.push(Action::Send { to, message });
+    }
+
+    /// Sends the message to every other node.
+    pub fn broadcast(&mut self, message: M) {
+        let this_node = self.this_node;
+        for to in (0..self.node_count).filter(|x| *x != this_node) {
+            self.send(to, message.clone());
+        }
+    }
+
+    /// Sets a timer that expires after `delay` milliseconds, calling `on_timer()` with the given `timer`.
+    pub fn set_timer(&mut self, delay: u64, timer: u64) {
+        self.actions.push(Action::SetTimer { delay, timer });
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimulationStats {
+    pub delivered_messages: u64,
+    pub dropped_messages: u64,
+    pub fired_timers: u64,
+}
+
+enum EventKind<M> {
+    Deliver { from: NodeIndex, message: M },
+    Timer(u64),
+}
+
+struct Event<M> {
+    time: SimulatedTime,
+    /// The order of scheduling, which breaks ties between the events of the same time.
+    sequence: u64,
+    node: NodeIndex,
+    /// The incarnation of the node that the event is for; events for a crashed incarnation are discarded.
+    incarnation: u64,
+    kind: EventKind<M>,
+}
+
+impl<M> PartialEq for Event<M> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.time, self.sequence) == (other.time, other.sequence)
+    }
+}
+
+impl<M> Eq for Event<M> {}
+
+impl<M> PartialOrd for Event<M> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<M> Ord for Event<M> {
+    /// Reversed, so that `BinaryHeap` pops the earliest event first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.time, other.sequence).cmp(&(self.time, self.sequence))
+    }
+}
+
+pub struct Simulator<N: SimulatedNode> {
+    nodes: Vec<N>,
+    crashed: Vec<bool>,
+    incarnations: Vec<u64>,
+    /// The nodes that are paused; the events for them are held until they resume.
+    paused: Vec<bool>,
+    held: Vec<Vec<Event<N::Message>>>,
+    /// The partition group of each node. Messages between different groups are lost.
+    groups: Vec<usize>,
+    links: HashMap<(NodeIndex, NodeIndex), LinkFault>,
+    conditions: NetworkConditions,
+    now: SimulatedTime,
+    sequence: u64,
+    queue: BinaryHeap<Event<N::Message>>,
+    rng: StdRng,
+    stats: SimulationStats,
+    started: bool,
+}
+
+impl<N: SimulatedNode> Simulator<N> {
+    pub fn new(nodes: Vec<N>, conditions: NetworkConditions, seed: u64) -> Self {
+        let n = nodes.len();
+        Self {
+            nodes,
+            crashed: vec![false; n],
+            incarnations: vec![0; n],
+            paused: vec![false; n],
+            held: (0..n).map(|_| Vec::new()).collect(),
+            groups: vec![0; n],
+            links: HashMap::new(),
+            conditions,
+            now: 0,
+            sequence: 0,
+            queue: BinaryHeap::new(),
+            rng: StdRng::seed_from_u64(seed),
+            stats: SimulationStats::default(),
+            started: false,
+        }
+    }
+
+    pub fn nodes(&self) -> &[N] {
+        &self.nodes
+    }
+
+    pub fn node(&self, node: NodeIndex) -> &N {
+        &self.nodes[node]
+    }
+
+    pub fn now(&self) -> SimulatedTime {
+        self.now
+    }
+
+    pub fn stats(&self) -> &SimulationStats {
+        &self.stats
+    }
+
+    pub fn is_crashed(&self, node: NodeIndex) -> bool {
+        self.crashed[node]
+    }
+
+    pub fn set_conditions(&mut self, conditions: NetworkConditions) {
+        self.conditions = conditions;
+    }
+
+    /// Splits the network into the given groups. Nodes not listed in any group form their own group.
+    ///
+    /// Messages in flight between different groups are lost as well.
+    pub fn partition(&mut self, groups: &[Vec<NodeIndex>]) {
+        self.groups = vec![0; self.nodes.len()];
+        for (i, group) in groups.iter().enumerate() {
+            for node in group {
+                self.groups[*node] = i + 1;
+            }
+        }
+    }
+
+    pub fn heal(&mut self) {
+        self.groups = vec![0; self.nodes.len()];
+    }
+
+    /// Injects the fault into the link from `from` to `to`, replacing the previous one.
+    pub fn set_link_fault(&mut self, from: NodeIndex, to: NodeIndex, fault: LinkFault) {
+        self.links.insert((from, to), fault);
+    }
+
+    /// Removes the fault from the link from `from` to `to`.
+    pub fn restore_link(&mut self, from: NodeIndex, to: NodeIndex) {
+        self.links.remove(&(from, to));
+    }
+
+    pub fn restore_all_links(&mut self) {
+        self.links.clear();
+    }
+
+    pub fn is_paused(&self, node: NodeIndex) -> bool {
+        self.paused[node]
+    }
+
+    /// Freezes the node (e.g., a long GC pause or a suspended VM) without losing its state:
+    /// the messages to it and its timers are held until it resumes.
+    pub fn pause(&mut self, node: NodeIndex) {
+        self.paused[node] = true;
+    }
+
+    /// Resumes the paused node, which then handles the held events in their order.
+    pub fn resume(&mut self, node: NodeIndex) {
+        self.paused[node] = false;
+        for event in std::mem::take(&mut self.held[node]) {
+            self.sequence += 1;
+            self.queue.push(Event {
+                time: self.now,
+                sequence: self.sequence,
+                ..event
+            });
+        }
+    }
+
+    /// Stops the node; all the messages to it and its pending timers are discarded.
+    pub fn crash(&mut self, node: NodeIndex) {
+        self.crashed[node] = true;
+        self.incarnations[node] += 1;
+        self.paused[node] = false;
+        self.held[node].clear();
+    }
+
+    /// Restarts the crashed node with the given state (e.g., the one recovered from its storage).
+    pub fn restart(&mut self, node: NodeIndex, state: N) {
+        self.nodes[node] = state;
+        self.crashed[node] = false;
+        if self.started {
+            self.dispatch(node, |n, context| n.on_start(context));
+        }
+    }
+
+    fn schedule(&mut self, time: SimulatedTime, node: NodeIndex, kind: EventKind<N::Message>) {
+        self.sequence += 1;
+        self.queue.push(Event {
+            time,
+            sequence: self.sequence,
+            node,
+            incarnation: self.incarnations[node],
+            kind,
+        });
+    }
+
+    fn dispatch(&mut self, node: NodeIndex, f: impl FnOnce(&mut N, &mut Context<N::Message>)) {
+        let mut context = Context {
+            now: self.now,
+            this_node: node,
+            node_count: self.nodes.len(),
+            rng: &mut self.rng,
+            actions: Vec::new(),
+        };
+        f(&mut self.nodes[node], &mut context);
+        for action in context.actions {
+            match action {
+                Action::Send { to, message } => {
+                    if self.rng.gen_bool(self.conditions.drop_probability) {
+                        self.stats.dropped_messages += 1;
+                        continue;
+                    }
+                    let mut latency = self
+                        .rng
+                        .gen_range(self.conditions.min_latency_ms..=self.conditions.max_latency_ms);
+                    if let Some(LinkFault::Delay(delay)) = self.links.get(&(node, to)) {
+                        latency += delay;
+                    }
+                    self.schedule(
+                        self.now + latency,
+                        to,
+                        EventKind::Deliver {
+                            from: node,
+                            message,
+                        },
+                    );
+                }
+                Action::SetTimer { delay, timer } => {
+                    self.schedule(self.now + delay, node, EventKind::Timer(timer))
+                }
+            }
+        }
+    }
+
+    /// Starts all the (non-crashed) nodes. It is called implicitly by the first step.
+    pub fn start(&mut self) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        for node in 0..self.nodes.len() {
+            if !self.crashed[node] {
+                self.dispatch(node, |n, context| n.on_start(context));
+            }
+        }
+    }
+
+    /// Processes the next event, returning `false` if there is none.
+    pub fn step(&mut self) -> bool {
+        self.start();
+        let event = match self.queue.pop() {
+            Some(event) => event,
+            None => return false,
+        };
+        self.now = event.time;
+        let node = event.node;
+        let alive = !self.crashed[node] && event.incarnation == self.incarnations[node];
+        if alive && self.paused[node] {
+            self.held[node].push(event);
+            return true;
+        }
+        match event.kind {
+            EventKind::Deliver { from, message } => {
+                if !alive
+                    || self.groups[from] != self.groups[node]
+                    || self.links.get(&(from, node)) == Some(&LinkFault::Drop)
+                {
+                    self.stats.dropped_messages += 1;
+                } else {
+                    self.stats.delivered_messages += 1;
+                    self.dispatch(node, |n, context| n.on_message(context, from, message));
+                }
+            }
+            EventKind::Timer(timer) => {
+                if alive {
+                    self.stats.fired_timers += 1;
+                    self.dispatch(node, |n, context| n.on_timer(context, timer));
+                }
+            }
+        }
+        true
+    }
+
+    /// Processes all the events up to the given time, and then advances the clock to it.
+    pub fn run_until(&mut self, time: SimulatedTime) {
+        self.start();
+        while self.queue.peek().map_or(false, |e| e.time <= time) {
+            self.step();
+        }
+        self.now = self.now.max(time);
+    }
+
+    /// Runs until the condition holds, returning `false` if it doesn't
+    /// by the given time or there is no more event.
+    pub fn run_until_condition(
+        &mut self,
+        mut condition: impl FnMut(&[N]) -> bool,
+        max_time: SimulatedTime,
+    ) -> bool {
+        self.start();
+        while !condition(&self.nodes) {
+            if self.queue.peek().map_or(true, |e| e.time > max_time) {
+                return false;
+            }
+            self.step();
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+    use vetomint::*;
+
+    /// A node that floods the message it first sees to its neighbors.
+    #[derive(Debug, Clone)]
+    struct FloodingNode {
+        neighbors: Vec<NodeIndex>,
+        received_at: Option<SimulatedTime>,
+    }
+
+    impl SimulatedNode for FloodingNode {
+        type Message = ();
+
+        fn on_start(&mut self, context: &mut Context<()>) {
+            if context.this_node() == 0 {
+                self.received_at = Some(context.now());
+                for neighbor in &self.neighbors {
+                    context.send(*neighbor, ());
+                }
+            }
+        }
+
+        fn on_message(&mut self, context: &mut Context<()>, _from: NodeIndex, _message: ()) {
+            if self.received_at.is_none() {
+                self.received_at = Some(context.now());
+                for neighbor in &self.neighbors {
+                    context.send(*neighbor, ());
+                }
+            }
+        }
+    }
+
+    fn run_flooding(n: usize, conditions: NetworkConditions, seed: u64) -> Simulator<FloodingNode> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let nodes = (0..n)
+            .map(|i| FloodingNode {
+                neighbors: std::iter::once((i + 1) % n)
+                    .chain((0..7).map(|_| rng.gen_range(0..n)))
+                    .collect(),
+                received_at: None,
+            })
+            .collect();
+        let mut simulator = Simulator::new(nodes, conditions, seed);
+        while simulator.step() {}
+        simulator
+    }
+
+    fn trace(simulator: &Simulator<FloodingNode>) -> Vec<Option<SimulatedTime>> {
+        simulator.nodes().iter().map(|x| x.received_at).collect()
+    }
+
+    #[test]
+    fn flooding_broadcast() {
+        setup_test();
+        let simulator = run_flooding(300, NetworkConditions::default(), 1);
+        assert!(simulator.nodes().iter().all(|x| x.received_at.is_some()));
+    }
+
+    #[test]
+    fn reproducible_from_seed() {
+        setup_test();
+        let lossy = NetworkConditions {
+            drop_probability: 0.3,
+            ..Default::default()
+        };
+        let a = run_flooding(300, lossy.clone(), 7);
+        let b = run_flooding(300, lossy.clone(), 7);
+        let c = run_flooding(300, lossy, 8);
+        assert_eq!(trace(&a), trace(&b));
+        assert_eq!(a.stats(), b.stats());
+        assert_ne!(trace(&a), trace(&c));
+    }
+
+    fn fully_connected_flooding(n: usize) -> Vec<FloodingNode> {
+        (0..n)
+            .map(|i| FloodingNode {
+                neighbors: (0..n).filter(|x| *x != i).collect(),
+                received_at: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn partition() {
+        setup_test();
+        let mut simulator = Simulator::new(fully_connected_flooding(100), Default::default(), 0);
+        simulator.partition(&[(0..50).collect(), (50..100).collect()]);
+        simulator.run_until(10_000);
+        assert!(simulator.nodes()[..50]
+            .iter()
+            .all(|x| x.received_at.is_some()));
+        assert!(simulator.nodes()[50..]
+            .iter()
+            .all(|x| x.received_at.is_none()));
+    }
+
+    #[test]
+    fn link_faults() {
+        setup_test();
+        let mut simulator = Simulator::new(fully_connected_flooding(4), Default::default(), 0);
+        // Node 3 hears only from node 2, late.
+        simulator.set_link_fault(0, 3, LinkFault::Drop);
+        simulator.set_link_fault(1, 3, LinkFault::Drop);
+        simulator.set_link_fault(2, 3, LinkFault::Delay(5000));
+        while simulator.step() {}
+        assert!(simulator.node(1).received_at.unwrap() < 5000);
+        assert!(simulator.node(3).received_at.unwrap() >= 5000);
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        setup_test();
+        let mut simulator = Simulator::new(fully_connected_flooding(10), Default::default(), 0);
+        simulator.pause(5);
+        simulator.run_until(10_000);
+        assert!(simulator.is_paused(5));
+        assert!(simulator.node(5).received_at.is_none());
+        simulator.resume(5);
+        simulator.run_until(10_000);
+        assert_eq!(simulator.node(5).received_at, Some(10_000));
+    }
+
+    /// A node that discovers the others by periodically exchanging its known peers with a random one.
+    #[derive(Debug, Clone)]
+    struct DiscoveryNode {
+        known_peers: Vec<bool>,
+    }
+
+    const GOSSIP_INTERVAL_MS: u64 = 1000;
+
+    impl DiscoveryNode {
+        fn gossip(&self, context: &mut Context<Vec<bool>>) {
+            let known: Vec<_> = (0..self.known_peers.len())
+                .filter(|x| self.known_peers[*x] && *x != context.this_node())
+                .collect();
+            let peer = known[context.rng().gen_range(0..known.len())];
+            context.send(peer, self.known_peers.clone());
+        }
+    }
+
+    impl SimulatedNode for DiscoveryNode {
+        type Message = Vec<bool>;
+
+        fn on_start(&mut self, context: &mut Context<Vec<bool>>) {
+            let delay = context.rng().gen_range(0..GOSSIP_INTERVAL_MS);
+            context.set_timer(delay, 0);
+        }
+
+        fn on_message(
+            &mut self,
+            _context: &mut Context<Vec<bool>>,
+            from: NodeIndex,
+            peers: Vec<bool>,
+        ) {
+            self.known_peers[from] = true;
+            for (known, new) in self.known_peers.iter_mut().zip(peers) {
+                *known |= new;
+            }
+        }
+
+        fn on_timer(&mut self, context: &mut Context<Vec<bool>>, _timer: u64) {
+            self.gossip(context);
+            context.set_timer(GOSSIP_INTERVAL_MS, 0);
+        }
+    }
+
+    #[test]
+    fn peer_discovery() {
+        setup_test();
+        let n = 200;
+        let nodes = (0..n)
+            .map(|i| {
+                let mut known_peers = vec![false; n];
+                known_peers[i] = true;
+                known_peers[(i + 1) % n] = true;
+                DiscoveryNode { known_peers }
+            })
+            .collect();
+        let mut simulator = Simulator::new(nodes, NetworkConditions::default(), 3);
+        assert!(simulator.run_until_condition(
+            |nodes| nodes.iter().all(|x| x.known_peers.iter().all(|k| *k)),
+            600_000
+        ));
+    }
+
+    #[test]
+    fn peer_discovery_with_partition() {
+        setup_test();
+        let n = 100;
+        let nodes = (0..n)
+            .map(|i| {
+                let mut known_peers = vec![false; n];
+                known_peers[i] = true;
+                known_peers[(i + 1) % n] = true;
+                DiscoveryNode { known_peers }
+            })
+            .collect();
+        let mut simulator = Simulator::new(nodes, NetworkConditions::default(), 5);
+        simulator.partition(&[(0..50).collect(), (50..100).collect()]);
+        simulator.run_until(300_000);
+        assert!(!simulator.node(0).known_peers[75]);
+
+        simulator.heal();
+        let deadline = simulator.now() + 600_000;
+        assert!(simulator.run_until_condition(
+            |nodes| nodes.iter().all(|x| x.known_peers.iter().all(|k| *k)),
+            deadline
+        ));
+    }
+
+    /// A validator running `Vetomint`, which votes for every proposal.
+    #[derive(Debug, Clone)]
+    struct ConsensusNode {
+        consensus: Vetomint,
+        finalized: Option<BlockIdentifier>,
+    }
+
+    const CONSENSUS_TICK_MS: u64 = 50;
+
+    impl ConsensusNode {
+        fn new(validators: usize, index: ValidatorIndex) -> Self {
+            Self {
+                consensus: Vetomint::new(HeightInfo {
+                    validators: vec![1; validators],
+                    this_node_index: Some(index),
+                    timestamp: 0,
+                    consensus_params: ConsensusParams {
+                        timeout_ms: 1000,
+                        repeat_round_for_first_leader: 1,
+                    },
+                    initial_block_candidate: index,
+                }),
+                finalized: None,
+            }
+        }
+
+        fn progress(&mut self, context: &mut Context<ConsensusEvent>, event: ConsensusEvent) {
+            let this = context.this_node();
+            for response in self.consensus.progress(event, context.now() as i64) {
+                match response {
+                    ConsensusResponse::BroadcastProposal {
+                        proposal,
+                        valid_round,
+                        round,
+                    } => context.broadcast(ConsensusEvent::BlockProposalReceived {
+                        proposal,
+                        valid: true,
+                        valid_round,
+                        proposer: this,
+                        round,
+                        favor: true,
+                    }),
+                    ConsensusResponse::BroadcastPrevote { proposal, round } => {
+                        context.broadcast(ConsensusEvent::Prevote {
+                            proposal,
+                            signer: this,
+                            round,
+                        })
+                    }
+                    ConsensusResponse::BroadcastPrecommit { proposal, round } => {
+                        context.broadcast(ConsensusEvent::Precommit {
+                            proposal,
+                            signer: this,
+                            round,
+                        })
+                    }
+                    ConsensusResponse::FinalizeBlock { proposal, .. } => {
+                        self.finalized = Some(proposal)
+                    }
+                    ConsensusResponse::ViolationReport { description, .. } => {
+                        panic!("violation: {description}")
+                    }
+                }
+            }
+        }
+    }
+
+    impl SimulatedNode for ConsensusNode {
+        type Message = ConsensusEvent;
+
+        fn on_start(&mut self, context: &mut Context<ConsensusEvent>) {
+            self.progress(context, ConsensusEvent::Start);
+            context.set_timer(CONSENSUS_TICK_MS, 0);
+        }
+
+        fn on_message(
+            &mut self,
+            context: &mut Context<ConsensusEvent>,
+            _from: NodeIndex,
+            event: ConsensusEvent,
+        ) {
+            self.progress(context, event);
+        }
+
+        fn on_timer(&mut self, context: &mut Context<ConsensusEvent>, _timer: u64) {
+            if self.finalized.is_none() {
+                self.progress(context, ConsensusEvent::Timer);
+                context.set_timer(CONSENSUS_TICK_MS, 0);
+            }
+        }
+    }
+
+    fn run_consensus(n: usize, crashed: &[NodeIndex], seed: u64) -> Vec<Option<BlockIdentifier>> {
+        let nodes = (0..n).map(|i| ConsensusNode::new(n, i)).collect();
+        let mut simulator = Simulator::new(nodes, NetworkConditions::default(), seed);
+        for node in crashed {
+            simulator.crash(*node);
+        }
+        let live: Vec<_> = (0..n).filter(|x| !crashed.contains(x)).collect();
+        assert!(simulator.run_until_condition(
+            |nodes| live.iter().all(|x| nodes[*x].finalized.is_some()),
+            60_000
+        ));
+        live.iter().map(|x| simulator.node(*x).finalized).collect()
+    }
+
+    #[test]
+    fn consensus() {
+        setup_test();
+        let finalized = run_consensus(100, &[], 0);
+        assert!(finalized.iter().all(|x| *x == Some(0)));
+    }
+
+    #[test]
+    fn consensus_with_crashed_validators() {
+        setup_test();
+        let finalized = run_consensus(31, &[3, 5, 9], 0);
+        assert!(finalized.iter().all(|x| *x == Some(0)));
+    }
+}
@@ -0,0 +1,79 @@
+//! Tracing of the network tasks.
+//!
+//! The discovery rounds, the broadcasts (with the token of the message) and the connections
+//! are instrumented with `tracing` spans, so the events of a peer or a message can be followed
+//! across the tasks. They are emitted once a subscriber is installed,
+//! either by the application or by [`init_tracing()`] (which `Dms::serve()` calls with `dms::Config::tracing`).
+use super::*;
+use eyre::eyre;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TracingFormat {
+    /// A line per event.
+    Compact,
+    /// Multiple lines per event, for reading by humans.
+    Pretty,
+    /// A JSON object per line, for the log collectors.
+    Json,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TracingConfig {
+    /// The filter of the spans and the events, in the syntax of `RUST_LOG`
+    /// (e.g., `simperby_network=debug`).
+    pub filter: String,
+    pub format: TracingFormat,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            filter: "info".to_owned(),
+            format: TracingFormat::Compact,
+        }
+    }
+}
+
+impl TracingConfig {
+    pub fn env_filter(&self) -> Result<EnvFilter, Error> {
+        EnvFilter::try_new(&self.filter).map_err(|e| eyre!("invalid filter {}: {e}", self.filter))
+    }
+}
+
+/// Installs the global subscriber for the config.
+///
+/// Returns `false` if a global subscriber has been installed already, which is kept.
+pub fn init_tracing(config: &TracingConfig) -> Result<bool, Error> {
+    let builder = tracing_subscriber::fmt().with_env_filter(config.env_filter()?);
+    let result = match config.format {
+        TracingFormat::Compact => builder.compact().try_init(),
+        TracingFormat::Pretty => builder.pretty().try_init(),
+        TracingFormat::Json => builder.json().try_init(),
+    };
+    Ok(result.is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_test_suite::*;
+
+    #[test]
+    fn init() {
+        setup_test();
+        let config = TracingConfig {
+            filter: "simperby_network=debug,warn".to_owned(),
+            format: TracingFormat::Json,
+        };
+        assert!(config.env_filter().is_ok());
+        assert!(TracingConfig {
+            filter: "simperby_network=loud".to_owned(),
+            ..config.clone()
+        }
+        .env_filter()
+        .is_err());
+        assert!(init_tracing(&config).unwrap());
+        assert!(!init_tracing(&TracingConfig::default()).unwrap());
+    }
+}
@@ -0,0 +1,163 @@
+//! The signing ceremony of a threshold key (see `simperby_common::threshold`) among its operators.
+//!
+//! The operators share a DMS for each piece of data to sign, where each adds the signature of its
+//! share and fetches the others', until any of them has enough to combine.
+//!
+//! The result is the member's BLS signature, which is accepted only in aggregate finalization
+//! proofs; the consensus votes of the member are not signed by a ceremony.
+use super::dms::{DistributedMessageSet as DMS, Message, MessageFilter};
+use super::primitives::{GossipNetwork, Storage};
+use super::*;
+use simperby_common::signer::Signer;
+use simperby_common::threshold::{KeyShare, PartialSignature, ThresholdPublicKey};
+
+/// Generates the key of the DMS of the ceremony, which is unique to the key and the data.
+pub fn generate_dms_key(public_key: &ThresholdPublicKey, data: &Hash256) -> String {
+    format!(
+        "threshold-{}-{}",
+        &public_key.group_key.to_hash256().to_string()[0..8],
+        &data.to_string()[0..8]
+    )
+}
+
+/// Accepts only the valid partial signatures, each from the operator of the share.
+struct PartialSignatureFilter {
+    public_key: ThresholdPublicKey,
+    operators: Vec<PublicKey>,
+    data: Hash256,
+}
+
+impl MessageFilter for PartialSignatureFilter {
+    fn filter(&self, message: &Message) -> Result<(), String> {
+        let partial =
+            serde_spb::from_str::<PartialSignature>(message.data()).map_err(|e| e.to_string())?;
+        let operator = (partial.index as usize)
+            .checked_sub(1)
+            .and_then(|i| self.operators.get(i));
+        if operator != Some(message.signature().signer()) {
+            return Err(format!(
+                "the signer is not the operator of the share {}",
+                partial.index
+            ));
+        }
+        self.public_key
+            .verify_partial(self.data, &partial)
+            .map_err(|e| e.to_string())
+    }
+}
+
+pub struct SigningCeremony<N: GossipNetwork, S: Storage> {
+    dms: DMS<N, S>,
+    public_key: ThresholdPublicKey,
+    data: Hash256,
+}
+
+impl<N: GossipNetwork, S: Storage> SigningCeremony<N, S> {
+    /// Creates a ceremony to sign `data` over the given DMS (see [`generate_dms_key`]),
+    /// where `operators[i - 1]` is the network key of the operator of the share `i`.
+    pub fn new(
+        mut dms: DMS<N, S>,
+        public_key: ThresholdPublicKey,
+        operators: Vec<PublicKey>,
+        data: Hash256,
+    ) -> Result<Self, Error> {
+        if operators.len() != public_key.share_keys.len() {
+            return Err(eyre::eyre!(
+                "{} operators for {} shares",
+                operators.len(),
+                public_key.share_keys.len()
+            ));
+        }
+        dms.set_filter(Arc::new(PartialSignatureFilter {
+            public_key: public_key.clone(),
+            operators,
+            data,
+        }));
+        Ok(Self {
+            dms,
+            public_key,
+            data,
+        })
+    }
+
+    /// Signs with the share, adding the partial signature signed by the network key
+    /// of this operator.
+    pub async fn contribute(&mut self, share: &KeyShare, signer: &dyn Signer) -> Result<(), Error> {
+        let partial = share.sign(self.data)?;
+        self.public_key.verify_partial(self.data, &partial)?;
+        let data = serde_spb::to_string(&partial)?;
+        let message = Message::new(data.clone(), TypedSignature::sign_by(&data, signer).await?)?;
+        self.dms.add_message(message).await
+    }
+
+    /// Returns the partial signatures received so far.
+    pub async fn read(&self) -> Result<Vec<PartialSignature>, Error> {
+        self.dms
+            .read_messages()
+            .await?
+            .iter()
+            .map(|message| serde_spb::from_str(message.data()).map_err(Error::from))
+            .collect()
+    }
+
+    /// Combines the partial signatures into the signature of the group key,
+    /// or returns `None` if there aren't enough yet.
+    pub async fn try_combine(&self) -> Result<Option<BlsSignature>, Error> {
+        let partials = self.read().await?;
+        let indices = partials.iter().map(|x| x.index).collect::<HashSet<_>>();
+        if indices.len() < self.public_key.threshold as usize {
+            return Ok(None);
+        }
+        Ok(Some(self.public_key.combine(self.data, &partials)?))
+    }
+
+    /// Fetches the partial signatures from the other operators.
+    pub async fn fetch(&mut self) -> Result<(), Error> {
+        self.dms.fetch().await
+    }
+
+    /// Broadcasts the partial signatures known to this operator.
+    pub async fn broadcast(&self) -> Result<(), Error> {
+        self.dms.broadcast_all().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_common::threshold::deal_key_shares;
+    use simperby_test_suite::*;
+
+    #[test]
+    fn partial_signature_filter() {
+        setup_test();
+        let (_, private_key) = generate_bls_keypair("organization");
+        let (public_key, shares) = deal_key_shares(&private_key, 2, 3, "dealer").unwrap();
+        let operators = (0..3)
+            .map(|i| generate_keypair(format!("operator-{i}")))
+            .collect::<Vec<_>>();
+        let data = Hash256::hash("hello world");
+        let filter = PartialSignatureFilter {
+            public_key,
+            operators: operators.iter().map(|(x, _)| x.clone()).collect(),
+            data,
+        };
+        let message = |share: &KeyShare, data: Hash256, operator: usize| {
+            let data = serde_spb::to_string(&share.sign(data).unwrap()).unwrap();
+            let signature = TypedSignature::sign(&data, &operators[operator].1).unwrap();
+            Message::new(data, signature).unwrap()
+        };
+
+        filter.filter(&message(&shares[1], data, 1)).unwrap();
+        // From the operator of another share.
+        filter.filter(&message(&shares[1], data, 0)).unwrap_err();
+        // Of another data.
+        filter
+            .filter(&message(&shares[1], Hash256::hash("hello"), 1))
+            .unwrap_err();
+        assert_ne!(
+            generate_dms_key(&filter.public_key, &data),
+            generate_dms_key(&filter.public_key, &Hash256::hash("hello"))
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! The HTTP API for the operators of the node.
+//!
+//! Every request must carry an `Authorization: Bearer <token>` header granting the method
+//! (see [`auth::METHOD_ROLES`]), unless anonymous clients are allowed by the [`AuthConfig`].
+//!
+//! - `GET /peers` (`peers.export`) returns the known peers as a snapshot document.
+//! - `POST /peers` (`peers.import`) imports the peers in the snapshot document of the body,
+//!   returning the number of the peers added or updated.
+//! - `POST /sign` (`signer.sign`) signs the body with the key of the node,
+//!   returning the signature. The signature is recorded in the audit log.
+//!
+//! A request that is not authorized is answered with `401 Unauthorized` or `403 Forbidden`.
+use super::*;
+use crate::auth::{AuthConfig, AuthError};
+use crate::node::get_timestamp;
+use simperby_common::signer::Signer;
+use simperby_network::audit::SigningAuditLog;
+use simperby_network::NetworkConfig;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// The maximum size of a request, including the headers.
+const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+/// The audit log context of the signatures made through the API.
+pub const SIGN_AUDIT_CONTEXT: &str = "admin";
+
+/// What the admin API operates on, shared with the node.
+#[derive(Clone)]
+pub struct AdminContext {
+    pub path: String,
+    pub network_config: NetworkConfig,
+    pub peers: SharedKnownPeers,
+    pub signer: Arc<dyn Signer>,
+    pub audit_log: Arc<SigningAuditLog>,
+}
+
+impl AdminContext {
+    /// Signs the data with the key of the node, recording the signature in the audit log.
+    pub async fn sign(&self, data: String) -> Result<TypedSignature<String>> {
+        let signature = TypedSignature::sign_by(&data, &*self.signer).await?;
+        self.audit_log
+            .record(SIGN_AUDIT_CONTEXT, &data, &signature, get_timestamp())
+            .await?;
+        Ok(signature)
+    }
+}
+
+/// Imports the peers in a snapshot document, saving the known peers to `peers.json`.
+pub(crate) async fn import_peers(
+    network_config: &NetworkConfig,
+    peers: &SharedKnownPeers,
+    path: &str,
+    document: &str,
+) -> Result<usize> {
+    let imported =
+        simperby_network::peer_store::import_peers(network_config, peers, document).await?;
+    tokio::fs::write(
+        format!("{path}/peers.json"),
+        serde_spb::to_string(&peers.read().await)?,
+    )
+    .await?;
+    Ok(imported)
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    body: String,
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<HttpRequest> {
+    let mut buffer = Vec::new();
+    let header_end = loop {
+        if let Some(i) = buffer.windows(4).position(|x| x == b"\r\n\r\n") {
+            break i + 4;
+        }
+        if buffer.len() > MAX_REQUEST_SIZE {
+            return Err(eyre::eyre!("too large request"));
+        }
+        let mut chunk = [0; 4096];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(eyre::eyre!("connection closed before the request ends"));
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    };
+    let header = String::from_utf8_lossy(&buffer[..header_end]).into_owned();
+    let mut lines = header.lines();
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_owned();
+    let path = request_line.next().unwrap_or_default().to_owned();
+    let mut authorization = None;
+    let mut content_length = 0;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            match name.trim().to_ascii_lowercase().as_str() {
+                "authorization" => authorization = Some(value.trim().to_owned()),
+                "content-length" => content_length = value.trim().parse()?,
+                _ => (),
+            }
+        }
+    }
+    if header_end + content_length > MAX_REQUEST_SIZE {
+        return Err(eyre::eyre!("too large request"));
+    }
+    let mut body = buffer[header_end..].to_vec();
+    while body.len() < content_length {
+        let mut chunk = [0; 4096];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(eyre::eyre!("connection closed before the body ends"));
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    body.truncate(content_length);
+    Ok(HttpRequest {
+        method,
+        path,
+        authorization,
+        body: String::from_utf8(body)?,
+    })
+}
+
+async fn respond(context: &AdminContext, auth: &AuthConfig, request: HttpRequest) -> (u16, String) {
+    let method = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/peers") => "peers.export",
+        ("POST", "/peers") => "peers.import",
+        ("POST", "/sign") => "signer.sign",
+        _ => return (404, String::new()),
+    };
+    let token = request
+        .authorization
+        .as_deref()
+        .and_then(AuthConfig::parse_bearer);
+    if let Err(e) = auth.authorize(token, method) {
+        let status = match e {
+            AuthError::MissingToken | AuthError::UnknownToken => 401,
+            AuthError::Forbidden { .. } => 403,
+        };
+        return (status, e.to_string());
+    }
+    let result = match method {
+        "peers.export" => {
+            simperby_network::peer_store::export_peers(&context.network_config, &context.peers)
+                .await
+                .map_err(|e| (500, e))
+        }
+        "peers.import" => import_peers(
+            &context.network_config,
+            &context.peers,
+            &context.path,
+            &request.body,
+        )
+        .await
+        .map(|x| x.to_string())
+        .map_err(|e| (400, e)),
+        "signer.sign" => match context.sign(request.body).await {
+            Ok(signature) => serde_spb::to_string(&signature).map_err(|e| (500, e.into())),
+            Err(e) => Err((500, e)),
+        },
+        _ => unreachable!(),
+    };
+    match result {
+        Ok(body) => (200, body),
+        Err((status, e)) => (status, e.to_string()),
+    }
+}
+
+/// Runs the admin API server on the given address indefinitely.
+pub async fn serve_admin(
+    context: AdminContext,
+    address: SocketAddr,
+    auth: AuthConfig,
+) -> Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    loop {
+        let (mut stream, address) = listener.accept().await?;
+        let context = context.clone();
+        let auth = auth.clone();
+        tokio::spawn(async move {
+            let (status, body) = match read_request(&mut stream).await {
+                Ok(request) => respond(&context, &auth, request).await,
+                Err(e) => (400, e.to_string()),
+            };
+            let reason = match status {
+                200 => "OK",
+                400 => "Bad Request",
+                401 => "Unauthorized",
+                403 => "Forbidden",
+                404 => "Not Found",
+                _ => "Internal Server Error",
+            };
+            let response = format!(
+                "HTTP/1.1 {status} {reason}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            if let Err(e) = stream.write_all(response.as_bytes()).await {
+                log::warn!("failed to respond to an admin client {address}: {e}");
+            }
+        });
+    }
+}
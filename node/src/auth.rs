@@ -0,0 +1,158 @@
+//! Token-based authentication and role-based permissions for the node APIs.
+//!
+//! Each API token is bound to a [`Role`], and each API method requires a minimum role.
+//! Tokens are stored as their hashes so that the configuration file doesn't leak them.
+use super::*;
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// A role of an API client, in the ascending order of privilege.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Role {
+    /// Can query the node state and subscribe to the events.
+    ReadOnly,
+    /// Can additionally control the node.
+    Operator,
+    /// Can additionally make the node sign messages with its key, and call any method.
+    SignerAdmin,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    #[error("missing API token")]
+    MissingToken,
+    #[error("unknown API token")]
+    UnknownToken,
+    #[error("method `{method}` requires {required:?}, but the token has {given:?}")]
+    Forbidden {
+        method: String,
+        required: Role,
+        given: Role,
+    },
+}
+
+/// The methods that can be called through the APIs, with the role required for each.
+///
+/// Only the methods that a node API serves (and so checks) are listed;
+/// any other method requires `Role::SignerAdmin`.
+pub const METHOD_ROLES: &[(&str, Role)] = &[
+    ("events.subscribe", Role::ReadOnly),
+    ("peers.export", Role::ReadOnly),
+    ("peers.import", Role::Operator),
+    ("signer.sign", Role::SignerAdmin),
+];
+
+pub fn required_role(method: &str) -> Role {
+    METHOD_ROLES
+        .iter()
+        .find(|(m, _)| *m == method)
+        .map(|(_, role)| *role)
+        .unwrap_or(Role::SignerAdmin)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AuthConfig {
+    /// The hashes of the API tokens and their roles.
+    pub tokens: HashMap<Hash256, Role>,
+    /// The role granted to the clients without a token. `None` means that a token is required.
+    pub anonymous_role: Option<Role>,
+}
+
+impl AuthConfig {
+    /// Registers a new token with the given role.
+    pub fn add_token(&mut self, token: &str, role: Role) {
+        self.tokens.insert(Hash256::hash(token), role);
+    }
+
+    /// Checks whether the client with the given token can call the method, returning its role.
+    pub fn authorize(&self, token: Option<&str>, method: &str) -> Result<Role, AuthError> {
+        let given = match token {
+            Some(token) => *self
+                .tokens
+                .get(&Hash256::hash(token))
+                .ok_or(AuthError::UnknownToken)?,
+            None => self.anonymous_role.ok_or(AuthError::MissingToken)?,
+        };
+        let required = required_role(method);
+        if given < required {
+            return Err(AuthError::Forbidden {
+                method: method.to_owned(),
+                required,
+                given,
+            });
+        }
+        Ok(given)
+    }
+
+    /// Extracts the token from the value of an HTTP `Authorization` header (`Bearer <token>`).
+    pub fn parse_bearer(header: &str) -> Option<&str> {
+        header.strip_prefix("Bearer ").map(str::trim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authorize() {
+        let mut config = AuthConfig::default();
+        config.add_token("reader", Role::ReadOnly);
+        config.add_token("operator", Role::Operator);
+        config.add_token("admin", Role::SignerAdmin);
+
+        assert_eq!(
+            config.authorize(Some("reader"), "events.subscribe"),
+            Ok(Role::ReadOnly)
+        );
+        assert_eq!(
+            config.authorize(Some("operator"), "events.subscribe"),
+            Ok(Role::Operator)
+        );
+        assert_eq!(
+            config.authorize(Some("operator"), "peers.import"),
+            Ok(Role::Operator)
+        );
+        assert!(matches!(
+            config.authorize(Some("reader"), "peers.import"),
+            Err(AuthError::Forbidden {
+                required: Role::Operator,
+                given: Role::ReadOnly,
+                ..
+            })
+        ));
+        assert!(matches!(
+            config.authorize(Some("operator"), "signer.sign"),
+            Err(AuthError::Forbidden {
+                required: Role::SignerAdmin,
+                ..
+            })
+        ));
+        assert!(matches!(
+            config.authorize(Some("operator"), "unknown"),
+            Err(AuthError::Forbidden {
+                required: Role::SignerAdmin,
+                ..
+            })
+        ));
+        assert_eq!(
+            config.authorize(Some("admin"), "unknown"),
+            Ok(Role::SignerAdmin)
+        );
+        assert_eq!(
+            config.authorize(Some("stranger"), "events.subscribe"),
+            Err(AuthError::UnknownToken)
+        );
+        assert_eq!(
+            config.authorize(None, "events.subscribe"),
+            Err(AuthError::MissingToken)
+        );
+
+        config.anonymous_role = Some(Role::ReadOnly);
+        assert_eq!(
+            config.authorize(None, "events.subscribe"),
+            Ok(Role::ReadOnly)
+        );
+        assert!(config.authorize(None, "unknown").is_err());
+    }
+}
@@ -0,0 +1,445 @@
+//! Push notifications of node events over WebSocket.
+//!
+//! A client connects to the event server and sends an [`EventFilter`] as the first (text) message.
+//! Then the server streams every matching [`NodeEvent`] as a JSON text message
+//! until the client disconnects. A client may send another filter at any time to replace the current one.
+//!
+//! The handshake request must carry an `Authorization: Bearer <token>` header
+//! granting `events.subscribe`, unless anonymous clients are allowed by the [`AuthConfig`].
+//!
+//! Besides the node itself, the tasks here publish the events of the other components
+//! ([`publish_peer_changes`] and [`publish_deliveries`]).
+use super::*;
+use crate::auth::AuthConfig;
+use futures::{SinkExt, StreamExt};
+use simperby_network::latency::PeerLatency;
+use simperby_network::PeerEvent;
+use simperby_settlement::relayer::DeliveredExecution;
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::{http, Message};
+
+/// The number of events that a slow subscriber may lag behind before it starts to miss events.
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum NodeEvent {
+    /// A new block has been finalized.
+    BlockFinalized { height: BlockHeight, hash: Hash256 },
+    /// A new agenda has been created on the `work` branch.
+    AgendaCreated {
+        commit_hash: CommitHash,
+        agenda_hash: Hash256,
+    },
+    /// A vote for an agenda has been received.
+    VoteReceived {
+        agenda_hash: Hash256,
+        voter: PublicKey,
+    },
+    /// The set of known peers has changed.
+    PeersChanged { peers: Vec<PublicKey> },
+    /// An execution has been delivered to the settlement chain.
+    ExecutionDelivered {
+        target_chain: String,
+        transaction_hash: Hash256,
+    },
+    /// A member has not been seen on the network for a while.
+    MemberOffline {
+        member: PublicKey,
+        last_seen: Timestamp,
+    },
+    /// An internal task of the node has crashed and is being restarted.
+    SubsystemRestarted {
+        name: String,
+        reason: String,
+        restarts: u64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EventKind {
+    BlockFinalized,
+    AgendaCreated,
+    VoteReceived,
+    PeersChanged,
+    ExecutionDelivered,
+    MemberOffline,
+    SubsystemRestarted,
+}
+
+impl NodeEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            NodeEvent::BlockFinalized { .. } => EventKind::BlockFinalized,
+            NodeEvent::AgendaCreated { .. } => EventKind::AgendaCreated,
+            NodeEvent::VoteReceived { .. } => EventKind::VoteReceived,
+            NodeEvent::PeersChanged { .. } => EventKind::PeersChanged,
+            NodeEvent::ExecutionDelivered { .. } => EventKind::ExecutionDelivered,
+            NodeEvent::MemberOffline { .. } => EventKind::MemberOffline,
+            NodeEvent::SubsystemRestarted { .. } => EventKind::SubsystemRestarted,
+        }
+    }
+
+    /// Returns a one-line, human-readable description of the event.
+    pub fn summary(&self) -> String {
+        match self {
+            NodeEvent::BlockFinalized { height, hash } => {
+                format!("block #{height} ({hash}) has been finalized")
+            }
+            NodeEvent::AgendaCreated { commit_hash, .. } => {
+                format!("a new agenda ({commit_hash}) is waiting for the votes")
+            }
+            NodeEvent::VoteReceived { agenda_hash, voter } => {
+                format!("{voter} voted for agenda {agenda_hash}")
+            }
+            NodeEvent::PeersChanged { peers } => format!("{} peers are known", peers.len()),
+            NodeEvent::ExecutionDelivered {
+                target_chain,
+                transaction_hash,
+            } => format!("execution {transaction_hash} has been delivered to {target_chain}"),
+            NodeEvent::MemberOffline { member, last_seen } => {
+                format!("member {member} has been offline since {last_seen}")
+            }
+            NodeEvent::SubsystemRestarted {
+                name,
+                reason,
+                restarts,
+            } => format!("subsystem {name} restarted ({restarts} times so far): {reason}"),
+        }
+    }
+}
+
+/// A subscription filter sent by a client.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    /// The kinds of events to receive. Empty means all.
+    pub kinds: BTreeSet<EventKind>,
+    /// If set, only the `VoteReceived` events from the given voter are delivered.
+    pub voter: Option<PublicKey>,
+    /// If set, only the `ExecutionDelivered` events to the given chain are delivered.
+    pub target_chain: Option<String>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &NodeEvent) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(&event.kind()) {
+            return false;
+        }
+        match event {
+            NodeEvent::VoteReceived { voter, .. } => {
+                self.voter.as_ref().map_or(true, |x| x == voter)
+            }
+            NodeEvent::ExecutionDelivered { target_chain, .. } => self
+                .target_chain
+                .as_ref()
+                .map_or(true, |x| x == target_chain),
+            _ => true,
+        }
+    }
+}
+
+/// A cheaply clonable publisher of node events.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<NodeEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self { sender }
+    }
+
+    /// Publishes an event to all the current subscribers.
+    ///
+    /// Events published while there is no subscriber are simply dropped.
+    pub fn publish(&self, event: NodeEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// Publishes a `PeersChanged` event whenever a peer is added to or removed from the known peers,
+/// indefinitely.
+pub async fn publish_peer_changes(peers: SharedKnownPeers, bus: EventBus) -> Result<()> {
+    let mut receiver = peers.subscribe();
+    loop {
+        match receiver.recv().await {
+            Ok(PeerEvent::Added(_)) | Ok(PeerEvent::Removed(_)) => (),
+            Ok(PeerEvent::Updated(_)) => continue,
+            // Some changes are missed, so the peers are read again anyway.
+            Err(broadcast::error::RecvError::Lagged(_)) => (),
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        }
+        bus.publish(NodeEvent::PeersChanged {
+            peers: peers
+                .read()
+                .await
+                .into_iter()
+                .map(|x| x.public_key)
+                .collect(),
+        });
+    }
+}
+
+/// Publishes an `ExecutionDelivered` event for every execution confirmed by a relayer
+/// (see `simperby_settlement::relayer::Relayer::subscribe`), until the relayer is dropped.
+pub async fn publish_deliveries(
+    mut deliveries: broadcast::Receiver<DeliveredExecution>,
+    bus: EventBus,
+) -> Result<()> {
+    loop {
+        let delivery = match deliveries.recv().await {
+            Ok(delivery) => delivery,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                log::warn!("{n} deliveries are missed in the events");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        bus.publish(NodeEvent::ExecutionDelivered {
+            target_chain: delivery.execution.target_chain.to_string(),
+            transaction_hash: delivery.transaction_hash,
+        });
+    }
+}
+
+/// Returns the members that have not answered a ping for `offline_ms` at `now`,
+/// with the time when each of them was seen last.
+///
+/// The members that have never answered are not included, since they haven't been online.
+pub fn find_offline_members(
+    latencies: &HashMap<PublicKey, PeerLatency>,
+    members: &[PublicKey],
+    now: Timestamp,
+    offline_ms: Timestamp,
+) -> Vec<(PublicKey, Timestamp)> {
+    members
+        .iter()
+        .filter_map(|member| {
+            let last_seen = latencies.get(member)?.timestamp;
+            (now.saturating_sub(last_seen) > offline_ms).then(|| (member.clone(), last_seen))
+        })
+        .collect()
+}
+
+/// Runs the WebSocket event server on the given address indefinitely.
+pub async fn serve_events(bus: EventBus, address: SocketAddr, auth: AuthConfig) -> Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    loop {
+        let (stream, address) = listener.accept().await?;
+        let receiver = bus.subscribe();
+        let auth = auth.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, receiver, auth).await {
+                log::warn!("event subscriber {address} disconnected: {e}");
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    stream: tokio::net::TcpStream,
+    mut receiver: broadcast::Receiver<NodeEvent>,
+    auth: AuthConfig,
+) -> Result<()> {
+    // The signature of the callback is given by `tungstenite`.
+    #[allow(clippy::result_large_err)]
+    let check = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+        let token = request
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .and_then(|x| x.to_str().ok())
+            .and_then(AuthConfig::parse_bearer);
+        match auth.authorize(token, "events.subscribe") {
+            Ok(_) => Ok(response),
+            Err(e) => {
+                let mut response = ErrorResponse::new(Some(e.to_string()));
+                *response.status_mut() = http::StatusCode::UNAUTHORIZED;
+                Err(response)
+            }
+        }
+    };
+    let mut websocket = tokio_tungstenite::accept_hdr_async(stream, check).await?;
+    let mut filter: Option<EventFilter> = None;
+    loop {
+        tokio::select! {
+            message = websocket.next() => {
+                match message {
+                    Some(Ok(Message::Text(text))) => {
+                        filter = Some(serde_spb::from_str(&text)?);
+                    }
+                    Some(Ok(Message::Close(_))) | None => return Ok(()),
+                    Some(Ok(_)) => (),
+                    Some(Err(e)) => return Err(e.into()),
+                }
+            }
+            event = receiver.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        log::warn!("event subscriber lagged behind; {n} events are skipped");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                };
+                // Events are not delivered until the client subscribes.
+                if filter.as_ref().map_or(false, |f| f.matches(&event)) {
+                    websocket.send(Message::Text(serde_spb::to_string(&event)?)).await?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter() {
+        let (public_key, _) = generate_keypair("voter");
+        let vote = NodeEvent::VoteReceived {
+            agenda_hash: Hash256::hash("agenda"),
+            voter: public_key.clone(),
+        };
+        let block = NodeEvent::BlockFinalized {
+            height: 1,
+            hash: Hash256::hash("block"),
+        };
+
+        let all = EventFilter::default();
+        assert!(all.matches(&vote));
+        assert!(all.matches(&block));
+
+        let blocks_only = EventFilter {
+            kinds: vec![EventKind::BlockFinalized].into_iter().collect(),
+            ..Default::default()
+        };
+        assert!(!blocks_only.matches(&vote));
+        assert!(blocks_only.matches(&block));
+
+        let other_voter = EventFilter {
+            voter: Some(generate_keypair("other").0),
+            ..Default::default()
+        };
+        assert!(!other_voter.matches(&vote));
+        assert!(other_voter.matches(&block));
+    }
+
+    #[tokio::test]
+    async fn peer_changes() {
+        let peers = SharedKnownPeers::new_static(Vec::new());
+        let bus = EventBus::new();
+        let mut receiver = bus.subscribe();
+        tokio::spawn(publish_peer_changes(peers.clone(), bus.clone()));
+        tokio::task::yield_now().await;
+
+        let public_key = generate_keypair("peer").0;
+        peers
+            .add_or_replace(Peer {
+                public_key: public_key.clone(),
+                name: "peer".to_owned(),
+                address: "127.0.0.1:1".parse().unwrap(),
+                addresses: Vec::new(),
+                ports: Default::default(),
+                metadata: Default::default(),
+                recently_seen_timestamp: 0,
+            })
+            .await;
+        assert_eq!(
+            receiver.recv().await.unwrap(),
+            NodeEvent::PeersChanged {
+                peers: vec![public_key.clone()]
+            }
+        );
+        peers.remove(&public_key).await;
+        assert_eq!(
+            receiver.recv().await.unwrap(),
+            NodeEvent::PeersChanged { peers: Vec::new() }
+        );
+    }
+
+    #[tokio::test]
+    async fn websocket() {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let address: SocketAddr = format!("127.0.0.1:{}", simperby_test_suite::dispense_port())
+            .parse()
+            .unwrap();
+        let mut auth = AuthConfig::default();
+        auth.add_token("secret", crate::Role::ReadOnly);
+        let bus = EventBus::new();
+        tokio::spawn(serve_events(bus.clone(), address, auth));
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let url = format!("ws://{address}");
+        // A client without a token is rejected.
+        assert!(tokio_tungstenite::connect_async(&url).await.is_err());
+
+        let mut request = url.into_client_request().unwrap();
+        request.headers_mut().insert(
+            http::header::AUTHORIZATION,
+            "Bearer secret".parse().unwrap(),
+        );
+        let (mut websocket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+        let filter = EventFilter {
+            kinds: vec![EventKind::BlockFinalized].into_iter().collect(),
+            ..Default::default()
+        };
+        websocket
+            .send(Message::Text(serde_spb::to_string(&filter).unwrap()))
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        bus.publish(NodeEvent::VoteReceived {
+            agenda_hash: Hash256::hash("agenda"),
+            voter: generate_keypair("voter").0,
+        });
+        let block = NodeEvent::BlockFinalized {
+            height: 1,
+            hash: Hash256::hash("block"),
+        };
+        bus.publish(block.clone());
+        let message = websocket.next().await.unwrap().unwrap();
+        let event: NodeEvent = serde_spb::from_str(message.to_text().unwrap()).unwrap();
+        assert_eq!(event, block);
+    }
+
+    #[test]
+    fn offline_members() {
+        let members = vec![
+            generate_keypair("online").0,
+            generate_keypair("offline").0,
+            generate_keypair("unknown").0,
+        ];
+        let latency = |timestamp| PeerLatency {
+            rtt_ms: 10,
+            last_rtt_ms: 10,
+            timestamp,
+        };
+        let latencies = vec![
+            (members[0].clone(), latency(9_000)),
+            (members[1].clone(), latency(1_000)),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            find_offline_members(&latencies, &members, 10_000, 5_000),
+            vec![(members[1].clone(), 1_000)]
+        );
+    }
+}
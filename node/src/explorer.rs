@@ -0,0 +1,238 @@
+//! A static HTML block explorer.
+//!
+//! The explorer renders the finalized history into plain HTML files
+//! (`index.html`, `block-<height>.html`, `agendas.html`, `executions.html` and `members.html`),
+//! which can be served by any static file server or even browsed locally.
+//!
+//! Block pages never change once rendered, so only the pages for the new blocks are written
+//! on each update; the list pages are rewritten every time.
+use super::*;
+use std::fmt::Write as _;
+
+/// The commits that belong to a finalized block (i.e., between the previous block and it).
+#[derive(Debug, Clone)]
+struct RenderedBlock {
+    header: BlockHeader,
+    commit_hash: CommitHash,
+    transactions: Vec<(Transaction, CommitHash)>,
+    agenda: Option<(Agenda, CommitHash)>,
+    agenda_proof: Option<AgendaProof>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Explorer {
+    out_dir: String,
+}
+
+impl Explorer {
+    pub fn new(out_dir: &str) -> Self {
+        Self {
+            out_dir: out_dir.to_owned(),
+        }
+    }
+
+    /// Renders the given finalized commits (from the genesis, in order),
+    /// returning the number of newly rendered block pages.
+    pub async fn update(
+        &self,
+        commits: &[(Commit, CommitHash)],
+        reserved_state: &ReservedState,
+    ) -> Result<usize> {
+        tokio::fs::create_dir_all(&self.out_dir).await?;
+        let blocks = group_by_block(commits);
+
+        let mut rendered = 0;
+        for block in &blocks {
+            let path = format!("{}/block-{}.html", self.out_dir, block.header.height);
+            if tokio::fs::metadata(&path).await.is_ok() {
+                continue;
+            }
+            tokio::fs::write(&path, render_block(block)).await?;
+            rendered += 1;
+        }
+        let pages = [
+            ("index.html", render_index(&blocks, reserved_state)),
+            ("agendas.html", render_agendas(&blocks)),
+            ("executions.html", render_executions(&blocks)),
+            ("members.html", render_members(reserved_state)),
+        ];
+        for (name, content) in pages {
+            tokio::fs::write(format!("{}/{}", self.out_dir, name), content).await?;
+        }
+        Ok(rendered)
+    }
+}
+
+fn group_by_block(commits: &[(Commit, CommitHash)]) -> Vec<RenderedBlock> {
+    let mut blocks = Vec::new();
+    let mut transactions = Vec::new();
+    let mut agenda = None;
+    let mut agenda_proof = None;
+    for (commit, commit_hash) in commits {
+        match commit {
+            Commit::Block(header) => blocks.push(RenderedBlock {
+                header: header.clone(),
+                commit_hash: *commit_hash,
+                transactions: std::mem::take(&mut transactions),
+                agenda: agenda.take(),
+                agenda_proof: agenda_proof.take(),
+            }),
+            Commit::Transaction(t) => transactions.push((t.clone(), *commit_hash)),
+            Commit::Agenda(a) => agenda = Some((a.clone(), *commit_hash)),
+            Commit::AgendaProof(p) => agenda_proof = Some(p.clone()),
+            _ => (),
+        }
+    }
+    blocks
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n<body>\n\
+         <nav><a href=\"index.html\">Blocks</a> | <a href=\"agendas.html\">Agendas</a> | \
+         <a href=\"executions.html\">Executions</a> | <a href=\"members.html\">Members</a></nav>\n\
+         <h1>{}</h1>\n{}</body>\n</html>\n",
+        escape(title),
+        escape(title),
+        body
+    )
+}
+
+fn render_block(block: &RenderedBlock) -> String {
+    let header = &block.header;
+    let mut body = String::new();
+    writeln!(body, "<table>").unwrap();
+    for (key, value) in [
+        ("Hash", header.to_hash256().to_string()),
+        ("Commit", block.commit_hash.to_string()),
+        ("Author", header.author.to_string()),
+        ("Previous hash", header.previous_hash.to_string()),
+        ("Timestamp", header.timestamp.to_string()),
+        ("Commit Merkle root", header.commit_merkle_root.to_string()),
+        ("Version", header.version.clone()),
+    ] {
+        writeln!(body, "<tr><th>{}</th><td>{}</td></tr>", key, escape(&value)).unwrap();
+    }
+    writeln!(body, "</table>").unwrap();
+    if header.height > 0 {
+        writeln!(
+            body,
+            "<p><a href=\"block-{}.html\">Previous block</a></p>",
+            header.height - 1
+        )
+        .unwrap();
+    }
+    if let Some((agenda, commit_hash)) = &block.agenda {
+        writeln!(
+            body,
+            "<h2>Agenda</h2><p>{} by {} ({} signatures)</p>",
+            commit_hash,
+            escape(&agenda.author.to_string()),
+            block.agenda_proof.as_ref().map_or(0, |p| p.proof.len())
+        )
+        .unwrap();
+    }
+    writeln!(body, "<h2>Transactions</h2><ul>").unwrap();
+    for (transaction, commit_hash) in &block.transactions {
+        writeln!(
+            body,
+            "<li><code>{}</code> {}<pre>{}</pre></li>",
+            commit_hash,
+            escape(&transaction.head),
+            escape(&transaction.body)
+        )
+        .unwrap();
+    }
+    writeln!(body, "</ul>").unwrap();
+    page(&format!("Block {}", header.height), &body)
+}
+
+fn render_index(blocks: &[RenderedBlock], reserved_state: &ReservedState) -> String {
+    let mut body = format!(
+        "<p>Chain: {}</p>\n<table>\n<tr><th>Height</th><th>Hash</th><th>Timestamp</th><th>Transactions</th></tr>\n",
+        escape(&reserved_state.genesis_info.chain_name)
+    );
+    for block in blocks.iter().rev() {
+        writeln!(
+            body,
+            "<tr><td><a href=\"block-{0}.html\">{0}</a></td><td>{1}</td><td>{2}</td><td>{3}</td></tr>",
+            block.header.height,
+            block.header.to_hash256(),
+            block.header.timestamp,
+            block.transactions.len()
+        )
+        .unwrap();
+    }
+    body += "</table>\n";
+    page("Blocks", &body)
+}
+
+fn render_agendas(blocks: &[RenderedBlock]) -> String {
+    let mut body =
+        "<table>\n<tr><th>Height</th><th>Commit</th><th>Author</th><th>Approved</th></tr>\n"
+            .to_owned();
+    for block in blocks.iter().rev() {
+        if let Some((agenda, commit_hash)) = &block.agenda {
+            writeln!(
+                body,
+                "<tr><td><a href=\"block-{}.html\">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                block.header.height,
+                agenda.height,
+                commit_hash,
+                escape(&agenda.author.to_string()),
+                block.agenda_proof.is_some()
+            )
+            .unwrap();
+        }
+    }
+    body += "</table>\n";
+    page("Agendas", &body)
+}
+
+fn render_executions(blocks: &[RenderedBlock]) -> String {
+    let mut body =
+        "<table>\n<tr><th>Height</th><th>Commit</th><th>Execution</th></tr>\n".to_owned();
+    for block in blocks.iter().rev() {
+        for (transaction, commit_hash) in &block.transactions {
+            if !transaction.head.starts_with("ex-") {
+                continue;
+            }
+            writeln!(
+                body,
+                "<tr><td><a href=\"block-{0}.html\">{0}</a></td><td>{1}</td><td>{2}</td></tr>",
+                block.header.height,
+                commit_hash,
+                escape(&transaction.head)
+            )
+            .unwrap();
+        }
+    }
+    body += "</table>\n";
+    page("Executions", &body)
+}
+
+fn render_members(reserved_state: &ReservedState) -> String {
+    let mut body =
+        "<table>\n<tr><th>Name</th><th>Public key</th><th>Governance</th><th>Consensus</th></tr>\n"
+            .to_owned();
+    for member in &reserved_state.members {
+        writeln!(
+            body,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&member.name),
+            member.public_key,
+            member.governance_voting_power,
+            member.consensus_voting_power
+        )
+        .unwrap();
+    }
+    body += "</table>\n";
+    page("Members", &body)
+}
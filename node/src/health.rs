@@ -0,0 +1,214 @@
+//! Liveness and readiness probes.
+//!
+//! `GET /healthz` reports whether the node process is alive and its storage is usable,
+//! and `GET /readyz` additionally reports whether the node is ready to participate
+//! (serving the network, making progress in the consensus and not lagging behind its peers).
+//! Both return `200 OK` or `503 Service Unavailable` with a JSON body of [`HealthReport`].
+use super::*;
+use crate::node::get_timestamp;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::RwLock;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HealthConfig {
+    /// The maximum number of heights that the node may lag behind its peers to be ready.
+    pub max_height_lag: BlockHeight,
+    /// The maximum time since the last consensus progress for the node to be ready.
+    pub max_consensus_stall_ms: Timestamp,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            max_height_lag: 2,
+            max_consensus_stall_ms: 60_000,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    pub network_bound: bool,
+    pub storage_writable: bool,
+    pub consensus_progressing: bool,
+    pub last_finalized_height: BlockHeight,
+    pub highest_peer_height: BlockHeight,
+    pub within_peer_heights: bool,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.storage_writable
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.is_healthy()
+            && self.network_bound
+            && self.consensus_progressing
+            && self.within_peer_heights
+    }
+}
+
+#[derive(Debug, Default)]
+struct HealthState {
+    network_bound: bool,
+    last_consensus_progress: Option<Timestamp>,
+    last_finalized_height: BlockHeight,
+    highest_peer_height: BlockHeight,
+}
+
+/// A shared handle that the node reports its status to and the probe server reads from.
+#[derive(Debug, Clone)]
+pub struct HealthMonitor {
+    path: String,
+    config: HealthConfig,
+    state: Arc<RwLock<HealthState>>,
+}
+
+impl HealthMonitor {
+    pub fn new(path: &str, config: HealthConfig) -> Self {
+        Self {
+            path: path.to_owned(),
+            config,
+            state: Default::default(),
+        }
+    }
+
+    pub async fn set_network_bound(&self, bound: bool) {
+        self.state.write().await.network_bound = bound;
+    }
+
+    pub async fn report_consensus_progress(&self, timestamp: Timestamp) {
+        self.state.write().await.last_consensus_progress = Some(timestamp);
+    }
+
+    pub async fn report_finalized_height(&self, height: BlockHeight) {
+        self.state.write().await.last_finalized_height = height;
+    }
+
+    /// Reports the last finalized height of a peer.
+    pub async fn report_peer_height(&self, height: BlockHeight) {
+        let mut state = self.state.write().await;
+        state.highest_peer_height = std::cmp::max(state.highest_peer_height, height);
+    }
+
+    async fn check_storage(&self) -> bool {
+        let probe = format!("{}/.health_probe", self.path);
+        tokio::fs::write(&probe, b"ok").await.is_ok()
+            && tokio::fs::remove_file(&probe).await.is_ok()
+    }
+
+    /// Creates a report for the given current time.
+    pub async fn report(&self, timestamp: Timestamp) -> HealthReport {
+        let storage_writable = self.check_storage().await;
+        let state = self.state.read().await;
+        HealthReport {
+            network_bound: state.network_bound,
+            storage_writable,
+            consensus_progressing: state.last_consensus_progress.map_or(false, |x| {
+                timestamp - x <= self.config.max_consensus_stall_ms
+            }),
+            last_finalized_height: state.last_finalized_height,
+            highest_peer_height: state.highest_peer_height,
+            within_peer_heights: state.highest_peer_height
+                <= state.last_finalized_height + self.config.max_height_lag,
+        }
+    }
+}
+
+/// Runs the probe server on the given address indefinitely.
+pub async fn serve_health(monitor: HealthMonitor, address: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let monitor = monitor.clone();
+        tokio::spawn(async move {
+            let mut buffer = [0; 1024];
+            let n = match stream.read(&mut buffer).await {
+                Ok(n) => n,
+                Err(_) => return,
+            };
+            let request = String::from_utf8_lossy(&buffer[..n]);
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+            let response = match path {
+                "/healthz" | "/readyz" => {
+                    let report = monitor.report(get_timestamp()).await;
+                    let ok = if path == "/healthz" {
+                        report.is_healthy()
+                    } else {
+                        report.is_ready()
+                    };
+                    let body = serde_spb::to_string(&report).unwrap();
+                    format!(
+                        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        if ok { "200 OK" } else { "503 Service Unavailable" },
+                        body.len(),
+                        body
+                    )
+                }
+                _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_owned(),
+            };
+            if let Err(e) = stream.write_all(response.as_bytes()).await {
+                log::warn!("failed to respond to a health probe: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn readiness() {
+        let dir = std::env::temp_dir().to_str().unwrap().to_owned();
+        let monitor = HealthMonitor::new(&dir, HealthConfig::default());
+        let report = monitor.report(0).await;
+        assert!(report.is_healthy());
+        assert!(!report.is_ready());
+
+        monitor.set_network_bound(true).await;
+        monitor.report_consensus_progress(0).await;
+        monitor.report_finalized_height(10).await;
+        monitor.report_peer_height(12).await;
+        assert!(monitor.report(1000).await.is_ready());
+
+        monitor.report_peer_height(13).await;
+        assert!(!monitor.report(1000).await.is_ready());
+        monitor.report_finalized_height(13).await;
+        assert!(!monitor.report(1_000_000).await.is_ready());
+    }
+
+    #[tokio::test]
+    async fn probes() {
+        let dir = std::env::temp_dir().to_str().unwrap().to_owned();
+        let monitor = HealthMonitor::new(&dir, HealthConfig::default());
+        let address: SocketAddr = format!("127.0.0.1:{}", simperby_test_suite::dispense_port())
+            .parse()
+            .unwrap();
+        tokio::spawn(serve_health(monitor.clone(), address));
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let get = |path: &'static str| async move {
+            let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+            stream
+                .write_all(format!("GET {path} HTTP/1.1\r\n\r\n").as_bytes())
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+        assert!(get("/healthz").await.starts_with("HTTP/1.1 200"));
+        let response = get("/readyz").await;
+        assert!(response.starts_with("HTTP/1.1 503"));
+        let report: HealthReport =
+            serde_spb::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert!(!report.network_bound);
+        assert!(get("/unknown").await.starts_with("HTTP/1.1 404"));
+    }
+}
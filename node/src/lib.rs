@@ -23,23 +23,46 @@
 //!
 //! - `init`
 //! - `clone`
+//! - `state_sync`
 //! - `serve`
 //!
 //! The following CLI commands are not provided here because they are simple
 //! and so directly implemented in the CLI.
 //!
 //! - `sign`
+pub mod admin;
+pub mod auth;
+pub mod events;
+pub mod explorer;
+pub mod health;
 pub mod node;
+pub mod reload;
+pub mod state_sync;
+pub mod supervisor;
+pub mod webhook;
 
+pub use auth::{AuthConfig, Role};
+pub use events::{EventBus, EventFilter, EventKind, NodeEvent};
+pub use explorer::Explorer;
+pub use health::{HealthConfig, HealthMonitor, HealthReport};
+pub use reload::{ReloadHandle, RuntimeConfig};
 pub use simperby_common;
 pub use simperby_network;
 pub use simperby_repository;
+pub use state_sync::{
+    fetch_snapshot, serve_snapshot, state_sync, StateSnapshot, STATE_SYNC_PROTOCOL,
+};
+pub use supervisor::{Supervisor, SupervisorHandle};
+pub use webhook::WebhookConfig;
 
 use eyre::Result;
 use serde::{Deserialize, Serialize};
 use simperby_common::crypto::*;
 use simperby_common::*;
 use simperby_governance::Governance;
+use simperby_network::access::PeerPattern;
+use simperby_network::proxy::ProxyConfig;
+use simperby_network::psk::PreSharedKey;
 use simperby_network::{Peer, SharedKnownPeers};
 use simperby_repository::raw::{RawRepository, RawRepositoryImpl, SemanticCommit};
 use simperby_repository::CommitHash;
@@ -50,7 +73,14 @@ pub struct Config {
     pub chain_name: String,
 
     pub public_key: PublicKey,
-    pub private_key: PrivateKey,
+    /// Omitted if the node signs through a signer (see `SimperbyNode::initialize_with_signer()`),
+    /// or if the key is in `keystore`.
+    #[serde(default)]
+    pub private_key: Option<PrivateKey>,
+    /// The file (relative to the node directory) that holds the private key encrypted with
+    /// a passphrase, instead of `private_key` (see `simperby_common::keystore`).
+    #[serde(default)]
+    pub keystore: Option<String>,
 
     pub broadcast_interval_ms: Option<u64>,
     pub fetch_interval_ms: Option<u64>,
@@ -60,9 +90,63 @@ pub struct Config {
     /// They're added as a remote repo, named `public_#`.
     pub public_repo_url: Vec<String>,
 
-    pub governance_port: u16,
-    pub consensus_port: u16,
+    /// The port that the DMSs of the governance and the consensus are multiplexed on
+    /// (see `simperby_network::mux`).
+    pub network_port: u16,
     pub repository_port: u16,
+
+    /// The key of a private network, which the connections of the DMSs are encrypted with
+    /// (see `simperby_network::psk`).
+    #[serde(default)]
+    pub pre_shared_key: Option<PreSharedKey>,
+    /// The DNS seeds (`host:port`) to bootstrap the known peers from, re-resolved periodically
+    /// (see `simperby_network::seeds`).
+    #[serde(default)]
+    pub dns_seeds: Vec<String>,
+    /// Whether to discover the peers on the local subnet over mDNS (see `simperby_network::mdns`).
+    #[serde(default)]
+    pub enable_mdns: bool,
+    /// The SOCKS5 proxy to make the outbound connections through (see `simperby_network::proxy`).
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+    /// The peers refused by this node (see `simperby_network::access`).
+    #[serde(default)]
+    pub denied_peers: Vec<PeerPattern>,
+    /// If not empty, the only peers accepted by this node.
+    #[serde(default)]
+    pub allowed_peers: Vec<PeerPattern>,
+
+    #[serde(default)]
+    pub api: ApiConfig,
+}
+
+/// The APIs that the node serves to its operators and applications.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ApiConfig {
+    /// The address that the API servers bind to. It's the loopback if omitted,
+    /// so that an API is exposed to the other hosts only on purpose.
+    pub host: Option<std::net::IpAddr>,
+    /// The port of the WebSocket event server (see [`events`]), which isn't served if omitted.
+    pub events_port: Option<u16>,
+    /// The port of the admin HTTP API (see [`admin`]), which isn't served if omitted.
+    pub admin_port: Option<u16>,
+    /// The port of the liveness and readiness probes (see [`health`]), which aren't served if omitted.
+    pub health_port: Option<u16>,
+    /// The API tokens and their roles.
+    pub auth: AuthConfig,
+    /// The webhooks to notify the node events to (see [`webhook`]).
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+impl ApiConfig {
+    /// Returns the address of the API server on the given port.
+    pub fn address(&self, port: u16) -> std::net::SocketAddr {
+        let host = self
+            .host
+            .unwrap_or(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST));
+        std::net::SocketAddr::new(host, port)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -104,6 +188,33 @@ pub enum CommitInfo {
     }, // TODO
 }
 
+/// The versions of this node and the chain it is configured for.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct VersionInfo {
+    /// The version of the node crate (and so the binary).
+    pub node_version: String,
+    /// The core protocol version that this node implements.
+    pub protocol_version: String,
+    /// The protocol version of the last finalized block, which the chain is currently running.
+    pub chain_protocol_version: String,
+    pub network: simperby_network::NetworkVersion,
+    pub chain_name: String,
+    pub genesis_hash: Hash256,
+}
+
+/// An agenda waiting for the governance approval.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PendingAgenda {
+    pub commit_hash: CommitHash,
+    pub agenda: Agenda,
+    /// The transactions included in the agenda, with their titles.
+    pub transactions: Vec<(CommitHash, String)>,
+    pub voted_power: VotingPower,
+    pub total_voting_power: VotingPower,
+    /// Whether this node has already voted for the agenda.
+    pub voted: bool,
+}
+
 pub type SimperbyNode = node::Node<
     simperby_network::primitives::DummyGossipNetwork,
     simperby_network::storage::StorageImpl,
@@ -135,6 +246,33 @@ pub async fn initialize(config: Config, path: &str) -> Result<SimperbyNode> {
     SimperbyNode::initialize(config, path).await
 }
 
+/// Decrypts the private key in `config.keystore` with the passphrase.
+pub async fn unlock_keystore(config: &Config, path: &str, passphrase: &str) -> Result<PrivateKey> {
+    let file = config
+        .keystore
+        .as_ref()
+        .ok_or_else(|| eyre::eyre!("no keystore in the config"))?;
+    let keystore = keystore::Keystore::load(std::path::Path::new(path).join(file))?;
+    if keystore.public_key != config.public_key {
+        return Err(eyre::eyre!(
+            "the keystore doesn't match the public key of the config"
+        ));
+    }
+    // Argon2 takes a while on purpose.
+    let passphrase = passphrase.to_owned();
+    Ok(tokio::task::spawn_blocking(move || keystore.decrypt(&passphrase)).await??)
+}
+
+/// Initializes a node with the private key in `config.keystore`.
+pub async fn initialize_with_keystore(
+    config: Config,
+    path: &str,
+    passphrase: &str,
+) -> Result<SimperbyNode> {
+    let private_key = unlock_keystore(&config, path, passphrase).await?;
+    SimperbyNode::initialize_with_signer(config, path, private_key.into()).await
+}
+
 /// Clones a remote repository and initializes a node.
 pub async fn clone(_config: Config, _path: &str, _url: &str) -> Result<SimperbyNode> {
     todo!()
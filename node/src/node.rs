@@ -1,14 +1,32 @@
 use super::*;
 use eyre::eyre;
+use futures::FutureExt;
+use simperby_common::signer::Signer;
 use simperby_consensus::{Consensus, ConsensusParameters, ProgressResult};
+use simperby_network::audit::{AuditEntry, AuditQuery, SigningAuditLog};
+use simperby_network::auth_events::{AuthEvents, AuthFailure};
+use simperby_network::bandwidth::{BandwidthMeter, NetworkStats};
+use simperby_network::connections::{self, ConnectionManager, ConnectionState};
+use simperby_network::external_address::{self, ExternalAddress};
+use simperby_network::handshake::{HandshakeTable, PeerVersion};
+use simperby_network::identity::MemberIdentity;
+use simperby_network::latency::{LatencyTable, PeerLatency};
+use simperby_network::mdns;
+use simperby_network::metrics::{MetricsSnapshot, NetworkMetrics};
+use simperby_network::mux::{MuxRpc, MUX_PROTOCOL};
+use simperby_network::peer_store::{self, FilePeerStore};
 use simperby_network::primitives::{GossipNetwork, Storage};
+use simperby_network::priority::{Priority, PriorityBroadcaster};
+use simperby_network::scoring::PeerScoreBoard;
+use simperby_network::seeds;
 use simperby_network::NetworkConfig;
-use simperby_network::{dms, storage::StorageImpl, Dms, Peer, SharedKnownPeers};
-use simperby_repository::raw::{RawRepository, RawRepositoryImpl};
+use simperby_network::{dms, storage::StorageImpl, Dms, Peer, SharedKnownPeers, SharedMembers};
+use simperby_repository::raw::{run_command, RawRepository, RawRepositoryImpl};
 use simperby_repository::DistributedRepository;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
-fn get_timestamp() -> Timestamp {
+pub(crate) fn get_timestamp() -> Timestamp {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap()
@@ -22,19 +40,297 @@ pub struct Node<N: GossipNetwork, S: Storage, R: RawRepository> {
     consensus: Consensus<N, S>,
 
     last_reserved_state: ReservedState,
-    #[allow(dead_code)]
     last_finalized_header: BlockHeader,
 
     path: String,
     network_config: NetworkConfig,
+
+    events: EventBus,
+    health: HealthMonitor,
+    explorer: Option<Explorer>,
+    reload: ReloadHandle,
+    audit_log: Arc<SigningAuditLog>,
+    peer_scores: Arc<PeerScoreBoard>,
+    bandwidth: Arc<BandwidthMeter>,
+    handshakes: Arc<HandshakeTable>,
+    latencies: Arc<LatencyTable>,
+    network_metrics: Arc<NetworkMetrics>,
+    /// The peers refused by the network layer, for the operators.
+    auth_events: AuthEvents,
+    peers: SharedKnownPeers,
+    /// The members of the network, shared with the DMSs and updated on each finalized block.
+    members: SharedMembers,
+    /// The member keys that the connections of `mux` are pinned to, updated along with `members`.
+    identity: Arc<MemberIdentity>,
+    /// The transport of the DMSs, kept so that its port stays bound between `serve()`s.
+    mux: Arc<MuxRpc>,
+    /// The states of the connections to the members, kept alive by a service.
+    connections: Arc<ConnectionManager>,
+    /// The address of this node observed by the peers, advertised in its peer record.
+    external_address: Arc<ExternalAddress>,
+    /// The long-lived tasks of the node (e.g., the API servers), which run while the node is alive.
+    services: SupervisorHandle,
+    /// The latest consensus round observed in the current height, to report only the progress.
+    consensus_round: Option<ConsensusRound>,
+    /// Agendas that have been already notified as events, including the ones existing at startup.
+    notified_agendas: HashSet<Hash256>,
+    /// Members that have been already notified as offline, until they answer again.
+    offline_members: HashSet<PublicKey>,
+    /// Votes that have been already notified as events.
+    notified_votes: HashSet<(Hash256, PublicKey)>,
+    /// The offset added to the system clock, to emulate a skewed clock.
+    clock_offset_ms: i64,
+}
+
+/// How often the connection manager checks which members are due to be pinged.
+const CONNECTION_TICK_MS: u64 = 1000;
+
+/// How long a member may not answer the pings before it is notified as offline.
+const MEMBER_OFFLINE_MS: Timestamp = 5 * 60 * 1000;
+
+/// What the network services of the node work with.
+#[derive(Clone)]
+struct Network {
+    config: NetworkConfig,
+    mux: Arc<MuxRpc>,
+    members: SharedMembers,
+    connections: Arc<ConnectionManager>,
+    external_address: Arc<ExternalAddress>,
+    /// The peer of this node, to advertise in its peer record.
+    own: Peer,
+    signer: Arc<dyn Signer>,
+    peer_store: FilePeerStore,
+}
+
+/// Starts the long-lived tasks of the node under a supervisor, which restarts them on failures.
+fn start_services(
+    config: &Config,
+    events: &EventBus,
+    health: &HealthMonitor,
+    peers: &SharedKnownPeers,
+    reload: &ReloadHandle,
+    admin: admin::AdminContext,
+    network: Network,
+) -> SupervisorHandle {
+    let mut supervisor = Supervisor::new(Default::default()).with_events(events.clone());
+    let (events_, peers_) = (events.clone(), peers.clone());
+    supervisor.add_task(
+        "peer-events",
+        Box::new(move || {
+            Box::pin(events::publish_peer_changes(
+                peers_.clone(),
+                events_.clone(),
+            ))
+        }),
+    );
+    let (peer_store, peers_) = (network.peer_store.clone(), peers.clone());
+    supervisor.add_task(
+        "peer-store",
+        Box::new(move || {
+            Box::pin(peer_store::run_peer_store(
+                peer_store.clone(),
+                peers_.clone(),
+            ))
+        }),
+    );
+    let peers_ = peers.clone();
+    supervisor.add_task(
+        "peer-eviction",
+        Box::new(move || {
+            let mut task = supervisor::AbortOnDrop(peers_.spawn_eviction_task(Default::default()));
+            Box::pin(async move { Ok((&mut task.0).await?) })
+        }),
+    );
+    let (mux, port) = (Arc::clone(&network.mux), network.config.ports[MUX_PROTOCOL]);
+    supervisor.add_task(
+        "keep-alive",
+        Box::new(move || {
+            let mux = Arc::clone(&mux);
+            Box::pin(async move {
+                let _registration = supervisor::AbortOnDrop(
+                    connections::serve_keep_alive(mux.as_ref(), port).await?,
+                );
+                futures::future::pending().await
+            })
+        }),
+    );
+    let mux = Arc::clone(&network.mux);
+    supervisor.add_task(
+        "observed-address",
+        Box::new(move || {
+            let mux = Arc::clone(&mux);
+            Box::pin(async move {
+                let _registration = supervisor::AbortOnDrop(
+                    external_address::serve_observed_address(mux.as_ref(), port).await?,
+                );
+                futures::future::pending().await
+            })
+        }),
+    );
+    let network_ = network.clone();
+    supervisor.add_task(
+        "peer-record",
+        Box::new(move || {
+            let network = network_.clone();
+            Box::pin(async move {
+                let _registration = supervisor::AbortOnDrop(
+                    external_address::serve_advertised_peer_record(
+                        network.mux.as_ref(),
+                        port,
+                        network.config,
+                        network.own,
+                        network.signer,
+                        network.external_address,
+                    )
+                    .await?,
+                );
+                futures::future::pending().await
+            })
+        }),
+    );
+    let (network_, peers_) = (network.clone(), peers.clone());
+    supervisor.add_task(
+        "address-observer",
+        Box::new(move || {
+            Box::pin(
+                external_address::run_address_observer(
+                    Arc::clone(&network_.mux),
+                    network_.config.clone(),
+                    peers_.clone(),
+                    Arc::clone(&network_.external_address),
+                )
+                .map(Ok),
+            )
+        }),
+    );
+    if !network.config.dns_seeds.is_empty() {
+        let (network_, peers_) = (network.clone(), peers.clone());
+        supervisor.add_task(
+            "seeds",
+            Box::new(move || {
+                let mut task = supervisor::AbortOnDrop(seeds::spawn_seed_task(
+                    Arc::clone(&network_.mux),
+                    network_.config.clone(),
+                    network_.members.clone(),
+                    peers_.clone(),
+                    Default::default(),
+                ));
+                Box::pin(async move { Ok((&mut task.0).await?) })
+            }),
+        );
+    }
+    if network.config.enable_mdns {
+        let (network_, peers_) = (network.clone(), peers.clone());
+        supervisor.add_task(
+            "mdns",
+            Box::new(move || {
+                let task = mdns::spawn_mdns_discovery(
+                    Arc::clone(&network_.mux),
+                    network_.config.clone(),
+                    network_.members.clone(),
+                    peers_.clone(),
+                );
+                Box::pin(async move {
+                    if let Some(task) = task? {
+                        let mut task = supervisor::AbortOnDrop(task);
+                        (&mut task.0).await?;
+                    }
+                    Ok(())
+                })
+            }),
+        );
+    }
+    let peers_ = peers.clone();
+    supervisor.add_task(
+        "connections",
+        Box::new(move || {
+            Box::pin(
+                connections::run_connection_manager(
+                    Arc::clone(&network.mux),
+                    Arc::clone(&network.connections),
+                    network.config.clone(),
+                    network.members.clone(),
+                    peers_.clone(),
+                    CONNECTION_TICK_MS,
+                )
+                .map(Ok),
+            )
+        }),
+    );
+    #[cfg(unix)]
+    {
+        let reload = reload.clone();
+        supervisor.add_task(
+            "sighup",
+            Box::new(move || Box::pin(reload::watch_sighup(reload.clone()))),
+        );
+    }
+    if let Some(port) = config.api.health_port {
+        let (health_, address) = (health.clone(), config.api.address(port));
+        supervisor.add_task(
+            "health",
+            Box::new(move || Box::pin(health::serve_health(health_.clone(), address))),
+        );
+    }
+    if let Some(port) = config.api.admin_port {
+        let (address, auth) = (config.api.address(port), config.api.auth.clone());
+        supervisor.add_task(
+            "admin",
+            Box::new(move || Box::pin(admin::serve_admin(admin.clone(), address, auth.clone()))),
+        );
+    }
+    if !config.api.webhooks.is_empty() {
+        let (events_, webhooks) = (events.clone(), config.api.webhooks.clone());
+        supervisor.add_task(
+            "webhooks",
+            Box::new(move || Box::pin(webhook::run_webhooks(events_.clone(), webhooks.clone()))),
+        );
+    }
+    if let Some(port) = config.api.events_port {
+        let (events_, address, auth) = (
+            events.clone(),
+            config.api.address(port),
+            config.api.auth.clone(),
+        );
+        supervisor.add_task(
+            "events",
+            Box::new(move || {
+                Box::pin(events::serve_events(events_.clone(), address, auth.clone()))
+            }),
+        );
+    }
+    supervisor.run()
 }
 
 impl SimperbyNode {
     pub async fn initialize(config: Config, path: &str) -> Result<Self> {
+        let signer = config
+            .private_key
+            .clone()
+            .ok_or_else(|| eyre!("no private key in the config; see `initialize_with_signer()`"))?
+            .into();
+        Self::initialize_with_signer(config, path, signer).await
+    }
+
+    /// Initializes the node with the given signer (e.g., an HSM or a remote signer)
+    /// for everything that it signs, instead of `config.private_key`, which may be omitted.
+    pub async fn initialize_with_signer(
+        config: Config,
+        path: &str,
+        signer: Arc<dyn Signer>,
+    ) -> Result<Self> {
+        if signer.public_key() != config.public_key {
+            return Err(eyre!(
+                "the signer doesn't match the public key of the config"
+            ));
+        }
         // Step 0: initialize the repository module
         let peers: Vec<Peer> =
             serde_spb::from_str(&tokio::fs::read_to_string(&format!("{path}/peers.json")).await?)?;
         let peers = SharedKnownPeers::new_static(peers.clone());
+        // The peers discovered before the restart, which the `peer-store` service has saved.
+        let peer_store = FilePeerStore::new(&format!("{path}/known_peers.json"));
+        peer_store::load_stored_peers(&peer_store, &peers).await?;
         let raw_repository = RawRepositoryImpl::open(&format!("{path}/repository/repo")).await?;
         let repository = DistributedRepository::new(
             raw_repository,
@@ -54,14 +350,7 @@ impl SimperbyNode {
         let network_config = NetworkConfig {
             network_id: reserved_state.genesis_info.chain_name.clone(),
             ports: vec![
-                (
-                    format!("dms-{}", governance_dms_key.clone()),
-                    config.governance_port,
-                ),
-                (
-                    format!("dms-{}", consensus_dms_key.clone()),
-                    config.consensus_port,
-                ),
+                (MUX_PROTOCOL.to_owned(), config.network_port),
                 ("repository".to_owned(), config.repository_port),
             ]
             .into_iter()
@@ -73,37 +362,99 @@ impl SimperbyNode {
                 .collect(),
             public_key: config.public_key.clone(),
             private_key: config.private_key.clone(),
+            dns_seeds: config.dns_seeds.clone(),
+            enable_mdns: config.enable_mdns,
+            pre_shared_key: config.pre_shared_key.clone(),
+            proxy: config.proxy.clone(),
+            denied_peers: config.denied_peers.clone(),
+            allowed_peers: config.allowed_peers.clone(),
         };
         let dms_config = dms::Config {
             fetch_interval: Some(std::time::Duration::from_millis(500)),
             broadcast_interval: Some(std::time::Duration::from_millis(500)),
             network_config: network_config.clone(),
+            limits: Default::default(),
+            dial: Default::default(),
+            chunking: Default::default(),
+            seen_cache: Default::default(),
+            priority: Default::default(),
+            tracing: None,
         };
 
+        let audit_log = Arc::new(
+            simperby_network::audit::SigningAuditLog::open(&format!("{path}/audit.log")).await?,
+        );
+        let peer_scores = Arc::new(PeerScoreBoard::default());
+        let bandwidth = Arc::new(BandwidthMeter::default());
+        let handshakes = Arc::new(HandshakeTable::default());
+        let latencies = Arc::new(LatencyTable::default());
+        let network_metrics = Arc::new(NetworkMetrics::default());
+        let auth_events = AuthEvents::default();
+        // The votes go out before the governance messages queued with them.
+        // It stops once both DMSs are dropped.
+        let (broadcaster, _) = PriorityBroadcaster::spawn(Default::default());
+        let broadcaster = Arc::new(broadcaster);
+        let members = SharedMembers::new(network_config.members.clone());
+        let identity = Arc::new(MemberIdentity::new(&network_config, Arc::clone(&signer)));
+        // Both DMSs are served on the same port, over the same connections to each peer.
+        let mux = Arc::new(
+            MuxRpc::new(Default::default())
+                .with_pre_shared_key(network_config.pre_shared_key.clone())
+                .with_proxy(network_config.proxy.clone())
+                .with_peer_filter(network_config.peer_filter())
+                .with_auth_events(auth_events.clone())
+                .with_member_identity(Some(Arc::clone(&identity))),
+        );
+
         // Step 2: initialize the governance module
         let dms_path = format!("{path}/governance/dms");
         StorageImpl::create(&dms_path).await.unwrap();
         let storage = StorageImpl::open(&dms_path).await.unwrap();
-        let dms = Dms::new(
+        let mut dms = Dms::new(
             storage,
             governance_dms_key,
             dms_config.clone(),
             peers.clone(),
         )
         .await?;
-        let governance = Governance::new(dms, Some(config.private_key.clone())).await?;
+        dms.set_audit_log(Arc::clone(&audit_log));
+        dms.set_peer_scores(Arc::clone(&peer_scores));
+        dms.set_bandwidth_meter(Arc::clone(&bandwidth));
+        dms.set_handshake_table(Arc::clone(&handshakes));
+        dms.set_latency_table(Arc::clone(&latencies));
+        dms.set_network_metrics(Arc::clone(&network_metrics));
+        dms.set_shared_members(members.clone());
+        dms.set_auth_events(auth_events.clone());
+        dms.set_broadcaster(Arc::clone(&broadcaster));
+        dms.set_mux(Arc::clone(&mux))?;
+        dms.set_signer(Arc::clone(&signer));
+        let governance = Governance::new(dms, Some(Arc::clone(&signer))).await?;
 
         // Step 3: initialize the consensus module
         let dms_path = format!("{path}/consensus/dms");
         StorageImpl::create(&dms_path).await.unwrap();
         let storage = StorageImpl::open(&dms_path).await.unwrap();
-        let dms = Dms::new(
+        let mut dms = Dms::new(
             storage,
             consensus_dms_key,
-            dms_config.clone(),
+            dms::Config {
+                priority: Priority::Consensus,
+                ..dms_config.clone()
+            },
             peers.clone(),
         )
         .await?;
+        dms.set_audit_log(Arc::clone(&audit_log));
+        dms.set_peer_scores(Arc::clone(&peer_scores));
+        dms.set_bandwidth_meter(Arc::clone(&bandwidth));
+        dms.set_handshake_table(Arc::clone(&handshakes));
+        dms.set_latency_table(Arc::clone(&latencies));
+        dms.set_network_metrics(Arc::clone(&network_metrics));
+        dms.set_shared_members(members.clone());
+        dms.set_auth_events(auth_events.clone());
+        dms.set_broadcaster(broadcaster);
+        dms.set_mux(Arc::clone(&mux))?;
+        dms.set_signer(Arc::clone(&signer));
         let state_path = format!("{path}/consensus/state");
         StorageImpl::create(&state_path).await.unwrap();
         let consensus_state_storage = StorageImpl::open(&state_path).await.unwrap();
@@ -117,9 +468,60 @@ impl SimperbyNode {
                 repeat_round_for_first_leader: 100,
             },
             0,
-            Some(config.private_key.clone()),
+            Some(Arc::clone(&signer)),
         )
         .await?;
+        let health = HealthMonitor::new(path, HealthConfig::default());
+        health
+            .report_finalized_height(last_finalized_header.height)
+            .await;
+        let events = EventBus::new();
+        let reload = ReloadHandle::new(path, peers.clone());
+        let notified_agendas = repository
+            .get_agendas()
+            .await?
+            .into_iter()
+            .map(|(_, agenda_hash)| agenda_hash)
+            .collect();
+        let admin = admin::AdminContext {
+            path: path.to_owned(),
+            network_config: network_config.clone(),
+            peers: peers.clone(),
+            signer: Arc::clone(&signer),
+            audit_log: Arc::clone(&audit_log),
+        };
+        let connections = Arc::new(ConnectionManager::new(Default::default()));
+        let external_address = Arc::new(ExternalAddress::new(Default::default()));
+        // The node doesn't know its address until the peers observe it.
+        let own = Peer {
+            public_key: config.public_key.clone(),
+            name: reserved_state
+                .query_name(&config.public_key)
+                .ok_or_else(|| eyre!("the node is not a member"))?,
+            address: std::net::SocketAddrV4::new(std::net::Ipv4Addr::UNSPECIFIED, 0),
+            addresses: Vec::new(),
+            ports: network_config.ports.clone(),
+            metadata: Default::default(),
+            recently_seen_timestamp: 0,
+        };
+        let services = start_services(
+            &config,
+            &events,
+            &health,
+            &peers,
+            &reload,
+            admin,
+            Network {
+                config: network_config.clone(),
+                mux: Arc::clone(&mux),
+                members: members.clone(),
+                connections: Arc::clone(&connections),
+                external_address: Arc::clone(&external_address),
+                own,
+                signer,
+                peer_store,
+            },
+        );
         Ok(Self {
             config,
             repository,
@@ -129,6 +531,29 @@ impl SimperbyNode {
             last_finalized_header,
             path: path.to_owned(),
             network_config,
+            events,
+            health,
+            explorer: None,
+            reload,
+            audit_log,
+            peer_scores,
+            bandwidth,
+            handshakes,
+            latencies,
+            network_metrics,
+            auth_events,
+            peers,
+            members,
+            identity,
+            mux,
+            connections,
+            external_address,
+            services,
+            consensus_round: None,
+            notified_agendas,
+            offline_members: HashSet::new(),
+            notified_votes: HashSet::new(),
+            clock_offset_ms: 0,
         })
     }
 
@@ -140,11 +565,187 @@ impl SimperbyNode {
         self.repository.get_raw_mut()
     }
 
+    pub fn get_last_finalized_header(&self) -> &BlockHeader {
+        &self.last_finalized_header
+    }
+
+    /// Returns the versions of this node and the chain it is configured for.
+    pub fn version(&self) -> VersionInfo {
+        VersionInfo {
+            node_version: env!("CARGO_PKG_VERSION").to_owned(),
+            protocol_version: SIMPERBY_CORE_PROTOCOL_VERSION.to_owned(),
+            chain_protocol_version: self.last_finalized_header.version.clone(),
+            network: simperby_network::NetworkVersion::current(&self.network_config.network_id),
+            chain_name: self.last_reserved_state.genesis_info.chain_name.clone(),
+            genesis_hash: self.last_reserved_state.genesis_info.header.to_hash256(),
+        }
+    }
+
+    /// Returns the bus that the node publishes its events to.
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Publishes the executions delivered by the relayer as node events, until the relayer is dropped.
+    pub fn publish_deliveries(
+        &self,
+        relayer: &simperby_settlement::relayer::Relayer,
+    ) -> impl std::future::Future<Output = Result<()>> {
+        events::publish_deliveries(relayer.subscribe(), self.events.clone())
+    }
+
+    /// Returns the monitor that the node reports its status to.
+    pub fn health(&self) -> &HealthMonitor {
+        &self.health
+    }
+
+    /// Queries the log of the signatures produced by this node.
+    pub async fn query_audit_log(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>> {
+        self.audit_log.query(query).await
+    }
+
+    /// Returns the scores of the peers, where the misbehaving peers can be banned or unbanned.
+    pub fn peer_scores(&self) -> &PeerScoreBoard {
+        &self.peer_scores
+    }
+
+    /// Returns the bandwidth used with each peer so far.
+    pub fn network_stats(&self) -> NetworkStats {
+        self.bandwidth.stats()
+    }
+
+    /// Returns the network versions negotiated with the peers, including the refused ones.
+    pub fn peer_versions(&self) -> HashMap<PublicKey, PeerVersion> {
+        self.handshakes.versions()
+    }
+
+    /// Returns the peers running an older network protocol version, which need to upgrade.
+    pub fn outdated_peers(&self) -> Vec<PublicKey> {
+        self.handshakes
+            .outdated_peers(&simperby_network::NetworkVersion::current(
+                &self.network_config.network_id,
+            ))
+    }
+
+    /// Returns the round-trip times to the peers, measured by the periodic pings.
+    pub fn peer_latencies(&self) -> HashMap<PublicKey, PeerLatency> {
+        self.latencies.latencies()
+    }
+
+    /// Subscribes to the peers refused by the network layer from now on
+    /// (e.g., the gossiped messages signed by the non-members).
+    pub fn subscribe_auth_failures(&self) -> tokio::sync::broadcast::Receiver<AuthFailure> {
+        self.auth_events.subscribe()
+    }
+
+    /// Exports the known peers as a snapshot document, to bootstrap another node from.
+    pub async fn export_peers(&self) -> Result<String> {
+        simperby_network::peer_store::export_peers(&self.network_config, &self.peers).await
+    }
+
+    /// Imports the peers in a snapshot document exported by a node of the same network,
+    /// saving the known peers to `peers.json`. Returns the number of the peers added or updated.
+    pub async fn import_peers(&self, document: &str) -> Result<usize> {
+        admin::import_peers(&self.network_config, &self.peers, &self.path, document).await
+    }
+
+    /// Returns a snapshot of the metrics of the network layer.
+    pub async fn network_metrics(&self) -> MetricsSnapshot {
+        self.network_metrics
+            .snapshot(self.peers.len().await, self.bandwidth.stats().total)
+    }
+
+    /// Creates a task serving the network metrics at `GET /metrics` on the given port, for Prometheus.
+    ///
+    /// The task runs indefinitely, independently of the node, so it's up to the caller to spawn it.
+    pub fn metrics_exporter(
+        &self,
+        port: u16,
+    ) -> impl std::future::Future<Output = Result<()>> + Send + 'static {
+        let metrics = Arc::clone(&self.network_metrics);
+        let peers = self.peers.clone();
+        let bandwidth = Arc::clone(&self.bandwidth);
+        simperby_network::metrics::serve_metrics(port, move || {
+            let metrics = Arc::clone(&metrics);
+            let peers = peers.clone();
+            let bandwidth = Arc::clone(&bandwidth);
+            async move { metrics.snapshot(peers.len().await, bandwidth.stats().total) }
+        })
+    }
+
+    /// Returns the address of this node agreed on by the peers, if any.
+    pub fn external_address(&self) -> Option<std::net::IpAddr> {
+        self.external_address.consensus(get_timestamp())
+    }
+
+    /// Returns the states of the connections to the members.
+    pub fn connection_states(&self) -> HashMap<PublicKey, ConnectionState> {
+        self.connections.states()
+    }
+
+    /// Returns the crashes of the long-lived tasks of the node (e.g., the API servers), for each task.
+    pub async fn service_incidents(&self) -> HashMap<String, Vec<supervisor::Incident>> {
+        self.services.incidents().await
+    }
+
+    /// Returns the handle to reload the configuration while the node is running.
+    pub fn reload_handle(&self) -> &ReloadHandle {
+        &self.reload
+    }
+
+    /// Sets the static explorer, rendering the current finalized history into it.
+    ///
+    /// It will be updated on every finalization afterward.
+    pub async fn set_explorer(&mut self, explorer: Explorer) -> Result<()> {
+        self.explorer = Some(explorer);
+        self.update_explorer().await
+    }
+
+    async fn update_explorer(&self) -> Result<()> {
+        if let Some(explorer) = &self.explorer {
+            let commits = self.repository.get_finalized_commits().await?;
+            let reserved_state = self.repository.get_reserved_state().await?;
+            explorer.update(&commits, &reserved_state).await?;
+        }
+        Ok(())
+    }
+
+    /// Skews the clock of this node by the given offset (e.g., to test the clock drift tolerance).
+    pub fn set_clock_offset(&mut self, offset_ms: i64) {
+        self.clock_offset_ms = offset_ms;
+    }
+
+    fn now(&self) -> Timestamp {
+        get_timestamp() + self.clock_offset_ms
+    }
+
     /// TODO: revise this interface
     pub fn network_config(&self) -> &NetworkConfig {
         &self.network_config
     }
 
+    /// Creates a snapshot of the current finalized state, which can be handed to new nodes.
+    pub async fn create_snapshot(&self) -> Result<StateSnapshot> {
+        let headers = self.repository.get_finalized_block_headers().await?;
+        let last_finalization_proof = self.repository.get_last_finalization_proof().await?;
+        let reserved_state = self.repository.get_reserved_state().await?;
+
+        let bundle_path = format!("{}/snapshot.bundle", self.path);
+        run_command(format!(
+            "cd {}/repository/repo && git bundle create {bundle_path} --all",
+            self.path
+        ))?;
+        let repository_bundle = tokio::fs::read(&bundle_path).await?;
+        tokio::fs::remove_file(&bundle_path).await?;
+
+        Ok(StateSnapshot {
+            headers,
+            checkpoint_proof: last_finalization_proof.proof,
+            reserved_state,
+            repository_bundle,
+        })
+    }
+
     /// Synchronizes the `finalized` branch to the given commit.
     pub async fn sync(&mut self, _commmit: CommitHash) -> Result<()> {
         todo!()
@@ -166,26 +767,41 @@ impl SimperbyNode {
             .register_verified_block_hash(header.to_hash256())
             .await?;
         self.consensus
-            .set_proposal_candidate(header.to_hash256(), get_timestamp())
+            .set_proposal_candidate(header.to_hash256(), self.now())
             .await?;
         Ok(commit_hash)
     }
 
     /// Creates an agenda commit on the `work` branch.
     pub async fn create_agenda(&mut self) -> Result<CommitHash> {
-        let (_, commit_hash) = self
+        let (agenda, commit_hash) = self
             .repository
             .create_agenda(self.config.public_key.clone())
             .await?;
+        self.notified_agendas.insert(agenda.to_hash256());
+        self.events.publish(NodeEvent::AgendaCreated {
+            commit_hash,
+            agenda_hash: agenda.to_hash256(),
+        });
         Ok(commit_hash)
     }
 
+    /// Creates a transaction commit on the `work` branch.
+    pub async fn create_transaction(&mut self, transaction: Transaction) -> Result<CommitHash> {
+        self.repository.create_transaction(transaction).await
+    }
+
+    /// Returns all the transactions from the genesis to the `work` branch.
+    pub async fn get_transactions(&self) -> Result<Vec<Transaction>> {
+        self.repository.get_transactions().await
+    }
+
     /// Creates an extra-agenda transaction on the `work` branch.
     pub async fn create_extra_agenda_transaction(
         &mut self,
-        _tx: ExtraAgendaTransaction,
-    ) -> Result<()> {
-        unimplemented!()
+        tx: ExtraAgendaTransaction,
+    ) -> Result<CommitHash> {
+        self.repository.create_extra_agenda_transaction(&tx).await
     }
 
     /// Votes on the agenda corresponding to the given `agenda_commit` and propagates the result.
@@ -204,6 +820,93 @@ impl SimperbyNode {
         Ok(())
     }
 
+    /// Vetoes the agenda corresponding to the given `agenda_commit`.
+    ///
+    /// This is local; it only prevents the agenda from being voted by this node.
+    pub async fn veto_agenda(&mut self, agenda_commit: CommitHash) -> Result<()> {
+        let pending = self.get_pending_agendas().await?;
+        match pending.iter().find(|x| x.commit_hash == agenda_commit) {
+            Some(x) if x.voted => Err(eyre!("already voted for agenda {}", agenda_commit)),
+            Some(_) => self.repository.veto(agenda_commit).await,
+            None => Err(eyre!(
+                "the given commit hash {} is not one of the valid agendas",
+                agenda_commit
+            )),
+        }
+    }
+
+    /// Lists the agendas waiting for the governance approval, except the ones vetoed by this node.
+    pub async fn get_pending_agendas(&self) -> Result<Vec<PendingAgenda>> {
+        let raw = self.repository.get_raw();
+        let finalized = raw
+            .locate_branch(simperby_repository::FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let governance_set = self
+            .last_reserved_state
+            .get_governance_set()
+            .map_err(|e| eyre!(e))?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let governance_state = self.governance.read().await?;
+
+        let mut result = Vec::new();
+        for (commit_hash, agenda_hash) in self.repository.get_agendas().await? {
+            if self.repository.is_vetoed(commit_hash).await? {
+                continue;
+            }
+            let agenda = match self.show(commit_hash).await? {
+                CommitInfo::Agenda { agenda, .. } => agenda,
+                _ => return Err(eyre!("commit {} is not an agenda commit", commit_hash)),
+            };
+            let mut transactions = Vec::new();
+            for commit in raw.query_commit_path(finalized, commit_hash).await? {
+                let semantic_commit = raw.read_semantic_commit(commit).await?;
+                if let Commit::Transaction(_) =
+                    simperby_repository::format::from_semantic_commit(semantic_commit.clone())?
+                {
+                    transactions.push((commit, semantic_commit.title));
+                }
+            }
+            let votes = governance_state.votes.get(&agenda_hash);
+            result.push(PendingAgenda {
+                commit_hash,
+                agenda,
+                transactions,
+                voted_power: votes
+                    .map(|votes| {
+                        votes
+                            .keys()
+                            .filter_map(|voter| governance_set.get(voter))
+                            .sum()
+                    })
+                    .unwrap_or(0),
+                total_voting_power: governance_set.values().sum(),
+                voted: votes.map_or(false, |votes| votes.contains_key(&self.config.public_key)),
+            });
+        }
+        Ok(result)
+    }
+
+    /// Returns the diffs of the transactions included in the given agenda.
+    pub async fn get_agenda_diff(&self, agenda_commit: CommitHash) -> Result<String> {
+        let agenda = self
+            .get_pending_agendas()
+            .await?
+            .into_iter()
+            .find(|x| x.commit_hash == agenda_commit)
+            .ok_or_else(|| {
+                eyre!(
+                    "the given commit hash {} is not one of the valid agendas",
+                    agenda_commit
+                )
+            })?;
+        let mut diff = String::new();
+        for (commit_hash, _) in agenda.transactions {
+            diff += &self.repository.get_raw().show_commit(commit_hash).await?;
+        }
+        Ok(diff)
+    }
+
     /// Vetoes the current round.
     pub async fn veto_round(&mut self) -> Result<()> {
         unimplemented!()
@@ -261,15 +964,72 @@ impl SimperbyNode {
     ///
     /// TODO: it has to consume the object if finalized.
     pub async fn progress_for_consensus(&mut self) -> Result<String> {
-        let result = self.consensus.progress(get_timestamp()).await?;
+        let timestamp = self.now();
+        let result = self.consensus.progress(timestamp).await?;
+        // The consensus is progressing only if it moves to a new round or height.
+        let mut progressed = false;
         for result in result.iter() {
+            let round = match result {
+                ProgressResult::Proposed(round, _, _)
+                | ProgressResult::NonNilPreVoted(round, _, _)
+                | ProgressResult::NonNilPreCommitted(round, _, _)
+                | ProgressResult::NilPreVoted(round, _)
+                | ProgressResult::NilPreCommitted(round, _) => Some(*round),
+                _ => None,
+            };
+            if round.is_some() && round > self.consensus_round {
+                self.consensus_round = round;
+                progressed = true;
+            }
             if let ProgressResult::Finalized(hash, _, proof) = result {
+                self.consensus_round = None;
+                progressed = true;
                 self.repository.sync(hash, proof).await?;
+                self.last_finalized_header =
+                    self.repository.get_last_finalized_block_header().await?;
+                self.update_members().await?;
+                self.events.publish(NodeEvent::BlockFinalized {
+                    height: self.last_finalized_header.height,
+                    hash: *hash,
+                });
+                self.health
+                    .report_finalized_height(self.last_finalized_header.height)
+                    .await;
+                self.update_explorer().await?;
             }
         }
+        if progressed {
+            self.health.report_consensus_progress(timestamp).await;
+        }
         Ok(format!("{result:?}"))
     }
 
+    /// Applies the member set of the latest reserved state to the network layer,
+    /// without restarting it.
+    ///
+    /// The members whose keys have been rotated are known to the peers only by the new keys.
+    async fn update_members(&mut self) -> Result<()> {
+        let reserved_state = self.repository.get_reserved_state().await?;
+        for member in &reserved_state.members {
+            if let Some(old_key) = self.last_reserved_state.query_public_key(&member.name) {
+                if old_key != member.public_key {
+                    self.peers
+                        .rotate_key(&old_key, member.public_key.clone())
+                        .await;
+                }
+            }
+        }
+        let members = reserved_state
+            .members
+            .iter()
+            .map(|m| m.public_key.clone())
+            .collect::<Vec<_>>();
+        self.identity.update_members(members.clone());
+        self.members.update_members(members);
+        self.last_reserved_state = reserved_state;
+        Ok(())
+    }
+
     /// Gets the current status of the consensus.
     pub async fn get_consensus_status(&self) -> Result<ConsensusStatus> {
         todo!()
@@ -282,6 +1042,7 @@ impl SimperbyNode {
 
     pub async fn serve(self, ms: u64) -> Result<Self> {
         let repository_port = self.config.repository_port;
+        self.health.set_network_bound(true).await;
 
         let t1 = tokio::spawn(async move { self.governance.serve(ms).await.unwrap() });
         let t2 = tokio::spawn(async move { self.consensus.serve(ms).await.unwrap() });
@@ -299,6 +1060,7 @@ impl SimperbyNode {
         let governance = t1.await?;
         let consensus = t2.await?;
         t3.await?;
+        self.health.set_network_bound(false).await;
 
         Ok(Self {
             governance,
@@ -309,6 +1071,29 @@ impl SimperbyNode {
             last_finalized_header: self.last_finalized_header,
             path: self.path,
             network_config: self.network_config,
+            events: self.events,
+            health: self.health,
+            explorer: self.explorer,
+            reload: self.reload,
+            audit_log: self.audit_log,
+            peer_scores: self.peer_scores,
+            bandwidth: self.bandwidth,
+            handshakes: self.handshakes,
+            latencies: self.latencies,
+            network_metrics: self.network_metrics,
+            auth_events: self.auth_events,
+            peers: self.peers,
+            members: self.members,
+            identity: self.identity,
+            mux: self.mux,
+            connections: self.connections,
+            external_address: self.external_address,
+            services: self.services,
+            consensus_round: self.consensus_round,
+            notified_agendas: self.notified_agendas,
+            offline_members: self.offline_members,
+            notified_votes: self.notified_votes,
+            clock_offset_ms: self.clock_offset_ms,
         })
     }
 
@@ -326,6 +1111,16 @@ impl SimperbyNode {
             .into_iter()
             .collect::<HashMap<_, _>>();
         let governance_state = self.governance.read().await?;
+        for (agenda, votes) in governance_state.votes.iter() {
+            for voter in votes.keys() {
+                if self.notified_votes.insert((*agenda, voter.clone())) {
+                    self.events.publish(NodeEvent::VoteReceived {
+                        agenda_hash: *agenda,
+                        voter: voter.clone(),
+                    });
+                }
+            }
+        }
         let votes: Vec<(Hash256, VotingPower)> = governance_state
             .votes
             .iter()
@@ -356,6 +1151,32 @@ impl SimperbyNode {
             }
         }
 
+        // Notify the agendas received from the peers.
+        for (commit_hash, agenda_hash) in self.repository.get_agendas().await? {
+            if self.notified_agendas.insert(agenda_hash) {
+                self.events.publish(NodeEvent::AgendaCreated {
+                    commit_hash,
+                    agenda_hash,
+                });
+            }
+        }
+        self.publish_offline_members();
+
+        // Report the heights that the peers have finalized.
+        for (_, branch, commit_hash) in self
+            .repository
+            .get_raw()
+            .list_remote_tracking_branches()
+            .await?
+        {
+            if branch != simperby_repository::FINALIZED_BRANCH_NAME {
+                continue;
+            }
+            if let Ok(Commit::Block(header)) = self.repository.read_commit(commit_hash).await {
+                self.health.report_peer_height(header.height).await;
+            }
+        }
+
         // Update consensus
         for (_, block_hash) in self.repository.get_blocks().await? {
             self.consensus
@@ -365,6 +1186,31 @@ impl SimperbyNode {
         Ok(())
     }
 
+    /// Notifies the members that have gone offline, once until they answer the pings again.
+    fn publish_offline_members(&mut self) {
+        let members = self
+            .last_reserved_state
+            .members
+            .iter()
+            .map(|m| m.public_key.clone())
+            .filter(|m| *m != self.config.public_key)
+            .collect::<Vec<_>>();
+        let offline = events::find_offline_members(
+            &self.latencies.latencies(),
+            &members,
+            self.now(),
+            MEMBER_OFFLINE_MS,
+        );
+        let offline_members = offline.iter().map(|(m, _)| m.clone()).collect();
+        for (member, last_seen) in offline {
+            if !self.offline_members.contains(&member) {
+                self.events
+                    .publish(NodeEvent::MemberOffline { member, last_seen });
+            }
+        }
+        self.offline_members = offline_members;
+    }
+
     /// Broadcasts all the local messages and reports the result.
     pub async fn broadcast(&mut self) -> Result<Vec<String>> {
         let t1 = async { self.governance.broadcast().await };
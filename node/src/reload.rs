@@ -0,0 +1,153 @@
+//! Reloading a subset of the configuration without restarting the node.
+//!
+//! The following are reloaded from the node directory.
+//!
+//! - `peers.json`: the known peers
+//! - `runtime.json`: [`RuntimeConfig`] (the log level)
+//!
+//! A reload is triggered by [`ReloadHandle::reload`] or by `SIGHUP`,
+//! which the node watches with [`watch_sighup`] on Unix.
+use super::*;
+use eyre::eyre;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// The part of the configuration that can be changed while the node is running.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    /// The maximum log level (`error`, `warn`, `info`, `debug` or `trace`).
+    pub log_level: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReloadHandle {
+    path: String,
+    peers: SharedKnownPeers,
+    runtime: Arc<RwLock<RuntimeConfig>>,
+}
+
+impl ReloadHandle {
+    pub fn new(path: &str, peers: SharedKnownPeers) -> Self {
+        Self {
+            path: path.to_owned(),
+            peers,
+            runtime: Default::default(),
+        }
+    }
+
+    pub async fn runtime(&self) -> RuntimeConfig {
+        self.runtime.read().await.clone()
+    }
+
+    /// Reloads the configuration from the files, returning the new runtime config.
+    ///
+    /// Nothing is applied if any of the files is invalid.
+    pub async fn reload(&self) -> Result<RuntimeConfig> {
+        let peers: Vec<Peer> = serde_spb::from_str(
+            &tokio::fs::read_to_string(format!("{}/peers.json", self.path)).await?,
+        )?;
+        let runtime_path = format!("{}/runtime.json", self.path);
+        let runtime: RuntimeConfig = if tokio::fs::metadata(&runtime_path).await.is_ok() {
+            serde_spb::from_str(&tokio::fs::read_to_string(&runtime_path).await?)?
+        } else {
+            RuntimeConfig::default()
+        };
+        let log_level = runtime
+            .log_level
+            .as_ref()
+            .map(|x| {
+                x.parse::<log::LevelFilter>()
+                    .map_err(|_| eyre!("invalid log level: {}", x))
+            })
+            .transpose()?;
+
+        if let Some(log_level) = log_level {
+            log::set_max_level(log_level);
+        }
+        self.peers.replace_all(peers).await;
+        *self.runtime.write().await = runtime.clone();
+        log::info!("configuration reloaded");
+        Ok(runtime)
+    }
+}
+
+/// Reloads the configuration on every `SIGHUP`, indefinitely.
+#[cfg(unix)]
+pub async fn watch_sighup(handle: ReloadHandle) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = signal(SignalKind::hangup())?;
+    while hangup.recv().await.is_some() {
+        if let Err(e) = handle.reload().await {
+            log::error!("failed to reload the configuration: {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reload() {
+        let path = std::env::temp_dir().join(format!("simperby-reload-{}", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        tokio::fs::create_dir_all(&path).await.unwrap();
+        let peer = Peer {
+            public_key: generate_keypair("peer").0,
+            name: "peer".to_owned(),
+            address: "127.0.0.1:1".parse().unwrap(),
+            addresses: Vec::new(),
+            ports: Default::default(),
+            metadata: Default::default(),
+            recently_seen_timestamp: 0,
+        };
+        tokio::fs::write(
+            format!("{path}/peers.json"),
+            serde_spb::to_string(&vec![peer.clone()]).unwrap(),
+        )
+        .await
+        .unwrap();
+        let runtime = RuntimeConfig {
+            log_level: Some("info".to_owned()),
+        };
+        tokio::fs::write(
+            format!("{path}/runtime.json"),
+            serde_spb::to_string(&runtime).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        let peers = SharedKnownPeers::new_static(Vec::new());
+        let handle = ReloadHandle::new(&path, peers.clone());
+        assert_eq!(handle.reload().await.unwrap(), runtime);
+        assert_eq!(handle.runtime().await, runtime);
+        assert_eq!(peers.read().await, vec![peer]);
+
+        tokio::fs::write(format!("{path}/runtime.json"), "{\"log_level\": \"loud\"}")
+            .await
+            .unwrap();
+        assert!(handle.reload().await.is_err());
+        assert_eq!(handle.runtime().await, runtime);
+
+        // A `SIGHUP` reloads the configuration as well.
+        #[cfg(unix)]
+        {
+            let task = tokio::spawn(watch_sighup(handle.clone()));
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            tokio::fs::write(format!("{path}/runtime.json"), "{\"log_level\": \"debug\"}")
+                .await
+                .unwrap();
+            let status = std::process::Command::new("kill")
+                .arg("-HUP")
+                .arg(std::process::id().to_string())
+                .status()
+                .unwrap();
+            assert!(status.success());
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            assert_eq!(handle.runtime().await.log_level.as_deref(), Some("debug"));
+            task.abort();
+        }
+        tokio::fs::remove_dir_all(&path).await.unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+//! Snapshot-based state synchronization.
+//!
+//! Instead of replaying and verifying every commit from the genesis,
+//! a new node can start from a recent snapshot provided by a peer.
+//! The snapshot carries the chain of finalized block headers, which is verified
+//! header-to-header (each header carries the finalization proof of the previous one)
+//! starting from a header that the new node already trusts (usually the genesis header).
+//!
+//! A node serves its snapshot with [`serve_snapshot()`] under [`STATE_SYNC_PROTOCOL`],
+//! and a new node gets one with [`fetch_snapshot()`], asking for the headers from its trusted one.
+use super::*;
+use eyre::eyre;
+use simperby_common::verify;
+use simperby_network::primitives::RpcPrimitive;
+use simperby_repository::raw::run_command;
+use std::sync::Arc;
+
+/// The protocol (see `Peer::ports`) that the snapshots are served with.
+pub const STATE_SYNC_PROTOCOL: &str = "state-sync";
+
+/// A verifiable snapshot of a node's finalized state.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StateSnapshot {
+    /// The finalized block headers, in ascending order of height.
+    ///
+    /// They start from the genesis, or from the height requested by the new node
+    /// (see [`Self::since()`]).
+    /// The last one is the checkpoint that the snapshot is taken at.
+    pub headers: Vec<BlockHeader>,
+    /// The finalization proof of the checkpoint header.
+    pub checkpoint_proof: FinalizationProof,
+    /// The reserved state at the checkpoint.
+    pub reserved_state: ReservedState,
+    /// A Git bundle (`git bundle create --all`) of the repository at the checkpoint.
+    pub repository_bundle: Vec<u8>,
+}
+
+impl StateSnapshot {
+    /// Returns the header that the snapshot is taken at.
+    pub fn checkpoint(&self) -> Result<&BlockHeader> {
+        self.headers
+            .last()
+            .ok_or_else(|| eyre!("snapshot has no block header"))
+    }
+
+    /// Drops the headers below the given height, which the new node doesn't need to verify.
+    pub fn since(mut self, height: BlockHeight) -> Result<Self> {
+        let checkpoint_height = self.checkpoint()?.height;
+        if height > checkpoint_height {
+            return Err(eyre!(
+                "the snapshot is taken at height {checkpoint_height}, below {height}"
+            ));
+        }
+        self.headers.retain(|header| header.height >= height);
+        Ok(self)
+    }
+
+    /// Verifies the snapshot against the given trusted header.
+    ///
+    /// The trusted header must be one of the headers in the snapshot;
+    /// every header after it is verified with the finalization proof carried by its child,
+    /// and the checkpoint is verified with `checkpoint_proof`.
+    pub fn verify(&self, trusted_header: &BlockHeader) -> Result<()> {
+        let position = self
+            .headers
+            .iter()
+            .position(|header| header == trusted_header)
+            .ok_or_else(|| {
+                eyre!(
+                    "the trusted header (height {}) is not in the snapshot",
+                    trusted_header.height
+                )
+            })?;
+        for pair in self.headers[position..].windows(2) {
+            verify::verify_header_to_header(&pair[0], &pair[1])
+                .map_err(|e| eyre!("invalid header chain at height {}: {}", pair[1].height, e))?;
+        }
+        let checkpoint = self.checkpoint()?;
+        verify::verify_finalization_proof(checkpoint, &self.checkpoint_proof)
+            .map_err(|e| eyre!("invalid checkpoint finalization proof: {}", e))?;
+        // The genesis info can be checked only if the snapshot starts from the genesis;
+        // otherwise the validator set below is what ties the reserved state to the verified chain.
+        if self.headers[0].height == 0 && self.reserved_state.genesis_info.header != self.headers[0]
+        {
+            return Err(eyre!("the reserved state has a different genesis header"));
+        }
+        let validator_set = self
+            .reserved_state
+            .get_validator_set()
+            .map_err(|e| eyre!("invalid reserved state: {}", e))?;
+        if validator_set != checkpoint.validator_set {
+            return Err(eyre!(
+                "the reserved state does not match the validator set of the checkpoint"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Initializes a node from the given snapshot, after verifying it against the trusted header.
+///
+/// `path` must not contain a repository; it will be created from the bundle in the snapshot.
+/// The consensus and governance storages are created empty and filled by the peers
+/// once the node starts to participate.
+pub async fn state_sync(
+    config: Config,
+    path: &str,
+    snapshot: StateSnapshot,
+    trusted_header: &BlockHeader,
+) -> Result<SimperbyNode> {
+    snapshot.verify(trusted_header)?;
+    if snapshot.reserved_state.genesis_info.chain_name != config.chain_name {
+        return Err(eyre!(
+            "chain name mismatch: expected {}, got {}",
+            config.chain_name,
+            snapshot.reserved_state.genesis_info.chain_name
+        ));
+    }
+
+    let bundle_path = format!("{path}/snapshot.bundle");
+    tokio::fs::write(&bundle_path, &snapshot.repository_bundle).await?;
+    tokio::fs::create_dir_all(format!("{path}/repository")).await?;
+    run_command(format!(
+        "cd {path}/repository && git clone {bundle_path} repo"
+    ))?;
+    run_command(format!(
+        "cd {path}/repository/repo && git checkout --detach"
+    ))?;
+    for branch in [
+        simperby_repository::FINALIZED_BRANCH_NAME,
+        simperby_repository::FP_BRANCH_NAME,
+        simperby_repository::WORK_BRANCH_NAME,
+    ] {
+        run_command(format!(
+            "cd {path}/repository/repo && git branch -f {branch} origin/{branch}"
+        ))?;
+    }
+    run_command(format!(
+        "cd {path}/repository/repo && git checkout {} && git remote remove origin",
+        simperby_repository::FINALIZED_BRANCH_NAME
+    ))?;
+    tokio::fs::remove_file(&bundle_path).await?;
+
+    let node = SimperbyNode::initialize(config, path).await?;
+    // The bundle is not covered by the header verification; make sure it agrees with it.
+    if node.get_last_finalized_header() != snapshot.checkpoint()? {
+        return Err(eyre!(
+            "the repository bundle does not match the verified checkpoint"
+        ));
+    }
+    Ok(node)
+}
+
+/// Serves the snapshot on the given port, to the peers fetching it with [`fetch_snapshot()`].
+pub async fn serve_snapshot(
+    rpc: &impl RpcPrimitive,
+    port: u16,
+    snapshot: StateSnapshot,
+) -> Result<tokio::task::JoinHandle<Result<()>>> {
+    let snapshot = Arc::new(snapshot);
+    rpc.serve(port, move |height: BlockHeight| {
+        let snapshot = Arc::clone(&snapshot);
+        async move {
+            StateSnapshot::clone(&snapshot)
+                .since(height)
+                .map_err(|e| e.to_string())
+        }
+    })
+    .await
+}
+
+/// Fetches a snapshot from the peers serving [`STATE_SYNC_PROTOCOL`],
+/// returning the first one that verifies against the trusted header.
+pub async fn fetch_snapshot(
+    rpc: &impl RpcPrimitive,
+    peers: &[Peer],
+    trusted_header: &BlockHeader,
+) -> Result<StateSnapshot> {
+    for peer in peers
+        .iter()
+        .filter(|peer| peer.ports.contains_key(STATE_SYNC_PROTOCOL))
+    {
+        let snapshot = match rpc
+            .request::<_, StateSnapshot>(peer, STATE_SYNC_PROTOCOL, trusted_header.height)
+            .await
+        {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                log::warn!("failed to fetch a snapshot from {}: {e}", peer.public_key);
+                continue;
+            }
+        };
+        match snapshot.verify(trusted_header) {
+            Ok(()) => return Ok(snapshot),
+            Err(e) => log::warn!("invalid snapshot from {}: {e}", peer.public_key),
+        }
+    }
+    Err(eyre!("no peer provided a valid snapshot"))
+}
@@ -0,0 +1,237 @@
+//! Supervision of the long-running internal tasks of the node.
+//!
+//! A task is registered with a factory that creates a fresh instance of it.
+//! When the task fails (returns an error or panics), the supervisor records an incident,
+//! publishes a [`NodeEvent::SubsystemRestarted`] and restarts it with an exponential backoff.
+//! A task that finishes successfully is not restarted.
+//!
+//! The tasks live as long as the [`SupervisorHandle`]; dropping it stops them.
+use super::*;
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub type TaskFactory = Box<dyn Fn() -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackoffConfig {
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: 500,
+            max_delay_ms: 60_000,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Incident {
+    pub timestamp: Timestamp,
+    pub reason: String,
+}
+
+pub struct Supervisor {
+    tasks: Vec<(String, TaskFactory)>,
+    backoff: BackoffConfig,
+    events: Option<EventBus>,
+}
+
+/// A handle to the running supervisor, which stops the tasks when dropped.
+pub struct SupervisorHandle {
+    monitors: Vec<tokio::task::JoinHandle<()>>,
+    incidents: Arc<RwLock<HashMap<String, Vec<Incident>>>>,
+}
+
+impl Supervisor {
+    pub fn new(backoff: BackoffConfig) -> Self {
+        Self {
+            tasks: Vec::new(),
+            backoff,
+            events: None,
+        }
+    }
+
+    /// Publishes the incidents to the given event bus.
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    pub fn add_task(&mut self, name: &str, factory: TaskFactory) {
+        self.tasks.push((name.to_owned(), factory));
+    }
+
+    /// Starts all the registered tasks.
+    pub fn run(self) -> SupervisorHandle {
+        let incidents: Arc<RwLock<HashMap<String, Vec<Incident>>>> = Default::default();
+        let monitors = self
+            .tasks
+            .into_iter()
+            .map(|(name, factory)| {
+                tokio::spawn(monitor(
+                    name,
+                    factory,
+                    self.backoff.clone(),
+                    self.events.clone(),
+                    Arc::clone(&incidents),
+                ))
+            })
+            .collect();
+        SupervisorHandle {
+            monitors,
+            incidents,
+        }
+    }
+}
+
+/// Aborts the task when dropped, so that an instance doesn't outlive its monitor.
+pub(crate) struct AbortOnDrop<T>(pub tokio::task::JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn monitor(
+    name: String,
+    factory: TaskFactory,
+    backoff: BackoffConfig,
+    events: Option<EventBus>,
+    incidents: Arc<RwLock<HashMap<String, Vec<Incident>>>>,
+) {
+    let mut delay = backoff.initial_delay_ms;
+    let mut restarts = 0;
+    loop {
+        let mut instance = AbortOnDrop(tokio::spawn(factory()));
+        let reason = match (&mut instance.0).await {
+            Ok(Ok(())) => return,
+            Ok(Err(e)) => e.to_string(),
+            Err(e) if e.is_panic() => format!("panicked: {e}"),
+            Err(_) => return,
+        };
+        restarts += 1;
+        log::error!("subsystem {name} crashed (restarting in {delay}ms): {reason}");
+        incidents
+            .write()
+            .await
+            .entry(name.clone())
+            .or_default()
+            .push(Incident {
+                timestamp: crate::node::get_timestamp(),
+                reason: reason.clone(),
+            });
+        if let Some(events) = &events {
+            events.publish(NodeEvent::SubsystemRestarted {
+                name: name.clone(),
+                reason,
+                restarts,
+            });
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+        delay = std::cmp::min(delay * 2, backoff.max_delay_ms);
+    }
+}
+
+impl SupervisorHandle {
+    /// Returns the incidents so far, for each task.
+    pub async fn incidents(&self) -> HashMap<String, Vec<Incident>> {
+        self.incidents.read().await.clone()
+    }
+
+    /// Waits until all the tasks finish successfully.
+    pub async fn join(mut self) {
+        for monitor in std::mem::take(&mut self.monitors) {
+            let _ = monitor.await;
+        }
+    }
+
+    /// Stops the tasks, including their currently running instances.
+    pub fn abort(&self) {
+        for monitor in &self.monitors {
+            monitor.abort();
+        }
+    }
+}
+
+impl Drop for SupervisorHandle {
+    fn drop(&mut self) {
+        self.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn restart() {
+        let count = Arc::new(AtomicU32::new(0));
+        let count_ = Arc::clone(&count);
+        let mut supervisor = Supervisor::new(BackoffConfig {
+            initial_delay_ms: 1,
+            max_delay_ms: 10,
+        });
+        let events = EventBus::new();
+        let mut receiver = events.subscribe();
+        supervisor = supervisor.with_events(events);
+        supervisor.add_task(
+            "flaky",
+            Box::new(move || {
+                let count = Arc::clone(&count_);
+                Box::pin(async move {
+                    match count.fetch_add(1, Ordering::SeqCst) {
+                        0 => Err(eyre::eyre!("failure")),
+                        1 => panic!("panic"),
+                        _ => Ok(()),
+                    }
+                })
+            }),
+        );
+        let handle = supervisor.run();
+        let incidents = {
+            let incidents = Arc::clone(&handle.incidents);
+            handle.join().await;
+            incidents
+        };
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+        assert_eq!(incidents.read().await["flaky"].len(), 2);
+        assert!(matches!(
+            receiver.recv().await.unwrap(),
+            NodeEvent::SubsystemRestarted { restarts: 1, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn stop_on_drop() {
+        let count = Arc::new(AtomicU32::new(0));
+        let count_ = Arc::clone(&count);
+        let mut supervisor = Supervisor::new(Default::default());
+        supervisor.add_task(
+            "ticker",
+            Box::new(move || {
+                let count = Arc::clone(&count_);
+                Box::pin(async move {
+                    loop {
+                        count.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                    }
+                })
+            }),
+        );
+        let handle = supervisor.run();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(count.load(Ordering::SeqCst) > 0);
+        drop(handle);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let stopped = count.load(Ordering::SeqCst);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(count.load(Ordering::SeqCst), stopped);
+    }
+}
@@ -0,0 +1,164 @@
+//! Webhook notifications of node events.
+//!
+//! Each webhook receives an HTTP POST for every event matching its filter.
+//! The body is the JSON of the [`NodeEvent`] unless a template is given;
+//! in a template, the following placeholders are replaced with JSON values.
+//!
+//! - `{{kind}}`: the kind of the event (e.g., `"BlockFinalized"`)
+//! - `{{summary}}`: a human-readable description of the event
+//! - `{{event}}`: the event itself
+//!
+//! For example, `{"text": {{summary}}}` works for Slack incoming webhooks
+//! and `{"content": {{summary}}}` works for Discord.
+use super::*;
+use eyre::eyre;
+use tokio::sync::broadcast;
+
+/// The number of attempts to deliver a notification before giving up.
+const MAX_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// The events to be notified.
+    pub filter: EventFilter,
+    /// The template of the request body. `None` means the plain JSON of the event.
+    pub template: Option<String>,
+}
+
+impl WebhookConfig {
+    /// Renders the request body for the event.
+    pub fn render(&self, event: &NodeEvent) -> Result<String> {
+        let event_json = serde_spb::to_string(event)?;
+        match &self.template {
+            None => Ok(event_json),
+            Some(template) => Ok(template
+                .replace("{{kind}}", &serde_spb::to_string(&event.kind())?)
+                .replace("{{summary}}", &serde_spb::to_string(&event.summary())?)
+                .replace("{{event}}", &event_json)),
+        }
+    }
+}
+
+async fn post(client: &reqwest::Client, url: &str, body: String) -> Result<()> {
+    let mut last_error = None;
+    for attempt in 0..MAX_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(500 << attempt)).await;
+        }
+        match client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .body(body.clone())
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => last_error = Some(eyre!("status {}", response.status())),
+            Err(e) => last_error = Some(e.into()),
+        }
+    }
+    Err(last_error.unwrap())
+}
+
+/// Delivers the events published to the bus to the webhooks, until the bus is closed.
+///
+/// A failure of a webhook is logged and doesn't affect the others.
+pub async fn run_webhooks(bus: EventBus, webhooks: Vec<WebhookConfig>) -> Result<()> {
+    let client = reqwest::Client::new();
+    let mut receiver = bus.subscribe();
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                log::warn!("webhooks lagged behind; {n} events are skipped");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        for webhook in webhooks.iter().filter(|w| w.filter.matches(&event)) {
+            let body = webhook.render(&event)?;
+            let client = client.clone();
+            let url = webhook.url.clone();
+            tokio::spawn(async move {
+                if let Err(e) = post(&client, &url, body).await {
+                    log::warn!("failed to deliver a webhook to {url}: {e}");
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let event = NodeEvent::BlockFinalized {
+            height: 3,
+            hash: Hash256::zero(),
+        };
+        let mut webhook = WebhookConfig {
+            url: "http://localhost".to_owned(),
+            filter: EventFilter::default(),
+            template: None,
+        };
+        assert_eq!(
+            webhook.render(&event).unwrap(),
+            serde_spb::to_string(&event).unwrap()
+        );
+
+        webhook.template = Some(r#"{"text": {{summary}}, "kind": {{kind}}}"#.to_owned());
+        let body = webhook.render(&event).unwrap();
+        assert_eq!(
+            body,
+            format!(
+                r#"{{"text": "{}", "kind": "BlockFinalized"}}"#,
+                event.summary()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn deliver() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let port = simperby_test_suite::dispense_port();
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+            .await
+            .unwrap();
+        let bus = EventBus::new();
+        let webhook = WebhookConfig {
+            url: format!("http://127.0.0.1:{port}/hook"),
+            filter: EventFilter {
+                kinds: vec![EventKind::BlockFinalized].into_iter().collect(),
+                ..Default::default()
+            },
+            template: Some(r#"{"text": {{summary}}}"#.to_owned()),
+        };
+        tokio::spawn(run_webhooks(bus.clone(), vec![webhook]));
+        tokio::task::yield_now().await;
+
+        bus.publish(NodeEvent::PeersChanged { peers: Vec::new() });
+        let event = NodeEvent::BlockFinalized {
+            height: 3,
+            hash: Hash256::zero(),
+        };
+        bus.publish(event.clone());
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let expected_body = format!(r#"{{"text": "{}"}}"#, event.summary());
+        while !String::from_utf8_lossy(&request).ends_with(&expected_body) {
+            let mut buffer = [0; 1024];
+            let n = stream.read(&mut buffer).await.unwrap();
+            assert_ne!(n, 0);
+            request.extend_from_slice(&buffer[..n]);
+        }
+        assert!(String::from_utf8_lossy(&request).starts_with("POST /hook"));
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .await
+            .unwrap();
+    }
+}
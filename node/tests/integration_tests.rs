@@ -3,19 +3,26 @@ use simperby_network::Peer;
 use simperby_node::{genesis, *};
 use simperby_repository::raw::RawRepository;
 use simperby_test_suite::*;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 fn generate_config(key: PrivateKey, chain_name: String) -> Config {
     Config {
         chain_name,
         public_key: key.public_key(),
-        private_key: key,
+        private_key: Some(key),
         broadcast_interval_ms: None,
         fetch_interval_ms: None,
         public_repo_url: vec![],
-        governance_port: dispense_port(),
-        consensus_port: dispense_port(),
+        network_port: dispense_port(),
         repository_port: dispense_port(),
+        pre_shared_key: None,
+        dns_seeds: Vec::new(),
+        enable_mdns: false,
+        proxy: None,
+        denied_peers: Vec::new(),
+        allowed_peers: Vec::new(),
+        keystore: None,
+        api: Default::default(),
     }
 }
 
@@ -35,10 +42,12 @@ async fn normal_1() {
     let (rs, keys) = generate_standard_genesis(5);
     let chain_name = "normal_1".to_owned();
 
-    let configs = keys
+    let mut configs = keys
         .iter()
         .map(|(_, private_key)| generate_config(private_key.clone(), chain_name.clone()))
         .collect::<Vec<_>>();
+    let health_port = dispense_port();
+    configs[0].api.health_port = Some(health_port);
 
     // Step 0: initialize each's repo
     let server_dir = create_temp_dir();
@@ -56,8 +65,9 @@ async fn normal_1() {
                 public_key: configs[0].public_key.clone(),
                 name: "proposer".to_owned(),
                 address: "127.0.0.1:1".parse().unwrap(),
+                addresses: Vec::new(),
                 ports: proposer_node.network_config().ports.clone(),
-                message: "123".to_owned(),
+                metadata: Default::default(),
                 recently_seen_timestamp: 0,
             }],
         )
@@ -76,7 +86,16 @@ async fn normal_1() {
     let serve = tokio::spawn(async move { proposer_node.serve(5000).await.unwrap() });
     sleep_ms(500).await;
     for node in other_nodes.iter_mut() {
+        let mut events = node.events().subscribe();
         node.fetch().await.unwrap();
+        let mut agenda_notified = false;
+        while let Ok(event) = events.try_recv() {
+            agenda_notified |= matches!(
+                event,
+                NodeEvent::AgendaCreated { commit_hash, .. } if commit_hash == agenda_commit
+            );
+        }
+        assert!(agenda_notified);
         node.vote(agenda_commit).await.unwrap();
         node.broadcast().await.unwrap();
     }
@@ -140,6 +159,29 @@ async fn normal_1() {
     let _ = proposer_node.progress_for_consensus().await;
     proposer_node.broadcast().await.unwrap();
 
+    // Step 5: Check the health of the nodes
+    log::info!("STEP 5");
+    let serve = tokio::spawn(async move { proposer_node.serve(3000).await.unwrap() });
+    sleep_ms(500).await;
+    for node in other_nodes.iter_mut() {
+        node.fetch().await.unwrap();
+        let report = node.health().report(0).await;
+        assert_eq!(report.highest_peer_height, 1);
+        assert!(report.within_peer_heights);
+        assert!(report.consensus_progressing);
+    }
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", health_port))
+        .await
+        .unwrap();
+    stream
+        .write_all(b"GET /healthz HTTP/1.1\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"));
+    let proposer_node = serve.await.unwrap();
+
     for node in std::iter::once(proposer_node).chain(other_nodes.into_iter()) {
         let finalized = node
             .get_raw_repo()
@@ -155,3 +197,421 @@ async fn normal_1() {
         assert_eq!(title, ">block: 1");
     }
 }
+
+#[tokio::test]
+async fn private_network() {
+    setup_test();
+    let (rs, keys) = generate_standard_genesis(4);
+    let chain_name = "private_network".to_owned();
+    let key = simperby_network::psk::PreSharedKey::from_passphrase("consortium");
+    let mut configs = keys
+        .iter()
+        .zip([
+            key.clone(),
+            key.clone(),
+            simperby_network::psk::PreSharedKey::from_passphrase("other"),
+            key,
+        ])
+        .map(|((_, private_key), key)| Config {
+            pre_shared_key: Some(key),
+            ..generate_config(private_key.clone(), chain_name.clone())
+        })
+        .collect::<Vec<_>>();
+    // The last node refuses the server, even with the same key.
+    configs[3].denied_peers = vec![simperby_network::access::PeerPattern::Key(
+        keys[0].0.clone(),
+    )];
+
+    let server_dir = create_temp_dir();
+    setup_peer(&server_dir, &[]).await;
+    setup_pre_genesis_repository(&server_dir, rs.clone()).await;
+    genesis(configs[0].clone(), &server_dir).await.unwrap();
+    let mut server_node = initialize(configs[0].clone(), &server_dir).await.unwrap();
+    let mut other_nodes = Vec::new();
+    for config in configs[1..].iter() {
+        let dir = create_temp_dir();
+        copy_repository(&server_dir, &dir).await;
+        setup_peer(
+            &dir,
+            &[Peer {
+                public_key: configs[0].public_key.clone(),
+                name: "server".to_owned(),
+                address: "127.0.0.1:1".parse().unwrap(),
+                addresses: Vec::new(),
+                ports: server_node.network_config().ports.clone(),
+                metadata: Default::default(),
+                recently_seen_timestamp: 0,
+            }],
+        )
+        .await;
+        other_nodes.push(initialize(config.clone(), &dir).await.unwrap());
+    }
+
+    let agenda_commit = server_node.create_agenda().await.unwrap();
+    server_node.vote(agenda_commit).await.unwrap();
+    let serve = tokio::spawn(async move { server_node.serve(3000).await.unwrap() });
+    sleep_ms(500).await;
+    for node in other_nodes.iter_mut() {
+        node.fetch().await.unwrap();
+    }
+    serve.await.unwrap();
+
+    // Only the node with the same key, not refusing the server, gets the vote over the DMS.
+    let voted_power = |agendas: Vec<PendingAgenda>| agendas[0].voted_power;
+    assert!(voted_power(other_nodes[0].get_pending_agendas().await.unwrap()) > 0);
+    for node in &other_nodes[1..] {
+        assert_eq!(voted_power(node.get_pending_agendas().await.unwrap()), 0);
+    }
+}
+
+#[tokio::test]
+async fn external_signer() {
+    setup_test();
+    let (rs, keys) = generate_standard_genesis(2);
+    let chain_name = "external_signer".to_owned();
+    let configs = keys
+        .iter()
+        .map(|(_, private_key)| generate_config(private_key.clone(), chain_name.clone()))
+        .collect::<Vec<_>>();
+
+    let server_dir = create_temp_dir();
+    setup_peer(&server_dir, &[]).await;
+    setup_pre_genesis_repository(&server_dir, rs.clone()).await;
+    genesis(configs[0].clone(), &server_dir).await.unwrap();
+    // The server keeps its key only in the signer.
+    let server_config = Config {
+        private_key: None,
+        ..configs[0].clone()
+    };
+    assert!(initialize(server_config.clone(), &server_dir)
+        .await
+        .is_err());
+    let mut server_node =
+        SimperbyNode::initialize_with_signer(server_config, &server_dir, keys[0].1.clone().into())
+            .await
+            .unwrap();
+    let dir = create_temp_dir();
+    copy_repository(&server_dir, &dir).await;
+    setup_peer(
+        &dir,
+        &[Peer {
+            public_key: configs[0].public_key.clone(),
+            name: "server".to_owned(),
+            address: "127.0.0.1:1".parse().unwrap(),
+            addresses: Vec::new(),
+            ports: server_node.network_config().ports.clone(),
+            metadata: Default::default(),
+            recently_seen_timestamp: 0,
+        }],
+    )
+    .await;
+    let mut other_node = initialize(configs[1].clone(), &dir).await.unwrap();
+
+    // The connection is pinned to the key proven by the signer.
+    let agenda_commit = server_node.create_agenda().await.unwrap();
+    server_node.vote(agenda_commit).await.unwrap();
+    let serve = tokio::spawn(async move { server_node.serve(3000).await.unwrap() });
+    sleep_ms(500).await;
+    other_node.fetch().await.unwrap();
+    serve.await.unwrap();
+    assert!(other_node.get_pending_agendas().await.unwrap()[0].voted_power > 0);
+}
+
+#[tokio::test]
+async fn pending_agendas() {
+    setup_test();
+    let (rs, keys) = generate_standard_genesis(4);
+    let dir = create_temp_dir();
+    setup_peer(&dir, &[]).await;
+    setup_pre_genesis_repository(&dir, rs).await;
+    let config = generate_config(keys[0].1.clone(), "pending_agendas".to_owned());
+    genesis(config.clone(), &dir).await.unwrap();
+    let mut node = initialize(config, &dir).await.unwrap();
+
+    let transaction_commit = node
+        .create_transaction(Transaction {
+            author: keys[0].0.clone(),
+            timestamp: get_timestamp(),
+            head: "Update the readme".to_owned(),
+            body: "".to_owned(),
+            diff: Diff::None,
+        })
+        .await
+        .unwrap();
+    let agenda_commit = node.create_agenda().await.unwrap();
+    let pending = node.get_pending_agendas().await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].commit_hash, agenda_commit);
+    assert_eq!(
+        pending[0].transactions,
+        vec![(transaction_commit, "Update the readme".to_owned())]
+    );
+    assert_eq!(pending[0].voted_power, 0);
+    assert_eq!(pending[0].total_voting_power, 4);
+    assert!(!pending[0].voted);
+    let diff = node.get_agenda_diff(agenda_commit).await.unwrap();
+    assert!(diff.contains("Update the readme"));
+
+    // A vetoed agenda is neither listed nor voted for.
+    node.veto_agenda(agenda_commit).await.unwrap();
+    assert!(node.get_pending_agendas().await.unwrap().is_empty());
+    assert!(node.get_agenda_diff(agenda_commit).await.is_err());
+    assert!(node.vote(agenda_commit).await.is_err());
+}
+
+#[tokio::test]
+async fn keystore() {
+    setup_test();
+    let (rs, keys) = generate_standard_genesis(1);
+    let dir = create_temp_dir();
+    setup_peer(&dir, &[]).await;
+    setup_pre_genesis_repository(&dir, rs).await;
+    let config = generate_config(keys[0].1.clone(), "keystore".to_owned());
+    genesis(config.clone(), &dir).await.unwrap();
+    simperby_common::keystore::Keystore::encrypt(&keys[0].1, "passphrase")
+        .unwrap()
+        .save(format!("{dir}/keystore.json"))
+        .unwrap();
+    let config = Config {
+        private_key: None,
+        keystore: Some("keystore.json".to_owned()),
+        ..config
+    };
+    assert!(initialize_with_keystore(config.clone(), &dir, "wrong")
+        .await
+        .is_err());
+    let node = initialize_with_keystore(config, &dir, "passphrase")
+        .await
+        .unwrap();
+    assert_eq!(node.get_last_finalized_header().height, 0);
+}
+
+async fn http_request(port: u16, request: &str) -> (u16, String) {
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+        .await
+        .unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, body.to_owned())
+}
+
+#[tokio::test]
+async fn stored_peers() {
+    use simperby_network::peer_store::{FilePeerStore, PeerSnapshot, PeerStore};
+    setup_test();
+    let (rs, keys) = generate_standard_genesis(2);
+    let dir = create_temp_dir();
+    setup_peer(&dir, &[]).await;
+    setup_pre_genesis_repository(&dir, rs).await;
+    let config = generate_config(keys[0].1.clone(), "stored_peers".to_owned());
+    genesis(config.clone(), &dir).await.unwrap();
+
+    // A peer discovered before the restart.
+    let peer = Peer {
+        public_key: keys[1].0.clone(),
+        name: "other".to_owned(),
+        address: "127.0.0.1:1".parse().unwrap(),
+        addresses: Vec::new(),
+        ports: Default::default(),
+        metadata: Default::default(),
+        recently_seen_timestamp: 10,
+    };
+    FilePeerStore::new(&format!("{dir}/known_peers.json"))
+        .save(std::slice::from_ref(&peer))
+        .await
+        .unwrap();
+    let node = initialize(config, &dir).await.unwrap();
+    let snapshot: PeerSnapshot = serde_spb::from_str(&node.export_peers().await.unwrap()).unwrap();
+    assert_eq!(snapshot.peers, vec![peer]);
+}
+
+#[tokio::test]
+async fn admin_api() {
+    setup_test();
+    let (rs, keys) = generate_standard_genesis(1);
+    let dir = create_temp_dir();
+    setup_peer(&dir, &[]).await;
+    setup_pre_genesis_repository(&dir, rs).await;
+    let mut config = generate_config(keys[0].1.clone(), "admin_api".to_owned());
+    let port = dispense_port();
+    config.api.admin_port = Some(port);
+    // A service that fails to start is restarted, recording the incidents.
+    let occupied = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    config.api.health_port = Some(occupied.local_addr().unwrap().port());
+    config.api.auth.add_token("reader", Role::ReadOnly);
+    config.api.auth.add_token("operator", Role::Operator);
+    config.api.auth.add_token("admin", Role::SignerAdmin);
+    genesis(config.clone(), &dir).await.unwrap();
+    let node = initialize(config.clone(), &dir).await.unwrap();
+    sleep_ms(200).await;
+
+    let (status, _) = http_request(port, "GET /peers HTTP/1.1\r\n\r\n").await;
+    assert_eq!(status, 401);
+    let (status, document) = http_request(
+        port,
+        "GET /peers HTTP/1.1\r\nAuthorization: Bearer reader\r\n\r\n",
+    )
+    .await;
+    assert_eq!(status, 200);
+    let post = |token: &str, path: &str, body: &str| {
+        format!(
+            "POST {path} HTTP/1.1\r\nAuthorization: Bearer {token}\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    };
+    let (status, _) = http_request(port, &post("reader", "/peers", &document)).await;
+    assert_eq!(status, 403);
+    let (status, imported) = http_request(port, &post("operator", "/peers", &document)).await;
+    assert_eq!((status, imported.as_str()), (200, "0"));
+
+    let (status, _) = http_request(port, &post("operator", "/sign", "hello")).await;
+    assert_eq!(status, 403);
+    let (status, signature) = http_request(port, &post("admin", "/sign", "hello")).await;
+    assert_eq!(status, 200);
+    let signature: TypedSignature<String> = serde_spb::from_str(&signature).unwrap();
+    signature.verify(&"hello".to_owned()).unwrap();
+    assert_eq!(signature.signer(), &config.public_key);
+    let entries = node
+        .query_audit_log(&simperby_network::audit::AuditQuery {
+            context: Some(admin::SIGN_AUDIT_CONTEXT.to_owned()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].data, "hello");
+    assert!(!node.service_incidents().await["health"].is_empty());
+}
+
+#[tokio::test]
+async fn test_cluster() {
+    setup_test();
+    let cluster = TestCluster::new("test_cluster", 4).await.unwrap();
+    assert_eq!(cluster.size(), 4);
+    // The nodes keep the connections to each other, whose peers are configured
+    // and never evicted, even though they're never seen (`recently_seen_timestamp` is 0).
+    sleep_ms(3000).await;
+    let states = cluster.nodes[0].connection_states();
+    assert_eq!(states.len(), 3);
+    assert!(states.values().all(|x| matches!(
+        x,
+        simperby_network::connections::ConnectionState::Connected { .. }
+    )));
+    // The nodes serve the address observations to each other, but the observers are all
+    // in the same subnet, so they never agree on an external address.
+    let incidents = cluster.nodes[0].service_incidents().await;
+    for service in [
+        "observed-address",
+        "peer-record",
+        "address-observer",
+        "peer-eviction",
+    ] {
+        assert!(incidents.get(service).map_or(true, |x| x.is_empty()));
+    }
+    assert_eq!(cluster.nodes[0].external_address(), None);
+    for node in &cluster.nodes {
+        assert_eq!(
+            node.get_last_finalized_header(),
+            &cluster.reserved_state.genesis_info.header
+        );
+    }
+}
+
+#[tokio::test]
+async fn static_explorer() {
+    setup_test();
+    let mut cluster = TestCluster::new("static_explorer", 1).await.unwrap();
+    let out_dir = create_temp_dir();
+    cluster.nodes[0]
+        .set_explorer(Explorer::new(&out_dir))
+        .await
+        .unwrap();
+    let index = tokio::fs::read_to_string(format!("{out_dir}/index.html"))
+        .await
+        .unwrap();
+    assert!(index.contains("block-0.html"));
+    tokio::fs::metadata(format!("{out_dir}/block-0.html"))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn chaos() {
+    setup_test();
+    let cluster = TestCluster::new("chaos", 4).await.unwrap();
+    let proposer_dir = cluster.dirs[0].clone();
+    let mut harness = ChaosHarness::new(cluster);
+
+    use ChaosStep::*;
+    use Fault::*;
+    // Get an agenda approved and propose a block on it.
+    let agenda = harness.node_mut(0).unwrap().create_agenda().await.unwrap();
+    harness.run_script(&[Run { ms: 1000 }]).await.unwrap();
+    for i in 0..4 {
+        harness.node_mut(i).unwrap().vote(agenda).await.unwrap();
+    }
+    harness
+        .run_script(&[Run { ms: 1000 }, Run { ms: 1000 }])
+        .await
+        .unwrap();
+    run_command(format!(
+        "cd {proposer_dir}/repository/repo && git branch -f work HEAD"
+    ))
+    .await;
+    harness.node_mut(0).unwrap().create_block().await.unwrap();
+
+    // The block is finalized by the three live nodes, one with a skewed clock.
+    harness
+        .run_script(&[
+            Inject(Kill(1)),
+            Inject(SkewClock {
+                node: 2,
+                offset_ms: 1_000,
+            }),
+            Inject(FillDisk {
+                node: 3,
+                bytes: 16 * 1024 * 1024,
+            }),
+        ])
+        .await
+        .unwrap();
+    for _ in 0..8 {
+        harness
+            .run_script(&[Progress, Run { ms: 1000 }, Check])
+            .await
+            .unwrap();
+        if [0, 2, 3].iter().all(|i| harness.finalized_height(*i) == 1) {
+            break;
+        }
+    }
+    for i in [0, 2, 3] {
+        assert_eq!(harness.finalized_height(i), 1, "{:?}", harness.incidents);
+    }
+
+    harness
+        .run_script(&[
+            Inject(Partition(vec![vec![0, 2], vec![3]])),
+            Inject(SkewClock {
+                node: 2,
+                offset_ms: 60_000,
+            }),
+            Run { ms: 1000 },
+            Check,
+            Inject(Restart(1)),
+            Run { ms: 1000 },
+            Check,
+            Inject(Heal),
+            Inject(FreeDisk(3)),
+            Run { ms: 1000 },
+            Check,
+        ])
+        .await
+        .unwrap();
+    assert!((0..4).all(|i| harness.is_alive(i)));
+    for i in [0, 2, 3] {
+        assert_eq!(harness.finalized_height(i), 1);
+    }
+}
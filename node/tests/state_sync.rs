@@ -0,0 +1,175 @@
+use simperby_common::*;
+use simperby_network::{rpc::TcpRpc, Peer};
+use simperby_node::*;
+use simperby_repository::raw::RawRepository;
+use simperby_test_suite::*;
+
+/// Returns a snapshot of a chain with a single block on top of the genesis.
+fn generate_snapshot() -> StateSnapshot {
+    let (rs, keys) = generate_standard_genesis(4);
+    let genesis_info = rs.genesis_info.clone();
+    let block_header = BlockHeader {
+        author: keys[0].0.clone(),
+        prev_block_finalization_proof: genesis_info.genesis_proof.clone(),
+        previous_hash: genesis_info.header.to_hash256(),
+        height: 1,
+        timestamp: 0,
+        commit_merkle_root: Hash256::zero(),
+        repository_merkle_root: Hash256::zero(),
+        validator_set: rs.get_validator_set().unwrap(),
+        version: genesis_info.header.version.clone(),
+    };
+    let checkpoint_proof = keys
+        .iter()
+        .map(|(_, private_key)| TypedSignature::sign(&block_header, private_key).unwrap())
+        .collect();
+    StateSnapshot {
+        headers: vec![genesis_info.header, block_header],
+        checkpoint_proof,
+        reserved_state: rs,
+        repository_bundle: Vec::new(),
+    }
+}
+
+#[test]
+fn verify_snapshot() {
+    setup_test();
+    let snapshot = generate_snapshot();
+    snapshot.verify(&snapshot.headers[0]).unwrap();
+    snapshot.verify(&snapshot.headers[1]).unwrap();
+}
+
+#[test]
+fn verify_snapshot_since() {
+    setup_test();
+    let snapshot = generate_snapshot();
+    let trusted_header = snapshot.headers[1].clone();
+    let snapshot = snapshot.since(1).unwrap();
+    assert_eq!(snapshot.headers, vec![trusted_header.clone()]);
+    snapshot.verify(&trusted_header).unwrap();
+    assert!(snapshot.clone().since(2).is_err());
+
+    // The genesis header is not in the snapshot anymore.
+    let (rs, _) = generate_standard_genesis(4);
+    assert!(snapshot.verify(&rs.genesis_info.header).is_err());
+}
+
+#[test]
+fn verify_snapshot_untrusted() {
+    setup_test();
+    let snapshot = generate_snapshot();
+    let (rs, _) = generate_standard_genesis(5);
+    assert!(snapshot.verify(&rs.genesis_info.header).is_err());
+}
+
+#[test]
+fn verify_snapshot_invalid_proof() {
+    setup_test();
+    let mut snapshot = generate_snapshot();
+    snapshot.checkpoint_proof.truncate(2);
+    assert!(snapshot.verify(&snapshot.headers[0]).is_err());
+
+    let mut snapshot = generate_snapshot();
+    snapshot.headers[1].timestamp = 1;
+    assert!(snapshot.verify(&snapshot.headers[0]).is_err());
+}
+
+fn generate_config(key: PrivateKey, chain_name: String) -> Config {
+    Config {
+        chain_name,
+        public_key: key.public_key(),
+        private_key: Some(key),
+        broadcast_interval_ms: None,
+        fetch_interval_ms: None,
+        public_repo_url: vec![],
+        network_port: dispense_port(),
+        repository_port: dispense_port(),
+        pre_shared_key: None,
+        dns_seeds: Vec::new(),
+        enable_mdns: false,
+        proxy: None,
+        denied_peers: Vec::new(),
+        allowed_peers: Vec::new(),
+        keystore: None,
+        api: Default::default(),
+    }
+}
+
+async fn setup_peer(path: &str, peers: &[Peer]) {
+    tokio::fs::write(
+        format!("{path}/peers.json"),
+        serde_spb::to_string(&peers).unwrap(),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn state_sync_from_peer() {
+    setup_test();
+    let (rs, keys) = generate_standard_genesis(4);
+    let configs = keys
+        .iter()
+        .map(|(_, private_key)| {
+            generate_config(private_key.clone(), rs.genesis_info.chain_name.clone())
+        })
+        .collect::<Vec<_>>();
+
+    let server_dir = create_temp_dir();
+    setup_peer(&server_dir, &[]).await;
+    setup_pre_genesis_repository(&server_dir, rs.clone()).await;
+    genesis(configs[0].clone(), &server_dir).await.unwrap();
+    let server_node = initialize(configs[0].clone(), &server_dir).await.unwrap();
+
+    let rpc = TcpRpc::new(Default::default());
+    let port = dispense_port();
+    let _server = serve_snapshot(&rpc, port, server_node.create_snapshot().await.unwrap())
+        .await
+        .unwrap();
+    let peer = Peer {
+        public_key: configs[0].public_key.clone(),
+        name: "server".to_owned(),
+        address: "127.0.0.1:1".parse().unwrap(),
+        addresses: Vec::new(),
+        ports: vec![(STATE_SYNC_PROTOCOL.to_owned(), port)]
+            .into_iter()
+            .collect(),
+        metadata: Default::default(),
+        recently_seen_timestamp: 0,
+    };
+
+    // A snapshot of another chain doesn't verify.
+    let (other_rs, _) = generate_standard_genesis(5);
+    assert!(fetch_snapshot(
+        &rpc,
+        std::slice::from_ref(&peer),
+        &other_rs.genesis_info.header
+    )
+    .await
+    .is_err());
+
+    let trusted_header = rs.genesis_info.header.clone();
+    let snapshot = fetch_snapshot(&rpc, std::slice::from_ref(&peer), &trusted_header)
+        .await
+        .unwrap();
+    let dir = create_temp_dir();
+    setup_peer(&dir, &[peer]).await;
+    let node = state_sync(configs[1].clone(), &dir, snapshot, &trusted_header)
+        .await
+        .unwrap();
+    assert_eq!(
+        node.get_last_finalized_header(),
+        server_node.get_last_finalized_header()
+    );
+    assert_eq!(
+        node.get_raw_repo()
+            .locate_branch("finalized".to_owned())
+            .await
+            .unwrap(),
+        server_node
+            .get_raw_repo()
+            .locate_branch("finalized".to_owned())
+            .await
+            .unwrap()
+    );
+}
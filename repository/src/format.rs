@@ -37,15 +37,53 @@ pub fn to_semantic_commit(commit: &Commit) -> SemanticCommit {
                 diff: Diff::None,
             }
         }
-        Commit::ExtraAgendaTransaction(_) => unimplemented!(),
+        Commit::ExtraAgendaTransaction(tx) => {
+            let title = format!(">tx-{}", extra_agenda_transaction_kind(tx));
+            let body = serde_spb::to_string(tx).unwrap();
+            // The diff of the reserved state is decided by the repository,
+            // which knows the state that the transaction applies to.
+            SemanticCommit {
+                title,
+                body,
+                diff: Diff::None,
+            }
+        }
         Commit::ChatLog(_) => unimplemented!(),
     }
 }
 
+fn extra_agenda_transaction_kind(tx: &ExtraAgendaTransaction) -> &'static str {
+    match tx {
+        ExtraAgendaTransaction::Delegate(_) => "delegate",
+        ExtraAgendaTransaction::Undelegate(_) => "undelegate",
+        ExtraAgendaTransaction::Report(_) => "report",
+        ExtraAgendaTransaction::RotateKey(_) => "rotate-key",
+    }
+}
+
 /// Converts a semantic commit to a commit.
 ///
 /// TODO: retrieve author and timestamp from the commit metadata.
 pub fn from_semantic_commit(semantic_commit: SemanticCommit) -> Result<Commit, Error> {
+    let pattern = Regex::new(r"^>tx-([a-z-]+)$").unwrap();
+    if let Some(captures) = pattern.captures(&semantic_commit.title) {
+        let kind = captures.get(1).map(|m| m.as_str()).ok_or_else(|| {
+            eyre!(
+                "Failed to parse transaction kind from commit title: {}",
+                semantic_commit.title
+            )
+        })?;
+        let tx: ExtraAgendaTransaction = serde_spb::from_str(&semantic_commit.body)?;
+        let expected = extra_agenda_transaction_kind(&tx);
+        if kind != expected {
+            return Err(eyre!(
+                "extra-agenda transaction kind mismatch: expected {}, got {}",
+                expected,
+                kind
+            ));
+        }
+        return Ok(Commit::ExtraAgendaTransaction(tx));
+    }
     let pattern = Regex::new(r"^>((agenda)|(block)|(agenda-proof)): (\d+)$").unwrap();
     let captures = pattern.captures(&semantic_commit.title);
     if let Some(captures) = captures {
@@ -214,6 +252,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn format_extra_agenda_transaction_commit() {
+        simperby_test_suite::setup_test();
+        let (old_key, old_private_key) = generate_keypair("old");
+        let (new_key, new_private_key) = generate_keypair("new");
+        let data = KeyRotationData {
+            old_key: old_key.clone(),
+            new_key: new_key.clone(),
+            block_height: 3,
+        };
+        let tx = Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::RotateKey(TxRotateKey {
+            old_key,
+            new_key,
+            old_key_proof: TypedSignature::sign(&data, &old_private_key).unwrap(),
+            new_key_proof: TypedSignature::sign(&data, &new_private_key).unwrap(),
+            timestamp: 123,
+        }));
+        let semantic_commit = to_semantic_commit(&tx);
+        assert_eq!(semantic_commit.title, ">tx-rotate-key");
+        assert_eq!(tx, from_semantic_commit(semantic_commit.clone()).unwrap());
+
+        let mismatched = SemanticCommit {
+            title: ">tx-delegate".to_string(),
+            ..semantic_commit
+        };
+        assert!(from_semantic_commit(mismatched).is_err());
+    }
+
     #[test]
     fn format_fp() {
         let fp = LastFinalizationProof {
@@ -175,6 +175,58 @@ impl<T: RawRepository> DistributedRepository<T> {
         }
     }
 
+    /// Returns the finalization proof of the last finalized block from the `fp` branch.
+    pub async fn get_last_finalization_proof(&self) -> Result<LastFinalizationProof, Error> {
+        let commit_hash = self.raw.locate_branch(FP_BRANCH_NAME.into()).await?;
+        let semantic_commit = self.raw.read_semantic_commit(commit_hash).await?;
+        format::fp_from_semantic_commit(semantic_commit)
+    }
+
+    /// Returns all the block headers in the `finalized` branch, from the genesis block
+    /// to the last finalized block.
+    pub async fn get_finalized_block_headers(&self) -> Result<Vec<BlockHeader>, Error> {
+        Ok(self
+            .get_finalized_commits()
+            .await?
+            .into_iter()
+            .filter_map(|(commit, _)| match commit {
+                Commit::Block(header) => Some(header),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Returns all the commits from the genesis to the `finalized` branch, in order.
+    pub async fn get_finalized_commits(&self) -> Result<Vec<(Commit, CommitHash)>, Error> {
+        let finalized_commit = self.raw.locate_branch(FINALIZED_BRANCH_NAME.into()).await?;
+        // The root of the `finalized` branch, rather than `get_initial_commit()`,
+        // which orders the commits by time and may confuse the ones made in the same second.
+        let initial_commit = self
+            .raw
+            .list_ancestors(finalized_commit, None)
+            .await?
+            .pop()
+            .ok_or_else(|| eyre!("`finalized` branch has no pre-genesis commit"))?;
+        Ok(read_commits(self, initial_commit, finalized_commit).await?)
+    }
+
+    /// Returns all the transactions from the genesis to the `work` branch, in order.
+    pub async fn get_transactions(&self) -> Result<Vec<Transaction>, Error> {
+        let initial_commit = self.raw.get_initial_commit().await?;
+        let work_commit = self.raw.locate_branch(WORK_BRANCH_NAME.into()).await?;
+        if initial_commit == work_commit {
+            return Ok(Vec::new());
+        }
+        let commits = read_commits(self, initial_commit, work_commit).await?;
+        Ok(commits
+            .into_iter()
+            .filter_map(|(commit, _)| match commit {
+                Commit::Transaction(transaction) => Some(transaction),
+                _ => None,
+            })
+            .collect())
+    }
+
     pub async fn read_commit(&self, commit_hash: CommitHash) -> Result<Commit, Error> {
         let semantic_commit = self.raw.read_semantic_commit(commit_hash).await?;
         format::from_semantic_commit(semantic_commit).map_err(|e| eyre!(e))
@@ -185,6 +237,22 @@ impl<T: RawRepository> DistributedRepository<T> {
         self.raw.read_reserved_state().await.map_err(|e| eyre!(e))
     }
 
+    /// Checks out the `finalized` branch and reads the reserved state from it,
+    /// which is where a commit sequence verifier starts from.
+    ///
+    /// Note that the working tree of the `work` branch may already contain
+    /// the state changed by extra-agenda transactions, which can't be applied twice.
+    ///
+    /// It leaves `HEAD` detached at the `finalized` commit.
+    pub(crate) async fn read_finalized_reserved_state(&mut self) -> Result<ReservedState, Error> {
+        let finalized_commit_hash = self.raw.locate_branch(FINALIZED_BRANCH_NAME.into()).await?;
+        self.raw.checkout_clean().await?;
+        self.raw.checkout(FINALIZED_BRANCH_NAME.into()).await?;
+        let reserved_state = self.get_reserved_state().await?;
+        self.raw.checkout_detach(finalized_commit_hash).await?;
+        Ok(reserved_state)
+    }
+
     /// Cleans all the outdated commits, remote repositories and branches.
     ///
     /// It will leave only
@@ -362,7 +430,7 @@ impl<T: RawRepository> DistributedRepository<T> {
 
         // Verify every commit along the way.
         let last_finalized_block_header = self.get_last_finalized_block_header().await?;
-        let reserved_state = self.get_reserved_state().await?;
+        let reserved_state = self.read_finalized_reserved_state().await?;
         let mut verifier = CommitSequenceVerifier::new(
             last_finalized_block_header.clone(),
             reserved_state.clone(),
@@ -505,7 +573,7 @@ impl<T: RawRepository> DistributedRepository<T> {
 
         // Verify all the incoming commits
         let finalized_header = self.get_last_finalized_block_header().await?;
-        let reserved_state = self.get_reserved_state().await?;
+        let reserved_state = self.read_finalized_reserved_state().await?;
         let finalized_commit_hash = self.raw.locate_branch(FINALIZED_BRANCH_NAME.into()).await?;
         let commits = utils::read_commits(self, finalized_commit_hash, agenda_commit_hash).await?;
         let mut verifier = CommitSequenceVerifier::new(finalized_header.clone(), reserved_state)
@@ -583,7 +651,7 @@ impl<T: RawRepository> DistributedRepository<T> {
             ));
         }
         // Check the validity of the commit sequence
-        let reserved_state = self.get_reserved_state().await?;
+        let reserved_state = self.read_finalized_reserved_state().await?;
         let mut verifier = CommitSequenceVerifier::new(last_header.clone(), reserved_state)
             .map_err(|e| eyre!("failed to create a commit sequence verifier: {}", e))?;
         let commits = read_commits(self, last_header_commit, work_commit).await?;
@@ -626,12 +694,51 @@ impl<T: RawRepository> DistributedRepository<T> {
         Ok((agenda, result))
     }
 
+    /// Creates a transaction commit on top of the `work` branch.
+    pub async fn create_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<CommitHash, Error> {
+        let last_header = self.get_last_finalized_block_header().await?;
+        let work_commit = self.raw.locate_branch(WORK_BRANCH_NAME.into()).await?;
+        let last_header_commit = self.raw.locate_branch(FINALIZED_BRANCH_NAME.into()).await?;
+
+        // Check the validity of the commit sequence including the new transaction.
+        let reserved_state = self.read_finalized_reserved_state().await?;
+        let mut verifier = CommitSequenceVerifier::new(last_header.clone(), reserved_state)
+            .map_err(|e| eyre!("failed to create a commit sequence verifier: {}", e))?;
+        if work_commit != last_header_commit {
+            for (commit, hash) in read_commits(self, last_header_commit, work_commit).await? {
+                verifier
+                    .apply_commit(&commit)
+                    .map_err(|e| eyre!("verification error on commit {}: {}", hash, e))?;
+            }
+        }
+        let commit = Commit::Transaction(transaction);
+        verifier
+            .apply_commit(&commit)
+            .map_err(|e| eyre!("invalid transaction: {}", e))?;
+
+        self.raw.checkout_clean().await?;
+        self.raw.checkout(WORK_BRANCH_NAME.into()).await?;
+        let result = self
+            .raw
+            .create_semantic_commit(to_semantic_commit(&commit))
+            .await?;
+        Ok(result)
+    }
+
     /// Puts a 'vote' tag on the commit.
+    ///
+    /// It fails if the agenda has been vetoed by this node.
     pub async fn vote(&mut self, commit_hash: CommitHash) -> Result<(), Error> {
         let semantic_commit = self.raw.read_semantic_commit(commit_hash).await?;
         let commit = format::from_semantic_commit(semantic_commit).map_err(|e| eyre!(e))?;
         // Check if the commit is an agenda commit.
         if let Commit::Agenda(_) = commit {
+            if self.is_vetoed(commit_hash).await? {
+                return Err(eyre!("agenda {} has been vetoed", commit_hash));
+            }
             let mut vote_tag_name = commit.to_hash256().to_string();
             vote_tag_name.truncate(TAG_NAME_HASH_DIGITS);
             let vote_tag_name = format!("vote-{vote_tag_name}");
@@ -642,22 +749,38 @@ impl<T: RawRepository> DistributedRepository<T> {
         }
     }
 
-    /// Puts a 'veto' tag on the commit.
+    /// Puts a 'veto' tag on the block or agenda commit.
+    ///
+    /// A vetoed agenda won't be voted for by this node.
+    /// Unlike a block veto, it is purely local; the governance has no negative vote.
     pub async fn veto(&mut self, commit_hash: CommitHash) -> Result<(), Error> {
         let semantic_commit = self.raw.read_semantic_commit(commit_hash).await?;
         let commit = format::from_semantic_commit(semantic_commit).map_err(|e| eyre!(e))?;
-        // Check if the commit is a block commit.
-        if let Commit::Block(_) = commit {
+        // Check if the commit is a block or an agenda commit.
+        if let Commit::Block(_) | Commit::Agenda(_) = commit {
             let mut veto_tag_name = commit.to_hash256().to_string();
             veto_tag_name.truncate(TAG_NAME_HASH_DIGITS);
             let veto_tag_name = format!("veto-{veto_tag_name}");
             self.raw.create_tag(veto_tag_name, commit_hash).await?;
             Ok(())
         } else {
-            Err(eyre!("commit {} is not a block commit", commit_hash))
+            Err(eyre!(
+                "commit {} is neither a block nor an agenda commit",
+                commit_hash
+            ))
         }
     }
 
+    /// Checks whether the commit has a 'veto' tag.
+    pub async fn is_vetoed(&self, commit_hash: CommitHash) -> Result<bool, Error> {
+        Ok(self
+            .raw
+            .get_tag(commit_hash)
+            .await?
+            .iter()
+            .any(|tag| tag.starts_with("veto-")))
+    }
+
     /// Creates a block commit on top of the `work` branch.
     pub async fn create_block(
         &mut self,
@@ -683,9 +806,8 @@ impl<T: RawRepository> DistributedRepository<T> {
         // Check the validity of the commit sequence
         let commits = read_commits(self, last_header_commit, work_commit).await?;
         let last_header = self.get_last_finalized_block_header().await?;
-        self.raw.checkout(WORK_BRANCH_NAME.into()).await?;
-        let reserved_state = self.get_reserved_state().await?;
-        let mut verifier = CommitSequenceVerifier::new(last_header.clone(), reserved_state.clone())
+        let reserved_state = self.read_finalized_reserved_state().await?;
+        let mut verifier = CommitSequenceVerifier::new(last_header.clone(), reserved_state)
             .map_err(|e| eyre!("verification error on commit {}: {}", last_header_commit, e))?;
         for (commit, hash) in commits.iter() {
             verifier
@@ -743,7 +865,11 @@ impl<T: RawRepository> DistributedRepository<T> {
                     .collect::<Vec<_>>(),
             ),
             repository_merkle_root: Hash256::zero(), // TODO
-            validator_set: reserved_state.get_validator_set().unwrap(),
+            // The key rotations of this block take effect from this block.
+            validator_set: verifier
+                .get_reserved_state()
+                .get_validator_set()
+                .map_err(|e| eyre!("failed to get the validator set: {}", e))?,
             version: SIMPERBY_CORE_PROTOCOL_VERSION.to_string(),
         };
         let block_commit = Commit::Block(block_header.clone());
@@ -759,11 +885,41 @@ impl<T: RawRepository> DistributedRepository<T> {
         Ok((block_header, result))
     }
 
-    /// Creates an agenda commit on top of the `work` branch.
+    /// Creates an extra-agenda transaction commit on top of the `work` branch.
+    ///
+    /// The `work` branch must be in the agenda proof phase or the extra-agenda transaction phase.
+    /// If the transaction changes the reserved state, the commit carries the new state
+    /// so that it persists once the block is finalized.
     pub async fn create_extra_agenda_transaction(
         &mut self,
-        _transaction: &ExtraAgendaTransaction,
+        transaction: &ExtraAgendaTransaction,
     ) -> Result<CommitHash, Error> {
-        unimplemented!()
+        let last_header = self.get_last_finalized_block_header().await?;
+        let work_commit = self.raw.locate_branch(WORK_BRANCH_NAME.into()).await?;
+        let last_header_commit = self.raw.locate_branch(FINALIZED_BRANCH_NAME.into()).await?;
+
+        // Check the validity of the commit sequence including the new transaction.
+        let reserved_state = self.read_finalized_reserved_state().await?;
+        let mut verifier = CommitSequenceVerifier::new(last_header.clone(), reserved_state)
+            .map_err(|e| eyre!("failed to create a commit sequence verifier: {}", e))?;
+        for (commit, hash) in read_commits(self, last_header_commit, work_commit).await? {
+            verifier
+                .apply_commit(&commit)
+                .map_err(|e| eyre!("verification error on commit {}: {}", hash, e))?;
+        }
+        let previous_state = verifier.get_reserved_state().clone();
+        let commit = Commit::ExtraAgendaTransaction(transaction.clone());
+        verifier
+            .apply_commit(&commit)
+            .map_err(|e| eyre!("invalid extra-agenda transaction: {}", e))?;
+        let mut semantic_commit = to_semantic_commit(&commit);
+        if verifier.get_reserved_state() != &previous_state {
+            semantic_commit.diff = Diff::Reserved(Box::new(verifier.get_reserved_state().clone()));
+        }
+
+        self.raw.checkout_clean().await?;
+        self.raw.checkout(WORK_BRANCH_NAME.into()).await?;
+        let result = self.raw.create_semantic_commit(semantic_commit).await?;
+        Ok(result)
     }
 }
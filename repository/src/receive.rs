@@ -34,7 +34,7 @@ pub async fn receive<T: RawRepository>(
 ) -> Result<Result<(), String>, Error> {
     let last_finalized_block_header = this.get_last_finalized_block_header().await?;
     let last_finalized_commit_hash = this.raw.locate_branch(FINALIZED_BRANCH_NAME.into()).await?;
-    let reserved_state = this.get_reserved_state().await?;
+    let reserved_state = this.read_finalized_reserved_state().await?;
     let mut csv =
         CommitSequenceVerifier::new(last_finalized_block_header.clone(), reserved_state.clone())
             .map_err(|e| {
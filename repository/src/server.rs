@@ -44,6 +44,7 @@ pub async fn run_server_legacy(path: &str, port: u16) -> GitServer {
         .arg("daemon")
         .arg(format!("--base-path={path}"))
         .arg("--export-all")
+        .arg("--reuseaddr")
         .arg(format!("--port={port}"))
         .arg(format!("--pid-file={pid_path}"))
         .spawn()
@@ -17,8 +17,9 @@ async fn basic_1() {
         public_key: keys[0].0.clone(),
         name: "server-node".to_owned(),
         address: format!("127.0.0.1:{}", 1).parse().unwrap(),
+        addresses: Vec::new(),
         ports: vec![("repository".to_owned(), port)].into_iter().collect(),
-        message: "".to_owned(),
+        metadata: Default::default(),
         recently_seen_timestamp: 0,
     }];
     let peers = SharedKnownPeers::new_static(peers);
@@ -114,3 +115,74 @@ async fn basic_1() {
 
     git_server.await.unwrap();
 }
+
+#[tokio::test]
+async fn rotate_key() {
+    setup_test();
+
+    let (rs, keys) = generate_standard_genesis(4);
+    let config = Config {
+        mirrors: Vec::new(),
+        long_range_attack_distance: 1,
+    };
+    let dir = create_temp_dir();
+    setup_pre_genesis_repository(&dir, rs.clone()).await;
+    let mut repo = DistributedRepository::new(
+        RawRepositoryImpl::open(&format!("{dir}/repository/repo"))
+            .await
+            .unwrap(),
+        config,
+        SharedKnownPeers::new_static(Vec::new()),
+    )
+    .await
+    .unwrap();
+    repo.genesis().await.unwrap();
+
+    let (agenda, _) = repo.create_agenda(keys[0].0.clone()).await.unwrap();
+    let agenda_proof = repo
+        .approve(
+            &agenda.to_hash256(),
+            keys.iter()
+                .map(|(_, private_key)| TypedSignature::sign(&agenda, private_key).unwrap())
+                .collect(),
+        )
+        .await
+        .unwrap();
+    simperby_test_suite::run_command(format!(
+        "cd {dir}/repository/repo && git branch -f work {agenda_proof}"
+    ))
+    .await;
+
+    // Rotate the key of the first member in the block of height 1.
+    let (new_key, new_private_key) = generate_keypair_random();
+    let data = KeyRotationData {
+        old_key: keys[0].0.clone(),
+        new_key: new_key.clone(),
+        block_height: 1,
+    };
+    let tx = ExtraAgendaTransaction::RotateKey(TxRotateKey {
+        old_key: keys[0].0.clone(),
+        new_key: new_key.clone(),
+        old_key_proof: TypedSignature::sign(&data, &keys[0].1).unwrap(),
+        new_key_proof: TypedSignature::sign(&data, &new_private_key).unwrap(),
+        timestamp: get_timestamp(),
+    });
+    repo.create_extra_agenda_transaction(&tx).await.unwrap();
+    // The same rotation can't be applied twice.
+    assert!(repo.create_extra_agenda_transaction(&tx).await.is_err());
+
+    let (block, _) = repo.create_block(keys[1].0.clone()).await.unwrap();
+    assert!(block.validator_set.iter().any(|(key, _)| key == &new_key));
+    assert!(!block.validator_set.iter().any(|(key, _)| key == &keys[0].0));
+
+    // The block is finalized by the rotated validator set.
+    let block_proof = std::iter::once(&new_private_key)
+        .chain(keys[1..].iter().map(|(_, private_key)| private_key))
+        .map(|private_key| TypedSignature::sign(&block, private_key).unwrap())
+        .collect();
+    repo.sync(&block.to_hash256(), &block_proof).await.unwrap();
+    assert_eq!(repo.get_last_finalized_block_header().await.unwrap(), block);
+    let reserved_state = repo.get_reserved_state().await.unwrap();
+    assert!(reserved_state.query_name(&new_key).is_some());
+    assert!(reserved_state.query_name(&keys[0].0).is_none());
+}
@@ -0,0 +1,306 @@
+//! The canonical calldata of the executions for the reference EVM treasury contract.
+//!
+//! The transfers and the contract calls are encoded in the Solidity ABI as the calls
+//! of the following functions, so that the relayer and the off-chain verifiers agree
+//! on the exact bytes that the treasury executes.
+//!
+//! ```solidity
+//! function transferFungibleToken(address token, uint256 amount, address receiver) external;
+//! function transferNonFungibleToken(address collection, uint256 tokenId, address receiver) external;
+//! function contractCall(address target, uint256 value, bytes calldata data) external;
+//! ```
+//!
+//! The addresses must be EVM addresses, and are decoded in the lower case. The token indices
+//! are decimal integers of up to 256 bits, and are decoded without the leading zeros.
+use super::*;
+use address::AddressFormat;
+
+const TRANSFER_FUNGIBLE_TOKEN: &str = "transferFungibleToken(address,uint256,address)";
+const TRANSFER_NON_FUNGIBLE_TOKEN: &str = "transferNonFungibleToken(address,uint256,address)";
+const CONTRACT_CALL: &str = "contractCall(address,uint256,bytes)";
+
+type Word = [u8; 32];
+
+/// Returns the function selector of the signature, i.e., the first 4 bytes of its Keccak-256 hash.
+pub fn selector(signature: &str) -> [u8; 4] {
+    Hash256::hash(signature.as_bytes()).as_ref()[..4]
+        .try_into()
+        .unwrap()
+}
+
+/// Encodes the message into the calldata of the treasury contract.
+pub fn encode_message(message: &ExecutionMessage) -> Result<Vec<u8>, String> {
+    let (signature, words, tail) = match message {
+        ExecutionMessage::TransferFungibleToken(x) => (
+            TRANSFER_FUNGIBLE_TOKEN,
+            vec![
+                encode_address(&x.token_address)?,
+                encode_u128(x.amount),
+                encode_address(&x.receiver_address)?,
+            ],
+            Vec::new(),
+        ),
+        ExecutionMessage::TransferNonFungibleToken(x) => (
+            TRANSFER_NON_FUNGIBLE_TOKEN,
+            vec![
+                encode_address(&x.collection_address)?,
+                encode_decimal(&x.token_index)?,
+                encode_address(&x.receiver_address)?,
+            ],
+            Vec::new(),
+        ),
+        ExecutionMessage::ContractCall(x) => (
+            CONTRACT_CALL,
+            vec![
+                encode_address(&x.contract_address)?,
+                encode_u128(x.value),
+                // The offset of `data`, right after the three head words.
+                encode_u128(3 * 32),
+            ],
+            encode_bytes(&x.calldata),
+        ),
+        _ => return Err(format!("No calldata for the message of {}", message.kind())),
+    };
+    let mut calldata = selector(signature).to_vec();
+    for word in words {
+        calldata.extend_from_slice(&word);
+    }
+    calldata.extend(tail);
+    Ok(calldata)
+}
+
+/// Decodes the calldata of the treasury contract into the message, accepting only the canonical encoding.
+pub fn decode_message(calldata: &[u8]) -> Result<ExecutionMessage, String> {
+    if calldata.len() < 4 || (calldata.len() - 4) % 32 != 0 {
+        return Err("Invalid calldata length".to_string());
+    }
+    let (function, arguments) = calldata.split_at(4);
+    let words = arguments
+        .chunks(32)
+        .map(|x| x.try_into().unwrap())
+        .collect::<Vec<Word>>();
+    if words.len() < 3 {
+        return Err("Missing arguments".to_string());
+    }
+    let message = if function == selector(TRANSFER_FUNGIBLE_TOKEN) {
+        ExecutionMessage::TransferFungibleToken(TransferFungibleToken {
+            token_address: decode_address(&words[0])?,
+            amount: decode_u128(&words[1])?,
+            receiver_address: decode_address(&words[2])?,
+        })
+    } else if function == selector(TRANSFER_NON_FUNGIBLE_TOKEN) {
+        ExecutionMessage::TransferNonFungibleToken(TransferNonFungibleToken {
+            collection_address: decode_address(&words[0])?,
+            token_index: decode_decimal(&words[1]),
+            receiver_address: decode_address(&words[2])?,
+        })
+    } else if function == selector(CONTRACT_CALL) {
+        if decode_u128(&words[2])? != 3 * 32 || words.len() < 4 {
+            return Err("Invalid offset of the data".to_string());
+        }
+        let length = decode_u128(&words[3])? as usize;
+        let data = words[4..].concat();
+        if length > data.len()
+            || data.len() != padded_length(length)
+            || data[length..].iter().any(|x| *x != 0)
+        {
+            return Err("Invalid data".to_string());
+        }
+        ExecutionMessage::ContractCall(ContractCall {
+            contract_address: decode_address(&words[0])?,
+            calldata: data[..length].to_vec(),
+            value: decode_u128(&words[1])?,
+        })
+    } else {
+        return Err("Unknown function".to_string());
+    };
+    // The transfers take exactly three words.
+    if !matches!(message, ExecutionMessage::ContractCall(_)) && words.len() != 3 {
+        return Err("Too many arguments".to_string());
+    }
+    Ok(message)
+}
+
+fn padded_length(length: usize) -> usize {
+    (length + 31) / 32 * 32
+}
+
+fn encode_address(address: &str) -> Result<Word, String> {
+    address::validate_address(address, &AddressFormat::Evm)
+        .map_err(|e| format!("Invalid address {address}: {e}"))?;
+    let mut word = [0; 32];
+    for (i, x) in word[12..].iter_mut().enumerate() {
+        *x = u8::from_str_radix(&address[2 + 2 * i..4 + 2 * i], 16).unwrap();
+    }
+    Ok(word)
+}
+
+fn decode_address(word: &Word) -> Result<String, String> {
+    if word[..12].iter().any(|x| *x != 0) {
+        return Err("Invalid address".to_string());
+    }
+    Ok(format!(
+        "0x{}",
+        word[12..]
+            .iter()
+            .map(|x| format!("{x:02x}"))
+            .collect::<String>()
+    ))
+}
+
+fn encode_u128(value: u128) -> Word {
+    let mut word = [0; 32];
+    word[16..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+fn decode_u128(word: &Word) -> Result<u128, String> {
+    if word[..16].iter().any(|x| *x != 0) {
+        return Err("Integer overflow".to_string());
+    }
+    Ok(u128::from_be_bytes(word[16..].try_into().unwrap()))
+}
+
+/// Encodes a decimal integer of up to 256 bits.
+fn encode_decimal(decimal: &str) -> Result<Word, String> {
+    if decimal.is_empty() || !decimal.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("Not a decimal integer: {decimal}"));
+    }
+    let mut word = [0u8; 32];
+    for digit in decimal.bytes().map(|c| c - b'0') {
+        // word = word * 10 + digit
+        let mut carry = u16::from(digit);
+        for x in word.iter_mut().rev() {
+            let value = u16::from(*x) * 10 + carry;
+            *x = value as u8;
+            carry = value >> 8;
+        }
+        if carry != 0 {
+            return Err(format!("Integer overflow: {decimal}"));
+        }
+    }
+    Ok(word)
+}
+
+fn decode_decimal(word: &Word) -> String {
+    let mut word = *word;
+    let mut digits = Vec::new();
+    while word.iter().any(|x| *x != 0) {
+        // word, remainder = word / 10, word % 10
+        let mut remainder = 0u16;
+        for x in word.iter_mut() {
+            let value = (remainder << 8) | u16::from(*x);
+            *x = (value / 10) as u8;
+            remainder = value % 10;
+        }
+        digits.push(b'0' + remainder as u8);
+    }
+    if digits.is_empty() {
+        return "0".to_owned();
+    }
+    digits.reverse();
+    String::from_utf8(digits).unwrap()
+}
+
+fn encode_bytes(data: &[u8]) -> Vec<u8> {
+    let mut encoded = encode_u128(data.len() as u128).to_vec();
+    encoded.extend_from_slice(data);
+    encoded.resize(32 + padded_length(data.len()), 0);
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed";
+    const RECEIVER: &str = "0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359";
+
+    #[test]
+    fn selectors() {
+        // Of ERC-20.
+        assert_eq!(
+            selector("transfer(address,uint256)"),
+            [0xa9, 0x05, 0x9c, 0xbb]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let messages = vec![
+            ExecutionMessage::TransferFungibleToken(TransferFungibleToken {
+                token_address: TOKEN.to_owned(),
+                amount: u128::MAX,
+                receiver_address: RECEIVER.to_owned(),
+            }),
+            ExecutionMessage::TransferNonFungibleToken(TransferNonFungibleToken {
+                collection_address: TOKEN.to_owned(),
+                token_index:
+                    "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+                        .to_owned(),
+                receiver_address: RECEIVER.to_owned(),
+            }),
+            ExecutionMessage::ContractCall(ContractCall {
+                contract_address: TOKEN.to_owned(),
+                calldata: Vec::new(),
+                value: 0,
+            }),
+            ExecutionMessage::ContractCall(ContractCall {
+                contract_address: TOKEN.to_owned(),
+                calldata: (0..33).collect(),
+                value: 1,
+            }),
+        ];
+        for message in messages {
+            let calldata = encode_message(&message).unwrap();
+            assert_eq!(decode_message(&calldata).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn canonical_encoding() {
+        let message = ExecutionMessage::TransferNonFungibleToken(TransferNonFungibleToken {
+            collection_address: TOKEN.to_owned(),
+            token_index: "258".to_owned(),
+            receiver_address: RECEIVER.to_owned(),
+        });
+        let calldata = encode_message(&message).unwrap();
+        assert_eq!(calldata.len(), 4 + 3 * 32);
+        assert_eq!(calldata[..4], selector(TRANSFER_NON_FUNGIBLE_TOKEN));
+        assert_eq!(calldata[4 + 2 * 32 - 2..4 + 2 * 32], [1, 2]);
+
+        let message = ExecutionMessage::ContractCall(ContractCall {
+            contract_address: TOKEN.to_owned(),
+            calldata: vec![0xde, 0xad],
+            value: 0,
+        });
+        let calldata = encode_message(&message).unwrap();
+        assert_eq!(calldata.len(), 4 + 5 * 32);
+        assert_eq!(calldata[4 + 4 * 32..4 + 4 * 32 + 3], [0xde, 0xad, 0]);
+
+        // Non-zero padding.
+        let mut malformed = calldata;
+        *malformed.last_mut().unwrap() = 1;
+        decode_message(&malformed).unwrap_err();
+
+        // The checksum is checked, and the addresses are decoded in the lower case.
+        let checksummed = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+        let message = ExecutionMessage::TransferFungibleToken(TransferFungibleToken {
+            token_address: checksummed.to_owned(),
+            amount: 1,
+            receiver_address: RECEIVER.to_owned(),
+        });
+        match decode_message(&encode_message(&message).unwrap()).unwrap() {
+            ExecutionMessage::TransferFungibleToken(x) => assert_eq!(x.token_address, TOKEN),
+            _ => panic!(),
+        }
+        let message = ExecutionMessage::TransferFungibleToken(TransferFungibleToken {
+            token_address: checksummed.replacen('a', "A", 1),
+            amount: 1,
+            receiver_address: RECEIVER.to_owned(),
+        });
+        encode_message(&message).unwrap_err();
+        encode_decimal("0x12").unwrap_err();
+        encode_decimal(&"9".repeat(78)).unwrap_err();
+    }
+}
@@ -0,0 +1,366 @@
+//! Validating the addresses in the executions by the formats of their target chains.
+//!
+//! The known chains have their address formats: EVM addresses (with the EIP-55 checksum
+//! if in the mixed case) for the EVM chains, and Bech32 with the prefix of the chain
+//! for the Cosmos chains. The addresses on a custom chain can't be validated.
+//!
+//! In [`AddressValidation::Lenient`], only the addresses that look like the format of the chain,
+//! or like another format, are validated, so that a free-form identifier (e.g., a native denomination)
+//! passes. In [`AddressValidation::Strict`], every address must be well-formed in the format of the chain.
+use super::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum AddressFormat {
+    /// `0x` and 20 bytes in hex.
+    Evm,
+    /// Bech32 with the human-readable prefix (e.g., `cosmos`).
+    Bech32 { prefix: String },
+}
+
+impl std::fmt::Display for AddressFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Evm => write!(f, "EVM"),
+            Self::Bech32 { prefix } => write!(f, "Bech32 ({prefix})"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum AddressValidation {
+    #[default]
+    Lenient,
+    Strict,
+}
+
+/// The result of validating an address in an execution.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AddressCheck {
+    pub address: String,
+    /// The format of the target chain, if known.
+    pub expected: Option<AddressFormat>,
+    /// The format that the address is well-formed in, if any.
+    pub detected: Option<AddressFormat>,
+    /// Why the address is rejected, if it is.
+    pub error: Option<String>,
+}
+
+impl ChainId {
+    /// Returns the format of the addresses on the chain, if it's a known chain.
+    pub fn address_format(&self) -> Option<AddressFormat> {
+        match self {
+            Self::Ethereum | Self::EthereumGoerli | Self::Polygon => Some(AddressFormat::Evm),
+            Self::CosmosHub => Some(AddressFormat::Bech32 {
+                prefix: "cosmos".to_owned(),
+            }),
+            Self::Osmosis => Some(AddressFormat::Bech32 {
+                prefix: "osmo".to_owned(),
+            }),
+            Self::Custom(_) => None,
+        }
+    }
+}
+
+/// Returns the format that the address is well-formed in, if any.
+pub fn detect_address_format(address: &str) -> Option<AddressFormat> {
+    if validate_address(address, &AddressFormat::Evm).is_ok() {
+        return Some(AddressFormat::Evm);
+    }
+    decode_bech32_prefix(address)
+        .ok()
+        .map(|prefix| AddressFormat::Bech32 { prefix })
+}
+
+/// Checks whether the address is well-formed in the format.
+pub fn validate_address(address: &str, format: &AddressFormat) -> Result<(), String> {
+    match format {
+        AddressFormat::Evm => {
+            let hex = address.strip_prefix("0x").ok_or("Missing 0x prefix")?;
+            if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err("Not 20 bytes in hex".to_string());
+            }
+            let mixed_case = hex.chars().any(|c| c.is_ascii_uppercase())
+                && hex.chars().any(|c| c.is_ascii_lowercase());
+            if mixed_case && eip55_checksum(hex) != hex {
+                return Err("Invalid EIP-55 checksum".to_string());
+            }
+            Ok(())
+        }
+        AddressFormat::Bech32 { prefix } => {
+            let found = decode_bech32_prefix(address)?;
+            if &found != prefix {
+                return Err(format!("Prefix {found} instead of {prefix}"));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Validates the addresses in the execution by the format of its target chain.
+///
+/// Every address is reported, including the ones passed without being validated.
+pub fn check_execution_addresses(
+    execution: &Execution,
+    validation: AddressValidation,
+) -> Vec<AddressCheck> {
+    let expected = execution.target_chain.address_format();
+    let mut addresses = Vec::new();
+    if let Some(treasury) = &execution.treasury {
+        addresses.push((treasury.as_str(), false));
+    }
+    if let Some(fee_payer) = execution.fee.as_ref().and_then(|x| x.fee_payer.as_ref()) {
+        addresses.push((fee_payer.as_str(), false));
+    }
+    collect_addresses(&execution.message, &mut addresses);
+    addresses
+        .into_iter()
+        .map(|(address, is_token)| {
+            let detected = detect_address_format(address);
+            let error = expected.as_ref().and_then(|expected| {
+                check_address(address, is_token, expected, detected.as_ref(), validation).err()
+            });
+            AddressCheck {
+                address: address.to_owned(),
+                expected: expected.clone(),
+                detected,
+                error,
+            }
+        })
+        .collect()
+}
+
+/// Validates the addresses in the execution, failing with the first rejected one.
+pub fn validate_execution_addresses(
+    execution: &Execution,
+    validation: AddressValidation,
+) -> Result<(), String> {
+    for check in check_execution_addresses(execution, validation) {
+        if let Some(error) = check.error {
+            return Err(format!("Invalid address {}: {error}", check.address));
+        }
+    }
+    Ok(())
+}
+
+fn check_address(
+    address: &str,
+    is_token: bool,
+    expected: &AddressFormat,
+    detected: Option<&AddressFormat>,
+    validation: AddressValidation,
+) -> Result<(), String> {
+    let looks_like = match expected {
+        AddressFormat::Evm => address.starts_with("0x"),
+        AddressFormat::Bech32 { prefix } => address
+            .to_ascii_lowercase()
+            .starts_with(&format!("{prefix}1")),
+    };
+    // A fungible token on a Cosmos chain may be a native denomination (e.g., `uatom`).
+    let denomination =
+        is_token && matches!(expected, AddressFormat::Bech32 { .. }) && is_denomination(address);
+    if looks_like || (validation == AddressValidation::Strict && !denomination) {
+        return validate_address(address, expected);
+    }
+    match detected {
+        Some(detected) if !denomination => Err(format!(
+            "{detected} address on a chain of {expected} addresses"
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Collects the addresses in the message, with whether each is of a fungible token.
+fn collect_addresses<'a>(message: &'a ExecutionMessage, addresses: &mut Vec<(&'a str, bool)>) {
+    match message {
+        ExecutionMessage::Dummy { .. }
+        | ExecutionMessage::UpdateValidatorSet(_)
+        | ExecutionMessage::CancelExecution(_) => (),
+        ExecutionMessage::TransferNativeCoin(x) => addresses.push((&x.receiver_address, false)),
+        ExecutionMessage::TransferFungibleToken(x) => {
+            addresses.push((&x.token_address, true));
+            addresses.push((&x.receiver_address, false));
+        }
+        ExecutionMessage::TransferNonFungibleToken(x) => {
+            addresses.push((&x.collection_address, false));
+            addresses.push((&x.receiver_address, false));
+        }
+        ExecutionMessage::TransferSemiFungibleToken(x) => {
+            addresses.push((&x.collection_address, false));
+            addresses.push((&x.receiver_address, false));
+        }
+        ExecutionMessage::ContractCall(x) => addresses.push((&x.contract_address, false)),
+        ExecutionMessage::Batch(messages) => {
+            for message in messages {
+                collect_addresses(message, addresses);
+            }
+        }
+    }
+}
+
+/// Whether the string is a valid Cosmos SDK denomination.
+fn is_denomination(s: &str) -> bool {
+    (3..=128).contains(&s.len())
+        && s.starts_with(|c: char| c.is_ascii_alphabetic())
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/:._-".contains(c))
+}
+
+/// Returns the hex of the address in the mixed case of the EIP-55 checksum.
+fn eip55_checksum(hex: &str) -> String {
+    let lower = hex.to_ascii_lowercase();
+    let hash = Hash256::hash(lower.as_bytes());
+    lower
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let byte = hash.as_ref()[i / 2];
+            let nibble = if i % 2 == 0 { byte >> 4 } else { byte & 0xf };
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+const BECH32_CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = (checksum & 0x1ffffff) << 5 ^ u32::from(*value);
+        for (i, x) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= x;
+            }
+        }
+    }
+    checksum
+}
+
+/// Returns the human-readable prefix of the address if it's valid Bech32 (BIP-173).
+fn decode_bech32_prefix(address: &str) -> Result<String, String> {
+    let lower = address.to_ascii_lowercase();
+    if lower != address && address.to_ascii_uppercase() != address {
+        return Err("Mixed case".to_string());
+    }
+    let (prefix, data) = lower.rsplit_once('1').ok_or("Missing separator")?;
+    if prefix.is_empty()
+        || data.len() < 6
+        || lower.len() > 90
+        || !prefix.bytes().all(|c| (33..=126).contains(&c))
+    {
+        return Err("Invalid length".to_string());
+    }
+    let mut values = prefix.bytes().map(|c| c >> 5).collect::<Vec<_>>();
+    values.push(0);
+    values.extend(prefix.bytes().map(|c| c & 31));
+    for c in data.chars() {
+        values.push(BECH32_CHARSET.find(c).ok_or("Invalid character")? as u8);
+    }
+    if bech32_polymod(&values) != 1 {
+        return Err("Invalid checksum".to_string());
+    }
+    Ok(prefix.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EVM_ADDRESS: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+    const COSMOS_ADDRESS: &str = "cosmos1qypqxpq9qcrsszg2pvxq6rs0zqg3yyc5lzv7xu";
+
+    fn transfer(target_chain: ChainId, token_address: &str, receiver_address: &str) -> Execution {
+        Execution {
+            version: EXECUTION_VERSION,
+            target_chain,
+            treasury: None,
+            contract_sequence: 0,
+            valid_until: None,
+            fee: None,
+            message: ExecutionMessage::TransferFungibleToken(TransferFungibleToken {
+                token_address: token_address.to_owned(),
+                amount: 100,
+                receiver_address: receiver_address.to_owned(),
+            }),
+        }
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(detect_address_format(EVM_ADDRESS), Some(AddressFormat::Evm));
+        assert_eq!(
+            detect_address_format(&EVM_ADDRESS.to_ascii_lowercase()),
+            Some(AddressFormat::Evm)
+        );
+        // A single letter flipped.
+        assert_eq!(
+            detect_address_format(&EVM_ADDRESS.replacen('a', "A", 1)),
+            None
+        );
+        assert_eq!(
+            detect_address_format(COSMOS_ADDRESS),
+            Some(AddressFormat::Bech32 {
+                prefix: "cosmos".to_owned()
+            })
+        );
+        assert_eq!(
+            detect_address_format(&COSMOS_ADDRESS.replace("xu", "xv")),
+            None
+        );
+        assert_eq!(detect_address_format("receiver-address"), None);
+        validate_address(COSMOS_ADDRESS, &ChainId::Osmosis.address_format().unwrap()).unwrap_err();
+    }
+
+    #[test]
+    fn execution_addresses() {
+        let lenient = AddressValidation::Lenient;
+        let strict = AddressValidation::Strict;
+        let execution = transfer(ChainId::Ethereum, EVM_ADDRESS, "receiver");
+        validate_execution_addresses(&execution, lenient).unwrap();
+        validate_execution_addresses(&execution, strict).unwrap_err();
+
+        let execution = transfer(
+            ChainId::Ethereum,
+            EVM_ADDRESS,
+            &EVM_ADDRESS.to_ascii_uppercase().replace("0X", "0x"),
+        );
+        validate_execution_addresses(&execution, strict).unwrap();
+        let execution = transfer(
+            ChainId::Ethereum,
+            EVM_ADDRESS,
+            &EVM_ADDRESS.replacen('a', "A", 1),
+        );
+        validate_execution_addresses(&execution, lenient).unwrap_err();
+        let execution = transfer(ChainId::Ethereum, EVM_ADDRESS, COSMOS_ADDRESS);
+        let checks = check_execution_addresses(&execution, lenient);
+        assert_eq!(checks[0].error, None);
+        assert_eq!(checks[1].expected, Some(AddressFormat::Evm));
+        assert_eq!(
+            checks[1].error.as_deref(),
+            Some("Bech32 (cosmos) address on a chain of EVM addresses")
+        );
+
+        // A native denomination as the token.
+        let execution = transfer(ChainId::CosmosHub, "uatom", COSMOS_ADDRESS);
+        validate_execution_addresses(&execution, strict).unwrap();
+        let execution = transfer(ChainId::Osmosis, "uosmo", COSMOS_ADDRESS);
+        validate_execution_addresses(&execution, lenient).unwrap_err();
+
+        // Including the treasury.
+        let mut execution = transfer(ChainId::CosmosHub, "uatom", COSMOS_ADDRESS);
+        execution.treasury = Some(EVM_ADDRESS.to_owned());
+        validate_execution_addresses(&execution, lenient).unwrap_err();
+
+        // Not validated on a custom chain.
+        let execution = transfer("mythereum".parse().unwrap(), "token", "receiver");
+        let checks = check_execution_addresses(&execution, strict);
+        assert!(checks
+            .iter()
+            .all(|x| x.expected.is_none() && x.error.is_none()));
+    }
+}
@@ -0,0 +1,520 @@
+//! The settlement chain driver for the Cosmos chains with CosmWasm, over Tendermint RPC.
+//!
+//! The treasury contract takes the following messages in JSON (i.e., `serde_spb::to_string`),
+//! with the headers, the proofs and the transactions in their JSON encoding as well.
+//!
+//! - Execute `{"update_light_client": {"header": .., "proof": ..}}`
+//! - Execute `{"execute": {"transaction": .., "block_height": .., "merkle_proof": ..}}`
+//! - Query `{"light_client_header": {}}`, returning the header.
+//!
+//! The fungible tokens are either the CW20 contracts or the native denominations (e.g., `uatom`),
+//! and the non-fungible tokens are the CW721 contracts.
+//!
+//! The relays are signed by the relayer account and sent one at a time, each waiting until
+//! it's committed, since the account sequence is read from the committed state. Being final
+//! once committed, the outcome of an execution is known as soon as it's returned.
+use super::*;
+use cosmrs::cosmwasm::MsgExecuteContract;
+use cosmrs::crypto::secp256k1::SigningKey;
+use cosmrs::proto::cosmos::auth::v1beta1::{
+    BaseAccount, QueryAccountRequest, QueryAccountResponse,
+};
+use cosmrs::proto::cosmos::bank::v1beta1::{QueryBalanceRequest, QueryBalanceResponse};
+use cosmrs::proto::cosmwasm::wasm::v1::{
+    QuerySmartContractStateRequest, QuerySmartContractStateResponse,
+};
+use cosmrs::rpc::{Client as _, HttpClient};
+use cosmrs::tendermint::{chain, Hash};
+use cosmrs::tx::{Body, Fee, Msg as _, SignDoc, SignerInfo};
+use cosmrs::{AccountId, Coin};
+use prost::Message as _;
+use serde::de::DeserializeOwned;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The number of the CW721 tokens queried at once.
+const TOKEN_PAGE_SIZE: u32 = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CosmWasmConfig {
+    pub chain: ChainId,
+    /// The Tendermint RPC endpoint of the full node.
+    pub rpc_url: String,
+    /// The chain id of the Cosmos chain (e.g., `cosmoshub-4`).
+    pub cosmos_chain_id: String,
+    /// The Bech32 prefix of the accounts (e.g., `cosmos`).
+    pub account_prefix: String,
+    pub treasury_address: String,
+    /// The hex-encoded secp256k1 private key of the relayer account.
+    pub relayer_private_key: String,
+    /// The denomination that the fees are paid in.
+    pub fee_denom: String,
+    /// The fee paid for each relay, in `fee_denom`.
+    pub fee_amount: u128,
+    pub gas_limit: u64,
+    /// The decimals of the native denominations (e.g., `6` for `uatom`).
+    pub native_decimals: u32,
+    /// How long to wait for a relay to be committed, in milliseconds.
+    pub confirmation_timeout_ms: u64,
+}
+
+impl Default for CosmWasmConfig {
+    fn default() -> Self {
+        Self {
+            chain: ChainId::CosmosHub,
+            rpc_url: "http://localhost:26657".to_owned(),
+            cosmos_chain_id: "cosmoshub-4".to_owned(),
+            account_prefix: "cosmos".to_owned(),
+            treasury_address: String::new(),
+            relayer_private_key: String::new(),
+            fee_denom: "uatom".to_owned(),
+            fee_amount: 10_000,
+            gas_limit: 1_000_000,
+            native_decimals: 6,
+            confirmation_timeout_ms: 60 * 1000,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum TreasuryExecuteMsg {
+    UpdateLightClient {
+        header: BlockHeader,
+        proof: FinalizationProof,
+    },
+    Execute {
+        transaction: Transaction,
+        block_height: u64,
+        merkle_proof: MerkleProof,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum TreasuryQueryMsg {
+    LightClientHeader {},
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Cw20QueryMsg {
+    Balance { address: String },
+    TokenInfo {},
+}
+
+#[derive(Deserialize)]
+struct Cw20Balance {
+    balance: String,
+}
+
+#[derive(Deserialize)]
+struct Cw20TokenInfo {
+    decimals: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Cw721QueryMsg {
+    Tokens {
+        owner: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+}
+
+#[derive(Deserialize)]
+struct Cw721Tokens {
+    tokens: Vec<String>,
+}
+
+pub struct CosmWasmChain {
+    config: CosmWasmConfig,
+    client: HttpClient,
+    /// The raw secp256k1 key of the relayer. A `SigningKey` isn't `Send`,
+    /// so it's made only where a transaction is signed.
+    relayer_private_key: Vec<u8>,
+    relayer: AccountId,
+    treasury: AccountId,
+    /// Held while a relay is being sent, so that the relays don't take the same account sequence.
+    sending: tokio::sync::Mutex<()>,
+}
+
+impl CosmWasmChain {
+    pub fn new(config: CosmWasmConfig) -> Result<Self, Error> {
+        let client = HttpClient::new(config.rpc_url.as_str())?;
+        let relayer_private_key = hex::decode(&config.relayer_private_key)?;
+        let relayer = SigningKey::from_slice(&relayer_private_key)?
+            .public_key()
+            .account_id(&config.account_prefix)?;
+        let treasury = AccountId::from_str(&config.treasury_address)?;
+        Ok(Self {
+            config,
+            client,
+            relayer_private_key,
+            relayer,
+            treasury,
+            sending: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    async fn query<Q: prost::Message, R: prost::Message + Default>(
+        &self,
+        path: &str,
+        request: Q,
+    ) -> Result<R, Error> {
+        let response = self
+            .client
+            .abci_query(Some(path.to_owned()), request.encode_to_vec(), None, false)
+            .await?;
+        if response.code.is_err() {
+            return Err(eyre::eyre!("the query {path} failed: {}", response.log));
+        }
+        Ok(R::decode(response.value.as_slice())?)
+    }
+
+    async fn query_contract<T: DeserializeOwned>(
+        &self,
+        contract: &AccountId,
+        query: &impl Serialize,
+    ) -> Result<T, Error> {
+        let response: QuerySmartContractStateResponse = self
+            .query(
+                "/cosmwasm.wasm.v1.Query/SmartContractState",
+                QuerySmartContractStateRequest {
+                    address: contract.to_string(),
+                    query_data: serde_spb::to_string(query)?.into_bytes(),
+                },
+            )
+            .await?;
+        Ok(serde_spb::from_str(std::str::from_utf8(&response.data)?)?)
+    }
+
+    async fn get_bank_balance(&self, address: &AccountId, denom: &str) -> Result<Decimal, Error> {
+        let response: QueryBalanceResponse = self
+            .query(
+                "/cosmos.bank.v1beta1.Query/Balance",
+                QueryBalanceRequest {
+                    address: address.to_string(),
+                    denom: denom.to_owned(),
+                },
+            )
+            .await?;
+        let amount = response
+            .balance
+            .map_or_else(|| "0".to_owned(), |x| x.amount);
+        to_decimal(&amount, self.config.native_decimals)
+    }
+
+    async fn get_relayer_account(&self) -> Result<BaseAccount, Error> {
+        let response: QueryAccountResponse = self
+            .query(
+                "/cosmos.auth.v1beta1.Query/Account",
+                QueryAccountRequest {
+                    address: self.relayer.to_string(),
+                },
+            )
+            .await?;
+        let account = response
+            .account
+            .ok_or_else(|| eyre::eyre!("the relayer account {} doesn't exist", self.relayer))?;
+        Ok(BaseAccount::decode(account.value.as_slice())?)
+    }
+
+    /// Executes the treasury contract with the message, waiting until it's committed.
+    ///
+    /// Returns the hash of the transaction and whether it has succeeded.
+    async fn execute_treasury(
+        &self,
+        message: &TreasuryExecuteMsg,
+    ) -> Result<(Hash, TransactionOutcome), Error> {
+        let _sending = self.sending.lock().await;
+        let account = self.get_relayer_account().await?;
+        let message = MsgExecuteContract {
+            sender: self.relayer.clone(),
+            contract: self.treasury.clone(),
+            msg: serde_spb::to_string(message)?.into_bytes(),
+            funds: Vec::new(),
+        }
+        .to_any()?;
+        let body = Body::new(vec![message], "", 0u32);
+        let fee = Fee::from_amount_and_gas(
+            Coin {
+                denom: self.config.fee_denom.parse()?,
+                amount: self.config.fee_amount,
+            },
+            self.config.gas_limit,
+        );
+        let transaction = {
+            let signing_key = SigningKey::from_slice(&self.relayer_private_key)?;
+            let auth_info =
+                SignerInfo::single_direct(Some(signing_key.public_key()), account.sequence)
+                    .auth_info(fee);
+            let sign_doc = SignDoc::new(
+                &body,
+                &auth_info,
+                &self.config.cosmos_chain_id.parse::<chain::Id>()?,
+                account.account_number,
+            )?;
+            sign_doc.sign(&signing_key)?
+        };
+        let response = self
+            .client
+            .broadcast_tx_sync(transaction.to_bytes()?)
+            .await?;
+        if response.code.is_err() {
+            return Err(eyre::eyre!(
+                "the transaction has been rejected: {}",
+                response.log
+            ));
+        }
+        let outcome = self.wait_for_commit(response.hash).await?;
+        Ok((response.hash, outcome))
+    }
+
+    async fn wait_for_commit(&self, hash: Hash) -> Result<TransactionOutcome, Error> {
+        let deadline = tokio::time::Instant::now()
+            + Duration::from_millis(self.config.confirmation_timeout_ms);
+        loop {
+            match self.client.tx(hash, false).await {
+                Ok(response) => return Ok(to_outcome(&response.tx_result)),
+                // Not committed yet.
+                Err(_) if tokio::time::Instant::now() < deadline => {
+                    tokio::time::sleep(Duration::from_millis(500)).await
+                }
+                Err(e) => {
+                    return Err(eyre::eyre!(
+                        "the transaction {hash} hasn't been committed: {e}"
+                    ))
+                }
+            }
+        }
+    }
+}
+
+fn to_outcome(tx_result: &cosmrs::tendermint::abci::response::DeliverTx) -> TransactionOutcome {
+    if tx_result.code.is_err() {
+        TransactionOutcome::Failed(tx_result.log.clone())
+    } else {
+        TransactionOutcome::Succeeded
+    }
+}
+
+/// Converts the integer amount in the smallest unit to the decimal one.
+fn to_decimal(amount: &str, decimals: u32) -> Result<Decimal, Error> {
+    let mut amount = Decimal::from_str(amount)?;
+    amount.set_scale(decimals)?;
+    Ok(amount)
+}
+
+#[async_trait::async_trait]
+impl SettlementChain for CosmWasmChain {
+    async fn get_chain_name(&self) -> String {
+        self.config.chain.to_string()
+    }
+
+    async fn get_treasury_address(&self) -> Option<String> {
+        Some(self.treasury.to_string())
+    }
+
+    async fn check_connection(&self) -> Result<(), Error> {
+        let status = self.client.status().await?;
+        if status.node_info.network.as_str() != self.config.cosmos_chain_id {
+            return Err(eyre::eyre!(
+                "the node is on the chain {}, not {}",
+                status.node_info.network,
+                self.config.cosmos_chain_id
+            ));
+        }
+        if status.sync_info.catching_up {
+            return Err(eyre::eyre!("the node is catching up"));
+        }
+        Ok(())
+    }
+
+    /// Returns the latest block, which is final by the Tendermint consensus.
+    async fn get_last_block(&self) -> Result<SettlementChainBlock, Error> {
+        let header = self.client.latest_block().await?.block.header;
+        Ok(SettlementChainBlock {
+            height: header.height.value(),
+            timestamp: header.time.unix_timestamp() as u64,
+        })
+    }
+
+    async fn get_relayer_account_info(&self) -> Result<(String, Decimal), Error> {
+        let balance = self
+            .get_bank_balance(&self.relayer, &self.config.fee_denom)
+            .await?;
+        Ok((self.relayer.to_string(), balance))
+    }
+
+    async fn get_light_client_header(&self) -> Result<BlockHeader, Error> {
+        self.query_contract(&self.treasury, &TreasuryQueryMsg::LightClientHeader {})
+            .await
+    }
+
+    /// Returns the balance of a CW20 contract, or of a native denomination if not an address.
+    async fn get_treasury_fungible_token_balance(&self, address: String) -> Result<Decimal, Error> {
+        let token = match AccountId::from_str(&address) {
+            Ok(x) => x,
+            Err(_) => return self.get_bank_balance(&self.treasury, &address).await,
+        };
+        let balance: Cw20Balance = self
+            .query_contract(
+                &token,
+                &Cw20QueryMsg::Balance {
+                    address: self.treasury.to_string(),
+                },
+            )
+            .await?;
+        let token_info: Cw20TokenInfo = self
+            .query_contract(&token, &Cw20QueryMsg::TokenInfo {})
+            .await?;
+        to_decimal(&balance.balance, token_info.decimals)
+    }
+
+    async fn get_treasury_non_fungible_token_balance(
+        &self,
+        address: String,
+    ) -> Result<Vec<String>, Error> {
+        let collection = AccountId::from_str(&address)?;
+        let mut token_indices: Vec<String> = Vec::new();
+        loop {
+            let page: Cw721Tokens = self
+                .query_contract(
+                    &collection,
+                    &Cw721QueryMsg::Tokens {
+                        owner: self.treasury.to_string(),
+                        start_after: token_indices.last().cloned(),
+                        limit: Some(TOKEN_PAGE_SIZE),
+                    },
+                )
+                .await?;
+            let last_page = page.tokens.len() < TOKEN_PAGE_SIZE as usize;
+            token_indices.extend(page.tokens);
+            if last_page {
+                return Ok(token_indices);
+            }
+        }
+    }
+
+    async fn get_fungible_token_decimals(&self, address: String) -> Result<u32, Error> {
+        let token = match AccountId::from_str(&address) {
+            Ok(x) => x,
+            Err(_) => return Ok(self.config.native_decimals),
+        };
+        let token_info: Cw20TokenInfo = self
+            .query_contract(&token, &Cw20QueryMsg::TokenInfo {})
+            .await?;
+        Ok(token_info.decimals)
+    }
+
+    /// Returns the fixed fee of a relay (`fee_amount`).
+    async fn estimate_execution_fee(&self, _execution: &Execution) -> Result<Decimal, Error> {
+        to_decimal(
+            &self.config.fee_amount.to_string(),
+            self.config.native_decimals,
+        )
+    }
+
+    /// Accepts the Bech32 addresses with the prefix of the chain.
+    fn validate_address(&self, address: &str) -> Result<(), String> {
+        let account = AccountId::from_str(address).map_err(|e| e.to_string())?;
+        if account.prefix() != self.config.account_prefix {
+            return Err(format!(
+                "the address {address} is not prefixed with {}",
+                self.config.account_prefix
+            ));
+        }
+        Ok(())
+    }
+
+    async fn update_treasury_light_client(
+        &self,
+        header: BlockHeader,
+        proof: FinalizationProof,
+    ) -> Result<(), Error> {
+        let (hash, outcome) = self
+            .execute_treasury(&TreasuryExecuteMsg::UpdateLightClient { header, proof })
+            .await?;
+        match outcome {
+            TransactionOutcome::Failed(reason) => {
+                Err(eyre::eyre!("the transaction {hash} has failed: {reason}"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn execute(
+        &self,
+        transaction: Transaction,
+        block_height: u64,
+        proof: MerkleProof,
+    ) -> Result<String, Error> {
+        let (hash, _) = self
+            .execute_treasury(&TreasuryExecuteMsg::Execute {
+                transaction,
+                block_height,
+                merkle_proof: proof,
+            })
+            .await?;
+        Ok(hash.to_string())
+    }
+
+    async fn check_transaction(&self, transaction_hash: &str) -> Result<TransactionOutcome, Error> {
+        let hash = Hash::from_str(transaction_hash)?;
+        let response = self.client.tx(hash, false).await?;
+        Ok(to_outcome(&response.tx_result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        assert_eq!(
+            serde_spb::to_string(&TreasuryQueryMsg::LightClientHeader {})
+                .unwrap()
+                .split_whitespace()
+                .collect::<String>(),
+            r#"{"light_client_header":{}}"#
+        );
+        assert_eq!(to_decimal("1234567", 6).unwrap().to_string(), "1.234567");
+    }
+
+    #[tokio::test]
+    async fn fee_estimate() {
+        let treasury = SigningKey::from_slice(&[2; 32])
+            .unwrap()
+            .public_key()
+            .account_id("cosmos")
+            .unwrap();
+        let chain = CosmWasmChain::new(CosmWasmConfig {
+            treasury_address: treasury.to_string(),
+            relayer_private_key: hex::encode([1; 32]),
+            ..Default::default()
+        })
+        .unwrap();
+        let execution = Execution {
+            version: EXECUTION_VERSION,
+            target_chain: ChainId::CosmosHub,
+            treasury: None,
+            contract_sequence: 0,
+            valid_until: None,
+            fee: None,
+            message: ExecutionMessage::Dummy {
+                msg: "hello".to_owned(),
+            },
+        };
+        assert_eq!(
+            chain
+                .estimate_execution_fee(&execution)
+                .await
+                .unwrap()
+                .to_string(),
+            "0.010000"
+        );
+    }
+}
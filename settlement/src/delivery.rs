@@ -0,0 +1,91 @@
+//! Tracking whether the executions have actually succeeded on their settlement chains.
+//!
+//! A [`DeliveryStore`] keeps the [`DeliveryStatus`] of every execution that the relayer has found,
+//! keyed by its treasury and contract sequence. It's saved along with the queues of the relayer,
+//! so that the statuses survive the restarts.
+use super::*;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// Waiting to be delivered.
+    Pending,
+    /// Delivered by the transaction of the hash, which is not confirmed yet.
+    Submitted(String),
+    /// Succeeded by the transaction of the hash.
+    Confirmed(String),
+    /// The last delivery has failed with the reason, and will be retried.
+    Failed(String),
+    /// Never to be delivered for the reason (e.g., expired or cancelled).
+    Skipped(String),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DeliveryStore {
+    statuses: BTreeMap<TreasuryId, BTreeMap<u128, DeliveryStatus>>,
+}
+
+impl DeliveryStore {
+    pub fn get(&self, treasury: &TreasuryId, contract_sequence: u128) -> Option<&DeliveryStatus> {
+        self.statuses
+            .get(treasury)
+            .and_then(|x| x.get(&contract_sequence))
+    }
+
+    /// Returns the statuses of the executions to the treasury, in order of their contract sequences.
+    pub fn list(&self, treasury: &TreasuryId) -> Vec<(u128, DeliveryStatus)> {
+        self.statuses
+            .get(treasury)
+            .map(|x| x.iter().map(|(k, v)| (*k, v.clone())).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the treasuries that any execution has been found to.
+    pub fn treasuries(&self) -> Vec<TreasuryId> {
+        self.statuses.keys().cloned().collect()
+    }
+
+    pub fn set(&mut self, treasury: &TreasuryId, contract_sequence: u128, status: DeliveryStatus) {
+        self.statuses
+            .entry(treasury.clone())
+            .or_default()
+            .insert(contract_sequence, status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store() {
+        let ethereum = TreasuryId::from(ChainId::Ethereum);
+        let polygon = TreasuryId::from(ChainId::Polygon);
+        let other_treasury = TreasuryId::new(ChainId::Ethereum, Some("0x1234".to_owned()));
+        let mut store = DeliveryStore::default();
+        store.set(&ethereum, 1, DeliveryStatus::Pending);
+        store.set(&ethereum, 0, DeliveryStatus::Pending);
+        store.set(&polygon, 0, DeliveryStatus::Failed("oops".to_owned()));
+        store.set(&ethereum, 0, DeliveryStatus::Confirmed("0x1234".to_owned()));
+        assert_eq!(
+            store.get(&ethereum, 0),
+            Some(&DeliveryStatus::Confirmed("0x1234".to_owned()))
+        );
+        assert_eq!(store.get(&ethereum, 2), None);
+        assert_eq!(store.get(&other_treasury, 0), None);
+        assert_eq!(
+            store.list(&ethereum),
+            vec![
+                (0, DeliveryStatus::Confirmed("0x1234".to_owned())),
+                (1, DeliveryStatus::Pending)
+            ]
+        );
+        assert!(store.list(&other_treasury).is_empty());
+        store.set(&other_treasury, 0, DeliveryStatus::Pending);
+        assert_eq!(store.treasuries(), vec![ethereum, other_treasury, polygon]);
+        assert_eq!(
+            serde_spb::from_str::<DeliveryStore>(&serde_spb::to_string(&store).unwrap()).unwrap(),
+            store
+        );
+    }
+}
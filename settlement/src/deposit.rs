@@ -0,0 +1,404 @@
+//! Recording the assets deposited into the treasuries, the inbound counterpart of the executions.
+//!
+//! A [`DepositWatcher`] reads the deposit events of the treasury contracts from their settlement chains
+//! (see [`SettlementChain::get_treasury_events`]), and turns each into a [`Deposit`], which is recorded
+//! as a Simperby transaction (e.g., `dep-ft: ethereum`) so that the ledger reflects what the treasuries
+//! receive as well as what they send.
+//!
+//! Only the finalized blocks of the settlement chains are read. A deposit names the event it records
+//! by the hash of its transaction and its index, so the same event is never recorded twice,
+//! even if it's read again after a restart (see [`DepositWatcher::restore`]).
+use super::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// The version of the encoding of [`Deposit`] that this crate writes.
+pub const DEPOSIT_VERSION: u32 = 1;
+
+/// A deposit event emitted by a treasury contract.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct TreasuryEvent {
+    /// The height of the settlement chain block that the event is emitted in.
+    pub block_height: u64,
+    /// The hash of the settlement chain transaction that the event is emitted by.
+    pub transaction_hash: String,
+    /// The index of the event in the block (e.g., the log index on an EVM chain).
+    pub event_index: u64,
+    pub sender_address: String,
+    pub message: DepositMessage,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub enum DepositMessage {
+    /// The native coin of the settlement chain (e.g., ETH or ATOM) received by the treasury contract.
+    NativeCoin(DepositNativeCoin),
+    /// A fungible token received by the treasury contract.
+    FungibleToken(DepositFungibleToken),
+    /// An NFT received by the treasury contract.
+    NonFungibleToken(DepositNonFungibleToken),
+}
+
+impl DepositMessage {
+    /// Returns the kind of the message, which the head of the transaction carries
+    /// (e.g., `ft` in `dep-ft: ethereum`).
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::NativeCoin(_) => "native",
+            Self::FungibleToken(_) => "ft",
+            Self::NonFungibleToken(_) => "nft",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct DepositNativeCoin {
+    pub amount: u128,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct DepositFungibleToken {
+    pub token_address: String,
+    pub amount: u128,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct DepositNonFungibleToken {
+    pub collection_address: String,
+    pub token_index: String,
+}
+
+/// The record of a deposit event, carried by a deposit transaction.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Deposit {
+    /// The version of the encoding of the transaction body, rejected by the readers that don't know it.
+    pub version: u32,
+    /// The settlement chain which the treasury is deployed on.
+    pub source_chain: ChainId,
+    /// The address of the treasury contract, if not the default one of the chain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub treasury: Option<String>,
+    pub event: TreasuryEvent,
+}
+
+impl Deposit {
+    /// Returns the treasury contract that the deposit is made to.
+    pub fn treasury_id(&self) -> TreasuryId {
+        TreasuryId::new(self.source_chain.clone(), self.treasury.clone())
+    }
+}
+
+/// Checks whether the deposit is well-formed, regardless of the state of the source chain.
+pub fn validate_deposit(deposit: &Deposit) -> Result<(), String> {
+    if deposit.version == 0 || deposit.version > DEPOSIT_VERSION {
+        return Err(format!("Unsupported version {}", deposit.version));
+    }
+    deposit.source_chain.validate()?;
+    let event = &deposit.event;
+    let mut fields = vec![&event.transaction_hash, &event.sender_address];
+    fields.extend(&deposit.treasury);
+    match &event.message {
+        DepositMessage::NativeCoin(_) => (),
+        DepositMessage::FungibleToken(x) => fields.push(&x.token_address),
+        DepositMessage::NonFungibleToken(x) => {
+            fields.push(&x.collection_address);
+            fields.push(&x.token_index);
+        }
+    }
+    if fields.iter().any(|x| x.is_empty()) {
+        return Err("Empty field".to_string());
+    }
+    Ok(())
+}
+
+/// Creates a deposit transaction that records the deposit once finalized.
+pub fn create_deposit_transaction(
+    deposit: &Deposit,
+    author: PublicKey,
+    timestamp: Timestamp,
+) -> Result<Transaction, String> {
+    validate_deposit(deposit)?;
+    Ok(Transaction {
+        author,
+        timestamp,
+        head: format!(
+            "dep-{}: {}",
+            deposit.event.message.kind(),
+            deposit.source_chain
+        ),
+        body: serde_spb::to_string(deposit).unwrap(),
+        diff: Diff::None,
+    })
+}
+
+/// Reads a deposit transaction, failing in the same ways as reading an execution.
+pub fn convert_transaction_to_deposit(
+    transaction: &Transaction,
+) -> Result<Deposit, ExecutionParseError> {
+    let (kind, source_chain) = transaction
+        .head
+        .strip_prefix("dep-")
+        .and_then(|x| x.split_once(": "))
+        .ok_or(ExecutionParseError::InvalidHead)?;
+    let source_chain = source_chain
+        .parse::<ChainId>()
+        .map_err(|_| ExecutionParseError::InvalidHead)?;
+    let deposit: Deposit = serde_spb::from_str(&transaction.body)
+        .map_err(|e| ExecutionParseError::DeserializationFailed(e.to_string()))?;
+    validate_deposit(&deposit).map_err(ExecutionParseError::DeserializationFailed)?;
+    if deposit.source_chain != source_chain {
+        return Err(ExecutionParseError::ChainMismatch {
+            head: source_chain,
+            body: deposit.source_chain,
+        });
+    }
+    if deposit.event.message.kind() != kind {
+        return Err(ExecutionParseError::KindMismatch {
+            head: kind.to_owned(),
+            body: deposit.event.message.kind(),
+        });
+    }
+    Ok(deposit)
+}
+
+/// Identifies a deposit event across the treasuries.
+type EventId = (TreasuryId, String, u64);
+
+fn event_id(deposit: &Deposit) -> EventId {
+    (
+        deposit.treasury_id(),
+        deposit.event.transaction_hash.clone(),
+        deposit.event.event_index,
+    )
+}
+
+pub struct DepositWatcher {
+    /// The maximum number of the blocks to read the events of at once.
+    max_blocks: u64,
+    treasuries: HashMap<TreasuryId, Arc<dyn SettlementChain>>,
+    /// The last block read, per treasury. Never held across an `.await`.
+    scanned: Mutex<HashMap<TreasuryId, u64>>,
+    /// The events already recorded. Never held across an `.await`.
+    recorded: Mutex<HashSet<EventId>>,
+}
+
+impl DepositWatcher {
+    pub fn new(max_blocks: u64) -> Self {
+        Self {
+            max_blocks,
+            treasuries: HashMap::new(),
+            scanned: Mutex::new(HashMap::new()),
+            recorded: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Sets the backend of the treasury, whose events are read from the block at `start_height`
+    /// (e.g., the height of the deployment).
+    pub fn set_treasury(
+        &mut self,
+        treasury: TreasuryId,
+        chain: Arc<dyn SettlementChain>,
+        start_height: u64,
+    ) {
+        self.scanned
+            .lock()
+            .unwrap()
+            .insert(treasury.clone(), start_height.saturating_sub(1));
+        self.treasuries.insert(treasury, chain);
+    }
+
+    /// Marks the deposits already recorded in the transactions, and skips the blocks before them.
+    ///
+    /// The block of the last deposit is read again, as its other events might not be recorded yet.
+    pub fn restore(&self, transactions: &[Transaction]) {
+        let mut scanned = self.scanned.lock().unwrap();
+        let mut recorded = self.recorded.lock().unwrap();
+        for deposit in transactions
+            .iter()
+            .filter_map(|t| convert_transaction_to_deposit(t).ok())
+        {
+            if let Some(height) = scanned.get_mut(&deposit.treasury_id()) {
+                *height = (*height).max(deposit.event.block_height.saturating_sub(1));
+            }
+            recorded.insert(event_id(&deposit));
+        }
+    }
+
+    /// Returns the last block read for the treasury.
+    pub fn scanned_height(&self, treasury: &TreasuryId) -> Option<u64> {
+        self.scanned.lock().unwrap().get(treasury).copied()
+    }
+
+    /// Reads the new events of the treasury, up to `max_blocks` of the finalized blocks,
+    /// and returns the deposits not recorded yet, in order.
+    ///
+    /// The returned deposits are regarded as recorded; the caller is expected to create their transactions.
+    pub async fn poll(&self, treasury: &TreasuryId) -> Result<Vec<Deposit>, Error> {
+        let chain = self
+            .treasuries
+            .get(treasury)
+            .ok_or_else(|| eyre::eyre!("no backend for {treasury}"))?;
+        let from = self.scanned_height(treasury).unwrap_or_default() + 1;
+        let last = chain.get_last_block().await?.height;
+        if last < from {
+            return Ok(Vec::new());
+        }
+        let to = last.min(from + self.max_blocks.max(1) - 1);
+        let mut events = chain.get_treasury_events(from, to).await?;
+        events.sort_by_key(|x| (x.block_height, x.event_index));
+
+        let deposits = {
+            let mut recorded = self.recorded.lock().unwrap();
+            events
+                .into_iter()
+                .map(|event| Deposit {
+                    version: DEPOSIT_VERSION,
+                    source_chain: treasury.target_chain.clone(),
+                    treasury: treasury.treasury.clone(),
+                    event,
+                })
+                .filter(|x| recorded.insert(event_id(x)))
+                .collect::<Vec<_>>()
+        };
+        self.scanned.lock().unwrap().insert(treasury.clone(), to);
+        for deposit in &deposits {
+            log::info!(
+                "observed a deposit of {} to {treasury} in {}",
+                deposit.event.message.kind(),
+                deposit.event.transaction_hash
+            );
+        }
+        Ok(deposits)
+    }
+
+    /// Polls every treasury in order, skipping (and logging) those that fail.
+    pub async fn poll_all(&self) -> Vec<Deposit> {
+        let mut treasuries = self.treasuries.keys().cloned().collect::<Vec<_>>();
+        treasuries.sort();
+        let mut deposits = Vec::new();
+        for treasury in treasuries {
+            match self.poll(&treasury).await {
+                Ok(x) => deposits.extend(x),
+                Err(e) => log::warn!("failed to read the events of {treasury}: {e}"),
+            }
+        }
+        deposits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use relay::tests::MockChain;
+
+    fn event(block_height: u64, event_index: u64, message: DepositMessage) -> TreasuryEvent {
+        TreasuryEvent {
+            block_height,
+            transaction_hash: format!("tx{block_height}"),
+            event_index,
+            sender_address: "alice".to_owned(),
+            message,
+        }
+    }
+
+    fn usdc(amount: u128) -> DepositMessage {
+        DepositMessage::FungibleToken(DepositFungibleToken {
+            token_address: "usdc".to_owned(),
+            amount,
+        })
+    }
+
+    #[test]
+    fn deposit_transaction() {
+        let (public_key, _) = generate_keypair("author");
+        let deposit = Deposit {
+            version: DEPOSIT_VERSION,
+            source_chain: ChainId::Ethereum,
+            treasury: Some("treasury-a".to_owned()),
+            event: event(3, 0, usdc(100)),
+        };
+        let transaction = create_deposit_transaction(&deposit, public_key.clone(), 0).unwrap();
+        assert_eq!(transaction.head, "dep-ft: ethereum");
+        assert_eq!(
+            convert_transaction_to_deposit(&transaction).unwrap(),
+            deposit
+        );
+        // Not an execution.
+        convert_transaction_to_execution(&transaction).unwrap_err();
+
+        let with_head = |head: &str| Transaction {
+            head: head.to_owned(),
+            ..transaction.clone()
+        };
+        assert_eq!(
+            convert_transaction_to_deposit(&with_head("dep-nft: ethereum")),
+            Err(ExecutionParseError::KindMismatch {
+                head: "nft".to_owned(),
+                body: "ft"
+            })
+        );
+        assert_eq!(
+            convert_transaction_to_deposit(&with_head("ex-ft: ethereum")),
+            Err(ExecutionParseError::InvalidHead)
+        );
+
+        let mut invalid = deposit.clone();
+        invalid.version = DEPOSIT_VERSION + 1;
+        create_deposit_transaction(&invalid, public_key.clone(), 0).unwrap_err();
+        let mut invalid = deposit;
+        invalid.event.sender_address = String::new();
+        create_deposit_transaction(&invalid, public_key, 0).unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn watcher() {
+        let ethereum = Arc::new(MockChain::new("ethereum", 0));
+        let nft = DepositMessage::NonFungibleToken(DepositNonFungibleToken {
+            collection_address: "punks".to_owned(),
+            token_index: "7".to_owned(),
+        });
+        ethereum.events.lock().unwrap().extend([
+            event(1, 0, usdc(1)),
+            event(4, 1, nft),
+            event(
+                4,
+                0,
+                DepositMessage::NativeCoin(DepositNativeCoin { amount: 5 }),
+            ),
+            event(9, 0, usdc(2)),
+        ]);
+        *ethereum.settlement_height.lock().unwrap() = 8;
+        let treasury = TreasuryId::from(ChainId::Ethereum);
+        let mut watcher = DepositWatcher::new(5);
+        watcher.set_treasury(treasury.clone(), Arc::clone(&ethereum) as _, 2);
+
+        // From the start height, up to the last block.
+        let deposits = watcher.poll(&treasury).await.unwrap();
+        assert_eq!(
+            deposits
+                .iter()
+                .map(|x| (x.event.block_height, x.event.event_index))
+                .collect::<Vec<_>>(),
+            vec![(4, 0), (4, 1)]
+        );
+        assert_eq!(watcher.scanned_height(&treasury), Some(6));
+        assert_eq!(watcher.poll(&treasury).await.unwrap(), vec![]);
+        assert_eq!(watcher.scanned_height(&treasury), Some(8));
+
+        // A restarted watcher skips what's already recorded.
+        let (public_key, _) = generate_keypair("author");
+        let transactions = deposits
+            .iter()
+            .map(|x| create_deposit_transaction(x, public_key.clone(), 0).unwrap())
+            .collect::<Vec<_>>();
+        let mut restarted = DepositWatcher::new(100);
+        restarted.set_treasury(treasury.clone(), Arc::clone(&ethereum) as _, 2);
+        restarted.restore(&transactions);
+        assert_eq!(restarted.scanned_height(&treasury), Some(3));
+        *ethereum.settlement_height.lock().unwrap() = 9;
+        let deposits = restarted.poll_all().await;
+        assert_eq!(deposits.len(), 1);
+        assert_eq!(deposits[0].event, event(9, 0, usdc(2)));
+
+        assert!(restarted.poll(&ChainId::Polygon.into()).await.is_err());
+    }
+}
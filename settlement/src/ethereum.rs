@@ -0,0 +1,415 @@
+//! The settlement chain driver for Ethereum and the EVM-compatible chains, over JSON-RPC.
+//!
+//! It talks to a treasury contract exposing the following interface, where the headers,
+//! the proofs and the transactions are in the binary encoding of `serde_spb::to_vec`.
+//!
+//! ```solidity
+//! function lightClientHeight() external view returns (uint64);
+//! function lightClientHeader() external view returns (bytes memory);
+//! function updateLightClient(bytes calldata header, bytes calldata proof) external;
+//! function execute(bytes calldata transaction, uint64 blockHeight, bytes calldata merkleProof) external;
+//! event Deposited(address indexed sender, address indexed token, uint256 amount);
+//! event NftReceived(address indexed sender, address indexed collection, uint256 tokenId);
+//! ```
+//!
+//! `Deposited` is emitted with the zero address as the token for the native coin.
+//!
+//! The fee budget of an execution (see [`FeeBudget`]) bounds the gas and the gas price of its delivery,
+//! and the delivery fails without being submitted if it can't be kept.
+//!
+//! The transactions are signed by the relayer account, whose nonces are managed locally
+//! so that the relays don't wait for each other to be mined. The light client updates are waited
+//! until confirmed, while the executions are returned on the submission to be checked later.
+use super::*;
+use deposit::*;
+use ethers::contract::{abigen, ContractCall as EthereumCall};
+use ethers::middleware::{NonceManagerMiddleware, SignerMiddleware};
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::{Address, BlockNumber, H256, U256, U64};
+use std::str::FromStr;
+use std::sync::Arc;
+
+abigen!(
+    TreasuryContract,
+    r#"[
+        function lightClientHeight() external view returns (uint64)
+        function lightClientHeader() external view returns (bytes)
+        function updateLightClient(bytes header, bytes proof) external
+        function execute(bytes transaction, uint64 blockHeight, bytes merkleProof) external
+        event Deposited(address indexed sender, address indexed token, uint256 amount)
+        event NftReceived(address indexed sender, address indexed collection, uint256 tokenId)
+    ]"#
+);
+
+abigen!(
+    Erc20Contract,
+    r#"[
+        function balanceOf(address owner) external view returns (uint256)
+        function decimals() external view returns (uint8)
+    ]"#
+);
+
+abigen!(
+    Erc721EnumerableContract,
+    r#"[
+        function balanceOf(address owner) external view returns (uint256)
+        function tokenOfOwnerByIndex(address owner, uint256 index) external view returns (uint256)
+    ]"#
+);
+
+type Client = NonceManagerMiddleware<SignerMiddleware<Provider<Http>, LocalWallet>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EthereumConfig {
+    pub chain: ChainId,
+    /// The JSON-RPC endpoint of the full node.
+    pub rpc_url: String,
+    /// The EIP-155 chain id (e.g., `1` for the mainnet).
+    pub evm_chain_id: u64,
+    pub treasury_address: String,
+    /// The hex-encoded private key of the relayer account.
+    pub relayer_private_key: String,
+    /// How much gas is given beyond the estimation, in percent.
+    pub gas_margin_percent: u64,
+    /// The number of the blocks to wait for after a relay is mined.
+    pub confirmations: usize,
+}
+
+impl Default for EthereumConfig {
+    fn default() -> Self {
+        Self {
+            chain: ChainId::Ethereum,
+            rpc_url: "http://localhost:8545".to_owned(),
+            evm_chain_id: 1,
+            treasury_address: String::new(),
+            relayer_private_key: String::new(),
+            gas_margin_percent: 20,
+            confirmations: 1,
+        }
+    }
+}
+
+pub struct EthereumChain {
+    config: EthereumConfig,
+    client: Arc<Client>,
+    treasury: TreasuryContract<Client>,
+}
+
+impl EthereumChain {
+    pub fn new(config: EthereumConfig) -> Result<Self, Error> {
+        let provider = Provider::<Http>::try_from(config.rpc_url.as_str())?;
+        let wallet = config
+            .relayer_private_key
+            .parse::<LocalWallet>()?
+            .with_chain_id(config.evm_chain_id);
+        let relayer = wallet.address();
+        let client = Arc::new(NonceManagerMiddleware::new(
+            SignerMiddleware::new(provider, wallet),
+            relayer,
+        ));
+        let treasury = TreasuryContract::new(
+            parse_address(&config.treasury_address)?,
+            Arc::clone(&client),
+        );
+        Ok(Self {
+            config,
+            client,
+            treasury,
+        })
+    }
+
+    /// Gives the call the estimated gas with the margin.
+    async fn with_gas(
+        &self,
+        call: EthereumCall<Client, ()>,
+    ) -> Result<EthereumCall<Client, ()>, Error> {
+        let gas = call.estimate_gas().await?;
+        Ok(call.gas(gas * (100 + self.config.gas_margin_percent) / 100))
+    }
+
+    /// Gives the call the estimated gas and the gas price within the fee budget of the execution.
+    async fn with_budget(
+        &self,
+        call: EthereumCall<Client, ()>,
+        budget: &FeeBudget,
+    ) -> Result<EthereumCall<Client, ()>, Error> {
+        if let Some(fee_payer) = &budget.fee_payer {
+            if parse_address(fee_payer)? != self.client.inner().address() {
+                return Err(eyre::eyre!(
+                    "the fee must be paid by {fee_payer}, not this relayer"
+                ));
+            }
+        }
+        let gas = call.estimate_gas().await?;
+        let mut gas_limit = gas * (100 + self.config.gas_margin_percent) / 100;
+        if let Some(max_gas) = budget.max_gas {
+            if gas > U256::from(max_gas) {
+                return Err(eyre::eyre!(
+                    "the execution needs {gas} gas, over the budget of {max_gas}"
+                ));
+            }
+            gas_limit = gas_limit.min(U256::from(max_gas));
+        }
+        let call = call.gas(gas_limit);
+        Ok(match budget.gas_price {
+            GasPriceStrategy::Market => call,
+            GasPriceStrategy::Fixed(price) => call.gas_price(price),
+            GasPriceStrategy::Capped(cap) => {
+                let price = self.client.get_gas_price().await?;
+                if price > U256::from(cap) {
+                    return Err(eyre::eyre!(
+                        "the gas price {price} is over the cap of {cap}"
+                    ));
+                }
+                call.gas_price(price)
+            }
+        })
+    }
+
+    /// Sends the call, waiting until it's confirmed.
+    async fn send(&self, call: EthereumCall<Client, ()>) -> Result<(), Error> {
+        let receipt = self
+            .with_gas(call)
+            .await?
+            .send()
+            .await?
+            .confirmations(self.config.confirmations)
+            .await?
+            .ok_or_else(|| eyre::eyre!("the transaction has been dropped"))?;
+        if receipt.status != Some(U64::from(1)) {
+            return Err(eyre::eyre!(
+                "the transaction {:?} has been reverted",
+                receipt.transaction_hash
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn parse_address(address: &str) -> Result<Address, Error> {
+    Address::from_str(address).map_err(|e| eyre::eyre!("invalid address {address}: {e}"))
+}
+
+#[async_trait::async_trait]
+impl SettlementChain for EthereumChain {
+    async fn get_chain_name(&self) -> String {
+        self.config.chain.to_string()
+    }
+
+    async fn get_treasury_address(&self) -> Option<String> {
+        Some(ethers::utils::to_checksum(&self.treasury.address(), None))
+    }
+
+    async fn check_connection(&self) -> Result<(), Error> {
+        let chain_id = self.client.get_chainid().await?;
+        if chain_id != U256::from(self.config.evm_chain_id) {
+            return Err(eyre::eyre!(
+                "the node is on the chain {chain_id}, not {}",
+                self.config.evm_chain_id
+            ));
+        }
+        Ok(())
+    }
+
+    async fn get_last_block(&self) -> Result<SettlementChainBlock, Error> {
+        let block = self
+            .client
+            .get_block(BlockNumber::Finalized)
+            .await?
+            .ok_or_else(|| eyre::eyre!("no finalized block"))?;
+        Ok(SettlementChainBlock {
+            height: block
+                .number
+                .ok_or_else(|| eyre::eyre!("the finalized block is pending"))?
+                .as_u64(),
+            timestamp: block.timestamp.as_u64(),
+        })
+    }
+
+    async fn get_relayer_account_info(&self) -> Result<(String, Decimal), Error> {
+        let address = self.client.inner().address();
+        let balance = self.client.get_balance(address, None).await?;
+        Ok((
+            ethers::utils::to_checksum(&address, None),
+            Decimal::from_str(&ethers::utils::format_ether(balance))?,
+        ))
+    }
+
+    async fn get_light_client_header(&self) -> Result<BlockHeader, Error> {
+        let header = self.treasury.light_client_header().call().await?;
+        Ok(serde_spb::from_slice(&header)?)
+    }
+
+    async fn get_light_client_height(&self) -> Result<BlockHeight, Error> {
+        Ok(self.treasury.light_client_height().call().await?)
+    }
+
+    async fn get_treasury_fungible_token_balance(&self, address: String) -> Result<Decimal, Error> {
+        let token = Erc20Contract::new(parse_address(&address)?, Arc::clone(&self.client));
+        let balance = token.balance_of(self.treasury.address()).call().await?;
+        let decimals = token.decimals().call().await?;
+        Ok(Decimal::from_str(&ethers::utils::format_units(
+            balance,
+            u32::from(decimals),
+        )?)?)
+    }
+
+    async fn get_treasury_non_fungible_token_balance(
+        &self,
+        address: String,
+    ) -> Result<Vec<String>, Error> {
+        let collection =
+            Erc721EnumerableContract::new(parse_address(&address)?, Arc::clone(&self.client));
+        let owner = self.treasury.address();
+        let count = collection.balance_of(owner).call().await?;
+        let mut token_indices = Vec::new();
+        for i in 0..count.as_u64() {
+            let token_index = collection
+                .token_of_owner_by_index(owner, U256::from(i))
+                .call()
+                .await?;
+            token_indices.push(token_index.to_string());
+        }
+        Ok(token_indices)
+    }
+
+    async fn get_fungible_token_decimals(&self, address: String) -> Result<u32, Error> {
+        let token = Erc20Contract::new(parse_address(&address)?, Arc::clone(&self.client));
+        Ok(u32::from(token.decimals().call().await?))
+    }
+
+    /// Accepts the addresses in a single case, or in the mixed case of the EIP-55 checksum.
+    fn validate_address(&self, address: &str) -> Result<(), String> {
+        let parsed = parse_address(address).map_err(|e| e.to_string())?;
+        let hex = address.trim_start_matches("0x");
+        let mixed_case = hex.chars().any(|c| c.is_ascii_uppercase())
+            && hex.chars().any(|c| c.is_ascii_lowercase());
+        if mixed_case && ethers::utils::to_checksum(&parsed, None)[2..] != *hex {
+            return Err(format!("invalid checksum of {address}"));
+        }
+        Ok(())
+    }
+
+    async fn get_treasury_events(&self, from: u64, to: u64) -> Result<Vec<TreasuryEvent>, Error> {
+        let logs = self
+            .treasury
+            .events()
+            .from_block(from)
+            .to_block(to)
+            .query_with_meta()
+            .await?;
+        let mut events = Vec::new();
+        for (log, meta) in logs {
+            let (sender, message) = match log {
+                TreasuryContractEvents::DepositedFilter(x) => {
+                    let amount = u128::try_from(x.amount)
+                        .map_err(|_| eyre::eyre!("too large amount {}", x.amount))?;
+                    let message = if x.token == Address::zero() {
+                        DepositMessage::NativeCoin(DepositNativeCoin { amount })
+                    } else {
+                        DepositMessage::FungibleToken(DepositFungibleToken {
+                            token_address: ethers::utils::to_checksum(&x.token, None),
+                            amount,
+                        })
+                    };
+                    (x.sender, message)
+                }
+                TreasuryContractEvents::NftReceivedFilter(x) => (
+                    x.sender,
+                    DepositMessage::NonFungibleToken(DepositNonFungibleToken {
+                        collection_address: ethers::utils::to_checksum(&x.collection, None),
+                        token_index: x.token_id.to_string(),
+                    }),
+                ),
+            };
+            events.push(TreasuryEvent {
+                block_height: meta.block_number.as_u64(),
+                transaction_hash: format!("{:?}", meta.transaction_hash),
+                event_index: meta.log_index.as_u64(),
+                sender_address: ethers::utils::to_checksum(&sender, None),
+                message,
+            });
+        }
+        Ok(events)
+    }
+
+    async fn update_treasury_light_client(
+        &self,
+        header: BlockHeader,
+        proof: FinalizationProof,
+    ) -> Result<(), Error> {
+        let call = self.treasury.update_light_client(
+            serde_spb::to_vec(&header)?.into(),
+            serde_spb::to_vec(&proof)?.into(),
+        );
+        self.send(call).await
+    }
+
+    /// Estimates the fee at the gas budget of the execution, since the gas of its delivery
+    /// can't be estimated without the proof, which exists only after it's finalized.
+    async fn estimate_execution_fee(&self, execution: &Execution) -> Result<Decimal, Error> {
+        let budget = execution.fee.clone().unwrap_or_default();
+        let gas = budget.max_gas.ok_or_else(|| {
+            eyre::eyre!("the fee can't be estimated before finalization without the gas budget")
+        })?;
+        let price = match budget.gas_price {
+            GasPriceStrategy::Market => self.client.get_gas_price().await?,
+            GasPriceStrategy::Fixed(price) => U256::from(price),
+            GasPriceStrategy::Capped(cap) => {
+                let price = self.client.get_gas_price().await?;
+                if price > U256::from(cap) {
+                    return Err(eyre::eyre!(
+                        "the gas price {price} is over the cap of {cap}"
+                    ));
+                }
+                price
+            }
+        };
+        Ok(Decimal::from_str(&ethers::utils::format_ether(
+            price * U256::from(gas),
+        ))?)
+    }
+
+    async fn execute(
+        &self,
+        transaction: Transaction,
+        block_height: u64,
+        proof: MerkleProof,
+    ) -> Result<String, Error> {
+        let budget = convert_transaction_to_execution(&transaction)?
+            .fee
+            .unwrap_or_default();
+        let call = self.treasury.execute(
+            serde_spb::to_vec(&transaction)?.into(),
+            block_height,
+            serde_spb::to_vec(&proof)?.into(),
+        );
+        let call = self.with_budget(call, &budget).await?;
+        let pending = call.send().await?;
+        Ok(format!("{:?}", pending.tx_hash()))
+    }
+
+    async fn check_transaction(&self, transaction_hash: &str) -> Result<TransactionOutcome, Error> {
+        let hash = H256::from_str(transaction_hash)
+            .map_err(|e| eyre::eyre!("invalid transaction hash {transaction_hash}: {e}"))?;
+        let receipt = match self.client.get_transaction_receipt(hash).await? {
+            Some(x) => x,
+            None => return Ok(TransactionOutcome::Unconfirmed),
+        };
+        let block_number = match receipt.block_number {
+            Some(x) => x.as_u64(),
+            None => return Ok(TransactionOutcome::Unconfirmed),
+        };
+        let last_block_number = self.client.get_block_number().await?.as_u64();
+        if last_block_number + 1 < block_number + self.config.confirmations as u64 {
+            return Ok(TransactionOutcome::Unconfirmed);
+        }
+        if receipt.status != Some(U64::from(1)) {
+            return Ok(TransactionOutcome::Failed(
+                "the transaction has been reverted".to_owned(),
+            ));
+        }
+        Ok(TransactionOutcome::Succeeded)
+    }
+}
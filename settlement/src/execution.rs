@@ -1,24 +1,326 @@
 use super::*;
+use address::AddressValidation;
 use simperby_common::*;
+use std::collections::HashSet;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// A settlement chain, either one of the known chains or a custom one.
+///
+/// It's encoded as its name (e.g., `ethereum`), the same as the free-form strings used before,
+/// so a known chain can't be given as [`ChainId::Custom`].
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
+pub enum ChainId {
+    Ethereum,
+    EthereumGoerli,
+    Polygon,
+    CosmosHub,
+    Osmosis,
+    /// A chain not in the registry, with its name.
+    Custom(String),
+}
+
+impl ChainId {
+    /// The chains in the registry.
+    pub const KNOWN: [ChainId; 5] = [
+        ChainId::Ethereum,
+        ChainId::EthereumGoerli,
+        ChainId::Polygon,
+        ChainId::CosmosHub,
+        ChainId::Osmosis,
+    ];
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Ethereum => "ethereum",
+            Self::EthereumGoerli => "ethereum-goerli",
+            Self::Polygon => "polygon",
+            Self::CosmosHub => "cosmoshub",
+            Self::Osmosis => "osmosis",
+            Self::Custom(name) => name,
+        }
+    }
+
+    /// Checks whether the chain id can be encoded unambiguously.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Self::Custom(name) = self {
+            if name.is_empty() || name.contains(": ") {
+                return Err("Invalid target chain".to_string());
+            }
+            if Self::KNOWN.iter().any(|x| x.as_str() == name) {
+                return Err(format!("Custom chain id of the known chain {name}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for ChainId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ChainId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let chain_id = Self::KNOWN
+            .iter()
+            .find(|x| x.as_str() == s)
+            .cloned()
+            .unwrap_or_else(|| Self::Custom(s.to_owned()));
+        chain_id.validate()?;
+        Ok(chain_id)
+    }
+}
+
+impl Serialize for ChainId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ChainId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// A treasury contract that the executions are delivered to: either the default one of the chain,
+/// or another one on the same chain, by its address.
+///
+/// It's encoded as `<target chain>` or `<target chain>: <treasury address>`,
+/// so that of the default treasury is the same as its [`ChainId`].
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
+pub struct TreasuryId {
+    pub target_chain: ChainId,
+    /// The address of the treasury contract, or `None` for the default one of the chain.
+    pub treasury: Option<String>,
+}
+
+impl TreasuryId {
+    pub fn new(target_chain: ChainId, treasury: Option<String>) -> Self {
+        Self {
+            target_chain,
+            treasury,
+        }
+    }
+}
+
+impl From<ChainId> for TreasuryId {
+    fn from(target_chain: ChainId) -> Self {
+        Self::new(target_chain, None)
+    }
+}
+
+impl std::fmt::Display for TreasuryId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.treasury {
+            Some(treasury) => write!(f, "{}: {treasury}", self.target_chain),
+            None => write!(f, "{}", self.target_chain),
+        }
+    }
+}
+
+impl FromStr for TreasuryId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(": ") {
+            Some((_, "")) => Err("Empty treasury address".to_string()),
+            Some((target_chain, treasury)) => {
+                Ok(Self::new(target_chain.parse()?, Some(treasury.to_owned())))
+            }
+            None => Ok(Self::new(s.parse()?, None)),
+        }
+    }
+}
+
+impl Serialize for TreasuryId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for TreasuryId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// The version of the execution encoding that is created by default.
+///
+/// - `1`: The original, unversioned encoding without the `version` field.
+/// - `2`: With the `version` field, which the readers check before the message.
+/// - `3`: With the optional `treasury` field.
+/// - `4`: With the optional `valid_until` field and [`ExecutionMessage::CancelExecution`].
+/// - `5`: With the optional `fee` field.
+pub const EXECUTION_VERSION: u32 = 5;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct Execution {
+    /// The version of the encoding of the transaction body.
+    ///
+    /// A reader rejects the versions that it doesn't know, instead of misreading
+    /// the messages introduced after it.
+    pub version: u32,
     /// The target settlement chain which this message will be delivered to.
-    pub target_chain: String,
+    pub target_chain: ChainId,
+    /// The address of the target treasury contract, if not the default one of the chain.
+    ///
+    /// It's absent before the version `3`, where every execution is to the default treasury.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub treasury: Option<String>,
     /// A unique sequence for the target contract.
+    ///
+    /// A batch takes a single sequence as a whole.
     pub contract_sequence: u128,
+    /// The time after which the execution must not take effect, in milliseconds.
+    ///
+    /// The relayer skips the execution once it's expired, and the treasury contract
+    /// should reject it by its own clock as well. It's absent before the version `4`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<Timestamp>,
+    /// The bound of what the relayer may spend delivering the execution.
+    ///
+    /// It's absent before the version `5`, where the relayer spends as much as it takes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee: Option<FeeBudget>,
     /// The actual content to deliver.
     pub message: ExecutionMessage,
 }
 
+impl Execution {
+    /// Returns the treasury contract that the execution is delivered to.
+    pub fn treasury_id(&self) -> TreasuryId {
+        TreasuryId::new(self.target_chain.clone(), self.treasury.clone())
+    }
+}
+
+/// How much the relayer may spend delivering an execution.
+///
+/// A backend that can't keep the budget fails the delivery, which is retried later
+/// (e.g., when the gas price has come down under the cap).
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+pub struct FeeBudget {
+    /// The maximum gas (or the gas limit of the non-EVM chains) of the delivering transaction.
+    pub max_gas: Option<u64>,
+    pub gas_price: GasPriceStrategy,
+    /// The account that must pay the fee, which is the relayer account submitting the delivery.
+    ///
+    /// Any relayer may deliver the execution if absent.
+    pub fee_payer: Option<String>,
+}
+
+/// How the gas price of the delivering transaction is decided, in the smallest unit of the native coin.
+///
+/// It's written as `market`, `fixed:<price>` or `capped:<price>`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+pub enum GasPriceStrategy {
+    /// The price suggested by the chain.
+    #[default]
+    Market,
+    /// Exactly the given price, regardless of the market.
+    Fixed(u128),
+    /// The price suggested by the chain, unless it's above the given one.
+    Capped(u128),
+}
+
+impl std::fmt::Display for GasPriceStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Market => write!(f, "market"),
+            Self::Fixed(price) => write!(f, "fixed:{price}"),
+            Self::Capped(price) => write!(f, "capped:{price}"),
+        }
+    }
+}
+
+impl FromStr for GasPriceStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let price = |x: &str| {
+            x.parse::<u128>()
+                .map_err(|e| format!("invalid gas price {x}: {e}"))
+        };
+        match s.split_once(':') {
+            None if s == "market" => Ok(Self::Market),
+            Some(("fixed", x)) => Ok(Self::Fixed(price(x)?)),
+            Some(("capped", x)) => Ok(Self::Capped(price(x)?)),
+            _ => Err(format!("invalid gas price strategy {s}")),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum ExecutionMessage {
     /// Does nothing but make the treasury contract verify the commitment anyway.
     Dummy { msg: String },
+    /// Transfers the native coin of the settlement chain (e.g., ETH or ATOM) from the treasury contract.
+    TransferNativeCoin(TransferNativeCoin),
     /// Transfers a fungible token from the treasury contract.
     TransferFungibleToken(TransferFungibleToken),
     /// Transfers an NFT from the treasury contract.
     TransferNonFungibleToken(TransferNonFungibleToken),
+    /// Transfers a semi-fungible token (e.g., of ERC-1155) from the treasury contract.
+    TransferSemiFungibleToken(TransferSemiFungibleToken),
+    /// Calls an arbitrary contract from the treasury contract.
+    ContractCall(ContractCall),
+    /// Advances the light client embedded in the treasury contract to the new validator set.
+    UpdateValidatorSet(UpdateValidatorSet),
+    /// Executes the messages in order, atomically; if any of them fails, none takes effect.
+    ///
+    /// It must not be empty nor contain another batch.
+    Batch(Vec<ExecutionMessage>),
+    /// Voids an earlier execution to the same treasury, which has not been delivered yet.
+    ///
+    /// The relayer drops the cancelled execution, and delivering this one moves the sequence
+    /// of the treasury past it for good. It can't be in a batch.
+    CancelExecution(CancelExecution),
+}
+
+impl ExecutionMessage {
+    /// Returns the kind of the message, which the head of the transaction carries
+    /// (e.g., `dummy` in `ex-dummy: ethereum`).
+    ///
+    /// This is the only table of the kinds, both for creating and reading the transactions.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Dummy { .. } => "dummy",
+            Self::TransferNativeCoin(_) => "transfer-native",
+            Self::TransferFungibleToken(_) => "transfer-ft",
+            Self::TransferNonFungibleToken(_) => "transfer-nft",
+            Self::TransferSemiFungibleToken(_) => "transfer-sft",
+            Self::ContractCall(_) => "contract-call",
+            Self::UpdateValidatorSet(_) => "update-validator-set",
+            Self::Batch(_) => "batch",
+            Self::CancelExecution(_) => "cancel",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct TransferNativeCoin {
+    pub amount: u128,
+    pub receiver_address: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
@@ -35,22 +337,291 @@ pub struct TransferNonFungibleToken {
     pub receiver_address: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct TransferSemiFungibleToken {
+    pub collection_address: String,
+    pub token_index: String,
+    pub amount: u128,
+    pub receiver_address: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct ContractCall {
+    pub contract_address: String,
+    /// The raw call data, interpreted by the settlement chain (e.g., ABI-encoded for an EVM chain).
+    pub calldata: Vec<u8>,
+    /// The amount of the native token to send along with the call.
+    pub value: u128,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct CancelExecution {
+    /// The contract sequence of the execution to cancel, which must be before that of the cancellation.
+    pub contract_sequence: u128,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct UpdateValidatorSet {
+    /// The height from which the validator set is effective.
+    pub height: BlockHeight,
+    pub validator_set: Vec<(PublicKey, VotingPower)>,
+}
+
+/// The encoding of [`Execution`] of the version `1`.
+#[derive(Serialize, Deserialize)]
+struct ExecutionV1 {
+    target_chain: ChainId,
+    contract_sequence: u128,
+    message: ExecutionMessage,
+}
+
+/// The binary encoding of [`Execution`], where the optional fields are always present
+/// as the encoding is not self-describing.
+///
+/// The fields are only appended by the later versions, so that the version is read first.
+#[derive(Serialize, Deserialize)]
+struct ExecutionBinary {
+    version: u32,
+    target_chain: ChainId,
+    treasury: Option<String>,
+    contract_sequence: u128,
+    valid_until: Option<Timestamp>,
+    fee: Option<FeeBudget>,
+    message: ExecutionMessage,
+}
+
+/// How the body of an execution transaction is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BodyEncoding {
+    /// `serde_spb::to_string`, which is readable as it is.
+    #[default]
+    Text,
+    /// The hex-encoded `serde_spb::to_vec`, marked by the `+bin` suffix of the kind in the head
+    /// (e.g., `ex-contract-call+bin: ethereum`).
+    ///
+    /// It's far more compact for the messages with call data, but not available for the version `1`.
+    /// The treasury contracts must read both encodings to deliver such executions.
+    Binary,
+}
+
+/// Reads only the version of an encoded [`Execution`], which is absent in the version `1`.
+#[derive(Deserialize)]
+struct ExecutionVersion {
+    version: Option<u32>,
+}
+
+/// Why a transaction couldn't be read as an execution.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ExecutionParseError {
+    /// The head is not of the form `ex-<kind>: <target chain>` with a valid chain id.
+    #[error("invalid head")]
+    InvalidHead,
+    /// The head and the body are to different chains.
+    #[error("chain mismatch: {head} in the head, {body} in the body")]
+    ChainMismatch { head: ChainId, body: ChainId },
+    /// The head and the body are of different kinds of messages.
+    #[error("kind mismatch: {head} in the head, {body} in the body")]
+    KindMismatch { head: String, body: &'static str },
+    /// The body is not an execution of a supported version.
+    #[error("deserialization failed: {0}")]
+    DeserializationFailed(String),
+}
+
+fn validate_version(version: u32) -> Result<(), String> {
+    if version == 0 || version > EXECUTION_VERSION {
+        return Err(format!("Unsupported version {version}"));
+    }
+    Ok(())
+}
+
+/// Checks the fields that only the later versions have.
+fn validate_fields(execution: &Execution) -> Result<(), String> {
+    let version = execution.version;
+    match &execution.treasury {
+        Some(_) if version < 3 => return Err(format!("Treasury address in the version {version}")),
+        Some(treasury) if treasury.is_empty() => return Err("Empty treasury address".to_string()),
+        _ => (),
+    }
+    if execution.valid_until.is_some() && version < 4 {
+        return Err(format!("Expiry in the version {version}"));
+    }
+    if let Some(fee) = &execution.fee {
+        if version < 5 {
+            return Err(format!("Fee budget in the version {version}"));
+        }
+        if fee.max_gas == Some(0) {
+            return Err("Zero max gas".to_string());
+        }
+        if let GasPriceStrategy::Fixed(0) | GasPriceStrategy::Capped(0) = fee.gas_price {
+            return Err("Zero gas price".to_string());
+        }
+        if fee.fee_payer.as_deref() == Some("") {
+            return Err("Empty fee payer".to_string());
+        }
+    }
+    if let ExecutionMessage::CancelExecution(_) = execution.message {
+        if version < 4 {
+            return Err(format!("Cancellation in the version {version}"));
+        }
+    }
+    Ok(())
+}
+
+/// Checks whether the execution is well-formed, regardless of the state of the target chain.
+pub fn validate_execution(execution: &Execution) -> Result<(), String> {
+    validate_version(execution.version)?;
+    execution.target_chain.validate()?;
+    validate_fields(execution)?;
+    match &execution.message {
+        ExecutionMessage::Batch(messages) => {
+            if messages.is_empty() {
+                return Err("Empty batch".to_string());
+            }
+            messages.iter().try_for_each(validate_message)
+        }
+        ExecutionMessage::CancelExecution(x) => {
+            if x.contract_sequence >= execution.contract_sequence {
+                return Err("Cancelling a later execution".to_string());
+            }
+            Ok(())
+        }
+        message => validate_message(message),
+    }
+}
+
+/// Checks whether the message, which is not a batch, is well-formed.
+fn validate_message(message: &ExecutionMessage) -> Result<(), String> {
+    let addresses = match message {
+        ExecutionMessage::Dummy { .. } => vec![],
+        ExecutionMessage::TransferNativeCoin(x) => {
+            if x.amount == 0 {
+                return Err("Zero amount".to_string());
+            }
+            vec![&x.receiver_address]
+        }
+        ExecutionMessage::TransferFungibleToken(x) => {
+            if x.amount == 0 {
+                return Err("Zero amount".to_string());
+            }
+            vec![&x.token_address, &x.receiver_address]
+        }
+        ExecutionMessage::TransferNonFungibleToken(x) => {
+            if x.token_index.is_empty() {
+                return Err("Empty token index".to_string());
+            }
+            vec![&x.collection_address, &x.receiver_address]
+        }
+        ExecutionMessage::TransferSemiFungibleToken(x) => {
+            if x.token_index.is_empty() {
+                return Err("Empty token index".to_string());
+            }
+            if x.amount == 0 {
+                return Err("Zero amount".to_string());
+            }
+            vec![&x.collection_address, &x.receiver_address]
+        }
+        ExecutionMessage::ContractCall(x) => vec![&x.contract_address],
+        ExecutionMessage::UpdateValidatorSet(x) => {
+            if x.validator_set
+                .iter()
+                .map(|(_, power)| power)
+                .sum::<VotingPower>()
+                == 0
+            {
+                return Err("No voting power".to_string());
+            }
+            let keys = x
+                .validator_set
+                .iter()
+                .map(|(key, _)| key)
+                .collect::<HashSet<_>>();
+            if keys.len() != x.validator_set.len() {
+                return Err("Duplicate validator".to_string());
+            }
+            vec![]
+        }
+        ExecutionMessage::Batch(_) => return Err("Nested batch".to_string()),
+        ExecutionMessage::CancelExecution(_) => return Err("Cancellation in a batch".to_string()),
+    };
+    if addresses.iter().any(|x| x.is_empty()) {
+        return Err("Empty address".to_string());
+    }
+    Ok(())
+}
+
 /// Creates an execution transaction that will be delivered to the target chain once finalized.
+///
+/// The addresses are validated leniently by the format of the target chain;
+/// see [`create_execution_transaction_with`] to validate them strictly.
 pub fn create_execution_transaction(
     execution: &Execution,
     author: PublicKey,
     timestamp: Timestamp,
 ) -> Result<Transaction, String> {
-    let head = match &execution.message {
-        ExecutionMessage::Dummy { .. } => format!("ex-dummy: {}", execution.target_chain),
-        ExecutionMessage::TransferFungibleToken(_) => {
-            format!("ex-transfer-ft: {}", execution.target_chain)
-        }
-        ExecutionMessage::TransferNonFungibleToken(_) => {
-            format!("ex-transfer-nft: {}", execution.target_chain)
+    create_execution_transaction_with(execution, author, timestamp, AddressValidation::Lenient)
+}
+
+/// Creates an execution transaction, validating the addresses as given.
+pub fn create_execution_transaction_with(
+    execution: &Execution,
+    author: PublicKey,
+    timestamp: Timestamp,
+    address_validation: AddressValidation,
+) -> Result<Transaction, String> {
+    create_execution_transaction_encoded(
+        execution,
+        author,
+        timestamp,
+        address_validation,
+        BodyEncoding::Text,
+    )
+}
+
+/// Creates an execution transaction with the body in the given encoding, validating the addresses as given.
+pub fn create_execution_transaction_encoded(
+    execution: &Execution,
+    author: PublicKey,
+    timestamp: Timestamp,
+    address_validation: AddressValidation,
+    encoding: BodyEncoding,
+) -> Result<Transaction, String> {
+    validate_execution(execution)?;
+    address::validate_execution_addresses(execution, address_validation)?;
+    let (suffix, body) = match encoding {
+        BodyEncoding::Text if execution.version == 1 => (
+            "",
+            serde_spb::to_string(&ExecutionV1 {
+                target_chain: execution.target_chain.clone(),
+                contract_sequence: execution.contract_sequence,
+                message: execution.message.clone(),
+            })
+            .unwrap(),
+        ),
+        BodyEncoding::Text => ("", serde_spb::to_string(&execution).unwrap()),
+        BodyEncoding::Binary if execution.version == 1 => {
+            return Err("Binary body in the version 1".to_string())
         }
+        BodyEncoding::Binary => (
+            "+bin",
+            hex::encode(
+                serde_spb::to_vec(&ExecutionBinary {
+                    version: execution.version,
+                    target_chain: execution.target_chain.clone(),
+                    treasury: execution.treasury.clone(),
+                    contract_sequence: execution.contract_sequence,
+                    valid_until: execution.valid_until,
+                    fee: execution.fee.clone(),
+                    message: execution.message.clone(),
+                })
+                .unwrap(),
+            ),
+        ),
     };
-    let body = serde_spb::to_string(&execution).unwrap();
+    let head = format!(
+        "ex-{}{suffix}: {}",
+        execution.message.kind(),
+        execution.target_chain
+    );
     Ok(Transaction {
         author,
         timestamp,
@@ -61,40 +632,680 @@ pub fn create_execution_transaction(
 }
 
 /// Reads an execution transaction and tries to extract an execution message.
-pub fn convert_transaction_to_execution(transaction: &Transaction) -> Result<Execution, String> {
-    let execution: Execution = serde_spb::from_str(&transaction.body).map_err(|e| e.to_string())?;
-    if !transaction.head.starts_with("ex-") {
-        return Err("Invalid head".to_string());
-    }
-    let execution_message =
-        transaction.head.split(": ").next().ok_or("Invalid head")?[3..].to_owned();
-    let target_chain = transaction.head.split(": ").nth(1).ok_or("Invalid head")?;
+///
+/// The body is read according to its version and encoding (see [`BodyEncoding`]),
+/// so the transactions of every supported version are accepted.
+pub fn convert_transaction_to_execution(
+    transaction: &Transaction,
+) -> Result<Execution, ExecutionParseError> {
+    let (kind, target_chain) = transaction
+        .head
+        .strip_prefix("ex-")
+        .and_then(|x| x.split_once(": "))
+        .ok_or(ExecutionParseError::InvalidHead)?;
+    let target_chain = target_chain
+        .parse::<ChainId>()
+        .map_err(|_| ExecutionParseError::InvalidHead)?;
+    let (kind, execution) = match kind.strip_suffix("+bin") {
+        Some(kind) => (kind, read_binary_body(&transaction.body)?),
+        None => (kind, read_text_body(&transaction.body)?),
+    };
     if execution.target_chain != target_chain {
-        return Err("Invalid target chain".to_string());
+        return Err(ExecutionParseError::ChainMismatch {
+            head: target_chain,
+            body: execution.target_chain,
+        });
+    }
+    if execution.message.kind() != kind {
+        return Err(ExecutionParseError::KindMismatch {
+            head: kind.to_owned(),
+            body: execution.message.kind(),
+        });
     }
-    match execution_message.as_str() {
-        "dummy" => {
-            if !matches!(execution.message, ExecutionMessage::Dummy { .. }) {
-                return Err("Invalid message".to_string());
+    validate_execution(&execution).map_err(ExecutionParseError::DeserializationFailed)?;
+    Ok(execution)
+}
+
+fn read_text_body(body: &str) -> Result<Execution, ExecutionParseError> {
+    let version: ExecutionVersion = read_body(body)?;
+    let execution = match version.version {
+        None => {
+            let execution: ExecutionV1 = read_body(body)?;
+            Execution {
+                version: 1,
+                target_chain: execution.target_chain,
+                treasury: None,
+                contract_sequence: execution.contract_sequence,
+                valid_until: None,
+                fee: None,
+                message: execution.message,
             }
         }
-        "transfer-ft" => {
-            if !matches!(
-                execution.message,
-                ExecutionMessage::TransferFungibleToken { .. }
-            ) {
-                return Err("Invalid message".to_string());
+        // The version `1` is never encoded explicitly.
+        Some(1) => {
+            return Err(ExecutionParseError::DeserializationFailed(
+                "Invalid version".to_string(),
+            ))
+        }
+        Some(version) => {
+            validate_version(version).map_err(ExecutionParseError::DeserializationFailed)?;
+            let execution = read_body(body)?;
+            validate_fields(&execution).map_err(ExecutionParseError::DeserializationFailed)?;
+            execution
+        }
+    };
+    Ok(execution)
+}
+
+fn read_binary_body(body: &str) -> Result<Execution, ExecutionParseError> {
+    let failed = |e: String| ExecutionParseError::DeserializationFailed(e);
+    let bytes = hex::decode(body).map_err(|e| failed(e.to_string()))?;
+    let version: u32 = serde_spb::from_slice(&bytes).map_err(|e| failed(e.to_string()))?;
+    if version == 1 {
+        return Err(failed("Invalid version".to_string()));
+    }
+    validate_version(version).map_err(failed)?;
+    let execution: ExecutionBinary =
+        serde_spb::from_slice(&bytes).map_err(|e| failed(e.to_string()))?;
+    let execution = Execution {
+        version: execution.version,
+        target_chain: execution.target_chain,
+        treasury: execution.treasury,
+        contract_sequence: execution.contract_sequence,
+        valid_until: execution.valid_until,
+        fee: execution.fee,
+        message: execution.message,
+    };
+    validate_fields(&execution).map_err(failed)?;
+    Ok(execution)
+}
+
+fn read_body<T: serde::de::DeserializeOwned>(body: &str) -> Result<T, ExecutionParseError> {
+    serde_spb::from_str(body).map_err(|e| ExecutionParseError::DeserializationFailed(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mythereum() -> ChainId {
+        ChainId::Custom("mythereum".to_owned())
+    }
+
+    #[test]
+    fn chain_id() {
+        for chain_id in ChainId::KNOWN {
+            assert_eq!(chain_id.as_str().parse::<ChainId>().unwrap(), chain_id);
+        }
+        assert_eq!("mythereum".parse::<ChainId>().unwrap(), mythereum());
+        "".parse::<ChainId>().unwrap_err();
+        "a: b".parse::<ChainId>().unwrap_err();
+        ChainId::Custom("ethereum".to_owned())
+            .validate()
+            .unwrap_err();
+
+        // Encoded the same as the plain strings.
+        assert_eq!(
+            serde_spb::to_string(&ChainId::EthereumGoerli).unwrap(),
+            serde_spb::to_string(&"ethereum-goerli").unwrap()
+        );
+        let decoded: ChainId =
+            serde_spb::from_str(&serde_spb::to_string(&"ethereum").unwrap()).unwrap();
+        assert_eq!(decoded, ChainId::Ethereum);
+        serde_spb::from_str::<ChainId>(&serde_spb::to_string(&"").unwrap()).unwrap_err();
+    }
+
+    fn contract_call(sequence: u128) -> Execution {
+        Execution {
+            version: EXECUTION_VERSION,
+            target_chain: mythereum(),
+            treasury: None,
+            contract_sequence: sequence,
+            valid_until: None,
+            fee: None,
+            message: ExecutionMessage::ContractCall(ContractCall {
+                contract_address: "contract-address".to_owned(),
+                calldata: vec![0xde, 0xad, 0xbe, 0xef],
+                value: 0,
+            }),
+        }
+    }
+
+    #[test]
+    fn contract_call_transaction() {
+        let (public_key, _) = generate_keypair("author");
+        let execution = contract_call(0);
+        validate_execution(&execution).unwrap();
+        let transaction = create_execution_transaction(&execution, public_key, 0).unwrap();
+        assert_eq!(transaction.head, "ex-contract-call: mythereum");
+        assert_eq!(
+            convert_transaction_to_execution(&transaction).unwrap(),
+            execution
+        );
+    }
+
+    #[test]
+    fn native_coin_transaction() {
+        let (public_key, _) = generate_keypair("author");
+        let execution = Execution {
+            version: EXECUTION_VERSION,
+            target_chain: mythereum(),
+            treasury: None,
+            contract_sequence: 0,
+            valid_until: None,
+            fee: None,
+            message: ExecutionMessage::TransferNativeCoin(TransferNativeCoin {
+                amount: 100,
+                receiver_address: "receiver-address".to_owned(),
+            }),
+        };
+        validate_execution(&execution).unwrap();
+        let mut transaction = create_execution_transaction(&execution, public_key, 0).unwrap();
+        assert_eq!(transaction.head, "ex-transfer-native: mythereum");
+        assert_eq!(
+            convert_transaction_to_execution(&transaction).unwrap(),
+            execution
+        );
+        // Not to be confused with a fungible token transfer.
+        transaction.head = "ex-transfer-ft: mythereum".to_owned();
+        assert_eq!(
+            convert_transaction_to_execution(&transaction).unwrap_err(),
+            ExecutionParseError::KindMismatch {
+                head: "transfer-ft".to_owned(),
+                body: "transfer-native"
+            }
+        );
+    }
+
+    #[test]
+    fn semi_fungible_token_transaction() {
+        let (public_key, _) = generate_keypair("author");
+        let mut execution = Execution {
+            version: EXECUTION_VERSION,
+            target_chain: mythereum(),
+            treasury: None,
+            contract_sequence: 0,
+            valid_until: None,
+            fee: None,
+            message: ExecutionMessage::TransferSemiFungibleToken(TransferSemiFungibleToken {
+                collection_address: "collection-address".to_owned(),
+                token_index: "7".to_owned(),
+                amount: 10,
+                receiver_address: "receiver-address".to_owned(),
+            }),
+        };
+        validate_execution(&execution).unwrap();
+        let transaction = create_execution_transaction(&execution, public_key, 0).unwrap();
+        assert_eq!(transaction.head, "ex-transfer-sft: mythereum");
+        assert_eq!(
+            convert_transaction_to_execution(&transaction).unwrap(),
+            execution
+        );
+
+        if let ExecutionMessage::TransferSemiFungibleToken(x) = &mut execution.message {
+            x.amount = 0;
+        }
+        validate_execution(&execution).unwrap_err();
+    }
+
+    #[test]
+    fn validator_set_transaction() {
+        let (public_key, _) = generate_keypair("author");
+        let validator_set = (0..4)
+            .map(|i| (generate_keypair(format!("validator{i}")).0, 1))
+            .collect::<Vec<_>>();
+        let mut execution = Execution {
+            version: EXECUTION_VERSION,
+            target_chain: mythereum(),
+            treasury: None,
+            contract_sequence: 0,
+            valid_until: None,
+            fee: None,
+            message: ExecutionMessage::UpdateValidatorSet(UpdateValidatorSet {
+                height: 10,
+                validator_set: validator_set.clone(),
+            }),
+        };
+        validate_execution(&execution).unwrap();
+        let transaction = create_execution_transaction(&execution, public_key, 0).unwrap();
+        assert_eq!(transaction.head, "ex-update-validator-set: mythereum");
+        assert_eq!(
+            convert_transaction_to_execution(&transaction).unwrap(),
+            execution
+        );
+
+        if let ExecutionMessage::UpdateValidatorSet(x) = &mut execution.message {
+            x.validator_set.push(validator_set[0].clone());
+        }
+        assert_eq!(
+            validate_execution(&execution).unwrap_err(),
+            "Duplicate validator".to_owned()
+        );
+        if let ExecutionMessage::UpdateValidatorSet(x) = &mut execution.message {
+            x.validator_set = Vec::new();
+        }
+        assert_eq!(
+            validate_execution(&execution).unwrap_err(),
+            "No voting power".to_owned()
+        );
+    }
+
+    #[test]
+    fn batch() {
+        let (public_key, _) = generate_keypair("author");
+        let mut execution = Execution {
+            version: EXECUTION_VERSION,
+            target_chain: mythereum(),
+            treasury: None,
+            contract_sequence: 0,
+            valid_until: None,
+            fee: None,
+            message: ExecutionMessage::Batch(vec![
+                contract_call(0).message,
+                ExecutionMessage::Dummy {
+                    msg: "hello".to_owned(),
+                },
+            ]),
+        };
+        validate_execution(&execution).unwrap();
+        let transaction = create_execution_transaction(&execution, public_key, 0).unwrap();
+        assert_eq!(transaction.head, "ex-batch: mythereum");
+        assert_eq!(
+            convert_transaction_to_execution(&transaction).unwrap(),
+            execution
+        );
+
+        execution.message = ExecutionMessage::Batch(vec![execution.message.clone()]);
+        assert_eq!(
+            validate_execution(&execution).unwrap_err(),
+            "Nested batch".to_owned()
+        );
+        execution.message = ExecutionMessage::Batch(Vec::new());
+        assert_eq!(
+            validate_execution(&execution).unwrap_err(),
+            "Empty batch".to_owned()
+        );
+    }
+
+    #[test]
+    fn v1_transaction() {
+        let (public_key, _) = generate_keypair("author");
+        // As created before the version was introduced.
+        let v1_body = r#"{
+            "target_chain": "mythereum",
+            "contract_sequence": 0,
+            "message": {
+                "ContractCall": {
+                    "contract_address": "contract-address",
+                    "calldata": [222, 173, 190, 239],
+                    "value": 0
+                }
+            }
+        }"#;
+        let mut transaction = Transaction {
+            author: public_key.clone(),
+            timestamp: 0,
+            head: "ex-contract-call: mythereum".to_owned(),
+            body: v1_body.to_owned(),
+            diff: Diff::None,
+        };
+        let mut execution = contract_call(0);
+        execution.version = 1;
+        assert_eq!(
+            convert_transaction_to_execution(&transaction).unwrap(),
+            execution
+        );
+        let mut tracker = crate::sequence::SequenceTracker::new(None);
+        tracker.scan(&[transaction.clone()]);
+        assert_eq!(tracker.allocate_sequence(&mythereum().into()), 1);
+
+        // Created again in the version 1, without the version.
+        let recreated = create_execution_transaction(&execution, public_key.clone(), 0).unwrap();
+        assert!(!recreated.body.contains("version"));
+        assert_eq!(
+            convert_transaction_to_execution(&recreated).unwrap(),
+            execution
+        );
+
+        // Migrated to the current version.
+        execution.version = EXECUTION_VERSION;
+        let migrated = create_execution_transaction(&execution, public_key, 0).unwrap();
+        assert!(migrated.body.contains("version"));
+        assert_eq!(
+            convert_transaction_to_execution(&migrated).unwrap(),
+            execution
+        );
+
+        // The version 1 is never explicit, and the future versions are unknown.
+        transaction.body = v1_body.replacen('{', r#"{ "version": 1,"#, 1);
+        assert_eq!(
+            convert_transaction_to_execution(&transaction).unwrap_err(),
+            ExecutionParseError::DeserializationFailed("Invalid version".to_owned())
+        );
+        transaction.body = v1_body.replacen('{', r#"{ "version": 6,"#, 1);
+        assert_eq!(
+            convert_transaction_to_execution(&transaction).unwrap_err(),
+            ExecutionParseError::DeserializationFailed("Unsupported version 6".to_owned())
+        );
+        execution.version = EXECUTION_VERSION + 1;
+        validate_execution(&execution).unwrap_err();
+    }
+
+    #[test]
+    fn parse_errors() {
+        let (public_key, _) = generate_keypair("author");
+        let transaction = create_execution_transaction(&contract_call(0), public_key, 0).unwrap();
+        let with_head = |head: &str| Transaction {
+            head: head.to_owned(),
+            ..transaction.clone()
+        };
+        for head in [
+            "contract-call: mythereum",
+            "ex-contract-call",
+            "ex-contract-call:mythereum",
+            "ex-contract-call: ",
+            "ex-contract-call: a: b",
+        ] {
+            assert_eq!(
+                convert_transaction_to_execution(&with_head(head)).unwrap_err(),
+                ExecutionParseError::InvalidHead
+            );
+        }
+        assert_eq!(
+            convert_transaction_to_execution(&with_head("ex-contract-call: ethereum")).unwrap_err(),
+            ExecutionParseError::ChainMismatch {
+                head: ChainId::Ethereum,
+                body: mythereum()
             }
+        );
+        assert_eq!(
+            convert_transaction_to_execution(&with_head("ex-unknown: mythereum")).unwrap_err(),
+            ExecutionParseError::KindMismatch {
+                head: "unknown".to_owned(),
+                body: "contract-call"
+            }
+        );
+        let not_execution = Transaction {
+            body: "hello".to_owned(),
+            ..transaction
+        };
+        assert!(matches!(
+            convert_transaction_to_execution(&not_execution),
+            Err(ExecutionParseError::DeserializationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_execution() {
+        let mut execution = contract_call(0);
+        if let ExecutionMessage::ContractCall(x) = &mut execution.message {
+            x.contract_address = String::new();
         }
-        "transfer-nft" => {
-            if !matches!(
-                execution.message,
-                ExecutionMessage::TransferNonFungibleToken { .. }
-            ) {
-                return Err("Invalid message".to_string());
+        validate_execution(&execution).unwrap_err();
+    }
+
+    #[test]
+    fn treasury_transaction() {
+        let (public_key, _) = generate_keypair("author");
+        let mut execution = contract_call(0);
+        execution.treasury = Some("treasury-address".to_owned());
+        validate_execution(&execution).unwrap();
+        let mut transaction =
+            create_execution_transaction(&execution, public_key.clone(), 0).unwrap();
+        assert_eq!(
+            convert_transaction_to_execution(&transaction).unwrap(),
+            execution
+        );
+        assert_eq!(
+            execution.treasury_id().to_string(),
+            "mythereum: treasury-address"
+        );
+
+        // Without a treasury, encoded the same as in the version 2.
+        let mut default_treasury = contract_call(0);
+        default_treasury.version = 2;
+        let v2 = create_execution_transaction(&default_treasury, public_key.clone(), 0).unwrap();
+        assert!(!v2.body.contains("treasury"));
+        assert_eq!(
+            convert_transaction_to_execution(&v2).unwrap(),
+            default_treasury
+        );
+
+        // Not supported before the version 3.
+        execution.version = 2;
+        validate_execution(&execution).unwrap_err();
+        create_execution_transaction(&execution, public_key, 0).unwrap_err();
+        transaction.body = transaction.body.replacen(
+            &format!(r#""version": {EXECUTION_VERSION}"#),
+            r#""version": 2"#,
+            1,
+        );
+        assert!(matches!(
+            convert_transaction_to_execution(&transaction),
+            Err(ExecutionParseError::DeserializationFailed(_))
+        ));
+        execution.version = EXECUTION_VERSION;
+        execution.treasury = Some(String::new());
+        validate_execution(&execution).unwrap_err();
+    }
+
+    #[test]
+    fn expiry_and_cancellation() {
+        let (public_key, _) = generate_keypair("author");
+        let mut execution = contract_call(0);
+        execution.valid_until = Some(1000);
+        let transaction = create_execution_transaction(&execution, public_key.clone(), 0).unwrap();
+        assert_eq!(
+            convert_transaction_to_execution(&transaction).unwrap(),
+            execution
+        );
+        execution.version = 3;
+        assert_eq!(
+            validate_execution(&execution).unwrap_err(),
+            "Expiry in the version 3".to_owned()
+        );
+
+        let mut cancellation = Execution {
+            version: EXECUTION_VERSION,
+            target_chain: mythereum(),
+            treasury: None,
+            contract_sequence: 1,
+            valid_until: None,
+            fee: None,
+            message: ExecutionMessage::CancelExecution(CancelExecution {
+                contract_sequence: 0,
+            }),
+        };
+        validate_execution(&cancellation).unwrap();
+        let transaction = create_execution_transaction(&cancellation, public_key, 0).unwrap();
+        assert_eq!(transaction.head, "ex-cancel: mythereum");
+        assert_eq!(
+            convert_transaction_to_execution(&transaction).unwrap(),
+            cancellation
+        );
+
+        cancellation.contract_sequence = 0;
+        assert_eq!(
+            validate_execution(&cancellation).unwrap_err(),
+            "Cancelling a later execution".to_owned()
+        );
+        cancellation.message = ExecutionMessage::Batch(vec![cancellation.message.clone()]);
+        assert_eq!(
+            validate_execution(&cancellation).unwrap_err(),
+            "Cancellation in a batch".to_owned()
+        );
+    }
+
+    #[test]
+    fn malformed_batches() {
+        let (public_key, _) = generate_keypair("author");
+        let mut execution = contract_call(0);
+        let valid = execution.message.clone();
+        for (message, error) in [
+            (ExecutionMessage::Batch(vec![]), "Empty batch"),
+            (
+                ExecutionMessage::Batch(vec![ExecutionMessage::Batch(vec![valid.clone()])]),
+                "Nested batch",
+            ),
+            (
+                ExecutionMessage::Batch(vec![ExecutionMessage::CancelExecution(CancelExecution {
+                    contract_sequence: 0,
+                })]),
+                "Cancellation in a batch",
+            ),
+        ] {
+            execution.message = message;
+            assert_eq!(
+                create_execution_transaction(&execution, public_key.clone(), 0).unwrap_err(),
+                error
+            );
+            // Nor are they accepted from a transaction made elsewhere, in any encoding.
+            for (suffix, body) in [
+                ("", serde_spb::to_string(&execution).unwrap()),
+                (
+                    "",
+                    serde_spb::to_string(&ExecutionV1 {
+                        target_chain: execution.target_chain.clone(),
+                        contract_sequence: execution.contract_sequence,
+                        message: execution.message.clone(),
+                    })
+                    .unwrap(),
+                ),
+                (
+                    "+bin",
+                    hex::encode(
+                        serde_spb::to_vec(&ExecutionBinary {
+                            version: execution.version,
+                            target_chain: execution.target_chain.clone(),
+                            treasury: None,
+                            contract_sequence: execution.contract_sequence,
+                            valid_until: None,
+                            fee: None,
+                            message: execution.message.clone(),
+                        })
+                        .unwrap(),
+                    ),
+                ),
+            ] {
+                let transaction = Transaction {
+                    author: public_key.clone(),
+                    timestamp: 0,
+                    head: format!("ex-batch{suffix}: mythereum"),
+                    body,
+                    diff: Diff::None,
+                };
+                assert_eq!(
+                    convert_transaction_to_execution(&transaction),
+                    Err(ExecutionParseError::DeserializationFailed(error.to_owned()))
+                );
             }
         }
-        _ => return Err("Invalid message".to_string()),
     }
-    Ok(execution)
+
+    #[test]
+    fn fee_budget() {
+        let (public_key, _) = generate_keypair("author");
+        let mut execution = contract_call(0);
+        execution.fee = Some(FeeBudget {
+            max_gas: Some(100_000),
+            gas_price: GasPriceStrategy::Capped(30_000_000_000),
+            fee_payer: Some("relayer".to_owned()),
+        });
+        let transaction = create_execution_transaction(&execution, public_key, 0).unwrap();
+        assert_eq!(
+            convert_transaction_to_execution(&transaction).unwrap(),
+            execution
+        );
+
+        let mut invalid = execution.clone();
+        invalid.fee.as_mut().unwrap().gas_price = GasPriceStrategy::Fixed(0);
+        assert_eq!(
+            validate_execution(&invalid).unwrap_err(),
+            "Zero gas price".to_owned()
+        );
+        execution.version = 4;
+        assert_eq!(
+            validate_execution(&execution).unwrap_err(),
+            "Fee budget in the version 4".to_owned()
+        );
+
+        for strategy in [
+            GasPriceStrategy::Market,
+            GasPriceStrategy::Fixed(1),
+            GasPriceStrategy::Capped(u128::MAX),
+        ] {
+            assert_eq!(
+                strategy.to_string().parse::<GasPriceStrategy>().unwrap(),
+                strategy
+            );
+        }
+        "fixed".parse::<GasPriceStrategy>().unwrap_err();
+        "capped:-1".parse::<GasPriceStrategy>().unwrap_err();
+    }
+
+    #[test]
+    fn binary_body() {
+        let (public_key, _) = generate_keypair("author");
+        let mut execution = contract_call(0);
+        execution.valid_until = Some(1000);
+        if let ExecutionMessage::ContractCall(x) = &mut execution.message {
+            x.calldata = vec![0xab; 1024];
+        }
+        let encode = |execution: &Execution, encoding| {
+            create_execution_transaction_encoded(
+                execution,
+                public_key.clone(),
+                0,
+                AddressValidation::Lenient,
+                encoding,
+            )
+        };
+        let text = encode(&execution, BodyEncoding::Text).unwrap();
+        let binary = encode(&execution, BodyEncoding::Binary).unwrap();
+        assert_eq!(binary.head, "ex-contract-call+bin: mythereum");
+        assert!(binary.body.len() * 2 < text.body.len());
+        assert_eq!(
+            convert_transaction_to_execution(&binary).unwrap(),
+            execution
+        );
+
+        // The encoding is told by the head.
+        let mismatched = Transaction {
+            head: text.head.clone(),
+            ..binary.clone()
+        };
+        convert_transaction_to_execution(&mismatched).unwrap_err();
+        let mismatched = Transaction {
+            head: binary.head.clone(),
+            ..text
+        };
+        convert_transaction_to_execution(&mismatched).unwrap_err();
+
+        execution.version = 1;
+        execution.valid_until = None;
+        assert_eq!(
+            encode(&execution, BodyEncoding::Binary).unwrap_err(),
+            "Binary body in the version 1".to_owned()
+        );
+    }
+
+    #[test]
+    fn treasury_id() {
+        for (s, treasury_id) in [
+            ("ethereum", TreasuryId::from(ChainId::Ethereum)),
+            (
+                "mythereum: 0x1234",
+                TreasuryId::new(mythereum(), Some("0x1234".to_owned())),
+            ),
+        ] {
+            assert_eq!(s.parse::<TreasuryId>().unwrap(), treasury_id);
+            assert_eq!(treasury_id.to_string(), s);
+            assert_eq!(
+                serde_spb::to_string(&treasury_id).unwrap(),
+                serde_spb::to_string(&s).unwrap()
+            );
+        }
+        "ethereum: ".parse::<TreasuryId>().unwrap_err();
+        ": 0x1234".parse::<TreasuryId>().unwrap_err();
+    }
 }
@@ -0,0 +1,165 @@
+//! Querying what the treasuries actually hold on their settlement chains.
+//!
+//! A [`HoldingsQuerier`] reads the balances of the tokens tracked for each chain through its backend,
+//! and caches them for a while, so that the governance tooling can show them repeatedly
+//! (e.g., while drafting transfer executions) without hitting the full nodes every time.
+use super::*;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// The tokens whose balances are queried, as the treasury can't enumerate what it holds.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TrackedTokens {
+    /// The addresses of the fungible tokens (or the native denominations, if the backend supports them).
+    pub fungible_tokens: Vec<String>,
+    /// The addresses of the non-fungible token collections.
+    pub non_fungible_collections: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TreasuryHoldings {
+    /// The balances of the fungible tokens, by their addresses.
+    pub fungible_tokens: BTreeMap<String, Decimal>,
+    /// The token indices held, by the addresses of their collections.
+    pub non_fungible_tokens: BTreeMap<String, Vec<String>>,
+    /// When the holdings were queried, in milliseconds.
+    pub queried_at: Timestamp,
+}
+
+pub struct HoldingsQuerier {
+    /// How long the queried holdings are served from the cache, in milliseconds.
+    max_age_ms: u64,
+    chains: HashMap<ChainId, (Arc<dyn SettlementChain>, TrackedTokens)>,
+    /// Never held across an `.await`.
+    cache: Mutex<HashMap<ChainId, TreasuryHoldings>>,
+}
+
+impl HoldingsQuerier {
+    pub fn new(max_age_ms: u64) -> Self {
+        Self {
+            max_age_ms,
+            chains: HashMap::new(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the backend of the chain and the tokens to query on it, dropping its cached holdings.
+    pub fn set_chain(
+        &mut self,
+        target_chain: ChainId,
+        chain: Arc<dyn SettlementChain>,
+        tokens: TrackedTokens,
+    ) {
+        self.cache.lock().unwrap().remove(&target_chain);
+        self.chains.insert(target_chain, (chain, tokens));
+    }
+
+    /// Returns the holdings on the chain, querying them if not cached or older than the max age at `now`.
+    pub async fn get(
+        &self,
+        target_chain: &ChainId,
+        now: Timestamp,
+    ) -> Result<TreasuryHoldings, Error> {
+        if let Some(holdings) = self.cached(target_chain) {
+            if now < holdings.queried_at + self.max_age_ms as Timestamp {
+                return Ok(holdings);
+            }
+        }
+        self.refresh(target_chain, now).await
+    }
+
+    /// Queries the holdings on the chain regardless of the cache, and caches them.
+    pub async fn refresh(
+        &self,
+        target_chain: &ChainId,
+        now: Timestamp,
+    ) -> Result<TreasuryHoldings, Error> {
+        let (chain, tokens) = self
+            .chains
+            .get(target_chain)
+            .ok_or_else(|| eyre::eyre!("no backend for {target_chain}"))?;
+        let mut holdings = TreasuryHoldings {
+            queried_at: now,
+            ..Default::default()
+        };
+        for address in &tokens.fungible_tokens {
+            let balance = chain
+                .get_treasury_fungible_token_balance(address.clone())
+                .await?;
+            holdings.fungible_tokens.insert(address.clone(), balance);
+        }
+        for address in &tokens.non_fungible_collections {
+            let token_indices = chain
+                .get_treasury_non_fungible_token_balance(address.clone())
+                .await?;
+            holdings
+                .non_fungible_tokens
+                .insert(address.clone(), token_indices);
+        }
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(target_chain.clone(), holdings.clone());
+        Ok(holdings)
+    }
+
+    /// Returns the cached holdings on the chain, however old they are.
+    pub fn cached(&self, target_chain: &ChainId) -> Option<TreasuryHoldings> {
+        self.cache.lock().unwrap().get(target_chain).cloned()
+    }
+
+    /// Drops the cached holdings on the chain (e.g., after an execution is delivered to it).
+    pub fn invalidate(&self, target_chain: &ChainId) {
+        self.cache.lock().unwrap().remove(target_chain);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use relay::tests::MockChain;
+
+    #[tokio::test]
+    async fn holdings() {
+        let ethereum = Arc::new(MockChain::new("ethereum", 0));
+        ethereum
+            .fungible_tokens
+            .lock()
+            .unwrap()
+            .insert("usdc".to_owned(), Decimal::new(1005, 1));
+        ethereum
+            .non_fungible_tokens
+            .lock()
+            .unwrap()
+            .insert("punks".to_owned(), vec!["1".to_owned(), "7".to_owned()]);
+        let mut querier = HoldingsQuerier::new(1000);
+        querier.set_chain(
+            ChainId::Ethereum,
+            Arc::clone(&ethereum) as _,
+            TrackedTokens {
+                fungible_tokens: vec!["usdc".to_owned()],
+                non_fungible_collections: vec!["punks".to_owned()],
+            },
+        );
+
+        let holdings = querier.get(&ChainId::Ethereum, 0).await.unwrap();
+        assert_eq!(holdings.fungible_tokens["usdc"], Decimal::new(1005, 1));
+        assert_eq!(holdings.non_fungible_tokens["punks"], vec!["1", "7"]);
+
+        // Served from the cache until it gets old.
+        ethereum
+            .fungible_tokens
+            .lock()
+            .unwrap()
+            .insert("usdc".to_owned(), Decimal::new(5, 0));
+        let cached = querier.get(&ChainId::Ethereum, 999).await.unwrap();
+        assert_eq!(cached, holdings);
+        let refreshed = querier.get(&ChainId::Ethereum, 1000).await.unwrap();
+        assert_eq!(refreshed.fungible_tokens["usdc"], Decimal::new(5, 0));
+        assert_eq!(refreshed.queried_at, 1000);
+        querier.invalidate(&ChainId::Ethereum);
+        assert_eq!(querier.cached(&ChainId::Ethereum), None);
+
+        assert!(querier.get(&ChainId::Polygon, 0).await.is_err());
+    }
+}
@@ -1,4 +1,21 @@
+pub mod abi;
+pub mod address;
+#[cfg(feature = "cosmwasm")]
+pub mod cosmwasm;
+pub mod delivery;
+pub mod deposit;
+#[cfg(feature = "ethereum")]
+pub mod ethereum;
 pub mod execution;
+pub mod holdings;
+pub mod light_client_update;
+pub mod proof;
+pub mod relay;
+pub mod relayer;
+pub mod sequence;
+pub mod simulation;
+#[cfg(feature = "test-util")]
+pub mod test_vectors;
 
 use execution::*;
 use eyre::Error;
@@ -16,15 +33,36 @@ pub struct SettlementChainBlock {
     pub timestamp: u64,
 }
 
+/// The outcome of a message delivering transaction submitted to a settlement chain.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+pub enum TransactionOutcome {
+    /// The transaction is not included yet, or not as deep as the chain considers final.
+    Unconfirmed,
+    Succeeded,
+    /// The transaction is included but has failed (e.g., reverted), with the reason.
+    Failed(String),
+}
+
 /// An abstraction of a settlement chain with its treasury deployed on it.
 ///
 /// One trivial implementation of this trait would carry the API endpoint of the full node and
 /// the relayer account used to submit message delivering transactions.
+///
+/// The implementations for particular chains are behind their features (e.g., `ethereum` and `cosmwasm`),
+/// and the relaying logic in [`relay`] works with any of them.
 #[async_trait::async_trait]
 pub trait SettlementChain: Send + Sync {
     /// Returns the name of the chain.
     async fn get_chain_name(&self) -> String;
 
+    /// Returns the address of the treasury contract, if known to the implementation.
+    ///
+    /// A chain may host several treasuries; the executions naming theirs are relayed
+    /// only through the implementation of the same address.
+    async fn get_treasury_address(&self) -> Option<String> {
+        None
+    }
+
     /// Checks whether the chain is healthy and the full node is running.
     async fn check_connection(&self) -> Result<(), Error>;
 
@@ -40,6 +78,13 @@ pub trait SettlementChain: Send + Sync {
     /// Returns the latest header that the light client has verified.
     async fn get_light_client_header(&self) -> Result<BlockHeader, Error>;
 
+    /// Returns the height of the latest header that the light client has verified.
+    ///
+    /// Implementations may override this if the height can be read without the whole header.
+    async fn get_light_client_height(&self) -> Result<BlockHeight, Error> {
+        Ok(self.get_light_client_header().await?.height)
+    }
+
     /// Returns the current balance of a particular fungible token in the treasury contract.
     async fn get_treasury_fungible_token_balance(&self, address: String) -> Result<Decimal, Error>;
 
@@ -50,6 +95,34 @@ pub trait SettlementChain: Send + Sync {
         address: String,
     ) -> Result<Vec<String>, Error>;
 
+    /// Returns the decimals of a fungible token, which the amounts in the executions are scaled down by.
+    async fn get_fungible_token_decimals(&self, _address: String) -> Result<u32, Error> {
+        Err(eyre::eyre!(
+            "token decimals are not supported for this chain"
+        ))
+    }
+
+    /// Checks whether the address is well-formed on this chain (e.g., with a valid checksum).
+    ///
+    /// The default accepts any address.
+    fn validate_address(&self, _address: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Returns the deposit events of the treasury contract emitted in the blocks of the heights `from..=to`.
+    ///
+    /// The heights are of the settlement chain, and only the finalized blocks (see [`SettlementChain::get_last_block`])
+    /// are queried by the [`deposit::DepositWatcher`].
+    async fn get_treasury_events(
+        &self,
+        _from: u64,
+        _to: u64,
+    ) -> Result<Vec<deposit::TreasuryEvent>, Error> {
+        Err(eyre::eyre!(
+            "treasury events are not supported for this chain"
+        ))
+    }
+
     /// Updates the light client state in the treasury by providing the next, valid block header and its proof.
     ///
     /// This is one of the message delivery methods; a transaction that carries the given data will be submitted to the chain.
@@ -59,14 +132,30 @@ pub trait SettlementChain: Send + Sync {
         proof: FinalizationProof,
     ) -> Result<(), Error>;
 
+    /// Estimates the fee (in the native token) to deliver the execution, without submitting it.
+    ///
+    /// Implementations should simulate the execution against the current state of the chain
+    /// and fail if it would fail (e.g., insufficient treasury balance).
+    async fn estimate_execution_fee(&self, _execution: &Execution) -> Result<Decimal, Error> {
+        Err(eyre::eyre!(
+            "fee estimation is not supported for this chain"
+        ))
+    }
+
     /// Delivers an execution transaction to the settlement chain with the commitment proof.
     ///
-    /// - `execution`: The execution to deliver.
+    /// - `transaction`: The execution transaction to deliver, which the proof is of.
     /// - `block_height`: The height of the block that the transaction is included in.
+    ///
+    /// Returns the hash of the submitted transaction, which might not be confirmed yet;
+    /// whether the execution has succeeded is known by [`SettlementChain::check_transaction`].
     async fn execute(
         &self,
-        execution: Execution,
+        transaction: Transaction,
         block_height: u64,
         proof: MerkleProof,
-    ) -> Result<(), Error>;
+    ) -> Result<String, Error>;
+
+    /// Checks the outcome of a transaction submitted by this relayer, identified by its hash.
+    async fn check_transaction(&self, transaction_hash: &str) -> Result<TransactionOutcome, Error>;
 }
@@ -0,0 +1,397 @@
+//! The compact updates of the light clients on the settlement chains.
+//!
+//! Advancing a light client header by header costs a transaction (and a full header with
+//! its finalization proof) per block. A [`LightClientUpdate`] instead carries a run of
+//! consecutive headers on top of the header that the light client already trusts,
+//! without what the light client can derive by itself:
+//!
+//! - the heights and the previous hashes, which follow from the previous headers,
+//! - the finalization proofs except the last one, as each is already in the next header
+//!   as its `prev_block_finalization_proof`,
+//! - the validator sets, which are replaced with their changes from the previous headers,
+//! - the versions, unless they change.
+use super::*;
+use relay::FinalizedHeader;
+use simperby_common::verify;
+
+/// The validator set of a header, relative to that of its previous header.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ValidatorSetChange {
+    Unchanged,
+    /// Applied to the previous validator set by removing the validators (keeping the order
+    /// of the rest), then updating the voting powers in place, then appending the new validators.
+    Diff {
+        removed: Vec<PublicKey>,
+        updated: Vec<(PublicKey, VotingPower)>,
+        appended: Vec<(PublicKey, VotingPower)>,
+    },
+    /// The whole validator set, for the changes that a diff can't express (e.g., a reordering).
+    Full(Vec<(PublicKey, VotingPower)>),
+}
+
+impl ValidatorSetChange {
+    /// Calculates the smallest change from the previous validator set to the next one.
+    pub fn between(
+        previous: &[(PublicKey, VotingPower)],
+        next: &[(PublicKey, VotingPower)],
+    ) -> Self {
+        if previous == next {
+            return ValidatorSetChange::Unchanged;
+        }
+        let removed = previous
+            .iter()
+            .filter(|(x, _)| !next.iter().any(|(y, _)| x == y))
+            .map(|(x, _)| x.clone())
+            .collect::<Vec<_>>();
+        let kept = previous
+            .iter()
+            .filter(|(x, _)| !removed.contains(x))
+            .collect::<Vec<_>>();
+        let updated = kept
+            .iter()
+            .zip(next)
+            .filter(|((_, x), (_, y))| x != y)
+            .map(|(_, (key, power))| (key.clone(), *power))
+            .collect::<Vec<_>>();
+        let appended = next.get(kept.len()..).unwrap_or_default().to_vec();
+        let size = removed.len() + updated.len() + appended.len();
+        let diff = ValidatorSetChange::Diff {
+            removed,
+            updated,
+            appended,
+        };
+        // The diff doesn't hold if the kept validators have been reordered.
+        if size < next.len() && diff.apply(previous).ok().as_deref() == Some(next) {
+            diff
+        } else {
+            ValidatorSetChange::Full(next.to_vec())
+        }
+    }
+
+    /// Applies the change to the previous validator set.
+    pub fn apply(
+        &self,
+        previous: &[(PublicKey, VotingPower)],
+    ) -> Result<Vec<(PublicKey, VotingPower)>, String> {
+        match self {
+            ValidatorSetChange::Unchanged => Ok(previous.to_vec()),
+            ValidatorSetChange::Diff {
+                removed,
+                updated,
+                appended,
+            } => {
+                let mut validator_set = previous
+                    .iter()
+                    .filter(|(x, _)| !removed.contains(x))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if validator_set.len() + removed.len() != previous.len() {
+                    return Err("removing a validator not in the set".to_owned());
+                }
+                for (key, power) in updated {
+                    validator_set
+                        .iter_mut()
+                        .find(|(x, _)| x == key)
+                        .ok_or_else(|| format!("updating {key}, which is not in the set"))?
+                        .1 = *power;
+                }
+                for (key, power) in appended {
+                    if validator_set.iter().any(|(x, _)| x == key) {
+                        return Err(format!("appending {key}, which is already in the set"));
+                    }
+                    validator_set.push((key.clone(), *power));
+                }
+                Ok(validator_set)
+            }
+            ValidatorSetChange::Full(validator_set) => Ok(validator_set.clone()),
+        }
+    }
+}
+
+/// A block header without what follows from its previous header.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CompactHeader {
+    pub author: PublicKey,
+    pub prev_block_finalization_proof: FinalizationProof,
+    pub timestamp: Timestamp,
+    pub commit_merkle_root: Hash256,
+    pub repository_merkle_root: Hash256,
+    pub validator_set: ValidatorSetChange,
+    /// `None` if the same as that of the previous header.
+    pub version: Option<String>,
+}
+
+/// Consecutive headers on top of the header that the light client trusts, to advance it at once.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LightClientUpdate {
+    pub headers: Vec<CompactHeader>,
+    /// The finalization proof of the last header.
+    pub finalization_proof: FinalizationProof,
+}
+
+impl LightClientUpdate {
+    /// Builds the update from the trusted header through the headers, verifying them on the way.
+    ///
+    /// The headers must be the ones right after the trusted header, in order of their heights.
+    pub fn build(trusted: &BlockHeader, headers: &[FinalizedHeader]) -> Result<Self, Error> {
+        let last = headers
+            .last()
+            .ok_or_else(|| eyre::eyre!("no header to update with"))?;
+        let mut previous = trusted;
+        let mut compact_headers = Vec::new();
+        for FinalizedHeader { header, .. } in headers {
+            verify::verify_header_to_header(previous, header)?;
+            compact_headers.push(CompactHeader {
+                author: header.author.clone(),
+                prev_block_finalization_proof: header.prev_block_finalization_proof.clone(),
+                timestamp: header.timestamp,
+                commit_merkle_root: header.commit_merkle_root,
+                repository_merkle_root: header.repository_merkle_root,
+                validator_set: ValidatorSetChange::between(
+                    &previous.validator_set,
+                    &header.validator_set,
+                ),
+                version: (header.version != previous.version).then(|| header.version.clone()),
+            });
+            previous = header;
+        }
+        verify::verify_finalization_proof(&last.header, &last.proof)?;
+        Ok(Self {
+            headers: compact_headers,
+            finalization_proof: last.proof.clone(),
+        })
+    }
+
+    /// Restores the full headers on top of the trusted header, verifying them on the way.
+    ///
+    /// This is what the light client does with the update; the returned headers are finalized.
+    pub fn expand(&self, trusted: &BlockHeader) -> Result<Vec<FinalizedHeader>, Error> {
+        let mut previous = trusted.clone();
+        let mut headers: Vec<FinalizedHeader> = Vec::new();
+        for compact_header in &self.headers {
+            let header = BlockHeader {
+                author: compact_header.author.clone(),
+                prev_block_finalization_proof: compact_header.prev_block_finalization_proof.clone(),
+                previous_hash: previous.to_hash256(),
+                height: previous.height + 1,
+                timestamp: compact_header.timestamp,
+                commit_merkle_root: compact_header.commit_merkle_root,
+                repository_merkle_root: compact_header.repository_merkle_root,
+                validator_set: compact_header
+                    .validator_set
+                    .apply(&previous.validator_set)
+                    .map_err(|e| eyre::eyre!("invalid validator set change: {e}"))?,
+                version: compact_header
+                    .version
+                    .clone()
+                    .unwrap_or_else(|| previous.version.clone()),
+            };
+            verify::verify_header_to_header(&previous, &header)?;
+            if let Some(last) = headers.last_mut() {
+                // Each proof is in its next header.
+                last.proof = header.prev_block_finalization_proof.clone();
+            }
+            headers.push(FinalizedHeader {
+                header: header.clone(),
+                proof: Vec::new(),
+            });
+            previous = header;
+        }
+        let last = headers
+            .last_mut()
+            .ok_or_else(|| eyre::eyre!("empty update"))?;
+        verify::verify_finalization_proof(&last.header, &self.finalization_proof)?;
+        last.proof = self.finalization_proof.clone();
+        Ok(headers)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        Ok(serde_spb::to_vec(self)?)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        Ok(serde_spb::from_slice(bytes)?)
+    }
+}
+
+/// Builds the updates from the trusted header through the headers,
+/// each with at most `max_headers` headers and on top of the last header of the previous one.
+pub fn build_updates(
+    trusted: &BlockHeader,
+    headers: &[FinalizedHeader],
+    max_headers: usize,
+) -> Result<Vec<LightClientUpdate>, Error> {
+    if max_headers == 0 {
+        return Err(eyre::eyre!("max_headers must be positive"));
+    }
+    let mut trusted = trusted;
+    let mut updates = Vec::new();
+    for chunk in headers.chunks(max_headers) {
+        updates.push(LightClientUpdate::build(trusted, chunk)?);
+        trusted = &chunk.last().unwrap().header;
+    }
+    Ok(updates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds the genesis header and the finalized headers after it, where the validator set
+    /// changes at height 3 and the version at height 4.
+    fn headers(count: u64) -> (BlockHeader, Vec<FinalizedHeader>) {
+        let keys = (0..5)
+            .map(|i| generate_keypair(format!("validator{i}")))
+            .collect::<Vec<_>>();
+        let sign = |header: &BlockHeader| {
+            header
+                .validator_set
+                .iter()
+                .map(|(public_key, _)| {
+                    let (_, private_key) = keys.iter().find(|(x, _)| x == public_key).unwrap();
+                    TypedSignature::sign(header, private_key).unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut previous = BlockHeader {
+            author: keys[1].0.clone(),
+            prev_block_finalization_proof: Vec::new(),
+            previous_hash: Hash256::zero(),
+            height: 0,
+            timestamp: 0,
+            commit_merkle_root: Hash256::zero(),
+            repository_merkle_root: Hash256::zero(),
+            validator_set: keys[..4].iter().map(|(x, _)| (x.clone(), 1)).collect(),
+            version: "0.0.0".to_owned(),
+        };
+        let genesis = previous.clone();
+        let mut headers = Vec::new();
+        for height in 1..=count {
+            let mut validator_set = previous.validator_set.clone();
+            if height == 3 {
+                validator_set.remove(0);
+                validator_set[0].1 = 2;
+                validator_set.push((keys[4].0.clone(), 1));
+            }
+            let header = BlockHeader {
+                author: keys[1].0.clone(),
+                prev_block_finalization_proof: sign(&previous),
+                previous_hash: previous.to_hash256(),
+                height,
+                timestamp: height as Timestamp,
+                commit_merkle_root: Hash256::hash(height.to_be_bytes()),
+                repository_merkle_root: Hash256::zero(),
+                validator_set,
+                version: if height >= 4 { "0.1.0" } else { "0.0.0" }.to_owned(),
+            };
+            let proof = sign(&header);
+            headers.push(FinalizedHeader {
+                header: header.clone(),
+                proof,
+            });
+            previous = header;
+        }
+        (genesis, headers)
+    }
+
+    #[test]
+    fn update() {
+        let (genesis, headers) = headers(5);
+        let update = LightClientUpdate::build(&genesis, &headers).unwrap();
+        assert_eq!(update.headers.len(), 5);
+        assert_eq!(
+            update.headers[1].validator_set,
+            ValidatorSetChange::Unchanged
+        );
+        assert_eq!(
+            update.headers[2].validator_set,
+            ValidatorSetChange::Diff {
+                removed: vec![genesis.validator_set[0].0.clone()],
+                updated: vec![(genesis.validator_set[1].0.clone(), 2)],
+                appended: vec![headers[2].header.validator_set[3].clone()],
+            }
+        );
+        assert_eq!(update.headers[2].version, None);
+        assert_eq!(update.headers[3].version, Some("0.1.0".to_owned()));
+
+        let encoded = update.encode().unwrap();
+        assert!(encoded.len() < serde_spb::to_vec(&headers).unwrap().len());
+        let decoded = LightClientUpdate::decode(&encoded).unwrap();
+        assert_eq!(decoded, update);
+        assert_eq!(decoded.expand(&genesis).unwrap(), headers);
+
+        // Not on top of the trusted header.
+        assert!(LightClientUpdate::build(&genesis, &headers[1..]).is_err());
+        assert!(update.expand(&headers[0].header).is_err());
+        let mut tampered = update.clone();
+        tampered.headers[1].timestamp += 1;
+        assert!(tampered.expand(&genesis).is_err());
+        let mut tampered = update;
+        // Only the validator of the voting power 2 out of 5.
+        tampered.finalization_proof.truncate(1);
+        assert!(tampered.expand(&genesis).is_err());
+    }
+
+    #[test]
+    fn batches() {
+        let (genesis, headers) = headers(5);
+        let updates = build_updates(&genesis, &headers, 2).unwrap();
+        assert_eq!(
+            updates.iter().map(|x| x.headers.len()).collect::<Vec<_>>(),
+            vec![2, 2, 1]
+        );
+        let mut trusted = genesis;
+        let mut expanded = Vec::new();
+        for update in updates {
+            let headers = update.expand(&trusted).unwrap();
+            trusted = headers.last().unwrap().header.clone();
+            expanded.extend(headers);
+        }
+        assert_eq!(expanded, headers);
+        assert!(build_updates(&trusted, &headers, 0).is_err());
+    }
+
+    #[test]
+    fn validator_set_changes() {
+        let keys = (0..4)
+            .map(|i| generate_keypair(format!("validator{i}")).0)
+            .collect::<Vec<_>>();
+        let set = |x: &[(usize, VotingPower)]| {
+            x.iter()
+                .map(|(i, power)| (keys[*i].clone(), *power))
+                .collect::<Vec<_>>()
+        };
+        let cases = vec![
+            (set(&[(0, 1), (1, 1), (2, 1)]), set(&[(0, 1), (2, 1)])),
+            (
+                set(&[(0, 1), (1, 1), (2, 1)]),
+                set(&[(0, 1), (1, 1), (2, 1), (3, 1)]),
+            ),
+            // Reordered.
+            (
+                set(&[(0, 1), (1, 1), (2, 1)]),
+                set(&[(1, 1), (0, 1), (2, 1)]),
+            ),
+            (set(&[(0, 1), (1, 1)]), set(&[(2, 1), (3, 1)])),
+            (set(&[]), set(&[(0, 1)])),
+        ];
+        for (previous, next) in cases {
+            let change = ValidatorSetChange::between(&previous, &next);
+            assert_eq!(change.apply(&previous).unwrap(), next);
+        }
+        assert!(matches!(
+            ValidatorSetChange::between(
+                &set(&[(0, 1), (1, 1), (2, 1)]),
+                &set(&[(1, 1), (0, 1), (2, 1)])
+            ),
+            ValidatorSetChange::Full(_)
+        ));
+        ValidatorSetChange::Diff {
+            removed: vec![keys[3].clone()],
+            updated: Vec::new(),
+            appended: Vec::new(),
+        }
+        .apply(&set(&[(0, 1)]))
+        .unwrap_err();
+    }
+}
@@ -0,0 +1,138 @@
+//! Proving that an execution has been finalized.
+//!
+//! A treasury contract accepts an execution only with a proof that its transaction is
+//! in a finalized block: the header of the block with its finalization proof, and the Merkle proof
+//! of the transaction against `BlockHeader::commit_merkle_root`. An [`ExecutionProof`] bundles them,
+//! and is delivered in the binary encoding of `serde_spb::to_vec`, the same one that the light client
+//! hashes the transaction with.
+//!
+//! The target treasury (see [`Execution::treasury`]) is in the body of the transaction,
+//! so the proof commits to it as well, and a treasury contract must reject the executions naming another one.
+use super::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ExecutionProof {
+    /// The header of the block that the transaction is included in.
+    pub header: BlockHeader,
+    /// The signatures of the validators finalizing the block.
+    pub finalization_proof: FinalizationProof,
+    /// The execution transaction.
+    pub transaction: Transaction,
+    /// The inclusion proof of the transaction against the commit Merkle root of the block.
+    pub merkle_proof: MerkleProof,
+}
+
+impl ExecutionProof {
+    /// Creates the proof of the transaction, which is one of the commits of the finalized block.
+    ///
+    /// - `commits`: The commits of the block in order, excluding the block itself.
+    pub fn create(
+        header: &BlockHeader,
+        finalization_proof: FinalizationProof,
+        commits: &[Commit],
+        transaction: &Transaction,
+    ) -> Result<Self, Error> {
+        let merkle_tree =
+            OneshotMerkleTree::create(commits.iter().map(|x| x.to_hash256()).collect());
+        if merkle_tree.root() != header.commit_merkle_root {
+            return Err(eyre::eyre!(
+                "the commits don't match the commit Merkle root of the block"
+            ));
+        }
+        let merkle_proof = merkle_tree
+            .create_merkle_proof(transaction.to_hash256())
+            .ok_or_else(|| eyre::eyre!("the transaction is not included in the block"))?;
+        let proof = Self {
+            header: header.clone(),
+            finalization_proof,
+            transaction: transaction.clone(),
+            merkle_proof,
+        };
+        proof.verify()?;
+        Ok(proof)
+    }
+
+    /// Verifies the proof as the treasury contract would,
+    /// except checking the header against its light client.
+    pub fn verify(&self) -> Result<(), Error> {
+        verify::verify_finalization_proof(&self.header, &self.finalization_proof)?;
+        self.merkle_proof.verify(
+            self.header.commit_merkle_root,
+            &serde_spb::to_vec(&self.transaction)?,
+        )?;
+        Ok(())
+    }
+
+    /// Encodes the proof to be delivered to the treasury contract.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        Ok(serde_spb::to_vec(self)?)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        Ok(serde_spb::from_slice(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use light_client::LightClient;
+
+    fn transaction(i: u128) -> Transaction {
+        let execution = Execution {
+            version: EXECUTION_VERSION,
+            target_chain: ChainId::Ethereum,
+            treasury: None,
+            contract_sequence: i,
+            valid_until: None,
+            fee: None,
+            message: ExecutionMessage::Dummy {
+                msg: "hello".to_owned(),
+            },
+        };
+        create_execution_transaction(&execution, PublicKey::zero(), 0).unwrap()
+    }
+
+    #[test]
+    fn create_and_verify() {
+        let keys = (0..4)
+            .map(|i| generate_keypair(format!("validator{i}")))
+            .collect::<Vec<_>>();
+        let commits = (0..3)
+            .map(|i| Commit::Transaction(transaction(i)))
+            .collect::<Vec<_>>();
+        let header = BlockHeader {
+            author: keys[0].0.clone(),
+            prev_block_finalization_proof: Vec::new(),
+            previous_hash: Hash256::zero(),
+            height: 1,
+            timestamp: 0,
+            commit_merkle_root: BlockHeader::calculate_commit_merkle_root(&commits),
+            repository_merkle_root: Hash256::zero(),
+            validator_set: keys.iter().map(|(x, _)| (x.clone(), 1)).collect(),
+            version: "0.0.0".to_owned(),
+        };
+        let sign = |n: usize| {
+            keys[0..n]
+                .iter()
+                .map(|(_, private_key)| TypedSignature::sign(&header, private_key).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let proof = ExecutionProof::create(&header, sign(3), &commits, &transaction(1)).unwrap();
+        assert_eq!(
+            ExecutionProof::decode(&proof.encode().unwrap()).unwrap(),
+            proof
+        );
+        let light_client = LightClient::new(header.clone());
+        assert!(light_client.verify_transaction_commitment(
+            &proof.transaction,
+            header.height,
+            proof.merkle_proof.clone()
+        ));
+
+        assert!(ExecutionProof::create(&header, sign(3), &commits, &transaction(3)).is_err());
+        assert!(ExecutionProof::create(&header, sign(2), &commits, &transaction(1)).is_err());
+        assert!(ExecutionProof::create(&header, sign(3), &commits[1..], &transaction(1)).is_err());
+    }
+}
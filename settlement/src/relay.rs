@@ -0,0 +1,333 @@
+//! Relaying the finalized executions to the settlement chains.
+//!
+//! The relaying is written only against [`SettlementChain`], so that the same logic drives
+//! every backend (e.g., [`crate::ethereum`] or [`crate::cosmwasm`]): the light client of the treasury
+//! is advanced header by header up to the block of the execution, and then the execution is
+//! delivered with its proof.
+use super::*;
+use proof::ExecutionProof;
+
+/// A finalized block header with its finalization proof, which a light client is advanced with.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FinalizedHeader {
+    pub header: BlockHeader,
+    pub proof: FinalizationProof,
+}
+
+/// Advances the light client of the treasury through the headers, returning its new height.
+///
+/// The headers must be in order of their heights, and the ones that the light client
+/// has already verified are skipped.
+pub async fn update_light_client(
+    chain: &dyn SettlementChain,
+    headers: &[FinalizedHeader],
+) -> Result<BlockHeight, Error> {
+    let start = chain.get_light_client_height().await?;
+    let mut height = start;
+    for x in headers.iter().filter(|x| x.header.height > start) {
+        if x.header.height != height + 1 {
+            return Err(eyre::eyre!("missing the header at {}", height + 1));
+        }
+        chain
+            .update_treasury_light_client(x.header.clone(), x.proof.clone())
+            .await?;
+        height = x.header.height;
+    }
+    Ok(height)
+}
+
+/// Delivers the execution to the chain, first advancing the light client up to the block of it.
+///
+/// Returns the hash of the delivering transaction, as [`SettlementChain::execute`] does.
+pub async fn relay_execution(
+    chain: &dyn SettlementChain,
+    headers: &[FinalizedHeader],
+    proof: &ExecutionProof,
+) -> Result<String, Error> {
+    let execution =
+        convert_transaction_to_execution(&proof.transaction).map_err(|e| eyre::eyre!(e))?;
+    let chain_name = chain.get_chain_name().await;
+    if execution.target_chain.as_str() != chain_name {
+        return Err(eyre::eyre!(
+            "the execution is to {}, not {chain_name}",
+            execution.target_chain
+        ));
+    }
+    if let (Some(treasury), Some(address)) =
+        (&execution.treasury, chain.get_treasury_address().await)
+    {
+        if !treasury.eq_ignore_ascii_case(&address) {
+            return Err(eyre::eyre!(
+                "the execution is to the treasury {treasury}, not {address}"
+            ));
+        }
+    }
+    let block_height = proof.header.height;
+    let end = headers.partition_point(|x| x.header.height <= block_height);
+    let height = update_light_client(chain, &headers[..end]).await?;
+    if height < block_height {
+        return Err(eyre::eyre!(
+            "the light client is at {height}, behind the execution at {block_height}"
+        ));
+    }
+    chain
+        .execute(
+            proof.transaction.clone(),
+            block_height,
+            proof.merkle_proof.clone(),
+        )
+        .await
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    /// Records the relays, trusting every header and proof.
+    pub(crate) struct MockChain {
+        pub name: String,
+        pub treasury: Option<String>,
+        pub height: Mutex<BlockHeight>,
+        pub executed: Mutex<Vec<(Transaction, BlockHeight)>>,
+        /// The number of the next executions to fail.
+        pub failures: Mutex<usize>,
+        /// The number of the next checks to find the transaction unconfirmed.
+        pub unconfirmed: Mutex<usize>,
+        pub fungible_tokens: Mutex<BTreeMap<String, Decimal>>,
+        pub non_fungible_tokens: Mutex<BTreeMap<String, Vec<String>>>,
+        /// The height of the last block of the chain itself, not of the light client.
+        pub settlement_height: Mutex<u64>,
+        pub events: Mutex<Vec<deposit::TreasuryEvent>>,
+    }
+
+    impl MockChain {
+        pub fn new(name: &str, height: BlockHeight) -> Self {
+            Self {
+                name: name.to_owned(),
+                treasury: None,
+                height: Mutex::new(height),
+                executed: Mutex::new(Vec::new()),
+                failures: Mutex::new(0),
+                unconfirmed: Mutex::new(0),
+                fungible_tokens: Mutex::new(BTreeMap::new()),
+                non_fungible_tokens: Mutex::new(BTreeMap::new()),
+                settlement_height: Mutex::new(0),
+                events: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl SettlementChain for MockChain {
+        async fn get_chain_name(&self) -> String {
+            self.name.clone()
+        }
+
+        async fn get_treasury_address(&self) -> Option<String> {
+            self.treasury.clone()
+        }
+
+        async fn check_connection(&self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        async fn get_last_block(&self) -> Result<SettlementChainBlock, Error> {
+            Ok(SettlementChainBlock {
+                height: *self.settlement_height.lock().unwrap(),
+                timestamp: 0,
+            })
+        }
+
+        async fn get_relayer_account_info(&self) -> Result<(String, Decimal), Error> {
+            unimplemented!()
+        }
+
+        async fn get_light_client_header(&self) -> Result<BlockHeader, Error> {
+            unimplemented!()
+        }
+
+        async fn get_light_client_height(&self) -> Result<BlockHeight, Error> {
+            Ok(*self.height.lock().unwrap())
+        }
+
+        async fn get_treasury_fungible_token_balance(
+            &self,
+            address: String,
+        ) -> Result<Decimal, Error> {
+            self.fungible_tokens
+                .lock()
+                .unwrap()
+                .get(&address)
+                .cloned()
+                .ok_or_else(|| eyre::eyre!("unknown token {address}"))
+        }
+
+        async fn get_treasury_non_fungible_token_balance(
+            &self,
+            address: String,
+        ) -> Result<Vec<String>, Error> {
+            self.non_fungible_tokens
+                .lock()
+                .unwrap()
+                .get(&address)
+                .cloned()
+                .ok_or_else(|| eyre::eyre!("unknown collection {address}"))
+        }
+
+        /// Every token has 2 decimals.
+        async fn get_fungible_token_decimals(&self, _address: String) -> Result<u32, Error> {
+            Ok(2)
+        }
+
+        async fn get_treasury_events(
+            &self,
+            from: u64,
+            to: u64,
+        ) -> Result<Vec<deposit::TreasuryEvent>, Error> {
+            Ok(self
+                .events
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|x| (from..=to).contains(&x.block_height))
+                .cloned()
+                .collect())
+        }
+
+        async fn update_treasury_light_client(
+            &self,
+            header: BlockHeader,
+            _proof: FinalizationProof,
+        ) -> Result<(), Error> {
+            let mut height = self.height.lock().unwrap();
+            assert_eq!(header.height, *height + 1);
+            *height = header.height;
+            Ok(())
+        }
+
+        async fn execute(
+            &self,
+            transaction: Transaction,
+            block_height: u64,
+            _proof: MerkleProof,
+        ) -> Result<String, Error> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(eyre::eyre!("the node is down"));
+            }
+            let mut executed = self.executed.lock().unwrap();
+            executed.push((transaction, block_height));
+            Ok(format!("tx{}", executed.len() - 1))
+        }
+
+        async fn check_transaction(
+            &self,
+            transaction_hash: &str,
+        ) -> Result<TransactionOutcome, Error> {
+            let mut unconfirmed = self.unconfirmed.lock().unwrap();
+            if *unconfirmed > 0 {
+                *unconfirmed -= 1;
+                return Ok(TransactionOutcome::Unconfirmed);
+            }
+            let index: usize = transaction_hash
+                .strip_prefix("tx")
+                .and_then(|x| x.parse().ok())
+                .ok_or_else(|| eyre::eyre!("unknown transaction {transaction_hash}"))?;
+            if index >= self.executed.lock().unwrap().len() {
+                return Err(eyre::eyre!("unknown transaction {transaction_hash}"));
+            }
+            Ok(TransactionOutcome::Succeeded)
+        }
+    }
+
+    fn header(height: BlockHeight) -> FinalizedHeader {
+        FinalizedHeader {
+            header: BlockHeader {
+                author: PublicKey::zero(),
+                prev_block_finalization_proof: Vec::new(),
+                previous_hash: Hash256::zero(),
+                height,
+                timestamp: 0,
+                commit_merkle_root: Hash256::zero(),
+                repository_merkle_root: Hash256::zero(),
+                validator_set: Vec::new(),
+                version: "0.0.0".to_owned(),
+            },
+            proof: Vec::new(),
+        }
+    }
+
+    fn execution_proof(height: BlockHeight, treasury: Option<&str>) -> ExecutionProof {
+        let execution = Execution {
+            version: EXECUTION_VERSION,
+            target_chain: ChainId::Ethereum,
+            treasury: treasury.map(str::to_owned),
+            contract_sequence: 0,
+            valid_until: None,
+            fee: None,
+            message: ExecutionMessage::Dummy {
+                msg: "hello".to_owned(),
+            },
+        };
+        ExecutionProof {
+            header: header(height).header,
+            finalization_proof: Vec::new(),
+            transaction: create_execution_transaction(&execution, PublicKey::zero(), 0).unwrap(),
+            merkle_proof: MerkleProof { proof: Vec::new() },
+        }
+    }
+
+    #[tokio::test]
+    async fn relay() {
+        let chain = MockChain::new("ethereum", 1);
+        let headers = (1..=4).map(header).collect::<Vec<_>>();
+        let hash = relay_execution(&chain, &headers, &execution_proof(3, None))
+            .await
+            .unwrap();
+        assert_eq!(
+            chain.check_transaction(&hash).await.unwrap(),
+            TransactionOutcome::Succeeded
+        );
+        assert_eq!(*chain.height.lock().unwrap(), 3);
+        assert_eq!(chain.executed.lock().unwrap()[0].1, 3);
+
+        // The light client is already past the block.
+        relay_execution(&chain, &[], &execution_proof(2, None))
+            .await
+            .unwrap();
+        assert_eq!(chain.executed.lock().unwrap().len(), 2);
+
+        assert!(
+            relay_execution(&chain, &[header(5)], &execution_proof(6, None))
+                .await
+                .is_err()
+        );
+        let polygon = MockChain::new("polygon", 1);
+        assert!(
+            relay_execution(&polygon, &headers, &execution_proof(1, None))
+                .await
+                .is_err()
+        );
+        assert!(polygon.executed.lock().unwrap().is_empty());
+
+        // Only through the treasury that the execution names.
+        let treasury = MockChain {
+            treasury: Some("Treasury-A".to_owned()),
+            ..MockChain::new("ethereum", 1)
+        };
+        assert!(
+            relay_execution(&treasury, &headers, &execution_proof(1, Some("treasury-b")))
+                .await
+                .is_err()
+        );
+        relay_execution(&treasury, &headers, &execution_proof(1, Some("treasury-a")))
+            .await
+            .unwrap();
+        relay_execution(&treasury, &headers, &execution_proof(1, None))
+            .await
+            .unwrap();
+    }
+}
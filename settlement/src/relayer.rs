@@ -0,0 +1,685 @@
+//! The relayer, which delivers the finalized executions to their settlement chains.
+//!
+//! A [`Relayer`] scans the newly finalized blocks for the execution transactions,
+//! creates their proofs, and queues them per treasury (see [`TreasuryId`]). The queue of each treasury
+//! is delivered in order (as the treasury accepts the contract sequences only in order) through the backend
+//! configured for the treasury, retrying the head with a backoff until it succeeds. A delivered
+//! execution stays at the head until its transaction is confirmed, and the outcome of every execution
+//! is recorded in a [`DeliveryStore`].
+//!
+//! The expired executions (see [`Execution::valid_until`]) and the cancelled ones
+//! (see [`ExecutionMessage::CancelExecution`]) are skipped unless already submitted.
+//! As the treasury accepts the sequences only in increasing order, a skipped execution can't
+//! be delivered once a later one is.
+//!
+//! With `RelayerConfig::queue_path`, the scanned height, the queues and the delivery statuses
+//! are saved to the file after every step, and loaded back on the start.
+use super::*;
+use delivery::{DeliveryStatus, DeliveryStore};
+use proof::ExecutionProof;
+use relay::FinalizedHeader;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Where the relayer reads the finalized blocks from (e.g., the repository of a node).
+#[async_trait::async_trait]
+pub trait FinalizedBlockSource: Send + Sync {
+    async fn get_last_finalized_height(&self) -> Result<BlockHeight, Error>;
+
+    /// Returns the finalized block at the height with its finalization proof,
+    /// and the commits of it in order, excluding the block itself.
+    async fn get_finalized_block(
+        &self,
+        height: BlockHeight,
+    ) -> Result<(FinalizedHeader, Vec<Commit>), Error>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RelayerConfig {
+    /// How often to scan for the newly finalized blocks, in milliseconds.
+    pub poll_interval_ms: u64,
+    /// How long to wait before retrying a failed delivery for the first time, in milliseconds.
+    ///
+    /// It doubles on every failure, up to `max_retry_interval_ms`.
+    pub retry_interval_ms: u64,
+    pub max_retry_interval_ms: u64,
+    /// The file that the state of the relayer is saved to, if it's kept across the restarts.
+    pub queue_path: Option<String>,
+    /// The height to scan from when there is no saved state (e.g., the height of the deployment).
+    pub start_height: BlockHeight,
+}
+
+impl Default for RelayerConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_ms: 10 * 1000,
+            retry_interval_ms: 10 * 1000,
+            max_retry_interval_ms: 10 * 60 * 1000,
+            queue_path: None,
+            start_height: 1,
+        }
+    }
+}
+
+/// An execution waiting to be delivered.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PendingExecution {
+    pub execution: Execution,
+    pub proof: ExecutionProof,
+    /// The number of the failed deliveries.
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    /// When the delivery can be attempted again, in milliseconds.
+    pub next_attempt: Timestamp,
+    /// The hash of the delivering transaction, while it's not confirmed.
+    pub submitted: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RelayerStatus {
+    /// The last finalized height scanned for the executions.
+    pub scanned_height: BlockHeight,
+    /// The executions waiting to be delivered, per treasury in order.
+    pub pending: BTreeMap<TreasuryId, VecDeque<PendingExecution>>,
+    /// The number of the executions delivered so far.
+    pub delivered: u64,
+}
+
+/// An execution whose delivering transaction has been confirmed, notified to the subscribers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveredExecution {
+    pub execution: Execution,
+    /// The hash of the execution transaction in the Simperby chain.
+    pub transaction_hash: Hash256,
+    /// The hash of the delivering transaction in the settlement chain.
+    pub chain_transaction: String,
+}
+
+/// The number of the deliveries kept for a subscriber that falls behind.
+const DELIVERY_CHANNEL_CAPACITY: usize = 1024;
+
+/// What is saved to `RelayerConfig::queue_path`.
+#[derive(Serialize, Deserialize)]
+struct SavedState {
+    status: RelayerStatus,
+    deliveries: DeliveryStore,
+}
+
+pub struct Relayer {
+    config: RelayerConfig,
+    source: Arc<dyn FinalizedBlockSource>,
+    chains: HashMap<TreasuryId, Arc<dyn SettlementChain>>,
+    /// Never held across an `.await`, so that the status can be queried during a delivery.
+    status: Mutex<RelayerStatus>,
+    /// Locked after `status` if both are needed.
+    deliveries: Mutex<DeliveryStore>,
+    delivered: tokio::sync::broadcast::Sender<DeliveredExecution>,
+}
+
+impl Relayer {
+    /// Creates a relayer with the queues saved in `RelayerConfig::queue_path`, if any.
+    pub async fn load(
+        config: RelayerConfig,
+        source: Arc<dyn FinalizedBlockSource>,
+    ) -> Result<Self, Error> {
+        let mut state = SavedState {
+            status: RelayerStatus {
+                scanned_height: config.start_height.saturating_sub(1),
+                ..Default::default()
+            },
+            deliveries: DeliveryStore::default(),
+        };
+        if let Some(path) = &config.queue_path {
+            if tokio::fs::metadata(path).await.is_ok() {
+                state = serde_spb::from_str(&tokio::fs::read_to_string(path).await?)?;
+            }
+        }
+        Ok(Self {
+            config,
+            source,
+            chains: HashMap::new(),
+            status: Mutex::new(state.status),
+            deliveries: Mutex::new(state.deliveries),
+            delivered: tokio::sync::broadcast::channel(DELIVERY_CHANNEL_CAPACITY).0,
+        })
+    }
+
+    /// Sets the backend that the executions to the default treasury of the chain are delivered through.
+    ///
+    /// The executions to a treasury without a backend are kept queued.
+    pub fn set_chain(&mut self, target_chain: ChainId, chain: Arc<dyn SettlementChain>) {
+        self.set_treasury(target_chain.into(), chain);
+    }
+
+    /// Sets the backend that the executions to the treasury are delivered through,
+    /// which must be configured with the address of the treasury.
+    pub fn set_treasury(&mut self, treasury: TreasuryId, chain: Arc<dyn SettlementChain>) {
+        self.chains.insert(treasury, chain);
+    }
+
+    /// Subscribes to the executions confirmed after this call.
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<DeliveredExecution> {
+        self.delivered.subscribe()
+    }
+
+    pub fn status(&self) -> RelayerStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Returns the delivery status of the execution, if the relayer has found it.
+    pub fn delivery_status(
+        &self,
+        treasury: &TreasuryId,
+        contract_sequence: u128,
+    ) -> Option<DeliveryStatus> {
+        self.deliveries
+            .lock()
+            .unwrap()
+            .get(treasury, contract_sequence)
+            .cloned()
+    }
+
+    /// Returns the delivery statuses of the executions to the treasury, in order of their contract sequences.
+    pub fn delivery_statuses(&self, treasury: &TreasuryId) -> Vec<(u128, DeliveryStatus)> {
+        self.deliveries.lock().unwrap().list(treasury)
+    }
+
+    /// Scans, delivers and saves repeatedly, logging the failures, until the task is dropped.
+    pub async fn run(&self) {
+        loop {
+            if let Err(e) = self.step(now()).await {
+                log::warn!("failed to relay: {e}");
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(
+                self.config.poll_interval_ms,
+            ))
+            .await;
+        }
+    }
+
+    /// Scans the newly finalized blocks, delivers the executions due at `now`, and saves the queues.
+    pub async fn step(&self, now: Timestamp) -> Result<(), Error> {
+        self.scan(now).await?;
+        let chains = self
+            .chains
+            .iter()
+            .map(|(id, chain)| (id.clone(), Arc::clone(chain)))
+            .collect::<Vec<_>>();
+        for (treasury, chain) in chains {
+            self.deliver(&treasury, chain.as_ref(), now).await?;
+        }
+        self.save().await
+    }
+
+    async fn scan(&self, now: Timestamp) -> Result<(), Error> {
+        let last_height = self.source.get_last_finalized_height().await?;
+        let scanned_height = self.status.lock().unwrap().scanned_height;
+        for height in scanned_height + 1..=last_height {
+            let (block, commits) = self.source.get_finalized_block(height).await?;
+            let mut found = Vec::new();
+            for commit in &commits {
+                let transaction = match commit {
+                    Commit::Transaction(x) => x,
+                    _ => continue,
+                };
+                let execution = match convert_transaction_to_execution(transaction) {
+                    Ok(x) => x,
+                    Err(_) => continue,
+                };
+                let proof = ExecutionProof::create(
+                    &block.header,
+                    block.proof.clone(),
+                    &commits,
+                    transaction,
+                )?;
+                found.push(PendingExecution {
+                    execution,
+                    proof,
+                    attempts: 0,
+                    last_error: None,
+                    next_attempt: now,
+                    submitted: None,
+                });
+            }
+            let mut status = self.status.lock().unwrap();
+            let mut deliveries = self.deliveries.lock().unwrap();
+            for pending in found {
+                let treasury = pending.execution.treasury_id();
+                if let ExecutionMessage::CancelExecution(x) = &pending.execution.message {
+                    let reason = format!(
+                        "cancelled by the execution #{}",
+                        pending.execution.contract_sequence
+                    );
+                    let queue = status.pending.entry(treasury.clone()).or_default();
+                    match queue.iter().position(|p| {
+                        p.execution.contract_sequence == x.contract_sequence
+                            && p.submitted.is_none()
+                    }) {
+                        Some(index) => {
+                            queue.remove(index);
+                            log::info!(
+                                "skipping the execution #{} to {treasury}: {reason}",
+                                x.contract_sequence
+                            );
+                            deliveries.set(
+                                &treasury,
+                                x.contract_sequence,
+                                DeliveryStatus::Skipped(reason),
+                            );
+                        }
+                        None => log::warn!(
+                            "the execution #{} to {treasury} is not pending to be cancelled",
+                            x.contract_sequence
+                        ),
+                    }
+                }
+                deliveries.set(
+                    &treasury,
+                    pending.execution.contract_sequence,
+                    DeliveryStatus::Pending,
+                );
+                status
+                    .pending
+                    .entry(treasury)
+                    .or_default()
+                    .push_back(pending);
+            }
+            status.scanned_height = height;
+        }
+        Ok(())
+    }
+
+    /// Delivers the queue of the treasury in order, stopping at the first failure, the one not due yet,
+    /// or the one not confirmed yet.
+    async fn deliver(
+        &self,
+        treasury: &TreasuryId,
+        chain: &dyn SettlementChain,
+        now: Timestamp,
+    ) -> Result<(), Error> {
+        loop {
+            let head = {
+                let status = self.status.lock().unwrap();
+                match status.pending.get(treasury).and_then(|x| x.front()) {
+                    Some(x) if x.next_attempt <= now => x.clone(),
+                    _ => return Ok(()),
+                }
+            };
+            let sequence = head.execution.contract_sequence;
+            if let (None, Some(valid_until)) = (&head.submitted, head.execution.valid_until) {
+                if now > valid_until {
+                    let reason = format!("expired at {valid_until}");
+                    log::info!("skipping the execution #{sequence} to {treasury}: {reason}");
+                    let mut status = self.status.lock().unwrap();
+                    let mut deliveries = self.deliveries.lock().unwrap();
+                    pop_head(&mut status, treasury);
+                    deliveries.set(treasury, sequence, DeliveryStatus::Skipped(reason));
+                    continue;
+                }
+            }
+            let result = match &head.submitted {
+                Some(hash) => match chain.check_transaction(hash).await {
+                    Ok(outcome) => Ok((hash.clone(), outcome)),
+                    Err(e) => {
+                        // Checked again on the next step.
+                        log::warn!("failed to check the transaction {hash}: {e}");
+                        return Ok(());
+                    }
+                },
+                None => match self.collect_headers(chain, head.proof.header.height).await {
+                    Ok(headers) => relay::relay_execution(chain, &headers, &head.proof)
+                        .await
+                        .map(|hash| (hash, TransactionOutcome::Unconfirmed)),
+                    Err(e) => Err(e),
+                },
+            };
+            let mut status = self.status.lock().unwrap();
+            let mut deliveries = self.deliveries.lock().unwrap();
+            let queue = status.pending.entry(treasury.clone()).or_default();
+            let front = queue.front_mut().expect("only this task pops the queue");
+            let error = match result {
+                Ok((hash, TransactionOutcome::Succeeded)) => {
+                    pop_head(&mut status, treasury);
+                    status.delivered += 1;
+                    deliveries.set(treasury, sequence, DeliveryStatus::Confirmed(hash.clone()));
+                    // Fails only if there is no subscriber.
+                    let _ = self.delivered.send(DeliveredExecution {
+                        execution: head.execution.clone(),
+                        transaction_hash: head.proof.transaction.to_hash256(),
+                        chain_transaction: hash,
+                    });
+                    continue;
+                }
+                Ok((hash, TransactionOutcome::Unconfirmed)) => {
+                    front.submitted = Some(hash.clone());
+                    deliveries.set(treasury, sequence, DeliveryStatus::Submitted(hash));
+                    // Checked right away if just submitted, or on the next step otherwise.
+                    if head.submitted.is_none() {
+                        continue;
+                    }
+                    return Ok(());
+                }
+                Ok((hash, TransactionOutcome::Failed(reason))) => {
+                    format!("the transaction {hash} has failed: {reason}")
+                }
+                Err(e) => e.to_string(),
+            };
+            log::warn!("failed to deliver the execution #{sequence} to {treasury}: {error}");
+            let backoff = self
+                .config
+                .retry_interval_ms
+                .saturating_mul(1 << front.attempts.min(32))
+                .min(self.config.max_retry_interval_ms);
+            front.attempts += 1;
+            front.last_error = Some(error.clone());
+            front.next_attempt = now + backoff as Timestamp;
+            front.submitted = None;
+            deliveries.set(treasury, sequence, DeliveryStatus::Failed(error));
+            return Ok(());
+        }
+    }
+
+    /// Reads the headers that the light client of the chain needs to reach the height.
+    async fn collect_headers(
+        &self,
+        chain: &dyn SettlementChain,
+        height: BlockHeight,
+    ) -> Result<Vec<FinalizedHeader>, Error> {
+        let light_client_height = chain.get_light_client_height().await?;
+        let mut headers = Vec::new();
+        for x in light_client_height + 1..=height {
+            headers.push(self.source.get_finalized_block(x).await?.0);
+        }
+        Ok(headers)
+    }
+
+    async fn save(&self) -> Result<(), Error> {
+        let path = match &self.config.queue_path {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let state = SavedState {
+            status: self.status(),
+            deliveries: self.deliveries.lock().unwrap().clone(),
+        };
+        // Write to a temporary file first so that a crash never leaves a truncated file.
+        let temp_path = format!("{path}.tmp");
+        tokio::fs::write(&temp_path, serde_spb::to_string(&state)?).await?;
+        tokio::fs::rename(&temp_path, path).await?;
+        Ok(())
+    }
+}
+
+/// Removes the head of the queue of the treasury, and the queue if it gets empty.
+fn pop_head(status: &mut RelayerStatus, treasury: &TreasuryId) {
+    if let Some(queue) = status.pending.get_mut(treasury) {
+        queue.pop_front();
+        if queue.is_empty() {
+            status.pending.remove(treasury);
+        }
+    }
+}
+
+fn now() -> Timestamp {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as Timestamp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use relay::tests::MockChain;
+
+    /// Finalized blocks, each signed by the validators.
+    struct MockSource {
+        blocks: Vec<(FinalizedHeader, Vec<Commit>)>,
+    }
+
+    #[async_trait::async_trait]
+    impl FinalizedBlockSource for MockSource {
+        async fn get_last_finalized_height(&self) -> Result<BlockHeight, Error> {
+            Ok(self.blocks.len() as BlockHeight)
+        }
+
+        async fn get_finalized_block(
+            &self,
+            height: BlockHeight,
+        ) -> Result<(FinalizedHeader, Vec<Commit>), Error> {
+            Ok(self.blocks[height as usize - 1].clone())
+        }
+    }
+
+    fn execution_transaction(treasury: &TreasuryId, sequence: u128) -> Commit {
+        let execution = Execution {
+            version: EXECUTION_VERSION,
+            target_chain: treasury.target_chain.clone(),
+            treasury: treasury.treasury.clone(),
+            contract_sequence: sequence,
+            valid_until: None,
+            fee: None,
+            message: ExecutionMessage::Dummy {
+                msg: "hello".to_owned(),
+            },
+        };
+        Commit::Transaction(create_execution_transaction(&execution, PublicKey::zero(), 0).unwrap())
+    }
+
+    fn block(height: BlockHeight, commits: Vec<Commit>) -> (FinalizedHeader, Vec<Commit>) {
+        let keys = (0..4)
+            .map(|i| generate_keypair(format!("validator{i}")))
+            .collect::<Vec<_>>();
+        let header = BlockHeader {
+            author: keys[0].0.clone(),
+            prev_block_finalization_proof: Vec::new(),
+            previous_hash: Hash256::zero(),
+            height,
+            timestamp: 0,
+            commit_merkle_root: BlockHeader::calculate_commit_merkle_root(&commits),
+            repository_merkle_root: Hash256::zero(),
+            validator_set: keys.iter().map(|(x, _)| (x.clone(), 1)).collect(),
+            version: "0.0.0".to_owned(),
+        };
+        let proof = keys
+            .iter()
+            .map(|(_, private_key)| TypedSignature::sign(&header, private_key).unwrap())
+            .collect();
+        (FinalizedHeader { header, proof }, commits)
+    }
+
+    #[tokio::test]
+    async fn relayer() {
+        let path = std::env::temp_dir().join(format!("simperby-relayer-{}", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let _ = tokio::fs::remove_file(&path).await;
+        let not_execution = Commit::Transaction(Transaction {
+            author: PublicKey::zero(),
+            timestamp: 0,
+            head: "hello".to_owned(),
+            body: String::new(),
+            diff: Diff::None,
+        });
+        let ethereum_treasury = TreasuryId::from(ChainId::Ethereum);
+        let polygon_treasury = TreasuryId::from(ChainId::Polygon);
+        let other_treasury = TreasuryId::new(ChainId::Ethereum, Some("treasury-2".to_owned()));
+        let source = Arc::new(MockSource {
+            blocks: vec![
+                block(1, vec![not_execution]),
+                block(
+                    2,
+                    vec![
+                        execution_transaction(&ethereum_treasury, 0),
+                        execution_transaction(&polygon_treasury, 0),
+                        execution_transaction(&other_treasury, 0),
+                        execution_transaction(&ethereum_treasury, 1),
+                    ],
+                ),
+            ],
+        });
+        let config = RelayerConfig {
+            retry_interval_ms: 100,
+            queue_path: Some(path.clone()),
+            ..Default::default()
+        };
+        let ethereum = Arc::new(MockChain::new("ethereum", 0));
+        *ethereum.failures.lock().unwrap() = 1;
+        let mut relayer = Relayer::load(config.clone(), Arc::clone(&source) as _)
+            .await
+            .unwrap();
+        relayer.set_chain(ChainId::Ethereum, Arc::clone(&ethereum) as _);
+        let other = Arc::new(MockChain::new("ethereum", 0));
+        relayer.set_treasury(other_treasury.clone(), Arc::clone(&other) as _);
+        let mut delivered = relayer.subscribe();
+
+        relayer.step(0).await.unwrap();
+        let status = relayer.status();
+        assert_eq!(status.scanned_height, 2);
+        assert_eq!(status.pending[&ethereum_treasury].len(), 2);
+        assert_eq!(status.pending[&ethereum_treasury][0].attempts, 1);
+        assert_eq!(status.pending[&ethereum_treasury][0].next_attempt, 100);
+        // The other treasury on the same chain isn't held up by the failure.
+        assert_eq!(other.executed.lock().unwrap().len(), 1);
+        assert_eq!(
+            relayer.delivery_statuses(&other_treasury),
+            vec![(0, DeliveryStatus::Confirmed("tx0".to_owned()))]
+        );
+        let delivery = delivered.try_recv().unwrap();
+        assert_eq!(delivery.execution.treasury_id(), other_treasury);
+        assert_eq!(delivery.chain_transaction, "tx0");
+        assert!(delivered.try_recv().is_err());
+        assert_eq!(
+            relayer.delivery_statuses(&ethereum_treasury),
+            vec![
+                (0, DeliveryStatus::Failed("the node is down".to_owned())),
+                (1, DeliveryStatus::Pending)
+            ]
+        );
+
+        // Not due yet.
+        relayer.step(50).await.unwrap();
+        assert!(ethereum.executed.lock().unwrap().is_empty());
+        // Submitted, but not confirmed yet.
+        *ethereum.unconfirmed.lock().unwrap() = 1;
+        relayer.step(100).await.unwrap();
+        assert_eq!(ethereum.executed.lock().unwrap().len(), 1);
+        assert_eq!(
+            relayer.delivery_status(&ethereum_treasury, 0),
+            Some(DeliveryStatus::Submitted("tx0".to_owned()))
+        );
+        relayer.step(100).await.unwrap();
+        let executed = ethereum.executed.lock().unwrap().clone();
+        assert_eq!(executed.len(), 2);
+        assert_eq!(
+            convert_transaction_to_execution(&executed[1].0)
+                .unwrap()
+                .contract_sequence,
+            1
+        );
+        assert_eq!(*ethereum.height.lock().unwrap(), 2);
+        assert_eq!(
+            relayer.delivery_statuses(&ethereum_treasury),
+            vec![
+                (0, DeliveryStatus::Confirmed("tx0".to_owned())),
+                (1, DeliveryStatus::Confirmed("tx1".to_owned()))
+            ]
+        );
+
+        // The execution to Polygon, without a backend, stays across the restart.
+        let status = relayer.status();
+        assert_eq!(status.delivered, 3);
+        assert_eq!(
+            status.pending.keys().collect::<Vec<_>>(),
+            vec![&polygon_treasury]
+        );
+        let restarted = Relayer::load(config, source).await.unwrap();
+        assert_eq!(restarted.status(), status);
+        assert_eq!(
+            restarted.delivery_status(&ethereum_treasury, 1),
+            Some(DeliveryStatus::Confirmed("tx1".to_owned()))
+        );
+        assert_eq!(
+            restarted.delivery_status(&polygon_treasury, 0),
+            Some(DeliveryStatus::Pending)
+        );
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn skipping() {
+        let treasury = TreasuryId::from(ChainId::Ethereum);
+        let commit = |contract_sequence, valid_until, message| {
+            let execution = Execution {
+                version: EXECUTION_VERSION,
+                target_chain: ChainId::Ethereum,
+                treasury: None,
+                contract_sequence,
+                valid_until,
+                fee: None,
+                message,
+            };
+            Commit::Transaction(
+                create_execution_transaction(&execution, PublicKey::zero(), 0).unwrap(),
+            )
+        };
+        let dummy = || ExecutionMessage::Dummy {
+            msg: "hello".to_owned(),
+        };
+        let source = Arc::new(MockSource {
+            blocks: vec![
+                block(
+                    1,
+                    vec![
+                        commit(0, Some(100), dummy()),
+                        commit(1, None, dummy()),
+                        commit(2, Some(1000), dummy()),
+                    ],
+                ),
+                block(
+                    2,
+                    vec![commit(
+                        3,
+                        None,
+                        ExecutionMessage::CancelExecution(CancelExecution {
+                            contract_sequence: 1,
+                        }),
+                    )],
+                ),
+            ],
+        });
+        let ethereum = Arc::new(MockChain::new("ethereum", 0));
+        let mut relayer = Relayer::load(RelayerConfig::default(), source)
+            .await
+            .unwrap();
+        relayer.set_chain(ChainId::Ethereum, Arc::clone(&ethereum) as _);
+
+        relayer.step(200).await.unwrap();
+        let executed = ethereum
+            .executed
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(x, _)| {
+                convert_transaction_to_execution(x)
+                    .unwrap()
+                    .contract_sequence
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(executed, vec![2, 3]);
+        assert_eq!(
+            relayer.delivery_statuses(&treasury),
+            vec![
+                (0, DeliveryStatus::Skipped("expired at 100".to_owned())),
+                (
+                    1,
+                    DeliveryStatus::Skipped("cancelled by the execution #3".to_owned())
+                ),
+                (2, DeliveryStatus::Confirmed("tx0".to_owned())),
+                (3, DeliveryStatus::Confirmed("tx1".to_owned())),
+            ]
+        );
+        let status = relayer.status();
+        assert_eq!(status.delivered, 2);
+        assert!(status.pending.is_empty());
+    }
+}
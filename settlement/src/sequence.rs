@@ -0,0 +1,282 @@
+//! Bookkeeping of the contract sequences.
+//!
+//! Every execution delivered to a treasury contract carries a sequence, which the contract
+//! accepts only in order. A [`SequenceTracker`] keeps the next sequence of each treasury contract
+//! (a chain may host a new one after a redeployment), both the one expected by the finalized
+//! executions and the one to allocate for a new execution, so that the executions created
+//! before the earlier ones are finalized don't take the same sequence.
+//!
+//! An execution naming its treasury (see [`Execution::treasury`]) is tracked under that contract,
+//! and the others under the one currently set for their chain
+//! (or the default treasury of the chain, with an empty contract, if none is set).
+//!
+//! With a path, the sequences are saved to the file and loaded back on the start.
+use super::*;
+use std::collections::BTreeMap;
+
+/// A treasury contract on a settlement chain.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SequenceKey {
+    pub target_chain: ChainId,
+    /// The address of the treasury contract, or empty for the default one of the chain
+    /// while no contract is set for it.
+    pub contract: String,
+}
+
+/// An irregularity in the sequences of the finalized executions.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum SequenceAnomaly {
+    /// The sequences from `expected` to `found` (exclusive) were skipped.
+    Gap {
+        key: SequenceKey,
+        expected: u128,
+        found: u128,
+    },
+    /// The sequence was already taken by an earlier execution.
+    Duplicate { key: SequenceKey, sequence: u128 },
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+struct Sequences {
+    /// The sequence that the next finalized execution is expected to have.
+    finalized: u128,
+    /// The sequence to allocate next.
+    allocated: u128,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct TrackerState {
+    /// The treasury contract currently in use for each chain.
+    contracts: BTreeMap<ChainId, String>,
+    sequences: Vec<(SequenceKey, Sequences)>,
+}
+
+/// Tracks the next contract sequence per treasury contract.
+#[derive(Debug)]
+pub struct SequenceTracker {
+    path: Option<String>,
+    contracts: BTreeMap<ChainId, String>,
+    sequences: BTreeMap<SequenceKey, Sequences>,
+}
+
+impl SequenceTracker {
+    /// Creates an empty tracker, which is saved to the path if given.
+    pub fn new(path: Option<String>) -> Self {
+        Self {
+            path,
+            contracts: BTreeMap::new(),
+            sequences: BTreeMap::new(),
+        }
+    }
+
+    /// Creates a tracker with the sequences saved in the path, if any.
+    pub async fn load(path: Option<String>) -> Result<Self, Error> {
+        let mut tracker = Self::new(path.clone());
+        let path = match path {
+            Some(x) => x,
+            None => return Ok(tracker),
+        };
+        if tokio::fs::metadata(&path).await.is_err() {
+            return Ok(tracker);
+        }
+        let state: TrackerState = serde_spb::from_str(&tokio::fs::read_to_string(&path).await?)?;
+        tracker.contracts = state.contracts;
+        tracker.sequences = state.sequences.into_iter().collect();
+        Ok(tracker)
+    }
+
+    /// Saves the sequences to the path, if any.
+    pub async fn save(&self) -> Result<(), Error> {
+        let path = match &self.path {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let state = TrackerState {
+            contracts: self.contracts.clone(),
+            sequences: self
+                .sequences
+                .iter()
+                .map(|(key, sequences)| (key.clone(), sequences.clone()))
+                .collect(),
+        };
+        // Write to a temporary file first so that a crash never leaves a truncated file.
+        let temp_path = format!("{path}.tmp");
+        tokio::fs::write(&temp_path, serde_spb::to_string(&state)?).await?;
+        tokio::fs::rename(&temp_path, path).await?;
+        Ok(())
+    }
+
+    /// Sets the treasury contract that the executions to the chain are delivered to from now on.
+    ///
+    /// A contract not seen before starts from the sequence `0`.
+    pub fn set_contract(&mut self, target_chain: ChainId, contract: String) {
+        self.contracts.insert(target_chain, contract);
+    }
+
+    /// Returns the key of the treasury contract currently in use for the chain, if any.
+    pub fn current_key(&self, target_chain: &ChainId) -> Option<SequenceKey> {
+        self.contracts
+            .get(target_chain)
+            .map(|contract| SequenceKey {
+                target_chain: target_chain.clone(),
+                contract: contract.clone(),
+            })
+    }
+
+    /// Returns the key of the treasury contract, resolving the default one of the chain to the one currently in use.
+    pub fn key_of(&self, treasury: &TreasuryId) -> SequenceKey {
+        match &treasury.treasury {
+            Some(contract) => SequenceKey {
+                target_chain: treasury.target_chain.clone(),
+                contract: contract.clone(),
+            },
+            None => self
+                .current_key(&treasury.target_chain)
+                .unwrap_or_else(|| SequenceKey {
+                    target_chain: treasury.target_chain.clone(),
+                    contract: String::new(),
+                }),
+        }
+    }
+
+    /// Returns the sequence that the next finalized execution to the contract is expected to have.
+    pub fn next_finalized_sequence(&self, key: &SequenceKey) -> u128 {
+        self.sequences.get(key).map_or(0, |x| x.finalized)
+    }
+
+    /// Allocates the sequence for a new execution to the treasury.
+    ///
+    /// An allocated sequence that never gets finalized leaves a gap,
+    /// which [`SequenceTracker::scan`] reports once a later one is finalized.
+    pub fn allocate_sequence(&mut self, treasury: &TreasuryId) -> u128 {
+        let key = self.key_of(treasury);
+        let sequences = self.sequences.entry(key).or_default();
+        let sequence = sequences.allocated.max(sequences.finalized);
+        sequences.allocated = sequence + 1;
+        sequence
+    }
+
+    /// Reads the finalized transactions in order, advancing the finalized sequences.
+    pub fn scan(&mut self, transactions: &[Transaction]) -> Vec<SequenceAnomaly> {
+        let mut anomalies = Vec::new();
+        for execution in transactions
+            .iter()
+            .filter_map(|t| convert_transaction_to_execution(t).ok())
+        {
+            let key = self.key_of(&execution.treasury_id());
+            let sequences = self.sequences.entry(key.clone()).or_default();
+            let sequence = execution.contract_sequence;
+            if sequence < sequences.finalized {
+                anomalies.push(SequenceAnomaly::Duplicate { key, sequence });
+                continue;
+            }
+            if sequence > sequences.finalized {
+                anomalies.push(SequenceAnomaly::Gap {
+                    key,
+                    expected: sequences.finalized,
+                    found: sequence,
+                });
+            }
+            sequences.finalized = sequence + 1;
+        }
+        anomalies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn execution_transaction(treasury: &TreasuryId, sequence: u128) -> Transaction {
+        let execution = Execution {
+            version: EXECUTION_VERSION,
+            target_chain: treasury.target_chain.clone(),
+            treasury: treasury.treasury.clone(),
+            contract_sequence: sequence,
+            valid_until: None,
+            fee: None,
+            message: ExecutionMessage::Dummy {
+                msg: "hello".to_owned(),
+            },
+        };
+        create_execution_transaction(&execution, PublicKey::zero(), 0).unwrap()
+    }
+
+    #[test]
+    fn allocation_and_scan() {
+        let chain = ChainId::Ethereum;
+        let treasury = TreasuryId::from(chain.clone());
+        let mut tracker = SequenceTracker::new(None);
+        // The default treasury is tracked on its own until a contract is set.
+        assert_eq!(tracker.allocate_sequence(&treasury), 0);
+        tracker.set_contract(chain.clone(), "treasury-1".to_owned());
+        let key = tracker.current_key(&chain).unwrap();
+
+        assert_eq!(tracker.allocate_sequence(&treasury), 0);
+        assert_eq!(tracker.allocate_sequence(&treasury), 1);
+        let transactions = [0, 1, 1, 3]
+            .iter()
+            .map(|x| execution_transaction(&treasury, *x))
+            .chain(std::iter::once(execution_transaction(
+                &ChainId::Polygon.into(),
+                5,
+            )))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tracker.scan(&transactions),
+            vec![
+                SequenceAnomaly::Duplicate {
+                    key: key.clone(),
+                    sequence: 1
+                },
+                SequenceAnomaly::Gap {
+                    key: key.clone(),
+                    expected: 2,
+                    found: 3
+                },
+                SequenceAnomaly::Gap {
+                    key: tracker.key_of(&ChainId::Polygon.into()),
+                    expected: 0,
+                    found: 5
+                },
+            ]
+        );
+        assert_eq!(tracker.next_finalized_sequence(&key), 4);
+        assert_eq!(tracker.allocate_sequence(&treasury), 4);
+
+        // A redeployed treasury starts over.
+        tracker.set_contract(chain.clone(), "treasury-2".to_owned());
+        assert_eq!(tracker.allocate_sequence(&treasury), 0);
+
+        // Another treasury on the same chain, named by the executions.
+        let other = TreasuryId::new(chain.clone(), Some("treasury-1".to_owned()));
+        assert_eq!(tracker.key_of(&other), key);
+        assert_eq!(tracker.allocate_sequence(&other), 5);
+        let third = TreasuryId::new(chain, Some("treasury-3".to_owned()));
+        assert_eq!(
+            tracker.scan(&[
+                execution_transaction(&third, 0),
+                execution_transaction(&treasury, 0)
+            ]),
+            vec![]
+        );
+        assert_eq!(tracker.next_finalized_sequence(&tracker.key_of(&third)), 1);
+        assert_eq!(tracker.next_finalized_sequence(&key), 4);
+    }
+
+    #[tokio::test]
+    async fn persistence() {
+        let path = std::env::temp_dir().join(format!("simperby-sequences-{}", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let _ = tokio::fs::remove_file(&path).await;
+
+        let mut tracker = SequenceTracker::load(Some(path.clone())).await.unwrap();
+        tracker.set_contract(ChainId::Ethereum, "treasury".to_owned());
+        tracker.allocate_sequence(&ChainId::Ethereum.into());
+        tracker.save().await.unwrap();
+
+        let mut restarted = SequenceTracker::load(Some(path.clone())).await.unwrap();
+        assert_eq!(restarted.allocate_sequence(&ChainId::Ethereum.into()), 1);
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
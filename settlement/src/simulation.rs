@@ -0,0 +1,304 @@
+//! Predicting whether an execution would succeed, before it's put on the agenda.
+//!
+//! [`simulate_execution`] checks an execution against the current state of its target chain:
+//! whether it's well-formed, whether its addresses are valid on the chain, whether the treasury
+//! holds what it transfers, and whether the relayer can pay for the delivery. Nothing is submitted.
+use super::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum CheckOutcome {
+    Passed,
+    /// Predicted to fail, with the reason.
+    Failed(String),
+    /// Couldn't be checked on the chain, with the reason.
+    Skipped(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SimulationCheck {
+    /// What has been checked (e.g., `balance of 0x1234..`).
+    pub subject: String,
+    pub outcome: CheckOutcome,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SimulationReport {
+    pub checks: Vec<SimulationCheck>,
+    /// The estimated fee of the delivery in the native token, or why it couldn't be estimated.
+    ///
+    /// An error here is either a predicted failure or an unsupported estimation,
+    /// so it doesn't fail the simulation by itself.
+    pub estimated_fee: Result<Decimal, String>,
+}
+
+impl SimulationReport {
+    /// Whether the execution is predicted to succeed, i.e., no check has failed.
+    pub fn succeeds(&self) -> bool {
+        !self
+            .checks
+            .iter()
+            .any(|x| matches!(x.outcome, CheckOutcome::Failed(_)))
+    }
+
+    fn push(&mut self, subject: impl Into<String>, outcome: CheckOutcome) {
+        self.checks.push(SimulationCheck {
+            subject: subject.into(),
+            outcome,
+        });
+    }
+}
+
+/// What the messages of an execution take from the treasury, summed up over a batch.
+#[derive(Default)]
+struct Requirements {
+    /// The addresses to check the format of.
+    addresses: BTreeSet<String>,
+    /// The total amounts of the fungible tokens, in their smallest units.
+    fungible_tokens: BTreeMap<String, u128>,
+    /// The token indices of the non-fungible tokens, by their collections.
+    non_fungible_tokens: BTreeSet<(String, String)>,
+    /// What can't be checked through `SettlementChain`, with the reasons.
+    unchecked: Vec<(String, String)>,
+}
+
+impl Requirements {
+    fn add(&mut self, message: &ExecutionMessage) {
+        match message {
+            ExecutionMessage::Dummy { .. }
+            | ExecutionMessage::UpdateValidatorSet(_)
+            | ExecutionMessage::CancelExecution(_) => (),
+            ExecutionMessage::TransferNativeCoin(x) => {
+                self.addresses.insert(x.receiver_address.clone());
+                self.unchecked.push((
+                    "native coin balance".to_owned(),
+                    "the native coin balance of the treasury can't be queried".to_owned(),
+                ));
+            }
+            ExecutionMessage::TransferFungibleToken(x) => {
+                self.addresses.insert(x.receiver_address.clone());
+                let amount = self
+                    .fungible_tokens
+                    .entry(x.token_address.clone())
+                    .or_default();
+                *amount = amount.saturating_add(x.amount);
+            }
+            ExecutionMessage::TransferNonFungibleToken(x) => {
+                self.addresses.insert(x.collection_address.clone());
+                self.addresses.insert(x.receiver_address.clone());
+                self.non_fungible_tokens
+                    .insert((x.collection_address.clone(), x.token_index.clone()));
+            }
+            ExecutionMessage::TransferSemiFungibleToken(x) => {
+                self.addresses.insert(x.collection_address.clone());
+                self.addresses.insert(x.receiver_address.clone());
+                self.unchecked.push((
+                    format!("balance of {}", x.collection_address),
+                    "the semi-fungible token balances can't be queried".to_owned(),
+                ));
+            }
+            ExecutionMessage::ContractCall(x) => {
+                self.addresses.insert(x.contract_address.clone());
+                self.unchecked.push((
+                    format!("call to {}", x.contract_address),
+                    "the contract calls can't be simulated".to_owned(),
+                ));
+            }
+            ExecutionMessage::Batch(messages) => {
+                for message in messages {
+                    self.add(message);
+                }
+            }
+        }
+    }
+}
+
+/// Simulates the execution against the current state of the chain, which is its target chain.
+///
+/// It fails only if the chain can't be queried; the predicted failures are in the report.
+pub async fn simulate_execution(
+    execution: &Execution,
+    chain: &dyn SettlementChain,
+) -> Result<SimulationReport, Error> {
+    let mut report = SimulationReport {
+        checks: Vec::new(),
+        estimated_fee: Err("not estimated".to_owned()),
+    };
+    report.push(
+        "well-formedness",
+        match validate_execution(execution) {
+            Ok(()) => CheckOutcome::Passed,
+            Err(e) => CheckOutcome::Failed(e),
+        },
+    );
+    let chain_name = chain.get_chain_name().await;
+    if execution.target_chain.as_str() != chain_name {
+        report.push(
+            "target chain",
+            CheckOutcome::Failed(format!(
+                "the execution is to {}, not {chain_name}",
+                execution.target_chain
+            )),
+        );
+        return Ok(report);
+    }
+    report.push("target chain", CheckOutcome::Passed);
+
+    let mut requirements = Requirements::default();
+    requirements.add(&execution.message);
+    for address in &requirements.addresses {
+        report.push(
+            format!("address {address}"),
+            match chain.validate_address(address) {
+                Ok(()) => CheckOutcome::Passed,
+                Err(e) => CheckOutcome::Failed(e),
+            },
+        );
+    }
+    for (token, amount) in &requirements.fungible_tokens {
+        let subject = format!("balance of {token}");
+        let decimals = match chain.get_fungible_token_decimals(token.clone()).await {
+            Ok(x) => x,
+            Err(e) => {
+                report.push(subject, CheckOutcome::Skipped(e.to_string()));
+                continue;
+            }
+        };
+        let balance = chain
+            .get_treasury_fungible_token_balance(token.clone())
+            .await?;
+        let outcome = match to_decimal(*amount, decimals) {
+            Some(required) if required <= balance => CheckOutcome::Passed,
+            Some(required) => {
+                CheckOutcome::Failed(format!("{required} required, but {balance} held"))
+            }
+            None => CheckOutcome::Failed(format!("{amount} is more than any balance")),
+        };
+        report.push(subject, outcome);
+    }
+    let mut held = BTreeMap::new();
+    for (collection, token_index) in &requirements.non_fungible_tokens {
+        if !held.contains_key(collection) {
+            let token_indices = chain
+                .get_treasury_non_fungible_token_balance(collection.clone())
+                .await?;
+            held.insert(collection.clone(), token_indices);
+        }
+        report.push(
+            format!("token {token_index} of {collection}"),
+            if held[collection].contains(token_index) {
+                CheckOutcome::Passed
+            } else {
+                CheckOutcome::Failed("not held by the treasury".to_owned())
+            },
+        );
+    }
+    for (subject, reason) in requirements.unchecked {
+        report.push(subject, CheckOutcome::Skipped(reason));
+    }
+
+    report.estimated_fee = chain
+        .estimate_execution_fee(execution)
+        .await
+        .map_err(|e| e.to_string());
+    if let Ok(fee) = report.estimated_fee {
+        let (relayer, balance) = chain.get_relayer_account_info().await?;
+        report.push(
+            format!("relayer balance of {relayer}"),
+            if fee <= balance {
+                CheckOutcome::Passed
+            } else {
+                CheckOutcome::Failed(format!("{fee} required, but {balance} held"))
+            },
+        );
+    }
+    Ok(report)
+}
+
+/// Scales the amount in the smallest unit down by the decimals, if it fits in a `Decimal`.
+fn to_decimal(amount: u128, decimals: u32) -> Option<Decimal> {
+    let mut amount = Decimal::from_str_exact(&amount.to_string()).ok()?;
+    amount.set_scale(decimals).ok()?;
+    Some(amount)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use relay::tests::MockChain;
+
+    fn transfer(token_address: &str, amount: u128) -> ExecutionMessage {
+        ExecutionMessage::TransferFungibleToken(TransferFungibleToken {
+            token_address: token_address.to_owned(),
+            amount,
+            receiver_address: "receiver".to_owned(),
+        })
+    }
+
+    fn failed(report: &SimulationReport) -> Vec<String> {
+        report
+            .checks
+            .iter()
+            .filter(|x| matches!(x.outcome, CheckOutcome::Failed(_)))
+            .map(|x| x.subject.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn simulation() {
+        let chain = MockChain::new("ethereum", 0);
+        chain
+            .fungible_tokens
+            .lock()
+            .unwrap()
+            .insert("usdc".to_owned(), Decimal::new(1000, 2));
+        chain
+            .non_fungible_tokens
+            .lock()
+            .unwrap()
+            .insert("punks".to_owned(), vec!["7".to_owned()]);
+        let mut execution = Execution {
+            version: EXECUTION_VERSION,
+            target_chain: ChainId::Ethereum,
+            treasury: None,
+            contract_sequence: 0,
+            valid_until: None,
+            fee: None,
+            message: ExecutionMessage::Batch(vec![
+                transfer("usdc", 600),
+                transfer("usdc", 400),
+                ExecutionMessage::TransferNonFungibleToken(TransferNonFungibleToken {
+                    collection_address: "punks".to_owned(),
+                    token_index: "7".to_owned(),
+                    receiver_address: "receiver".to_owned(),
+                }),
+            ]),
+        };
+        let report = simulate_execution(&execution, &chain).await.unwrap();
+        assert!(report.succeeds(), "{report:?}");
+        // The mock doesn't estimate the fee.
+        report.estimated_fee.unwrap_err();
+
+        // More than held over the batch.
+        if let ExecutionMessage::Batch(messages) = &mut execution.message {
+            messages.push(transfer("usdc", 1));
+            messages.push(ExecutionMessage::TransferNonFungibleToken(
+                TransferNonFungibleToken {
+                    collection_address: "punks".to_owned(),
+                    token_index: "8".to_owned(),
+                    receiver_address: "receiver".to_owned(),
+                },
+            ));
+        }
+        let report = simulate_execution(&execution, &chain).await.unwrap();
+        assert_eq!(failed(&report), vec!["balance of usdc", "token 8 of punks"]);
+
+        execution.message = transfer("usdc", u128::MAX);
+        let report = simulate_execution(&execution, &chain).await.unwrap();
+        assert_eq!(failed(&report), vec!["balance of usdc"]);
+
+        execution.target_chain = ChainId::Polygon;
+        let report = simulate_execution(&execution, &chain).await.unwrap();
+        assert_eq!(failed(&report), vec!["target chain"]);
+    }
+}
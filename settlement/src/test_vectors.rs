@@ -0,0 +1,221 @@
+//! Test vectors of the executions for the implementers of the treasury contracts, enabled by the `test-util` feature.
+//!
+//! [`generate`] finalizes a block of the test chain (see `simperby_common::test_util`) that holds an execution
+//! of every kind of message, and records the exact bytes that a treasury contract receives:
+//! the header of the block with its finalization proof to update the light client with, and for each execution
+//! the encoded transaction, its hash and its Merkle proof. A contract in another language (e.g., Solidity or CosmWasm)
+//! can be tested by starting its light client at [`TestVectors::genesis_header`], updating it, and accepting
+//! every vector.
+//!
+//! Everything is deterministic, and the vectors are kept in `test-vectors/executions.json` of this crate,
+//! which is checked against [`generate`] by the tests. To update it, run
+//! `cargo test -p simperby-settlement --features test-util -- --ignored write_test_vectors`.
+use super::*;
+use simperby_common::test_util::*;
+
+/// The file of the vectors, relative to the root of this crate.
+pub const TEST_VECTORS_PATH: &str = "test-vectors/executions.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TestVectors {
+    /// The header that the light client of the treasury starts with.
+    pub genesis_header: BlockHeader,
+    /// The header of the block that the executions are included in.
+    pub header: BlockHeader,
+    pub finalization_proof: FinalizationProof,
+    /// The hex-encoded `serde_spb::to_vec` of the header, which the light client is updated with.
+    pub header_bytes: String,
+    /// The hex-encoded `serde_spb::to_vec` of the finalization proof.
+    pub finalization_proof_bytes: String,
+    pub vectors: Vec<TestVector>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TestVector {
+    /// The kind of the message, with `+bin` for the binary body (e.g., `contract-call+bin`).
+    pub name: String,
+    pub execution: Execution,
+    pub transaction: Transaction,
+    /// The hex-encoded `serde_spb::to_vec` of the transaction, which the treasury contract receives.
+    pub transaction_bytes: String,
+    /// The hash of the transaction, which is the leaf of the Merkle proof.
+    pub transaction_hash: Hash256,
+    /// The inclusion proof of the transaction against the commit Merkle root of the header.
+    pub merkle_proof: MerkleProof,
+    /// The hex-encoded `serde_spb::to_vec` of the Merkle proof.
+    pub merkle_proof_bytes: String,
+}
+
+fn messages() -> Vec<ExecutionMessage> {
+    let address = |name: &str| format!("{name}-address");
+    let transfer = ExecutionMessage::TransferFungibleToken(TransferFungibleToken {
+        token_address: address("token"),
+        amount: 1_000_000,
+        receiver_address: address("receiver"),
+    });
+    let contract_call = ExecutionMessage::ContractCall(ContractCall {
+        contract_address: address("contract"),
+        calldata: vec![0xde, 0xad, 0xbe, 0xef],
+        value: 7,
+    });
+    vec![
+        ExecutionMessage::Dummy {
+            msg: "hello".to_owned(),
+        },
+        ExecutionMessage::TransferNativeCoin(TransferNativeCoin {
+            amount: 1_000_000_000,
+            receiver_address: address("receiver"),
+        }),
+        transfer.clone(),
+        ExecutionMessage::TransferNonFungibleToken(TransferNonFungibleToken {
+            collection_address: address("collection"),
+            token_index: "42".to_owned(),
+            receiver_address: address("receiver"),
+        }),
+        ExecutionMessage::TransferSemiFungibleToken(TransferSemiFungibleToken {
+            collection_address: address("collection"),
+            token_index: "42".to_owned(),
+            amount: 3,
+            receiver_address: address("receiver"),
+        }),
+        contract_call.clone(),
+        ExecutionMessage::UpdateValidatorSet(UpdateValidatorSet {
+            height: 2,
+            validator_set: test_keys().into_iter().map(|(key, _)| (key, 1)).collect(),
+        }),
+        ExecutionMessage::Batch(vec![transfer, contract_call]),
+        ExecutionMessage::CancelExecution(CancelExecution {
+            contract_sequence: 0,
+        }),
+    ]
+}
+
+/// Generates the vectors, one for every kind of message and one of the binary body.
+pub fn generate() -> Result<TestVectors, Error> {
+    let mut executions = messages()
+        .into_iter()
+        .enumerate()
+        .map(|(i, message)| {
+            let execution = Execution {
+                version: EXECUTION_VERSION,
+                target_chain: ChainId::Ethereum,
+                treasury: None,
+                contract_sequence: i as u128,
+                valid_until: None,
+                fee: None,
+                message,
+            };
+            (execution, BodyEncoding::Text)
+        })
+        .collect::<Vec<_>>();
+    let mut binary = executions[5].0.clone();
+    binary.contract_sequence = executions.len() as u128;
+    executions.push((binary, BodyEncoding::Binary));
+
+    let transactions = executions
+        .iter()
+        .enumerate()
+        .map(|(i, (execution, encoding))| {
+            create_execution_transaction_encoded(
+                execution,
+                test_keys()[0].0.clone(),
+                i as Timestamp,
+                address::AddressValidation::Lenient,
+                *encoding,
+            )
+            .map_err(|e| eyre::eyre!(e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let sequence = build_commit_sequence(vec![transactions.clone()]);
+    let (header, commits) = match sequence.commits.split_last() {
+        Some((Commit::Block(header), commits)) => (header, commits),
+        _ => return Err(eyre::eyre!("the sequence doesn't end with a block")),
+    };
+
+    let mut vectors = Vec::new();
+    for ((execution, _), transaction) in executions.into_iter().zip(transactions) {
+        let proof = proof::ExecutionProof::create(
+            header,
+            sequence.last_finalization_proof.clone(),
+            commits,
+            &transaction,
+        )?;
+        let (kind, _) = transaction
+            .head
+            .trim_start_matches("ex-")
+            .split_once(": ")
+            .ok_or_else(|| eyre::eyre!("invalid head {}", transaction.head))?;
+        vectors.push(TestVector {
+            name: kind.to_owned(),
+            execution,
+            transaction_bytes: hex::encode(serde_spb::to_vec(&transaction)?),
+            transaction_hash: transaction.to_hash256(),
+            transaction,
+            merkle_proof_bytes: hex::encode(serde_spb::to_vec(&proof.merkle_proof)?),
+            merkle_proof: proof.merkle_proof,
+        });
+    }
+    Ok(TestVectors {
+        genesis_header: sequence.reserved_state.genesis_info.header,
+        header: header.clone(),
+        finalization_proof: sequence.last_finalization_proof.clone(),
+        header_bytes: hex::encode(serde_spb::to_vec(header)?),
+        finalization_proof_bytes: hex::encode(serde_spb::to_vec(
+            &sequence.last_finalization_proof,
+        )?),
+        vectors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use light_client::LightClient;
+
+    fn path() -> String {
+        format!("{}/{TEST_VECTORS_PATH}", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    #[test]
+    fn test_vectors() {
+        let vectors = generate().unwrap();
+        assert_eq!(vectors.vectors.len(), 10);
+        let mut light_client = LightClient::new(vectors.genesis_header.clone());
+        let header: BlockHeader =
+            serde_spb::from_slice(&hex::decode(&vectors.header_bytes).unwrap()).unwrap();
+        let finalization_proof: FinalizationProof =
+            serde_spb::from_slice(&hex::decode(&vectors.finalization_proof_bytes).unwrap())
+                .unwrap();
+        light_client.update(header, finalization_proof).unwrap();
+        for vector in &vectors.vectors {
+            let transaction: Transaction =
+                serde_spb::from_slice(&hex::decode(&vector.transaction_bytes).unwrap()).unwrap();
+            assert_eq!(transaction, vector.transaction);
+            assert_eq!(
+                convert_transaction_to_execution(&transaction).unwrap(),
+                vector.execution
+            );
+            let merkle_proof: MerkleProof =
+                serde_spb::from_slice(&hex::decode(&vector.merkle_proof_bytes).unwrap()).unwrap();
+            assert!(light_client.verify_transaction_commitment(
+                &transaction,
+                vectors.header.height,
+                merkle_proof
+            ));
+        }
+
+        let saved = std::fs::read_to_string(path()).unwrap();
+        assert!(
+            saved == serde_spb::to_string(&vectors).unwrap(),
+            "{TEST_VECTORS_PATH} is outdated; run the ignored `write_test_vectors` to update it"
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn write_test_vectors() {
+        let vectors = generate().unwrap();
+        std::fs::create_dir_all(std::path::Path::new(&path()).parent().unwrap()).unwrap();
+        std::fs::write(path(), serde_spb::to_string(&vectors).unwrap()).unwrap();
+    }
+}
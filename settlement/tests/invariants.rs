@@ -0,0 +1,171 @@
+//! Property-based tests of the execution transactions.
+//!
+//! Run with `cargo test --features test-util`.
+#![cfg(feature = "test-util")]
+
+use proptest::prelude::*;
+use simperby_common::test_util::*;
+use simperby_common::*;
+use simperby_settlement::execution::*;
+
+fn arb_address() -> impl Strategy<Value = String> {
+    "[0-9a-zA-Z]{1,42}"
+}
+
+/// A message that is not a batch.
+fn arb_single_message() -> impl Strategy<Value = ExecutionMessage> {
+    prop_oneof![
+        ".{0,64}".prop_map(|msg| ExecutionMessage::Dummy { msg }),
+        (1..u128::MAX, arb_address()).prop_map(|(amount, receiver_address)| {
+            ExecutionMessage::TransferNativeCoin(TransferNativeCoin {
+                amount,
+                receiver_address,
+            })
+        }),
+        (arb_address(), 1..u128::MAX, arb_address()).prop_map(
+            |(token_address, amount, receiver_address)| {
+                ExecutionMessage::TransferFungibleToken(TransferFungibleToken {
+                    token_address,
+                    amount,
+                    receiver_address,
+                })
+            }
+        ),
+        (arb_address(), "[0-9]{1,10}", arb_address()).prop_map(
+            |(collection_address, token_index, receiver_address)| {
+                ExecutionMessage::TransferNonFungibleToken(TransferNonFungibleToken {
+                    collection_address,
+                    token_index,
+                    receiver_address,
+                })
+            }
+        ),
+        (arb_address(), "[0-9]{1,10}", 1..u128::MAX, arb_address()).prop_map(
+            |(collection_address, token_index, amount, receiver_address)| {
+                ExecutionMessage::TransferSemiFungibleToken(TransferSemiFungibleToken {
+                    collection_address,
+                    token_index,
+                    amount,
+                    receiver_address,
+                })
+            }
+        ),
+        (
+            arb_address(),
+            proptest::collection::vec(any::<u8>(), 0..64),
+            any::<u128>()
+        )
+            .prop_map(|(contract_address, calldata, value)| {
+                ExecutionMessage::ContractCall(ContractCall {
+                    contract_address,
+                    calldata,
+                    value,
+                })
+            }),
+        (
+            any::<u64>(),
+            proptest::sample::subsequence(test_keys(), 1..=TEST_MEMBER_NUMBER),
+            1..1000u64
+        )
+            .prop_map(|(height, keys, power)| {
+                ExecutionMessage::UpdateValidatorSet(UpdateValidatorSet {
+                    height,
+                    validator_set: keys.into_iter().map(|(key, _)| (key, power)).collect(),
+                })
+            }),
+    ]
+}
+
+fn arb_execution_message() -> impl Strategy<Value = ExecutionMessage> {
+    prop_oneof![
+        4 => arb_single_message(),
+        1 => proptest::collection::vec(arb_single_message(), 1..8).prop_map(ExecutionMessage::Batch),
+    ]
+}
+
+fn arb_fee_budget() -> impl Strategy<Value = FeeBudget> {
+    (
+        proptest::option::of(1..u64::MAX),
+        prop_oneof![
+            Just(GasPriceStrategy::Market),
+            (1..u128::MAX).prop_map(GasPriceStrategy::Fixed),
+            (1..u128::MAX).prop_map(GasPriceStrategy::Capped),
+        ],
+        proptest::option::of(arb_address()),
+    )
+        .prop_map(|(max_gas, gas_price, fee_payer)| FeeBudget {
+            max_gas,
+            gas_price,
+            fee_payer,
+        })
+}
+
+fn arb_execution() -> impl Strategy<Value = Execution> {
+    (
+        1..=EXECUTION_VERSION,
+        "[a-z][a-z0-9-]{0,15}".prop_map(|x| x.parse::<ChainId>().unwrap()),
+        proptest::option::of("[a-z0-9]{1,16}"),
+        any::<u128>(),
+        proptest::option::of(arb_timestamp()),
+        proptest::option::of(arb_fee_budget()),
+        arb_execution_message(),
+    )
+        .prop_map(
+            |(version, target_chain, treasury, contract_sequence, valid_until, fee, message)| {
+                Execution {
+                    version,
+                    target_chain,
+                    // Supported from the version 3.
+                    treasury: treasury.filter(|_| version >= 3),
+                    contract_sequence,
+                    // Supported from the version 4.
+                    valid_until: valid_until.filter(|_| version >= 4),
+                    // Supported from the version 5.
+                    fee: fee.filter(|_| version >= 5),
+                    message,
+                }
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn execution_transaction_identity(
+        execution in arb_execution(),
+        author in arb_member_key(),
+        timestamp in arb_timestamp(),
+    ) {
+        prop_assert!(validate_execution(&execution).is_ok());
+        let transaction = create_execution_transaction(&execution, author, timestamp).unwrap();
+        prop_assert_eq!(convert_transaction_to_execution(&transaction), Ok(execution));
+    }
+
+    #[test]
+    fn binary_execution_transaction_identity(execution in arb_execution()) {
+        let transaction = create_execution_transaction_encoded(
+            &execution,
+            PublicKey::zero(),
+            0,
+            simperby_settlement::address::AddressValidation::Lenient,
+            BodyEncoding::Binary,
+        );
+        if execution.version == 1 {
+            prop_assert!(transaction.is_err());
+        } else {
+            prop_assert_eq!(
+                convert_transaction_to_execution(&transaction.unwrap()),
+                Ok(execution)
+            );
+        }
+    }
+
+    #[test]
+    fn execution_transaction_survives_serialization(execution in arb_execution()) {
+        let transaction =
+            create_execution_transaction(&execution, PublicKey::zero(), 0).unwrap();
+        let decoded: Transaction =
+            serde_spb::from_str(&serde_spb::to_string(&transaction).unwrap()).unwrap();
+        prop_assert_eq!(transaction.to_hash256(), decoded.to_hash256());
+        prop_assert_eq!(convert_transaction_to_execution(&decoded), Ok(execution));
+    }
+}
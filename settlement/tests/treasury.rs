@@ -77,11 +77,12 @@ impl MythereumTreasuryContract {
         simperby_height: BlockHeight,
         proof: MerkleProof,
     ) -> Result<(), String> {
-        let execution = convert_transaction_to_execution(&execution_transaction)?;
+        let execution =
+            convert_transaction_to_execution(&execution_transaction).map_err(|e| e.to_string())?;
         if execution.contract_sequence != self.sequence {
             return Err("Invalid sequence".to_string());
         }
-        if execution.target_chain != "mythereum" {
+        if execution.target_chain.as_str() != "mythereum" {
             return Err("Invalid target chain".to_string());
         }
 
@@ -112,7 +113,13 @@ impl MythereumTreasuryContract {
                     return Err("Insufficient balance".to_string());
                 }
             }
+            ExecutionMessage::TransferNativeCoin(_) => todo!(),
             ExecutionMessage::TransferNonFungibleToken(_) => todo!(),
+            ExecutionMessage::TransferSemiFungibleToken(_) => todo!(),
+            ExecutionMessage::ContractCall(_) => todo!(),
+            ExecutionMessage::UpdateValidatorSet(_) => todo!(),
+            ExecutionMessage::Batch(_) => todo!(),
+            ExecutionMessage::CancelExecution(_) => todo!(),
         }
 
         Ok(())
@@ -132,8 +139,12 @@ fn relay_1() {
     .unwrap();
     let tx1 = create_execution_transaction(
         &Execution {
-            target_chain: "mythereum".to_string(),
+            version: EXECUTION_VERSION,
+            target_chain: "mythereum".parse().unwrap(),
+            treasury: None,
             contract_sequence: 0,
+            valid_until: None,
+            fee: None,
             message: ExecutionMessage::TransferFungibleToken(TransferFungibleToken {
                 token_address: "tether-address".to_string(),
                 amount: 100,
@@ -146,8 +157,12 @@ fn relay_1() {
     .unwrap();
     let tx2 = create_execution_transaction(
         &Execution {
-            target_chain: "mythereum".to_string(),
+            version: EXECUTION_VERSION,
+            target_chain: "mythereum".parse().unwrap(),
+            treasury: None,
             contract_sequence: 1,
+            valid_until: None,
+            fee: None,
             message: ExecutionMessage::TransferFungibleToken(TransferFungibleToken {
                 token_address: "tether-address".to_string(),
                 amount: 200,
@@ -0,0 +1,290 @@
+//! Builders of the test networks, for writing integration tests against Simperby.
+//!
+//! - [`TestNetBuilder`]: network members with their configs and the peers they initially know.
+//! - [`DmsClusterBuilder`]: a server DMS and client DMSs connected to it.
+//! - [`TestClusterBuilder`]: full nodes (see [`TestCluster`]).
+use super::*;
+use simperby_node::simperby_network::limits::ResourceLimits;
+use std::time::Duration;
+
+/// A deterministic source of the key pairs, so that a test always gets the same keys.
+///
+/// The keys are the children of the master key derived from the mnemonic (see [`hd`]).
+#[derive(Debug, Clone)]
+pub struct KeyStore {
+    master_key: hd::ExtendedPrivateKey,
+    count: u32,
+}
+
+impl KeyStore {
+    pub fn new(mnemonic: &str) -> Self {
+        Self {
+            master_key: hd::ExtendedPrivateKey::from_seed(hd::mnemonic_to_seed(mnemonic, ""))
+                .expect("invalid master key"),
+            count: 0,
+        }
+    }
+
+    pub fn generate_keypair(&mut self) -> (PublicKey, PrivateKey) {
+        self.count += 1;
+        let private_key = self
+            .master_key
+            .derive_child(self.count)
+            .expect("invalid child key")
+            .private_key;
+        (private_key.public_key(), private_key)
+    }
+}
+
+/// A member of a [`TestNet`].
+#[derive(Debug, Clone)]
+pub struct TestNetMember {
+    pub network_config: NetworkConfig,
+    /// The peers that this member knows at the start.
+    pub known_peers: SharedKnownPeers,
+}
+
+/// A set of network members, each of which initially knows only some of the others.
+#[derive(Debug, Clone)]
+pub struct TestNet {
+    pub members: Vec<TestNetMember>,
+}
+
+impl TestNet {
+    /// Returns the peer information of the member, as seen by the others.
+    pub fn peer(&self, index: usize) -> Peer {
+        let config = &self.members[index].network_config;
+        Peer {
+            public_key: config.public_key.clone(),
+            name: format!("member-{index:04}"),
+            address: "127.0.0.1:1".parse().unwrap(),
+            addresses: Vec::new(),
+            ports: config.ports.clone(),
+            metadata: Default::default(),
+            recently_seen_timestamp: 0,
+        }
+    }
+
+    /// Returns the peer information of all the members.
+    pub fn peers(&self) -> Vec<Peer> {
+        (0..self.members.len()).map(|i| self.peer(i)).collect()
+    }
+}
+
+pub struct TestNetBuilder {
+    network_id: String,
+    size: usize,
+    bootstrap: usize,
+    services: Vec<String>,
+}
+
+impl TestNetBuilder {
+    pub fn new(network_id: &str) -> Self {
+        Self {
+            network_id: network_id.to_owned(),
+            size: 4,
+            bootstrap: usize::MAX,
+            services: vec![format!("dms-{network_id}")],
+        }
+    }
+
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// Makes every member initially know only the first `bootstrap` members (the bootstrap nodes).
+    ///
+    /// By default, every member knows all the others.
+    pub fn bootstrap(mut self, bootstrap: usize) -> Self {
+        self.bootstrap = bootstrap;
+        self
+    }
+
+    /// Sets the services for which each member is given a port.
+    pub fn services(mut self, services: Vec<String>) -> Self {
+        self.services = services;
+        self
+    }
+
+    pub fn build(self) -> TestNet {
+        let mut key_store = KeyStore::new(&self.network_id);
+        let keys = (0..self.size)
+            .map(|_| key_store.generate_keypair())
+            .collect::<Vec<_>>();
+        let configs = keys
+            .iter()
+            .map(|(public_key, private_key)| NetworkConfig {
+                network_id: self.network_id.clone(),
+                ports: self
+                    .services
+                    .iter()
+                    .map(|service| (service.clone(), dispense_port()))
+                    .collect(),
+                members: keys.iter().map(|(k, _)| k.clone()).collect(),
+                public_key: public_key.clone(),
+                private_key: Some(private_key.clone()),
+                dns_seeds: Vec::new(),
+                enable_mdns: false,
+                pre_shared_key: None,
+                proxy: None,
+                denied_peers: Vec::new(),
+                allowed_peers: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        let mut testnet = TestNet {
+            members: configs
+                .into_iter()
+                .map(|network_config| TestNetMember {
+                    network_config,
+                    known_peers: SharedKnownPeers::new_static(Vec::new()),
+                })
+                .collect(),
+        };
+        let peers = testnet.peers();
+        for (i, member) in testnet.members.iter_mut().enumerate() {
+            member.known_peers = SharedKnownPeers::new_static(
+                peers
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i && *j < self.bootstrap)
+                    .map(|(_, peer)| peer.clone())
+                    .collect(),
+            );
+        }
+        testnet
+    }
+}
+
+/// A server DMS that every client DMS knows.
+pub struct DmsCluster {
+    pub server: Dms,
+    pub server_config: NetworkConfig,
+    pub clients: Vec<Dms>,
+    pub client_configs: Vec<NetworkConfig>,
+}
+
+pub struct DmsClusterBuilder {
+    dms_key: String,
+    clients: usize,
+    interval: Option<Duration>,
+    limits: ResourceLimits,
+}
+
+impl DmsClusterBuilder {
+    pub fn new(dms_key: &str) -> Self {
+        Self {
+            dms_key: dms_key.to_owned(),
+            clients: 3,
+            interval: Some(Duration::from_millis(500)),
+            limits: ResourceLimits::default(),
+        }
+    }
+
+    pub fn clients(mut self, clients: usize) -> Self {
+        self.clients = clients;
+        self
+    }
+
+    /// Sets the interval of the periodic broadcast and fetch in `serve()`. `None` disables them.
+    pub fn interval(mut self, interval: Option<Duration>) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    async fn create_dms(&self, network_config: NetworkConfig, peers: SharedKnownPeers) -> Dms {
+        let path = create_temp_dir();
+        StorageImpl::create(&path).await.unwrap();
+        let storage = StorageImpl::open(&path).await.unwrap();
+        Dms::new(
+            storage,
+            self.dms_key.clone(),
+            dms::Config {
+                fetch_interval: self.interval,
+                broadcast_interval: self.interval,
+                network_config,
+                limits: self.limits.clone(),
+                dial: Default::default(),
+                chunking: Default::default(),
+                seen_cache: Default::default(),
+                priority: Default::default(),
+                tracing: None,
+            },
+            peers,
+        )
+        .await
+        .unwrap()
+    }
+
+    pub async fn build(self) -> DmsCluster {
+        let testnet = TestNetBuilder::new(&self.dms_key)
+            .size(self.clients + 1)
+            .bootstrap(1)
+            .build();
+        let mut members = testnet.members.into_iter();
+        let server_config = members.next().unwrap().network_config;
+        let server = self
+            .create_dms(
+                server_config.clone(),
+                SharedKnownPeers::new_static(Vec::new()),
+            )
+            .await;
+        let mut clients = Vec::new();
+        let mut client_configs = Vec::new();
+        for member in members {
+            clients.push(
+                self.create_dms(member.network_config.clone(), member.known_peers)
+                    .await,
+            );
+            client_configs.push(member.network_config);
+        }
+        DmsCluster {
+            server,
+            server_config,
+            clients,
+            client_configs,
+        }
+    }
+}
+
+pub struct TestClusterBuilder {
+    chain_name: String,
+    size: usize,
+    delegated: bool,
+}
+
+impl TestClusterBuilder {
+    pub fn new(chain_name: &str) -> Self {
+        Self {
+            chain_name: chain_name.to_owned(),
+            size: 4,
+            delegated: false,
+        }
+    }
+
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// Uses `generate_delegated_genesis` instead of `generate_standard_genesis`,
+    /// which leaves the first member (who delegated) without a node.
+    pub fn delegated(mut self, delegated: bool) -> Self {
+        self.delegated = delegated;
+        self
+    }
+
+    pub async fn build(self) -> eyre::Result<TestCluster> {
+        let (reserved_state, keys) = if self.delegated {
+            generate_delegated_genesis(self.size)
+        } else {
+            generate_standard_genesis(self.size)
+        };
+        TestCluster::with_genesis(&self.chain_name, reserved_state, keys).await
+    }
+}
@@ -0,0 +1,271 @@
+//! Chaos testing of full nodes.
+//!
+//! A [`ChaosHarness`] drives a [`TestCluster`] through a script of faults
+//! (killing and restarting nodes, skewing clocks, partitioning the network, filling disks)
+//! and checks the safety and recovery invariants whenever asked to.
+use super::*;
+use eyre::{eyre, Result};
+use simperby_node::simperby_network::audit::AuditQuery;
+use simperby_node::{initialize, SimperbyNode};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Stops the node abruptly, dropping all of its in-memory state.
+    Kill(usize),
+    /// Restarts a killed node from its directory.
+    Restart(usize),
+    SkewClock {
+        node: usize,
+        offset_ms: i64,
+    },
+    /// Splits the network so that each node can reach only the nodes in the same group.
+    /// Nodes not listed in any group are isolated.
+    Partition(Vec<Vec<usize>>),
+    Heal,
+    /// Writes a ballast file of the given size into the node directory.
+    ///
+    /// It really exhausts the disk only if the directory is on a size-limited filesystem (e.g., a small tmpfs).
+    FillDisk {
+        node: usize,
+        bytes: u64,
+    },
+    FreeDisk(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChaosStep {
+    Inject(Fault),
+    /// Lets the live nodes exchange their data, each for the given time.
+    Run {
+        ms: u64,
+    },
+    /// Makes a progress for the consensus on each live node.
+    Progress,
+    /// Checks the invariants, failing the script on a violation.
+    Check,
+}
+
+pub struct ChaosHarness {
+    cluster: TestCluster,
+    /// `None` for the killed nodes.
+    nodes: Vec<Option<SimperbyNode>>,
+    clock_offsets: Vec<i64>,
+    /// The hash of every finalized block that has been observed, by height.
+    finalized: BTreeMap<BlockHeight, Hash256>,
+    /// The highest finalized height that has been observed for each node.
+    heights: Vec<BlockHeight>,
+    /// Errors of the live nodes that are expected under faults (e.g., failing to fetch from a killed peer).
+    pub incidents: Vec<String>,
+}
+
+impl ChaosHarness {
+    pub fn new(mut cluster: TestCluster) -> Self {
+        let nodes = cluster.nodes.drain(..).map(Some).collect::<Vec<_>>();
+        let size = nodes.len();
+        Self {
+            cluster,
+            nodes,
+            clock_offsets: vec![0; size],
+            finalized: BTreeMap::new(),
+            heights: vec![0; size],
+            incidents: Vec::new(),
+        }
+    }
+
+    pub fn cluster(&self) -> &TestCluster {
+        &self.cluster
+    }
+
+    /// Returns the node if it's alive.
+    pub fn node_mut(&mut self, index: usize) -> Option<&mut SimperbyNode> {
+        self.nodes[index].as_mut()
+    }
+
+    pub fn is_alive(&self, index: usize) -> bool {
+        self.nodes[index].is_some()
+    }
+
+    /// Returns the highest finalized height of the node, as of the last check.
+    pub fn finalized_height(&self, index: usize) -> BlockHeight {
+        self.heights[index]
+    }
+
+    /// Stops the harness, returning the cluster with the live nodes only.
+    pub fn into_cluster(mut self) -> TestCluster {
+        self.cluster.nodes = self.nodes.into_iter().flatten().collect();
+        self.cluster
+    }
+
+    pub async fn run_script(&mut self, script: &[ChaosStep]) -> Result<()> {
+        for (i, step) in script.iter().enumerate() {
+            log::info!("chaos step {i}: {step:?}");
+            match step {
+                ChaosStep::Inject(fault) => self.inject(fault.clone()).await?,
+                ChaosStep::Run { ms } => self.run(*ms).await?,
+                ChaosStep::Progress => self.progress().await,
+                ChaosStep::Check => self
+                    .check()
+                    .await
+                    .map_err(|e| eyre!("invariant violated at step {i}: {e}"))?,
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn inject(&mut self, fault: Fault) -> Result<()> {
+        match fault {
+            Fault::Kill(index) => {
+                if self.nodes[index].take().is_none() {
+                    return Err(eyre!("node {index} is already killed"));
+                }
+            }
+            Fault::Restart(index) => {
+                if self.nodes[index].is_some() {
+                    return Err(eyre!("node {index} is alive"));
+                }
+                let mut node = initialize(
+                    self.cluster.configs[index].clone(),
+                    &self.cluster.dirs[index],
+                )
+                .await
+                .map_err(|e| eyre!("node {index} failed to recover: {e}"))?;
+                node.set_clock_offset(self.clock_offsets[index]);
+                self.nodes[index] = Some(node);
+            }
+            Fault::SkewClock { node, offset_ms } => {
+                self.clock_offsets[node] = offset_ms;
+                if let Some(node) = self.nodes[node].as_mut() {
+                    node.set_clock_offset(offset_ms);
+                }
+            }
+            Fault::Partition(groups) => {
+                let group_of = |index: usize| groups.iter().position(|g| g.contains(&index));
+                for index in 0..self.nodes.len() {
+                    let reachable = self.cluster.peers[index]
+                        .iter()
+                        .filter(|peer| {
+                            let other = self
+                                .cluster
+                                .keys
+                                .iter()
+                                .position(|(public_key, _)| *public_key == peer.public_key)
+                                .expect("peer must be a member");
+                            group_of(index).is_some() && group_of(index) == group_of(other)
+                        })
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    self.set_peers(index, &reachable).await?;
+                }
+            }
+            Fault::Heal => {
+                for index in 0..self.nodes.len() {
+                    let peers = self.cluster.peers[index].clone();
+                    self.set_peers(index, &peers).await?;
+                }
+            }
+            Fault::FillDisk { node, bytes } => {
+                tokio::fs::write(
+                    format!("{}/chaos-ballast", self.cluster.dirs[node]),
+                    vec![0u8; bytes as usize],
+                )
+                .await?;
+            }
+            Fault::FreeDisk(node) => {
+                tokio::fs::remove_file(format!("{}/chaos-ballast", self.cluster.dirs[node]))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_peers(&self, index: usize, peers: &[Peer]) -> Result<()> {
+        write_peers(&self.cluster.dirs[index], peers).await?;
+        if let Some(node) = self.nodes[index].as_ref() {
+            node.reload_handle().reload().await?;
+        }
+        Ok(())
+    }
+
+    /// Lets each live node broadcast and fetch in turn, while all the other live nodes serve for the given time.
+    pub async fn run(&mut self, ms: u64) -> Result<()> {
+        for index in 0..self.nodes.len() {
+            let mut node = match self.nodes[index].take() {
+                Some(node) => node,
+                None => continue,
+            };
+            let servers = self
+                .nodes
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(i, x)| x.take().map(|x| (i, x)))
+                .map(|(i, x)| (i, tokio::spawn(async move { x.serve(ms).await })))
+                .collect::<Vec<_>>();
+            // Give the servers a moment to bind.
+            tokio::time::sleep(std::time::Duration::from_millis(ms.min(200))).await;
+            if let Err(e) = node.broadcast().await {
+                self.incidents
+                    .push(format!("node {index} failed to broadcast: {e}"));
+            }
+            if let Err(e) = node.fetch().await {
+                self.incidents
+                    .push(format!("node {index} failed to fetch: {e}"));
+            }
+            for (i, server) in servers {
+                self.nodes[i] = Some(server.await??);
+            }
+            self.nodes[index] = Some(node);
+        }
+        Ok(())
+    }
+
+    pub async fn progress(&mut self) {
+        for (index, node) in self.nodes.iter_mut().enumerate() {
+            if let Some(node) = node {
+                if let Err(e) = node.progress_for_consensus().await {
+                    self.incidents
+                        .push(format!("node {index} failed to progress: {e}"));
+                }
+            }
+        }
+    }
+
+    /// Checks the invariants over the live nodes.
+    ///
+    /// - Safety: no two blocks of the same height have ever been finalized.
+    /// - Durability: the finalized height of a node never decreases, even across restarts.
+    /// - Integrity: the signing audit log of every node is unbroken.
+    pub async fn check(&mut self) -> Result<()> {
+        for (index, node) in self.nodes.iter().enumerate() {
+            let node = match node {
+                Some(node) => node,
+                None => continue,
+            };
+            let header = node.get_last_finalized_header();
+            let hash = header.to_hash256();
+            match self.finalized.get(&header.height) {
+                Some(x) if *x != hash => {
+                    return Err(eyre!(
+                        "node {index} finalized {hash} at height {}, but {x} was finalized before",
+                        header.height
+                    ))
+                }
+                _ => {
+                    self.finalized.insert(header.height, hash);
+                }
+            }
+            if header.height < self.heights[index] {
+                return Err(eyre!(
+                    "node {index} went back to height {} from {}",
+                    header.height,
+                    self.heights[index]
+                ));
+            }
+            self.heights[index] = header.height;
+            node.query_audit_log(&AuditQuery::default())
+                .await
+                .map_err(|e| eyre!("node {index} has a broken audit log: {e}"))?;
+        }
+        Ok(())
+    }
+}
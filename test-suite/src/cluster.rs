@@ -0,0 +1,156 @@
+use super::*;
+use eyre::Result;
+use simperby_node::{genesis, initialize, Config, SimperbyNode};
+
+/// A set of fully functional Simperby nodes running in this process,
+/// each backed by its own temporary directory and wired to each other as peers.
+///
+/// Every node is a member of the same genesis (`generate_standard_genesis`)
+/// and knows all the other nodes from the beginning.
+pub struct TestCluster {
+    pub nodes: Vec<SimperbyNode>,
+    pub dirs: Vec<String>,
+    pub reserved_state: ReservedState,
+    pub keys: Vec<(PublicKey, PrivateKey)>,
+    pub configs: Vec<Config>,
+    /// The full peer list of each node (i.e., all the other nodes).
+    pub peers: Vec<Vec<Peer>>,
+}
+
+/// Generates a node config with freshly dispensed ports.
+pub fn generate_node_config(private_key: PrivateKey, chain_name: String) -> Config {
+    Config {
+        chain_name,
+        public_key: private_key.public_key(),
+        private_key: Some(private_key),
+        broadcast_interval_ms: None,
+        fetch_interval_ms: None,
+        public_repo_url: vec![],
+        network_port: dispense_port(),
+        repository_port: dispense_port(),
+        pre_shared_key: None,
+        dns_seeds: Vec::new(),
+        enable_mdns: false,
+        proxy: None,
+        denied_peers: Vec::new(),
+        allowed_peers: Vec::new(),
+        keystore: None,
+        api: Default::default(),
+    }
+}
+
+/// Writes the `peers.json` file in the given node directory.
+pub async fn write_peers(path: &str, peers: &[Peer]) -> Result<()> {
+    tokio::fs::write(format!("{path}/peers.json"), serde_spb::to_string(&peers)?).await?;
+    Ok(())
+}
+
+impl TestCluster {
+    /// Creates a genesis repository and initializes `size` nodes on it.
+    pub async fn new(chain_name: &str, size: usize) -> Result<Self> {
+        let (reserved_state, keys) = generate_standard_genesis(size);
+        Self::with_genesis(chain_name, reserved_state, keys).await
+    }
+
+    /// Creates a repository of the given genesis and initializes a node for each key.
+    pub async fn with_genesis(
+        chain_name: &str,
+        reserved_state: ReservedState,
+        keys: Vec<(PublicKey, PrivateKey)>,
+    ) -> Result<Self> {
+        let size = keys.len();
+        let configs = keys
+            .iter()
+            .map(|(_, private_key)| {
+                generate_node_config(private_key.clone(), chain_name.to_owned())
+            })
+            .collect::<Vec<_>>();
+        let dirs = (0..size).map(|_| create_temp_dir()).collect::<Vec<_>>();
+
+        // The first node creates the genesis commit and the others copy it.
+        setup_pre_genesis_repository(&dirs[0], reserved_state.clone()).await;
+        write_peers(&dirs[0], &[]).await?;
+        genesis(configs[0].clone(), &dirs[0]).await?;
+        for dir in dirs.iter().skip(1) {
+            copy_repository(&dirs[0], dir).await;
+        }
+
+        // The port map of each node is determined on the initialization,
+        // so initialize once without peers to learn it.
+        let mut ports = Vec::new();
+        for (config, dir) in configs.iter().zip(dirs.iter()) {
+            write_peers(dir, &[]).await?;
+            let node = initialize(config.clone(), dir).await?;
+            ports.push(node.network_config().ports.clone());
+        }
+
+        let mut nodes = Vec::new();
+        let mut all_peers = Vec::new();
+        for (i, (config, dir)) in configs.iter().zip(dirs.iter()).enumerate() {
+            let peers = configs
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(j, other)| Peer {
+                    public_key: other.public_key.clone(),
+                    name: reserved_state
+                        .members
+                        .iter()
+                        .find(|m| m.public_key == other.public_key)
+                        .expect("every key must belong to a member")
+                        .name
+                        .clone(),
+                    address: "127.0.0.1:1".parse().unwrap(),
+                    addresses: Vec::new(),
+                    ports: ports[j].clone(),
+                    metadata: Default::default(),
+                    recently_seen_timestamp: 0,
+                })
+                .collect::<Vec<_>>();
+            write_peers(dir, &peers).await?;
+            nodes.push(initialize(config.clone(), dir).await?);
+            all_peers.push(peers);
+        }
+        Ok(Self {
+            nodes,
+            dirs,
+            reserved_state,
+            keys,
+            configs,
+            peers: all_peers,
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Runs the servers of all the nodes for the given time, and then gets them back.
+    pub async fn serve_all(&mut self, time_in_ms: u64) -> Result<()> {
+        let tasks = self
+            .nodes
+            .drain(..)
+            .map(|node| tokio::spawn(async move { node.serve(time_in_ms).await }))
+            .collect::<Vec<_>>();
+        for task in tasks {
+            self.nodes.push(task.await??);
+        }
+        Ok(())
+    }
+
+    /// Fetches data from the peers for all the nodes.
+    pub async fn fetch_all(&mut self) -> Result<()> {
+        for node in self.nodes.iter_mut() {
+            node.fetch().await?;
+        }
+        Ok(())
+    }
+
+    /// Broadcasts the local data of all the nodes.
+    pub async fn broadcast_all(&mut self) -> Result<()> {
+        for node in self.nodes.iter_mut() {
+            node.broadcast().await?;
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,16 @@
+//! Utilities for testing Simperby and the applications built on it.
+//!
+//! Besides the helpers for the genesis and the temporary directories,
+//! it provides builders of test networks at each layer:
+//! [`TestNetBuilder`] (network members), [`DmsClusterBuilder`] (DMS instances),
+//! and [`TestClusterBuilder`] (full nodes, optionally driven by a [`ChaosHarness`]).
+mod builders;
+mod chaos;
+mod cluster;
+
+pub use builders::*;
+pub use chaos::*;
+pub use cluster::*;
 use path_slash::PathExt as _;
 use simperby_node::simperby_common::*;
 use simperby_node::simperby_network::primitives::Storage;
@@ -253,6 +266,12 @@ pub async fn create_test_dms(
             fetch_interval: Some(std::time::Duration::from_millis(500)),
             broadcast_interval: Some(std::time::Duration::from_millis(500)),
             network_config,
+            limits: Default::default(),
+            dial: Default::default(),
+            chunking: Default::default(),
+            seen_cache: Default::default(),
+            priority: Default::default(),
+            tracing: None,
         },
         peers,
     )
@@ -272,7 +291,13 @@ pub async fn setup_server_client_nodes(
             .collect(),
         members: Vec::new(),
         public_key,
-        private_key,
+        private_key: Some(private_key),
+        dns_seeds: Vec::new(),
+        enable_mdns: false,
+        pre_shared_key: None,
+        proxy: None,
+        denied_peers: Vec::new(),
+        allowed_peers: Vec::new(),
     };
     let mut clients = Vec::new();
     for _ in 0..client_n {
@@ -284,7 +309,13 @@ pub async fn setup_server_client_nodes(
                 .collect(),
             members: Vec::new(),
             public_key,
-            private_key: private_key.clone(),
+            private_key: Some(private_key.clone()),
+            dns_seeds: Vec::new(),
+            enable_mdns: false,
+            pre_shared_key: None,
+            proxy: None,
+            denied_peers: Vec::new(),
+            allowed_peers: Vec::new(),
         };
         clients.push(network_config);
     }
@@ -292,8 +323,9 @@ pub async fn setup_server_client_nodes(
         public_key: server.public_key.clone(),
         name: "server".to_owned(),
         address: "127.0.0.1:1".parse().unwrap(),
+        addresses: Vec::new(),
         ports: server.ports.clone(),
-        message: "".to_owned(),
+        metadata: Default::default(),
         recently_seen_timestamp: 0,
     }]);
     (server, clients, peer)